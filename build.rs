@@ -1,3 +1,4 @@
+use std::ffi::OsStr;
 use std::fs;
 use std::io::{self, Write};
 use std::process::exit;
@@ -23,4 +24,61 @@ fn main() {
     app.gen_completions("s3find", Shell::Fish, &outdir);
     app.gen_completions("s3find", Shell::Zsh, &outdir);
     app.gen_completions("s3find", Shell::PowerShell, &outdir);
+
+    complete_prefixes(&outdir);
+}
+
+const BASH_PREFIX_COMPLETION: &str = r#"
+_s3find_prefix() {
+    # COMP_WORDS splits s3://bucket at the colon, take the whole word
+    local line="${COMP_LINE:0:COMP_POINT}"
+    local cur="${line##* }"
+    if [[ "${cur}" == s3://*/* ]]; then
+        local IFS=$'\n'
+        COMPREPLY=( $(s3find __complete-prefix "${cur}" 2>/dev/null) )
+        if [[ "${COMP_WORDBREAKS}" == *:* ]]; then
+            COMPREPLY=( "${COMPREPLY[@]#s3:}" )
+        fi
+        compopt -o nospace 2>/dev/null
+        return 0
+    fi
+    _s3find "$@"
+}
+
+complete -F _s3find_prefix -o bashdefault -o default s3find
+"#;
+
+const ZSH_PREFIX_COMPLETION: &str = r#"
+_s3find_prefix() {
+    local -a candidates
+    candidates=(${(f)"$(s3find __complete-prefix "$PREFIX" 2>/dev/null)"})
+    compadd -S '' -- $candidates
+}
+
+"#;
+
+// Complete s3:// paths with the hidden __complete-prefix helper
+fn complete_prefixes(outdir: &OsStr) {
+    let bash = Path::new(outdir).join("s3find.bash");
+    let script = fs::read_to_string(&bash).unwrap();
+    fs::write(&bash, script + BASH_PREFIX_COMPLETION).unwrap();
+
+    let zsh = Path::new(outdir).join("_s3find");
+    let script = fs::read_to_string(&zsh).unwrap();
+    let script = script
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("':path -- ") {
+                line.replace(":_files'", ":_s3find_prefix'")
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let script = script.replace(
+        "\n_s3find \"$@\"",
+        &format!("{}_s3find \"$@\"", ZSH_PREFIX_COMPLETION),
+    );
+    fs::write(&zsh, script + "\n").unwrap();
 }
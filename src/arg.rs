@@ -1,7 +1,12 @@
 use aws_types::region::Region;
 use glob::Pattern;
 use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 use thiserror::Error;
@@ -9,6 +14,69 @@ use thiserror::Error;
 fn region(s: &str) -> Region {
     Region::new(s.to_owned())
 }
+
+fn sample_rate(s: &str) -> Result<f64, anyhow::Error> {
+    match s.parse() {
+        Ok(rate) if rate > 0.0 && rate <= 1.0 => Ok(rate),
+        _ => Err(FindError::SampleRateParse.into()),
+    }
+}
+
+fn percent(s: &str) -> Result<f64, anyhow::Error> {
+    match s.parse() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        _ => Err(FindError::PercentParse.into()),
+    }
+}
+
+fn prefetch(s: &str) -> Result<usize, anyhow::Error> {
+    match s.parse() {
+        Ok(pages) if pages > 0 => Ok(pages),
+        _ => Err(FindError::PrefetchParse.into()),
+    }
+}
+
+/// Age in days with an optional d suffix
+fn age_days(s: &str) -> Result<i64, anyhow::Error> {
+    match s.strip_suffix('d').unwrap_or(s).parse() {
+        Ok(days) if days > 0 => Ok(days),
+        _ => Err(FindError::AgeDaysParse.into()),
+    }
+}
+
+fn gzip_level(s: &str) -> Result<u32, anyhow::Error> {
+    match s.parse() {
+        Ok(level) if level <= 9 => Ok(level),
+        _ => Err(FindError::GzipLevelParse.into()),
+    }
+}
+
+/// Suffix of the keys written by recompress, an empty suffix would write
+/// over the source key
+fn key_suffix(s: &str) -> Result<String, anyhow::Error> {
+    if s.is_empty() {
+        return Err(FindError::SuffixEmpty.into());
+    }
+    Ok(s.to_owned())
+}
+
+/// Storage class a lifecycle rule can transition to
+fn transition_class(s: &str) -> Result<String, anyhow::Error> {
+    let class = s.to_uppercase();
+    match class.as_str() {
+        "STANDARD" | "REDUCED_REDUNDANCY" => Err(FindError::StorageClassParse(class).into()),
+        _ if STORAGE_CLASSES.contains(&class.as_str()) => Ok(class),
+        _ => Err(FindError::StorageClassParse(class).into()),
+    }
+}
+
+/// Size in bytes with an optional k, M, G, T or P suffix
+fn size_bytes(s: &str) -> Result<u64, anyhow::Error> {
+    match s.parse()? {
+        FindSize::Equal(bytes) => Ok(bytes as u64),
+        _ => Err(FindError::SizeParse.into()),
+    }
+}
 /// Walk an Amazon S3 path hierarchy
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(
@@ -27,8 +95,32 @@ The authorization flow is the following chain:
 )]
 pub struct FindOpt {
     /// S3 path to walk through. It should be s3://bucket/path
-    #[structopt(name = "path")]
-    pub path: S3Path,
+    #[structopt(name = "path", required_unless_one = &["stdin", "dump-cli-schema"])]
+    pub path: Option<S3Path>,
+
+    /// Read s3:// URLs of keys from stdin, one per line, instead of listing
+    /// a path
+    #[structopt(
+        name = "stdin",
+        long = "stdin",
+        conflicts_with = "path",
+        long_help = r#"Read s3:// URLs of keys from stdin, one per line, instead of listing
+a path, e.g. the output of ls of another run:
+    s3find s3://bucket/logs/ --name '*.log' ls | grep -v keep | s3find --stdin delete
+The keys may be in several buckets, the command runs bucket by bucket.
+A key is sent a HEAD request only when a filter or the command needs more
+than its name. Invalid lines are reported with their number and skipped."#
+    )]
+    pub stdin: bool,
+
+    /// Stop at the first invalid line of --stdin instead of skipping it
+    #[structopt(name = "strict", long = "strict", requires = "stdin")]
+    pub strict: bool,
+
+    /// The URLs of --stdin end with a NUL byte instead of a newline, like
+    /// the output of ls --print0
+    #[structopt(name = "null", long = "null", short = "0", requires = "stdin")]
+    pub null: bool,
 
     /// AWS access key. Unrequired.
     #[structopt(
@@ -46,22 +138,98 @@ pub struct FindOpt {
     )]
     pub aws_secret_key: Option<String>,
 
-    /// The region to use. Default value is us-east-1
-    #[structopt(name = "aws-region", long = "aws-region", default_value = "us-east-1", parse(from_str = region))]
-    pub aws_region: Region,
+    /// Command printing credentials in the credential_process JSON format,
+    /// re-run when the credentials near expiration
+    #[structopt(
+        name = "credentials-command",
+        long = "credentials-command",
+        conflicts_with = "aws-access-key"
+    )]
+    pub credentials_command: Option<String>,
+
+    /// Do not sign requests, for anonymous access to public buckets
+    #[structopt(
+        name = "no-sign-request",
+        long = "no-sign-request",
+        conflicts_with_all = &["aws-access-key", "credentials-command"]
+    )]
+    pub no_sign_request: bool,
+
+    /// The region to use. Default value is taken from AWS_REGION, the profile
+    /// or the instance metadata
+    #[structopt(name = "aws-region", long = "aws-region", parse(from_str = region))]
+    pub aws_region: Option<Region>,
 
     /// Glob pattern for match, can be multiple
-    #[structopt(name = "npatern", long = "name", number_of_values = 1)]
+    #[structopt(
+        name = "npatern",
+        long = "name",
+        number_of_values = 1,
+        parse(try_from_str = glob_pattern),
+        long_help = r#"Glob pattern for match, can be multiple.
+`*` matches any sequence, `?` any character and `[...]` a character class.
+A backslash makes the next `*`, `?`, `[`, `]` or `\` literal, outside of
+character classes only; any other backslash is a literal backslash.
+Examples:
+    --name 'report\[2024\].csv'
+    --name '*\*draft\*'"#
+    )]
     pub name: Vec<NameGlob>,
 
-    /// Case-insensitive glob pattern for match, can be multiple
+    /// Case-insensitive glob pattern for match, can be multiple, escaped like --name.
+    /// Only ASCII letters are compared case-insensitively
     #[structopt(name = "ipatern", long = "iname", number_of_values = 1)]
     pub iname: Vec<InameGlob>,
 
+    /// Where --name and --iname patterns match: anywhere matches the whole
+    /// key, prefix matches the key below the search prefix, like find -name
+    /// below the starting directory
+    #[structopt(name = "anchor", long = "anchor", default_value = "anywhere")]
+    pub anchor: NameAnchor,
+
+    /// Key or trailing key segments to match exactly, without glob
+    /// interpretation, can be multiple
+    #[structopt(name = "literal", long = "name-literal", number_of_values = 1)]
+    pub name_literal: Vec<NameLiteral>,
+
+    /// Extensions of the key name to match, comma-separated and
+    /// case-insensitive, e.g. parquet,csv
+    #[structopt(
+        name = "ext",
+        long = "ext",
+        conflicts_with = "no-ext",
+        long_help = r#"Extensions of the key name to match, comma-separated and
+case-insensitive, e.g. parquet,csv. The extension is the part of the name
+after its last dot: data.tar.gz has the extension gz, and a hidden name
+like .profile has none."#
+    )]
+    pub ext: Option<Extensions>,
+
+    /// Match the keys whose name has no extension, like README or .profile
+    #[structopt(name = "no-ext", long = "no-ext")]
+    pub no_ext: bool,
+
     /// Regex pattern for match, can be multiple
     #[structopt(name = "rpatern", long = "regex", number_of_values = 1)]
     pub regex: Vec<Regex>,
 
+    /// Filter expression combining predicates with and, or, not and parentheses
+    #[structopt(
+        name = "where",
+        long = "where",
+        long_help = r#"Filter expression combining predicates with and, or, not
+and parentheses; not binds tighter than and, and tighter than or.
+Predicates:
+    name=GLOB, iname=GLOB, regex=REGEX - like --name, --iname, --regex
+    size=SIZE, mtime=TIME - like --size and --mtime, e.g. size=+1G
+    storage_class=CLASS - e.g. storage_class=GLACIER
+    depth=N, depth<N, depth>N - the number of / in the key
+    etag=ETAG - the ETag, with or without quotes
+Values with spaces or parentheses are quoted with ' or ". Example:
+    --where '(name=*.log and size=+1G) or storage_class=GLACIER'"#
+    )]
+    pub where_expr: Option<WhereExpr>,
+
     /// Modification time for match
     #[structopt(
         name = "time",
@@ -79,10 +247,21 @@ Possible time units are as follows:
     d - days
     w - weeks
 
-Can be multiple, but should be overlaping"#
+Can be multiple, but should be overlaping. Now is the start of the run,
+every key is tested against the same instant however long the run takes."#
     )]
     pub mtime: Vec<FindTime>,
 
+    /// Check --mtime and the mtime predicates of --where again against the
+    /// current time before each batch of a command which changes objects,
+    /// the keys which no longer match are skipped and counted
+    #[structopt(long = "reevaluate-mtime-at-action")]
+    pub reevaluate_mtime_at_action: bool,
+
+    /// The instant the --mtime periods are measured from
+    #[structopt(skip)]
+    pub reference_time: ReferenceTime,
+
     /// File size for match
     #[structopt(
         name = "bytes-size",
@@ -103,6 +282,51 @@ Possible file size units are as follows:
     )]
     pub size: Vec<FindSize>,
 
+    /// Exclude keys starting with any prefix listed in the file
+    #[structopt(
+        name = "exclude-prefixes-from",
+        long = "exclude-prefixes-from",
+        long_help = r#"Exclude keys starting with any prefix listed in the file.
+One key prefix per line, blank lines and lines starting
+with '#' are ignored."#
+    )]
+    pub exclude_prefixes: Option<ExcludePrefixes>,
+
+    /// Match only keys starting with any prefix listed in the file
+    #[structopt(
+        name = "include-prefixes-from",
+        long = "include-prefixes-from",
+        long_help = r#"Match only keys starting with any prefix listed in the file.
+One key prefix per line, blank lines and lines starting
+with '#' are ignored."#
+    )]
+    pub include_prefixes: Option<IncludePrefixes>,
+
+    /// Exclude keys matching the glob patterns of the file, ./.s3findignore
+    /// is used when it exists
+    #[structopt(
+        name = "ignore-file",
+        long = "ignore-file",
+        long_help = r#"Exclude keys matching the glob patterns of the file.
+Without this option ./.s3findignore is used when it exists.
+One glob per line, matched against the whole key relative to
+the search prefix; '*' also matches '/'. A pattern ending
+with '/' matches every key below it. A line starting with '!'
+includes again the keys matched by the lines above it, the
+last matching line wins. Blank lines and lines starting with
+'#' are ignored, '\#' and '\!' start a pattern with '#' or '!'."#
+    )]
+    pub ignore_file: Option<IgnoreFile>,
+
+    /// Do not use ./.s3findignore
+    #[structopt(name = "no-ignore", long = "no-ignore", conflicts_with = "ignore-file")]
+    pub no_ignore: bool,
+
+    /// Version of the single key given by the path to operate on, the listing is skipped.
+    /// Only download, lstags and tags are supported
+    #[structopt(name = "version-id", long = "version-id")]
+    pub version_id: Option<String>,
+
     /// Limit result
     #[structopt(name = "limit", long = "limit")]
     pub limit: Option<usize>,
@@ -114,15 +338,315 @@ Possible file size units are as follows:
         default_value = "1000",
         long_help = r#"The number of results to return in each response to a
 list operation. The default value is 1000 (the maximum
-allowed). Using a lower value may help if an operation
-times out."#
+allowed). A list request which times out is retried with
+half the page size, down to 50, and the page size is
+doubled back after 20 pages listed in a row."#
     )]
     pub page_size: i64,
 
+    /// The number of listing pages requested ahead of the filters and the
+    /// command, 1 lists the next page only when the current one is done
+    #[structopt(
+        name = "prefetch",
+        long = "prefetch",
+        default_value = "1",
+        parse(try_from_str = prefetch)
+    )]
+    pub prefetch: usize,
+
+    /// The listing API: v2 is ListObjectsV2, v1 is ListObjects for
+    /// S3-compatible services without ListObjectsV2, auto falls back to v1
+    /// when the first ListObjectsV2 request is not implemented
+    #[structopt(name = "list-api", long = "list-api", default_value = "auto")]
+    pub list_api: ListApi,
+
+    /// List only the keys directly under the path, with a / delimiter and
+    /// without recursing; ls and print show the prefixes under the path as
+    /// PRE lines, the filters test the keys only
+    #[structopt(long = "one-level", conflicts_with = "cache-dir")]
+    pub one_level: bool,
+
+    /// Color the text output of ls and print: auto colors it when stdout
+    /// is a terminal and NO_COLOR is not set
+    #[structopt(
+        name = "color",
+        long = "color",
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"]
+    )]
+    pub color: ColorMode,
+
+    /// Skip the keys matched more than once in a run
+    #[structopt(
+        name = "dedup",
+        long = "dedup",
+        default_value = "none",
+        possible_values = &["key", "key-version", "none"],
+        long_help = r#"Skip the keys matched more than once in a run, e.g. the
+same key read twice with --stdin:
+    key - a key is passed to the command once
+    key-version - a key is passed once per version, told apart by its
+                  ETag and modification time
+    none - every matched key is passed to the command
+Up to a million keys are remembered exactly. Above that the seen keys are
+kept in a bloom filter of 16 MiB, which is probabilistic: a few distinct
+keys may be skipped as duplicates."#
+    )]
+    pub dedup: DedupMode,
+
     /// Print summary statistic
     #[structopt(name = "summarize", long = "summarize")]
     pub summarize: bool,
 
+    /// Boundaries of the object size histogram of --summarize, ascending
+    /// sizes like 128k,1M; a size equal to a boundary is counted above it
+    #[structopt(long = "size-buckets", default_value = "128k,1M,16M,128M,1G")]
+    pub size_buckets: SizeBuckets,
+
+    /// Print the listing, filters, command requests and safety limits of the
+    /// invocation instead of running it
+    #[structopt(name = "explain", long = "explain")]
+    pub explain: bool,
+
+    /// Print the options and subcommands as JSON for tools which generate
+    /// wrappers or docs, see schema::cli_schema
+    #[structopt(name = "dump-cli-schema", long = "dump-cli-schema", hidden = true)]
+    pub dump_cli_schema: bool,
+
+    /// Show the listing progress on stderr while stdout is a terminal
+    #[structopt(name = "progress", long = "progress")]
+    pub progress: bool,
+
+    /// Show the listing progress on stderr even when stdout is piped
+    #[structopt(name = "progress-always", long = "progress-always")]
+    pub progress_always: bool,
+
+    /// Write the progress as JSON lines to this open file descriptor of a
+    /// supervising process (Unix only)
+    #[structopt(name = "progress-fd", long = "progress-fd")]
+    pub progress_fd: Option<i32>,
+
+    /// Write the progress as JSON lines to this Unix socket of a supervising
+    /// process (Unix only)
+    #[structopt(
+        name = "progress-socket",
+        long = "progress-socket",
+        conflicts_with = "progress-fd"
+    )]
+    pub progress_socket: Option<PathBuf>,
+
+    /// Seconds between two events of --progress-fd or --progress-socket
+    #[structopt(
+        name = "progress-interval",
+        long = "progress-interval",
+        default_value = "5"
+    )]
+    pub progress_interval: u64,
+
+    /// Directory of the listing cache: a run repeated on the same path within
+    /// --cache-ttl reads the listing from it
+    #[structopt(name = "cache-dir", long = "cache-dir")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// How long a cached listing is served, like 10m or 1h
+    #[structopt(name = "cache-ttl", long = "cache-ttl", default_value = "10m")]
+    pub cache_ttl: CacheTtl,
+
+    /// Print the object count and size of the bucket from its CloudWatch
+    /// storage metrics before listing, needs a build with the cloudwatch
+    /// feature
+    #[structopt(name = "estimate", long = "estimate")]
+    pub estimate: bool,
+
+    /// Estimated object count above which --estimate warns of a run without
+    /// --limit or filters
+    #[structopt(
+        name = "estimate-threshold",
+        long = "estimate-threshold",
+        default_value = "10000000"
+    )]
+    pub estimate_threshold: u64,
+
+    /// Print a header line to stderr before every batch the command runs on
+    #[structopt(name = "batch-headers", long = "batch-headers")]
+    pub batch_headers: bool,
+
+    /// Emit run metrics to statsd or as CloudWatch EMF
+    #[structopt(
+        name = "metrics",
+        long = "metrics",
+        long_help = r#"Emit run metrics at the end of the run:
+    statsd://host:port - send statsd counters over UDP
+    emf:stdout - print a CloudWatch Embedded Metric Format line
+
+Metrics (statsd name / EMF name):
+    s3find.objects.scanned / ObjectsScanned
+    s3find.objects.matched / ObjectsMatched
+    s3find.bytes.matched / BytesMatched
+    s3find.objects.deleted / ObjectsDeleted
+    s3find.bytes.downloaded / BytesDownloaded
+    s3find.requests / Requests
+    s3find.duration / Duration (milliseconds)
+
+Dimensions: bucket, prefix, command"#
+    )]
+    pub metrics: Option<MetricsTarget>,
+
+    /// Write the run metrics to a node_exporter textfile at the end of the run
+    #[structopt(
+        name = "prom-textfile",
+        long = "prom-textfile",
+        parse(from_os_str),
+        long_help = r#"Write the run metrics at the end of the run to a
+node_exporter textfile, like /var/lib/node_exporter/textfile/s3find_job.prom.
+The file is replaced at once through a temporary file next to it.
+
+Metrics, labeled with bucket, prefix and command:
+    s3find_objects_scanned_total
+    s3find_objects_matched_total
+    s3find_bytes_total
+    s3find_requests_total{api="list|get|put|delete"}
+    s3find_errors_total
+    s3find_duration_seconds"#
+    )]
+    pub prom_textfile: Option<PathBuf>,
+
+    /// Write a run report at the end of the run, Markdown for .md and HTML for .html
+    #[structopt(name = "report-file", long = "report-file")]
+    pub report_file: Option<ReportFile>,
+
+    /// Stop the run after this many S3 requests
+    #[structopt(
+        name = "max-requests",
+        long = "max-requests",
+        long_help = r#"Stop the run after this many S3 requests. No more
+pages are listed once the budget is reached, the batch in flight
+is finished and the run exits with code 3."#
+    )]
+    pub max_requests: Option<u64>,
+
+    /// Stop the run after the estimated request cost in USD
+    #[structopt(
+        name = "max-cost-usd",
+        long = "max-cost-usd",
+        long_help = r#"Stop the run once the estimated request cost reaches
+this amount in USD. The estimate uses S3 Standard request prices:
+$0.005 per 1000 PUT, COPY, POST and LIST requests, $0.0004 per 1000
+GET, HEAD and other requests, DELETE requests are free."#
+    )]
+    pub max_cost_usd: Option<f64>,
+
+    /// Stop the run after this period, like 50m or 2h
+    #[structopt(
+        name = "max-duration",
+        long = "max-duration",
+        long_help = r#"Stop the run after this period, like 50m or 2h. The
+timer starts at launch, the time spent waiting at a confirmation
+prompt counts. No more pages are listed once the period is over,
+the batch in flight is finished, the partial summary is printed and
+the run exits with code 4."#
+    )]
+    pub max_duration: Option<MaxDuration>,
+
+    /// Post a JSON event to the URL after every batch of changed keys
+    #[structopt(
+        name = "notify-url",
+        long = "notify-url",
+        long_help = r#"Post a JSON event to the http(s) URL after every batch
+of keys deleted, moved, tagged or changed to another storage class.
+A failed notification is retried and reported, the change is kept.
+The event schema is described in the README."#
+    )]
+    pub notify_url: Option<NotifyUrl>,
+
+    /// Publish the JSON event to the SNS topic after every batch of changed keys
+    #[structopt(
+        name = "notify-sns",
+        long = "notify-sns",
+        long_help = r#"Publish the JSON event of --notify-url to the SNS topic
+arn:aws:sns:<region>:<account>:<topic> after every batch of changed
+keys, with the credentials of the run. Needs a build with the sns
+feature, a failed publish is retried and reported like a failed post."#
+    )]
+    pub notify_sns: Option<SnsTopic>,
+
+    /// Upload an S3 Batch Operations manifest of the matched objects to s3://bucket/key
+    #[structopt(
+        name = "emit-manifest",
+        long = "emit-manifest",
+        conflicts_with = "manifest-local"
+    )]
+    pub emit_manifest: Option<S3Path>,
+
+    /// Write the manifest of the matched objects to a local file
+    #[structopt(name = "manifest-local", long = "manifest-local")]
+    pub manifest_local: Option<PathBuf>,
+
+    /// Manifest format: csv (bucket,key[,versionId] lines) or json (one object per line)
+    #[structopt(
+        name = "manifest-format",
+        long = "manifest-format",
+        default_value = "csv"
+    )]
+    pub manifest_format: ManifestFormat,
+
+    /// Process a random sample of the listed keys with this probability, e.g. 0.01
+    #[structopt(
+        name = "sample-rate",
+        long = "sample-rate",
+        parse(try_from_str = sample_rate),
+        long_help = r#"Process a random sample of the listed keys, every key is
+selected with this probability, e.g. 0.01. The whole prefix is still
+listed, the sample is taken before the filters and the command.
+The summary adds estimates for the whole prefix scaled by the rate."#
+    )]
+    pub sample_rate: Option<f64>,
+
+    /// Seed of the sample, the same seed selects the same keys in every run
+    #[structopt(name = "sample-seed", long = "sample-seed", requires = "sample-rate")]
+    pub sample_seed: Option<u64>,
+
+    /// Process only the shard i of n of the keys, for n cooperating runs
+    /// numbered from 0, e.g. 2/8
+    #[structopt(
+        name = "shard",
+        long = "shard",
+        long_help = r#"Process only the shard i of n of the keys, for n cooperating
+runs numbered from 0, e.g. 2/8. Every run lists the whole prefix and keeps
+the keys whose hash falls in its shard, unless --shard-by-range."#
+    )]
+    pub shard: Option<Shard>,
+
+    /// Split the keys of --shard into ranges found by listing the top level
+    /// below the prefix, so every run lists only its range
+    #[structopt(
+        name = "shard-by-range",
+        long = "shard-by-range",
+        requires = "shard",
+        conflicts_with = "cache-dir"
+    )]
+    pub shard_by_range: bool,
+
+    /// Match only the oldest N percent of the otherwise matched objects by
+    /// modification time, found by an extra listing pass
+    #[structopt(
+        name = "oldest-percent",
+        long = "oldest-percent",
+        conflicts_with = "version-id",
+        parse(try_from_str = percent)
+    )]
+    pub oldest_percent: Option<f64>,
+
+    /// Match only the largest N percent of the otherwise matched objects by
+    /// size, found by an extra listing pass
+    #[structopt(
+        name = "largest-percent",
+        long = "largest-percent",
+        conflicts_with = "version-id",
+        parse(try_from_str = percent)
+    )]
+    pub largest_percent: Option<f64>,
+
     //  /// Action to be ran with matched list of paths
     #[structopt(subcommand)]
     pub cmd: Option<Cmd>,
@@ -154,6 +678,10 @@ pub enum Cmd {
     #[structopt(name = "move")]
     Move(S3Move),
 
+    /// Print the target keys copy or move would write, without copying
+    #[structopt(name = "plan-copy")]
+    PlanCopy(PlanCopy),
+
     /// Print the list of matched keys
     #[structopt(name = "ls")]
     Ls(FastPrint),
@@ -170,38 +698,303 @@ pub enum Cmd {
     #[structopt(name = "public")]
     Public(SetPublic),
 
+    /// Report or fix objects whose storage class differs from the rules
+    #[structopt(name = "enforce-storage")]
+    EnforceStorage(EnforceStorage),
+
+    /// Preview what lifecycle rules would do to the matched keys today
+    #[structopt(name = "simulate-lifecycle")]
+    SimulateLifecycle(SimulateLifecycle),
+
+    /// Copy or move matched keys to destinations chosen by their tags
+    #[structopt(name = "route")]
+    Route(TagRoute),
+
+    /// Propose a lifecycle configuration from the ages of the matched keys
+    #[structopt(name = "suggest-lifecycle")]
+    SuggestLifecycle(SuggestLifecycle),
+
+    /// Move matched keys under a dated trash prefix of the bucket
+    #[structopt(name = "trash")]
+    Trash(Trash),
+
+    /// Move trashed keys back to their original keys
+    #[structopt(name = "untrash")]
+    Untrash(Untrash),
+
+    /// Gzip matched keys in place or to new keys with a suffix
+    #[structopt(name = "recompress")]
+    Recompress(Recompress),
+
+    /// Copy matched keys onto themselves with the same metadata, so their
+    /// modification time changes and S3 sends new ObjectCreated events
+    #[structopt(name = "touch")]
+    Touch(Touch),
+
+    /// Compare the tags of matched keys with the keys of another path
+    #[structopt(name = "diff-tags")]
+    DiffTags(DiffTags),
+
+    /// Compare the listed size and ETag of matched keys with a HEAD request
+    #[structopt(name = "verify")]
+    Verify(Verify),
+
+    /// Report the ACL grants of matched keys besides the owner
+    #[structopt(name = "acl-report")]
+    AclReport(AclReport),
+
+    /// Export matched keys, and optionally their tags, into a SQLite database
+    #[structopt(name = "export")]
+    Export(Export),
+
+    /// List the incomplete multipart uploads under the path instead of the keys
+    #[structopt(name = "list-multipart")]
+    ListMultipart(ListMultipart),
+
+    /// Request the restore of matched archived keys, optionally recording
+    /// every request in a ledger to resume from
+    #[structopt(name = "restore")]
+    Restore(Restore),
+
+    /// Reports over the matched keys, printed once the listing is done
+    #[structopt(name = "report")]
+    Report(Report),
+
     /// Do not do anything with keys, do not print them as well
     #[structopt(name = "nothing")]
     Nothing(DoNothing),
 }
 
+impl FindOpt {
+    /// Uses ./.s3findignore when it exists, unless --ignore-file or
+    /// --no-ignore is given
+    pub fn discover_ignore_file(&mut self) -> Result<(), FindError> {
+        if self.ignore_file.is_none()
+            && !self.no_ignore
+            && std::path::Path::new(DEFAULT_IGNORE_FILE).is_file()
+        {
+            self.ignore_file = Some(IgnoreFile::from_file(DEFAULT_IGNORE_FILE)?);
+        }
+        Ok(())
+    }
+
+    /// Checks the option combinations which cannot be expressed by structopt.
+    /// It runs right after parsing, before any client is built, and is the
+    /// place for every new rule between options: a combination which would
+    /// only warn or misbehave at run time is refused here instead.
+    /// Whether a filter compares the modification time, --mtime or an
+    /// mtime predicate of --where
+    pub fn tests_mtime(&self) -> bool {
+        !self.mtime.is_empty() || self.where_expr.as_ref().is_some_and(WhereExpr::tests_mtime)
+    }
+
+    pub fn validate(&self) -> Result<(), FindError> {
+        if self.estimate && self.no_sign_request {
+            return Err(FindError::EstimateUnsigned);
+        }
+        if self.notify_sns.is_some() && self.no_sign_request {
+            return Err(FindError::NotifySnsUnsigned);
+        }
+
+        if self.stdin {
+            if self.version_id.is_some() {
+                return Err(FindError::StdinOption("--version-id"));
+            }
+            if self.oldest_percent.is_some() || self.largest_percent.is_some() {
+                return Err(FindError::StdinOption(
+                    "--oldest-percent and --largest-percent",
+                ));
+            }
+            if self.estimate {
+                return Err(FindError::StdinOption("--estimate"));
+            }
+            if self.shard_by_range {
+                return Err(FindError::StdinOption("--shard-by-range"));
+            }
+            if self.one_level {
+                return Err(FindError::StdinOption("--one-level"));
+            }
+            if let Some(Cmd::ListMultipart(_)) = &self.cmd {
+                return Err(FindError::StdinOption("list-multipart"));
+            }
+        }
+
+        if self.version_id.is_some() {
+            match self.path.as_ref().and_then(S3Path::key) {
+                Some(key) if !key.contains(&['*', '?', '['][..]) => {}
+                _ => return Err(FindError::VersionIdPath),
+            }
+
+            match self.cmd {
+                Some(Cmd::Download(_)) | Some(Cmd::LsTags(_)) | Some(Cmd::Tags(_)) => {}
+                _ => return Err(FindError::VersionIdCommand),
+            }
+        }
+
+        if let Some(path) = &self.emit_manifest {
+            if path.key().is_none() {
+                return Err(FindError::ManifestKey);
+            }
+        }
+
+        if let Some(Cmd::ListMultipart(_)) = &self.cmd {
+            // uploads have no size until they are completed
+            if !self.size.is_empty() {
+                return Err(FindError::MultipartOption("--size"));
+            }
+            if self.oldest_percent.is_some() || self.largest_percent.is_some() {
+                return Err(FindError::MultipartOption(
+                    "--oldest-percent and --largest-percent",
+                ));
+            }
+            if self.emit_manifest.is_some() || self.manifest_local.is_some() {
+                return Err(FindError::MultipartOption("--emit-manifest"));
+            }
+            if self.shard_by_range {
+                return Err(FindError::MultipartOption("--shard-by-range"));
+            }
+            if self.one_level {
+                return Err(FindError::MultipartOption("--one-level"));
+            }
+        }
+
+        if self.reevaluate_mtime_at_action && !self.tests_mtime() {
+            return Err(FindError::ReevaluateWithoutMtime);
+        }
+
+        if let Some(Cmd::Public(SetPublic {
+            acknowledged: false,
+            ..
+        })) = &self.cmd
+        {
+            return Err(FindError::PublicNotAcknowledged);
+        }
+
+        if let Some(Cmd::Tags(SetTags { tags, .. })) = &self.cmd {
+            if tags.len() > MAX_OBJECT_TAGS {
+                return Err(FindError::TooManyTags(tags.len()));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Default for Cmd {
     fn default() -> Self {
-        Cmd::Ls(FastPrint {})
+        Cmd::Ls(FastPrint::default())
     }
 }
 
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct FastPrint {}
+#[derive(StructOpt, Debug, PartialEq, Clone, Default)]
+pub struct FastPrint {
+    /// Print every URL as it is listed followed by a NUL byte, without
+    /// escaping its key, for --stdin --null; no PRE line is printed
+    #[structopt(long = "print0")]
+    pub print0: bool,
 
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct AdvancedPrint {}
+    #[structopt(skip)]
+    pub color: bool,
+}
 
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct MultipleDelete {}
+#[derive(StructOpt, Debug, PartialEq, Clone, Default)]
+pub struct AdvancedPrint {
+    /// Write the output to a file instead of stdout, gzip compressed when
+    /// the name ends with .gz
+    #[structopt(name = "output-file", long = "output-file", parse(from_os_str))]
+    pub output_file: Option<PathBuf>,
+
+    /// Upload the output to a S3 key in parts as it is printed, {date} is
+    /// the UTC date the run started
+    #[structopt(long = "output-s3", conflicts_with = "output-file")]
+    pub output_s3: Option<OutputKey>,
+
+    /// Print sizes in binary units, like 1.50 KiB, right-aligned
+    #[structopt(long = "human-readable")]
+    pub human_readable: bool,
+
+    /// Format of the modification time
+    #[structopt(
+        long = "date-format",
+        default_value = "iso",
+        long_help = r#"Format of the modification time:
+    iso - 2024-03-31T01:30:00Z, with the offset of the zone outside UTC
+    rfc2822 - Sun, 31 Mar 2024 01:30:00 +0000
+    epoch - seconds since 1970-01-01 UTC
+    a strftime pattern, like '%Y-%m-%d %H:%M %Z'"#
+    )]
+    pub date_format: DateFormat,
+
+    /// Time zone of the modification time: UTC, local or an IANA name like
+    /// Europe/Berlin
+    #[structopt(long = "timezone", default_value = "UTC")]
+    pub timezone: Timezone,
+
+    #[structopt(skip)]
+    pub color: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone, Default)]
+pub struct MultipleDelete {
+    /// Skip keys which were modified after they were listed
+    #[structopt(long = "if-unmodified")]
+    pub if_unmodified: bool,
+
+    /// Delete every key on its own, only while it has the ETag it was listed
+    /// with, so a key uploaded again after the listing is kept
+    #[structopt(long = "snapshot-guard", conflicts_with = "if-unmodified")]
+    pub snapshot_guard: bool,
+
+    /// Do not warn that a versioned bucket keeps the deleted versions
+    #[structopt(long = "quiet-versioning-warning")]
+    pub quiet_versioning_warning: bool,
+
+    #[structopt(skip)]
+    pub if_match_unsupported: IfMatchUnsupported,
+}
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct ListTags {}
+pub struct ListTags {
+    #[structopt(skip)]
+    pub version_id: Option<String>,
+}
 
 // region ?
 #[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct SetPublic {}
+pub struct SetPublic {
+    /// Acknowledge that the matched keys become readable by anyone
+    #[structopt(long = "i-understand-this-makes-objects-public")]
+    pub acknowledged: bool,
+
+    #[structopt(skip)]
+    pub blocked: AclsBlocked,
+}
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
 pub struct Exec {
     /// Utility(program) to run
     #[structopt(name = "utility")]
     pub utility: String,
+
+    /// Download every key to a temporary file, substituted for {file} in the
+    /// utility; the file is removed once the utility exits
+    #[structopt(long = "download-temp")]
+    pub download_temp: bool,
+
+    /// Skip the keys bigger than this size with --download-temp, e.g. 100M
+    #[structopt(long = "max-size", requires = "download-temp", parse(try_from_str = size_bytes))]
+    pub max_size: Option<u64>,
+
+    /// Pass a key starting with - as it is, without the -- argument put
+    /// before it
+    #[structopt(
+        long = "no-option-guard",
+        long_help = r#"Pass a key starting with - as it is. By default the first {} standing as a
+word of its own is preceded by a -- argument when the key starts with -, so
+the utility does not take the key for an option. A word with the key is one
+argument, even when the key has spaces. A placeholder inside a word, like
+--input={}, is never guarded and is the responsibility of the utility."#
+    )]
+    pub no_option_guard: bool,
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
@@ -213,17 +1006,110 @@ pub struct Download {
     /// Directory destination to download files to
     #[structopt(name = "destination")]
     pub destination: String,
+
+    /// Download only the first N bytes of every key into a .partial file
+    #[structopt(long = "range-bytes", conflicts_with = "range")]
+    pub range_bytes: Option<u64>,
+
+    /// Download only the start-end byte range of every key into a .partial file
+    #[structopt(name = "range", long = "range")]
+    pub range: Option<ByteRange>,
+
+    /// Take the size of the keys listed with a size of 0 from a HEAD request
+    /// and check the downloaded length against the size
+    #[structopt(long = "verify-size-with-head")]
+    pub verify_size_with_head: bool,
+
+    #[structopt(skip)]
+    pub version_id: Option<String>,
+}
+
+impl Download {
+    /// The byte range requested by --range-bytes or --range
+    pub fn byte_range(&self) -> Option<ByteRange> {
+        self.range_bytes
+            .map(|bytes| ByteRange {
+                start: 0,
+                end: bytes.saturating_sub(1),
+            })
+            .or(self.range)
+    }
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
 pub struct S3Copy {
-    /// S3 path destination to copy files to
-    #[structopt(name = "destination")]
-    pub destination: S3Path,
+    /// S3 path or local directory destination to copy files to
+    #[structopt(
+        name = "destination",
+        long_help = r#"Destination to copy files to:
+    s3://bucket/path - copy the keys to another s3 location
+    /local/dir or file:///local/dir - download the keys into an existing directory"#
+    )]
+    pub destination: CopyDestination,
 
     /// Copy keys like files
     #[structopt(long = "flat", short = "f")]
     pub flat: bool,
+
+    /// Do not check that the destination is writable before listing
+    #[structopt(long = "no-preflight")]
+    pub no_preflight: bool,
+
+    /// Skip keys which were modified after they were listed
+    #[structopt(long = "if-unmodified")]
+    pub if_unmodified: bool,
+
+    /// Refuse a destination inside the source prefix instead of skipping
+    /// the keys under the destination
+    #[structopt(long = "no-auto-exclude")]
+    pub no_auto_exclude: bool,
+
+    /// Request the restore of archived source keys, GLACIER and
+    /// DEEP_ARCHIVE, which are copied by a later run once restored
+    #[structopt(long = "restore-and-retry")]
+    pub restore_and_retry: bool,
+
+    /// Days the restored copy of an archived key stays readable
+    #[structopt(long = "restore-days", default_value = "1")]
+    pub restore_days: i32,
+
+    /// Wait for the requested restores and copy the keys in the same run
+    #[structopt(long = "wait", requires = "restore-and-retry")]
+    pub wait: bool,
+
+    /// What to do when two keys map to the same target key
+    #[structopt(
+        long = "collision-strategy",
+        default_value = "error",
+        possible_values = &["error", "skip", "suffix"],
+        long_help = r#"What to do when two keys map to the same target key
+(e.g. with --flat):
+    error - report the second key as failed
+    skip - skip the second key
+    suffix - append a numeric disambiguator: key.1, key.2, ..."#
+    )]
+    pub collision_strategy: CollisionStrategy,
+
+    /// The number of copies run at once, or auto to adapt it to the
+    /// throttling of the bucket
+    #[structopt(long = "workers", default_value = "1")]
+    pub workers: Workers,
+
+    /// The order the keys of a batch are copied in
+    #[structopt(
+        long = "schedule",
+        default_value = "listing",
+        possible_values = &["largest-first", "smallest-first", "listing"],
+        long_help = r#"The order the keys of a batch are copied in:
+    largest-first - the largest keys first, so the long transfers do not
+                    trail at the end of the batch
+    smallest-first - the smallest keys first
+    listing - the order of the listing"#
+    )]
+    pub schedule: Schedule,
+
+    #[structopt(skip)]
+    pub targets: TargetTracker,
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
@@ -235,46 +1121,599 @@ pub struct S3Move {
     /// Copy keys like files
     #[structopt(long = "flat", short = "f")]
     pub flat: bool,
-}
 
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct SetTags {
-    /// List of the tags to set
-    #[structopt(name = "key:value", min_values = 1)]
-    pub tags: Vec<FindTag>,
-}
+    /// Do not check that the destination is writable before listing
+    #[structopt(long = "no-preflight")]
+    pub no_preflight: bool,
 
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct DoNothing {}
+    /// Skip keys which were modified after they were listed
+    #[structopt(long = "if-unmodified")]
+    pub if_unmodified: bool,
 
-#[derive(Error, Debug)]
-pub enum FindError {
-    #[error("Invalid s3 path")]
-    S3Parse,
-    #[error("Invalid size parameter")]
-    SizeParse,
-    #[error("Invalid mtime parameter")]
-    TimeParse,
-    #[error("Cannot parse tag")]
-    TagParseError,
-    #[error("Cannot parse tag key")]
-    TagKeyParseError,
-    #[error("Cannot parse tag value")]
-    TagValueParseError,
-}
+    /// Refuse a destination inside the source prefix instead of skipping
+    /// the keys under the destination
+    #[structopt(long = "no-auto-exclude")]
+    pub no_auto_exclude: bool,
 
-#[derive(Debug, Clone, PartialEq)]
+    /// Do not warn that a versioned bucket keeps the moved versions
+    #[structopt(long = "quiet-versioning-warning")]
+    pub quiet_versioning_warning: bool,
+
+    /// Request the restore of archived source keys, GLACIER and
+    /// DEEP_ARCHIVE, which are copied by a later run once restored
+    #[structopt(long = "restore-and-retry")]
+    pub restore_and_retry: bool,
+
+    /// Days the restored copy of an archived key stays readable
+    #[structopt(long = "restore-days", default_value = "1")]
+    pub restore_days: i32,
+
+    /// Wait for the requested restores and copy the keys in the same run
+    #[structopt(long = "wait", requires = "restore-and-retry")]
+    pub wait: bool,
+
+    /// What to do when two keys map to the same target key
+    #[structopt(
+        long = "collision-strategy",
+        default_value = "error",
+        possible_values = &["error", "skip", "suffix"],
+        long_help = r#"What to do when two keys map to the same target key
+(e.g. with --flat):
+    error - report the second key as failed
+    skip - skip the second key
+    suffix - append a numeric disambiguator: key.1, key.2, ..."#
+    )]
+    pub collision_strategy: CollisionStrategy,
+
+    /// The number of copies run at once, or auto to adapt it to the
+    /// throttling of the bucket
+    #[structopt(long = "workers", default_value = "1")]
+    pub workers: Workers,
+
+    /// The order the keys of a batch are moved in
+    #[structopt(
+        long = "schedule",
+        default_value = "listing",
+        possible_values = &["largest-first", "smallest-first", "listing"],
+        long_help = r#"The order the keys of a batch are moved in:
+    largest-first - the largest keys first, so the long transfers do not
+                    trail at the end of the batch
+    smallest-first - the smallest keys first
+    listing - the order of the listing"#
+    )]
+    pub schedule: Schedule,
+
+    /// Copy and verify every key before deleting any source, the copied
+    /// keys are recorded in a marker object next to the destination
+    #[structopt(long = "two-phase")]
+    pub two_phase: bool,
+
+    /// Delete the keys copied by the run when a copy of --two-phase fails
+    #[structopt(long = "rollback-on-failure", requires = "two-phase")]
+    pub rollback_on_failure: bool,
+
+    #[structopt(skip)]
+    pub targets: TargetTracker,
+
+    #[structopt(skip)]
+    pub phases: MovePhases,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct PlanCopy {
+    /// S3 path destination the keys would be copied or moved to
+    #[structopt(name = "destination")]
+    pub destination: S3Path,
+
+    /// Copy keys like files
+    #[structopt(long = "flat", short = "f")]
+    pub flat: bool,
+
+    /// Refuse a destination inside the source prefix instead of skipping
+    /// the keys under the destination
+    #[structopt(long = "no-auto-exclude")]
+    pub no_auto_exclude: bool,
+
+    /// What copy or move would do when two keys map to the same target key
+    #[structopt(
+        long = "collision-strategy",
+        default_value = "error",
+        possible_values = &["error", "skip", "suffix"]
+    )]
+    pub collision_strategy: CollisionStrategy,
+
+    #[structopt(skip)]
+    pub targets: TargetTracker,
+
+    #[structopt(skip)]
+    pub tally: PlanTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SetTags {
+    /// List of the tags to set
+    #[structopt(name = "key:value", min_values = 1)]
+    pub tags: Vec<FindTag>,
+
+    #[structopt(skip)]
+    pub version_id: Option<String>,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct EnforceStorage {
+    /// Storage class rule, the first rule matching a key applies
+    #[structopt(
+        name = "rule",
+        long = "rule",
+        number_of_values = 1,
+        required = true,
+        long_help = r#"Storage class rule: glob=CLASS[@age]
+The glob is matched against the whole key and the first matching rule
+applies to an object; later rules are not considered. With an age the
+rule applies only to objects last modified at least that long ago.
+Examples:
+    --rule 'logs/raw/*=STANDARD_IA@30d'
+    --rule 'logs/archive/*=DEEP_ARCHIVE'"#
+    )]
+    pub rules: Vec<StorageRule>,
+
+    /// Change the storage class of the drifted objects instead of printing them
+    #[structopt(long = "apply")]
+    pub apply: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct TagRoute {
+    /// Routing rule, the first rule matching the key tags applies
+    #[structopt(
+        name = "route",
+        long = "route",
+        number_of_values = 1,
+        required = true,
+        long_help = r#"Routing rule: tagkey=value=>s3://bucket/prefix
+A key tagged with tagkey:value is copied under the destination
+prefix. The first rule matching the key tags applies; later rules
+are not considered. Examples:
+    --route 'dataset=alpha=>s3://lake/alpha/'
+    --route 'dataset=beta=>s3://lake/beta/'"#
+    )]
+    pub routes: Vec<RouteRule>,
+
+    /// Destination for the keys matching no rule, they are skipped without it
+    #[structopt(name = "default", long = "default")]
+    pub default: Option<S3Path>,
+
+    /// Move the keys instead of copying them
+    #[structopt(long = "move")]
+    pub move_keys: bool,
+
+    /// Do not check that the destinations are writable before listing
+    #[structopt(long = "no-preflight")]
+    pub no_preflight: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SimulateLifecycle {
+    /// Lifecycle rule, rules of the same action are evaluated in order
+    #[structopt(
+        name = "rule",
+        long = "rule",
+        number_of_values = 1,
+        required = true,
+        long_help = r#"Lifecycle rule:
+    transition:CLASS:<days>d - transition to the storage class
+    expire:<days>d - expire the key
+The age is counted like S3 does: the action is due at the
+midnight UTC following last modification + days. Rules of the
+same action are evaluated in order and the first due rule wins,
+an expiration wins over a transition."#
+    )]
+    pub rules: Vec<LifecycleRule>,
+
+    /// Print the action for every matched key
+    #[structopt(long = "verbose", short = "v")]
+    pub verbose: bool,
+
+    #[structopt(skip)]
+    pub tally: LifecycleTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SuggestLifecycle {
+    /// The number of key segments below the path prefix grouped under one rule
+    #[structopt(long = "depth", default_value = "1")]
+    pub depth: usize,
+
+    /// Storage class proposed for the keys past the transition age
+    #[structopt(
+        long = "transition-class",
+        default_value = "STANDARD_IA",
+        parse(try_from_str = transition_class)
+    )]
+    pub transition_class: String,
+
+    /// Percent of the keys of a prefix modified more recently than the
+    /// proposed transition age
+    #[structopt(
+        long = "access-percent",
+        default_value = "80",
+        parse(try_from_str = percent)
+    )]
+    pub access_percent: f64,
+
+    /// The smallest transition age proposed, S3 requires 30 days for STANDARD_IA and ONEZONE_IA
+    #[structopt(
+        long = "min-transition-days",
+        default_value = "30",
+        parse(try_from_str = age_days)
+    )]
+    pub min_transition_days: i64,
+
+    /// Propose the expiration of the prefixes whose keys are all older than this age
+    #[structopt(long = "retention", parse(try_from_str = age_days))]
+    pub retention_days: Option<i64>,
+
+    /// Configuration format: json, as taken by the AWS CLI, or xml
+    #[structopt(long = "format", default_value = "json")]
+    pub format: LifecycleFormat,
+
+    /// Write the configuration to the file instead of stdout
+    #[structopt(long = "output-file")]
+    pub output_file: Option<PathBuf>,
+
+    /// Replace the lifecycle configuration of the bucket with the proposed
+    /// one, after a confirmation
+    #[structopt(long = "apply")]
+    pub apply: bool,
+
+    #[structopt(skip)]
+    pub tally: PrefixAgeTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Restore {
+    /// Days the restored copy of an archived key stays readable
+    #[structopt(long = "restore-days", default_value = "1")]
+    pub restore_days: i32,
+
+    /// The number of RestoreObject requests sent at once, or auto to adapt
+    /// it to the throttling of the bucket
+    #[structopt(long = "workers", default_value = "16")]
+    pub workers: Workers,
+
+    /// Append a JSON line per requested key with its outcome to this file;
+    /// the keys it records as initiated are skipped, so an interrupted run
+    /// resumes where it stopped
+    #[structopt(long = "ledger")]
+    pub ledger: Option<PathBuf>,
+
+    #[structopt(skip)]
+    pub tally: RestoreTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub enum Report {
+    /// Histogram of the leading characters of the keys, S3 partitions a
+    /// bucket by key prefix
+    #[structopt(name = "key-distribution")]
+    KeyDistribution(KeyDistribution),
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct KeyDistribution {
+    /// The number of leading characters of a key counted as its prefix
+    #[structopt(long = "prefix-length", default_value = "8")]
+    pub prefix_length: usize,
+
+    /// The number of heaviest prefixes printed
+    #[structopt(long = "top", default_value = "20")]
+    pub top: usize,
+
+    /// Percent of the keys above which a single prefix is flagged as hot
+    #[structopt(
+        long = "hot-percent",
+        default_value = "10",
+        parse(try_from_str = percent)
+    )]
+    pub hot_percent: f64,
+
+    /// The number of distinct prefixes kept, the lighter ones are counted
+    /// under other beyond it
+    #[structopt(long = "max-prefixes", default_value = "100000")]
+    pub max_prefixes: usize,
+
+    #[structopt(skip)]
+    pub histogram: KeyHistogramTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Trash {
+    /// Prefix prepended to the trashed keys, {date} is the current UTC date
+    #[structopt(long = "trash-prefix", default_value = ".trash/{date}/")]
+    pub trash_prefix: TrashPrefix,
+
+    /// Skip keys which were modified after they were listed
+    #[structopt(long = "if-unmodified")]
+    pub if_unmodified: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Untrash {
+    /// Prefix the keys were trashed with, {date} matches any date
+    #[structopt(long = "trash-prefix", default_value = ".trash/{date}/")]
+    pub trash_prefix: TrashPrefix,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Recompress {
+    /// Gzip compression level, from 0 (no compression) to 9 (best)
+    #[structopt(long = "level", default_value = "6", parse(try_from_str = gzip_level))]
+    pub level: u32,
+
+    /// Write the compressed object to the key with this suffix, like .gz,
+    /// instead of replacing the key
+    #[structopt(long = "suffix", parse(try_from_str = key_suffix))]
+    pub suffix: Option<String>,
+
+    /// Delete the original key once the compressed key is verified
+    #[structopt(long = "delete-source", requires = "suffix")]
+    pub delete_source: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Touch {
+    /// User metadata key set to the UTC time of the touch, like touched-at
+    /// for x-amz-meta-touched-at
+    #[structopt(long = "marker")]
+    pub marker: Option<String>,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Export {
+    /// Export format, sqlite needs a build with the sqlite feature
+    #[structopt(long = "format", default_value = "sqlite")]
+    pub format: ExportFormat,
+
+    /// The database file, created or extended
+    #[structopt(long = "output-file")]
+    pub output_file: PathBuf,
+
+    /// Also export the tags of every key, one GetObjectTagging request each
+    #[structopt(long = "include-tags")]
+    pub include_tags: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct DiffTags {
+    /// S3 path to compare with, keys are paired by their key relative to the paths
+    #[structopt(name = "target")]
+    pub target: S3Path,
+
+    /// Difference output format: text or json (one object per line)
+    #[structopt(long = "format", default_value = "text")]
+    pub format: OutputFormat,
+
+    #[structopt(skip)]
+    pub tally: TagDiffTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Verify {
+    /// The number of HEAD requests sent at once, or auto to adapt it to the
+    /// throttling of the bucket
+    #[structopt(long = "workers", default_value = "16")]
+    pub workers: Workers,
+
+    /// Report the keys deleted since listing as discrepancies as well
+    #[structopt(long = "missing")]
+    pub missing: bool,
+
+    /// Take the HEAD size of the keys listed with a size of 0 as their size,
+    /// counted as corrected instead of mismatched
+    #[structopt(long = "verify-size-with-head")]
+    pub verify_size_with_head: bool,
+
+    /// Discrepancy output format: text or json (one object per line)
+    #[structopt(long = "format", default_value = "text")]
+    pub format: OutputFormat,
+
+    #[structopt(skip)]
+    pub tally: VerifyTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct AclReport {
+    /// The number of GetObjectAcl requests sent at once, or auto to adapt it
+    /// to the throttling of the bucket
+    #[structopt(long = "workers", default_value = "16")]
+    pub workers: Workers,
+
+    /// Print only the keys with grants besides the owner
+    #[structopt(long = "only-flagged")]
+    pub only_flagged: bool,
+
+    /// Finding output format: text or json (one object per line)
+    #[structopt(long = "format", default_value = "text")]
+    pub format: OutputFormat,
+
+    #[structopt(skip)]
+    pub tally: AclTally,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct ListMultipart {
+    /// Abort the matched uploads instead of only printing them
+    #[structopt(long = "abort")]
+    pub abort: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct DoNothing {}
+
+/// The errors of the argument parsers, new variants may be added in a
+/// minor release
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum FindError {
+    #[error("Invalid s3 path")]
+    S3Parse,
+    #[error("Invalid size parameter")]
+    SizeParse,
+    #[error("Invalid mtime parameter")]
+    TimeParse,
+    #[error("Cannot parse tag")]
+    TagParseError,
+    #[error("Cannot parse tag key")]
+    TagKeyParseError,
+    #[error("Cannot parse tag value")]
+    TagValueParseError,
+    #[error("Tag key {0} is longer than 128 characters")]
+    TagKeyTooLong(String),
+    #[error("Tag value of {0} is longer than 256 characters")]
+    TagValueTooLong(String),
+    #[error("{0} tags given, an object can have at most 10 tags")]
+    TooManyTags(usize),
+    #[error("Invalid metrics target, expected statsd://host:port or emf:stdout")]
+    MetricsParse,
+    #[error("Cannot read prefix file {0}: {1}")]
+    PrefixFileError(String, String),
+    #[error("Cannot read ignore file {0}: {1}")]
+    IgnoreFileError(String, String),
+    #[error("Invalid pattern in ignore file {0} line {1}: {2}")]
+    IgnorePatternParse(String, usize, String),
+    #[error("Invalid collision strategy")]
+    CollisionStrategyParse,
+    #[error("Invalid report file, expected a .md or .html file")]
+    ReportFileParse,
+    #[error("Invalid --name-literal, expected a non-empty key")]
+    NameLiteralParse,
+    #[error("Invalid extension {0:?}, expected extensions like parquet,csv")]
+    ExtensionParse(String),
+    #[error("Invalid storage rule, expected glob=CLASS[@age]")]
+    StorageRuleParse,
+    #[error("Unknown storage class {0}")]
+    StorageClassParse(String),
+    #[error("Invalid --where expression at column {0}: {1}")]
+    WhereParse(usize, String),
+    #[error("Destination {0} is neither a valid s3://bucket/path nor an existing local directory")]
+    CopyDestinationParse(String),
+    #[error("Invalid lifecycle rule, expected transition:CLASS:<days>d or expire:<days>d")]
+    LifecycleRuleParse,
+    #[error("Invalid age, expected a number of days above 0 like 90d")]
+    AgeDaysParse,
+    #[error("Invalid lifecycle format, expected json or xml")]
+    LifecycleFormatParse,
+    #[error("Invalid notify URL, expected http://... or https://...")]
+    NotifyUrlParse,
+    #[error("Invalid SNS topic, expected arn:aws:sns:<region>:<account>:<topic>")]
+    SnsTopicParse,
+    #[error("Invalid byte range, expected start-end")]
+    ByteRangeParse,
+    #[error("Invalid route, expected tagkey=value=>s3://bucket/prefix")]
+    RouteRuleParse,
+    #[error("Invalid output format, expected text or json")]
+    OutputFormatParse,
+    #[error("Invalid export format, expected sqlite")]
+    ExportFormatParse,
+    #[error("Invalid manifest format, expected csv or json")]
+    ManifestFormatParse,
+    #[error("--emit-manifest requires an object key like s3://bucket/manifests/run.csv")]
+    ManifestKey,
+    #[error("Invalid sample rate, expected a probability above 0 and at most 1")]
+    SampleRateParse,
+    #[error("Invalid percent, expected a number above 0 and at most 100")]
+    PercentParse,
+    #[error("public makes the matched keys readable by anyone, pass --i-understand-this-makes-objects-public to proceed")]
+    PublicNotAcknowledged,
+    #[error("Invalid prefetch, expected a number of pages above 0")]
+    PrefetchParse,
+    #[error("{0} cannot be used with list-multipart")]
+    MultipartOption(&'static str),
+    #[error("{0} needs a listing and cannot be used with --stdin")]
+    StdinOption(&'static str),
+    #[error("--estimate reads the CloudWatch metrics of the bucket and needs credentials, drop --no-sign-request or --estimate")]
+    EstimateUnsigned,
+    #[error("--notify-sns publishes with the credentials of the run, drop --no-sign-request or --notify-sns")]
+    NotifySnsUnsigned,
+    #[error("Invalid trash prefix, expected a relative prefix like .trash/{{date}}/")]
+    TrashPrefixParse,
+    #[error("Invalid cache ttl, expected a period like 10m or 1h")]
+    CacheTtlParse,
+    #[error(
+        "Invalid output key, expected an object key like s3://bucket/reports/run-{{date}}.csv"
+    )]
+    OutputKeyParse,
+    #[error("Invalid gzip level, expected a number from 0 to 9")]
+    GzipLevelParse,
+    #[error("Invalid suffix, expected a non-empty suffix like .gz")]
+    SuffixEmpty,
+    #[error("--version-id requires the path to be a single key")]
+    VersionIdPath,
+    #[error("--version-id is supported only by download, lstags and tags")]
+    VersionIdCommand,
+    #[error("Invalid anchor, expected prefix or anywhere")]
+    NameAnchorParse,
+    #[error("Invalid workers, expected a number above 0 or auto")]
+    WorkersParse,
+    #[error("Invalid shard, expected i/n with i from 0 to n-1, e.g. 2/8")]
+    ShardParse,
+    #[error("Invalid list api, expected v1, v2 or auto")]
+    ListApiParse,
+    #[error("Invalid color mode, expected auto, always or never")]
+    ColorModeParse,
+    #[error("Invalid dedup mode, expected key, key-version or none")]
+    DedupModeParse,
+    #[error("Invalid size buckets, expected ascending sizes like 128k,1M,16M")]
+    SizeBucketsParse,
+    #[error("Invalid max duration, expected a period like 50m or 2h")]
+    MaxDurationParse,
+    #[error("Invalid schedule, expected largest-first, smallest-first or listing")]
+    ScheduleParse,
+    #[error("Invalid date format {0}, expected iso, rfc2822, epoch or a strftime pattern")]
+    DateFormatParse(String),
+    #[error("Unknown time zone {0}, expected UTC, local or an IANA name like Europe/Berlin")]
+    TimezoneParse(String),
+    #[error("--reevaluate-mtime-at-action needs --mtime or an mtime predicate of --where")]
+    ReevaluateWithoutMtime,
+}
+
+/// An S3 location: `s3://bucket` or `s3://bucket/prefix`. The bucket is
+/// made of word characters, spaces, `_` and `-`, the prefix adds `/` and
+/// `.`. The region is us-east-1 until the bucket location is looked up.
+#[derive(Debug, Clone, PartialEq)]
 pub struct S3Path {
     pub bucket: String,
     pub prefix: Option<String>,
     pub region: Region,
+    /// Whether the path was written as a key or a prefix, the listing is
+    /// by prefix either way
+    pub kind: PathKind,
+}
+
+/// `s3://bucket/dir/` and `s3://bucket` are prefixes, `s3://bucket/file.txt`
+/// names a key
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PathKind {
+    #[default]
+    Prefix,
+    Key,
+}
+
+impl S3Path {
+    /// The key of a path written as a key
+    pub fn key(&self) -> Option<&str> {
+        match self.kind {
+            PathKind::Key => self.prefix.as_deref(),
+            PathKind::Prefix => None,
+        }
+    }
 }
 
 impl FromStr for S3Path {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, anyhow::Error> {
-        let regex = Regex::new(r#"s3://([\d\w _-]+)(/([\d\w/ _-]*))?"#)?;
+        let regex = Regex::new(r#"s3://([\d\w _-]+)(/([\d\w/ ._-]*))?"#)?;
         let captures = regex.captures(s).ok_or(FindError::S3Parse)?;
 
         let bucket = captures
@@ -282,15 +1721,68 @@ impl FromStr for S3Path {
             .map(|x| x.as_str().to_owned())
             .ok_or(FindError::S3Parse)?;
         let prefix = captures.get(3).map(|x| x.as_str().to_owned());
+        let kind = match prefix.as_deref() {
+            Some(prefix) if !prefix.is_empty() && !prefix.ends_with('/') => PathKind::Key,
+            _ => PathKind::Prefix,
+        };
 
         Ok(S3Path {
             bucket,
             prefix,
             region: Region::from_static("us-east-1"),
+            kind,
         })
     }
 }
 
+/// The canonical `s3://bucket/prefix`, the region is left out
+impl std::fmt::Display for S3Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.prefix {
+            Some(prefix) => write!(f, "s3://{}/{}", self.bucket, prefix),
+            None => write!(f, "s3://{}", self.bucket),
+        }
+    }
+}
+
+/// An empty path, the path of a run over the keys of --stdin until their
+/// bucket is known
+impl Default for S3Path {
+    fn default() -> Self {
+        S3Path {
+            bucket: String::new(),
+            prefix: None,
+            region: Region::from_static("us-east-1"),
+            kind: PathKind::Prefix,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CopyDestination {
+    S3(S3Path),
+    Local(PathBuf),
+}
+
+impl FromStr for CopyDestination {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        if let Ok(path) = s.parse() {
+            return Ok(CopyDestination::S3(path));
+        }
+
+        let directory = Path::new(s.strip_prefix("file://").unwrap_or(s));
+        if directory.is_dir() {
+            Ok(CopyDestination::Local(directory.to_owned()))
+        } else {
+            Err(FindError::CopyDestinationParse(s.to_owned()).into())
+        }
+    }
+}
+
+/// A size filter: `[+-]<number>[kMGTP]`, bigger than with `+`, lower than
+/// with `-` and equal without a sign. The units are powers of 1024.
 #[derive(Debug, Clone, PartialEq)]
 pub enum FindSize {
     Equal(i64),
@@ -338,6 +1830,9 @@ impl FromStr for FindSize {
     }
 }
 
+/// A time filter: `[+-]<number>[smhdw]`, modified within the period with
+/// `-` and before it with `+` or without a sign. A number without a unit
+/// is in seconds.
 // Filter time range: 0__<time>__<now>
 #[derive(Debug, Clone, PartialEq)]
 pub enum FindTime {
@@ -387,160 +1882,2595 @@ impl FromStr for FindTime {
     }
 }
 
-pub type NameGlob = Pattern;
+/// A size in the largest unit dividing it
+fn size_flag(bytes: i64) -> String {
+    let units = [('P', 5), ('T', 4), ('G', 3), ('M', 2), ('k', 1)];
+    units
+        .iter()
+        .map(|(unit, power)| (unit, 1024_i64.pow(*power)))
+        .find(|(_, scale)| bytes != 0 && bytes % scale == 0)
+        .map(|(unit, scale)| format!("{}{}", bytes / scale, unit))
+        .unwrap_or_else(|| bytes.to_string())
+}
+
+/// A period in the largest unit dividing it
+fn time_flag(seconds: i64) -> String {
+    let units = [('w', 604_800), ('d', 86_400), ('h', 3_600), ('m', 60)];
+    units
+        .iter()
+        .find(|(_, scale)| seconds != 0 && seconds % scale == 0)
+        .map(|(unit, scale)| format!("{}{}", seconds / scale, unit))
+        .unwrap_or_else(|| format!("{}s", seconds))
+}
+
+/// The canonical size, like `+10M`
+impl std::fmt::Display for FindSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            FindSize::Equal(bytes) => write!(f, "{}", size_flag(bytes)),
+            FindSize::Bigger(bytes) => write!(f, "+{}", size_flag(bytes)),
+            FindSize::Lower(bytes) => write!(f, "-{}", size_flag(bytes)),
+        }
+    }
+}
+
+/// The canonical period, like `-2h` or `+30d`
+impl std::fmt::Display for FindTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            FindTime::Upper(seconds) => write!(f, "-{}", time_flag(seconds)),
+            FindTime::Lower(seconds) => write!(f, "+{}", time_flag(seconds)),
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct InameGlob(pub Pattern);
+pub enum MetricsTarget {
+    Statsd(String),
+    Emf,
+}
 
-impl FromStr for InameGlob {
+impl FromStr for MetricsTarget {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, anyhow::Error> {
-        let pattern = Pattern::from_str(s)?;
-        Ok(InameGlob(pattern))
+        match s {
+            "emf:stdout" => Ok(MetricsTarget::Emf),
+            _ => {
+                let address = s.strip_prefix("statsd://").ok_or(FindError::MetricsParse)?;
+                match address.rsplit_once(':') {
+                    Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                        Ok(MetricsTarget::Statsd(address.to_owned()))
+                    }
+                    _ => Err(FindError::MetricsParse.into()),
+                }
+            }
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct FindTag {
-    pub key: String,
-    pub value: String,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
 }
 
-impl FromStr for FindTag {
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportFile {
+    pub path: String,
+    pub format: ReportFormat,
+}
+
+impl FromStr for ReportFile {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, anyhow::Error> {
-        let re = Regex::new(r"(\w+):(\w+)$")?;
-        let m = re.captures(s).ok_or(FindError::TagParseError)?;
+        let extension = std::path::Path::new(s)
+            .extension()
+            .and_then(|x| x.to_str())
+            .map(|x| x.to_lowercase());
 
-        let key = m.get(1).ok_or(FindError::TagKeyParseError)?.as_str();
-        let value = m.get(2).ok_or(FindError::TagValueParseError)?.as_str();
+        let format = match extension.as_deref() {
+            Some("md") => ReportFormat::Markdown,
+            Some("html") | Some("htm") => ReportFormat::Html,
+            _ => return Err(FindError::ReportFileParse.into()),
+        };
 
-        Ok(FindTag {
-            key: key.to_string(),
-            value: value.to_string(),
+        Ok(ReportFile {
+            path: s.to_owned(),
+            format,
         })
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub const STORAGE_CLASSES: &[&str] = &[
+    "STANDARD",
+    "REDUCED_REDUNDANCY",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER",
+    "GLACIER_IR",
+    "DEEP_ARCHIVE",
+];
 
-    #[test]
-    fn s3path_correct() {
-        assert_eq!(
-            "s3://testbucket/".parse().ok(),
-            Some(S3Path {
-                bucket: "testbucket".to_owned(),
-                prefix: Some("".to_owned()),
-                region: Region::from_static("us-east-1"),
-            })
-        );
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageRule {
+    pub glob: Pattern,
+    pub class: String,
+    /// Minimal age in seconds
+    pub age: Option<i64>,
+}
 
-        assert_eq!(
-            "s3://testbucket/path".parse().ok(),
-            Some(S3Path {
-                bucket: "testbucket".to_owned(),
-                prefix: Some("path".to_owned()),
-                region: Region::from_static("us-east-1"),
-            })
-        );
+impl FromStr for StorageRule {
+    type Err = anyhow::Error;
 
-        assert_eq!(
-            "s3://testbucket/multi/path".parse().ok(),
-            Some(S3Path {
-                bucket: "testbucket".to_owned(),
-                prefix: Some("multi/path".to_owned()),
-                region: Region::from_static("us-east-1"),
-            })
-        );
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let (glob, target) = s.rsplit_once('=').ok_or(FindError::StorageRuleParse)?;
+        let (class, age) = match target.split_once('@') {
+            Some((class, age)) => (class, Some(age)),
+            None => (target, None),
+        };
 
-        assert_eq!(
+        if glob.is_empty() {
+            return Err(FindError::StorageRuleParse.into());
+        }
+
+        let class = class.to_uppercase();
+        if !STORAGE_CLASSES.contains(&class.as_str()) {
+            return Err(FindError::StorageClassParse(class).into());
+        }
+
+        let age = match age.map(str::parse) {
+            None => None,
+            Some(Ok(FindTime::Lower(seconds))) => Some(seconds),
+            Some(_) => return Err(FindError::StorageRuleParse.into()),
+        };
+
+        Ok(StorageRule {
+            glob: Pattern::new(glob)?,
+            class,
+            age,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifyUrl(pub String);
+
+impl FromStr for NotifyUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let host = s
+            .strip_prefix("https://")
+            .or_else(|| s.strip_prefix("http://"))
+            .ok_or(FindError::NotifyUrlParse)?;
+
+        if host.is_empty() || host.starts_with('/') {
+            return Err(FindError::NotifyUrlParse.into());
+        }
+        Ok(NotifyUrl(s.to_owned()))
+    }
+}
+
+/// Topic ARN of --notify-sns, the events are published in its region
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnsTopic {
+    pub arn: String,
+    pub region: String,
+}
+
+impl FromStr for SnsTopic {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        // arn:<partition>:sns:<region>:<account>:<topic>
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts.as_slice() {
+            ["arn", partition, "sns", region, account, topic]
+                if [partition, region, account, topic]
+                    .iter()
+                    .all(|x| !x.is_empty()) =>
+            {
+                Ok(SnsTopic {
+                    arn: s.to_owned(),
+                    region: (*region).to_owned(),
+                })
+            }
+            _ => Err(FindError::SnsTopicParse.into()),
+        }
+    }
+}
+
+/// Inclusive byte range of an object, as in the Range header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn header(&self) -> String {
+        format!("bytes={}-{}", self.start, self.end)
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let (start, end) = s.split_once('-').ok_or(FindError::ByteRangeParse)?;
+        let start: u64 = start.parse().map_err(|_| FindError::ByteRangeParse)?;
+        let end: u64 = end.parse().map_err(|_| FindError::ByteRangeParse)?;
+
+        if end < start {
+            return Err(FindError::ByteRangeParse.into());
+        }
+        Ok(ByteRange { start, end })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteRule {
+    pub key: String,
+    pub value: String,
+    pub destination: S3Path,
+}
+
+impl FromStr for RouteRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let (tag, destination) = s.split_once("=>").ok_or(FindError::RouteRuleParse)?;
+        let (key, value) = tag.split_once('=').ok_or(FindError::RouteRuleParse)?;
+
+        if key.is_empty() {
+            return Err(FindError::RouteRuleParse.into());
+        }
+
+        Ok(RouteRule {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            destination: destination.parse()?,
+        })
+    }
+}
+
+/// How long a cached listing is served, a period like 10m or 1h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheTtl(pub std::time::Duration);
+
+impl FromStr for CacheTtl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s.parse::<FindTime>() {
+            Ok(FindTime::Lower(seconds)) if !s.starts_with('+') => {
+                Ok(CacheTtl(std::time::Duration::from_secs(seconds as u64)))
+            }
+            _ => Err(FindError::CacheTtlParse.into()),
+        }
+    }
+}
+
+/// How long a run may last, a period like 50m or 2h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaxDuration(pub std::time::Duration);
+
+impl FromStr for MaxDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s.parse::<FindTime>() {
+            Ok(FindTime::Lower(seconds)) if seconds > 0 && !s.starts_with('+') => {
+                Ok(MaxDuration(std::time::Duration::from_secs(seconds as u64)))
+            }
+            _ => Err(FindError::MaxDurationParse.into()),
+        }
+    }
+}
+
+/// Template of the trashed keys prefix, `{date}` stands for a UTC date
+#[derive(Debug, Clone)]
+pub struct TrashPrefix {
+    pub template: String,
+    pattern: Regex,
+}
+
+impl PartialEq for TrashPrefix {
+    fn eq(&self, other: &Self) -> bool {
+        self.template == other.template
+    }
+}
+
+impl TrashPrefix {
+    /// The prefix of the keys trashed on the date
+    pub fn render(&self, date: &str) -> String {
+        self.template.replace("{date}", date)
+    }
+
+    /// The literal start of the template, every trashed key starts with it
+    pub fn root(&self) -> &str {
+        self.template.split("{date}").next().unwrap_or_default()
+    }
+
+    /// The original key of a trashed key
+    pub fn strip<'a>(&self, key: &'a str) -> Option<&'a str> {
+        let prefix = self.pattern.find(key)?;
+        Some(&key[prefix.end()..]).filter(|x| !x.is_empty())
+    }
+}
+
+impl FromStr for TrashPrefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        if s.is_empty() || s.starts_with('/') {
+            return Err(FindError::TrashPrefixParse.into());
+        }
+
+        let pattern = s
+            .split("{date}")
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join(r"\d{4}-\d{2}-\d{2}");
+        Ok(TrashPrefix {
+            template: s.to_owned(),
+            pattern: Regex::new(&format!("^{}", pattern))?,
+        })
+    }
+}
+
+/// S3 key of the print output, `{date}` stands for a UTC date
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputKey {
+    pub bucket: String,
+    pub template: String,
+}
+
+impl OutputKey {
+    /// The key of the output written on the date
+    pub fn render(&self, date: &str) -> S3Path {
+        S3Path {
+            bucket: self.bucket.clone(),
+            prefix: Some(self.template.replace("{date}", date)),
+            region: Region::from_static("us-east-1"),
+            kind: PathKind::Key,
+        }
+    }
+}
+
+impl FromStr for OutputKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let (bucket, template) = s
+            .strip_prefix("s3://")
+            .and_then(|x| x.split_once('/'))
+            .ok_or(FindError::OutputKeyParse)?;
+
+        // the placeholders are checked as the date they expand to
+        let path: S3Path = s.replace("{date}", "2000-01-01").parse()?;
+        let key = template.replace("{date}", "2000-01-01");
+        if path.bucket != bucket
+            || path.prefix.as_deref() != Some(&key)
+            || key.is_empty()
+            || key.ends_with('/')
+        {
+            return Err(FindError::OutputKeyParse.into());
+        }
+
+        Ok(OutputKey {
+            bucket: bucket.to_owned(),
+            template: template.to_owned(),
+        })
+    }
+}
+
+/// Filter expression of --where
+#[derive(Debug, Clone)]
+pub enum WhereExpr {
+    And(Box<WhereExpr>, Box<WhereExpr>),
+    Or(Box<WhereExpr>, Box<WhereExpr>),
+    Not(Box<WhereExpr>),
+    Name(NameGlob),
+    Iname(InameGlob),
+    Regex(Regex),
+    Size(FindSize),
+    Mtime(FindTime),
+    StorageClass(String),
+    /// The key depth compared with the number gives the ordering
+    Depth(std::cmp::Ordering, usize),
+    ETag(String),
+}
+
+impl WhereExpr {
+    /// Whether a predicate of the expression compares the modification time
+    pub fn tests_mtime(&self) -> bool {
+        match self {
+            WhereExpr::And(left, right) | WhereExpr::Or(left, right) => {
+                left.tests_mtime() || right.tests_mtime()
+            }
+            WhereExpr::Not(expr) => expr.tests_mtime(),
+            WhereExpr::Mtime(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for WhereExpr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let mut parser = WhereParser {
+            tokens: where_tokens(s)?,
+            position: 0,
+            end: s.chars().count(),
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((position, _)) => Err(where_error(*position, "unexpected input")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum WhereToken {
+    Open,
+    Close,
+    /// A keyword or a predicate, with the quotes removed
+    Word(String),
+}
+
+/// Error at the character position, reported as a column counted from 1
+fn where_error(position: usize, message: &str) -> anyhow::Error {
+    FindError::WhereParse(position + 1, message.to_owned()).into()
+}
+
+/// Splits the expression into parentheses and words, with the position of
+/// their first character
+fn where_tokens(s: &str) -> Result<Vec<(usize, WhereToken)>, anyhow::Error> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((i, WhereToken::Open));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((i, WhereToken::Close));
+                i += 1;
+            }
+            _ => {
+                let start = i;
+                let mut word = String::new();
+                while i < chars.len() && !chars[i].is_whitespace() && !"()".contains(chars[i]) {
+                    if chars[i] == '\'' || chars[i] == '"' {
+                        let quote = i;
+                        i += 1;
+                        while i < chars.len() && chars[i] != chars[quote] {
+                            word.push(chars[i]);
+                            i += 1;
+                        }
+                        if i == chars.len() {
+                            return Err(where_error(quote, "unterminated quote"));
+                        }
+                    } else {
+                        word.push(chars[i]);
+                    }
+                    i += 1;
+                }
+                tokens.push((start, WhereToken::Word(word)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser of the tokens:
+///     or := and ("or" and)*
+///     and := not ("and" not)*
+///     not := "not" not | "(" or ")" | predicate
+struct WhereParser {
+    tokens: Vec<(usize, WhereToken)>,
+    position: usize,
+    /// Position reported for a missing token
+    end: usize,
+}
+
+impl WhereParser {
+    fn peek(&self) -> Option<&(usize, WhereToken)> {
+        self.tokens.get(self.position)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some((_, WhereToken::Word(word))) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<WhereExpr, anyhow::Error> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = WhereExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<WhereExpr, anyhow::Error> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = WhereExpr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<WhereExpr, anyhow::Error> {
+        if self.keyword("not") {
+            return Ok(WhereExpr::Not(Box::new(self.not()?)));
+        }
+
+        let (position, token) = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(where_error(self.end, "expected a predicate")),
+        };
+        self.position += 1;
+
+        match token {
+            WhereToken::Open => {
+                let expr = self.or()?;
+                match self.peek() {
+                    Some((_, WhereToken::Close)) => {
+                        self.position += 1;
+                        Ok(expr)
+                    }
+                    Some((position, _)) => Err(where_error(*position, "expected )")),
+                    None => Err(where_error(self.end, "expected )")),
+                }
+            }
+            WhereToken::Close => Err(where_error(position, "expected a predicate")),
+            WhereToken::Word(word) => where_predicate(position, &word),
+        }
+    }
+}
+
+fn where_predicate(position: usize, word: &str) -> Result<WhereExpr, anyhow::Error> {
+    let operator = word
+        .find(|c| "=<>".contains(c))
+        .ok_or_else(|| where_error(position, "expected a predicate like name=GLOB"))?;
+    let field = &word[..operator];
+    let op = &word[operator..operator + 1];
+    let value = &word[operator + 1..];
+    let value_position = position + field.chars().count() + 1;
+    let invalid = |e: anyhow::Error| where_error(value_position, &e.to_string());
+
+    if op != "=" && field != "depth" {
+        return Err(where_error(position + field.chars().count(), "expected ="));
+    }
+
+    match field {
+        "name" => Ok(WhereExpr::Name(
+            glob_pattern(value).map_err(|e| invalid(e.into()))?,
+        )),
+        "iname" => Ok(WhereExpr::Iname(value.parse().map_err(invalid)?)),
+        "regex" => Ok(WhereExpr::Regex(
+            Regex::new(value).map_err(|e| invalid(e.into()))?,
+        )),
+        "size" => Ok(WhereExpr::Size(value.parse().map_err(invalid)?)),
+        "mtime" => Ok(WhereExpr::Mtime(value.parse().map_err(invalid)?)),
+        "storage_class" => {
+            let class = value.to_uppercase();
+            if !STORAGE_CLASSES.contains(&class.as_str()) {
+                return Err(invalid(FindError::StorageClassParse(class).into()));
+            }
+            Ok(WhereExpr::StorageClass(class))
+        }
+        "depth" => {
+            let depth = value
+                .parse()
+                .map_err(|_| where_error(value_position, "expected a number"))?;
+            let ordering = match op {
+                "<" => std::cmp::Ordering::Less,
+                ">" => std::cmp::Ordering::Greater,
+                _ => std::cmp::Ordering::Equal,
+            };
+            Ok(WhereExpr::Depth(ordering, depth))
+        }
+        "etag" => Ok(WhereExpr::ETag(value.to_owned())),
+        _ => Err(where_error(
+            position,
+            &format!("unknown predicate {}", field),
+        )),
+    }
+}
+
+pub type NameGlob = Pattern;
+
+/// Glob pattern with the backslash escapes translated into the one
+/// character classes glob understands
+pub fn glob_pattern(s: &str) -> Result<Pattern, glob::PatternError> {
+    let mut pattern = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('\\')) => {
+                pattern.push('\\');
+                chars.next();
+            }
+            ('\\', Some(&escaped)) if "*?[]".contains(escaped) => {
+                pattern.push('[');
+                pattern.push(escaped);
+                pattern.push(']');
+                chars.next();
+            }
+            (c, _) => pattern.push(c),
+        }
+    }
+    Pattern::new(&pattern)
+}
+
+/// Key, or trailing segments of a key, matched as is
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameLiteral(pub String);
+
+impl NameLiteral {
+    pub fn matches(&self, key: &str) -> bool {
+        key.strip_suffix(self.0.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.ends_with('/'))
+    }
+}
+
+/// The lowercase extensions of --ext, without their leading dot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extensions(pub BTreeSet<String>);
+
+impl FromStr for Extensions {
+    type Err = FindError;
+
+    fn from_str(s: &str) -> Result<Self, FindError> {
+        s.split(',')
+            .map(|x| {
+                let ext = x.trim();
+                let ext = ext.strip_prefix('.').unwrap_or(ext);
+                match ext.is_empty() || ext.contains(['.', '/']) {
+                    true => Err(FindError::ExtensionParse(x.to_owned())),
+                    false => Ok(ext.to_lowercase()),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Extensions)
+    }
+}
+
+impl FromStr for NameLiteral {
+    type Err = FindError;
+
+    fn from_str(s: &str) -> Result<Self, FindError> {
+        if s.is_empty() {
+            return Err(FindError::NameLiteralParse);
+        }
+        Ok(NameLiteral(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InameGlob(pub Pattern);
+
+impl FromStr for InameGlob {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        Ok(InameGlob(glob_pattern(s)?))
+    }
+}
+
+/// Sorted list of key prefixes with the prefixes covered by shorter ones
+/// removed, so a key can be checked with a single binary search
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PrefixSet(Vec<String>);
+
+impl PrefixSet {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn new<I: IntoIterator<Item = String>>(prefixes: I) -> Self {
+        let mut prefixes: Vec<_> = prefixes.into_iter().collect();
+        prefixes.sort();
+
+        let mut list: Vec<String> = Vec::with_capacity(prefixes.len());
+        for prefix in prefixes {
+            match list.last() {
+                Some(last) if prefix.starts_with(last.as_str()) => {}
+                _ => list.push(prefix),
+            }
+        }
+        PrefixSet(list)
+    }
+
+    pub fn parse(content: &str) -> Self {
+        PrefixSet::new(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                .map(str::to_owned),
+        )
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, FindError> {
+        std::fs::read_to_string(path)
+            .map(|x| PrefixSet::parse(&x))
+            .map_err(|e| FindError::PrefixFileError(path.to_owned(), e.to_string()))
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        // the only candidate is the greatest prefix which is not greater than the key
+        let position = self.0.partition_point(|x| x.as_str() <= key);
+        position > 0 && key.starts_with(self.0[position - 1].as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExcludePrefixes(pub PrefixSet);
+
+impl FromStr for ExcludePrefixes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        Ok(ExcludePrefixes(PrefixSet::from_file(s)?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncludePrefixes(pub PrefixSet);
+
+impl FromStr for IncludePrefixes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        Ok(IncludePrefixes(PrefixSet::from_file(s)?))
+    }
+}
+
+/// The ignore file found in the working directory
+pub const DEFAULT_IGNORE_FILE: &str = ".s3findignore";
+
+/// A glob of an ignore file, a negated glob includes the matched keys again
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnorePattern {
+    pub glob: Pattern,
+    pub negated: bool,
+}
+
+/// The patterns of --ignore-file or ./.s3findignore, in the file order
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnoreFile {
+    pub path: String,
+    pub patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreFile {
+    pub fn parse(path: &str, content: &str) -> Result<Self, FindError> {
+        let mut patterns = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, glob) = match line.strip_prefix('!') {
+                Some(glob) => (true, glob),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let glob = match glob.strip_prefix('\\') {
+                Some(escaped) if escaped.starts_with(&['#', '!'][..]) => escaped,
+                _ => glob,
+            };
+            // a directory pattern matches the keys below it
+            let glob = if glob.ends_with('/') {
+                format!("{}*", glob)
+            } else {
+                glob.to_owned()
+            };
+
+            let glob = Pattern::new(&glob).map_err(|e| {
+                FindError::IgnorePatternParse(path.to_owned(), number + 1, e.msg.to_owned())
+            })?;
+            patterns.push(IgnorePattern { glob, negated });
+        }
+
+        Ok(IgnoreFile {
+            path: path.to_owned(),
+            patterns,
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, FindError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| FindError::IgnoreFileError(path.to_owned(), e.to_string()))?;
+        IgnoreFile::parse(path, &content)
+    }
+}
+
+impl FromStr for IgnoreFile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        Ok(IgnoreFile::from_file(s)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollisionStrategy {
+    Error,
+    Skip,
+    Suffix,
+}
+
+impl FromStr for CollisionStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "error" => Ok(CollisionStrategy::Error),
+            "skip" => Ok(CollisionStrategy::Skip),
+            "suffix" => Ok(CollisionStrategy::Suffix),
+            _ => Err(FindError::CollisionStrategyParse.into()),
+        }
+    }
+}
+
+/// Whether the public access block of the bucket makes public ACLs
+/// ineffective, found by the public preflight
+#[derive(Debug, Clone, Default)]
+pub struct AclsBlocked(pub Arc<AtomicBool>);
+
+impl PartialEq for AclsBlocked {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.load(Ordering::Relaxed) == other.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether the backend rejected a conditional DeleteObject, the later keys
+/// of --snapshot-guard are compared with a HEAD request instead
+#[derive(Debug, Clone, Default)]
+pub struct IfMatchUnsupported(pub Arc<AtomicBool>);
+
+impl PartialEq for IfMatchUnsupported {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.load(Ordering::Relaxed) == other.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Target keys written during the run mapped to their source keys,
+/// shared between batches
+#[derive(Debug, Clone, Default)]
+pub struct TargetTracker(pub Arc<Mutex<HashMap<String, String>>>);
+
+impl PartialEq for TargetTracker {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+/// A key copied by the first phase of move --two-phase
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovedKey {
+    pub key: String,
+    pub target: String,
+    pub size: i64,
+}
+
+/// The progress of move --two-phase: the keys copied by the run, the keys
+/// copied by an interrupted run, read from its marker, and whether a copy
+/// or a source delete failed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MovePhase {
+    pub copied: Vec<MovedKey>,
+    pub resumed: BTreeMap<String, MovedKey>,
+    pub failed: bool,
+}
+
+/// The progress of move --two-phase, shared between batches
+#[derive(Debug, Clone, Default)]
+pub struct MovePhases(pub Arc<Mutex<MovePhase>>);
+
+impl PartialEq for MovePhases {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+/// Number of keys planned by plan-copy and the target keys of more than
+/// one source key, mapped to the source keys after the first one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlanCounts {
+    pub planned: u64,
+    pub collisions: BTreeMap<String, Vec<String>>,
+}
+
+/// Target keys planned by plan-copy, shared between batches
+#[derive(Debug, Clone, Default)]
+pub struct PlanTally(pub Arc<Mutex<PlanCounts>>);
+
+impl PartialEq for PlanTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LifecycleRule {
+    Transition { class: String, days: i64 },
+    Expire { days: i64 },
+}
+
+impl FromStr for LifecycleRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let days = |s: &str| -> Result<i64, FindError> {
+            s.strip_suffix('d')
+                .unwrap_or(s)
+                .parse()
+                .map_err(|_| FindError::LifecycleRuleParse)
+        };
+
+        match s.split(':').collect::<Vec<_>>()[..] {
+            ["transition", class, age] => {
+                let class = class.to_uppercase();
+                if !STORAGE_CLASSES.contains(&class.as_str()) {
+                    return Err(FindError::StorageClassParse(class).into());
+                }
+                Ok(LifecycleRule::Transition {
+                    class,
+                    days: days(age)?,
+                })
+            }
+            ["expire", age] => Ok(LifecycleRule::Expire { days: days(age)? }),
+            _ => Err(FindError::LifecycleRuleParse.into()),
+        }
+    }
+}
+
+/// Number of keys and bytes per simulated lifecycle action,
+/// shared between batches
+#[derive(Debug, Clone, Default)]
+pub struct LifecycleTally(pub Arc<Mutex<BTreeMap<String, (u64, u64)>>>);
+
+impl PartialEq for LifecycleTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LifecycleFormat {
+    Json,
+    Xml,
+}
+
+impl FromStr for LifecycleFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "json" => Ok(LifecycleFormat::Json),
+            "xml" => Ok(LifecycleFormat::Xml),
+            _ => Err(FindError::LifecycleFormatParse.into()),
+        }
+    }
+}
+
+/// Ages, size and storage classes of the keys under a prefix
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrefixAges {
+    /// Number of keys per age in days
+    pub ages: BTreeMap<i64, u64>,
+    pub bytes: u64,
+    /// Number of keys per storage class
+    pub classes: BTreeMap<String, u64>,
+}
+
+/// Ages per prefix group collected by suggest-lifecycle, shared between
+/// batches. The keys above the group depth are under the empty prefix.
+#[derive(Debug, Clone, Default)]
+pub struct PrefixAgeTally(pub Arc<Mutex<BTreeMap<String, PrefixAges>>>);
+
+impl PartialEq for PrefixAgeTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+/// Number and bytes of the keys under a prefix
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PrefixCounts {
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// The outcome of a RestoreObject request, as recorded in the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RestoreOutcome {
+    Initiated,
+    AlreadyInProgress,
+    /// The key is not archived, or already in an active tier
+    InvalidState,
+    Error,
+}
+
+impl RestoreOutcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RestoreOutcome::Initiated => "initiated",
+            RestoreOutcome::AlreadyInProgress => "already-in-progress",
+            RestoreOutcome::InvalidState => "invalid-state",
+            RestoreOutcome::Error => "error",
+        }
+    }
+}
+
+/// The keys initiated by an earlier run of the ledger or by this run, and
+/// the outcomes of this run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestoreCounts {
+    pub initiated: HashSet<(String, String)>,
+    pub outcomes: BTreeMap<RestoreOutcome, u64>,
+    /// Keys skipped as initiated in the ledger
+    pub skipped: u64,
+}
+
+/// The restore state shared between batches
+#[derive(Debug, Clone, Default)]
+pub struct RestoreTally(pub Arc<Mutex<RestoreCounts>>);
+
+impl PartialEq for RestoreTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+/// Keys per prefix of report key-distribution; the prefixes folded out of
+/// the histogram to bound it are counted together under other
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyHistogram {
+    pub prefixes: HashMap<String, PrefixCounts>,
+    pub other: PrefixCounts,
+}
+
+/// The histogram of report key-distribution, shared between batches
+#[derive(Debug, Clone, Default)]
+pub struct KeyHistogramTally(pub Arc<Mutex<KeyHistogram>>);
+
+impl PartialEq for KeyHistogramTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+/// Number of keys verified, mismatched and missing, shared between batches
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyCounts {
+    pub verified: u64,
+    pub mismatched: u64,
+    pub missing: u64,
+    /// Keys listed with a size of 0 which HEAD reports with content
+    pub corrected: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyTally(pub Arc<Mutex<VerifyCounts>>);
+
+impl PartialEq for VerifyTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+/// Bounds of --workers auto
+pub const AUTO_WORKERS_START: usize = 8;
+pub const AUTO_WORKERS_FLOOR: usize = 2;
+pub const AUTO_WORKERS_CEILING: usize = 128;
+/// Workers added after a batch without throttled requests
+pub const AUTO_WORKERS_STEP: usize = 4;
+
+/// Parallelism adapted after every batch like TCP congestion control: it
+/// grows by a step while no request is throttled and is halved when one is
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveConcurrency {
+    pub current: usize,
+    pub floor: usize,
+    pub ceiling: usize,
+    /// Throttled requests of the run
+    pub throttled: u64,
+}
+
+impl Default for AdaptiveConcurrency {
+    fn default() -> Self {
+        AdaptiveConcurrency {
+            current: AUTO_WORKERS_START,
+            floor: AUTO_WORKERS_FLOOR,
+            ceiling: AUTO_WORKERS_CEILING,
+            throttled: 0,
+        }
+    }
+}
+
+impl AdaptiveConcurrency {
+    /// Takes the throttled requests of a batch into account
+    pub fn update(&mut self, throttled: usize) {
+        self.throttled += throttled as u64;
+        self.current = if throttled > 0 {
+            (self.current / 2).max(self.floor)
+        } else {
+            (self.current + AUTO_WORKERS_STEP).min(self.ceiling)
+        };
+    }
+}
+
+/// The number of requests a command sends at once
+#[derive(Debug, Clone)]
+pub enum Workers {
+    Fixed(usize),
+    Auto(Arc<Mutex<AdaptiveConcurrency>>),
+}
+
+impl Workers {
+    /// The number of requests to send at once for the next batch
+    pub fn current(&self) -> usize {
+        match self {
+            Workers::Fixed(workers) => *workers,
+            Workers::Auto(concurrency) => concurrency.lock().unwrap().current,
+        }
+    }
+
+    /// Adapts auto workers to the throttled requests of a batch
+    pub fn observe(&self, throttled: usize) {
+        if let Workers::Auto(concurrency) = self {
+            concurrency.lock().unwrap().update(throttled);
+        }
+    }
+
+    /// The settled parallelism and the throttled requests of auto workers
+    pub fn settled(&self) -> Option<(usize, u64)> {
+        match self {
+            Workers::Fixed(_) => None,
+            Workers::Auto(concurrency) => {
+                let concurrency = concurrency.lock().unwrap();
+                Some((concurrency.current, concurrency.throttled))
+            }
+        }
+    }
+}
+
+impl PartialEq for Workers {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Workers::Fixed(a), Workers::Fixed(b)) => a == b,
+            (Workers::Auto(a), Workers::Auto(b)) => {
+                Arc::ptr_eq(a, b) || *a.lock().unwrap() == *b.lock().unwrap()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Workers {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "auto" => Ok(Workers::Auto(Default::default())),
+            _ => match s.parse() {
+                Ok(workers) if workers > 0 => Ok(Workers::Fixed(workers)),
+                _ => Err(FindError::WorkersParse.into()),
+            },
+        }
+    }
+}
+
+/// What an ACL grant gives away, the owner grants are not reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AclCategory {
+    PrivateOnly,
+    PublicRead,
+    PublicWrite,
+    CrossAccount,
+    LogDelivery,
+}
+
+impl AclCategory {
+    pub const ALL: [AclCategory; 5] = [
+        AclCategory::PrivateOnly,
+        AclCategory::PublicRead,
+        AclCategory::PublicWrite,
+        AclCategory::CrossAccount,
+        AclCategory::LogDelivery,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AclCategory::PrivateOnly => "private-only",
+            AclCategory::PublicRead => "public-read",
+            AclCategory::PublicWrite => "public-write",
+            AclCategory::CrossAccount => "cross-account",
+            AclCategory::LogDelivery => "log-delivery",
+        }
+    }
+}
+
+/// Keys per ACL category reported by acl-report, shared between batches
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AclCounts {
+    pub keys: BTreeMap<AclCategory, u64>,
+    /// The bucket enforces the bucket owner for every object
+    pub acls_disabled: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AclTally(pub Arc<Mutex<AclCounts>>);
+
+impl PartialEq for AclTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+/// Keys compared by diff-tags, shared between batches
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagDiffCounts {
+    pub equal: u64,
+    pub differing: u64,
+    pub missing_target: u64,
+    pub missing_source: u64,
+    /// Relative keys of the compared source keys
+    pub seen: HashSet<String>,
+    /// Region of the target bucket when it differs from the client one
+    pub target_region: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TagDiffTally(pub Arc<Mutex<TagDiffCounts>>);
+
+impl PartialEq for TagDiffTally {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(FindError::OutputFormatParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Sqlite,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "sqlite" => Ok(ExportFormat::Sqlite),
+            _ => Err(FindError::ExportFormatParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManifestFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ManifestFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "csv" => Ok(ManifestFormat::Csv),
+            "json" => Ok(ManifestFormat::Json),
+            _ => Err(FindError::ManifestFormatParse.into()),
+        }
+    }
+}
+
+/// The part of the key matched by the name globs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameAnchor {
+    Anywhere,
+    Prefix,
+}
+
+impl FromStr for NameAnchor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "anywhere" => Ok(NameAnchor::Anywhere),
+            "prefix" => Ok(NameAnchor::Prefix),
+            _ => Err(FindError::NameAnchorParse.into()),
+        }
+    }
+}
+
+/// The shard i of n of --shard, numbered from 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl FromStr for Shard {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let (index, count) = s.split_once('/').ok_or(FindError::ShardParse)?;
+        match (index.parse(), count.parse()) {
+            (Ok(index), Ok(count)) if index < count => Ok(Shard { index, count }),
+            _ => Err(FindError::ShardParse.into()),
+        }
+    }
+}
+
+impl std::fmt::Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// The listing API of --list-api
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ListApi {
+    V1,
+    V2,
+    #[default]
+    Auto,
+}
+
+impl FromStr for ListApi {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "v1" => Ok(ListApi::V1),
+            "v2" => Ok(ListApi::V2),
+            "auto" => Ok(ListApi::Auto),
+            _ => Err(FindError::ListApiParse.into()),
+        }
+    }
+}
+
+/// The identifier of the duplicate keys skipped by --dedup
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DedupMode {
+    Key,
+    KeyVersion,
+    #[default]
+    None,
+}
+
+impl FromStr for DedupMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "key" => Ok(DedupMode::Key),
+            "key-version" => Ok(DedupMode::KeyVersion),
+            "none" => Ok(DedupMode::None),
+            _ => Err(FindError::DedupModeParse.into()),
+        }
+    }
+}
+
+/// The instant the --mtime periods are measured from: the start of the
+/// run, when the arguments are parsed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReferenceTime(pub SystemTime);
+
+impl Default for ReferenceTime {
+    fn default() -> Self {
+        ReferenceTime(SystemTime::now())
+    }
+}
+
+/// The boundaries of the size histogram of --summarize, --size-buckets
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeBuckets(pub Vec<u64>);
+
+impl Default for SizeBuckets {
+    fn default() -> Self {
+        SizeBuckets(vec![
+            128 * 1024,
+            1024_i64.pow(2) as u64,
+            16 * 1024_i64.pow(2) as u64,
+            128 * 1024_i64.pow(2) as u64,
+            1024_i64.pow(3) as u64,
+        ])
+    }
+}
+
+impl FromStr for SizeBuckets {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let mut bounds = Vec::new();
+        for size in s.split(',') {
+            match size.trim().parse() {
+                Ok(FindSize::Equal(bytes)) if bounds.last().map_or(bytes > 0, |x| bytes > *x) => {
+                    bounds.push(bytes)
+                }
+                _ => return Err(FindError::SizeBucketsParse.into()),
+            }
+        }
+        Ok(SizeBuckets(bounds.into_iter().map(|x| x as u64).collect()))
+    }
+}
+
+/// When the text output is colored, --color
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Whether an output, a terminal or not, is colored
+    pub fn enabled(self, terminal: bool) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(FindError::ColorModeParse.into()),
+        }
+    }
+}
+
+/// How print renders the modification time, --date-format
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum DateFormat {
+    #[default]
+    Iso,
+    Rfc2822,
+    Epoch,
+    /// A strftime pattern with at least one specifier
+    Pattern(String),
+}
+
+impl FromStr for DateFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        use chrono::format::{Item, StrftimeItems};
+
+        match s {
+            "iso" => Ok(DateFormat::Iso),
+            "rfc2822" => Ok(DateFormat::Rfc2822),
+            "epoch" => Ok(DateFormat::Epoch),
+            _ if s.contains('%') && !StrftimeItems::new(s).any(|x| x == Item::Error) => {
+                Ok(DateFormat::Pattern(s.to_owned()))
+            }
+            _ => Err(FindError::DateFormatParse(s.to_owned()).into()),
+        }
+    }
+}
+
+/// Where the time zone files are looked up, like the C library does
+const ZONEINFO_DIRS: &[&str] = &[
+    "/usr/share/zoneinfo",
+    "/usr/lib/zoneinfo",
+    "/usr/share/lib/zoneinfo",
+];
+
+/// The zone print renders the modification time in, --timezone
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Timezone {
+    #[default]
+    Utc,
+    Local,
+    /// An IANA zone of the time zone database of the system
+    Named(String),
+}
+
+impl Timezone {
+    /// Whether the time zone database of the system has the zone
+    fn is_known(name: &str) -> bool {
+        let valid = !name.is_empty()
+            && !name.starts_with('/')
+            && name
+                .split('/')
+                .all(|x| !x.is_empty() && x != "." && x != "..")
+            && name
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || "/_-+".contains(x));
+        let tzdir = std::env::var("TZDIR").ok();
+        valid
+            && tzdir
+                .iter()
+                .map(String::as_str)
+                .chain(ZONEINFO_DIRS.iter().copied())
+                .any(|dir| Path::new(dir).join(name).is_file())
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "UTC" | "utc" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            _ if Timezone::is_known(s) => Ok(Timezone::Named(s.to_owned())),
+            _ => Err(FindError::TimezoneParse(s.to_owned()).into()),
+        }
+    }
+}
+
+/// The order copy and move start the copies of a batch in,
+/// --schedule
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Schedule {
+    #[default]
+    Listing,
+    LargestFirst,
+    SmallestFirst,
+}
+
+impl Schedule {
+    /// Sorts the work items by the size of their key; the sort is stable so
+    /// the keys of the same size keep the listing order
+    pub fn sort_by_size<T>(self, items: &mut [T], size: impl Fn(&T) -> i64) {
+        match self {
+            Schedule::Listing => {}
+            Schedule::LargestFirst => items.sort_by_key(|x| std::cmp::Reverse(size(x))),
+            Schedule::SmallestFirst => items.sort_by_key(size),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "listing" => Ok(Schedule::Listing),
+            "largest-first" => Ok(Schedule::LargestFirst),
+            "smallest-first" => Ok(Schedule::SmallestFirst),
+            _ => Err(FindError::ScheduleParse.into()),
+        }
+    }
+}
+
+/// S3 limits of the object tags
+pub const TAG_KEY_MAX_LENGTH: usize = 128;
+pub const TAG_VALUE_MAX_LENGTH: usize = 256;
+pub const MAX_OBJECT_TAGS: usize = 10;
+
+/// A tag filter: `<key>:<value>`, both made of word characters
+#[derive(Debug, PartialEq, Clone)]
+pub struct FindTag {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for FindTag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let re = Regex::new(r"(\w+):(\w+)$")?;
+        let m = re.captures(s).ok_or(FindError::TagParseError)?;
+
+        let key = m.get(1).ok_or(FindError::TagKeyParseError)?.as_str();
+        let value = m.get(2).ok_or(FindError::TagValueParseError)?.as_str();
+
+        if key.chars().count() > TAG_KEY_MAX_LENGTH {
+            return Err(FindError::TagKeyTooLong(key.to_owned()).into());
+        }
+        if value.chars().count() > TAG_VALUE_MAX_LENGTH {
+            return Err(FindError::TagValueTooLong(key.to_owned()).into());
+        }
+
+        Ok(FindTag {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// The canonical `key:value`
+impl std::fmt::Display for FindTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.key, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn s3path_correct() {
+        assert_eq!(
+            "s3://testbucket/".parse().ok(),
+            Some(S3Path {
+                bucket: "testbucket".to_owned(),
+                prefix: Some("".to_owned()),
+                region: Region::from_static("us-east-1"),
+                kind: PathKind::Prefix,
+            })
+        );
+
+        assert_eq!(
+            "s3://testbucket/path".parse().ok(),
+            Some(S3Path {
+                bucket: "testbucket".to_owned(),
+                prefix: Some("path".to_owned()),
+                region: Region::from_static("us-east-1"),
+                kind: PathKind::Key,
+            })
+        );
+
+        assert_eq!(
+            "s3://testbucket/multi/path".parse().ok(),
+            Some(S3Path {
+                bucket: "testbucket".to_owned(),
+                prefix: Some("multi/path".to_owned()),
+                region: Region::from_static("us-east-1"),
+                kind: PathKind::Key,
+            })
+        );
+
+        assert_eq!(
+            "s3://testbucket/.trash/file.txt".parse().ok(),
+            Some(S3Path {
+                bucket: "testbucket".to_owned(),
+                prefix: Some(".trash/file.txt".to_owned()),
+                region: Region::from_static("us-east-1"),
+                kind: PathKind::Key,
+            })
+        );
+
+        assert_eq!(
             "s3://testbucket".parse().ok(),
             Some(S3Path {
                 bucket: "testbucket".to_owned(),
                 prefix: None,
                 region: Region::from_static("us-east-1"),
+                kind: PathKind::Prefix,
+            })
+        );
+    }
+
+    #[test]
+    fn s3path_key() {
+        let path = |s: &str| s.parse::<S3Path>().unwrap();
+
+        assert_eq!(
+            path("s3://testbucket/dir/file.txt").key(),
+            Some("dir/file.txt")
+        );
+        assert_eq!(path("s3://testbucket/dir/file.txt").kind, PathKind::Key);
+        assert_eq!(path("s3://testbucket/dir/").key(), None);
+        assert_eq!(path("s3://testbucket/dir/").kind, PathKind::Prefix);
+        assert_eq!(path("s3://testbucket/").key(), None);
+        assert_eq!(path("s3://testbucket").key(), None);
+    }
+
+    #[test]
+    fn s3path_incorrect() {
+        assert!("testbucket".parse::<S3Path>().is_err());
+        assert!("s3://".parse::<S3Path>().is_err());
+        assert!("s3:/testbucket".parse::<S3Path>().is_err());
+        assert!("://testbucket".parse::<S3Path>().is_err());
+    }
+
+    #[test]
+    fn size_corect() {
+        assert_eq!("11".parse().ok(), Some(FindSize::Equal(11)));
+        assert_eq!("11k".parse().ok(), Some(FindSize::Equal(11 * 1024)));
+        assert_eq!(
+            "11M".parse().ok(),
+            Some(FindSize::Equal(11 * 1024_i64.pow(2)))
+        );
+        assert_eq!(
+            "11G".parse().ok(),
+            Some(FindSize::Equal(11 * 1024_i64.pow(3)))
+        );
+        assert_eq!(
+            "11T".parse().ok(),
+            Some(FindSize::Equal(11 * 1024_i64.pow(4)))
+        );
+        assert_eq!(
+            "11P".parse().ok(),
+            Some(FindSize::Equal(11 * 1024_i64.pow(5)))
+        );
+        assert_eq!("+11".parse().ok(), Some(FindSize::Bigger(11)));
+        assert_eq!("+11k".parse().ok(), Some(FindSize::Bigger(11 * 1024)));
+        assert_eq!("-11".parse().ok(), Some(FindSize::Lower(11)));
+        assert_eq!("-11k".parse().ok(), Some(FindSize::Lower(11 * 1024)));
+    }
+
+    #[test]
+    fn size_incorect() {
+        assert!("-".parse::<FindSize>().is_err());
+        assert!("-123w".parse::<FindSize>().is_err());
+    }
+
+    #[test]
+    fn time_corect() {
+        assert_eq!("11".parse().ok(), Some(FindTime::Lower(11)));
+        assert_eq!("11s".parse().ok(), Some(FindTime::Lower(11)));
+        assert_eq!("11m".parse().ok(), Some(FindTime::Lower(11 * 60)));
+        assert_eq!("11h".parse().ok(), Some(FindTime::Lower(11 * 3600)));
+        assert_eq!("11d".parse().ok(), Some(FindTime::Lower(11 * 3600 * 24)));
+        assert_eq!(
+            "11w".parse().ok(),
+            Some(FindTime::Lower(11 * 3600 * 24 * 7))
+        );
+        assert_eq!("+11".parse().ok(), Some(FindTime::Lower(11)));
+        assert_eq!("+11m".parse().ok(), Some(FindTime::Lower(11 * 60)));
+        assert_eq!("-11m".parse().ok(), Some(FindTime::Upper(11 * 60)));
+        assert_eq!("-11".parse().ok(), Some(FindTime::Upper(11)));
+    }
+
+    #[test]
+    fn time_incorect() {
+        assert!("-".parse::<FindTime>().is_err());
+        assert!("-10t".parse::<FindTime>().is_err());
+        assert!("+".parse::<FindTime>().is_err());
+        assert!("+10t".parse::<FindTime>().is_err());
+    }
+
+    #[test]
+    fn tag_ok() {
+        assert_eq!(
+            "tag1:value2".parse().ok(),
+            Some(FindTag {
+                key: "tag1".to_owned(),
+                value: "value2".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn metrics_target_correct() {
+        assert_eq!("emf:stdout".parse().ok(), Some(MetricsTarget::Emf));
+        assert_eq!(
+            "statsd://localhost:8125".parse().ok(),
+            Some(MetricsTarget::Statsd("localhost:8125".to_owned()))
+        );
+    }
+
+    #[test]
+    fn metrics_target_incorrect() {
+        assert!("emf".parse::<MetricsTarget>().is_err());
+        assert!("statsd://localhost".parse::<MetricsTarget>().is_err());
+        assert!("statsd://:8125".parse::<MetricsTarget>().is_err());
+        assert!("udp://localhost:8125".parse::<MetricsTarget>().is_err());
+    }
+
+    #[test]
+    fn storage_rule_correct() {
+        assert_eq!(
+            "raw/*=standard_ia@30d".parse().ok(),
+            Some(StorageRule {
+                glob: Pattern::new("raw/*").unwrap(),
+                class: "STANDARD_IA".to_owned(),
+                age: Some(30 * 24 * 3600),
+            })
+        );
+        assert_eq!(
+            "archive/*=DEEP_ARCHIVE".parse().ok(),
+            Some(StorageRule {
+                glob: Pattern::new("archive/*").unwrap(),
+                class: "DEEP_ARCHIVE".to_owned(),
+                age: None,
+            })
+        );
+        assert_eq!(
+            "a=b/*=GLACIER@1w"
+                .parse::<StorageRule>()
+                .ok()
+                .map(|x| x.glob.as_str().to_owned()),
+            Some("a=b/*".to_owned())
+        );
+    }
+
+    #[test]
+    fn storage_rule_incorrect() {
+        assert!("raw/*".parse::<StorageRule>().is_err());
+        assert!("=STANDARD".parse::<StorageRule>().is_err());
+        assert!("raw/*=COLD".parse::<StorageRule>().is_err());
+        assert!("raw/*=STANDARD@".parse::<StorageRule>().is_err());
+        assert!("raw/*=STANDARD@-30d".parse::<StorageRule>().is_err());
+        assert!("raw/*=STANDARD@30x".parse::<StorageRule>().is_err());
+        assert!("[=STANDARD".parse::<StorageRule>().is_err());
+    }
+
+    #[test]
+    fn notify_url_parse() {
+        assert_eq!(
+            "https://catalog.example.com/hooks/s3".parse().ok(),
+            Some(NotifyUrl("https://catalog.example.com/hooks/s3".to_owned()))
+        );
+        assert!("http://127.0.0.1:8080".parse::<NotifyUrl>().is_ok());
+        assert!("ftp://catalog.example.com".parse::<NotifyUrl>().is_err());
+        assert!("https://".parse::<NotifyUrl>().is_err());
+        assert!("catalog.example.com".parse::<NotifyUrl>().is_err());
+    }
+
+    #[test]
+    fn sns_topic_parse() {
+        assert_eq!(
+            "arn:aws:sns:eu-west-1:123456789012:catalog".parse().ok(),
+            Some(SnsTopic {
+                arn: "arn:aws:sns:eu-west-1:123456789012:catalog".to_owned(),
+                region: "eu-west-1".to_owned(),
             })
         );
+        assert!("arn:aws:sqs:eu-west-1:123456789012:catalog"
+            .parse::<SnsTopic>()
+            .is_err());
+        assert!("arn:aws:sns::123456789012:catalog"
+            .parse::<SnsTopic>()
+            .is_err());
+        assert!("catalog".parse::<SnsTopic>().is_err());
+    }
+
+    /// The expression with every operation in parentheses
+    fn where_shape(expr: &WhereExpr) -> String {
+        match expr {
+            WhereExpr::And(left, right) => {
+                format!("({} and {})", where_shape(left), where_shape(right))
+            }
+            WhereExpr::Or(left, right) => {
+                format!("({} or {})", where_shape(left), where_shape(right))
+            }
+            WhereExpr::Not(expr) => format!("(not {})", where_shape(expr)),
+            WhereExpr::Name(glob) => glob.as_str().to_owned(),
+            WhereExpr::Depth(ordering, depth) => format!("depth{:?}{}", ordering, depth),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn where_precedence() {
+        let shape = |s: &str| where_shape(&s.parse::<WhereExpr>().unwrap());
+
+        assert_eq!(shape("name=a or name=b and name=c"), "(a or (b and c))");
+        assert_eq!(shape("name=a and name=b or name=c"), "((a and b) or c)");
+        assert_eq!(shape("name=a and name=b and name=c"), "((a and b) and c)");
+        assert_eq!(shape("name=a or name=b or name=c"), "((a or b) or c)");
+        assert_eq!(shape("not name=a and name=b"), "((not a) and b)");
+        assert_eq!(shape("not not name=a"), "(not (not a))");
+        assert_eq!(shape("(name=a or name=b) and name=c"), "((a or b) and c)");
+        assert_eq!(
+            shape("NOT (name=a OR depth>2)"),
+            "(not (a or depthGreater2))"
+        );
+        assert_eq!(shape("name='a b)' and name=\"c\""), "(a b) and c)");
+        assert_eq!(shape("((name=a))"), "a");
+    }
+
+    #[test]
+    fn where_predicates() {
+        let parse = |s: &str| s.parse::<WhereExpr>().unwrap();
+
+        assert!(matches!(
+            parse("size=+1G"),
+            WhereExpr::Size(FindSize::Bigger(1073741824))
+        ));
+        assert!(matches!(
+            parse("mtime=-1d"),
+            WhereExpr::Mtime(FindTime::Upper(86400))
+        ));
+        assert!(
+            matches!(parse("storage_class=glacier"), WhereExpr::StorageClass(class) if class == "GLACIER")
+        );
+        assert!(matches!(
+            parse("depth<3"),
+            WhereExpr::Depth(std::cmp::Ordering::Less, 3)
+        ));
+        assert!(matches!(parse("etag=abc"), WhereExpr::ETag(e_tag) if e_tag == "abc"));
+        assert!(matches!(parse("iname=*.LOG"), WhereExpr::Iname(_)));
+        assert!(matches!(parse("regex=^logs/.*"), WhereExpr::Regex(_)));
+    }
+
+    #[test]
+    fn where_errors() {
+        let error = |s: &str| s.parse::<WhereExpr>().unwrap_err().to_string();
+
+        assert_eq!(
+            error("name=a and"),
+            "Invalid --where expression at column 11: expected a predicate"
+        );
+        assert_eq!(
+            error("(name=a or name=b"),
+            "Invalid --where expression at column 18: expected )"
+        );
+        assert_eq!(
+            error("name=a name=b"),
+            "Invalid --where expression at column 8: unexpected input"
+        );
+        assert_eq!(
+            error("name=a and colour=red"),
+            "Invalid --where expression at column 12: unknown predicate colour"
+        );
+        assert!(
+            error("name=a or size=big").starts_with("Invalid --where expression at column 16: ")
+        );
+        assert_eq!(
+            error("name='a"),
+            "Invalid --where expression at column 6: unterminated quote"
+        );
+        assert_eq!(
+            error("size<5"),
+            "Invalid --where expression at column 5: expected ="
+        );
+        assert_eq!(
+            error("storage_class=COLD"),
+            "Invalid --where expression at column 15: Unknown storage class COLD"
+        );
+        assert!(error(")").contains("column 1: expected a predicate"));
     }
 
     #[test]
-    fn s3path_incorrect() {
-        assert!("testbucket".parse::<S3Path>().is_err());
-        assert!("s3://".parse::<S3Path>().is_err());
-        assert!("s3:/testbucket".parse::<S3Path>().is_err());
-        assert!("://testbucket".parse::<S3Path>().is_err());
+    fn trash_prefix_parse() {
+        let trash_prefix = ".trash/{date}/".parse::<TrashPrefix>().unwrap();
+        assert_eq!(trash_prefix.root(), ".trash/");
+        assert!("".parse::<TrashPrefix>().is_err());
+        assert!("/trash/".parse::<TrashPrefix>().is_err());
     }
 
     #[test]
-    fn size_corect() {
-        assert_eq!("11".parse().ok(), Some(FindSize::Equal(11)));
-        assert_eq!("11k".parse().ok(), Some(FindSize::Equal(11 * 1024)));
+    fn ignore_file_parse() {
+        let content =
+            "# temporary prefixes\r\ntmp/\r\n\r\n*.bak\n!keep.bak\n\\#literal\n\\!bang\n   \n";
+        let file = IgnoreFile::parse(".s3findignore", content).unwrap();
+        let patterns: Vec<_> = file
+            .patterns
+            .iter()
+            .map(|x| (x.glob.as_str(), x.negated))
+            .collect();
         assert_eq!(
-            "11M".parse().ok(),
-            Some(FindSize::Equal(11 * 1024_i64.pow(2)))
+            patterns,
+            vec![
+                ("tmp/*", false),
+                ("*.bak", false),
+                ("keep.bak", true),
+                ("#literal", false),
+                ("!bang", false),
+            ]
         );
+
+        assert!(matches!(
+            IgnoreFile::parse(".s3findignore", "tmp/\n[z-a\n"),
+            Err(FindError::IgnorePatternParse(_, 2, _))
+        ));
+    }
+
+    #[test]
+    fn cache_ttl_parse() {
+        use std::time::Duration;
+
         assert_eq!(
-            "11G".parse().ok(),
-            Some(FindSize::Equal(11 * 1024_i64.pow(3)))
+            "10m".parse::<CacheTtl>().ok(),
+            Some(CacheTtl(Duration::from_secs(600)))
         );
         assert_eq!(
-            "11T".parse().ok(),
-            Some(FindSize::Equal(11 * 1024_i64.pow(4)))
+            "90".parse::<CacheTtl>().ok(),
+            Some(CacheTtl(Duration::from_secs(90)))
         );
+        assert!("-10m".parse::<CacheTtl>().is_err());
+        assert!("+10m".parse::<CacheTtl>().is_err());
+        assert!("10x".parse::<CacheTtl>().is_err());
+    }
+
+    #[test]
+    fn max_duration_parse() {
+        use std::time::Duration;
+
         assert_eq!(
-            "11P".parse().ok(),
-            Some(FindSize::Equal(11 * 1024_i64.pow(5)))
+            "50m".parse::<MaxDuration>().ok(),
+            Some(MaxDuration(Duration::from_secs(3000)))
         );
-        assert_eq!("+11".parse().ok(), Some(FindSize::Bigger(11)));
-        assert_eq!("+11k".parse().ok(), Some(FindSize::Bigger(11 * 1024)));
-        assert_eq!("-11".parse().ok(), Some(FindSize::Lower(11)));
-        assert_eq!("-11k".parse().ok(), Some(FindSize::Lower(11 * 1024)));
+        assert_eq!(
+            "2h".parse::<MaxDuration>().ok(),
+            Some(MaxDuration(Duration::from_secs(7200)))
+        );
+        assert!("0".parse::<MaxDuration>().is_err());
+        assert!("-50m".parse::<MaxDuration>().is_err());
+        assert!("+50m".parse::<MaxDuration>().is_err());
+        assert!("50x".parse::<MaxDuration>().is_err());
     }
 
     #[test]
-    fn size_incorect() {
-        assert!("-".parse::<FindSize>().is_err());
-        assert!("-123w".parse::<FindSize>().is_err());
+    fn output_key_parse() {
+        let key: OutputKey = "s3://reports/runs/run-{date}.csv".parse().unwrap();
+        assert_eq!(
+            key.render("2024-05-01").prefix.as_deref(),
+            Some("runs/run-2024-05-01.csv")
+        );
+        assert_eq!(key.render("2024-05-01").bucket, "reports");
+
+        assert!("s3://reports/".parse::<OutputKey>().is_err());
+        assert!("s3://reports/runs/".parse::<OutputKey>().is_err());
+        assert!("s3://reports/run-{time}.csv".parse::<OutputKey>().is_err());
+        assert!("reports/run.csv".parse::<OutputKey>().is_err());
     }
 
     #[test]
-    fn time_corect() {
-        assert_eq!("11".parse().ok(), Some(FindTime::Lower(11)));
-        assert_eq!("11s".parse().ok(), Some(FindTime::Lower(11)));
-        assert_eq!("11m".parse().ok(), Some(FindTime::Lower(11 * 60)));
-        assert_eq!("11h".parse().ok(), Some(FindTime::Lower(11 * 3600)));
-        assert_eq!("11d".parse().ok(), Some(FindTime::Lower(11 * 3600 * 24)));
+    fn gzip_level_parse() {
+        assert_eq!(gzip_level("0").ok(), Some(0));
+        assert_eq!(gzip_level("9").ok(), Some(9));
+        assert!(gzip_level("10").is_err());
+        assert!(gzip_level("-1").is_err());
+        assert!(gzip_level("best").is_err());
+    }
+
+    #[test]
+    fn recompress_suffix_parse() {
+        let opts = |suffix: &str| {
+            FindOpt::from_iter_safe([
+                "s3find",
+                "s3://bucket/path",
+                "recompress",
+                "--suffix",
+                suffix,
+                "--delete-source",
+            ])
+        };
+
+        assert!(opts(".gz").is_ok());
+        assert!(opts("").is_err());
+    }
+
+    #[test]
+    fn list_api_parse() {
+        assert_eq!("v1".parse::<ListApi>().ok(), Some(ListApi::V1));
+        assert_eq!("v2".parse::<ListApi>().ok(), Some(ListApi::V2));
+        assert_eq!("auto".parse::<ListApi>().ok(), Some(ListApi::Auto));
+        assert!("v3".parse::<ListApi>().is_err());
+
+        let opts = FindOpt::from_iter(&["s3find", "s3://test/"]);
+        assert_eq!(opts.list_api, ListApi::Auto);
+    }
+
+    #[test]
+    fn color_mode_parse() {
+        assert_eq!("always".parse::<ColorMode>().ok(), Some(ColorMode::Always));
+        assert_eq!("never".parse::<ColorMode>().ok(), Some(ColorMode::Never));
+        assert!("yes".parse::<ColorMode>().is_err());
+        assert!(ColorMode::Always.enabled(false));
+        assert!(!ColorMode::Never.enabled(true));
+        assert!(!ColorMode::Auto.enabled(false));
+
+        let opts = FindOpt::from_iter(&["s3find", "s3://test/"]);
+        assert_eq!(opts.color, ColorMode::Auto);
+    }
+
+    #[test]
+    fn name_anchor_parse() {
         assert_eq!(
-            "11w".parse().ok(),
-            Some(FindTime::Lower(11 * 3600 * 24 * 7))
+            "prefix".parse::<NameAnchor>().ok(),
+            Some(NameAnchor::Prefix)
         );
-        assert_eq!("+11".parse().ok(), Some(FindTime::Lower(11)));
-        assert_eq!("+11m".parse().ok(), Some(FindTime::Lower(11 * 60)));
-        assert_eq!("-11m".parse().ok(), Some(FindTime::Upper(11 * 60)));
-        assert_eq!("-11".parse().ok(), Some(FindTime::Upper(11)));
+        assert_eq!(
+            "anywhere".parse::<NameAnchor>().ok(),
+            Some(NameAnchor::Anywhere)
+        );
+        assert!("start".parse::<NameAnchor>().is_err());
+
+        let opts = FindOpt::from_iter(&["s3find", "s3://test/"]);
+        assert_eq!(opts.anchor, NameAnchor::Anywhere);
     }
 
     #[test]
-    fn time_incorect() {
-        assert!("-".parse::<FindTime>().is_err());
-        assert!("-10t".parse::<FindTime>().is_err());
-        assert!("+".parse::<FindTime>().is_err());
-        assert!("+10t".parse::<FindTime>().is_err());
+    fn sample_rate_parse() {
+        assert_eq!(sample_rate("0.01").ok(), Some(0.01));
+        assert_eq!(sample_rate("1").ok(), Some(1.0));
+        assert!(sample_rate("0").is_err());
+        assert!(sample_rate("1.5").is_err());
+        assert!(sample_rate("x").is_err());
+
+        let opts = FindOpt::from_iter_safe(&["s3find", "s3://test/", "--sample-seed", "7"]);
+        assert!(opts.is_err());
     }
 
     #[test]
-    fn tag_ok() {
+    fn percent_parse() {
+        assert_eq!(percent("10").ok(), Some(10.0));
+        assert_eq!(percent("0.5").ok(), Some(0.5));
+        assert_eq!(percent("100").ok(), Some(100.0));
+        assert!(percent("0").is_err());
+        assert!(percent("101").is_err());
+        assert!(percent("10%").is_err());
+    }
+
+    #[test]
+    fn prefetch_parse() {
+        assert_eq!(prefetch("1").ok(), Some(1));
+        assert_eq!(prefetch("4").ok(), Some(4));
+        assert!(prefetch("0").is_err());
+        assert!(prefetch("-1").is_err());
+    }
+
+    #[test]
+    fn shard_parse() {
         assert_eq!(
-            "tag1:value2".parse().ok(),
-            Some(FindTag {
-                key: "tag1".to_owned(),
-                value: "value2".to_owned()
+            "2/8".parse::<Shard>().ok(),
+            Some(Shard { index: 2, count: 8 })
+        );
+        assert_eq!(
+            "0/1".parse::<Shard>().ok().map(|x| x.to_string()),
+            Some("0/1".to_owned())
+        );
+        assert!("8/8".parse::<Shard>().is_err());
+        assert!("0/0".parse::<Shard>().is_err());
+        assert!("2".parse::<Shard>().is_err());
+        assert!("-1/4".parse::<Shard>().is_err());
+
+        let opts = FindOpt::from_iter_safe(&["s3find", "s3://test/", "--shard-by-range"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn date_format_parse() {
+        assert_eq!("epoch".parse::<DateFormat>().unwrap(), DateFormat::Epoch);
+        assert_eq!(
+            "%Y-%m-%d %H:%M".parse::<DateFormat>().unwrap(),
+            DateFormat::Pattern("%Y-%m-%d %H:%M".to_owned())
+        );
+        assert!("%Y-%Q".parse::<DateFormat>().is_err());
+        assert!("epcoh".parse::<DateFormat>().is_err());
+
+        assert_eq!("UTC".parse::<Timezone>().unwrap(), Timezone::Utc);
+        assert_eq!("local".parse::<Timezone>().unwrap(), Timezone::Local);
+        assert!("Europe/Atlantis".parse::<Timezone>().is_err());
+        assert!("../../etc/passwd".parse::<Timezone>().is_err());
+
+        let opts =
+            FindOpt::from_iter_safe(["s3find", "s3://b/", "print", "--timezone", "Mars/Base"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn schedule_parse() {
+        assert_eq!("listing".parse::<Schedule>().unwrap(), Schedule::Listing);
+        assert_eq!(
+            "largest-first".parse::<Schedule>().unwrap(),
+            Schedule::LargestFirst
+        );
+        assert!("largest".parse::<Schedule>().is_err());
+
+        let mut sizes = vec![(0, 5), (1, 9), (2, 5), (3, 1)];
+        Schedule::LargestFirst.sort_by_size(&mut sizes, |x| x.1);
+        assert_eq!(sizes, vec![(1, 9), (0, 5), (2, 5), (3, 1)]);
+        Schedule::SmallestFirst.sort_by_size(&mut sizes, |x| x.1);
+        assert_eq!(sizes, vec![(3, 1), (0, 5), (2, 5), (1, 9)]);
+    }
+
+    #[test]
+    fn size_buckets_parse() {
+        assert_eq!(
+            "128k,1M".parse::<SizeBuckets>().ok(),
+            Some(SizeBuckets(vec![128 * 1024, 1024 * 1024]))
+        );
+        assert_eq!(
+            "128k,1M,16M,128M,1G".parse::<SizeBuckets>().ok(),
+            Some(SizeBuckets::default())
+        );
+        for invalid in ["", "1M,1k", "1k,1k", "+1k", "0", "1k,x"] {
+            assert!(invalid.parse::<SizeBuckets>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn workers_parse() {
+        assert_eq!("16".parse::<Workers>().ok(), Some(Workers::Fixed(16)));
+        assert_eq!(
+            "auto".parse::<Workers>().ok(),
+            Some(Workers::Auto(Default::default()))
+        );
+        assert!("0".parse::<Workers>().is_err());
+        assert!("many".parse::<Workers>().is_err());
+    }
+
+    #[test]
+    fn adaptive_concurrency() {
+        let mut concurrency = AdaptiveConcurrency::default();
+        let mut steps = Vec::new();
+        // ramp up to the ceiling, back off on throttling down to the floor
+        for throttled in [0, 0, 0, 2, 0, 1, 1, 1, 1, 1, 0] {
+            concurrency.update(throttled);
+            steps.push(concurrency.current);
+        }
+        assert_eq!(steps, vec![12, 16, 20, 10, 14, 7, 3, 2, 2, 2, 6]);
+        assert_eq!(concurrency.throttled, 7);
+
+        for _ in 0..100 {
+            concurrency.update(0);
+        }
+        assert_eq!(concurrency.current, AUTO_WORKERS_CEILING);
+
+        let workers = Workers::Auto(Default::default());
+        workers.observe(1);
+        assert_eq!(workers.current(), AUTO_WORKERS_START / 2);
+        assert_eq!(workers.settled(), Some((AUTO_WORKERS_START / 2, 1)));
+
+        let fixed = Workers::Fixed(16);
+        fixed.observe(1);
+        assert_eq!(fixed.current(), 16);
+        assert_eq!(fixed.settled(), None);
+    }
+
+    #[test]
+    fn byte_range_parse() {
+        assert_eq!(
+            "100-199".parse().ok(),
+            Some(ByteRange {
+                start: 100,
+                end: 199
+            })
+        );
+        assert_eq!("0-0".parse::<ByteRange>().map(|x| x.size()).ok(), Some(1));
+        assert!("100".parse::<ByteRange>().is_err());
+        assert!("-100".parse::<ByteRange>().is_err());
+        assert!("100-".parse::<ByteRange>().is_err());
+        assert!("200-100".parse::<ByteRange>().is_err());
+
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://bucket/",
+            "download",
+            "--range-bytes",
+            "65536",
+            "/tmp",
+        ])
+        .unwrap();
+        match opts.cmd {
+            Some(Cmd::Download(cmd)) => {
+                assert_eq!(
+                    cmd.byte_range().map(|x| x.header()),
+                    Some("bytes=0-65535".to_owned())
+                )
+            }
+            _ => panic!("download expected"),
+        }
+        assert!(FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://bucket/",
+            "download",
+            "--range-bytes",
+            "10",
+            "--range",
+            "0-9",
+            "/tmp",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn route_rule_parse() {
+        assert_eq!(
+            "dataset=alpha=>s3://lake/alpha/".parse().ok(),
+            Some(RouteRule {
+                key: "dataset".to_owned(),
+                value: "alpha".to_owned(),
+                destination: "s3://lake/alpha/".parse().unwrap(),
+            })
+        );
+        assert_eq!(
+            "dataset==>s3://lake"
+                .parse::<RouteRule>()
+                .ok()
+                .map(|x| x.value),
+            Some("".to_owned())
+        );
+        assert!("dataset=alpha".parse::<RouteRule>().is_err());
+        assert!("dataset=>s3://lake/alpha/".parse::<RouteRule>().is_err());
+        assert!("=alpha=>s3://lake/alpha/".parse::<RouteRule>().is_err());
+        assert!("dataset=alpha=>/tmp/alpha".parse::<RouteRule>().is_err());
+    }
+
+    #[test]
+    fn version_id_validation() {
+        let opts = |args: &[&str]| {
+            FindOpt::from_iter_safe(
+                ["s3find", "--version-id", "3HL4kqtJlcpXroDTDmjVBH40Nrjfkd"]
+                    .iter()
+                    .chain(args),
+            )
+            .unwrap()
+        };
+
+        assert!(opts(&["s3://bucket/dir/key", "download", "/tmp"])
+            .validate()
+            .is_ok());
+        assert!(opts(&["s3://bucket/dir/key", "tags", "env:prod"])
+            .validate()
+            .is_ok());
+        assert!(opts(&["s3://bucket/dir/key", "lstags"]).validate().is_ok());
+
+        assert!(matches!(
+            opts(&["s3://bucket/dir/", "download", "/tmp"]).validate(),
+            Err(FindError::VersionIdPath)
+        ));
+        assert!(matches!(
+            opts(&["s3://bucket", "lstags"]).validate(),
+            Err(FindError::VersionIdPath)
+        ));
+        assert!(matches!(
+            opts(&["s3://bucket/dir/*", "lstags"]).validate(),
+            Err(FindError::VersionIdPath)
+        ));
+        assert!(matches!(
+            opts(&["s3://bucket/dir/key", "delete"]).validate(),
+            Err(FindError::VersionIdCommand)
+        ));
+        assert!(matches!(
+            opts(&["s3://bucket/dir/key", "copy", "s3://other/"]).validate(),
+            Err(FindError::VersionIdCommand)
+        ));
+        assert!(matches!(
+            opts(&["s3://bucket/dir/key"]).validate(),
+            Err(FindError::VersionIdCommand)
+        ));
+    }
+
+    #[test]
+    fn estimate_requires_credentials() {
+        let opts = |args: &[&str]| {
+            FindOpt::from_iter_safe([&["s3find", "s3://bucket/path", "--estimate"], args].concat())
+                .unwrap()
+        };
+
+        assert!(matches!(
+            opts(&["--no-sign-request"]).validate(),
+            Err(FindError::EstimateUnsigned)
+        ));
+        assert!(opts(&[]).validate().is_ok());
+    }
+
+    #[test]
+    fn notify_sns_requires_credentials() {
+        let opts = |args: &[&str]| {
+            FindOpt::from_iter_safe(
+                [
+                    &[
+                        "s3find",
+                        "s3://bucket/path",
+                        "--notify-sns",
+                        "arn:aws:sns:us-east-1:123456789012:catalog",
+                    ],
+                    args,
+                ]
+                .concat(),
+            )
+            .unwrap()
+        };
+
+        assert!(matches!(
+            opts(&["--no-sign-request"]).validate(),
+            Err(FindError::NotifySnsUnsigned)
+        ));
+        assert!(opts(&[]).validate().is_ok());
+    }
+
+    #[test]
+    fn public_requires_acknowledgement() {
+        let opts = |args: &[&str]| {
+            FindOpt::from_iter_safe([&["s3find", "s3://bucket/path", "public"], args].concat())
+                .unwrap()
+        };
+
+        assert!(matches!(
+            opts(&[]).validate(),
+            Err(FindError::PublicNotAcknowledged)
+        ));
+        assert!(opts(&["--i-understand-this-makes-objects-public"])
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn copy_destination() -> Result<(), anyhow::Error> {
+        let dir = tempfile::TempDir::new()?;
+        let local = dir.path().display().to_string();
+
+        assert_eq!(
+            "s3://bucket/path".parse::<CopyDestination>()?,
+            CopyDestination::S3("s3://bucket/path".parse()?)
+        );
+        assert_eq!(
+            local.parse::<CopyDestination>()?,
+            CopyDestination::Local(dir.path().to_owned())
+        );
+        assert_eq!(
+            format!("file://{}", local).parse::<CopyDestination>()?,
+            CopyDestination::Local(dir.path().to_owned())
+        );
+
+        let missing = dir.path().join("missing").display().to_string();
+        let error = missing.parse::<CopyDestination>().unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "Destination {} is neither a valid s3://bucket/path nor an existing local directory",
+                missing
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lifecycle_rule_parse() {
+        assert_eq!(
+            "transition:standard_ia:30d".parse().ok(),
+            Some(LifecycleRule::Transition {
+                class: "STANDARD_IA".to_owned(),
+                days: 30,
+            })
+        );
+        assert_eq!(
+            "expire:365".parse().ok(),
+            Some(LifecycleRule::Expire { days: 365 })
+        );
+
+        assert!("expire".parse::<LifecycleRule>().is_err());
+        assert!("expire:1y".parse::<LifecycleRule>().is_err());
+        assert!("transition:COLD:30d".parse::<LifecycleRule>().is_err());
+        assert!("transition:30d".parse::<LifecycleRule>().is_err());
+        assert!("delete:30d".parse::<LifecycleRule>().is_err());
+    }
+
+    #[test]
+    fn report_file_format() {
+        assert_eq!(
+            "reports/run.md".parse().ok(),
+            Some(ReportFile {
+                path: "reports/run.md".to_owned(),
+                format: ReportFormat::Markdown,
             })
         );
+        assert_eq!(
+            "run.HTML".parse::<ReportFile>().ok().map(|x| x.format),
+            Some(ReportFormat::Html)
+        );
+        assert!("run.txt".parse::<ReportFile>().is_err());
+        assert!("run".parse::<ReportFile>().is_err());
+    }
+
+    #[test]
+    fn prefix_set_overlapping() {
+        let set = PrefixSet::new(
+            vec!["logs/2024/", "logs/", "data/raw", "data/raw/old", "b"]
+                .into_iter()
+                .map(str::to_owned),
+        );
+
+        assert_eq!(
+            set,
+            PrefixSet(vec![
+                "b".to_owned(),
+                "data/raw".to_owned(),
+                "logs/".to_owned()
+            ])
+        );
+
+        assert!(set.matches("logs/2024/01.log"));
+        assert!(set.matches("logs/"));
+        assert!(set.matches("data/raw/old/file"));
+        assert!(set.matches("data/rawfile"));
+        assert!(set.matches("backup"));
+
+        assert!(!set.matches("log"));
+        assert!(!set.matches("data/"));
+        assert!(!set.matches("data/ra"));
+        assert!(!set.matches("a"));
+        assert!(!set.matches("c"));
+        assert!(!PrefixSet::default().matches("logs/"));
+    }
+
+    #[test]
+    fn prefix_set_parse() {
+        let set = PrefixSet::parse("# legal holds\n\nhold/a/\r\n  hold/b/  \n#hold/c/\n");
+
+        assert_eq!(
+            set,
+            PrefixSet(vec!["hold/a/".to_owned(), "hold/b/".to_owned()])
+        );
+    }
+
+    #[test]
+    fn prefix_file_missing() {
+        assert!("/nonexistent/prefixes.txt"
+            .parse::<ExcludePrefixes>()
+            .is_err());
+        assert!("/nonexistent/prefixes.txt"
+            .parse::<IncludePrefixes>()
+            .is_err());
+    }
+
+    #[test]
+    fn glob_escapes() {
+        let pattern = glob_pattern(r"report\[2024\].csv").unwrap();
+        assert_eq!(pattern.as_str(), "report[[]2024[]].csv");
+        assert!(pattern.matches("report[2024].csv"));
+        assert!(!pattern.matches("report2.csv"));
+
+        let pattern = glob_pattern(r"*\*draft\?\\").unwrap();
+        assert!(pattern.matches(r"docs/*draft?\"));
+        assert!(!pattern.matches(r"docs/xdraftx\"));
+
+        assert!(glob_pattern(r"a\b").unwrap().matches(r"a\b"));
+        assert!(glob_pattern("report[2024.csv").is_err());
+        assert!("report\\[2024.csv".parse::<InameGlob>().is_ok());
+    }
+
+    #[test]
+    fn name_literal() {
+        let literal: NameLiteral = "report[2024].csv".parse().unwrap();
+        assert!(literal.matches("report[2024].csv"));
+        assert!(literal.matches("exports/report[2024].csv"));
+        assert!(!literal.matches("exports/old-report[2024].csv"));
+        assert!(!literal.matches("report[2024].csv.bak"));
+
+        let literal: NameLiteral = "2024/*.csv".parse().unwrap();
+        assert!(literal.matches("exports/2024/*.csv"));
+        assert!(!literal.matches("exports/2024/a.csv"));
+
+        assert!("".parse::<NameLiteral>().is_err());
     }
 
     #[test]
@@ -549,4 +4479,174 @@ mod tests {
         assert!("tag1:value2:".parse::<FindTag>().is_err());
         assert!(":".parse::<FindTag>().is_err());
     }
+
+    #[test]
+    fn manifest_options() {
+        let opts =
+            |args: &[&str]| FindOpt::from_iter_safe(["s3find", "s3://bucket/"].iter().chain(args));
+
+        let manifest = opts(&["--emit-manifest", "s3://manifests/run.csv"]).unwrap();
+        assert!(manifest.validate().is_ok());
+        assert_eq!(manifest.manifest_format, ManifestFormat::Csv);
+
+        assert!(matches!(
+            opts(&["--emit-manifest", "s3://manifests/runs/"])
+                .unwrap()
+                .validate(),
+            Err(FindError::ManifestKey)
+        ));
+        assert!(opts(&[
+            "--emit-manifest",
+            "s3://manifests/run.csv",
+            "--manifest-local",
+            "run.csv"
+        ])
+        .is_err());
+        assert!(opts(&["--manifest-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn list_multipart_options() {
+        let opts = |args: &[&str]| {
+            FindOpt::from_iter_safe(["s3find", "s3://bucket/"].iter().chain(args)).unwrap()
+        };
+
+        assert!(opts(&[
+            "--name",
+            "*.bin",
+            "--mtime",
+            "+7d",
+            "list-multipart",
+            "--abort"
+        ])
+        .validate()
+        .is_ok());
+        assert!(matches!(
+            opts(&["--size", "+1M", "list-multipart"]).validate(),
+            Err(FindError::MultipartOption("--size"))
+        ));
+        assert!(matches!(
+            opts(&["--oldest-percent", "10", "list-multipart"]).validate(),
+            Err(FindError::MultipartOption(_))
+        ));
+        assert!(matches!(
+            opts(&["--manifest-local", "run.csv", "list-multipart"]).validate(),
+            Err(FindError::MultipartOption("--emit-manifest"))
+        ));
+    }
+
+    #[test]
+    fn stdin_options() {
+        let opts = |args: &[&str]| FindOpt::from_iter_safe(["s3find"].iter().chain(args));
+
+        let stdin = opts(&["--stdin", "--strict", "--size", "+1k", "delete"]).unwrap();
+        assert!(stdin.stdin && stdin.strict);
+        assert_eq!(stdin.path, None);
+        assert!(stdin.validate().is_ok());
+
+        assert!(opts(&["delete"]).is_err());
+        assert!(opts(&["--stdin", "s3://bucket/", "delete"]).is_err());
+        assert!(opts(&["--strict", "s3://bucket/", "delete"]).is_err());
+        assert!(matches!(
+            opts(&["--stdin", "--oldest-percent", "10", "ls"])
+                .unwrap()
+                .validate(),
+            Err(FindError::StdinOption(_))
+        ));
+        assert!(matches!(
+            opts(&["--stdin", "list-multipart"]).unwrap().validate(),
+            Err(FindError::StdinOption("list-multipart"))
+        ));
+        assert!(matches!(
+            opts(&["--stdin", "--one-level"]).unwrap().validate(),
+            Err(FindError::StdinOption("--one-level"))
+        ));
+    }
+
+    #[test]
+    fn reevaluate_mtime_options() {
+        let opts = |args: &[&str]| {
+            FindOpt::from_iter_safe(["s3find", "s3://bucket/"].iter().chain(args)).unwrap()
+        };
+
+        assert!(!opts(&["--reevaluate-mtime-at-action", "delete"]).tests_mtime());
+        assert!(matches!(
+            opts(&["--reevaluate-mtime-at-action", "delete"]).validate(),
+            Err(FindError::ReevaluateWithoutMtime)
+        ));
+        assert!(
+            opts(&["--reevaluate-mtime-at-action", "--mtime", "+30d", "delete"])
+                .validate()
+                .is_ok()
+        );
+        let expr = opts(&["--where", "not (name=*.log or mtime=-1d)"]);
+        assert!(expr.tests_mtime());
+        assert!(!opts(&["--where", "size=+1k"]).tests_mtime());
+    }
+
+    #[test]
+    fn suggest_lifecycle_options() {
+        assert_eq!(age_days("90d").ok(), Some(90));
+        assert_eq!(age_days("365").ok(), Some(365));
+        assert!(age_days("0d").is_err());
+        assert!(age_days("1y").is_err());
+
+        assert_eq!(
+            transition_class("glacier_ir").ok().as_deref(),
+            Some("GLACIER_IR")
+        );
+        assert!(transition_class("STANDARD").is_err());
+        assert!(transition_class("COLD").is_err());
+
+        let opts =
+            FindOpt::from_iter_safe(&["s3find", "s3://bucket/", "suggest-lifecycle"]).unwrap();
+        match opts.cmd {
+            Some(Cmd::SuggestLifecycle(cmd)) => {
+                assert_eq!(cmd.depth, 1);
+                assert_eq!(cmd.transition_class, "STANDARD_IA");
+                assert_eq!(cmd.access_percent, 80.0);
+                assert_eq!(cmd.min_transition_days, 30);
+                assert_eq!(cmd.retention_days, None);
+                assert_eq!(cmd.format, LifecycleFormat::Json);
+                assert!(!cmd.apply);
+            }
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+        assert!("yaml".parse::<LifecycleFormat>().is_err());
+    }
+
+    #[test]
+    fn tag_limits() {
+        let key = "k".repeat(TAG_KEY_MAX_LENGTH);
+        let value = "v".repeat(TAG_VALUE_MAX_LENGTH);
+        assert!(format!("{}:{}", key, value).parse::<FindTag>().is_ok());
+
+        let error = format!("{}k:value", key).parse::<FindTag>().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FindError>(),
+            Some(FindError::TagKeyTooLong(_))
+        ));
+        let error = format!("key:{}v", value).parse::<FindTag>().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FindError>(),
+            Some(FindError::TagValueTooLong(_))
+        ));
+
+        let tags = |count: usize| {
+            let tags: Vec<_> = (0..count).map(|n| format!("key{}:value", n)).collect();
+            FindOpt::from_iter_safe(
+                ["s3find", "s3://bucket/", "tags"]
+                    .iter()
+                    .map(|x| x.to_string())
+                    .chain(tags),
+            )
+            .unwrap()
+            .validate()
+        };
+        assert!(tags(MAX_OBJECT_TAGS).is_ok());
+        assert!(matches!(
+            tags(MAX_OBJECT_TAGS + 1),
+            Err(FindError::TooManyTags(11))
+        ));
+    }
 }
@@ -1,27 +1,325 @@
+use std::io::{self, IsTerminal};
+use std::process;
+use std::time::{Duration, Instant};
+
 use anyhow::Error;
+use futures::StreamExt;
 use structopt::StructOpt;
 
 use s3find::arg::*;
 use s3find::command::*;
+use s3find::complete::*;
+use s3find::estimate::CloudWatchMetrics;
+use s3find::manifest::{ManifestTarget, ManifestWriter};
+use s3find::metrics::{metrics_sink, MetricsSink, PrometheusTextfile};
+use s3find::notify::{Notifier, SnsNotifier, WebhookNotifier};
+use s3find::pipe::{read_urls, run_piped};
+use s3find::plan::Plan;
+use s3find::progress::{progress_emitter, ProgressReporter};
+use s3find::report::RunReport;
 use s3find::run::*;
+use s3find::schema::cli_schema;
+use s3find::wizard::{wizard, Invocation, Terminal, WIZARD_COMMAND};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let args = FindOpt::from_args();
-    let (find, filters) = Find::from_opts(&args).await;
-
-    let stats = list_filter_execute(
-        find.to_stream().stream(),
-        find.limit,
-        default_stats(find.summarize),
-        |x| filters.test_match(x.clone()),
-        &mut |acc, x| find.exec(acc, x),
-    )
-    .await;
+    let start = Instant::now();
+    let mut arguments = std::env::args().skip(1);
+    let (mut args, argv) = match arguments.next().as_deref() {
+        Some(COMPLETE_PREFIX_COMMAND) => {
+            complete(&arguments.next().unwrap_or_default()).await;
+            return Ok(());
+        }
+        Some(WIZARD_COMMAND) => match run_wizard().await? {
+            Some(invocation) => (
+                invocation.opts()?,
+                std::iter::once("s3find".to_owned())
+                    .chain(invocation.args)
+                    .collect(),
+            ),
+            None => return Ok(()),
+        },
+        _ => (FindOpt::from_args(), std::env::args().collect::<Vec<_>>()),
+    };
+    if args.dump_cli_schema {
+        println!("{:#}", cli_schema());
+        return Ok(());
+    }
+    args.discover_ignore_file()?;
+    args.validate()?;
+    if args.explain {
+        print!("{}", Plan::new(&args)?);
+        return Ok(());
+    }
+    let (mut find, mut filters) = Find::from_opts(&args).await?;
+    if let Some(note) = mtime_reference_note(&args) {
+        eprintln!("{}", note);
+    }
+    if let Some(shard) = find.shard_note() {
+        eprintln!("shard {}", shard);
+    }
+    if let Some(url) = &args.notify_url {
+        find.notifiers
+            .push(Notifier::Webhook(Box::new(WebhookNotifier::new(url)?)));
+    }
+    if let Some(topic) = &args.notify_sns {
+        let notifier = SnsNotifier::new(topic, find.credentials_provider.clone())?;
+        find.notifiers.push(Notifier::Sns(notifier));
+    }
+    find.manifest = ManifestTarget::from_opts(&args)
+        .map(|target| ManifestWriter::new(target, args.manifest_format, args.version_id.clone()))
+        .transpose()?;
+    if !args.no_sign_request {
+        find.check_credentials().await?;
+    }
+    // the keys of --stdin are checked bucket by bucket
+    if !args.stdin {
+        find.command.preflight(&find.client, &find.path).await?;
+    }
+
+    // the run goes on without a supervisor to report to
+    let emitter = progress_emitter(&args).unwrap_or_else(|e| {
+        eprintln!("warning: no progress events: {}", e);
+        None
+    });
+    let reporter = ProgressReporter::new(emitter, Duration::from_secs(args.progress_interval));
+
+    if let Some(Cmd::ListMultipart(cmd)) = &args.cmd {
+        let summary = cmd.run(&find, &filters).await?;
+        reporter.summary(&find.counters);
+        println!("{}", summary);
+        if let Some(error) = find.budget_error() {
+            eprintln!("Error: {}", error);
+            process::exit(BUDGET_EXHAUSTED_EXIT_CODE);
+        }
+        if let Some(error) = find.time_limit_error() {
+            eprintln!("Error: {}", error);
+            process::exit(TIME_LIMIT_EXIT_CODE);
+        }
+        if summary.errors > 0 {
+            process::exit(COMMAND_FAILED_EXIT_CODE);
+        }
+        return Ok(());
+    }
+
+    if args.estimate {
+        print_estimate(&find, &filters, &args).await;
+    }
+
+    let cutoffs = find
+        .percentile_cutoffs(&filters, args.oldest_percent, args.largest_percent)
+        .await;
+    for cutoff in cutoffs {
+        filters.push(cutoff);
+    }
+    filters.sort_by_cost();
+
+    // a single object command needs no listing when its key exists
+    let command = args.cmd.clone().unwrap_or_default();
+    let exact = match !args.stdin && command.single_object(&find.path) {
+        true => find.exact_object(args.version_id.as_deref()).await?,
+        false => None,
+    };
+
+    let stats = match exact {
+        Some(object) => find.exec_exact(&filters, object).await,
+        None if args.stdin => {
+            let keys = read_urls(io::stdin().lock(), args.strict, args.null)?;
+            let head = !command.key_only() || filters.reads_metadata();
+            let stats = find.new_stats();
+            run_piped(&mut find, &filters, keys, head, stats).await?
+        }
+        None => {
+            let show_progress =
+                args.progress_always || (args.progress && io::stdout().is_terminal());
+            let heartbeat = Heartbeat::new(HEARTBEAT_INTERVAL, print_heartbeat);
+            let heartbeat = &heartbeat;
+            let reporter = &reporter;
+            let delete_progress = match command {
+                Cmd::Delete(_) if show_progress => {
+                    Some(DeleteProgress::new(DELETE_PROGRESS_BATCHES))
+                }
+                _ => None,
+            };
+            let delete_progress = delete_progress.as_ref();
+            let find = &find;
+
+            let stats = list_filter_execute(
+                find.to_stream().stream().inspect(|page| {
+                    if show_progress {
+                        heartbeat.page(page)
+                    }
+                    reporter.page(&find.counters);
+                }),
+                find.limit,
+                find.new_stats(),
+                |x| {
+                    find.counters.scan();
+                    let matched = filters.test_match(x.object.clone());
+                    let identifier = find.dedup_identifier(x);
+                    let counters = &find.counters;
+                    async move {
+                        let matched = find.admit(matched.await, identifier);
+                        heartbeat.matched(counters.filtered(matched))
+                    }
+                },
+                &mut |acc, x| {
+                    if heartbeat.take_reported() {
+                        clear_heartbeat();
+                    }
+                    reporter.tick(&find.counters);
+                    async move {
+                        let acc = find.exec(acc, x).await;
+                        if let Some(line) = delete_progress.and_then(|x| x.batch(&find.counters)) {
+                            eprintln!("{}", line);
+                        }
+                        acc
+                    }
+                },
+            )
+            .await;
+
+            if heartbeat.take_reported() {
+                clear_heartbeat();
+            }
+            stats
+        }
+    };
+
+    // a failed finish or manifest still prints the summaries of the run
+    let finished = finish(&find).await;
+    invalidate_cache(&find, args.cmd.as_ref());
+    reporter.summary(&find.counters);
+
+    if let Some(note) = find.empty_note() {
+        eprintln!("{}", note);
+    }
+    if let Some(warning) = find.invalid_utf8_note() {
+        eprintln!("{}", warning);
+    }
+    if let Some(note) = find.duplicates_note() {
+        eprintln!("{}", note);
+    }
+    if let Some(note) = find.mtime_recheck_note() {
+        eprintln!("{}", note);
+    }
+    if let Some(hint) = filters.name_hint(args.anchor) {
+        eprintln!("{}", hint);
+    }
+
+    if let Some(summary) = find.command.summary() {
+        println!("{}", summary);
+    }
 
     if find.summarize {
-        println!("{}", stats.unwrap());
+        println!("{}", find.stat_summary(stats.as_ref().unwrap()));
+        if let Some(rate) = args.sample_rate {
+            println!("{}", SampleEstimate::new(stats.as_ref().unwrap(), rate));
+        }
+        if !filters.0.is_empty() {
+            println!("{}", SelectivityTable(&filters.selectivity()));
+        }
+    }
+
+    if command.downloads() {
+        println!("{}", DownloadStats::new(&find.counters, start.elapsed()));
+    }
+    if let Cmd::Delete(_) = command {
+        println!("{}", DeleteStats::new(&find.counters));
+    }
+    let metrics = find.metrics(command.name(), stats.as_ref(), start.elapsed());
+
+    if let Some(target) = &args.metrics {
+        metrics_sink(target).emit(&metrics)?;
     }
+    if let Some(path) = &args.prom_textfile {
+        PrometheusTextfile { path: path.clone() }.emit(&metrics)?;
+    }
+
+    if let Some(report_file) = &args.report_file {
+        RunReport::new(metrics, stats, &find.counters, argv)
+            .with_filters(filters.selectivity())
+            .write(report_file)?;
+    }
+
+    finished?;
+
+    if let Some(error) = find.stop_error() {
+        eprintln!("Error: {}", error);
+    }
+
+    if let Some(code) = find.exit_code() {
+        process::exit(code);
+    }
+
+    Ok(())
+}
 
+/// The command and manifest steps after the listing
+async fn finish(find: &Find) -> Result<(), Error> {
+    let finished = find.command.finish(&find.client, &find.path).await?;
+    find.counters.add(&finished);
+
+    if let Some(manifest) = &find.manifest {
+        println!("{}", manifest.finish(&find.client).await?);
+    }
     Ok(())
 }
+
+/// Prints the CloudWatch totals of the bucket before listing, a failed
+/// query is a note and never stops the run
+async fn print_estimate(find: &Find, filters: &FilterList<'_>, args: &FindOpt) {
+    // FindOpt::validate refuses --estimate with --no-sign-request
+    let provider = match &find.credentials_provider {
+        Some(provider) => provider.clone(),
+        None => return,
+    };
+
+    let metrics = CloudWatchMetrics::new(find.path.region.as_ref(), provider);
+    match metrics.estimate(&find.path.bucket).await {
+        Ok(estimate) => {
+            eprintln!("{}", estimate);
+            let narrowed = find.limit.is_some()
+                || !filters.0.is_empty()
+                || args.oldest_percent.is_some()
+                || args.largest_percent.is_some();
+            if let Some(warning) = estimate.warning(args.estimate_threshold, narrowed) {
+                eprintln!("{}", warning);
+            }
+        }
+        Err(e) => eprintln!("note: cannot read the CloudWatch estimate: {}", e),
+    }
+}
+
+/// Drops the cached listings a mutating command changed, a repeated run
+/// lists them again
+fn invalidate_cache(find: &Find, cmd: Option<&Cmd>) {
+    let (cache, cmd) = match (&find.cache, cmd) {
+        (Some(cache), Some(cmd)) if cmd.mutates() => (cache, cmd),
+        _ => return,
+    };
+
+    for path in std::iter::once(&find.path).chain(cmd.s3_destination()) {
+        if let Err(e) = cache.invalidate(path) {
+            eprintln!("warning: cannot invalidate the listing cache: {}", e);
+        }
+    }
+}
+
+/// The invocation built by `s3find wizard` when the user runs it now
+async fn run_wizard() -> Result<Option<Invocation>, Error> {
+    let client = completion_client().await;
+    let mut terminal = Terminal::new(io::stdin().lock(), io::stdout());
+    let invocation = wizard(&mut terminal, &client).await?;
+    Ok(Some(invocation).filter(|x| x.run))
+}
+
+/// Hidden helper for the shell completion scripts, errors print nothing
+async fn complete(partial: &str) {
+    let client = completion_client().await;
+    if let Ok(candidates) = complete_prefix(&client, partial).await {
+        for candidate in candidates {
+            println!("{}", candidate);
+        }
+    }
+}
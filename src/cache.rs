@@ -0,0 +1,323 @@
+//! Local cache of the listed pages for --cache-dir: a run repeated on the
+//! same path within --cache-ttl reads the pages from disk instead of S3.
+//!
+//! An entry is a JSON lines file, a header line with the listing parameters
+//! and the creation time followed by one line per listed page. The pages are
+//! written to a `.partial` file renamed once the listing reached its end, so
+//! an interrupted or failed listing is never served.
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use aws_sdk_s3::types::{Object, ObjectStorageClass, Owner};
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::arg::S3Path;
+use crate::utils::{add_key_bytes, ListedObject};
+
+const ENTRY_EXTENSION: &str = "jsonl";
+const PARTIAL_EXTENSION: &str = "partial";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingCache {
+    pub dir: PathBuf,
+    pub ttl: Duration,
+}
+
+/// The parameters a cached listing was requested with
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheKey {
+    pub bucket: String,
+    pub prefix: String,
+    pub page_size: i64,
+    pub fetch_owner: bool,
+}
+
+/// Pages read from a fresh cache entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedListing {
+    pub pages: Vec<Vec<ListedObject>>,
+    pub age: Duration,
+}
+
+impl CacheKey {
+    fn header(&self, created: i64) -> Value {
+        json!({
+            "Bucket": self.bucket,
+            "Prefix": self.prefix,
+            "PageSize": self.page_size,
+            "FetchOwner": self.fetch_owner,
+            "Created": created,
+        })
+    }
+
+    fn matches(&self, header: &Value) -> bool {
+        header["Bucket"] == self.bucket.as_str()
+            && header["Prefix"] == self.prefix.as_str()
+            && header["PageSize"] == self.page_size
+            && header["FetchOwner"] == self.fetch_owner
+    }
+
+    fn file_stem(&self) -> String {
+        let parameters = format!(
+            "{}\n{}\n{}\n{}",
+            self.bucket, self.prefix, self.page_size, self.fetch_owner
+        );
+        format!("{:016x}", fnv1a(parameters.as_bytes()))
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl ListingCache {
+    fn entry_path(&self, key: &CacheKey, extension: &str) -> PathBuf {
+        self.dir.join(key.file_stem()).with_extension(extension)
+    }
+
+    /// The pages of a fresh complete entry, a stale, unreadable or
+    /// mismatched entry is ignored
+    pub fn read(&self, key: &CacheKey) -> Option<CachedListing> {
+        let file = File::open(self.entry_path(key, ENTRY_EXTENSION)).ok()?;
+        let mut lines = BufReader::new(file).lines();
+
+        let header: Value = serde_json::from_str(&lines.next()?.ok()?).ok()?;
+        if !key.matches(&header) {
+            return None;
+        }
+        let age = Utc::now().timestamp() - header["Created"].as_i64()?;
+        let age = Duration::from_secs(u64::try_from(age).ok()?);
+        if age >= self.ttl {
+            return None;
+        }
+
+        let mut pages = Vec::new();
+        for line in lines {
+            let page: Value = serde_json::from_str(&line.ok()?).ok()?;
+            let page = page
+                .as_array()?
+                .iter()
+                .map(json_object)
+                .collect::<Option<Vec<_>>>()?;
+            pages.push(page);
+        }
+        Some(CachedListing { pages, age })
+    }
+
+    /// Starts a new entry, the listing is served from it once committed
+    pub fn writer(&self, key: &CacheKey) -> io::Result<CacheWriter> {
+        fs::create_dir_all(&self.dir)?;
+        let partial = self.entry_path(key, PARTIAL_EXTENSION);
+        let mut file = BufWriter::new(File::create(&partial)?);
+        writeln!(file, "{}", key.header(Utc::now().timestamp()))?;
+
+        Ok(CacheWriter {
+            file,
+            partial,
+            entry: self.entry_path(key, ENTRY_EXTENSION),
+        })
+    }
+
+    /// Removes the entries of the bucket listing keys under the path or
+    /// above it, returns the number of removed entries
+    pub fn invalidate(&self, path: &S3Path) -> io::Result<usize> {
+        let prefix = path.prefix.as_deref().unwrap_or_default();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?.path();
+            if entry.extension().and_then(|x| x.to_str()) != Some(ENTRY_EXTENSION) {
+                continue;
+            }
+            let header = match entry_header(&entry) {
+                Some(header) => header,
+                None => continue,
+            };
+            let cached = header["Prefix"].as_str().unwrap_or_default();
+            if header["Bucket"] == path.bucket.as_str()
+                && (cached.starts_with(prefix) || prefix.starts_with(cached))
+            {
+                fs::remove_file(&entry)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn entry_header(path: &Path) -> Option<Value> {
+    let file = File::open(path).ok()?;
+    let line = BufReader::new(file).lines().next()?.ok()?;
+    serde_json::from_str(&line).ok()
+}
+
+/// Writes the listed pages of a new entry
+#[derive(Debug)]
+pub struct CacheWriter {
+    file: BufWriter<File>,
+    partial: PathBuf,
+    entry: PathBuf,
+}
+
+impl CacheWriter {
+    pub fn append(&mut self, page: &[ListedObject]) -> io::Result<()> {
+        let page: Vec<_> = page.iter().map(object_json).collect();
+        writeln!(self.file, "{}", Value::Array(page))
+    }
+
+    /// Publishes the entry, the listing reached its end
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.partial, &self.entry)
+    }
+}
+
+fn object_json(listed: &ListedObject) -> Value {
+    let object = &listed.object;
+    let mut value = json!({
+        "Key": object.key,
+        "Size": object.size,
+        "LastModified": object
+            .last_modified
+            .and_then(|x| x.fmt(Format::DateTime).ok()),
+        "ETag": object.e_tag,
+        "StorageClass": object.storage_class.as_ref().map(|x| x.as_str()),
+        "Owner": object.owner.as_ref().map(|x| json!({
+            "ID": x.id,
+            "DisplayName": x.display_name,
+        })),
+    });
+    add_key_bytes(&mut value, listed.key_bytes.as_deref());
+    value
+}
+
+fn json_object(value: &Value) -> Option<ListedObject> {
+    let last_modified = match value["LastModified"].as_str() {
+        Some(x) => Some(DateTime::from_str(x, Format::DateTime).ok()?),
+        None => None,
+    };
+    let owner = value["Owner"].as_object().map(|x| {
+        Owner::builder()
+            .set_id(x.get("ID").and_then(Value::as_str).map(str::to_owned))
+            .set_display_name(
+                x.get("DisplayName")
+                    .and_then(Value::as_str)
+                    .map(str::to_owned),
+            )
+            .build()
+    });
+
+    // the listed bytes of a key which is not valid UTF-8
+    let key_bytes = match value["key_bytes_base64"].as_str() {
+        Some(bytes) => Some(aws_smithy_types::base64::decode(bytes).ok()?),
+        None => None,
+    };
+
+    let object = Object::builder()
+        .set_key(value["Key"].as_str().map(str::to_owned))
+        .set_size(value["Size"].as_i64())
+        .set_last_modified(last_modified)
+        .set_e_tag(value["ETag"].as_str().map(str::to_owned))
+        .set_storage_class(value["StorageClass"].as_str().map(ObjectStorageClass::from))
+        .set_owner(owner)
+        .build();
+    Some(ListedObject { object, key_bytes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(prefix: &str) -> CacheKey {
+        CacheKey {
+            bucket: "test".to_owned(),
+            prefix: prefix.to_owned(),
+            page_size: 1000,
+            fetch_owner: false,
+        }
+    }
+
+    fn pages() -> Vec<Vec<ListedObject>> {
+        vec![
+            vec![Object::builder()
+                .key("dir/a")
+                .size(10)
+                .last_modified(DateTime::from_secs(1_700_000_000))
+                .e_tag("\"etag\"")
+                .storage_class(ObjectStorageClass::Glacier)
+                .owner(Owner::builder().id("owner").display_name("name").build())
+                .build()
+                .into()],
+            vec![
+                Object::builder().key("dir/b").size(0).build().into(),
+                ListedObject {
+                    object: Object::builder().key("dir/c\u{fffd}").build(),
+                    key_bytes: Some(b"dir/c\xff".to_vec()),
+                },
+            ],
+        ]
+    }
+
+    #[test]
+    fn test_cache_roundtrip() -> io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let cache = ListingCache {
+            dir: dir.path().join("cache"),
+            ttl: Duration::from_secs(600),
+        };
+
+        let mut writer = cache.writer(&key("dir/"))?;
+        for page in pages() {
+            writer.append(&page)?;
+        }
+        // a partial entry is never served
+        assert_eq!(cache.read(&key("dir/")), None);
+
+        writer.commit()?;
+        let listing = cache.read(&key("dir/")).unwrap();
+        assert_eq!(listing.pages, pages());
+        assert!(listing.age < Duration::from_secs(600));
+        assert_eq!(cache.read(&key("other/")), None);
+
+        let stale = ListingCache {
+            ttl: Duration::from_secs(0),
+            ..cache.clone()
+        };
+        assert_eq!(stale.read(&key("dir/")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_invalidate() -> Result<(), anyhow::Error> {
+        let dir = tempfile::TempDir::new()?;
+        let cache = ListingCache {
+            dir: dir.path().to_owned(),
+            ttl: Duration::from_secs(600),
+        };
+        for prefix in &["dir/", "dir/sub/", "other/"] {
+            cache.writer(&key(prefix))?.commit()?;
+        }
+
+        assert_eq!(cache.invalidate(&"s3://test/dir/sub/".parse()?)?, 2);
+        assert_eq!(cache.read(&key("dir/")), None);
+        assert_eq!(cache.read(&key("dir/sub/")), None);
+        assert!(cache.read(&key("other/")).is_some());
+        assert_eq!(cache.invalidate(&"s3://another/".parse()?)?, 0);
+        Ok(())
+    }
+}
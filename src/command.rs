@@ -1,35 +1,149 @@
+use std::convert::TryFrom;
 use std::fmt;
+use std::io::IsTerminal;
 use std::ops::Add;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
+use anyhow::Error;
 use aws_config::meta::credentials::CredentialsProviderChain;
-use aws_config::BehaviorVersion;
-use aws_sdk_s3::config::{Credentials, Region};
+use aws_config::{BehaviorVersion, ConfigLoader, SdkConfig};
+use aws_credential_types::provider::error::CredentialsError;
+use aws_sdk_s3::config::interceptors::BeforeTransmitInterceptorContextRef;
+use aws_sdk_s3::config::{
+    ConfigBag, Credentials, Intercept, ProvideCredentials, Region, RuntimeComponents,
+    SharedCredentialsProvider,
+};
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::types::{CommonPrefix, EncodingType, Object, ObjectStorageClass};
 use aws_sdk_s3::Client;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use glob::Pattern;
 use humansize::*;
 use regex::Regex;
+use tokio::time::Instant;
 
 use crate::arg::*;
-use crate::filter::Filter;
+use crate::cache::{CacheKey, ListingCache};
+use crate::credentials::CommandCredentialsProvider;
+use crate::dedup::Dedup;
+use crate::error::FunctionError;
+use crate::filter::{
+    AnchoredGlob, AtReference, ExcludeDestination, Filter, FilterCost, GlobSet, IgnoreSet,
+    KeySample, KeyShard, MtimeRecheck, NoExtension, PercentileCutoff, Reservoir,
+    GLOB_SET_THRESHOLD,
+};
 use crate::function::*;
+use crate::manifest::ManifestWriter;
+use crate::metrics::RunMetrics;
+use crate::notify::{MutationEvent, Notifier};
+use crate::shard::{shard_range, KeyRange};
+use crate::utils::{
+    decode_listed_key, decode_url_key, display_key, error_context, listed_terminal_key, s3_error,
+    terminal_key, KeyBytes, ListedObject,
+};
+
+/// Reservoir size of the --oldest-percent and --largest-percent pass, the
+/// cutoff rank is off by about 0.5% of the objects at most
+pub const PERCENTILE_SAMPLE: usize = 10_000;
 
 pub struct AWSPair {
     access: Option<String>,
     secret: Option<String>,
+    command: Option<String>,
+    no_sign_request: bool,
+}
+
+/// A filter of the list with the number of objects it tested and
+/// rejected, counted with relaxed atomics so batches may be tested
+/// concurrently
+pub struct CountedFilter<'a> {
+    pub filter: Box<dyn Filter + 'a>,
+    evaluated: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl<'a> CountedFilter<'a> {
+    pub fn new(filter: impl Filter + 'a) -> Self {
+        CountedFilter {
+            filter: Box::new(filter),
+            evaluated: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn test(&self, object: &Object) -> bool {
+        self.evaluated.fetch_add(1, Ordering::Relaxed);
+        let matched = self.filter.filter(object);
+        if !matched {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        matched
+    }
+
+    pub fn selectivity(&self) -> FilterSelectivity {
+        FilterSelectivity {
+            flag: self.filter.flag(),
+            evaluated: self.evaluated.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Filter for CountedFilter<'_> {
+    fn filter(&self, object: &Object) -> bool {
+        self.filter.filter(object)
+    }
+
+    fn filter_at(&self, object: &Object, reference: SystemTime) -> bool {
+        self.filter.filter_at(object, reference)
+    }
+
+    fn describe(&self) -> String {
+        self.filter.describe()
+    }
+
+    fn flag(&self) -> String {
+        self.filter.flag()
+    }
+
+    fn cost(&self) -> FilterCost {
+        self.filter.cost()
+    }
+
+    fn reads_metadata(&self) -> bool {
+        self.filter.reads_metadata()
+    }
 }
 
-pub struct FilterList<'a>(pub Vec<&'a dyn Filter>);
+pub struct FilterList<'a>(pub Vec<CountedFilter<'a>>);
 
 impl<'a> FilterList<'a> {
+    /// Whether every filter matches the object, counted for the
+    /// selectivity of the filters
     pub async fn test_match(&self, object: aws_sdk_s3::types::Object) -> bool {
-        for item in &self.0 {
-            if !item.filter(&object) {
-                return false;
-            }
-        }
+        self.0.iter().all(|item| item.test(&object))
+    }
+
+    /// Whether every filter matches the object, without counting it
+    pub fn matches(&self, object: &Object) -> bool {
+        self.0.iter().all(|item| item.filter(object))
+    }
+
+    /// Whether a filter reads more than the key of the objects
+    pub fn reads_metadata(&self) -> bool {
+        self.0.iter().any(|x| x.reads_metadata())
+    }
+
+    pub fn push(&mut self, filter: impl Filter + 'a) {
+        self.0.push(CountedFilter::new(filter));
+    }
 
-        true
+    /// The objects tested and rejected by every filter, in the testing order
+    pub fn selectivity(&self) -> Vec<FilterSelectivity> {
+        self.0.iter().map(CountedFilter::selectivity).collect()
     }
 
     pub fn new(
@@ -38,30 +152,185 @@ impl<'a> FilterList<'a> {
         regex: &'a [Regex],
         size: &'a [FindSize],
         mtime: &'a [FindTime],
+        exclude_prefixes: &'a Option<ExcludePrefixes>,
+        include_prefixes: &'a Option<IncludePrefixes>,
     ) -> FilterList<'a> {
-        let mut list: Vec<&dyn Filter> = Vec::new();
+        let mut filters = FilterList(Vec::new());
+
+        if let Some(filter) = exclude_prefixes {
+            filters.push(filter);
+        }
+
+        if let Some(filter) = include_prefixes {
+            filters.push(filter);
+        }
 
-        for filter in name {
-            list.push(filter);
+        match (name.len() > GLOB_SET_THRESHOLD).then(|| GlobSet::new(name, true)) {
+            Some(Ok(set)) => filters.push(set),
+            _ => {
+                for filter in name {
+                    filters.push(filter);
+                }
+            }
         }
 
-        for filter in iname {
-            list.push(filter);
+        match (iname.len() > GLOB_SET_THRESHOLD)
+            .then(|| GlobSet::new(iname.iter().map(|x| &x.0), false))
+        {
+            Some(Ok(set)) => filters.push(set),
+            _ => {
+                for filter in iname {
+                    filters.push(filter);
+                }
+            }
         }
 
         for filter in regex {
-            list.push(filter);
+            filters.push(filter);
         }
 
         for filter in size {
-            list.push(filter);
+            filters.push(filter);
         }
 
         for filter in mtime {
-            list.push(filter);
+            filters.push(filter);
+        }
+
+        filters.sort_by_cost();
+        filters
+    }
+
+    /// Adds --name and --iname patterns matched below the search prefix,
+    /// for --anchor prefix
+    pub fn push_anchored(&mut self, name: &'a [Pattern], iname: &'a [InameGlob], prefix: &'a str) {
+        match (name.len() > GLOB_SET_THRESHOLD).then(|| GlobSet::new(name, true)) {
+            Some(Ok(set)) => self.push(set.anchored(prefix)),
+            _ => {
+                for pattern in name {
+                    self.push(AnchoredGlob {
+                        pattern,
+                        case_sensitive: true,
+                        prefix,
+                    });
+                }
+            }
+        }
+
+        match (iname.len() > GLOB_SET_THRESHOLD)
+            .then(|| GlobSet::new(iname.iter().map(|x| &x.0), false))
+        {
+            Some(Ok(set)) => self.push(set.anchored(prefix)),
+            _ => {
+                for glob in iname {
+                    self.push(AnchoredGlob {
+                        pattern: &glob.0,
+                        case_sensitive: false,
+                        prefix,
+                    });
+                }
+            }
+        }
+    }
+
+    /// A hint for a --name or --iname filter which rejected every key it
+    /// tested, the usual sign of a glob written for the key below the
+    /// search prefix while it matches the whole key
+    pub fn name_hint(&self, anchor: NameAnchor) -> Option<String> {
+        if anchor == NameAnchor::Prefix {
+            return None;
         }
+        self.selectivity()
+            .into_iter()
+            .find(|x| {
+                (x.flag.starts_with("--name ") || x.flag.starts_with("--iname "))
+                    && x.evaluated > 0
+                    && x.rejected == x.evaluated
+            })
+            .map(|x| {
+                format!(
+                    "hint: {} matched none of {} listed keys; globs match the whole key, use --anchor prefix to match the key below the search prefix or start the glob with *",
+                    x.flag, x.evaluated
+                )
+            })
+    }
+
+    /// Orders the filters from the cheapest, the filters of the same cost
+    /// keep their order. Every filter has to match, so the order does not
+    /// change the matched keys.
+    pub fn sort_by_cost(&mut self) {
+        self.0.sort_by_key(|filter| filter.cost());
+    }
 
-        FilterList(list)
+    /// The filters of the options in the order they are tested
+    pub fn from_opts(opts: &'a FindOpt) -> Result<FilterList<'a>, FunctionError> {
+        let prefix = opts
+            .path
+            .as_ref()
+            .and_then(|x| x.prefix.as_deref())
+            .unwrap_or_default();
+        let (name, iname): (&[NameGlob], &[InameGlob]) = match opts.anchor {
+            NameAnchor::Anywhere => (&opts.name, &opts.iname),
+            NameAnchor::Prefix => (&[], &[]),
+        };
+        let mut filters = FilterList::new(
+            name,
+            iname,
+            &opts.regex,
+            &opts.size,
+            &[],
+            &opts.exclude_prefixes,
+            &opts.include_prefixes,
+        );
+        if opts.anchor == NameAnchor::Prefix {
+            filters.push_anchored(&opts.name, &opts.iname, prefix);
+        }
+        for literal in &opts.name_literal {
+            filters.push(literal);
+        }
+        if let Some(extensions) = &opts.ext {
+            filters.push(extensions);
+        }
+        if opts.no_ext {
+            filters.push(NoExtension);
+        }
+        // every key is tested against the same instant, the start of the run
+        let reference = opts.reference_time.0;
+        for filter in &opts.mtime {
+            filters.push(AtReference { filter, reference });
+        }
+        if let Some(filter) = &opts.where_expr {
+            filters.push(AtReference { filter, reference });
+        }
+        if let Some(file) = &opts.ignore_file {
+            // the globs are validated by the parser of the file, their regex
+            // set may still be over the size limit
+            let set = IgnoreSet::new(file, prefix)
+                .map_err(|e| FunctionError::IgnorePatterns(file.path.clone(), e.to_string()))?;
+            filters.push(set);
+        }
+        if let Some(prefix) = opts
+            .cmd
+            .as_ref()
+            .zip(opts.path.as_ref())
+            .and_then(|(cmd, path)| cmd.auto_exclude(path))
+        {
+            filters.push(ExcludeDestination(prefix));
+        }
+        if let (Some(shard), false) = (opts.shard, opts.shard_by_range) {
+            filters.push(KeyShard(shard));
+        }
+        // the sample depends on the key only, it selects the same keys
+        // wherever it is tested
+        if let Some(rate) = opts.sample_rate {
+            let sample = KeySample {
+                rate,
+                seed: opts.sample_seed.unwrap_or_default(),
+            };
+            filters.push(sample);
+        }
+        filters.sort_by_cost();
+        Ok(filters)
     }
 }
 
@@ -70,104 +339,632 @@ pub struct Find {
     pub path: S3Path,
     pub limit: Option<usize>,
     pub page_size: i64,
+    pub prefetch: usize,
     pub stats: bool,
     pub summarize: bool,
     pub command: Box<dyn RunCommand>,
+    /// Whether the command sends the matched keys in S3 requests
+    pub key_requests: bool,
+    pub counters: Arc<RunCounters>,
+    pub credentials_provider: Option<SharedCredentialsProvider>,
+    pub budget: RequestBudget,
+    pub notifiers: Vec<Notifier>,
+    pub manifest: Option<ManifestWriter>,
+    pub batch_headers: Option<BatchHeaders>,
+    pub cache: Option<ListingCache>,
+    /// The --shard of the run, with its key range for --shard-by-range
+    pub shard: Option<(Shard, Option<KeyRange>)>,
+    pub list_api: ListApi,
+    /// The --dedup stage, none when every matched key is passed on
+    pub dedup: Option<Dedup>,
+    pub size_buckets: SizeBuckets,
+    /// --one-level, the listing does not recurse below the path
+    pub one_level: bool,
+    /// --reevaluate-mtime-at-action of a command which changes objects
+    pub mtime_recheck: Option<MtimeRecheck>,
 }
 
 impl Find {
     pub async fn new(
         aws_credentials: AWSPair,
-        aws_region: &Region,
+        aws_region: Option<Region>,
         cmd: Option<Cmd>,
         path: S3Path,
         page_size: i64,
         summarize: bool,
         limit: Option<usize>,
-    ) -> Self {
-        let client = get_s3_client(
-            aws_credentials.access,
-            aws_credentials.secret,
-            aws_region.to_owned(),
-        )
-        .await;
-        let command = cmd.unwrap_or_default().downcast();
+    ) -> Result<Self, FunctionError> {
+        let counters = Arc::new(RunCounters::default());
+        let shared_config = config_loader(aws_credentials, aws_region)
+            .await
+            .load()
+            .await;
+        let region = shared_config
+            .region()
+            .cloned()
+            .ok_or(FunctionError::RegionNotFound)?;
+        let client = s3_client(&shared_config, counters.clone());
+        let cmd = cmd.unwrap_or_default();
+        let key_requests = cmd.requests_keys();
+        let command = cmd.downcast();
 
-        Find {
+        Ok(Find {
             client,
-            path,
+            path: S3Path { region, ..path },
             command,
+            key_requests,
             page_size,
+            prefetch: 1,
             summarize,
             limit,
             stats: summarize,
-        }
+            counters,
+            credentials_provider: shared_config.credentials_provider(),
+            budget: RequestBudget::default(),
+            notifiers: Vec::new(),
+            manifest: None,
+            batch_headers: None,
+            cache: None,
+            shard: None,
+            list_api: ListApi::Auto,
+            dedup: None,
+            size_buckets: SizeBuckets::default(),
+            one_level: false,
+            mtime_recheck: None,
+        })
     }
 
-    pub async fn exec(
-        &self,
-        acc: Option<FindStat>,
-        list: Vec<aws_sdk_s3::types::Object>,
-    ) -> Option<FindStat> {
+    pub async fn exec(&self, acc: Option<FindStat>, list: Vec<ListedObject>) -> Option<FindStat> {
+        // the batches after a failed one are left alone
+        if self.counters.command_error.lock().unwrap().is_some() {
+            return acc;
+        }
+        let list = match self.recheck_mtime(list) {
+            Some(list) => list,
+            None => return acc,
+        };
+        let (list, key_bytes) = KeyBytes::split(list);
         let status = acc.map(|stat| stat + &list);
+        let (list, key_bytes) = self.addressable(list, key_bytes);
+        if list.is_empty() {
+            return status;
+        }
+
+        if let Some(manifest) = &self.manifest {
+            if let Err(e) = manifest
+                .append(&self.client, &self.path.bucket, &list, &key_bytes)
+                .await
+            {
+                let error = format!("cannot write the manifest: {}", e);
+                eprintln!("error: {}", error);
+                self.counters.add(&CommandStats {
+                    errors: vec![error],
+                    ..Default::default()
+                });
+            }
+        }
+
+        if let Some(header) = self.batch_headers.as_ref().and_then(|x| x.next(&list)) {
+            eprintln!("{}", header);
+        }
 
-        self.command
-            .execute(&self.client, &self.path, &list)
+        let command_stats = match self
+            .command
+            .execute_listed(&self.client, &self.path, &list, &key_bytes)
             .await
-            .unwrap();
+        {
+            Ok(stats) => stats,
+            Err(e) => {
+                let error = format!("command failed on a batch of {} keys: {}", list.len(), e);
+                eprintln!("error: {}", error);
+                *self.counters.command_error.lock().unwrap() = Some(error.clone());
+                CommandStats {
+                    errors: vec![error],
+                    ..Default::default()
+                }
+            }
+        };
+        self.counters.add(&command_stats);
+        for mutation in &command_stats.mutations {
+            self.notify(mutation).await;
+        }
         status
     }
 
+    /// The keys of the batch which still match the mtime filters at the
+    /// current time, none when --reevaluate-mtime-at-action skipped them all
+    fn recheck_mtime(&self, list: Vec<ListedObject>) -> Option<Vec<ListedObject>> {
+        let recheck = match &self.mtime_recheck {
+            Some(recheck) if !list.is_empty() => recheck,
+            _ => return Some(list),
+        };
+        let now = recheck.now();
+        let (kept, skipped): (Vec<_>, Vec<_>) = list
+            .into_iter()
+            .partition(|object| recheck.matches(object, now));
+        for object in &skipped {
+            eprintln!(
+                "skipped: s3://{}/{} (no longer matches the mtime filters)",
+                self.path.bucket,
+                display_key(object.key.as_deref().unwrap_or_default())
+            );
+        }
+        self.counters
+            .mtime_skipped
+            .fetch_add(skipped.len() as u64, Ordering::Relaxed);
+        (!kept.is_empty()).then_some(kept)
+    }
+
+    /// The keys of the batch the command can send in S3 requests. A key
+    /// which is not valid UTF-8 would be sent with U+FFFD in place of its
+    /// listed bytes, addressing another key; it is reported as an error
+    fn addressable(&self, list: Vec<Object>, key_bytes: KeyBytes) -> (Vec<Object>, KeyBytes) {
+        if !self.key_requests {
+            return (list, key_bytes);
+        }
+
+        let mut errors = Vec::new();
+        let mut kept = Vec::with_capacity(list.len());
+        for (i, object) in list.into_iter().enumerate() {
+            match key_bytes.get(i) {
+                Some(bytes) => {
+                    let error = format!(
+                        "s3://{}/{}: the key is not valid UTF-8, S3 requests cannot address it",
+                        self.path.bucket,
+                        listed_terminal_key(object.key.as_deref().unwrap_or_default(), Some(bytes))
+                    );
+                    eprintln!("error: {}", error);
+                    errors.push(error);
+                }
+                None => kept.push(object),
+            }
+        }
+        if !errors.is_empty() {
+            self.counters.add(&CommandStats {
+                errors,
+                ..Default::default()
+            });
+        }
+        (kept, KeyBytes::default())
+    }
+
+    /// Reports the changed keys to --notify-url and --notify-sns, a failed
+    /// notification is counted as an error of the run
+    async fn notify(&self, mutation: &Mutation) {
+        if self.notifiers.is_empty() {
+            return;
+        }
+
+        let event = MutationEvent::new(mutation, &self.path.bucket);
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(&event).await {
+                let error = format!(
+                    "cannot notify {} of {} keys: {}",
+                    event.action,
+                    event.keys.len(),
+                    e
+                );
+                eprintln!("error: {}", error);
+                self.counters.add(&CommandStats {
+                    errors: vec![error],
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    pub fn metrics(
+        &self,
+        command: &str,
+        stats: Option<&FindStat>,
+        duration: Duration,
+    ) -> RunMetrics {
+        RunMetrics {
+            bucket: self.path.bucket.clone(),
+            prefix: self.path.prefix.clone().unwrap_or_default(),
+            command: command.to_owned(),
+            scanned: self.counters.scanned.load(Ordering::Relaxed),
+            matched: self.counters.matched.load(Ordering::Relaxed),
+            matched_bytes: stats.map(|x| x.total_space).unwrap_or_default(),
+            deleted: self.counters.deleted.load(Ordering::Relaxed),
+            downloaded_bytes: self.counters.downloaded_bytes.load(Ordering::Relaxed),
+            requests: self.counters.requests.load(Ordering::Relaxed),
+            api_requests: RequestApi::ALL
+                .iter()
+                .map(|x| {
+                    (
+                        x.name(),
+                        self.counters.api_requests[*x as usize].load(Ordering::Relaxed),
+                    )
+                })
+                .collect(),
+            errors: self.counters.error_count.load(Ordering::Relaxed),
+            duration,
+        }
+    }
+
+    /// The object of a path written as a key, from a HEAD request used
+    /// instead of a listing; None for a prefix path or a missing key. A
+    /// missing version is an error
+    pub async fn exact_object(&self, version_id: Option<&str>) -> Result<Option<Object>, Error> {
+        let key = match self.path.key() {
+            Some(key) => key.to_owned(),
+            None => return Ok(None),
+        };
+        let head = match self
+            .client
+            .head_object()
+            .bucket(&self.path.bucket)
+            .key(&key)
+            .set_version_id(version_id.map(str::to_owned))
+            .send()
+            .await
+        {
+            Ok(head) => head,
+            Err(e)
+                if version_id.is_none()
+                    && e.raw_response().map(|x| x.status().as_u16()) == Some(404) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(s3_error(e)),
+        };
+
+        Ok(Some(head_listing(key, head)))
+    }
+
+    /// Runs the command on the object of a key path found by
+    /// `exact_object`, which goes through the filters like a listed key
+    pub async fn exec_exact(&self, filters: &FilterList<'_>, object: Object) -> Option<FindStat> {
+        self.counters.scan();
+        let matched = filters.test_match(object.clone()).await;
+        match self.counters.filtered(matched) {
+            true => self.exec(self.new_stats(), vec![object.into()]).await,
+            false => self.new_stats(),
+        }
+    }
+
+    /// A note for a run which matched nothing, telling an empty prefix
+    /// from filters which excluded every key
+    pub fn empty_note(&self) -> Option<String> {
+        let scanned = self.counters.scanned.load(Ordering::Relaxed);
+        let matched = self.counters.matched.load(Ordering::Relaxed);
+
+        match (scanned, matched) {
+            (0, _) => Some("prefix contains no objects".to_owned()),
+            (scanned, 0) => Some(format!("scanned {} objects, 0 matched filters", scanned)),
+            _ => None,
+        }
+    }
+
+    /// A warning for a run which listed keys that are not valid UTF-8,
+    /// the filters saw them with U+FFFD in place of the invalid bytes
+    pub fn invalid_utf8_note(&self) -> Option<String> {
+        match self.counters.invalid_utf8.load(Ordering::Relaxed) {
+            0 => None,
+            keys => Some(format!(
+                "warning: {} keys are not valid UTF-8, filters matched them with U+FFFD in place of the invalid bytes",
+                keys
+            )),
+        }
+    }
+
+    /// The --dedup identifier of a listed key, taken before the filters
+    /// are done with it
+    pub fn dedup_identifier(&self, object: &Object) -> Option<String> {
+        self.dedup.as_ref().map(|x| x.identifier(object))
+    }
+
+    /// Whether a matched key is passed to the command, a key seen before
+    /// with --dedup is counted as a duplicate and skipped
+    pub fn admit(&self, matched: bool, identifier: Option<String>) -> bool {
+        let first = match (matched, &self.dedup, identifier) {
+            (true, Some(dedup), Some(identifier)) => dedup.first_seen(identifier),
+            _ => return matched,
+        };
+        if !first {
+            self.counters.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+        first
+    }
+
+    /// A note for a run which skipped duplicate keys with --dedup
+    pub fn duplicates_note(&self) -> Option<String> {
+        let dedup = self.dedup.as_ref()?;
+        let duplicates = self.counters.duplicates.load(Ordering::Relaxed);
+        let mut note = format!("skipped {} duplicate keys", duplicates);
+        if dedup.approximate() {
+            note.push_str(
+                ", more keys were seen than are kept exactly, a few distinct keys may have been skipped as duplicates",
+            );
+        }
+        Some(note)
+    }
+
+    /// A note for a run which checked the mtime filters again with
+    /// --reevaluate-mtime-at-action
+    pub fn mtime_recheck_note(&self) -> Option<String> {
+        self.mtime_recheck.as_ref()?;
+        Some(format!(
+            "skipped {} keys which no longer matched the mtime filters at action time",
+            self.counters.mtime_skipped.load(Ordering::Relaxed)
+        ))
+    }
+
+    /// Resolves the credentials before the run, so missing credentials are
+    /// reported once instead of failing the first request
+    pub async fn check_credentials(&self) -> Result<(), Error> {
+        let provider = match &self.credentials_provider {
+            Some(provider) => provider,
+            None => return Ok(()),
+        };
+
+        match provider.provide_credentials().await {
+            Ok(_) => Ok(()),
+            Err(CredentialsError::CredentialsNotLoaded(_)) => {
+                Err(FunctionError::CredentialsNotFound.into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub fn to_stream(&self) -> FindStream {
         FindStream {
             client: self.client.clone(),
             path: self.path.clone(),
             token: None,
             page_size: self.page_size,
+            prefetch: self.prefetch,
             initial: true,
+            accounted: None,
+            fetch_owner: self.command.fetch_owner(),
+            budget: self.budget,
+            counters: self.counters.clone(),
+            cache: self.cache.clone(),
+            range: self.shard.as_ref().and_then(|(_, range)| range.clone()),
+            list_api: self.list_api,
+            tuning: PageSizeTuning::new(self.page_size),
+            one_level: self.one_level,
+            list_prefixes: self.one_level && self.command.lists_prefixes(),
+        }
+    }
+
+    /// The first listing pass of --oldest-percent and --largest-percent: the
+    /// cutoffs among the objects matched by the other filters. Without such
+    /// objects the cutoffs match nothing.
+    pub async fn percentile_cutoffs(
+        &self,
+        filters: &FilterList<'_>,
+        oldest: Option<f64>,
+        largest: Option<f64>,
+    ) -> Vec<PercentileCutoff> {
+        if oldest.is_none() && largest.is_none() {
+            return Vec::new();
+        }
+
+        let mut ages = Reservoir::new(PERCENTILE_SAMPLE);
+        // negated, so the largest sizes come first
+        let mut sizes = Reservoir::new(PERCENTILE_SAMPLE);
+        let pages = self.to_stream().stream();
+        futures::pin_mut!(pages);
+
+        while let Some(page) = pages.next().await {
+            for object in page {
+                if !filters.matches(&object) {
+                    continue;
+                }
+                if let Some(last_modified) = &object.last_modified {
+                    ages.add(last_modified.secs());
+                }
+                sizes.add(-object.size.unwrap_or_default());
+            }
+        }
+
+        let mut cutoffs = Vec::new();
+        if let Some(percent) = oldest {
+            let cutoff = ages.quantile(percent / 100.0).unwrap_or(i64::MIN);
+            cutoffs.push(PercentileCutoff::Oldest(percent, cutoff));
+        }
+        if let Some(percent) = largest {
+            let cutoff = sizes.quantile(percent / 100.0).map_or(i64::MAX, |x| -x);
+            cutoffs.push(PercentileCutoff::Largest(percent, cutoff));
+        }
+        cutoffs
+    }
+
+    /// The error for a run stopped by --max-requests or --max-cost-usd
+    pub fn budget_error(&self) -> Option<FunctionError> {
+        if !self.counters.budget_exhausted.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(FunctionError::RequestBudgetExhausted(
+            self.counters.requests.load(Ordering::Relaxed),
+            self.counters.request_cost.load(Ordering::Relaxed) as f64 / NANO_USD,
+        ))
+    }
+
+    /// The error for a run stopped by --max-duration
+    pub fn time_limit_error(&self) -> Option<FunctionError> {
+        if !self.counters.time_limit_reached.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        Some(FunctionError::TimeLimitReached(
+            self.budget
+                .max_duration
+                .map(|x| x.as_secs())
+                .unwrap_or_default(),
+        ))
+    }
+
+    /// The error for a listing stopped by a failed request or a backend
+    /// continuation anomaly
+    pub fn listing_error(&self) -> Option<FunctionError> {
+        self.counters
+            .listing_error
+            .lock()
+            .unwrap()
+            .clone()
+            .map(FunctionError::ListingAnomaly)
+    }
+
+    /// The error for a run stopped by a command which failed a whole batch
+    pub fn command_error(&self) -> Option<FunctionError> {
+        self.counters
+            .command_error
+            .lock()
+            .unwrap()
+            .clone()
+            .map(FunctionError::CommandStopped)
+    }
+
+    /// Why the run stopped before the end of the listing
+    pub fn stop_error(&self) -> Option<FunctionError> {
+        self.listing_error()
+            .or_else(|| self.command_error())
+            .or_else(|| self.budget_error())
+            .or_else(|| self.time_limit_error())
+    }
+
+    /// The --summarize statistic, labeled partial when the run stopped
+    /// before the end of the listing
+    pub fn stat_summary(&self, stats: &FindStat) -> String {
+        let mut summary = match self.stop_error() {
+            Some(error) => format!("Partial summary, {}\n{}", error, stats),
+            None => stats.to_string(),
+        };
+        if let Some(shard) = self.shard_note() {
+            summary.push_str(&format!("{:19} {}\n", "Shard:", shard));
+        }
+        summary
+    }
+
+    /// The shard of the run and the keys it processes
+    pub fn shard_note(&self) -> Option<String> {
+        self.shard.as_ref().map(|(shard, range)| match range {
+            Some(range) => format!("{} ({})", shard, range),
+            None => format!("{} (keys by hash)", shard),
+        })
+    }
+
+    /// The exit code of a run which stopped early or whose command failed
+    pub fn exit_code(&self) -> Option<i32> {
+        if self.listing_error().is_some() || self.command_error().is_some() {
+            Some(COMMAND_FAILED_EXIT_CODE)
+        } else if self.budget_error().is_some() {
+            Some(BUDGET_EXHAUSTED_EXIT_CODE)
+        } else if self.time_limit_error().is_some() {
+            Some(TIME_LIMIT_EXIT_CODE)
+        } else if self.command.failed() {
+            Some(self.command.exit_code())
+        } else {
+            None
         }
     }
 
-    pub async fn from_opts(opts: &FindOpt) -> (Find, FilterList<'_>) {
+    pub async fn from_opts(opts: &FindOpt) -> Result<(Find, FilterList<'_>), FunctionError> {
+        // the time limit runs from the launch, before the client is loaded
+        let launched = Instant::now();
         let FindOpt {
             aws_access_key,
             aws_secret_key,
+            credentials_command,
+            no_sign_request,
             aws_region,
             path,
             cmd,
             page_size,
+            prefetch,
             summarize,
             limit,
-            name,
-            iname,
-            regex,
-            size,
-            mtime,
+            metrics,
+            prom_textfile,
+            report_file,
+            version_id,
+            max_requests,
+            max_cost_usd,
+            max_duration,
+            batch_headers,
+            cache_dir,
+            cache_ttl,
+            shard,
+            shard_by_range,
+            list_api,
+            color,
+            dedup,
+            size_buckets,
+            one_level,
             ..
         } = opts;
 
-        let path = S3Path {
-            region: aws_region.to_owned(),
-            ..path.clone()
-        };
-
-        let find = Find::new(
+        let mut find = Find::new(
             AWSPair {
                 access: aws_access_key.clone(),
                 secret: aws_secret_key.clone(),
+                command: credentials_command.clone(),
+                no_sign_request: *no_sign_request,
             },
-            aws_region,
-            cmd.clone(),
-            path,
+            aws_region.clone(),
+            Some(
+                cmd.clone()
+                    .unwrap_or_default()
+                    .with_version_id(version_id.clone())
+                    .with_color(*color, std::io::stdout().is_terminal()),
+            ),
+            // --stdin sets the path of every bucket it reads
+            path.clone().unwrap_or_default(),
             *page_size,
             *summarize,
             *limit,
         )
-        .await;
+        .await?;
+        find.prefetch = *prefetch;
+        find.list_api = *list_api;
+        find.dedup = Dedup::new(*dedup);
+        find.size_buckets = size_buckets.clone();
+        find.one_level = *one_level;
+        if opts.reevaluate_mtime_at_action && cmd.as_ref().is_some_and(Cmd::mutates) {
+            find.mtime_recheck = Some(MtimeRecheck::new(opts));
+        }
+        find.stats =
+            *summarize || metrics.is_some() || prom_textfile.is_some() || report_file.is_some();
+        find.budget = RequestBudget {
+            max_requests: *max_requests,
+            max_cost: max_cost_usd.map(|x| (x * NANO_USD) as u64),
+            max_duration: max_duration.map(|x| x.0),
+            deadline: max_duration.map(|x| launched + x.0),
+        };
+        // the json output stays machine readable without the headers
+        let json_output = cmd.as_ref().is_some_and(Cmd::json_output);
+        if *batch_headers && !json_output {
+            find.batch_headers = Some(BatchHeaders::default());
+        }
+        find.cache = cache_dir.as_ref().map(|dir| ListingCache {
+            dir: dir.clone(),
+            ttl: cache_ttl.0,
+        });
+        if let Some(shard) = *shard {
+            let range = match shard_by_range {
+                true => Some(
+                    shard_range(&find.client, &find.path, shard)
+                        .await
+                        .map_err(|e| FunctionError::ShardSampleError(e.to_string()))?,
+                ),
+                false => None,
+            };
+            find.shard = Some((shard, range));
+        }
 
-        let filters = FilterList::new(name, iname, regex, size, mtime);
+        Ok((find, FilterList::from_opts(opts)?))
+    }
 
-        (find, filters)
+    /// The statistic of a run gathering one, with the --size-buckets
+    pub fn new_stats(&self) -> Option<FindStat> {
+        default_stats(self.stats).map(|stats| FindStat {
+            sizes: SizeHistogram::new(&self.size_buckets.0),
+            ..stats
+        })
     }
 }
 
@@ -179,48 +976,538 @@ pub fn default_stats(summarize: bool) -> Option<FindStat> {
     }
 }
 
+/// The instant the mtime filters are measured from, logged when a filter
+/// compares the modification time
+pub fn mtime_reference_note(opts: &FindOpt) -> Option<String> {
+    opts.tests_mtime().then(|| {
+        format!(
+            "note: the mtime filters are evaluated against {}",
+            chrono::DateTime::<chrono::Utc>::from(opts.reference_time.0)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        )
+    })
+}
+
+/// The listed object of a key from its HEAD response
+pub(crate) fn head_listing(key: String, head: HeadObjectOutput) -> Object {
+    Object::builder()
+        .key(key)
+        .set_size(head.content_length)
+        .set_last_modified(head.last_modified)
+        .set_e_tag(head.e_tag)
+        .set_storage_class(
+            head.storage_class
+                .map(|x| ObjectStorageClass::from(x.as_str())),
+        )
+        .build()
+}
+
 pub struct FindStream {
     pub client: Client,
     pub path: S3Path,
     pub token: Option<String>,
     pub page_size: i64,
+    /// Pages listed ahead of the consumer, 1 lists on demand
+    pub prefetch: usize,
     pub initial: bool,
+    /// The continuation token of the last page passed to the consumer
+    pub accounted: Option<String>,
+    pub fetch_owner: bool,
+    pub budget: RequestBudget,
+    pub counters: Arc<RunCounters>,
+    pub cache: Option<ListingCache>,
+    /// The key range of --shard-by-range
+    pub range: Option<KeyRange>,
+    /// The listing API, auto turns to v1 when the first page is not
+    /// implemented
+    pub list_api: ListApi,
+    /// The page size given back after timeouts shrank it
+    pub tuning: PageSizeTuning,
+    /// List with a / delimiter, the keys directly under the path only
+    pub one_level: bool,
+    /// Print the common prefixes of a one level listing as PRE lines
+    pub list_prefixes: bool,
 }
 
-impl FindStream {
-    async fn list(mut self) -> Option<(Vec<aws_sdk_s3::types::Object>, Self)> {
-        if !self.initial && self.token.is_none() {
+/// The smallest page size a listing timeout shrinks the page size to
+pub const MIN_PAGE_SIZE: i64 = 50;
+
+/// The pages listed in a row before a shrunk page size is doubled again
+pub const PAGE_SIZE_RECOVERY: u32 = 20;
+
+/// The requested page size of a listing and the pages listed since the
+/// last timeout, the page size is halved on a timeout and doubled back
+/// after a streak of listed pages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSizeTuning {
+    pub requested: i64,
+    pub streak: u32,
+}
+
+impl PageSizeTuning {
+    pub fn new(requested: i64) -> Self {
+        PageSizeTuning {
+            requested,
+            streak: 0,
+        }
+    }
+
+    /// The page size to retry a timed out page with, none at the floor
+    fn shrink(&mut self, page_size: i64) -> Option<i64> {
+        self.streak = 0;
+        match page_size > MIN_PAGE_SIZE {
+            true => Some((page_size / 2).max(MIN_PAGE_SIZE)),
+            false => None,
+        }
+    }
+
+    /// The page size grown back after a listed page, if the streak is long
+    /// enough
+    fn listed(&mut self, page_size: i64) -> Option<i64> {
+        if page_size >= self.requested {
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < PAGE_SIZE_RECOVERY {
             return None;
         }
+        self.streak = 0;
+        Some((page_size * 2).min(self.requested))
+    }
+}
+
+impl FindStream {
+    async fn list(mut self) -> Option<(Vec<ListedObject>, Self)> {
+        loop {
+            if !self.initial && self.token.is_none() {
+                return None;
+            }
+
+            if self.budget.exhausted(&self.counters) {
+                self.counters
+                    .budget_exhausted
+                    .store(true, Ordering::Relaxed);
+                return None;
+            }
+            if self.budget.expired(&self.counters) {
+                return None;
+            }
+            if self.counters.command_error.lock().unwrap().is_some() {
+                return None;
+            }
+
+            let page = self.list_page().await?;
+            let (mut token, mut objects) = (page.token, page.contents);
+            if let (Some(range), Some(page)) = (&self.range, objects.as_mut()) {
+                // the keys are listed in order, the range ends at the first
+                // key after it
+                if page
+                    .last()
+                    .and_then(|x| x.key.as_deref())
+                    .is_some_and(|key| range.ends_before(key))
+                {
+                    token = None;
+                }
+                page.retain(|x| range.contains(x.key.as_deref().unwrap_or_default()));
+            }
+            let accounted = self.account(token.as_deref());
+            self.initial = false;
+            self.token = token;
+            if accounted && self.list_prefixes {
+                for prefix in &page.prefixes {
+                    println!("{}", prefix_line(&self.path.bucket, prefix));
+                }
+            }
+            if accounted {
+                // some backends end a listing of an exact multiple of the
+                // page size with an empty page, or return an empty truncated
+                // page in the middle; the listing goes on from its token
+                match objects {
+                    Some(objects) if !objects.is_empty() => return Some((objects, self)),
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    /// Requests the page after the current token, None stops the listing
+    /// with the reason recorded in the run counters
+    async fn list_page(&mut self) -> Option<ListedPage> {
+        let mut attempts = 0;
+        loop {
+            let page = match self.list_api {
+                ListApi::V1 => self.list_page_v1().await,
+                ListApi::V2 | ListApi::Auto => self.list_page_v2().await,
+            };
+            let page = match page {
+                Ok(page) => page,
+                Err(e) if e.timeout => match self.tuning.shrink(self.page_size) {
+                    Some(page_size) => {
+                        eprintln!(
+                            "note: listing timed out ({}), retrying with a page size of {}",
+                            e.context, page_size
+                        );
+                        self.page_size = page_size;
+                        continue;
+                    }
+                    None => {
+                        self.stop(&e.context);
+                        return None;
+                    }
+                },
+                Err(e) if self.list_api == ListApi::Auto && self.initial && e.unsupported() => {
+                    eprintln!(
+                        "note: the endpoint does not implement ListObjectsV2 ({}), listing with ListObjects",
+                        e.context
+                    );
+                    self.list_api = ListApi::V1;
+                    continue;
+                }
+                Err(e) => {
+                    self.stop(&e.context);
+                    return None;
+                }
+            };
+
+            if let Some(page_size) = self.tuning.listed(self.page_size) {
+                eprintln!(
+                    "note: {} pages listed without a timeout, page size raised to {}",
+                    PAGE_SIZE_RECOVERY, page_size
+                );
+                self.page_size = page_size;
+            }
+
+            match page_anomaly(
+                page.is_truncated,
+                page.token.as_deref(),
+                self.token.as_deref(),
+            ) {
+                None => return Some(page),
+                Some(_) if attempts < PAGE_ANOMALY_RETRIES => attempts += 1,
+                Some(anomaly) => {
+                    self.stop(&format!("{} after {} attempts", anomaly, attempts + 1));
+                    return None;
+                }
+            }
+        }
+    }
 
-        let (token, objects) = self
+    async fn list_page_v2(&self) -> Result<ListedPage, ListFailure> {
+        // keys with control characters cannot be listed in plain XML
+        let page = self
             .client
             .list_objects_v2()
             .bucket(self.path.bucket.clone())
             .prefix(self.path.prefix.clone().unwrap_or_else(|| "".to_owned()))
             .max_keys(self.page_size as i32)
-            .set_continuation_token(self.token)
+            .set_delimiter(self.delimiter())
+            .fetch_owner(self.fetch_owner)
+            .encoding_type(EncodingType::Url)
+            .set_start_after(self.range.as_ref().and_then(KeyRange::start_after))
+            .set_continuation_token(self.token.clone())
             .send()
             .await
-            .map(|x| (x.next_continuation_token, x.contents))
-            .unwrap();
-
-        self.initial = false;
-        self.token = token;
-        objects.map(|x| (x, self))
-    }
+            .map_err(ListFailure::new)?;
 
-    pub fn stream(self) -> impl Stream<Item = Vec<aws_sdk_s3::types::Object>> {
-        futures::stream::unfold(self, |s| async { s.list().await })
+        let url_encoded = page.encoding_type == Some(EncodingType::Url);
+        let contents = match url_encoded {
+            true => page.contents.map(|x| decode_keys(x, &self.counters)),
+            false => page
+                .contents
+                .map(|x| x.into_iter().map(ListedObject::from).collect()),
+        };
+        Ok(ListedPage {
+            is_truncated: page.is_truncated,
+            token: page.next_continuation_token,
+            contents,
+            prefixes: common_prefixes(page.common_prefixes, url_encoded),
+        })
     }
-}
 
-impl PartialEq for FindStream {
-    fn eq(&self, other: &Self) -> bool {
+    /// ListObjects pages by the marker, the last key of a page without a
+    /// delimiter; the token of the listing is that marker
+    async fn list_page_v1(&self) -> Result<ListedPage, ListFailure> {
+        let page = self
+            .client
+            .list_objects()
+            .bucket(self.path.bucket.clone())
+            .prefix(self.path.prefix.clone().unwrap_or_else(|| "".to_owned()))
+            .max_keys(self.page_size as i32)
+            .set_delimiter(self.delimiter())
+            .encoding_type(EncodingType::Url)
+            .set_marker(
+                self.token
+                    .clone()
+                    .or_else(|| self.range.as_ref().and_then(KeyRange::start_after)),
+            )
+            .send()
+            .await
+            .map_err(ListFailure::new)?;
+
+        let url_encoded = page.encoding_type == Some(EncodingType::Url);
+        let contents = match url_encoded {
+            true => page.contents.map(|x| decode_keys(x, &self.counters)),
+            false => page
+                .contents
+                .map(|x| x.into_iter().map(ListedObject::from).collect()),
+        };
+        let marker = match url_encoded {
+            true => page.next_marker.as_deref().map(decode_url_key),
+            false => page.next_marker,
+        };
+        let token = match page.is_truncated {
+            Some(true) => marker.or_else(|| {
+                contents
+                    .as_ref()
+                    .and_then(|x| x.last())
+                    .and_then(|x| x.key.clone())
+            }),
+            _ => None,
+        };
+        Ok(ListedPage {
+            is_truncated: page.is_truncated,
+            token,
+            contents,
+            prefixes: common_prefixes(page.common_prefixes, url_encoded),
+        })
+    }
+
+    /// The delimiter of --one-level, none for the recursive listing
+    fn delimiter(&self) -> Option<String> {
+        self.one_level.then(|| "/".to_owned())
+    }
+
+    fn stop(&self, reason: &str) {
+        let error = format!(
+            "s3://{}/{}: {}",
+            self.path.bucket,
+            self.path.prefix.as_deref().unwrap_or_default(),
+            reason
+        );
+        *self.counters.listing_error.lock().unwrap() = Some(error);
+    }
+
+    /// Whether the page ending at the continuation token is listed for the
+    /// first time: the stats and the command see a page listed again, like
+    /// a retried page, only once
+    fn account(&mut self, token: Option<&str>) -> bool {
+        match token {
+            Some(token) if self.accounted.as_deref() == Some(token) => false,
+            Some(token) => {
+                self.accounted = Some(token.to_owned());
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// The listed pages, served from a fresh entry of --cache-dir when there
+    /// is one, otherwise listed and cached once the listing reached its end
+    pub fn stream(mut self) -> impl Stream<Item = Vec<ListedObject>> {
+        let cache = match self.cache.take() {
+            Some(cache) => cache,
+            None => return self.listed().left_stream(),
+        };
+
+        let key = CacheKey {
+            bucket: self.path.bucket.clone(),
+            prefix: self.path.prefix.clone().unwrap_or_default(),
+            page_size: self.page_size,
+            fetch_owner: self.fetch_owner,
+        };
+        if let Some(listing) = cache.read(&key) {
+            eprintln!(
+                "note: listing of s3://{}/{} served from the cache, {}s old",
+                key.bucket,
+                key.prefix,
+                listing.age.as_secs()
+            );
+            return futures::stream::iter(listing.pages)
+                .left_stream()
+                .right_stream();
+        }
+
+        let writer = match cache.writer(&key) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("warning: cannot write the listing cache: {}", e);
+                None
+            }
+        };
+        let counters = self.counters.clone();
+        let pages = Box::pin(self.listed());
+        futures::stream::unfold((pages, writer), move |(mut pages, mut writer)| {
+            let counters = counters.clone();
+            async move {
+                match pages.next().await {
+                    Some(page) => {
+                        if let Some(Err(e)) = writer.as_mut().map(|x| x.append(&page)) {
+                            eprintln!("warning: cannot write the listing cache: {}", e);
+                            writer = None;
+                        }
+                        Some((page, (pages, writer)))
+                    }
+                    None => {
+                        // a stopped listing is incomplete and stays uncached
+                        let complete = counters.listing_error.lock().unwrap().is_none()
+                            && counters.command_error.lock().unwrap().is_none()
+                            && !counters.budget_exhausted.load(Ordering::Relaxed);
+                        if let (true, Some(writer)) = (complete, writer) {
+                            if let Err(e) = writer.commit() {
+                                eprintln!("warning: cannot write the listing cache: {}", e);
+                            }
+                        }
+                        None
+                    }
+                }
+            }
+        })
+        .right_stream()
+        .right_stream()
+    }
+
+    fn listed(self) -> impl Stream<Item = Vec<ListedObject>> {
+        if self.prefetch <= 1 {
+            return futures::stream::unfold(self, |s| async { s.list().await }).left_stream();
+        }
+
+        // a listing task runs ahead of the consumer, the channel holds the
+        // pages already listed while the next one is requested
+        let (sender, receiver) = tokio::sync::mpsc::channel(self.prefetch - 1);
+        let listing = tokio::spawn(async move {
+            let pages = futures::stream::unfold(self, |s| async { s.list().await });
+            futures::pin_mut!(pages);
+            while let Some(page) = pages.next().await {
+                if sender.send(page).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        futures::stream::unfold((receiver, listing), |(mut receiver, listing)| async move {
+            if let Some(page) = receiver.recv().await {
+                return Some((page, (receiver, listing)));
+            }
+            // the listing task is done, a panic of the task surfaces here
+            if let Err(error) = listing.await {
+                if error.is_panic() {
+                    std::panic::resume_unwind(error.into_panic());
+                }
+            }
+            None
+        })
+        .right_stream()
+    }
+}
+
+/// The number of times a page with a continuation anomaly is requested again
+pub const PAGE_ANOMALY_RETRIES: u32 = 2;
+
+/// Some S3-compatible backends return a truncated page without a
+/// continuation token, or the token of the previous page; following such a
+/// page would stop the listing early or request the same page forever
+fn page_anomaly(
+    is_truncated: Option<bool>,
+    token: Option<&str>,
+    previous: Option<&str>,
+) -> Option<&'static str> {
+    match (is_truncated, token) {
+        (Some(true), None) => {
+            Some("the backend returned a truncated page without a continuation token")
+        }
+        (_, Some(token)) if Some(token) == previous => {
+            Some("the backend returned the same continuation token again")
+        }
+        _ => None,
+    }
+}
+
+/// A listed page of either listing API, with decoded keys
+struct ListedPage {
+    is_truncated: Option<bool>,
+    token: Option<String>,
+    contents: Option<Vec<ListedObject>>,
+    /// The common prefixes of a delimited listing
+    prefixes: Vec<String>,
+}
+
+/// The common prefixes of a delimited page, decoded like the keys
+fn common_prefixes(prefixes: Option<Vec<CommonPrefix>>, url_encoded: bool) -> Vec<String> {
+    prefixes
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|x| x.prefix)
+        .map(|x| match url_encoded {
+            true => decode_url_key(&x),
+            false => x,
+        })
+        .collect()
+}
+
+/// The line ls and print show for a common prefix of --one-level
+pub fn prefix_line(bucket: &str, prefix: &str) -> String {
+    format!("PRE s3://{}/{}", bucket, terminal_key(prefix))
+}
+
+/// A failed listing request
+struct ListFailure {
+    code: Option<String>,
+    context: String,
+    /// The request timed out, before or after it was sent
+    timeout: bool,
+}
+
+impl ListFailure {
+    fn new<E: ProvideErrorMetadata>(e: SdkError<E>) -> Self {
+        let timeout = match &e {
+            SdkError::TimeoutError(_) => true,
+            SdkError::DispatchFailure(failure) => failure.is_timeout(),
+            _ => false,
+        };
+        ListFailure {
+            code: e.code().map(str::to_owned),
+            context: error_context(&e),
+            timeout,
+        }
+    }
+
+    /// Whether the endpoint lacks ListObjectsV2, some reject its list-type
+    /// parameter as an invalid argument
+    fn unsupported(&self) -> bool {
+        matches!(
+            self.code.as_deref(),
+            Some("NotImplemented") | Some("InvalidArgument")
+        )
+    }
+}
+
+/// Decodes the keys of a page, counting the keys which are not valid UTF-8
+fn decode_keys(objects: Vec<Object>, counters: &RunCounters) -> Vec<ListedObject> {
+    objects
+        .into_iter()
+        .map(|mut object| {
+            let (key, key_bytes) = match object.key.as_deref().map(decode_listed_key) {
+                Some((key, key_bytes)) => (Some(key), key_bytes),
+                None => (None, None),
+            };
+            if key_bytes.is_some() {
+                counters.invalid_utf8.fetch_add(1, Ordering::Relaxed);
+            }
+            object.key = key;
+            ListedObject { object, key_bytes }
+        })
+        .collect()
+}
+
+impl PartialEq for FindStream {
+    fn eq(&self, other: &Self) -> bool {
         self.path == other.path
             && self.token == other.token
             && self.page_size == other.page_size
+            && self.prefetch == other.prefetch
             && self.initial == other.initial
+            && self.fetch_owner == other.fetch_owner
+            && self.budget == other.budget
     }
 }
 
@@ -234,45 +1521,379 @@ FindStream {{
     path: {:?},
     token: {:?},
     page_size: {},
+    prefetch: {},
     initial: {},
+    fetch_owner: {},
+    budget: {:?},
 }}",
-            self.path, self.token, self.page_size, self.initial
+            self.path,
+            self.token,
+            self.page_size,
+            self.prefetch,
+            self.initial,
+            self.fetch_owner,
+            self.budget
         )
     }
 }
 
-#[inline]
-async fn get_s3_client(
-    aws_access_key: Option<String>,
-    aws_secret_key: Option<String>,
-    region: Region,
-) -> Client {
-    let region_provider =
-        aws_config::meta::region::RegionProviderChain::first_try(region).or_default_provider();
+/// The objects a filter tested and rejected during the run
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterSelectivity {
+    pub flag: String,
+    pub evaluated: u64,
+    pub rejected: u64,
+}
 
-    let shared_config = match (aws_access_key, aws_secret_key) {
-        (Some(aws_access_key), Some(aws_secret_key)) => {
-            let credentials_provider =
-                Credentials::new(aws_access_key, aws_secret_key, None, None, "static");
-            aws_config::ConfigLoader::default()
-                .behavior_version(BehaviorVersion::v2024_03_28())
-                .region(region_provider)
-                .credentials_provider(credentials_provider)
-                .load()
-                .await
+impl FilterSelectivity {
+    /// The percentage of the tested objects the filter passed
+    pub fn pass_rate(&self) -> Option<f64> {
+        (self.evaluated > 0)
+            .then(|| (self.evaluated - self.rejected) as f64 * 100.0 / self.evaluated as f64)
+    }
+
+    pub fn pass_rate_text(&self) -> String {
+        self.pass_rate()
+            .map(|x| format!("{:.1}%", x))
+            .unwrap_or_else(|| "-".to_owned())
+    }
+}
+
+/// The --summarize table of the filter selectivity, with a note for the
+/// filters which rejected nothing
+pub struct SelectivityTable<'a>(pub &'a [FilterSelectivity]);
+
+impl fmt::Display for SelectivityTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|x| x.flag.chars().count())
+            .chain(Some("Filter".len()))
+            .max()
+            .unwrap_or_default();
+
+        write!(
+            f,
+            "{:<width$}  {:>10}  {:>10}  {:>12}",
+            "Filter",
+            "Evaluated",
+            "Rejected",
+            "Pass-through",
+            width = width
+        )?;
+        for filter in self.0 {
+            write!(
+                f,
+                "\n{:<width$}  {:>10}  {:>10}  {:>12}",
+                filter.flag,
+                filter.evaluated,
+                filter.rejected,
+                filter.pass_rate_text(),
+                width = width
+            )?;
         }
-        _ => {
-            let credentials_provider = CredentialsProviderChain::default_provider().await;
-            aws_config::ConfigLoader::default()
-                .behavior_version(BehaviorVersion::v2024_03_28())
-                .region(region_provider)
-                .credentials_provider(credentials_provider)
-                .load()
-                .await
+        for filter in self.0 {
+            if filter.evaluated > 0 && filter.rejected == 0 {
+                write!(
+                    f,
+                    "\nnote: {} rejected none of {} objects",
+                    filter.flag, filter.evaluated
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of error messages kept for the run report
+pub const MAX_KEPT_ERRORS: usize = 50;
+
+/// Counters shared between the driver, the commands and the S3 client
+#[derive(Debug, Default)]
+pub struct RunCounters {
+    pub scanned: AtomicU64,
+    pub matched: AtomicU64,
+    pub processed: AtomicU64,
+    pub deleted: AtomicU64,
+    pub reclaimed_bytes: AtomicU64,
+    pub downloaded_bytes: AtomicU64,
+    pub skipped: AtomicU64,
+    pub size_corrected: AtomicU64,
+    pub requests: AtomicU64,
+    /// The requests by `RequestApi`
+    pub api_requests: [AtomicU64; 4],
+    /// Estimated request cost in nano-USD
+    pub request_cost: AtomicU64,
+    pub budget_exhausted: AtomicBool,
+    /// The run stopped listing at its --max-duration
+    pub time_limit_reached: AtomicBool,
+    /// Why the listing stopped before its end
+    pub listing_error: Mutex<Option<String>>,
+    /// Why the command failed a whole batch, which stops the run
+    pub command_error: Mutex<Option<String>>,
+    pub error_count: AtomicU64,
+    pub errors: Mutex<Vec<String>>,
+    /// Listed keys which are not valid UTF-8
+    pub invalid_utf8: AtomicU64,
+    /// Matched keys skipped by --dedup
+    pub duplicates: AtomicU64,
+    /// Matched keys skipped by --reevaluate-mtime-at-action
+    pub mtime_skipped: AtomicU64,
+}
+
+impl RunCounters {
+    pub fn scan(&self) {
+        self.scanned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a key which passed the filters
+    pub fn filtered(&self, matched: bool) -> bool {
+        if matched {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+        }
+        matched
+    }
+
+    pub fn request(&self, method: &str, uri: &str) {
+        let api = RequestApi::of(method, uri);
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.api_requests[api as usize].fetch_add(1, Ordering::Relaxed);
+        self.request_cost
+            .fetch_add(request_price(api), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, stats: &CommandStats) {
+        self.processed
+            .fetch_add(stats.processed as u64, Ordering::Relaxed);
+        self.deleted
+            .fetch_add(stats.deleted as u64, Ordering::Relaxed);
+        self.reclaimed_bytes
+            .fetch_add(stats.reclaimed_bytes, Ordering::Relaxed);
+        self.downloaded_bytes
+            .fetch_add(stats.downloaded_bytes, Ordering::Relaxed);
+        self.skipped
+            .fetch_add(stats.skipped as u64, Ordering::Relaxed);
+        self.size_corrected
+            .fetch_add(stats.size_corrected as u64, Ordering::Relaxed);
+
+        if !stats.errors.is_empty() {
+            self.error_count
+                .fetch_add(stats.errors.len() as u64, Ordering::Relaxed);
+            let mut errors = self.errors.lock().unwrap();
+            let free = MAX_KEPT_ERRORS.saturating_sub(errors.len());
+            errors.extend(stats.errors.iter().take(free).cloned());
+        }
+    }
+}
+
+/// Exit code of a run stopped by --max-requests or --max-cost-usd
+pub const BUDGET_EXHAUSTED_EXIT_CODE: i32 = 3;
+
+/// Exit code of a run stopped by --max-duration
+pub const TIME_LIMIT_EXIT_CODE: i32 = 4;
+
+/// Exit code of a run whose command found problems, like verify mismatches
+pub const COMMAND_FAILED_EXIT_CODE: i32 = 1;
+
+/// Exit code of a plan-copy run which found target key collisions
+pub const PLAN_COLLISION_EXIT_CODE: i32 = 2;
+
+const NANO_USD: f64 = 1_000_000_000.0;
+
+// S3 Standard request prices in nano-USD per request
+const CLASS_A_REQUEST_PRICE: u64 = 5_000;
+const CLASS_B_REQUEST_PRICE: u64 = 400;
+
+/// The kind of a S3 request, the api label of the Prometheus metrics
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestApi {
+    List,
+    Get,
+    Put,
+    Delete,
+}
+
+impl RequestApi {
+    pub const ALL: [RequestApi; 4] = [
+        RequestApi::List,
+        RequestApi::Get,
+        RequestApi::Put,
+        RequestApi::Delete,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RequestApi::List => "list",
+            RequestApi::Get => "get",
+            RequestApi::Put => "put",
+            RequestApi::Delete => "delete",
+        }
+    }
+
+    /// Listings are the GET requests of ListObjects, ListObjectsV2, the
+    /// versions and the multipart uploads; PUT and POST other than the
+    /// multi-object delete are put, everything else is get
+    pub fn of(method: &str, uri: &str) -> RequestApi {
+        let query = uri.split_once('?').map(|(_, x)| x).unwrap_or_default();
+        let listing = query.split('&').any(|x| {
+            x.starts_with("list-type=")
+                || x.starts_with("max-keys=")
+                || x == "versions"
+                || x == "uploads"
+        });
+
+        match method {
+            "DELETE" => RequestApi::Delete,
+            "POST" if query.split('&').any(|x| x == "delete") => RequestApi::Delete,
+            "PUT" | "POST" => RequestApi::Put,
+            "GET" if listing => RequestApi::List,
+            _ => RequestApi::Get,
+        }
+    }
+}
+
+/// Estimated price of a request: PUT, COPY, POST and LIST are class A,
+/// DELETE is free and everything else is class B
+fn request_price(api: RequestApi) -> u64 {
+    match api {
+        RequestApi::Delete => 0,
+        RequestApi::Put | RequestApi::List => CLASS_A_REQUEST_PRICE,
+        RequestApi::Get => CLASS_B_REQUEST_PRICE,
+    }
+}
+
+/// Limits on the requests issued by a run and on its duration, checked
+/// before every listed page
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RequestBudget {
+    pub max_requests: Option<u64>,
+    /// Estimated request cost in nano-USD
+    pub max_cost: Option<u64>,
+    /// The --max-duration of the run, from its launch to `deadline`
+    pub max_duration: Option<Duration>,
+    pub deadline: Option<Instant>,
+}
+
+impl RequestBudget {
+    pub fn exhausted(&self, counters: &RunCounters) -> bool {
+        self.max_requests
+            .is_some_and(|x| counters.requests.load(Ordering::Relaxed) >= x)
+            || self
+                .max_cost
+                .is_some_and(|x| counters.request_cost.load(Ordering::Relaxed) >= x)
+    }
+
+    /// Whether the run is over its --max-duration, recorded in the counters
+    pub fn expired(&self, counters: &RunCounters) -> bool {
+        let expired = self.deadline.is_some_and(|x| Instant::now() >= x);
+        if expired {
+            counters.time_limit_reached.store(true, Ordering::Relaxed);
         }
-    };
+        expired
+    }
+}
+
+/// The longest key printed in a --batch-headers line
+pub const BATCH_HEADER_KEY_CHARS: usize = 60;
+
+/// Numbers the batches handed to the command for --batch-headers
+#[derive(Debug, Default)]
+pub struct BatchHeaders {
+    batches: AtomicU64,
+    keys: AtomicU64,
+}
+
+impl BatchHeaders {
+    /// The header line of the next batch, None for an empty batch
+    pub fn next(&self, list: &[Object]) -> Option<String> {
+        let first = list.first()?;
+        let last = list.last()?;
+        let batch = self.batches.fetch_add(1, Ordering::Relaxed) + 1;
+        let start = self.keys.fetch_add(list.len() as u64, Ordering::Relaxed);
+
+        Some(format!(
+            "--- batch {} (keys {}-{}: {} .. {}) ---",
+            batch,
+            start + 1,
+            start + list.len() as u64,
+            header_key(first),
+            header_key(last)
+        ))
+    }
+}
+
+fn header_key(object: &Object) -> String {
+    let key = object.key.as_deref().unwrap_or_default();
+    if key.chars().count() <= BATCH_HEADER_KEY_CHARS {
+        return key.to_owned();
+    }
+    let mut key: String = key.chars().take(BATCH_HEADER_KEY_CHARS - 3).collect();
+    key.push_str("...");
+    key
+}
+
+/// Counts every request attempt sent by the S3 client
+#[derive(Debug)]
+struct RequestCounter(Arc<RunCounters>);
+
+impl Intercept for RequestCounter {
+    fn name(&self) -> &'static str {
+        "RequestCounter"
+    }
+
+    fn read_before_transmit(
+        &self,
+        context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), aws_sdk_s3::error::BoxError> {
+        let request = context.request();
+        self.0.request(request.method(), request.uri());
+        Ok(())
+    }
+}
+
+/// Config loader with the credentials chosen by the options
+async fn config_loader(aws_credentials: AWSPair, region: Option<Region>) -> ConfigLoader {
+    let region_provider =
+        aws_config::meta::region::RegionProviderChain::first_try(region).or_default_provider();
+
+    let loader = aws_config::ConfigLoader::default()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(region_provider);
+
+    match aws_credentials {
+        AWSPair {
+            no_sign_request: true,
+            ..
+        } => loader.no_credentials(),
+        AWSPair {
+            command: Some(command),
+            ..
+        } => loader.credentials_provider(CommandCredentialsProvider::new(&command)),
+        AWSPair {
+            access: Some(aws_access_key),
+            secret: Some(aws_secret_key),
+            ..
+        } => loader.credentials_provider(Credentials::new(
+            aws_access_key,
+            aws_secret_key,
+            None,
+            None,
+            "static",
+        )),
+        _ => loader.credentials_provider(CredentialsProviderChain::default_provider().await),
+    }
+}
+
+#[inline]
+fn s3_client(shared_config: &SdkConfig, counters: Arc<RunCounters>) -> Client {
+    let config = aws_sdk_s3::config::Builder::from(shared_config)
+        .interceptor(RequestCounter(counters))
+        .build();
 
-    Client::new(&shared_config)
+    Client::from_conf(config)
 }
 
 impl fmt::Display for FindStat {
@@ -281,91 +1902,2580 @@ impl fmt::Display for FindStat {
         writeln!(f)?;
         writeln!(f, "Summary")?;
         writeln!(f, "{:19} {}", "Total files:", &self.total_files)?;
-        writeln!(
-            f,
-            "Total space:        {}",
-            file_size(self.total_space as u64),
-        )?;
+        writeln!(f, "{:19} {}", "Total space:", file_size(self.total_space))?;
         writeln!(f, "{:19} {}", "Largest file:", &self.max_key)?;
         writeln!(
             f,
             "{:19} {}",
             "Largest file size:",
-            file_size(self.max_size.unwrap_or_default() as u64),
+            file_size(self.max_size.unwrap_or_default()),
         )?;
         writeln!(f, "{:19} {}", "Smallest file:", &self.min_key)?;
-        writeln!(f, "{:19} {}", "Smallest file size:", self.min_key,)?;
+        writeln!(
+            f,
+            "{:19} {}",
+            "Smallest file size:",
+            file_size(self.min_size.unwrap_or_default()),
+        )?;
         writeln!(
             f,
             "{:19} {}",
             "Average file size:",
-            file_size(self.average_size as u64),
+            file_size(self.average_size()),
         )?;
-        Ok(())
+        if self.negative_sizes > 0 {
+            writeln!(f, "{:19} {}", "Negative sizes:", self.negative_sizes)?;
+        }
+        write!(f, "{}", self.sizes)
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct FindStat {
-    pub total_files: usize,
-    pub total_space: i64,
-    pub max_size: Option<i64>,
-    pub min_size: Option<i64>,
-    pub max_key: String,
-    pub min_key: String,
-    pub average_size: i64,
+/// The objects and bytes of a bucket of the size histogram
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SizeBucket {
+    pub objects: u64,
+    pub bytes: u64,
 }
 
-impl Add<&[aws_sdk_s3::types::Object]> for FindStat {
-    type Output = FindStat;
-
-    #[allow(clippy::suspicious_arithmetic_impl)]
-    fn add(mut self: FindStat, list: &[aws_sdk_s3::types::Object]) -> Self {
-        for x in list {
-            self.total_files += 1;
-            let size = x.size;
-            self.total_space += size.unwrap_or_default();
+/// Object sizes counted by the --size-buckets boundaries. A bucket holds
+/// the sizes from its lower boundary up to the next one, so a size equal to
+/// a boundary goes to the bucket above it: 1M is in 1M-16M
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeHistogram {
+    pub bounds: Vec<u64>,
+    /// One bucket more than the boundaries, below the first and above the last
+    pub buckets: Vec<SizeBucket>,
+    /// Objects listed without a size or with a negative one
+    pub unknown: u64,
+}
 
-            match self.max_size {
-                None => {
-                    self.max_size = size;
-                    self.max_key = x.key.clone().unwrap_or_default();
-                }
-                Some(max_size) if max_size <= size.unwrap_or_default() => {
-                    self.max_size = size;
-                    self.max_key = x.key.clone().unwrap_or_default();
-                }
-                _ => {}
-            }
+impl SizeHistogram {
+    pub fn new(bounds: &[u64]) -> Self {
+        SizeHistogram {
+            bounds: bounds.to_vec(),
+            buckets: vec![SizeBucket::default(); bounds.len() + 1],
+            unknown: 0,
+        }
+    }
 
-            match self.min_size {
-                None => {
-                    self.min_size = size;
-                    self.min_key = x.key.clone().unwrap_or_default();
-                }
-                Some(min_size) if min_size > size.unwrap_or_default() => {
-                    self.min_size = size;
-                    self.min_key = x.key.clone().unwrap_or_default();
-                }
-                _ => {}
+    pub fn add(&mut self, size: Option<i64>) {
+        match size.and_then(|x| u64::try_from(x).ok()) {
+            Some(size) => {
+                let bucket = &mut self.buckets[self.bounds.partition_point(|x| *x <= size)];
+                bucket.objects += 1;
+                bucket.bytes = bucket.bytes.saturating_add(size);
             }
+            None => self.unknown += 1,
+        }
+    }
 
-            self.average_size = self.total_space / (self.total_files as i64);
+    /// The name of a bucket by its boundaries, like 1M-16M
+    pub fn label(&self, index: usize) -> String {
+        let size = |x: u64| FindSize::Equal(x as i64).to_string();
+        match (
+            index.checked_sub(1).map(|x| self.bounds[x]),
+            self.bounds.get(index),
+        ) {
+            (None, Some(upper)) => format!("<{}", size(*upper)),
+            (Some(lower), Some(upper)) => format!("{}-{}", size(lower), size(*upper)),
+            (Some(lower), None) => format!(">={}", size(lower)),
+            (None, None) => "all".to_owned(),
         }
-        self
     }
 }
 
-impl Default for FindStat {
+impl Default for SizeHistogram {
     fn default() -> Self {
-        FindStat {
-            total_files: 0,
-            total_space: 0,
-            max_size: None,
-            min_size: None,
-            max_key: "".to_owned(),
-            min_key: "".to_owned(),
-            average_size: 0,
+        SizeHistogram::new(&SizeBuckets::default().0)
+    }
+}
+
+impl fmt::Display for SizeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file_size = make_format(BINARY);
+        let share = |part: u64, total: u64| match total {
+            0 => 0.0,
+            total => part as f64 * 100.0 / total as f64,
+        };
+        let objects = self.buckets.iter().map(|x| x.objects).sum::<u64>() + self.unknown;
+        let bytes = self
+            .buckets
+            .iter()
+            .fold(0u64, |total, x| total.saturating_add(x.bytes));
+
+        writeln!(f, "Size buckets:")?;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:11} {:>10} {:>6.2}% {:>12} {:>6.2}%",
+                self.label(index),
+                bucket.objects,
+                share(bucket.objects, objects),
+                file_size(bucket.bytes),
+                share(bucket.bytes, bytes)
+            )?;
+        }
+        if self.unknown > 0 {
+            writeln!(
+                f,
+                "  {:11} {:>10} {:>6.2}%",
+                "unknown",
+                self.unknown,
+                share(self.unknown, objects)
+            )?;
         }
+        Ok(())
+    }
+}
+
+/// Totals of a sampled run scaled up to the whole prefix
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleEstimate {
+    pub rate: f64,
+    pub sampled_files: usize,
+    pub sampled_space: u64,
+}
+
+impl SampleEstimate {
+    pub fn new(stats: &FindStat, rate: f64) -> Self {
+        SampleEstimate {
+            rate,
+            sampled_files: stats.total_files,
+            sampled_space: stats.total_space,
+        }
+    }
+
+    pub fn estimated_files(&self) -> u64 {
+        (self.sampled_files as f64 / self.rate).round() as u64
+    }
+
+    pub fn estimated_space(&self) -> u64 {
+        (self.sampled_space as f64 / self.rate).round() as u64
+    }
+}
+
+impl fmt::Display for SampleEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file_size = make_format(BINARY);
+        writeln!(f)?;
+        writeln!(f, "Sample")?;
+        writeln!(f, "{:19} {}", "Sample rate:", self.rate)?;
+        writeln!(f, "{:19} {}", "Sampled files:", self.sampled_files)?;
+        writeln!(
+            f,
+            "{:19} {}",
+            "Sampled space:",
+            file_size(self.sampled_space)
+        )?;
+        writeln!(f, "{:19} ~{}", "Estimated files:", self.estimated_files())?;
+        writeln!(
+            f,
+            "{:19} ~{}",
+            "Estimated space:",
+            file_size(self.estimated_space())
+        )?;
+        Ok(())
+    }
+}
+
+/// Summary of the files written by a download run
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadStats {
+    pub downloaded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub size_corrected: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl DownloadStats {
+    pub fn new(counters: &RunCounters, elapsed: Duration) -> Self {
+        DownloadStats {
+            downloaded: counters.processed.load(Ordering::Relaxed),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+            failed: counters.error_count.load(Ordering::Relaxed),
+            size_corrected: counters.size_corrected.load(Ordering::Relaxed),
+            bytes: counters.downloaded_bytes.load(Ordering::Relaxed),
+            elapsed,
+        }
+    }
+
+    /// Bytes per second
+    pub fn throughput(&self) -> u64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => (self.bytes as f64 / secs) as u64,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for DownloadStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file_size = make_format(BINARY);
+        writeln!(f)?;
+        writeln!(f, "Download summary")?;
+        writeln!(f, "{:19} {}", "Files downloaded:", self.downloaded)?;
+        writeln!(f, "{:19} {}", "Files skipped:", self.skipped)?;
+        writeln!(f, "{:19} {}", "Files failed:", self.failed)?;
+        if self.size_corrected > 0 {
+            writeln!(f, "{:19} {}", "Sizes corrected:", self.size_corrected)?;
+        }
+        writeln!(f, "{:19} {}", "Bytes transferred:", file_size(self.bytes))?;
+        writeln!(f, "{:19} {:.1}s", "Elapsed:", self.elapsed.as_secs_f64())?;
+        writeln!(f, "{:19} {}/s", "Throughput:", file_size(self.throughput()))?;
+        Ok(())
+    }
+}
+
+/// Summary of the keys removed by a delete run
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeleteStats {
+    pub deleted: u64,
+    pub bytes: u64,
+    pub failed: u64,
+}
+
+impl DeleteStats {
+    pub fn new(counters: &RunCounters) -> Self {
+        DeleteStats {
+            deleted: counters.deleted.load(Ordering::Relaxed),
+            bytes: counters.reclaimed_bytes.load(Ordering::Relaxed),
+            failed: counters.error_count.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The running totals printed between the batches with --progress
+    pub fn progress_line(&self) -> String {
+        let file_size = make_format(BINARY);
+        format!(
+            "deleted {} objects, ~{} so far",
+            self.deleted,
+            file_size(self.bytes)
+        )
+    }
+}
+
+impl fmt::Display for DeleteStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file_size = make_format(BINARY);
+        writeln!(f)?;
+        writeln!(f, "Delete summary")?;
+        writeln!(f, "{:16} {}", "Objects deleted:", self.deleted)?;
+        writeln!(f, "{:16} {}", "Bytes deleted:", file_size(self.bytes))?;
+        writeln!(f, "{:16} {}", "Objects failed:", self.failed)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FindStat {
+    pub total_files: usize,
+    pub total_space: u64,
+    pub max_size: Option<u64>,
+    pub min_size: Option<u64>,
+    pub max_key: String,
+    pub min_key: String,
+    /// Objects listed with a negative size, left out of the sizes
+    pub negative_sizes: usize,
+    pub sizes: SizeHistogram,
+}
+
+impl FindStat {
+    /// Average size of the files with a valid size
+    pub fn average_size(&self) -> u64 {
+        match (self.total_files - self.negative_sizes) as u64 {
+            0 => 0,
+            files => self.total_space / files,
+        }
+    }
+}
+
+impl Add<&[aws_sdk_s3::types::Object]> for FindStat {
+    type Output = FindStat;
+
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn add(mut self: FindStat, list: &[aws_sdk_s3::types::Object]) -> Self {
+        for x in list {
+            self.total_files += 1;
+            self.sizes.add(x.size);
+            // a negative size is a backend bug, it would corrupt the totals
+            let size = match u64::try_from(x.size.unwrap_or_default()) {
+                Ok(size) => size,
+                Err(_) => {
+                    self.negative_sizes += 1;
+                    continue;
+                }
+            };
+            self.total_space = self.total_space.saturating_add(size);
+
+            match self.max_size {
+                Some(max_size) if max_size > size => {}
+                _ => {
+                    self.max_size = Some(size);
+                    self.max_key = x.key.clone().unwrap_or_default();
+                }
+            }
+
+            match self.min_size {
+                Some(min_size) if min_size <= size => {}
+                _ => {
+                    self.min_size = Some(size);
+                    self.min_key = x.key.clone().unwrap_or_default();
+                }
+            }
+        }
+        self
+    }
+}
+
+impl Default for FindStat {
+    fn default() -> Self {
+        FindStat {
+            total_files: 0,
+            total_space: 0,
+            max_size: None,
+            min_size: None,
+            max_key: "".to_owned(),
+            min_key: "".to_owned(),
+            negative_sizes: 0,
+            sizes: SizeHistogram::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    use aws_sdk_s3::primitives::DateTime;
+    use structopt::StructOpt;
+
+    use crate::credentials::Clock;
+    use crate::notify::WebhookNotifier;
+    use crate::replay::*;
+    use crate::run::{list_filter_execute, Heartbeat, ListingProgress};
+
+    /// Regex tests and matches over 1M keys, one in 100 has the size
+    async fn regex_tests(sorted: bool) -> (u64, usize) {
+        let regex = Regex::new(r"^logs/(\d+/)*\d+\.gz$").unwrap();
+        let size = FindSize::Bigger(990_000);
+
+        let mut filters = FilterList(Vec::new());
+        filters.push(&regex);
+        filters.push(&size);
+        if sorted {
+            filters.sort_by_cost();
+        }
+        let mut matched = 0;
+        for n in 0..1_000_000 {
+            let object = Object::builder()
+                .key(format!("logs/{}.gz", n))
+                .size(n)
+                .build();
+            if filters.test_match(object).await {
+                matched += 1;
+            }
+        }
+        let tests = filters
+            .selectivity()
+            .into_iter()
+            .find(|x| x.flag.starts_with("--regex"))
+            .map(|x| x.evaluated)
+            .unwrap_or_default();
+        (tests, matched)
+    }
+
+    #[tokio::test]
+    async fn test_filter_cost_order() {
+        assert_eq!(regex_tests(false).await, (1_000_000, 10_000));
+        assert_eq!(regex_tests(true).await, (10_000, 10_000));
+    }
+
+    #[tokio::test]
+    async fn test_ext_with_size() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>data/b.CSV</Key></Deleted></DeleteResult>"#,
+        )]);
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://test/data/",
+            "--ext",
+            "csv,parquet",
+            "--size",
+            "+1k",
+            "delete",
+            "--quiet-versioning-warning",
+        ])?;
+        let filters = FilterList::from_opts(&opts)?;
+        let mut find = test_find(client, opts.cmd.clone().unwrap());
+        find.path = opts.path.clone().unwrap();
+
+        let objects = [
+            ("data/a.csv", 10),
+            ("data/b.CSV", 2048),
+            ("data/c.json", 4096),
+            ("data/d.csv.gz", 4096),
+        ]
+        .iter()
+        .map(|(key, size)| Object::builder().key(*key).size(*size).build().into())
+        .collect();
+        list_filter_execute(
+            futures::stream::iter(vec![objects]),
+            None,
+            None,
+            |x| filters.test_match(x.object.clone()),
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        let body = String::from_utf8(requests[0].body.clone())?;
+        assert!(body.contains("<Key>data/b.CSV</Key>"));
+        assert!(!body.contains("a.csv"));
+        assert!(!body.contains("c.json"));
+        assert!(!body.contains("d.csv.gz"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dedup_across_pages() -> Result<(), Error> {
+        let run = |dedup: &'static str| async move {
+            let (client, _) = replay_client(vec![]);
+            let opts =
+                FindOpt::from_iter_safe(&["s3find", "s3://test/", "--dedup", dedup, "nothing"])?;
+            let filters = FilterList::from_opts(&opts)?;
+            let mut find = test_find(client, opts.cmd.clone().unwrap());
+            find.dedup = Dedup::new(opts.dedup);
+
+            let page = |keys: &[&str]| -> Vec<ListedObject> {
+                keys.iter()
+                    .map(|key| Object::builder().key(*key).size(10).build().into())
+                    .collect()
+            };
+            let find = &find;
+            let stats = list_filter_execute(
+                futures::stream::iter(vec![page(&["a", "b"]), page(&["a", "c"])]),
+                None,
+                Some(FindStat::default()),
+                |x| {
+                    let matched = filters.test_match(x.object.clone());
+                    let identifier = find.dedup_identifier(x);
+                    async move {
+                        find.counters
+                            .filtered(find.admit(matched.await, identifier))
+                    }
+                },
+                &mut |acc, x| find.exec(acc, x),
+            )
+            .await
+            .unwrap();
+            Ok::<_, Error>((stats.total_files, find.duplicates_note()))
+        };
+
+        assert_eq!(
+            run("key").await?,
+            (3, Some("skipped 1 duplicate keys".to_owned()))
+        );
+        assert_eq!(run("none").await?, (4, None));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_selectivity() -> Result<(), Error> {
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://test/",
+            "--name",
+            "*.log",
+            "--size",
+            "+1k",
+            "--regex",
+            "^x",
+        ])?;
+        let filters = FilterList::from_opts(&opts)?;
+        // size 0, 512, ... 4608: 8 of them are at least 1k, 4 of those are logs
+        for n in 0..10 {
+            let object = Object::builder()
+                .key(format!("{}.{}", n, if n % 2 == 0 { "log" } else { "csv" }))
+                .size(n * 512)
+                .build();
+            assert!(!filters.test_match(object).await);
+        }
+
+        assert_eq!(
+            filters.selectivity(),
+            vec![
+                FilterSelectivity {
+                    flag: "--size +1k".to_owned(),
+                    evaluated: 10,
+                    rejected: 2,
+                },
+                FilterSelectivity {
+                    flag: "--name *.log".to_owned(),
+                    evaluated: 8,
+                    rejected: 4,
+                },
+                FilterSelectivity {
+                    flag: "--regex ^x".to_owned(),
+                    evaluated: 4,
+                    rejected: 4,
+                },
+            ]
+        );
+        assert_eq!(
+            SelectivityTable(&filters.selectivity()).to_string(),
+            "\
+Filter         Evaluated    Rejected  Pass-through
+--size +1k            10           2         80.0%
+--name *.log           8           4         50.0%
+--regex ^x             4           4          0.0%"
+        );
+
+        // the percentile pass is not counted
+        assert!(!filters.matches(&Object::builder().key("a.log").size(2048).build()));
+        assert_eq!(filters.selectivity()[0].evaluated, 10);
+
+        let unused = FilterSelectivity {
+            flag: "--name *".to_owned(),
+            evaluated: 3,
+            rejected: 0,
+        };
+        let idle = FilterSelectivity {
+            flag: "--size -1k".to_owned(),
+            evaluated: 0,
+            rejected: 0,
+        };
+        assert_eq!(idle.pass_rate(), None);
+        assert_eq!(
+            SelectivityTable(&[unused, idle]).to_string(),
+            "\
+Filter       Evaluated    Rejected  Pass-through
+--name *             3           0        100.0%
+--size -1k           0           0             -
+note: --name * rejected none of 3 objects"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_name_anchor() -> Result<(), Error> {
+        let keys = [
+            "project/data.csv",
+            "project/raw/data.csv",
+            "project/meta.json",
+        ];
+        let matched = |args: &[&str]| {
+            let opts = FindOpt::from_iter_safe(
+                ["s3find", "s3://test/project/"]
+                    .iter()
+                    .chain(args)
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap();
+            let filters = FilterList::from_opts(&opts).unwrap();
+            keys.iter()
+                .filter(|key| filters.matches(&Object::builder().key(**key).build()))
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // the whole key
+        assert!(matched(&["--name", "data*"]).is_empty());
+        assert_eq!(
+            matched(&["--name", "*data*"]),
+            vec!["project/data.csv", "project/raw/data.csv"]
+        );
+
+        // the key below the prefix, anchored at its start
+        assert_eq!(
+            matched(&["--name", "data*", "--anchor", "prefix"]),
+            vec!["project/data.csv"]
+        );
+        assert_eq!(
+            matched(&["--iname", "DATA*", "--anchor", "prefix"]),
+            vec!["project/data.csv"]
+        );
+        assert_eq!(
+            matched(&["--name", "raw/*", "--anchor", "prefix"]),
+            vec!["project/raw/data.csv"]
+        );
+
+        // the patterns compiled into a set are anchored as well
+        let mut args = vec!["--anchor", "prefix"];
+        for _ in 0..=GLOB_SET_THRESHOLD {
+            args.extend(&["--name", "*.csv", "--name", "data*"]);
+        }
+        assert_eq!(matched(&args), vec!["project/data.csv"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_name_hint() -> Result<(), Error> {
+        async fn listed(anchor: &str, name: &str) -> Result<Option<String>, Error> {
+            let opts = FindOpt::from_iter_safe(&[
+                "s3find",
+                "s3://test/project/",
+                "--anchor",
+                anchor,
+                "--name",
+                name,
+            ])?;
+            let filters = FilterList::from_opts(&opts)?;
+            for key in &["project/data.csv", "project/meta.json"] {
+                filters
+                    .test_match(Object::builder().key(*key).build())
+                    .await;
+            }
+            Ok(filters.name_hint(opts.anchor))
+        }
+
+        assert_eq!(
+            listed("anywhere", "data*").await?.as_deref(),
+            Some("hint: --name data* matched none of 2 listed keys; globs match the whole key, use --anchor prefix to match the key below the search prefix or start the glob with *")
+        );
+        // a glob matching a key, or anchored to the prefix
+        assert_eq!(listed("anywhere", "*data*").await?, None);
+        assert_eq!(listed("prefix", "nothing*").await?, None);
+
+        // no listed keys
+        let opts = FindOpt::from_iter_safe(&["s3find", "s3://test/", "--name", "data*"])?;
+        assert_eq!(
+            FilterList::from_opts(&opts)?.name_hint(NameAnchor::Anywhere),
+            None
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_order_equivalence() -> Result<(), Error> {
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://test/",
+            "--regex",
+            r"\d\.log$",
+            "--name",
+            "logs/*",
+            "--size",
+            "+100",
+            "--mtime",
+            "-400d",
+            "--where",
+            "storage_class=STANDARD or size=-50",
+            "--sample-rate",
+            "0.5",
+        ])?;
+        let now = DateTime::from(std::time::SystemTime::now()).secs();
+        let objects: Vec<_> = (0..300)
+            .map(|n| {
+                Object::builder()
+                    .key(format!(
+                        "{}/{}.{}",
+                        if n % 3 == 0 { "logs" } else { "tmp" },
+                        n,
+                        if n % 2 == 0 { "log" } else { "csv" }
+                    ))
+                    .size(n)
+                    .last_modified(DateTime::from_secs(now - n * 86_400))
+                    .storage_class(if n % 5 == 0 {
+                        ObjectStorageClass::Glacier
+                    } else {
+                        ObjectStorageClass::Standard
+                    })
+                    .build()
+            })
+            .collect();
+
+        let sorted = FilterList::from_opts(&opts)?;
+        assert!(sorted.0.windows(2).all(|x| x[0].cost() <= x[1].cost()));
+        assert_eq!(sorted.0[0].describe(), "size >= 100 bytes");
+        assert_eq!(
+            sorted.0.last().unwrap().describe(),
+            "key matches regex \\d\\.log$"
+        );
+
+        let mut reversed = FilterList::from_opts(&opts)?;
+        reversed.0.reverse();
+        let mut matched = 0;
+        for object in objects {
+            let expected = sorted.test_match(object.clone()).await;
+            assert_eq!(reversed.test_match(object).await, expected);
+            matched += expected as usize;
+        }
+        assert!(matched > 0);
+        Ok(())
+    }
+
+    fn test_find(client: Client, cmd: Cmd) -> Find {
+        Find {
+            client,
+            path: "s3://test/".parse().unwrap(),
+            limit: None,
+            page_size: 1000,
+            prefetch: 1,
+            stats: false,
+            summarize: false,
+            key_requests: cmd.requests_keys(),
+            command: cmd.downcast(),
+            counters: Default::default(),
+            credentials_provider: None,
+            budget: Default::default(),
+            notifiers: Vec::new(),
+            manifest: None,
+            batch_headers: None,
+            cache: None,
+            shard: None,
+            list_api: ListApi::Auto,
+            dedup: None,
+            size_buckets: SizeBuckets::default(),
+            one_level: false,
+            mtime_recheck: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exclude_prefixes_delete() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>tmp/b</Key></Deleted></DeleteResult>"#,
+        )]);
+        let find = test_find(
+            client,
+            Cmd::Delete(MultipleDelete {
+                if_unmodified: false,
+                quiet_versioning_warning: true,
+                ..Default::default()
+            }),
+        );
+
+        let exclude = Some(ExcludePrefixes(PrefixSet::new(vec!["hold/".to_owned()])));
+        let filters = FilterList::new(&[], &[], &[], &[], &[], &exclude, &None);
+
+        let objects = vec![
+            Object::builder().key("hold/a").build().into(),
+            Object::builder().key("tmp/b").build().into(),
+        ];
+
+        list_filter_execute(
+            futures::stream::iter(vec![objects]),
+            None,
+            None,
+            |x| filters.test_match(x.object.clone()),
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        let body = String::from_utf8(requests[0].body.clone())?;
+        assert!(body.contains("<Key>tmp/b</Key>"));
+        assert!(!body.contains("hold/a"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ignore_file_delete() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let ignore_file = dir.path().join(".s3findignore");
+        std::fs::write(&ignore_file, "# scratch areas\ntmp/\n*.bak\n!keep.bak\n")?;
+
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>data/a.csv</Key></Deleted><Deleted><Key>data/keep.bak</Key></Deleted></DeleteResult>"#,
+        )]);
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://test/data/",
+            "--ignore-file",
+            &ignore_file.display().to_string(),
+            "delete",
+            "--quiet-versioning-warning",
+        ])?;
+        let filters = FilterList::from_opts(&opts)?;
+        let mut find = test_find(client, opts.cmd.clone().unwrap());
+        find.path = opts.path.clone().unwrap();
+
+        let objects = [
+            "data/a.csv",
+            "data/tmp/b.csv",
+            "data/c.bak",
+            "data/keep.bak",
+        ]
+        .iter()
+        .map(|key| Object::builder().key(*key).build().into())
+        .collect();
+        list_filter_execute(
+            futures::stream::iter(vec![objects]),
+            None,
+            None,
+            |x| filters.test_match(x.object.clone()),
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        let body = String::from_utf8(requests[0].body.clone())?;
+        assert!(body.contains("<Key>data/a.csv</Key>"));
+        assert!(body.contains("<Key>data/keep.bak</Key>"));
+        assert!(!body.contains("data/tmp/b.csv"));
+        assert!(!body.contains("data/c.bak"));
+
+        let ignore = &filters.selectivity()[0];
+        assert!(ignore.flag.starts_with("--ignore-file "));
+        assert_eq!((ignore.evaluated, ignore.rejected), (4, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_file_over_the_regex_limit() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let ignore_file = dir.path().join(".s3findignore");
+        std::fs::write(&ignore_file, "?".repeat(100_000))?;
+
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://test/data/",
+            "--ignore-file",
+            &ignore_file.display().to_string(),
+        ])?;
+        let error = FilterList::from_opts(&opts).err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("Cannot compile the patterns of ignore file"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_excludes_nested_destination() -> Result<(), Error> {
+        let listing = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult>
+    <Name>test</Name>
+    <KeyCount>4</KeyCount>
+    <Contents><Key>data/a.csv</Key><Size>1</Size></Contents>
+    <Contents><Key>data/backup/data/a.csv</Key><Size>1</Size></Contents>
+    <Contents><Key>data/backup/data/b.csv</Key><Size>1</Size></Contents>
+    <Contents><Key>data/b.csv</Key><Size>1</Size></Contents>
+</ListBucketResult>"#;
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, listing),
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(200, ""),
+        ]);
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://test/data/",
+            "copy",
+            "--no-preflight",
+            "s3://test/data/backup",
+        ])?;
+        let filters = FilterList::from_opts(&opts)?;
+        let mut find = test_find(client, opts.cmd.clone().unwrap());
+        find.path = opts.path.clone().unwrap();
+
+        list_filter_execute(
+            find.to_stream().stream(),
+            None,
+            None,
+            |x| {
+                find.counters.scan();
+                let matched = filters.test_match(x.object.clone());
+                let counters = &find.counters;
+                async move { counters.filtered(matched.await) }
+            },
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+
+        let requests = http_client.requests();
+        let copied: Vec<_> = requests[1..].iter().map(|x| x.path()).collect();
+        assert_eq!(
+            copied,
+            vec![
+                "https://test.s3.us-east-1.amazonaws.com/data/backup/data/a.csv",
+                "https://test.s3.us-east-1.amazonaws.com/data/backup/data/b.csv",
+            ]
+        );
+        assert_eq!(find.counters.scanned.load(Ordering::Relaxed), 4);
+        assert_eq!(find.counters.matched.load(Ordering::Relaxed), 2);
+        assert_eq!(find.counters.processed.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_percentile_passes() -> Result<(), Error> {
+        let list_result = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult>
+    <Name>test</Name>
+    <KeyCount>5</KeyCount>
+    <Contents><Key>a.log</Key><Size>10</Size><LastModified>2024-01-01T00:00:00.000Z</LastModified></Contents>
+    <Contents><Key>b.log</Key><Size>40</Size><LastModified>2024-02-01T00:00:00.000Z</LastModified></Contents>
+    <Contents><Key>c.log</Key><Size>30</Size><LastModified>2024-03-01T00:00:00.000Z</LastModified></Contents>
+    <Contents><Key>d.log</Key><Size>20</Size><LastModified>2024-04-01T00:00:00.000Z</LastModified></Contents>
+    <Contents><Key>e.txt</Key><Size>99</Size><LastModified>2023-01-01T00:00:00.000Z</LastModified></Contents>
+</ListBucketResult>"#;
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, list_result),
+            ReplayResponse::new(200, list_result),
+        ]);
+        let find = test_find(client, Cmd::Nothing(DoNothing {}));
+
+        let name = vec![Pattern::new("*.log")?];
+        let mut filters = FilterList::new(&name, &[], &[], &[], &[], &None, &None);
+        let cutoffs = find
+            .percentile_cutoffs(&filters, Some(50.0), Some(50.0))
+            .await;
+        assert_eq!(
+            cutoffs,
+            vec![
+                PercentileCutoff::Oldest(50.0, 1_706_745_600),
+                PercentileCutoff::Largest(50.0, 30),
+            ]
+        );
+        for cutoff in cutoffs {
+            filters.push(cutoff);
+        }
+
+        let matched = Mutex::new(Vec::new());
+        list_filter_execute(
+            find.to_stream().stream(),
+            None,
+            None,
+            |x| filters.test_match(x.object.clone()),
+            &mut |acc, list| {
+                matched
+                    .lock()
+                    .unwrap()
+                    .extend(list.into_iter().filter_map(|x| x.object.key));
+                async { acc }
+            },
+        )
+        .await;
+
+        // oldest half: a, b; largest half: b, c
+        assert_eq!(*matched.lock().unwrap(), vec!["b.log"]);
+        assert_eq!(http_client.requests().len(), 2);
+        Ok(())
+    }
+
+    fn list_page(key: &str, truncated: bool, token: Option<&str>) -> ReplayResponse {
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><KeyCount>1</KeyCount><IsTruncated>{}</IsTruncated>{}<Contents><Key>{}</Key><Size>1</Size></Contents></ListBucketResult>"#,
+                truncated,
+                token
+                    .map(|x| format!("<NextContinuationToken>{}</NextContinuationToken>", x))
+                    .unwrap_or_default(),
+                key
+            ),
+        )
+    }
+
+    fn keys_page(keys: &[&str], truncated: bool, token: Option<&str>) -> ReplayResponse {
+        let contents: String = keys
+            .iter()
+            .map(|key| format!("<Contents><Key>{}</Key><Size>1</Size></Contents>", key))
+            .collect();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><KeyCount>{}</KeyCount><MaxKeys>2</MaxKeys><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>"#,
+                keys.len(),
+                truncated,
+                token
+                    .map(|x| format!("<NextContinuationToken>{}</NextContinuationToken>", x))
+                    .unwrap_or_default(),
+                contents
+            ),
+        )
+    }
+
+    async fn listed_keys(find: &Find) -> Vec<String> {
+        find.to_stream()
+            .stream()
+            .map(|page| futures::stream::iter(page.into_iter().filter_map(|x| x.object.key)))
+            .flatten()
+            .collect()
+            .await
+    }
+
+    fn delimited_page(keys: &[&str], prefixes: &[&str], token: Option<&str>) -> ReplayResponse {
+        let contents: String = keys
+            .iter()
+            .map(|key| format!("<Contents><Key>{}</Key><Size>1</Size></Contents>", key))
+            .collect();
+        let prefixes: String = prefixes
+            .iter()
+            .map(|x| format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", x))
+            .collect();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><Prefix>data/</Prefix><Delimiter>/</Delimiter><EncodingType>url</EncodingType><IsTruncated>{}</IsTruncated>{}{}{}</ListBucketResult>"#,
+                token.is_some(),
+                token
+                    .map(|x| format!("<NextContinuationToken>{}</NextContinuationToken>", x))
+                    .unwrap_or_default(),
+                contents,
+                prefixes
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_one_level_listing() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![delimited_page(
+            &["data/a.csv", "data/b.csv"],
+            &["data/2023/", "data/my%20dir/"],
+            None,
+        )]);
+        let mut find = test_find(client, Cmd::default());
+        find.path = "s3://test/data/".parse()?;
+        find.one_level = true;
+
+        let stream = find.to_stream();
+        assert!(stream.one_level && stream.list_prefixes);
+        // the keys of the prefixes are never listed
+        assert_eq!(listed_keys(&find).await, vec!["data/a.csv", "data/b.csv"]);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].uri.contains("delimiter=%2F"));
+        assert!(requests[0].uri.contains("prefix=data%2F"));
+
+        let page = common_prefixes(
+            Some(vec![CommonPrefix::builder()
+                .prefix("data/my%20dir/")
+                .build()]),
+            true,
+        );
+        assert_eq!(page, vec!["data/my dir/"]);
+        assert_eq!(
+            prefix_line("test", "data/2023/"),
+            "PRE s3://test/data/2023/"
+        );
+
+        // the pages of a long level are listed, still without recursing
+        let (client, http_client) = replay_client(vec![
+            delimited_page(&[], &["data/2023/"], Some("t1")),
+            delimited_page(&["data/c.csv"], &[], None),
+        ]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.path = "s3://test/data/".parse()?;
+        find.one_level = true;
+
+        assert!(!find.to_stream().list_prefixes);
+        assert_eq!(listed_keys(&find).await, vec!["data/c.csv"]);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|x| x.uri.contains("delimiter=%2F")));
+        assert!(requests[1].uri.contains("continuation-token=t1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recursive_listing_has_no_delimiter() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![keys_page(&["a/b/c"], false, None)]);
+        let find = test_find(client, Cmd::default());
+
+        assert_eq!(listed_keys(&find).await, vec!["a/b/c"]);
+        assert!(!http_client.requests()[0].uri.contains("delimiter"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_page_size_halved_on_timeout() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::timeout(),
+            keys_page(&["a", "b"], true, Some("t1")),
+            ReplayResponse::timeout(),
+            keys_page(&["c"], false, None),
+        ]);
+        let find = test_find(client, Cmd::default());
+
+        assert_eq!(listed_keys(&find).await, vec!["a", "b", "c"]);
+        let max_keys: Vec<_> = http_client
+            .requests()
+            .iter()
+            .map(|x| {
+                x.uri
+                    .split("max-keys=")
+                    .nth(1)
+                    .unwrap()
+                    .split('&')
+                    .next()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        assert_eq!(max_keys, vec!["1000", "500", "500", "250"]);
+        assert!(find.counters.listing_error.lock().unwrap().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_size_tuning() {
+        let mut tuning = PageSizeTuning::new(1000);
+        assert_eq!(tuning.shrink(1000), Some(500));
+        assert_eq!(tuning.shrink(80), Some(MIN_PAGE_SIZE));
+        assert_eq!(tuning.shrink(MIN_PAGE_SIZE), None);
+
+        // the page size creeps back after a streak of listed pages
+        for _ in 1..PAGE_SIZE_RECOVERY {
+            assert_eq!(tuning.listed(400), None);
+        }
+        assert_eq!(tuning.listed(400), Some(800));
+        for _ in 1..PAGE_SIZE_RECOVERY {
+            assert_eq!(tuning.listed(800), None);
+        }
+        assert_eq!(tuning.listed(800), Some(1000));
+        assert_eq!(tuning.listed(1000), None);
+        assert_eq!(tuning.streak, 0);
+    }
+
+    #[tokio::test]
+    async fn test_page_boundaries() -> Result<(), Error> {
+        let cases = vec![
+            (
+                "exact multiple",
+                vec![
+                    keys_page(&["a", "b"], true, Some("t1")),
+                    keys_page(&["c", "d"], false, None),
+                ],
+                vec!["a", "b", "c", "d"],
+            ),
+            (
+                "exact multiple with an empty last page",
+                vec![
+                    keys_page(&["a", "b"], true, Some("t1")),
+                    keys_page(&["c", "d"], true, Some("t2")),
+                    keys_page(&[], false, None),
+                ],
+                vec!["a", "b", "c", "d"],
+            ),
+            (
+                "one over",
+                vec![
+                    keys_page(&["a", "b"], true, Some("t1")),
+                    keys_page(&["c", "d"], true, Some("t2")),
+                    keys_page(&["e"], false, None),
+                ],
+                vec!["a", "b", "c", "d", "e"],
+            ),
+            (
+                "empty truncated middle page",
+                vec![
+                    keys_page(&["a", "b"], true, Some("t1")),
+                    keys_page(&[], true, Some("t2")),
+                    keys_page(&["c"], false, None),
+                ],
+                vec!["a", "b", "c"],
+            ),
+            (
+                "empty truncated first page",
+                vec![
+                    keys_page(&[], true, Some("t1")),
+                    keys_page(&["a"], false, None),
+                ],
+                vec!["a"],
+            ),
+            ("empty prefix", vec![keys_page(&[], false, None)], vec![]),
+        ];
+
+        for prefetch in &[1, 3] {
+            for (case, pages, keys) in &cases {
+                let (client, http_client) = replay_client(pages.clone());
+                let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+                find.page_size = 2;
+                find.prefetch = *prefetch;
+
+                let listed: Vec<_> = find.to_stream().stream().collect().await;
+                assert!(
+                    listed.iter().all(|page| !page.is_empty()),
+                    "{}, prefetch {}",
+                    case,
+                    prefetch
+                );
+                let listed_keys: Vec<_> = listed
+                    .iter()
+                    .flatten()
+                    .filter_map(|x| x.key.as_deref())
+                    .collect();
+                assert_eq!(&listed_keys, keys, "{}, prefetch {}", case, prefetch);
+                assert_eq!(
+                    http_client.requests().len(),
+                    pages.len(),
+                    "{}, prefetch {}",
+                    case,
+                    prefetch
+                );
+                assert!(find.listing_error().is_none());
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_page_boundary_counts() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![
+            keys_page(&["a", "b"], true, Some("t1")),
+            keys_page(&[], true, Some("t2")),
+            keys_page(&["c", "d"], true, Some("t3")),
+            keys_page(&[], false, None),
+        ]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.page_size = 2;
+
+        let stats = list_filter_execute(
+            find.to_stream().stream(),
+            None,
+            default_stats(true),
+            |_| async { true },
+            &mut |acc, list| find.exec(acc, list),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stats.total_files, 4);
+        assert_eq!(stats.max_key, "d");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncated_page_without_token() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            list_page("a", true, None),
+            list_page("a", true, None),
+            list_page("a", true, None),
+            list_page("never", false, None),
+        ]);
+        let find = test_find(client, Cmd::Nothing(DoNothing {}));
+
+        assert!(listed_keys(&find).await.is_empty());
+        assert_eq!(http_client.requests().len(), 3);
+        assert_eq!(
+            find.listing_error().unwrap().to_string(),
+            "Listing stopped at s3://test/: the backend returned a truncated page without a continuation token after 3 attempts"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shard_range_listing() -> Result<(), Error> {
+        // the listing starts before the range and ends past it
+        let (client, http_client) = replay_client(vec![
+            keys_page(&["b.csv", "b/1"], true, Some("t1")),
+            keys_page(&["b/2", "c/1"], true, Some("t2")),
+            keys_page(&["never"], false, None),
+        ]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.shard = Some((
+            Shard { index: 1, count: 3 },
+            Some(KeyRange {
+                from: Some("b/".to_owned()),
+                to: Some("c/".to_owned()),
+            }),
+        ));
+
+        assert_eq!(listed_keys(&find).await, vec!["b/1", "b/2"]);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].uri.ends_with("&start-after=b"));
+        assert_eq!(
+            find.shard_note().as_deref(),
+            Some("1/3 (keys from b/ before c/)")
+        );
+        assert!(find
+            .stat_summary(&FindStat::default())
+            .ends_with("Shard:              1/3 (keys from b/ before c/)\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shard_by_hash() -> Result<(), Error> {
+        let keys: Vec<_> = (0..100).map(|x| format!("data/{}.csv", x)).collect();
+        let mut processed = Vec::new();
+        for index in 0..3 {
+            let shard = format!("{}/3", index);
+            let opts = FindOpt::from_iter_safe(&["s3find", "s3://test/", "--shard", &shard])?;
+            let filters = FilterList::from_opts(&opts)?;
+            assert_eq!(filters.0[0].flag(), format!("--shard {}", shard));
+            for key in &keys {
+                if filters.matches(&Object::builder().key(key).build()) {
+                    processed.push(key.clone());
+                }
+            }
+        }
+        processed.sort_by_key(|x| x[5..x.len() - 4].parse::<u32>().unwrap());
+        assert_eq!(processed, keys);
+
+        // the range shards list only their range, without a hash filter
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://test/",
+            "--shard",
+            "0/3",
+            "--shard-by-range",
+        ])?;
+        assert!(FilterList::from_opts(&opts)?.0.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_repeated_continuation_token() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            list_page("a", true, Some("t1")),
+            list_page("b", true, Some("t1")),
+            list_page("b", true, Some("t1")),
+            list_page("b", true, Some("t1")),
+            list_page("never", false, None),
+        ]);
+        let find = test_find(client, Cmd::Nothing(DoNothing {}));
+
+        assert_eq!(listed_keys(&find).await, vec!["a"]);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[3].uri.contains("continuation-token=t1"));
+        assert_eq!(
+            find.listing_error().unwrap().to_string(),
+            "Listing stopped at s3://test/: the backend returned the same continuation token again after 3 attempts"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_page_anomaly_retry() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            list_page("a", true, None),
+            list_page("a", true, Some("t1")),
+            list_page("b", false, None),
+        ]);
+        let find = test_find(client, Cmd::Nothing(DoNothing {}));
+
+        assert_eq!(listed_keys(&find).await, vec!["a", "b"]);
+        assert_eq!(http_client.requests().len(), 3);
+        assert!(find.listing_error().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_listing_request_error() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            list_page("a", true, Some("t1")),
+            ReplayResponse {
+                status: 403,
+                timeout: false,
+                headers: vec![
+                    ("x-amz-request-id".to_owned(), "4442587FB7D0A2F9".to_owned()),
+                    ("x-amz-id-2".to_owned(), "vlR7PnpV2Ce81l0P".to_owned()),
+                ],
+                body: r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>"#
+                    .to_owned(),
+            },
+            list_page("never", false, None),
+        ]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.stats = true;
+
+        let stats = list_filter_execute(
+            find.to_stream().stream(),
+            None,
+            default_stats(find.stats),
+            |_| async { true },
+            &mut |acc, list| find.exec(acc, list),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(http_client.requests().len(), 2);
+        assert_eq!(stats.total_files, 1);
+        assert_eq!(stats.max_key, "a");
+        let error = "Listing stopped at s3://test/: AccessDenied: Access Denied \
+            (request id: 4442587FB7D0A2F9, extended request id: vlR7PnpV2Ce81l0P)";
+        assert_eq!(find.listing_error().unwrap().to_string(), error);
+        let summary = find.stat_summary(&stats);
+        assert!(summary.starts_with(&format!("Partial summary, {}\n", error)));
+        assert!(summary.contains(&stats.to_string()));
+        assert_eq!(find.exit_code(), Some(COMMAND_FAILED_EXIT_CODE));
+        Ok(())
+    }
+
+    fn v1_page(keys: &[&str], truncated: bool, next_marker: Option<&str>) -> ReplayResponse {
+        let contents: String = keys
+            .iter()
+            .map(|key| format!("<Contents><Key>{}</Key><Size>1</Size></Contents>", key))
+            .collect();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><EncodingType>url</EncodingType><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>"#,
+                truncated,
+                next_marker
+                    .map(|x| format!("<NextMarker>{}</NextMarker>", x))
+                    .unwrap_or_default(),
+                contents
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_list_objects_v1_fallback() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                501,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>NotImplemented</Code><Message>list-type=2 is not implemented</Message></Error>"#,
+            ),
+            v1_page(&["a", "b%20c"], true, None),
+            v1_page(&["d"], true, Some("d")),
+            v1_page(&["e"], false, None),
+        ]);
+        let find = test_find(client, Cmd::Nothing(DoNothing {}));
+
+        assert_eq!(listed_keys(&find).await, vec!["a", "b c", "d", "e"]);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].uri.contains("list-type=2"));
+        assert!(!requests[1].uri.contains("list-type"));
+        assert!(!requests[1].uri.contains("marker"));
+        assert!(requests[2].uri.contains("marker=b%20c"));
+        assert!(requests[3].uri.contains("marker=d"));
+        assert!(find.listing_error().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_objects_v1() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            v1_page(&["a"], true, None),
+            v1_page(&["b"], false, None),
+        ]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.list_api = ListApi::V1;
+
+        assert_eq!(listed_keys(&find).await, vec!["a", "b"]);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].uri.contains("list-type"));
+        assert!(requests[1].uri.contains("marker=a"));
+
+        // only the first request falls back, and only in auto mode
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            501,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>NotImplemented</Code><Message>Not Implemented</Message></Error>"#,
+        )]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.list_api = ListApi::V2;
+
+        assert!(listed_keys(&find).await.is_empty());
+        assert_eq!(http_client.requests().len(), 1);
+        assert!(find
+            .listing_error()
+            .unwrap()
+            .to_string()
+            .contains("NotImplemented"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_invalid_utf8_keys() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![v1_page(
+            &["utf8/ok", "utf8/list%FF%E2%80%AE"],
+            false,
+            None,
+        )]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.list_api = ListApi::V1;
+
+        let pages: Vec<_> = find.to_stream().stream().collect().await;
+        assert_eq!(pages[0][0].key.as_deref(), Some("utf8/ok"));
+        assert_eq!(pages[0][0].key_bytes, None);
+        assert_eq!(
+            pages[0][1].key.as_deref(),
+            Some("utf8/list\u{fffd}\u{202e}")
+        );
+        assert_eq!(
+            pages[0][1].key_bytes.as_deref(),
+            Some(&b"utf8/list\xff\xe2\x80\xae"[..])
+        );
+        assert_eq!(find.counters.invalid_utf8.load(Ordering::Relaxed), 1);
+        assert!(find
+            .invalid_utf8_note()
+            .unwrap()
+            .contains("1 keys are not valid UTF-8"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_keys_are_not_addressed() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>utf8/ok</Key></Deleted></DeleteResult>"#,
+        )]);
+        let find = test_find(
+            client,
+            Cmd::Delete(MultipleDelete {
+                quiet_versioning_warning: true,
+                ..Default::default()
+            }),
+        );
+        assert!(find.key_requests);
+
+        let (key, key_bytes) = decode_listed_key("utf8/bad%FF");
+        let invalid = ListedObject {
+            object: Object::builder().key(key).size(1).build(),
+            key_bytes,
+        };
+        let valid = Object::builder().key("utf8/ok").size(1).build().into();
+        let stats = find.exec(default_stats(true), vec![invalid, valid]).await;
+
+        // U+FFFD would address another key, only the valid key is deleted
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        let body = String::from_utf8(requests[0].body.clone())?;
+        assert!(body.contains("<Key>utf8/ok</Key>"));
+        assert!(!body.contains("utf8/bad"));
+        assert_eq!(stats.unwrap().total_files, 2);
+        assert_eq!(find.counters.deleted.load(Ordering::Relaxed), 1);
+        let errors = find.counters.errors.lock().unwrap();
+        assert_eq!(
+            errors[0],
+            r"s3://test/utf8/bad\xff: the key is not valid UTF-8, S3 requests cannot address it"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_listing_cache() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let cache = ListingCache {
+            dir: dir.path().to_owned(),
+            ttl: Duration::from_secs(600),
+        };
+        let listed = |client: Client| {
+            let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+            find.cache = Some(cache.clone());
+            async move {
+                let pages: Vec<_> = find.to_stream().stream().collect().await;
+                (pages, find)
+            }
+        };
+
+        // a failed listing is not cached
+        let (client, _) = replay_client(vec![
+            list_page("a", true, Some("t1")),
+            ReplayResponse::new(
+                403,
+                "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>",
+            ),
+        ]);
+        let (_, find) = listed(client).await;
+        assert!(find.listing_error().is_some());
+
+        let (client, http_client) = replay_client(vec![
+            list_page("a", true, Some("t1")),
+            list_page("b", false, None),
+        ]);
+        let (first, _) = listed(client).await;
+        assert_eq!(http_client.requests().len(), 2);
+
+        let (client, http_client) = replay_client(vec![]);
+        let (second, find) = listed(client).await;
+        assert!(http_client.requests().is_empty());
+        assert!(find.listing_error().is_none());
+        assert_eq!(first, second);
+        assert_eq!(
+            second
+                .iter()
+                .flatten()
+                .filter_map(|x| x.key.as_deref())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+
+        // a mutating run drops the entry, the next run lists again
+        assert_eq!(cache.invalidate(&find.path)?, 1);
+        let (client, http_client) = replay_client(vec![list_page("a", false, None)]);
+        listed(client).await;
+        assert_eq!(http_client.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_headers() -> Result<(), Error> {
+        let headers = BatchHeaders::default();
+        let long = "x".repeat(80);
+        let objects = |keys: &[&str]| -> Vec<Object> {
+            keys.iter()
+                .map(|key| Object::builder().key(*key).build())
+                .collect()
+        };
+
+        assert_eq!(
+            headers.next(&objects(&["a", "b"])).unwrap(),
+            "--- batch 1 (keys 1-2: a .. b) ---"
+        );
+        assert_eq!(headers.next(&[]), None);
+        assert_eq!(
+            headers.next(&objects(&["c", &long])).unwrap(),
+            format!("--- batch 2 (keys 3-4: c .. {}...) ---", "x".repeat(57))
+        );
+
+        // three listed pages, handed to the command in batches of 1000 keys
+        let keys: Vec<_> = (0..2500).map(|x| format!("key{:04}", x)).collect();
+        let pages: Vec<Vec<ListedObject>> = keys
+            .chunks(900)
+            .map(|x| {
+                objects(&x.iter().map(String::as_str).collect::<Vec<_>>())
+                    .into_iter()
+                    .map(ListedObject::from)
+                    .collect()
+            })
+            .collect();
+        let (client, http_client) = replay_client(vec![]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.batch_headers = Some(BatchHeaders::default());
+
+        list_filter_execute(
+            futures::stream::iter(pages),
+            None,
+            default_stats(find.stats),
+            |_| async { true },
+            &mut |acc, list| find.exec(acc, list),
+        )
+        .await;
+
+        assert!(http_client.requests().is_empty());
+        let headers = find.batch_headers.as_ref().unwrap();
+        assert_eq!(headers.batches.load(Ordering::Relaxed), 3);
+        assert_eq!(headers.keys.load(Ordering::Relaxed), 2500);
+        assert_eq!(
+            headers.next(&objects(&["d"])).unwrap(),
+            "--- batch 4 (keys 2501-2501: d .. d) ---"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_accounted_once() {
+        let (client, _) = replay_client(vec![]);
+        let find = test_find(client, Cmd::Nothing(DoNothing {}));
+        let mut stream = find.to_stream();
+
+        assert!(stream.account(Some("t1")));
+        assert!(stream.account(Some("t2")));
+        // the page ending at t2 listed again
+        assert!(!stream.account(Some("t2")));
+        assert!(stream.account(None));
+        assert_eq!(stream.accounted.as_deref(), Some("t2"));
+
+        assert_eq!(
+            find.stat_summary(&FindStat::default()),
+            FindStat::default().to_string()
+        );
+        assert_eq!(find.exit_code(), None);
+    }
+
+    async fn timed_listing(prefetch: usize) -> (Vec<String>, Duration) {
+        let (client, _) = delayed_replay_client(
+            vec![
+                list_page("a", true, Some("t1")),
+                list_page("b", true, Some("t2")),
+                list_page("c", true, Some("t3")),
+                list_page("d", false, None),
+            ],
+            Duration::from_millis(100),
+        );
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.prefetch = prefetch;
+
+        let start = std::time::Instant::now();
+        let keys = find
+            .to_stream()
+            .stream()
+            .then(|page| async move {
+                // the consumer is as slow as the listing
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                futures::stream::iter(page.into_iter().filter_map(|x| x.object.key))
+            })
+            .flatten()
+            .collect()
+            .await;
+        (keys, start.elapsed())
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_pages() -> Result<(), Error> {
+        let (serial_keys, serial) = timed_listing(1).await;
+        let (prefetched_keys, prefetched) = timed_listing(2).await;
+
+        assert_eq!(serial_keys, vec!["a", "b", "c", "d"]);
+        assert_eq!(prefetched_keys, serial_keys);
+        assert!(
+            prefetched < serial,
+            "prefetched {:?}, serial {:?}",
+            prefetched,
+            serial
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_error() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![
+            list_page("a", true, Some("t1")),
+            ReplayResponse::new(500, "<Error><Code>InternalError</Code></Error>"),
+        ]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.prefetch = 3;
+
+        assert_eq!(listed_keys(&find).await, vec!["a"]);
+        assert!(find
+            .listing_error()
+            .unwrap()
+            .to_string()
+            .starts_with("Listing stopped at s3://test/: "));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_print_fetches_owner() -> Result<(), Error> {
+        let list_result = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><KeyCount>0</KeyCount></ListBucketResult>"#;
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, list_result),
+            ReplayResponse::new(200, list_result),
+        ]);
+
+        let find = test_find(client.clone(), Cmd::Print(AdvancedPrint::default()));
+        Box::pin(find.to_stream().stream()).next().await;
+        let find = test_find(client, Cmd::Ls(FastPrint::default()));
+        Box::pin(find.to_stream().stream()).next().await;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].uri.contains("fetch-owner=true"));
+        assert!(!requests[1].uri.contains("fetch-owner=true"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_version_object() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse {
+            headers: vec![
+                ("Content-Length".to_owned(), "11".to_owned()),
+                ("ETag".to_owned(), "\"etag\"".to_owned()),
+            ],
+            ..ReplayResponse::new(200, "")
+        }]);
+        let mut find = test_find(client, Cmd::default());
+        find.path = "s3://test/dir/key".parse()?;
+
+        let object = find.exact_object(Some("v1")).await?.unwrap();
+
+        assert_eq!(object.key.as_deref(), Some("dir/key"));
+        assert_eq!(object.size, Some(11));
+        assert_eq!(object.e_tag.as_deref(), Some("\"etag\""));
+        let requests = http_client.requests();
+        assert_eq!(requests[0].method, "HEAD");
+        assert!(requests[0].uri.contains("versionId=v1"));
+
+        let (client, _) = replay_client(vec![ReplayResponse::new(404, "")]);
+        let mut find = test_find(client, Cmd::default());
+        find.path = "s3://test/dir/key".parse()?;
+        assert!(find.exact_object(Some("v2")).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exact_object_shortcut() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let head = ReplayResponse {
+            headers: vec![("Content-Length".to_owned(), "5".to_owned())],
+            ..ReplayResponse::new(200, "")
+        };
+        let (client, http_client) = replay_client(vec![
+            head.clone(),
+            ReplayResponse::new(200, "hello"),
+            head,
+            ReplayResponse::new(404, ""),
+        ]);
+        // a download without --version-id
+        let cmd = Cmd::Download(Download {
+            force: false,
+            destination: dir.path().display().to_string(),
+            range_bytes: None,
+            range: None,
+            verify_size_with_head: false,
+            version_id: None,
+        });
+        let mut find = test_find(client, cmd.clone());
+        find.stats = true;
+
+        // a prefix path is listed, without a HEAD
+        find.path = "s3://test/dir/".parse()?;
+        assert!(!cmd.single_object(&find.path));
+        assert!(find.exact_object(None).await?.is_none());
+        assert!(http_client.requests().is_empty());
+
+        // an existing key runs the command on its HEAD, nothing is listed
+        find.path = "s3://test/dir/key".parse()?;
+        assert!(cmd.single_object(&find.path));
+        let filters = FilterList(Vec::new());
+        let object = find.exact_object(None).await?.unwrap();
+        let stats = find.exec_exact(&filters, object).await.unwrap();
+        assert_eq!(stats.total_files, 1);
+        assert_eq!(stats.total_space, 5);
+        assert_eq!(std::fs::read(dir.path().join("dir/key"))?, b"hello");
+
+        // the key goes through the filters like a listed key
+        let opts = FindOpt::from_iter_safe(["s3find", "s3://test/dir/key", "--size", "+1k"])?;
+        let filters = FilterList::from_opts(&opts)?;
+        let object = find.exact_object(None).await?.unwrap();
+        assert_eq!(
+            find.exec_exact(&filters, object).await.unwrap().total_files,
+            0
+        );
+        assert_eq!(find.counters.scanned.load(Ordering::Relaxed), 2);
+        assert_eq!(find.counters.matched.load(Ordering::Relaxed), 1);
+
+        // a missing key goes back to the listing
+        assert!(find.exact_object(None).await?.is_none());
+
+        let requests = http_client.requests();
+        let methods: Vec<_> = requests.iter().map(|x| x.method.as_str()).collect();
+        assert_eq!(methods, vec!["HEAD", "GET", "HEAD", "HEAD"]);
+        assert!(requests[0].path().ends_with("/dir/key"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_stats() {
+        let counters = RunCounters::default();
+        for _ in 0..2 {
+            counters.add(&CommandStats {
+                deleted: 6000,
+                reclaimed_bytes: 3 << 30,
+                ..Default::default()
+            });
+        }
+
+        let stats = DeleteStats::new(&counters);
+        assert_eq!(
+            stats.progress_line(),
+            "deleted 12000 objects, ~6 GiB so far"
+        );
+        let out = stats.to_string();
+        assert!(out.contains("Objects deleted: 12000"));
+        assert!(out.contains("Bytes deleted:   6 GiB"));
+        assert!(out.contains("Objects failed:  0"));
+    }
+
+    #[test]
+    fn test_download_stats() {
+        let counters = RunCounters::default();
+        counters.add(&CommandStats {
+            processed: 1,
+            skipped: 1,
+            downloaded_bytes: 4096,
+            ..Default::default()
+        });
+
+        let stats = DownloadStats::new(&counters, Duration::from_secs(2));
+        assert_eq!(
+            stats,
+            DownloadStats {
+                downloaded: 1,
+                skipped: 1,
+                failed: 0,
+                size_corrected: 0,
+                bytes: 4096,
+                elapsed: Duration::from_secs(2),
+            }
+        );
+        assert_eq!(stats.throughput(), 2048);
+
+        let out = stats.to_string();
+        assert!(out.contains("Files skipped:      1"));
+        assert!(out.contains("Bytes transferred:  4 KiB"));
+        assert!(out.contains("Throughput:         2 KiB/s"));
+    }
+
+    #[test]
+    fn test_find_stat() {
+        let object =
+            |key: &str, size: Option<i64>| Object::builder().key(key).set_size(size).build();
+
+        let stats = FindStat::default()
+            + &[
+                object("a", Some(1)),
+                object("b", Some(2)),
+                object("c", Some(3)),
+            ][..];
+        assert_eq!(stats.total_space, 6);
+        assert_eq!(stats.average_size(), 2);
+        assert_eq!((stats.max_key.as_str(), stats.max_size), ("c", Some(3)));
+        assert_eq!((stats.min_key.as_str(), stats.min_size), ("a", Some(1)));
+        assert!(stats.to_string().contains("Smallest file size: 1 B"));
+        assert_eq!(FindStat::default().average_size(), 0);
+    }
+
+    #[test]
+    fn test_find_stat_adversarial_sizes() {
+        let object =
+            |key: &str, size: Option<i64>| Object::builder().key(key).set_size(size).build();
+
+        let stats = FindStat::default()
+            + &[
+                object("negative", Some(-5)),
+                object("huge", Some(i64::MAX)),
+                object("small", Some(10)),
+            ][..];
+        assert_eq!(stats.negative_sizes, 1);
+        assert_eq!(stats.total_space, i64::MAX as u64 + 10);
+        assert_eq!(stats.min_key, "small");
+        assert_eq!(stats.average_size(), (i64::MAX as u64 + 10) / 2);
+
+        // the total saturates instead of wrapping
+        let stats = stats + &[object("huger", Some(i64::MAX)), object("none", None)][..];
+        assert_eq!(stats.total_files, 5);
+        assert_eq!(stats.total_space, u64::MAX);
+        assert_eq!(stats.max_key, "huger");
+        assert_eq!((stats.min_key.as_str(), stats.min_size), ("none", Some(0)));
+        assert_eq!(stats.average_size(), u64::MAX / 4);
+
+        let out = stats.to_string();
+        assert!(out.contains("Total space:        16 EiB"));
+        assert!(out.contains("Negative sizes:     1"));
+        assert!(!FindStat::default().to_string().contains("Negative sizes"));
+
+        let only_negative = FindStat::default() + &[object("negative", Some(-1))][..];
+        assert_eq!(only_negative.average_size(), 0);
+        assert_eq!(only_negative.max_size, None);
+    }
+
+    #[test]
+    fn test_size_histogram() {
+        let mut sizes = SizeHistogram::new(&"1k,1M".parse::<SizeBuckets>().unwrap().0);
+        for size in [
+            Some(0),
+            Some(1023),
+            Some(1024),
+            Some(1024 * 1024),
+            Some(-1),
+            None,
+        ] {
+            sizes.add(size);
+        }
+
+        // a size equal to a boundary is counted above it
+        let objects: Vec<_> = sizes.buckets.iter().map(|x| x.objects).collect();
+        assert_eq!(objects, vec![2, 1, 1]);
+        assert_eq!(sizes.buckets[1].bytes, 1024);
+        assert_eq!(sizes.unknown, 2);
+        let labels: Vec<_> = (0..3).map(|x| sizes.label(x)).collect();
+        assert_eq!(labels, vec!["<1k", "1k-1M", ">=1M"]);
+
+        let out = sizes.to_string();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "Size buckets:");
+        assert_eq!(
+            lines[1],
+            "  <1k                  2  33.33%       1023 B   0.10%"
+        );
+        assert_eq!(
+            lines[3],
+            "  >=1M                 1  16.67%        1 MiB  99.81%"
+        );
+        assert_eq!(lines[4], "  unknown              2  33.33%");
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_find_stat_size_buckets() {
+        let stats = FindStat::default()
+            + &[
+                Object::builder().key("a").size(128 * 1024).build(),
+                Object::builder().key("b").size(2 * 1024_i64.pow(3)).build(),
+            ][..];
+        let out = stats.to_string();
+        assert!(out.contains("  128k-1M              1  50.00%"));
+        assert!(out.contains("  >=1G                 1  50.00%"));
+        assert!(!out.contains("unknown"));
+    }
+
+    #[test]
+    fn test_sample_estimate() {
+        let stats = FindStat {
+            total_files: 25,
+            total_space: 25 * 1024,
+            ..Default::default()
+        };
+        let estimate = SampleEstimate::new(&stats, 0.01);
+
+        assert_eq!(estimate.estimated_files(), 2500);
+        assert_eq!(estimate.estimated_space(), 2_560_000);
+        let out = estimate.to_string();
+        assert!(out.contains("Sample rate:        0.01"));
+        assert!(out.contains("Sampled files:      25"));
+        assert!(out.contains("Sampled space:      25 KiB"));
+        assert!(out.contains("Estimated files:    ~2500"));
+        assert!(out.contains("Estimated space:    ~2.44 MiB"));
+    }
+
+    // a single test, as the environment is shared by the test threads
+    #[tokio::test]
+    async fn test_region_resolution() {
+        let find = |region: Option<&'static str>| {
+            Find::new(
+                AWSPair {
+                    access: Some("AKIAEXAMPLE".to_owned()),
+                    secret: Some("secret".to_owned()),
+                    command: None,
+                    no_sign_request: false,
+                },
+                region.map(Region::from_static),
+                None,
+                "s3://test/".parse().unwrap(),
+                1000,
+                false,
+                None,
+            )
+        };
+        std::env::set_var("AWS_CONFIG_FILE", "/nonexistent/config");
+        std::env::set_var("AWS_EC2_METADATA_DISABLED", "true");
+        std::env::remove_var("AWS_PROFILE");
+        std::env::remove_var("AWS_DEFAULT_REGION");
+
+        std::env::set_var("AWS_REGION", "eu-west-1");
+        let explicit = find(Some("eu-central-1")).await.unwrap();
+        assert_eq!(explicit.path.region.as_ref(), "eu-central-1");
+
+        let fallback = find(None).await.unwrap();
+        assert_eq!(fallback.path.region.as_ref(), "eu-west-1");
+
+        std::env::remove_var("AWS_REGION");
+        let missing = find(None).await;
+        assert!(matches!(missing, Err(FunctionError::RegionNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_no_sign_request() -> Result<(), Error> {
+        let list_result = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><KeyCount>0</KeyCount></ListBucketResult>"#;
+
+        for no_sign_request in [true, false] {
+            let (_, http_client) = replay_client(vec![ReplayResponse::new(200, list_result)]);
+            let credentials = AWSPair {
+                access: Some("AKIAEXAMPLE".to_owned()),
+                secret: Some("secret".to_owned()),
+                command: None,
+                no_sign_request,
+            };
+            let shared_config = config_loader(credentials, Some(Region::from_static("us-east-1")))
+                .await
+                .http_client(http_client.clone())
+                .load()
+                .await;
+            let mut find = test_find(
+                s3_client(&shared_config, Default::default()),
+                Cmd::default(),
+            );
+            find.path = "s3://test/".parse()?;
+            find.credentials_provider = shared_config.credentials_provider();
+
+            find.check_credentials().await?;
+            Box::pin(find.to_stream().stream()).next().await;
+
+            let requests = http_client.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(
+                requests[0].header("authorization").is_none(),
+                no_sign_request
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_credentials_not_found() {
+        let (client, _) = replay_client(vec![]);
+        let mut find = test_find(client, Cmd::default());
+        find.credentials_provider = Some(SharedCredentialsProvider::new(
+            aws_credential_types::credential_fn::provide_credentials_fn(|| async {
+                Err(CredentialsError::not_loaded("no providers in chain"))
+            }),
+        ));
+
+        let error = find.check_credentials().await.unwrap_err();
+        assert!(error.to_string().contains("--no-sign-request"));
+    }
+
+    #[test]
+    fn test_request_price() {
+        let uri = "https://test.s3.us-east-1.amazonaws.com";
+        let price =
+            |method, path: &str| request_price(RequestApi::of(method, &format!("{}{}", uri, path)));
+        assert_eq!(price("GET", "/?list-type=2&prefix="), CLASS_A_REQUEST_PRICE);
+        assert_eq!(
+            price("GET", "/?encoding-type=url&max-keys=1000&prefix="),
+            CLASS_A_REQUEST_PRICE
+        );
+        assert_eq!(price("PUT", "/key?tagging"), CLASS_A_REQUEST_PRICE);
+        assert_eq!(price("GET", "/key?tagging"), CLASS_B_REQUEST_PRICE);
+        assert_eq!(price("HEAD", "/key"), CLASS_B_REQUEST_PRICE);
+        assert_eq!(price("POST", "/?delete"), 0);
+        assert_eq!(price("DELETE", "/key"), 0);
+
+        assert_eq!(
+            RequestApi::of("GET", &format!("{}/?uploads", uri)),
+            RequestApi::List
+        );
+        assert_eq!(
+            RequestApi::of("POST", &format!("{}/?delete", uri)),
+            RequestApi::Delete
+        );
+        assert_eq!(
+            RequestApi::of("PUT", &format!("{}/key", uri)),
+            RequestApi::Put
+        );
+        assert_eq!(
+            RequestApi::of("HEAD", &format!("{}/key", uri)),
+            RequestApi::Get
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_requests() -> Result<(), Error> {
+        let page = |key: &str, token: Option<&str>| {
+            let next = token
+                .map(|x| format!("<NextContinuationToken>{}</NextContinuationToken>", x))
+                .unwrap_or_default();
+            ReplayResponse::new(
+                200,
+                &format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><Contents><Key>{}</Key></Contents>{}</ListBucketResult>"#,
+                    key, next
+                ),
+            )
+        };
+        let http_client = ReplayClient::new(vec![
+            page("a", Some("t1")),
+            page("b", Some("t2")),
+            page("c", None),
+        ]);
+        let credentials = AWSPair {
+            access: Some("AKIAEXAMPLE".to_owned()),
+            secret: Some("secret".to_owned()),
+            command: None,
+            no_sign_request: false,
+        };
+        let shared_config = config_loader(credentials, Some(Region::from_static("us-east-1")))
+            .await
+            .http_client(http_client.clone())
+            .load()
+            .await;
+        let counters = Arc::new(RunCounters::default());
+        let mut find = test_find(
+            s3_client(&shared_config, counters.clone()),
+            Cmd::Nothing(DoNothing {}),
+        );
+        find.counters = counters;
+        find.budget = RequestBudget {
+            max_requests: Some(2),
+            ..Default::default()
+        };
+
+        list_filter_execute(
+            find.to_stream().stream(),
+            None,
+            None,
+            |_| {
+                find.counters.scan();
+                async { true }
+            },
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+
+        assert_eq!(http_client.requests().len(), 2);
+        assert_eq!(find.counters.scanned.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            find.budget_error().unwrap().to_string(),
+            "Request budget exhausted after 2 requests, estimated cost $0.0000"
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_duration() -> Result<(), Error> {
+        // a page of a full batch, the batch runs before the next page
+        let keys: Vec<String> = (0..1000).map(|x| format!("k{:04}", x)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let (client, http_client) = replay_client(vec![
+            keys_page(&keys, true, Some("t1")),
+            keys_page(&keys, true, Some("t2")),
+            keys_page(&["e"], false, None),
+        ]);
+        let mut find = test_find(client, Cmd::Nothing(DoNothing {}));
+        find.budget = RequestBudget {
+            max_duration: Some(Duration::from_secs(3000)),
+            deadline: Some(Instant::now() + Duration::from_secs(3000)),
+            ..Default::default()
+        };
+
+        let mut batches = 0;
+        let stats = list_filter_execute(
+            find.to_stream().stream(),
+            None,
+            default_stats(true),
+            |_| {
+                find.counters.scan();
+                async { true }
+            },
+            &mut |acc, x| {
+                batches += 1;
+                let find = &find;
+                async move {
+                    let acc = find.exec(acc, x).await;
+                    // the period ends while the first batch runs
+                    tokio::time::advance(Duration::from_secs(3001)).await;
+                    acc
+                }
+            },
+        )
+        .await;
+
+        // the batch in flight is finished, no page is listed after it
+        assert_eq!(batches, 1);
+        assert_eq!(http_client.requests().len(), 1);
+        assert_eq!(find.counters.scanned.load(Ordering::Relaxed), 1000);
+        assert!(find.budget_error().is_none());
+        assert_eq!(
+            find.stop_error().unwrap().to_string(),
+            "Time limit reached, --max-duration 3000s"
+        );
+        assert!(find
+            .stat_summary(stats.as_ref().unwrap())
+            .starts_with("Partial summary, Time limit reached"));
+        assert_eq!(find.exit_code(), Some(TIME_LIMIT_EXIT_CODE));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_listing_heartbeat() -> Result<(), Error> {
+        let page = |keys: &[&str], token: Option<&str>| {
+            let contents: String = keys
+                .iter()
+                .map(|x| format!("<Contents><Key>{}</Key></Contents>", x))
+                .collect();
+            let next = token
+                .map(|x| format!("<NextContinuationToken>{}</NextContinuationToken>", x))
+                .unwrap_or_default();
+            ReplayResponse::new(
+                200,
+                &format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name>{}{}</ListBucketResult>"#,
+                    contents, next
+                ),
+            )
+        };
+        let (client, _) = replay_client(vec![
+            page(&["a1", "a2"], Some("t1")),
+            page(&["b1", "b2"], Some("t2")),
+            page(&["c1"], None),
+        ]);
+        let find = test_find(client, Cmd::Nothing(DoNothing {}));
+
+        let reports = Mutex::new(Vec::new());
+        let heartbeat = Heartbeat::new(Duration::from_secs(0), |x: &ListingProgress| {
+            reports.lock().unwrap().push(x.clone())
+        });
+        let heartbeat = &heartbeat;
+
+        list_filter_execute(
+            find.to_stream()
+                .stream()
+                .inspect(|page| heartbeat.page(page)),
+            None,
+            None,
+            |x| {
+                let matched = x.key.as_deref() != Some("b1");
+                async move { heartbeat.matched(matched) }
+            },
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+
+        let reports = reports.lock().unwrap();
+        let counts: Vec<_> = reports.iter().map(|x| (x.pages, x.scanned)).collect();
+        assert_eq!(counts, vec![(1, 2), (2, 4), (3, 5)]);
+        assert_eq!(reports[2].matched, 3);
+        assert_eq!(reports[2].key, "c1");
+        assert!(heartbeat.take_reported());
+        assert!(!heartbeat.take_reported());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_empty_note() -> Result<(), Error> {
+        let list_result = |contents: &str| {
+            ReplayResponse::new(
+                200,
+                &format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name>{}</ListBucketResult>"#,
+                    contents
+                ),
+            )
+        };
+        let (client, _) = replay_client(vec![
+            list_result(""),
+            list_result(
+                "<Contents><Key>a.log</Key></Contents><Contents><Key>b.log</Key></Contents>",
+            ),
+        ]);
+        let name = vec![Pattern::new("*.csv")?];
+        let filters = FilterList::new(&name, &[], &[], &[], &[], &None, &None);
+
+        let mut notes = Vec::new();
+        for _ in 0..2 {
+            let find = test_find(client.clone(), Cmd::Nothing(DoNothing {}));
+            list_filter_execute(
+                find.to_stream().stream(),
+                None,
+                None,
+                |x| {
+                    find.counters.scan();
+                    let matched = filters.test_match(x.object.clone());
+                    let counters = find.counters.clone();
+                    async move { counters.filtered(matched.await) }
+                },
+                &mut |acc, x| find.exec(acc, x),
+            )
+            .await;
+            notes.push(find.empty_note());
+        }
+
+        assert_eq!(notes[0].as_deref(), Some("prefix contains no objects"));
+        assert_eq!(
+            notes[1].as_deref(),
+            Some("scanned 2 objects, 0 matched filters")
+        );
+
+        let find = test_find(replay_client(vec![]).0, Cmd::default());
+        find.counters.scan();
+        find.counters.filtered(true);
+        assert_eq!(find.empty_note(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_notify() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>a</Key></Deleted><Deleted><Key>b</Key></Deleted></DeleteResult>"#,
+        )]);
+        let (url, bodies) = crate::replay::webhook_server(vec![]);
+        let mut find = test_find(
+            client,
+            Cmd::Delete(MultipleDelete {
+                if_unmodified: false,
+                quiet_versioning_warning: true,
+                ..Default::default()
+            }),
+        );
+        find.notifiers = vec![Notifier::Webhook(Box::new(WebhookNotifier::new(&url)?))];
+
+        find.exec(
+            None,
+            vec![
+                Object::builder().key("a").build().into(),
+                Object::builder().key("b").build().into(),
+            ],
+        )
+        .await;
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        let event: serde_json::Value = serde_json::from_str(&bodies[0])?;
+        assert_eq!(event["version"], 1);
+        assert_eq!(event["action"], "delete");
+        assert_eq!(event["bucket"], "test");
+        assert_eq!(event["keys"], serde_json::json!(["a", "b"]));
+        assert_eq!(find.counters.error_count.load(Ordering::Relaxed), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_command_error_stops_the_run() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let (client, _) = replay_client(vec![ReplayResponse::new(202, "")]);
+        // the ledger is a directory, appending the batch to it fails
+        let opts = FindOpt::from_iter_safe([
+            "s3find",
+            "s3://test/",
+            "restore",
+            "--ledger",
+            &dir.path().display().to_string(),
+        ])?;
+        let find = test_find(client, opts.cmd.unwrap());
+
+        let stats = find
+            .exec(
+                default_stats(true),
+                vec![Object::builder().key("a").build().into()],
+            )
+            .await;
+        // the next batch is not handed to the command
+        let stats = find
+            .exec(stats, vec![Object::builder().key("b").build().into()])
+            .await;
+
+        assert_eq!(find.counters.error_count.load(Ordering::Relaxed), 1);
+        let errors = find.counters.errors.lock().unwrap();
+        assert!(errors[0]
+            .starts_with("command failed on a batch of 1 keys: Cannot use the restore ledger"));
+        assert_eq!(stats.as_ref().unwrap().total_files, 1);
+        assert!(find
+            .stat_summary(stats.as_ref().unwrap())
+            .starts_with("Partial summary, Run stopped, command failed on a batch of 1 keys"));
+        assert_eq!(find.exit_code(), Some(COMMAND_FAILED_EXIT_CODE));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reevaluate_mtime_at_action() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>b</Key></Deleted></DeleteResult>"#,
+        )]);
+        let opts = FindOpt::from_iter_safe([
+            "s3find",
+            "s3://test/",
+            "--mtime",
+            "-1h",
+            "--reevaluate-mtime-at-action",
+            "delete",
+        ])?;
+        let started = 1_700_000_000;
+        let now = Arc::new(AtomicU64::new(started));
+        let clock_now = now.clone();
+        let clock: Clock = Arc::new(move || {
+            SystemTime::UNIX_EPOCH + Duration::from_secs(clock_now.load(Ordering::SeqCst))
+        });
+        let mut find = test_find(client, opts.cmd.clone().unwrap());
+        find.mtime_recheck = Some(MtimeRecheck::with_clock(&opts, clock));
+
+        let objects = vec![
+            Object::builder()
+                .key("a")
+                .last_modified(DateTime::from_secs(started as i64 - 3599))
+                .build(),
+            Object::builder()
+                .key("b")
+                .last_modified(DateTime::from_secs(started as i64 - 600))
+                .build(),
+        ];
+        let filters = FilterList(vec![CountedFilter::new(AtReference {
+            filter: FindTime::Upper(3600),
+            reference: SystemTime::UNIX_EPOCH + Duration::from_secs(started),
+        })]);
+        assert!(objects.iter().all(|x| filters.matches(x)));
+
+        // a crosses the -1h boundary between the listing and the delete
+        now.store(started + 2, Ordering::SeqCst);
+        let objects: Vec<ListedObject> = objects.into_iter().map(ListedObject::from).collect();
+        let stats = find.exec(Some(FindStat::default()), objects.clone()).await;
+
+        assert_eq!(stats.unwrap().total_files, 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        let body = String::from_utf8(requests[0].body.clone())?;
+        assert!(body.contains("<Key>b</Key>"));
+        assert!(!body.contains("<Key>a</Key>"));
+        assert_eq!(find.counters.mtime_skipped.load(Ordering::Relaxed), 1);
+
+        // a batch with no key left sends no request
+        now.store(started + 3600, Ordering::SeqCst);
+        find.exec(None, objects).await;
+        assert_eq!(http_client.requests().len(), 1);
+        assert_eq!(
+            find.mtime_recheck_note().unwrap(),
+            "skipped 3 keys which no longer matched the mtime filters at action time"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtime_reference_note() {
+        let mut opts = FindOpt::from_iter_safe(["s3find", "s3://test/", "--mtime", "+1d"]).unwrap();
+        opts.reference_time = ReferenceTime(SystemTime::UNIX_EPOCH + Duration::from_secs(86400));
+        assert_eq!(
+            mtime_reference_note(&opts).unwrap(),
+            "note: the mtime filters are evaluated against 1970-01-02T00:00:00Z"
+        );
+        opts.mtime.clear();
+        assert_eq!(mtime_reference_note(&opts), None);
+    }
+
+    #[tokio::test]
+    async fn test_url_encoded_keys() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><EncodingType>url</EncodingType>
+<Contents><Key>logs%01/a+b.txt</Key><Size>4</Size></Contents></ListBucketResult>"#,
+            ),
+            ReplayResponse::new(200, "body"),
+        ]);
+        let find = test_find(
+            client,
+            Cmd::Download(Download {
+                force: false,
+                destination: dir.path().display().to_string(),
+                range_bytes: None,
+                range: None,
+                verify_size_with_head: false,
+                version_id: None,
+            }),
+        );
+
+        let page = Box::pin(find.to_stream().stream()).next().await.unwrap();
+        assert_eq!(page[0].key.as_deref(), Some("logs\u{1}/a b.txt"));
+
+        let mut out = Vec::new();
+        FastPrint::default().print_object(&mut out, "test", &page[0], None)?;
+        assert_eq!(String::from_utf8(out)?, "s3://test/logs\\u{1}/a b.txt\n");
+
+        find.exec(None, page).await;
+
+        let requests = http_client.requests();
+        assert!(requests[0].uri.contains("encoding-type=url"));
+        assert_eq!(requests[1].method, "GET");
+        assert_eq!(
+            requests[1].path(),
+            "https://test.s3.us-east-1.amazonaws.com/logs%01/a%20b.txt"
+        );
+        Ok(())
     }
 }
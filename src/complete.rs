@@ -0,0 +1,133 @@
+//! Remote candidates for the `__complete-prefix` helper used by the shell
+//! completion scripts.
+
+use std::time::Duration;
+
+use anyhow::Error;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::timeout::TimeoutConfig;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::Client;
+
+pub const COMPLETE_PREFIX_COMMAND: &str = "__complete-prefix";
+pub const MAX_CANDIDATES: usize = 100;
+
+const COMPLETION_TIMEOUT: Duration = Duration::from_secs(3);
+const FALLBACK_REGION: &str = "us-east-1";
+
+/// Splits a partially typed s3://bucket/prefix into bucket and key prefix
+pub fn split_partial(partial: &str) -> Option<(&str, &str)> {
+    let rest = partial.strip_prefix("s3://")?;
+    match rest.split_once('/') {
+        Some((bucket, prefix)) if !bucket.is_empty() => Some((bucket, prefix)),
+        _ => None,
+    }
+}
+
+/// Client built from the environment and the shared profile, with a short
+/// timeout so a slow network never blocks the shell
+pub async fn completion_client() -> Client {
+    let region = RegionProviderChain::default_provider().or_else(FALLBACK_REGION);
+    let config = aws_config::defaults(BehaviorVersion::v2024_03_28())
+        .region(region)
+        .timeout_config(
+            TimeoutConfig::builder()
+                .operation_timeout(COMPLETION_TIMEOUT)
+                .build(),
+        )
+        .load()
+        .await;
+    Client::new(&config)
+}
+
+/// Candidates for a partial path: common prefixes end with "/", keys are
+/// returned as is. A partial without a bucket separator completes nothing.
+pub async fn complete_prefix(client: &Client, partial: &str) -> Result<Vec<String>, Error> {
+    let (bucket, prefix) = match split_partial(partial) {
+        Some(split) => split,
+        None => return Ok(Vec::new()),
+    };
+
+    let output = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .delimiter("/")
+        .max_keys(MAX_CANDIDATES as i32)
+        .send()
+        .await?;
+
+    let prefixes = output
+        .common_prefixes()
+        .iter()
+        .filter_map(|common| common.prefix());
+    let keys = output.contents().iter().filter_map(|object| object.key());
+
+    Ok(prefixes
+        .chain(keys)
+        .take(MAX_CANDIDATES)
+        .map(|key| format!("s3://{}/{}", bucket, key))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::*;
+
+    #[test]
+    fn test_split_partial() {
+        assert_eq!(split_partial("s3://bucket/"), Some(("bucket", "")));
+        assert_eq!(
+            split_partial("s3://bucket/logs/2024"),
+            Some(("bucket", "logs/2024"))
+        );
+        assert_eq!(split_partial("s3://bucket"), None);
+        assert_eq!(split_partial("s3:///key"), None);
+        assert_eq!(split_partial("bucket/key"), None);
+    }
+
+    #[tokio::test]
+    async fn test_complete_prefix() -> Result<(), Error> {
+        let listing = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    <Name>bucket</Name>
+    <Prefix>logs/</Prefix>
+    <Delimiter>/</Delimiter>
+    <KeyCount>3</KeyCount>
+    <MaxKeys>100</MaxKeys>
+    <IsTruncated>false</IsTruncated>
+    <Contents>
+        <Key>logs/index.txt</Key>
+        <Size>12</Size>
+    </Contents>
+    <CommonPrefixes>
+        <Prefix>logs/2023/</Prefix>
+    </CommonPrefixes>
+    <CommonPrefixes>
+        <Prefix>logs/2024/</Prefix>
+    </CommonPrefixes>
+</ListBucketResult>"#;
+        let (client, replay) = replay_client(vec![ReplayResponse::new(200, listing)]);
+
+        let candidates = complete_prefix(&client, "s3://bucket/logs/").await?;
+        assert_eq!(
+            candidates,
+            vec![
+                "s3://bucket/logs/2023/",
+                "s3://bucket/logs/2024/",
+                "s3://bucket/logs/index.txt",
+            ]
+        );
+
+        let requests = replay.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].uri.contains("delimiter=%2F"));
+        assert!(requests[0].uri.contains("prefix=logs%2F"));
+        assert!(requests[0].uri.contains("max-keys=100"));
+
+        assert!(complete_prefix(&client, "s3://bucket").await?.is_empty());
+        assert_eq!(replay.requests().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+//! Credentials vended by an external command, which prints the JSON document
+//! of the credential_process protocol:
+//!
+//! ```json
+//! {"Version": 1, "AccessKeyId": "..", "SecretAccessKey": "..",
+//!  "SessionToken": "..", "Expiration": "2024-01-01T00:00:00Z"}
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use aws_credential_types::provider::{error::CredentialsError, future};
+use aws_sdk_s3::config::{Credentials, ProvideCredentials};
+use aws_smithy_types::date_time::{DateTime, Format};
+use serde_json::Value;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::error::CredentialsCommandError;
+
+/// Credentials are refreshed this long before they expire
+pub const REFRESH_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+pub type Clock = Arc<dyn Fn() -> SystemTime + Send + Sync>;
+
+pub struct CommandCredentialsProvider {
+    command: String,
+    clock: Clock,
+    cached: Mutex<Option<Credentials>>,
+}
+
+impl CommandCredentialsProvider {
+    pub fn new(command: &str) -> Self {
+        Self::with_clock(command, Arc::new(SystemTime::now))
+    }
+
+    pub fn with_clock(command: &str, clock: Clock) -> Self {
+        CommandCredentialsProvider {
+            command: command.to_owned(),
+            clock,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Cached credentials, the command is re-invoked when they near
+    /// expiration; the requests waiting for a refresh share its result
+    pub async fn credentials(&self) -> Result<Credentials, CredentialsCommandError> {
+        let mut cached = self.cached.lock().await;
+
+        if let Some(credentials) = cached.as_ref() {
+            if !needs_refresh(credentials, (self.clock)()) {
+                return Ok(credentials.clone());
+            }
+        }
+
+        let credentials = run_credentials_command(&self.command).await?;
+        *cached = Some(credentials.clone());
+        Ok(credentials)
+    }
+}
+
+impl fmt::Debug for CommandCredentialsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CommandCredentialsProvider")
+            .field("command", &self.command)
+            .finish()
+    }
+}
+
+impl ProvideCredentials for CommandCredentialsProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            self.credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+pub fn needs_refresh(credentials: &Credentials, now: SystemTime) -> bool {
+    match credentials.expiry() {
+        Some(expiry) => expiry <= now + REFRESH_WINDOW,
+        None => false,
+    }
+}
+
+pub async fn run_credentials_command(
+    command: &str,
+) -> Result<Credentials, CredentialsCommandError> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .await
+        .map_err(|e| CredentialsCommandError::Spawn(command.to_owned(), e.to_string()))?;
+
+    if !output.status.success() {
+        return Err(CredentialsCommandError::Failed(
+            command.to_owned(),
+            output.status.to_string(),
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+
+    parse_credentials(&String::from_utf8_lossy(&output.stdout))
+}
+
+pub fn parse_credentials(output: &str) -> Result<Credentials, CredentialsCommandError> {
+    let document: Value =
+        serde_json::from_str(output).map_err(|e| CredentialsCommandError::Parse(e.to_string()))?;
+
+    match document["Version"].as_i64() {
+        Some(1) => {}
+        Some(version) => return Err(CredentialsCommandError::Version(version)),
+        None => return Err(missing_field("Version")),
+    }
+
+    let field = |name: &str| document[name].as_str().map(str::to_owned);
+
+    let access_key = field("AccessKeyId").ok_or_else(|| missing_field("AccessKeyId"))?;
+    let secret_key = field("SecretAccessKey").ok_or_else(|| missing_field("SecretAccessKey"))?;
+    let expiry = match field("Expiration") {
+        Some(expiration) => Some(
+            DateTime::from_str(&expiration, Format::DateTime)
+                .ok()
+                .and_then(|x| SystemTime::try_from(x).ok())
+                .ok_or_else(|| {
+                    CredentialsCommandError::Parse(format!("invalid Expiration {}", expiration))
+                })?,
+        ),
+        None => None,
+    };
+
+    Ok(Credentials::new(
+        access_key,
+        secret_key,
+        field("SessionToken"),
+        expiry,
+        "credentials-command",
+    ))
+}
+
+fn missing_field(name: &str) -> CredentialsCommandError {
+    CredentialsCommandError::Parse(format!("missing {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+    use std::fs;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::UNIX_EPOCH;
+    use tempfile::TempDir;
+
+    const OUTPUT: &str = r#"{
+  "Version": 1,
+  "AccessKeyId": "AKIAEXAMPLE",
+  "SecretAccessKey": "secret",
+  "SessionToken": "token",
+  "Expiration": "2023-11-14T22:13:20Z"
+}"#;
+
+    const EXPIRATION: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_parse_credentials() -> Result<(), Error> {
+        let credentials = parse_credentials(OUTPUT)?;
+
+        assert_eq!(credentials.access_key_id(), "AKIAEXAMPLE");
+        assert_eq!(credentials.secret_access_key(), "secret");
+        assert_eq!(credentials.session_token(), Some("token"));
+        assert_eq!(
+            credentials.expiry(),
+            Some(UNIX_EPOCH + Duration::from_secs(EXPIRATION))
+        );
+
+        let long_term = parse_credentials(
+            r#"{"Version": 1, "AccessKeyId": "AKIAEXAMPLE", "SecretAccessKey": "secret"}"#,
+        )?;
+        assert_eq!(long_term.session_token(), None);
+        assert_eq!(long_term.expiry(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_credentials_invalid() {
+        let error = |output: &str| parse_credentials(output).unwrap_err().to_string();
+
+        assert!(error("not json").starts_with("Invalid credentials command output"));
+        assert_eq!(
+            error(r#"{"Version": 2}"#),
+            "Unsupported credentials command output version 2"
+        );
+        assert!(error(r#"{"Version": 1, "AccessKeyId": "a"}"#).contains("SecretAccessKey"));
+        assert!(error(&OUTPUT.replace("2023-11-14T22:13:20Z", "soon")).contains("soon"));
+    }
+
+    #[test]
+    fn test_needs_refresh() -> Result<(), Error> {
+        let credentials = parse_credentials(OUTPUT)?;
+        let expiry = UNIX_EPOCH + Duration::from_secs(EXPIRATION);
+
+        assert!(!needs_refresh(
+            &credentials,
+            expiry - REFRESH_WINDOW - Duration::from_secs(1)
+        ));
+        assert!(needs_refresh(&credentials, expiry - REFRESH_WINDOW));
+        assert!(needs_refresh(&credentials, expiry));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_provider_refresh() -> Result<(), Error> {
+        let dir = TempDir::new()?;
+        let output = dir.path().join("output.json");
+        let calls = dir.path().join("calls");
+        fs::write(&output, OUTPUT)?;
+
+        let now = Arc::new(AtomicU64::new(EXPIRATION - 3600));
+        let clock_now = now.clone();
+        let clock: Clock =
+            Arc::new(move || UNIX_EPOCH + Duration::from_secs(clock_now.load(Ordering::SeqCst)));
+
+        let command = format!(
+            "echo call >> {} && cat {}",
+            calls.display(),
+            output.display()
+        );
+        let provider = CommandCredentialsProvider::with_clock(&command, clock);
+
+        provider.credentials().await?;
+        provider.provide_credentials().await?;
+        assert_eq!(fs::read_to_string(&calls)?.lines().count(), 1);
+
+        now.store(EXPIRATION - 60, Ordering::SeqCst);
+        provider.credentials().await?;
+        assert_eq!(fs::read_to_string(&calls)?.lines().count(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_command_failure() {
+        let error = run_credentials_command("echo 'token expired' >&2; exit 3")
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("failed"));
+        assert!(error.contains("token expired"));
+    }
+}
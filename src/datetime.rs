@@ -0,0 +1,99 @@
+//! Rendering of the modification time of print, --date-format and
+//! --timezone. A named zone is rendered by the local time of chrono, the
+//! zone is made the local one of the process with TZ when print starts.
+
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use chrono::{Local, SecondsFormat, TimeZone, Utc};
+
+use crate::arg::{DateFormat, Timezone};
+
+/// Makes a named zone the local zone of the process, the daylight saving
+/// time of the zone applies to every rendered time
+pub fn use_timezone(timezone: &Timezone) {
+    if let Timezone::Named(name) = timezone {
+        std::env::set_var("TZ", name);
+    }
+}
+
+/// The time in the format and the zone, the ISO format in UTC is the one
+/// print always had
+pub fn format_time(time: &DateTime, format: &DateFormat, timezone: &Timezone) -> String {
+    let utc = match Utc.timestamp_opt(time.secs(), time.subsec_nanos()).single() {
+        Some(utc) => utc,
+        None => return time.to_string(),
+    };
+
+    match (format, timezone) {
+        (DateFormat::Iso, Timezone::Utc) => time
+            .fmt(Format::DateTime)
+            .unwrap_or_else(|_| utc.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        (_, Timezone::Utc) => render(utc, format),
+        (_, Timezone::Local | Timezone::Named(_)) => render(utc.with_timezone(&Local), format),
+    }
+}
+
+fn render<Tz: TimeZone>(time: chrono::DateTime<Tz>, format: &DateFormat) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    match format {
+        DateFormat::Iso => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        DateFormat::Rfc2822 => time.to_rfc2822(),
+        DateFormat::Epoch => time.timestamp().to_string(),
+        DateFormat::Pattern(pattern) => time.format(pattern).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        let time = DateTime::from_secs(1_711_848_600);
+        let format = |x: &str| x.parse::<DateFormat>().unwrap();
+
+        assert_eq!(
+            format_time(&time, &DateFormat::Iso, &Timezone::Utc),
+            "2024-03-31T01:30:00Z"
+        );
+        assert_eq!(
+            format_time(&time, &format("epoch"), &Timezone::Utc),
+            "1711848600"
+        );
+        assert_eq!(
+            format_time(&time, &format("rfc2822"), &Timezone::Utc),
+            "Sun, 31 Mar 2024 01:30:00 +0000"
+        );
+        assert_eq!(
+            format_time(&time, &format("%d.%m.%Y %H:%M"), &Timezone::Utc),
+            "31.03.2024 01:30"
+        );
+    }
+
+    #[test]
+    fn test_format_time_across_dst() {
+        // Europe/Berlin moves from CET to CEST at 01:00 UTC on 2024-03-31
+        let timezone: Timezone = "Europe/Berlin".parse().unwrap();
+        use_timezone(&timezone);
+        let format = DateFormat::Pattern("%Y-%m-%d %H:%M %z".to_owned());
+
+        assert_eq!(
+            format_time(&DateTime::from_secs(1_711_846_800 - 60), &format, &timezone),
+            "2024-03-31 01:59 +0100"
+        );
+        assert_eq!(
+            format_time(&DateTime::from_secs(1_711_846_800), &format, &timezone),
+            "2024-03-31 03:00 +0200"
+        );
+        assert_eq!(
+            format_time(
+                &DateTime::from_secs(1_711_846_800),
+                &DateFormat::Iso,
+                &timezone
+            ),
+            "2024-03-31T03:00:00+02:00"
+        );
+    }
+}
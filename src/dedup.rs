@@ -0,0 +1,164 @@
+//! The --dedup stage between the filters and the batches: a matched key
+//! seen before in the run is skipped, so the commands never get it twice.
+//! The seen identifiers are kept exactly up to a limit, then in a bloom
+//! filter of a fixed size, where a distinct key may be taken for a seen one.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use aws_sdk_s3::types::Object;
+
+use crate::arg::DedupMode;
+
+/// The number of identifiers kept exactly
+pub const EXACT_LIMIT: usize = 1_000_000;
+
+/// The bits of the bloom filter, 16 MiB
+const BLOOM_BITS: u64 = 1 << 27;
+
+/// The bits set for an identifier, about 1% of false positives at 14
+/// million identifiers
+const BLOOM_HASHES: u64 = 7;
+
+/// Identifiers seen by the run
+#[derive(Debug)]
+enum Seen {
+    Exact(HashSet<String>),
+    Bloom(Vec<u64>),
+}
+
+#[derive(Debug)]
+pub struct Dedup {
+    mode: DedupMode,
+    exact_limit: usize,
+    seen: Mutex<Seen>,
+}
+
+impl Dedup {
+    /// The stage of the mode, none for --dedup none
+    pub fn new(mode: DedupMode) -> Option<Self> {
+        Self::with_limit(mode, EXACT_LIMIT)
+    }
+
+    pub fn with_limit(mode: DedupMode, exact_limit: usize) -> Option<Self> {
+        match mode {
+            DedupMode::None => None,
+            mode => Some(Dedup {
+                mode,
+                exact_limit,
+                seen: Mutex::new(Seen::Exact(HashSet::new())),
+            }),
+        }
+    }
+
+    /// The identifier of the object compared by the mode
+    pub fn identifier(&self, object: &Object) -> String {
+        let key = object.key.as_deref().unwrap_or_default();
+        match self.mode {
+            DedupMode::KeyVersion => format!(
+                "{}\n{}\n{}",
+                key,
+                object.e_tag.as_deref().unwrap_or_default(),
+                object
+                    .last_modified
+                    .map(|x| x.to_string())
+                    .unwrap_or_default()
+            ),
+            _ => key.to_owned(),
+        }
+    }
+
+    /// Records the identifier, false when it was seen before
+    pub fn first_seen(&self, identifier: String) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        match &mut *seen {
+            Seen::Exact(set) => {
+                let first = set.insert(identifier);
+                if set.len() > self.exact_limit {
+                    let mut bits = vec![0; (BLOOM_BITS / 64) as usize];
+                    for identifier in set.iter() {
+                        bloom_insert(&mut bits, identifier);
+                    }
+                    *seen = Seen::Bloom(bits);
+                }
+                first
+            }
+            Seen::Bloom(bits) => bloom_insert(bits, &identifier),
+        }
+    }
+
+    /// Whether the seen identifiers moved to the bloom filter, so a
+    /// distinct key may have been skipped
+    pub fn approximate(&self) -> bool {
+        matches!(*self.seen.lock().unwrap(), Seen::Bloom(_))
+    }
+}
+
+/// Sets the bits of the identifier, false when they were all set already
+fn bloom_insert(bits: &mut [u64], identifier: &str) -> bool {
+    let hash = |seed: u8| {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        identifier.hash(&mut hasher);
+        hasher.finish()
+    };
+    let (first, second) = (hash(0), hash(1) | 1);
+
+    let mut inserted = false;
+    for i in 0..BLOOM_HASHES {
+        let bit = first.wrapping_add(i.wrapping_mul(second)) % BLOOM_BITS;
+        let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+        inserted |= bits[word] & mask == 0;
+        bits[word] |= mask;
+    }
+    inserted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_smithy_types::DateTime;
+
+    #[test]
+    fn test_identifier() {
+        let object = |e_tag: &str| {
+            Object::builder()
+                .key("a")
+                .e_tag(e_tag)
+                .last_modified(DateTime::from_secs(1_700_000_000))
+                .build()
+        };
+
+        assert!(Dedup::new(DedupMode::None).is_none());
+        let key = Dedup::new(DedupMode::Key).unwrap();
+        assert_eq!(
+            key.identifier(&object("\"1\"")),
+            key.identifier(&object("\"2\""))
+        );
+        let version = Dedup::new(DedupMode::KeyVersion).unwrap();
+        assert_ne!(
+            version.identifier(&object("\"1\"")),
+            version.identifier(&object("\"2\""))
+        );
+    }
+
+    #[test]
+    fn test_first_seen() {
+        let dedup = Dedup::with_limit(DedupMode::Key, 3).unwrap();
+        assert!(dedup.first_seen("a".to_owned()));
+        assert!(dedup.first_seen("b".to_owned()));
+        assert!(!dedup.first_seen("a".to_owned()));
+        assert!(!dedup.approximate());
+
+        // above the limit the seen keys move to the bloom filter
+        assert!(dedup.first_seen("c".to_owned()));
+        assert!(dedup.first_seen("d".to_owned()));
+        assert!(dedup.approximate());
+        assert!(!dedup.first_seen("a".to_owned()));
+        assert!(!dedup.first_seen("d".to_owned()));
+        assert!(dedup.first_seen("e".to_owned()));
+        assert!(!dedup.first_seen("e".to_owned()));
+    }
+}
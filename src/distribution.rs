@@ -0,0 +1,278 @@
+//! report key-distribution: the keys and bytes per prefix of the first
+//! characters of the keys, the way S3 partitions a bucket, so a request
+//! heavy job can be sharded away from a hot prefix.
+
+use anyhow::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::types::Object;
+use aws_sdk_s3::Client;
+use humansize::*;
+
+use crate::arg::*;
+use crate::function::{CommandStats, RunCommand};
+
+/// The first `length` characters of the key, the whole key when shorter
+pub fn key_prefix(key: &str, length: usize) -> &str {
+    match key.char_indices().nth(length) {
+        Some((end, _)) => &key[..end],
+        None => key,
+    }
+}
+
+impl PrefixCounts {
+    fn add(&mut self, other: PrefixCounts) {
+        self.keys += other.keys;
+        self.bytes = self.bytes.saturating_add(other.bytes);
+    }
+}
+
+/// The share of the part in percent, 0 of an empty total
+fn share(part: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        total => part as f64 * 100.0 / total as f64,
+    }
+}
+
+impl KeyHistogram {
+    /// Counts a key under its prefix. A new prefix beyond `max_prefixes`
+    /// folds the lighter half of the prefixes into other first, so a heavy
+    /// prefix listed late still gets counted on its own.
+    pub fn add(&mut self, prefix: &str, size: u64, max_prefixes: usize) {
+        let counts = PrefixCounts {
+            keys: 1,
+            bytes: size,
+        };
+        if let Some(known) = self.prefixes.get_mut(prefix) {
+            known.add(counts);
+            return;
+        }
+
+        if self.prefixes.len() >= max_prefixes {
+            self.fold(max_prefixes / 2);
+        }
+        match max_prefixes {
+            0 => self.other.add(counts),
+            _ => {
+                self.prefixes.insert(prefix.to_owned(), counts);
+            }
+        }
+    }
+
+    /// Keeps the `keep` heaviest prefixes and counts the others under other
+    fn fold(&mut self, keep: usize) {
+        let mut prefixes: Vec<_> = self.prefixes.drain().collect();
+        prefixes.sort_by(|(a, x), (b, y)| y.keys.cmp(&x.keys).then_with(|| a.cmp(b)));
+        for (_, counts) in prefixes.drain(keep.min(prefixes.len())..) {
+            self.other.add(counts);
+        }
+        self.prefixes.extend(prefixes);
+    }
+
+    /// The keys and bytes of the whole histogram
+    pub fn total(&self) -> PrefixCounts {
+        let mut total = self.other;
+        for counts in self.prefixes.values() {
+            total.add(*counts);
+        }
+        total
+    }
+
+    /// The `top` prefixes with the most keys, the heaviest first
+    pub fn top(&self, top: usize) -> Vec<(&str, PrefixCounts)> {
+        let mut prefixes: Vec<_> = self
+            .prefixes
+            .iter()
+            .map(|(prefix, counts)| (prefix.as_str(), *counts))
+            .collect();
+        prefixes.sort_by(|(a, x), (b, y)| y.keys.cmp(&x.keys).then_with(|| a.cmp(b)));
+        prefixes.truncate(top);
+        prefixes
+    }
+}
+
+impl KeyDistribution {
+    /// The prefixes holding more than --hot-percent of the keys, with their
+    /// share of the keys
+    pub fn hot_prefixes<'a>(&self, histogram: &'a KeyHistogram) -> Vec<(&'a str, f64)> {
+        let total = histogram.total().keys;
+        histogram
+            .top(histogram.prefixes.len())
+            .into_iter()
+            .map(|(prefix, counts)| (prefix, share(counts.keys, total)))
+            .filter(|(_, share)| *share > self.hot_percent)
+            .collect()
+    }
+
+    /// The heaviest prefixes with their shares, the other keys on one line
+    /// and the hot prefixes flagged at the end
+    pub fn report(&self, histogram: &KeyHistogram) -> String {
+        let file_size = make_format(BINARY);
+        let total = histogram.total();
+        let line = |name: &str, counts: PrefixCounts| {
+            format!(
+                "{:width$} {:>10} keys {:>6.2}% {:>12} {:>6.2}%\n",
+                name,
+                counts.keys,
+                share(counts.keys, total.keys),
+                file_size(counts.bytes),
+                share(counts.bytes, total.bytes),
+                width = self.prefix_length.max(5)
+            )
+        };
+
+        let mut out = format!(
+            "Key distribution by the first {} characters: {} keys, {}\n",
+            self.prefix_length,
+            total.keys,
+            file_size(total.bytes)
+        );
+        let top = histogram.top(self.top);
+        let mut other = total;
+        for (prefix, counts) in &top {
+            out.push_str(&line(prefix, *counts));
+            other.keys -= counts.keys;
+            other.bytes -= counts.bytes;
+        }
+        if other.keys > 0 {
+            out.push_str(&line("other", other));
+        }
+        for (prefix, share) in self.hot_prefixes(histogram) {
+            out.push_str(&format!(
+                "hot prefix: {} holds {:.2}% of the keys, above {}%\n",
+                prefix, share, self.hot_percent
+            ));
+        }
+        out
+    }
+}
+
+#[async_trait]
+impl RunCommand for KeyDistribution {
+    async fn execute(
+        &self,
+        _c: &Client,
+        _p: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut histogram = self.histogram.0.lock().unwrap();
+        for object in list {
+            let key = object.key.as_deref().unwrap_or_default();
+            let size = object.size.unwrap_or_default().max(0) as u64;
+            histogram.add(key_prefix(key, self.prefix_length), size, self.max_prefixes);
+        }
+        Ok(CommandStats::processed(list.len()))
+    }
+
+    fn summary(&self) -> Option<String> {
+        let histogram = self.histogram.0.lock().unwrap();
+        Some(self.report(&histogram).trim_end().to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn distribution(args: &[&str]) -> KeyDistribution {
+        let mut argv = vec!["s3find", "s3://test/", "report", "key-distribution"];
+        argv.extend(args);
+        match FindOpt::from_iter_safe(&argv).unwrap().cmd {
+            Some(Cmd::Report(Report::KeyDistribution(cmd))) => cmd,
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+    }
+
+    fn histogram(keys: &[(&str, u64)], length: usize, max_prefixes: usize) -> KeyHistogram {
+        let mut histogram = KeyHistogram::default();
+        for (key, size) in keys {
+            histogram.add(key_prefix(key, length), *size, max_prefixes);
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_key_prefix() {
+        assert_eq!(key_prefix("logs/2024/a", 4), "logs");
+        assert_eq!(key_prefix("ab", 4), "ab");
+        assert_eq!(key_prefix("ключ/a", 3), "клю");
+        assert_eq!(key_prefix("a", 0), "");
+    }
+
+    #[test]
+    fn test_histogram_accumulation() {
+        let histogram = histogram(
+            &[("logs/a", 10), ("logs/b", 20), ("data/a", 5), ("img", 1)],
+            4,
+            100,
+        );
+
+        assert_eq!(histogram.prefixes.len(), 3);
+        assert_eq!(
+            histogram.prefixes["logs"],
+            PrefixCounts { keys: 2, bytes: 30 }
+        );
+        assert_eq!(histogram.prefixes["img"].keys, 1);
+        assert_eq!(histogram.total(), PrefixCounts { keys: 4, bytes: 36 });
+        assert_eq!(
+            histogram.top(2),
+            vec![
+                ("logs", PrefixCounts { keys: 2, bytes: 30 }),
+                ("data", PrefixCounts { keys: 1, bytes: 5 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_histogram_tail() {
+        // a heavy prefix listed after the cap survives the folding
+        let mut keys = vec![("a1", 1), ("a2", 1), ("a3", 1), ("a4", 1)];
+        keys.extend([("b1", 1); 5]);
+        keys.push(("c1", 1));
+        let histogram = histogram(&keys, 2, 4);
+
+        assert!(histogram.prefixes.len() <= 4);
+        assert_eq!(histogram.prefixes["b1"].keys, 5);
+        assert_eq!(histogram.other.keys, 2);
+        assert_eq!(histogram.total().keys, 10);
+
+        let histogram = self::histogram(&keys, 2, 0);
+        assert!(histogram.prefixes.is_empty());
+        assert_eq!(histogram.other.keys, 10);
+    }
+
+    #[test]
+    fn test_hot_prefixes() {
+        let cmd = distribution(&["--prefix-length", "2", "--top", "2", "--hot-percent", "30"]);
+        let histogram = histogram(
+            &[
+                ("aa1", 1),
+                ("aa2", 1),
+                ("aa3", 1),
+                ("aa4", 1),
+                ("bb1", 1),
+                ("bb2", 1),
+                ("bb3", 1),
+                ("cc1", 1),
+                ("dd1", 1),
+                ("ee1", 1),
+            ],
+            cmd.prefix_length,
+            cmd.max_prefixes,
+        );
+
+        assert_eq!(cmd.hot_prefixes(&histogram), vec![("aa", 40.0)]);
+        let report = cmd.report(&histogram);
+        assert!(report.starts_with("Key distribution by the first 2 characters: 10 keys"));
+        let lines: Vec<_> = report.lines().collect();
+        assert!(lines[1].starts_with("aa ") && lines[1].contains("40.00%"));
+        assert!(lines[2].starts_with("bb ") && lines[2].contains("30.00%"));
+        assert!(lines[3].starts_with("other") && lines[3].contains("30.00%"));
+        assert_eq!(
+            lines[4],
+            "hot prefix: aa holds 40.00% of the keys, above 30%"
+        );
+        assert_eq!(lines.len(), 5);
+    }
+}
@@ -16,4 +16,80 @@ pub enum FunctionError {
     PresentFileError,
     #[error("S3 Object is not complete")]
     ObjectFieldError,
+    #[error("Destination {0} is not writable: {1}")]
+    DestinationCheckError(String, String),
+    #[error("Key {0} collides with {1} on target key {2}")]
+    TargetCollisionError(String, String, String),
+    #[error(
+        "No AWS credentials found, use --no-sign-request to access a public bucket anonymously"
+    )]
+    CredentialsNotFound,
+    #[error("Search path {0} is inside the trash prefix {1}, refusing to trash it again")]
+    RecursiveTrash(String, String),
+    #[error("No AWS region found, set it with --aws-region, AWS_REGION or the profile")]
+    RegionNotFound,
+    #[error("Cannot list the top level of the prefix for --shard-by-range: {0}")]
+    ShardSampleError(String),
+    #[error("Notification to {0} failed with status {1}")]
+    NotifyError(String, u16),
+    #[error("Request budget exhausted after {0} requests, estimated cost ${1:.4}")]
+    RequestBudgetExhausted(u64, f64),
+    #[error("Time limit reached, --max-duration {0}s")]
+    TimeLimitReached(u64),
+    #[error("Cannot compile the patterns of ignore file {0}: {1}")]
+    IgnorePatterns(String, String),
+    #[error("Listing stopped at {0}")]
+    ListingAnomaly(String),
+    #[error("Run stopped, {0}")]
+    CommandStopped(String),
+    #[error(
+        "Destination {0} is inside the source path {1}, the copied keys would be listed again"
+    )]
+    NestedDestination(String, String),
+    #[error("Move marker {0} cannot be resumed: {1}")]
+    MoveMarker(String, String),
+    #[error("Downloaded {0} bytes, expected {1}")]
+    DownloadSizeMismatch(u64, u64),
+    #[error("Upload of the output to {0} was aborted after a failed request")]
+    OutputAborted(String),
+    #[error(
+        "--estimate needs a build with the cloudwatch feature: cargo install s3find --features cloudwatch"
+    )]
+    EstimateUnsupported,
+    #[error(
+        "--notify-sns needs a build with the sns feature: cargo install s3find --features sns"
+    )]
+    NotifySnsUnsupported,
+    #[error("Encrypted with a customer provided key, which a copy cannot preserve")]
+    CustomerKeyEncrypted,
+    #[error("Recompressed {0} holds {1} bytes, expected {2} gzip encoded bytes")]
+    RecompressVerifyError(String, u64, u64),
+    #[error("The wizard input ended before the invocation was complete")]
+    WizardCancelled,
+    #[error(
+        "export needs a build with the sqlite feature: cargo install s3find --features sqlite"
+    )]
+    ExportUnsupported,
+    #[error("The export database is not open")]
+    ExportNotOpen,
+    #[error("Database {0} has schema version {1}, expected {2}")]
+    ExportSchemaMismatch(String, String, u32),
+    #[error("Archived keys require restore first, run with --restore-and-retry: {0}")]
+    RestoreRequired(String),
+    #[error("Cannot use the restore ledger {0}: {1}")]
+    RestoreLedger(String, String),
+    #[error("Invalid line {0} of stdin: {1}")]
+    StdinLine(usize, String),
+}
+
+#[derive(Error, Debug)]
+pub enum CredentialsCommandError {
+    #[error("Cannot run credentials command {0}: {1}")]
+    Spawn(String, String),
+    #[error("Credentials command {0} failed with {1}: {2}")]
+    Failed(String, String, String),
+    #[error("Invalid credentials command output: {0}")]
+    Parse(String),
+    #[error("Unsupported credentials command output version {0}")]
+    Version(i64),
 }
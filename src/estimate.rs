@@ -0,0 +1,368 @@
+//! Bucket totals for --estimate from the daily S3 storage metrics of
+//! CloudWatch, BucketSizeBytes and NumberOfObjects, about a day old.
+//!
+//! The size is published per storage type, the total sums the latest size
+//! of every storage type of the bucket. The metrics are read with
+//! aws-sdk-cloudwatch, which needs a build with the `cloudwatch` feature.
+
+use std::fmt;
+
+use anyhow::Error;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use humansize::{format_size, BINARY};
+
+/// Totals of the bucket, missing for a bucket without published metrics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BucketEstimate {
+    pub objects: Option<u64>,
+    pub bytes: Option<u64>,
+}
+
+impl BucketEstimate {
+    /// The warning of a run about to walk more objects than the threshold,
+    /// --limit or a filter narrow the run
+    pub fn warning(&self, threshold: u64, narrowed: bool) -> Option<String> {
+        match self.objects {
+            Some(objects) if objects > threshold && !narrowed => Some(format!(
+                "WARNING: the run walks ~{} objects, above the estimate threshold of {}; narrow it with --limit or filters",
+                objects, threshold
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BucketEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.objects.is_none() && self.bytes.is_none() {
+            return write!(f, "note: no CloudWatch storage metrics for the bucket yet");
+        }
+
+        let objects = self.objects.map_or("?".to_owned(), |x| x.to_string());
+        let bytes = self
+            .bytes
+            .map_or("?".to_owned(), |x| format_size(x, BINARY));
+        write!(
+            f,
+            "bucket holds ~{} objects / ~{} (CloudWatch, 24h old)",
+            objects, bytes
+        )
+    }
+}
+
+#[cfg(feature = "cloudwatch")]
+pub use self::cloudwatch::{latest_average, CloudWatchMetrics};
+
+/// Reads the storage metrics of a bucket from CloudWatch
+#[cfg(not(feature = "cloudwatch"))]
+pub struct CloudWatchMetrics;
+
+#[cfg(not(feature = "cloudwatch"))]
+impl CloudWatchMetrics {
+    pub fn new(_region: &str, _credentials_provider: SharedCredentialsProvider) -> Self {
+        CloudWatchMetrics
+    }
+
+    pub async fn estimate(&self, _bucket: &str) -> Result<BucketEstimate, Error> {
+        Err(crate::error::FunctionError::EstimateUnsupported.into())
+    }
+}
+
+#[cfg(feature = "cloudwatch")]
+mod cloudwatch {
+    use std::collections::BTreeSet;
+    use std::time::{Duration, SystemTime};
+
+    use aws_sdk_cloudwatch::config::{BehaviorVersion, Region};
+    use aws_sdk_cloudwatch::primitives::DateTime;
+    use aws_sdk_cloudwatch::types::{Datapoint, Dimension, DimensionFilter, Statistic};
+    use aws_sdk_cloudwatch::{Client, Config};
+
+    use super::*;
+    use crate::utils::s3_error;
+
+    /// The metrics are published once a day, two days always hold the latest
+    const METRICS_WINDOW_DAYS: u64 = 2;
+    const METRICS_PERIOD_SECS: i32 = 86_400;
+
+    const NAMESPACE: &str = "AWS/S3";
+    const OBJECTS_METRIC: &str = "NumberOfObjects";
+    const BYTES_METRIC: &str = "BucketSizeBytes";
+    /// The storage type of the object count, the size has one per class
+    const ALL_STORAGE_TYPES: &str = "AllStorageTypes";
+
+    /// The average of the latest datapoint of a GetMetricStatistics response
+    pub fn latest_average(datapoints: &[Datapoint]) -> Option<f64> {
+        datapoints
+            .iter()
+            .filter_map(|x| Some((x.timestamp()?, x.average()?)))
+            .max_by_key(|(time, _)| *time)
+            .map(|(_, average)| average)
+    }
+
+    fn dimension(name: &str, value: &str) -> Dimension {
+        Dimension::builder().name(name).value(value).build()
+    }
+
+    /// Reads the storage metrics of a bucket from CloudWatch
+    pub struct CloudWatchMetrics {
+        client: Client,
+    }
+
+    impl CloudWatchMetrics {
+        pub fn new(region: &str, credentials_provider: SharedCredentialsProvider) -> Self {
+            let config = Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new(region.to_owned()))
+                .credentials_provider(credentials_provider)
+                .build();
+            Self::from_client(Client::from_conf(config))
+        }
+
+        pub fn from_client(client: Client) -> Self {
+            CloudWatchMetrics { client }
+        }
+
+        pub async fn estimate(&self, bucket: &str) -> Result<BucketEstimate, Error> {
+            let objects = self
+                .latest(bucket, OBJECTS_METRIC, ALL_STORAGE_TYPES)
+                .await?;
+            let mut bytes: Option<f64> = None;
+            for storage_type in self.storage_types(bucket).await? {
+                if let Some(size) = self.latest(bucket, BYTES_METRIC, &storage_type).await? {
+                    bytes = Some(bytes.unwrap_or_default() + size);
+                }
+            }
+
+            Ok(BucketEstimate {
+                objects: objects.map(|x| x as u64),
+                bytes: bytes.map(|x| x as u64),
+            })
+        }
+
+        /// The storage types the bucket size is published for, like
+        /// StandardStorage or GlacierStorage
+        async fn storage_types(&self, bucket: &str) -> Result<BTreeSet<String>, Error> {
+            let mut pages = self
+                .client
+                .list_metrics()
+                .namespace(NAMESPACE)
+                .metric_name(BYTES_METRIC)
+                .dimensions(
+                    DimensionFilter::builder()
+                        .name("BucketName")
+                        .value(bucket)
+                        .build(),
+                )
+                .into_paginator()
+                .send();
+
+            let mut storage_types = BTreeSet::new();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(s3_error)?;
+                storage_types.extend(
+                    page.metrics()
+                        .iter()
+                        .flat_map(|x| x.dimensions())
+                        .filter(|x| x.name() == Some("StorageType"))
+                        .filter_map(|x| x.value().map(str::to_owned)),
+                );
+            }
+            Ok(storage_types)
+        }
+
+        async fn latest(
+            &self,
+            bucket: &str,
+            metric: &str,
+            storage_type: &str,
+        ) -> Result<Option<f64>, Error> {
+            let end = SystemTime::now();
+            let start = end - Duration::from_secs(METRICS_WINDOW_DAYS * 86_400);
+
+            let output = self
+                .client
+                .get_metric_statistics()
+                .namespace(NAMESPACE)
+                .metric_name(metric)
+                .dimensions(dimension("BucketName", bucket))
+                .dimensions(dimension("StorageType", storage_type))
+                .start_time(DateTime::from(start))
+                .end_time(DateTime::from(end))
+                .period(METRICS_PERIOD_SECS)
+                .statistics(Statistic::Average)
+                .send()
+                .await
+                .map_err(s3_error)?;
+            Ok(latest_average(output.datapoints()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_display() {
+        let estimate = BucketEstimate {
+            objects: Some(15_000_000),
+            bytes: Some(3 * 1024 * 1024 * 1024 * 1024),
+        };
+        assert_eq!(
+            estimate.to_string(),
+            "bucket holds ~15000000 objects / ~3 TiB (CloudWatch, 24h old)"
+        );
+        assert_eq!(
+            BucketEstimate {
+                objects: Some(10),
+                bytes: None
+            }
+            .to_string(),
+            "bucket holds ~10 objects / ~? (CloudWatch, 24h old)"
+        );
+        assert_eq!(
+            BucketEstimate::default().to_string(),
+            "note: no CloudWatch storage metrics for the bucket yet"
+        );
+    }
+
+    #[test]
+    fn test_threshold_warning() {
+        let estimate = BucketEstimate {
+            objects: Some(15_000_000),
+            bytes: None,
+        };
+        assert!(estimate
+            .warning(10_000_000, false)
+            .unwrap()
+            .contains("~15000000 objects"));
+        assert_eq!(estimate.warning(10_000_000, true), None);
+        assert_eq!(estimate.warning(20_000_000, false), None);
+        assert_eq!(BucketEstimate::default().warning(0, false), None);
+    }
+
+    #[cfg(feature = "cloudwatch")]
+    mod cloudwatch {
+        use aws_sdk_cloudwatch::primitives::DateTime;
+        use aws_sdk_cloudwatch::types::Datapoint;
+
+        use super::*;
+        use crate::replay::{cloudwatch_replay_client, ReplayResponse};
+
+        fn statistics(averages: &[(&str, &str)]) -> ReplayResponse {
+            let members: String = averages
+                .iter()
+                .map(|(time, average)| {
+                    format!(
+                        "<member><Timestamp>{}</Timestamp><Unit>Bytes</Unit><Average>{}</Average></member>",
+                        time, average
+                    )
+                })
+                .collect();
+            ReplayResponse::new(
+                200,
+                &format!(
+                    r#"<GetMetricStatisticsResponse xmlns="http://monitoring.amazonaws.com/doc/2010-08-01/">
+<GetMetricStatisticsResult><Datapoints>{}</Datapoints><Label>m</Label></GetMetricStatisticsResult>
+</GetMetricStatisticsResponse>"#,
+                    members
+                ),
+            )
+        }
+
+        fn storage_types(types: &[&str]) -> ReplayResponse {
+            let members: String = types
+                .iter()
+                .map(|storage_type| {
+                    format!(
+                        "<member><Namespace>AWS/S3</Namespace><MetricName>BucketSizeBytes</MetricName><Dimensions>\
+<member><Name>StorageType</Name><Value>{}</Value></member>\
+<member><Name>BucketName</Name><Value>test</Value></member></Dimensions></member>",
+                        storage_type
+                    )
+                })
+                .collect();
+            ReplayResponse::new(
+                200,
+                &format!(
+                    r#"<ListMetricsResponse xmlns="http://monitoring.amazonaws.com/doc/2010-08-01/">
+<ListMetricsResult><Metrics>{}</Metrics></ListMetricsResult></ListMetricsResponse>"#,
+                    members
+                ),
+            )
+        }
+
+        #[test]
+        fn test_latest_average() {
+            let datapoint = |secs: i64, average: f64| {
+                Datapoint::builder()
+                    .timestamp(DateTime::from_secs(secs))
+                    .average(average)
+                    .build()
+            };
+            assert_eq!(
+                latest_average(&[datapoint(86_400, 1.5e7), datapoint(0, 1200.0)]),
+                Some(15_000_000.0)
+            );
+            assert_eq!(latest_average(&[]), None);
+        }
+
+        #[tokio::test]
+        async fn test_estimate_sums_storage_types() -> Result<(), Error> {
+            let (client, http_client) = cloudwatch_replay_client(vec![
+                statistics(&[
+                    ("2024-05-01T00:00:00Z", "1200.0"),
+                    ("2024-05-02T00:00:00Z", "1.5E7"),
+                ]),
+                storage_types(&["StandardStorage", "GlacierStorage", "StandardIAStorage"]),
+                statistics(&[("2024-05-02T00:00:00Z", "1024")]),
+                statistics(&[]),
+                statistics(&[("2024-05-02T00:00:00Z", "2048")]),
+            ]);
+            let estimate = CloudWatchMetrics::from_client(client)
+                .estimate("test")
+                .await?;
+
+            assert_eq!(
+                estimate,
+                BucketEstimate {
+                    objects: Some(15_000_000),
+                    bytes: Some(3072),
+                }
+            );
+            let bodies: Vec<_> = http_client
+                .requests()
+                .iter()
+                .map(|x| String::from_utf8_lossy(&x.body).into_owned())
+                .collect();
+            assert_eq!(bodies.len(), 5);
+            assert!(bodies[0].contains("MetricName=NumberOfObjects"));
+            assert!(bodies[0].contains("AllStorageTypes"));
+            assert!(bodies[1].contains("Action=ListMetrics"));
+            // the storage types are queried in their sorted order
+            for (body, storage_type) in
+                bodies[2..]
+                    .iter()
+                    .zip(["GlacierStorage", "StandardIAStorage", "StandardStorage"])
+            {
+                assert!(body.contains("MetricName=BucketSizeBytes"));
+                assert!(body.contains(storage_type));
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn test_estimate_error() {
+            let (client, _) = cloudwatch_replay_client(vec![ReplayResponse::new(
+                403,
+                r#"<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code><Message>denied</Message></Error></ErrorResponse>"#,
+            )]);
+            let error = CloudWatchMetrics::from_client(client)
+                .estimate("test")
+                .await
+                .unwrap_err();
+            assert!(error.to_string().starts_with("AccessDenied: denied"));
+        }
+    }
+}
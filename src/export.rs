@@ -0,0 +1,437 @@
+//! `export`: the matched objects, and their tags with --include-tags, in a
+//! SQLite database for SQL queries:
+//!
+//! ```sql
+//! meta(name, value)                       -- schema_version, bucket, prefix
+//! objects(key, size, last_modified, etag, storage_class, owner)
+//! tags(key, tag_key, tag_value)
+//! ```
+//!
+//! The rows are inserted in transactions of EXPORT_BATCH_ROWS rows. The
+//! SQLite format needs a build with the `sqlite` feature.
+
+use anyhow::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::types::Object;
+use aws_sdk_s3::Client;
+
+use crate::arg::{Export, S3Path};
+use crate::function::{CommandStats, RunCommand};
+
+/// The version of the tables, stored in the meta table
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Rows inserted in a single transaction
+pub const EXPORT_BATCH_ROWS: usize = 5000;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::ExportCommand;
+
+#[cfg(not(feature = "sqlite"))]
+pub struct ExportCommand {
+    pub export: Export,
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl ExportCommand {
+    pub fn new(export: Export) -> Self {
+        ExportCommand { export }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[async_trait]
+impl RunCommand for ExportCommand {
+    async fn execute(
+        &self,
+        _client: &Client,
+        _path: &S3Path,
+        _list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        Err(crate::error::FunctionError::ExportUnsupported.into())
+    }
+
+    async fn preflight(&self, _client: &Client, _path: &S3Path) -> Result<(), Error> {
+        Err(crate::error::FunctionError::ExportUnsupported.into())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::sync::Mutex;
+
+    use aws_smithy_types::date_time::Format;
+    use rusqlite::{params, Connection, OptionalExtension};
+
+    use super::*;
+    use crate::error::FunctionError;
+    use crate::function::fetch_tags;
+    use crate::utils::display_key;
+
+    const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (name TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS objects (
+    key TEXT PRIMARY KEY,
+    size INTEGER,
+    last_modified TEXT,
+    etag TEXT,
+    storage_class TEXT,
+    owner TEXT
+);
+CREATE TABLE IF NOT EXISTS tags (
+    key TEXT NOT NULL,
+    tag_key TEXT NOT NULL,
+    tag_value TEXT NOT NULL,
+    PRIMARY KEY (key, tag_key)
+);";
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct ObjectRow {
+        key: String,
+        size: Option<i64>,
+        last_modified: Option<String>,
+        etag: Option<String>,
+        storage_class: Option<String>,
+        owner: Option<String>,
+    }
+
+    impl From<&Object> for ObjectRow {
+        fn from(object: &Object) -> Self {
+            ObjectRow {
+                key: object.key.clone().unwrap_or_default(),
+                size: object.size,
+                last_modified: object
+                    .last_modified
+                    .and_then(|x| x.fmt(Format::DateTime).ok()),
+                etag: object.e_tag.clone(),
+                storage_class: object.storage_class.as_ref().map(|x| x.as_str().to_owned()),
+                owner: object
+                    .owner
+                    .as_ref()
+                    .and_then(|x| x.display_name.clone().or_else(|| x.id.clone())),
+            }
+        }
+    }
+
+    /// The open database with the rows not inserted yet
+    struct ExportState {
+        connection: Connection,
+        objects: Vec<ObjectRow>,
+        tags: Vec<(String, String, String)>,
+        exported: u64,
+    }
+
+    impl ExportState {
+        fn pending(&self) -> usize {
+            self.objects.len() + self.tags.len()
+        }
+
+        /// Inserts the pending rows in one transaction
+        fn flush(&mut self) -> Result<(), rusqlite::Error> {
+            let transaction = self.connection.transaction()?;
+            {
+                let mut insert = transaction.prepare_cached(
+                    "INSERT OR REPLACE INTO objects (key, size, last_modified, etag, storage_class, owner) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )?;
+                for row in &self.objects {
+                    insert.execute(params![
+                        row.key,
+                        row.size,
+                        row.last_modified,
+                        row.etag,
+                        row.storage_class,
+                        row.owner
+                    ])?;
+                }
+
+                let mut insert = transaction.prepare_cached(
+                    "INSERT OR REPLACE INTO tags (key, tag_key, tag_value) VALUES (?1, ?2, ?3)",
+                )?;
+                for (key, tag_key, tag_value) in &self.tags {
+                    insert.execute(params![key, tag_key, tag_value])?;
+                }
+            }
+            transaction.commit()?;
+
+            self.exported += self.objects.len() as u64;
+            self.objects.clear();
+            self.tags.clear();
+            Ok(())
+        }
+    }
+
+    /// Opens the database and creates its tables, a database of another
+    /// schema version is refused
+    fn open(export: &Export, path: &S3Path) -> Result<Connection, Error> {
+        let connection = Connection::open(&export.output_file)?;
+        connection.execute_batch(SCHEMA)?;
+
+        let version: Option<String> = connection
+            .query_row(
+                "SELECT value FROM meta WHERE name = 'schema_version'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match version {
+            Some(version) if version != EXPORT_SCHEMA_VERSION.to_string() => {
+                return Err(FunctionError::ExportSchemaMismatch(
+                    export.output_file.display().to_string(),
+                    version,
+                    EXPORT_SCHEMA_VERSION,
+                )
+                .into())
+            }
+            _ => (),
+        }
+
+        let mut insert =
+            connection.prepare("INSERT OR REPLACE INTO meta (name, value) VALUES (?1, ?2)")?;
+        insert.execute(params!["schema_version", EXPORT_SCHEMA_VERSION.to_string()])?;
+        insert.execute(params!["bucket", path.bucket])?;
+        insert.execute(params!["prefix", path.prefix.clone().unwrap_or_default()])?;
+        drop(insert);
+        Ok(connection)
+    }
+
+    /// Export into the database opened by the preflight
+    pub struct ExportCommand {
+        pub export: Export,
+        state: Mutex<Option<ExportState>>,
+    }
+
+    impl ExportCommand {
+        pub fn new(export: Export) -> Self {
+            ExportCommand {
+                export,
+                state: Mutex::new(None),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl RunCommand for ExportCommand {
+        async fn execute(
+            &self,
+            client: &Client,
+            path: &S3Path,
+            list: &[Object],
+        ) -> Result<CommandStats, Error> {
+            let mut stats = CommandStats::default();
+            let objects: Vec<_> = list
+                .iter()
+                .filter(|x| x.key.is_some())
+                .map(ObjectRow::from)
+                .collect();
+
+            let mut tags = Vec::new();
+            if self.export.include_tags {
+                let keys: Vec<_> = objects.iter().map(|x| x.key.clone()).collect();
+                let fetched = fetch_tags(client, &path.bucket, keys.clone()).await;
+                for (key, result) in keys.into_iter().zip(fetched) {
+                    match result {
+                        Ok(tag_set) => {
+                            tags.extend(tag_set.into_iter().map(|x| (key.clone(), x.key, x.value)))
+                        }
+                        Err(e) => {
+                            let error = format!(
+                                "s3://{}/{}: cannot read the tags: {}",
+                                path.bucket,
+                                display_key(&key),
+                                e
+                            );
+                            eprintln!("error: {}", error);
+                            stats.errors.push(error);
+                        }
+                    }
+                }
+            }
+
+            let mut guard = self.state.lock().unwrap();
+            let state = guard.as_mut().ok_or(FunctionError::ExportNotOpen)?;
+            stats.processed += objects.len();
+            state.objects.extend(objects);
+            state.tags.extend(tags);
+            if state.pending() >= EXPORT_BATCH_ROWS {
+                state.flush()?;
+            }
+            Ok(stats)
+        }
+
+        async fn preflight(&self, _client: &Client, path: &S3Path) -> Result<(), Error> {
+            let connection = open(&self.export, path)?;
+            *self.state.lock().unwrap() = Some(ExportState {
+                connection,
+                objects: Vec::new(),
+                tags: Vec::new(),
+                exported: 0,
+            });
+            Ok(())
+        }
+
+        fn fetch_owner(&self) -> bool {
+            true
+        }
+
+        async fn finish(&self, _client: &Client, _path: &S3Path) -> Result<CommandStats, Error> {
+            if let Some(state) = self.state.lock().unwrap().as_mut() {
+                state.flush()?;
+            }
+            Ok(CommandStats::default())
+        }
+
+        fn summary(&self) -> Option<String> {
+            let state = self.state.lock().unwrap();
+            state.as_ref().map(|state| {
+                format!(
+                    "Exported {} objects to {}",
+                    state.exported,
+                    self.export.output_file.display()
+                )
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::arg::ExportFormat;
+        use crate::replay::*;
+        use aws_sdk_s3::types::{ObjectStorageClass, Owner};
+        use aws_smithy_types::DateTime;
+
+        fn export(dir: &tempfile::TempDir, include_tags: bool) -> ExportCommand {
+            ExportCommand::new(Export {
+                format: ExportFormat::Sqlite,
+                output_file: dir.path().join("objects.db"),
+                include_tags,
+            })
+        }
+
+        fn object(key: &str, size: i64) -> Object {
+            Object::builder()
+                .key(key)
+                .size(size)
+                .e_tag(format!("\"{}\"", key))
+                .last_modified(DateTime::from_secs(1_700_000_000))
+                .storage_class(ObjectStorageClass::StandardIa)
+                .owner(Owner::builder().display_name("owner").build())
+                .build()
+        }
+
+        fn tagging(tags: &[(&str, &str)]) -> ReplayResponse {
+            let tags: String = tags
+                .iter()
+                .map(|(key, value)| {
+                    format!("<Tag><Key>{}</Key><Value>{}</Value></Tag>", key, value)
+                })
+                .collect();
+            ReplayResponse::new(
+                200,
+                &format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<Tagging><TagSet>{}</TagSet></Tagging>"#,
+                    tags
+                ),
+            )
+        }
+
+        #[tokio::test]
+        async fn test_export_with_tags() -> Result<(), Error> {
+            let dir = tempfile::TempDir::new()?;
+            let (client, http_client) = replay_client(vec![
+                tagging(&[("team", "data"), ("tier", "hot")]),
+                tagging(&[]),
+                tagging(&[("team", "web")]),
+            ]);
+            let cmd = export(&dir, true);
+            let path: S3Path = "s3://test/logs/".parse()?;
+
+            cmd.preflight(&client, &path).await?;
+            let stats = cmd
+                .execute(
+                    &client,
+                    &path,
+                    &[object("logs/a", 10), object("logs/b", 20)],
+                )
+                .await?;
+            assert_eq!(stats.processed, 2);
+            cmd.execute(&client, &path, &[object("logs/c", 30)]).await?;
+            cmd.finish(&client, &path).await?;
+
+            assert_eq!(http_client.requests().len(), 3);
+            assert_eq!(
+                cmd.summary().unwrap(),
+                format!(
+                    "Exported 3 objects to {}",
+                    dir.path().join("objects.db").display()
+                )
+            );
+
+            let connection = Connection::open(dir.path().join("objects.db"))?;
+            let count = |table: &str| -> Result<i64, rusqlite::Error> {
+                connection.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+            };
+            assert_eq!(count("objects")?, 3);
+            assert_eq!(count("tags")?, 3);
+
+            let (size, modified, class, owner): (i64, String, String, String) = connection
+                .query_row(
+                    "SELECT SUM(o.size), MIN(o.last_modified), MIN(o.storage_class), MIN(o.owner)
+                     FROM objects o JOIN tags t ON t.key = o.key
+                     WHERE t.tag_key = 'team'",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )?;
+            assert_eq!(size, 40);
+            assert_eq!(modified, "2023-11-14T22:13:20Z");
+            assert_eq!(class, "STANDARD_IA");
+            assert_eq!(owner, "owner");
+
+            let version: String = connection.query_row(
+                "SELECT value FROM meta WHERE name = 'schema_version'",
+                [],
+                |row| row.get(0),
+            )?;
+            assert_eq!(version, EXPORT_SCHEMA_VERSION.to_string());
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn test_export_batches_and_schema() -> Result<(), Error> {
+            let dir = tempfile::TempDir::new()?;
+            let (client, http_client) = replay_client(vec![]);
+            let cmd = export(&dir, false);
+            let path: S3Path = "s3://test/".parse()?;
+
+            cmd.preflight(&client, &path).await?;
+            let list: Vec<_> = (0..EXPORT_BATCH_ROWS)
+                .map(|i| object(&format!("k{}", i), 1))
+                .collect();
+            cmd.execute(&client, &path, &list).await?;
+            // a full batch is inserted without waiting for the end
+            assert_eq!(cmd.state.lock().unwrap().as_ref().unwrap().exported, 5000);
+            cmd.execute(&client, &path, &[object("last", 1)]).await?;
+            cmd.finish(&client, &path).await?;
+            assert!(http_client.requests().is_empty());
+
+            let connection = Connection::open(dir.path().join("objects.db"))?;
+            let count: i64 =
+                connection.query_row("SELECT COUNT(*) FROM objects", [], |row| row.get(0))?;
+            assert_eq!(count, 5001);
+
+            connection.execute(
+                "UPDATE meta SET value = '99' WHERE name = 'schema_version'",
+                [],
+            )?;
+            let error = export(&dir, false)
+                .preflight(&client, &path)
+                .await
+                .unwrap_err();
+            assert!(error.to_string().contains("schema version 99"));
+            Ok(())
+        }
+    }
+}
@@ -1,12 +1,86 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use aws_sdk_s3::primitives::DateTime;
 use aws_sdk_s3::types::Object;
-use chrono::prelude::*;
-use glob::MatchOptions;
-use regex::Regex;
+use glob::{MatchOptions, Pattern};
+use regex::{Regex, RegexSet};
 
 use crate::arg::*;
+use crate::credentials::Clock;
+use crate::function::storage_class;
+
+/// The number of --name or --iname patterns above which they are matched
+/// by a single GlobSet
+pub const GLOB_SET_THRESHOLD: usize = 8;
+
+/// Estimated cost of a filter test: the filters are tested from the
+/// cheapest, so a failed comparison skips the pattern matching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FilterCost {
+    /// The ignore file, tested first so an ignored key reaches no other filter
+    Ignore,
+    /// Comparison of a listed size, date or storage class
+    Compare,
+    /// Prefix comparison or hash of the key
+    Key,
+    /// Glob match of the key
+    Glob,
+    /// Regex match of the key
+    Regex,
+}
 
 pub trait Filter {
     fn filter(&self, object: &Object) -> bool;
+
+    /// Whether the object matches with the times measured from `reference`
+    /// instead of the current time, the same as `filter` for a filter
+    /// without a time
+    fn filter_at(&self, object: &Object, _reference: SystemTime) -> bool {
+        self.filter(object)
+    }
+
+    /// What the filter checks, as printed by --explain
+    fn describe(&self) -> String;
+
+    /// The command line flag of the filter, as printed with its selectivity
+    fn flag(&self) -> String;
+
+    fn cost(&self) -> FilterCost;
+
+    /// Whether the filter reads more than the key: the size, modification
+    /// time, storage class or ETag of the listing
+    fn reads_metadata(&self) -> bool {
+        self.cost() == FilterCost::Compare
+    }
+}
+
+impl<T: Filter + ?Sized> Filter for &T {
+    fn filter(&self, object: &Object) -> bool {
+        (**self).filter(object)
+    }
+
+    fn filter_at(&self, object: &Object, reference: SystemTime) -> bool {
+        (**self).filter_at(object, reference)
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+
+    fn flag(&self) -> String {
+        (**self).flag()
+    }
+
+    fn cost(&self) -> FilterCost {
+        (**self).cost()
+    }
+
+    fn reads_metadata(&self) -> bool {
+        (**self).reads_metadata()
+    }
 }
 
 impl Filter for FindSize {
@@ -18,19 +92,61 @@ impl Filter for FindSize {
             FindSize::Equal(size) => object_size == size,
         }
     }
+
+    fn describe(&self) -> String {
+        match *self {
+            FindSize::Bigger(size) => format!("size >= {} bytes", size),
+            FindSize::Lower(size) => format!("size <= {} bytes", size),
+            FindSize::Equal(size) => format!("size = {} bytes", size),
+        }
+    }
+
+    fn flag(&self) -> String {
+        format!("--size {}", self)
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Compare
+    }
 }
 
 impl Filter for FindTime {
     fn filter(&self, object: &Object) -> bool {
-        let last_modified_time = object.last_modified.map(|x| x.secs()).unwrap_or_default();
+        self.filter_at(object, SystemTime::now())
+    }
 
-        let now = Utc::now().timestamp();
+    fn filter_at(&self, object: &Object, reference: SystemTime) -> bool {
+        matches_mtime(self, object.last_modified.as_ref(), reference)
+    }
 
+    fn describe(&self) -> String {
         match *self {
-            FindTime::Lower(seconds) => (now - last_modified_time) >= seconds,
-            FindTime::Upper(seconds) => (now - last_modified_time) <= seconds,
+            FindTime::Lower(seconds) => format!("modified at least {}s ago", seconds),
+            FindTime::Upper(seconds) => format!("modified at most {}s ago", seconds),
         }
     }
+
+    fn flag(&self) -> String {
+        format!("--mtime {}", self)
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Compare
+    }
+}
+
+/// Whether the age of an object at `now` is in the rule range. Both times
+/// are compared as UTC epoch seconds. An object without a modification time
+/// is taken as modified at the epoch, so it matches `+N`; a modification
+/// time ahead of `now` (clock skew) counts as a zero age.
+pub fn matches_mtime(rule: &FindTime, last_modified: Option<&DateTime>, now: SystemTime) -> bool {
+    let last_modified = last_modified.map(|x| x.secs()).unwrap_or_default();
+    let age = (DateTime::from(now).secs() - last_modified).max(0);
+
+    match *rule {
+        FindTime::Lower(seconds) => age >= seconds,
+        FindTime::Upper(seconds) => age <= seconds,
+    }
 }
 
 impl Filter for NameGlob {
@@ -38,6 +154,18 @@ impl Filter for NameGlob {
         let object_key = object.key.clone().unwrap_or_default();
         self.matches(&object_key)
     }
+
+    fn describe(&self) -> String {
+        format!("name matches {}", self.as_str())
+    }
+
+    fn flag(&self) -> String {
+        format!("--name {}", self.as_str())
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Glob
+    }
 }
 
 impl Filter for InameGlob {
@@ -52,20 +180,895 @@ impl Filter for InameGlob {
             },
         )
     }
+
+    fn describe(&self) -> String {
+        format!("name matches {} ignoring case", self.0.as_str())
+    }
+
+    fn flag(&self) -> String {
+        format!("--iname {}", self.0.as_str())
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Glob
+    }
+}
+
+/// A --name or --iname pattern matched against the key below the search
+/// prefix, for --anchor prefix
+pub struct AnchoredGlob<'a> {
+    pub pattern: &'a Pattern,
+    pub case_sensitive: bool,
+    pub prefix: &'a str,
+}
+
+impl Filter for AnchoredGlob<'_> {
+    fn filter(&self, object: &Object) -> bool {
+        let key = object.key.as_deref().unwrap_or_default();
+        let relative = key.strip_prefix(self.prefix).unwrap_or(key);
+        self.pattern.matches_with(
+            relative,
+            MatchOptions {
+                case_sensitive: self.case_sensitive,
+                require_literal_separator: false,
+                require_literal_leading_dot: false,
+            },
+        )
+    }
+
+    fn describe(&self) -> String {
+        let case = if self.case_sensitive {
+            ""
+        } else {
+            " ignoring case"
+        };
+        format!(
+            "name below {} matches {}{}",
+            self.prefix,
+            self.pattern.as_str(),
+            case
+        )
+    }
+
+    fn flag(&self) -> String {
+        let flag = if self.case_sensitive {
+            "--name"
+        } else {
+            "--iname"
+        };
+        format!("{} {}", flag, self.pattern.as_str())
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Glob
+    }
+}
+
+impl Filter for NameLiteral {
+    fn filter(&self, object: &Object) -> bool {
+        self.matches(object.key.as_deref().unwrap_or_default())
+    }
+
+    fn describe(&self) -> String {
+        format!("key ends with the segments {}", self.0)
+    }
+
+    fn flag(&self) -> String {
+        format!("--name-literal {}", self.0)
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
+}
+
+impl Filter for ExcludePrefixes {
+    fn filter(&self, object: &Object) -> bool {
+        !self.0.matches(object.key.as_deref().unwrap_or_default())
+    }
+
+    fn describe(&self) -> String {
+        format!("key outside {} excluded prefixes", self.0.len())
+    }
+
+    fn flag(&self) -> String {
+        format!("--exclude-prefixes-from ({} prefixes)", self.0.len())
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
+}
+
+impl Filter for IncludePrefixes {
+    fn filter(&self, object: &Object) -> bool {
+        self.0.matches(object.key.as_deref().unwrap_or_default())
+    }
+
+    fn describe(&self) -> String {
+        format!("key under one of {} included prefixes", self.0.len())
+    }
+
+    fn flag(&self) -> String {
+        format!("--include-prefixes-from ({} prefixes)", self.0.len())
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
+}
+
+/// The extension of the name of a key: the part after its last dot. A name
+/// without a dot or ending with one has none, as has a hidden name like
+/// .profile whose only dot leads the name.
+pub fn key_extension(key: &str) -> Option<&str> {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    match name.rfind('.') {
+        None | Some(0) => None,
+        Some(dot) if dot + 1 == name.len() => None,
+        Some(dot) => Some(&name[dot + 1..]),
+    }
+}
+
+impl Filter for Extensions {
+    fn filter(&self, object: &Object) -> bool {
+        key_extension(object.key.as_deref().unwrap_or_default())
+            .is_some_and(|ext| self.0.contains(&ext.to_lowercase()))
+    }
+
+    fn describe(&self) -> String {
+        let extensions: Vec<_> = self.0.iter().map(String::as_str).collect();
+        format!("extension is one of {}", extensions.join(", "))
+    }
+
+    fn flag(&self) -> String {
+        let extensions: Vec<_> = self.0.iter().map(String::as_str).collect();
+        format!("--ext {}", extensions.join(","))
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
+}
+
+/// The keys whose name has no extension, --no-ext
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoExtension;
+
+impl Filter for NoExtension {
+    fn filter(&self, object: &Object) -> bool {
+        key_extension(object.key.as_deref().unwrap_or_default()).is_none()
+    }
+
+    fn describe(&self) -> String {
+        "name without extension".to_owned()
+    }
+
+    fn flag(&self) -> String {
+        "--no-ext".to_owned()
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
+}
+
+/// The keys under a copy or move destination nested in the source path,
+/// excluded unless --no-auto-exclude is set
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExcludeDestination(pub String);
+
+impl Filter for ExcludeDestination {
+    fn filter(&self, object: &Object) -> bool {
+        !object
+            .key
+            .as_deref()
+            .unwrap_or_default()
+            .starts_with(&self.0)
+    }
+
+    fn describe(&self) -> String {
+        format!("key outside the destination prefix {}", self.0)
+    }
+
+    fn flag(&self) -> String {
+        format!("destination {} (auto-excluded)", self.0)
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
 }
 
 impl Filter for Regex {
     fn filter(&self, object: &Object) -> bool {
-        let object_key = object.key.clone().unwrap_or_default();
-        self.is_match(&object_key)
+        self.is_match(object.key.as_deref().unwrap_or_default())
+    }
+
+    fn describe(&self) -> String {
+        format!("key matches regex {}", self.as_str())
+    }
+
+    fn flag(&self) -> String {
+        format!("--regex {}", self.as_str())
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Regex
+    }
+}
+
+impl Filter for WhereExpr {
+    fn filter(&self, object: &Object) -> bool {
+        self.filter_at(object, SystemTime::now())
+    }
+
+    fn filter_at(&self, object: &Object, reference: SystemTime) -> bool {
+        match self {
+            WhereExpr::And(left, right) => {
+                left.filter_at(object, reference) && right.filter_at(object, reference)
+            }
+            WhereExpr::Or(left, right) => {
+                left.filter_at(object, reference) || right.filter_at(object, reference)
+            }
+            WhereExpr::Not(expr) => !expr.filter_at(object, reference),
+            WhereExpr::Name(glob) => glob.filter(object),
+            WhereExpr::Iname(glob) => glob.filter(object),
+            WhereExpr::Regex(regex) => regex.filter(object),
+            WhereExpr::Size(size) => size.filter(object),
+            WhereExpr::Mtime(time) => time.filter_at(object, reference),
+            WhereExpr::StorageClass(class) => storage_class(object) == class,
+            WhereExpr::Depth(ordering, depth) => {
+                let key = object.key.as_deref().unwrap_or_default();
+                key.matches('/').count().cmp(depth) == *ordering
+            }
+            WhereExpr::ETag(e_tag) => {
+                object.e_tag.as_deref().map(|x| x.trim_matches('"'))
+                    == Some(e_tag.trim_matches('"'))
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            WhereExpr::And(left, right) => {
+                format!("({} and {})", left.describe(), right.describe())
+            }
+            WhereExpr::Or(left, right) => format!("({} or {})", left.describe(), right.describe()),
+            WhereExpr::Not(expr) => format!("not {}", expr.describe()),
+            WhereExpr::Name(glob) => glob.describe(),
+            WhereExpr::Iname(glob) => glob.describe(),
+            WhereExpr::Regex(regex) => regex.describe(),
+            WhereExpr::Size(size) => size.describe(),
+            WhereExpr::Mtime(time) => time.describe(),
+            WhereExpr::StorageClass(class) => format!("storage class = {}", class),
+            WhereExpr::Depth(ordering, depth) => {
+                let operator = match ordering {
+                    Ordering::Less => "<",
+                    Ordering::Equal => "=",
+                    Ordering::Greater => ">",
+                };
+                format!("depth {} {}", operator, depth)
+            }
+            WhereExpr::ETag(e_tag) => format!("etag = {}", e_tag.trim_matches('"')),
+        }
+    }
+
+    fn flag(&self) -> String {
+        format!("--where {}", self.describe())
+    }
+
+    /// The cost of the most expensive predicate
+    fn cost(&self) -> FilterCost {
+        match self {
+            WhereExpr::And(left, right) | WhereExpr::Or(left, right) => {
+                left.cost().max(right.cost())
+            }
+            WhereExpr::Not(expr) => expr.cost(),
+            WhereExpr::Name(_) | WhereExpr::Iname(_) => FilterCost::Glob,
+            WhereExpr::Regex(_) => FilterCost::Regex,
+            WhereExpr::Depth(..) => FilterCost::Key,
+            WhereExpr::Size(_)
+            | WhereExpr::Mtime(_)
+            | WhereExpr::StorageClass(_)
+            | WhereExpr::ETag(_) => FilterCost::Compare,
+        }
+    }
+
+    fn reads_metadata(&self) -> bool {
+        match self {
+            WhereExpr::And(left, right) | WhereExpr::Or(left, right) => {
+                left.reads_metadata() || right.reads_metadata()
+            }
+            WhereExpr::Not(expr) => expr.reads_metadata(),
+            expr => expr.cost() == FilterCost::Compare,
+        }
+    }
+}
+
+/// A filter with its times measured from a fixed reference, so every key
+/// of a run is tested against the same instant however long the run takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtReference<F> {
+    pub filter: F,
+    pub reference: SystemTime,
+}
+
+impl<F: Filter> Filter for AtReference<F> {
+    fn filter(&self, object: &Object) -> bool {
+        self.filter.filter_at(object, self.reference)
+    }
+
+    fn filter_at(&self, object: &Object, reference: SystemTime) -> bool {
+        self.filter.filter_at(object, reference)
+    }
+
+    fn describe(&self) -> String {
+        self.filter.describe()
+    }
+
+    fn flag(&self) -> String {
+        self.filter.flag()
+    }
+
+    fn cost(&self) -> FilterCost {
+        self.filter.cost()
+    }
+
+    fn reads_metadata(&self) -> bool {
+        self.filter.reads_metadata()
+    }
+}
+
+/// The mtime filters tested again right before a command changes the
+/// matched keys, --reevaluate-mtime-at-action: a key matched early in a long
+/// run may no longer be old or new enough when its batch is processed
+pub struct MtimeRecheck {
+    mtime: Vec<FindTime>,
+    where_expr: Option<WhereExpr>,
+    clock: Clock,
+}
+
+impl MtimeRecheck {
+    pub fn new(opts: &FindOpt) -> Self {
+        Self::with_clock(opts, Arc::new(SystemTime::now))
+    }
+
+    pub fn with_clock(opts: &FindOpt, clock: Clock) -> Self {
+        MtimeRecheck {
+            mtime: opts.mtime.clone(),
+            where_expr: opts.where_expr.clone(),
+            clock,
+        }
+    }
+
+    /// The current time of the check
+    pub fn now(&self) -> SystemTime {
+        (self.clock)()
+    }
+
+    /// Whether the object still matches the mtime filters at `now`
+    pub fn matches(&self, object: &Object, now: SystemTime) -> bool {
+        self.mtime.iter().all(|rule| rule.filter_at(object, now))
+            && self
+                .where_expr
+                .as_ref()
+                .is_none_or(|expr| expr.filter_at(object, now))
+    }
+}
+
+/// Deterministic random sample of the keys: a key is selected when its
+/// seeded hash falls below the rate, so a seed selects the same keys
+/// in every run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeySample {
+    pub rate: f64,
+    pub seed: u64,
+}
+
+impl KeySample {
+    pub fn selects(&self, key: &str) -> bool {
+        // the top 53 bits convert to a float in [0, 1) exactly
+        let position = (sample_hash(key, self.seed) >> 11) as f64 / (1u64 << 53) as f64;
+        position < self.rate
+    }
+}
+
+impl Filter for KeySample {
+    fn filter(&self, object: &Object) -> bool {
+        self.selects(object.key.as_deref().unwrap_or_default())
+    }
+
+    fn describe(&self) -> String {
+        format!("sample of {} of the keys, seed {}", self.rate, self.seed)
+    }
+
+    fn flag(&self) -> String {
+        format!("--sample-rate {} --sample-seed {}", self.rate, self.seed)
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
+}
+
+/// --shard: a key belongs to the shard its hash falls in, so the runs of
+/// the shards 0 to n-1 process every key exactly once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyShard(pub Shard);
+
+impl KeyShard {
+    pub fn selects(&self, key: &str) -> bool {
+        sample_hash(key, 0) % self.0.count == self.0.index
     }
 }
 
+impl Filter for KeyShard {
+    fn filter(&self, object: &Object) -> bool {
+        self.selects(object.key.as_deref().unwrap_or_default())
+    }
+
+    fn describe(&self) -> String {
+        format!("keys of shard {} by hash", self.0)
+    }
+
+    fn flag(&self) -> String {
+        format!("--shard {}", self.0)
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Key
+    }
+}
+
+/// FNV-1a of the seed and the key, mixed by the splitmix64 finalizer so
+/// the hashes are uniform over the whole range
+fn sample_hash(key: &str, seed: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in seed.to_le_bytes().iter().chain(key.as_bytes()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Uniform sample of at most `capacity` values (reservoir sampling), the
+/// quantiles are exact while fewer values were added
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reservoir {
+    capacity: usize,
+    seen: u64,
+    values: Vec<i64>,
+    state: u64,
+}
+
+impl Reservoir {
+    pub fn new(capacity: usize) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            values: Vec::new(),
+            state: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn add(&mut self, value: i64) {
+        self.seen += 1;
+        if self.values.len() < self.capacity {
+            self.values.push(value);
+            return;
+        }
+
+        let slot = self.next_random() % self.seen;
+        if let Some(x) = self.values.get_mut(slot as usize) {
+            *x = value;
+        }
+    }
+
+    /// The smallest value with at least `fraction` of the values at or below it
+    pub fn quantile(&self, fraction: f64) -> Option<i64> {
+        let mut values = self.values.clone();
+        values.sort_unstable();
+        let rank = (fraction * values.len() as f64).ceil().max(1.0) as usize;
+        values.get(rank.min(values.len()).checked_sub(1)?).copied()
+    }
+
+    // xorshift64*, the same sample in every run
+    fn next_random(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// Threshold computed by the first listing pass of --oldest-percent or
+/// --largest-percent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PercentileCutoff {
+    /// Modified at or before these epoch seconds
+    Oldest(f64, i64),
+    /// At least this size
+    Largest(f64, i64),
+}
+
+impl Filter for PercentileCutoff {
+    fn filter(&self, object: &Object) -> bool {
+        match *self {
+            PercentileCutoff::Oldest(_, cutoff) => object
+                .last_modified
+                .as_ref()
+                .is_some_and(|x| x.secs() <= cutoff),
+            PercentileCutoff::Largest(_, cutoff) => object.size.unwrap_or_default() >= cutoff,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            PercentileCutoff::Oldest(percent, cutoff) => format!(
+                "oldest {}%: modified at or before {}",
+                percent,
+                DateTime::from_secs(cutoff)
+            ),
+            PercentileCutoff::Largest(percent, cutoff) => {
+                format!("largest {}%: size >= {} bytes", percent, cutoff)
+            }
+        }
+    }
+
+    fn flag(&self) -> String {
+        match *self {
+            PercentileCutoff::Oldest(percent, _) => format!("--oldest-percent {}", percent),
+            PercentileCutoff::Largest(percent, _) => format!("--largest-percent {}", percent),
+        }
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Compare
+    }
+}
+
+/// Glob patterns compiled into one RegexSet, so a key is matched once
+/// whatever the number of patterns. Like the patterns used as separate
+/// filters, a key matches when every pattern matches it.
+pub struct GlobSet {
+    set: RegexSet,
+    case_sensitive: bool,
+    prefix: String,
+}
+
+impl GlobSet {
+    pub fn new<'a, I>(patterns: I, case_sensitive: bool) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator<Item = &'a Pattern>,
+    {
+        // a repeated pattern adds nothing to an all-match
+        let regexes: BTreeSet<_> = patterns
+            .into_iter()
+            .map(|x| glob_regex(x.as_str(), case_sensitive))
+            .collect();
+        Ok(GlobSet {
+            set: RegexSet::new(regexes)?,
+            case_sensitive,
+            prefix: String::new(),
+        })
+    }
+
+    /// Matches the patterns against the key below the prefix, for
+    /// --anchor prefix
+    pub fn anchored(self, prefix: &str) -> Self {
+        GlobSet {
+            prefix: prefix.to_owned(),
+            ..self
+        }
+    }
+}
+
+impl Filter for GlobSet {
+    fn filter(&self, object: &Object) -> bool {
+        let key = object.key.as_deref().unwrap_or_default();
+        let relative = key.strip_prefix(self.prefix.as_str()).unwrap_or(key);
+        let matches = self.set.matches(relative);
+        matches.iter().count() == matches.len()
+    }
+
+    fn describe(&self) -> String {
+        format!("name matches all of {} globs in one pass", self.set.len())
+    }
+
+    fn flag(&self) -> String {
+        let flag = if self.case_sensitive {
+            "--name"
+        } else {
+            "--iname"
+        };
+        format!("{} ({} globs)", flag, self.set.len())
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Regex
+    }
+}
+
+/// The patterns of an ignore file compiled into one RegexSet. A key is
+/// excluded when the last pattern matching its part below the search prefix
+/// is not negated.
+pub struct IgnoreSet {
+    set: RegexSet,
+    negated: Vec<bool>,
+    prefix: String,
+    path: String,
+}
+
+impl IgnoreSet {
+    pub fn new(file: &IgnoreFile, prefix: &str) -> Result<Self, regex::Error> {
+        let regexes = file
+            .patterns
+            .iter()
+            .map(|x| glob_regex(x.glob.as_str(), true));
+        Ok(IgnoreSet {
+            set: RegexSet::new(regexes)?,
+            negated: file.patterns.iter().map(|x| x.negated).collect(),
+            prefix: prefix.to_owned(),
+            path: file.path.clone(),
+        })
+    }
+
+    pub fn ignores(&self, key: &str) -> bool {
+        let relative = key.strip_prefix(self.prefix.as_str()).unwrap_or(key);
+        self.set
+            .matches(relative)
+            .iter()
+            .next_back()
+            .is_some_and(|x| !self.negated[x])
+    }
+}
+
+impl Filter for IgnoreSet {
+    fn filter(&self, object: &Object) -> bool {
+        !self.ignores(object.key.as_deref().unwrap_or_default())
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "key not ignored by {} patterns of {}",
+            self.set.len(),
+            self.path
+        )
+    }
+
+    fn flag(&self) -> String {
+        format!("--ignore-file {}", self.path)
+    }
+
+    fn cost(&self) -> FilterCost {
+        FilterCost::Ignore
+    }
+}
+
+/// A class character, escaped by its code point
+fn class_char(c: char) -> String {
+    format!("\\x{{{:x}}}", c as u32)
+}
+
+fn class_range(start: char, end: char) -> String {
+    format!("{}-{}", class_char(start), class_char(end))
+}
+
+/// Translates a valid glob pattern into a regex with the semantics of
+/// Pattern::matches_with with the default options besides the case:
+/// `*` and `**` also match separators and ASCII letters only are compared
+/// case-insensitively
+fn glob_regex(pattern: &str, case_sensitive: bool) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::from("(?s)\\A");
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '?' => {
+                regex.push('.');
+                i += 1;
+            }
+            '*' => {
+                let start = i;
+                while i < chars.len() && chars[i] == '*' {
+                    i += 1;
+                }
+
+                // a recursive sequence takes the following separator and
+                // matches whole path components, or anything at the end
+                if i - start == 2 && i < chars.len() {
+                    i += 1;
+                }
+                if i - start > 1 && i < chars.len() {
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '[' => {
+                let negated = chars[i + 1] == '!';
+                let first = if negated { i + 2 } else { i + 1 };
+                // the first character of a class may be ']'
+                let end = first
+                    + 1
+                    + chars[first + 1..]
+                        .iter()
+                        .position(|x| *x == ']')
+                        .unwrap_or_default();
+
+                let mut class = String::new();
+                let specifiers = &chars[first..end];
+                let mut j = 0;
+                while j < specifiers.len() {
+                    if j + 3 <= specifiers.len() && specifiers[j + 1] == '-' {
+                        class.push_str(&glob_range(
+                            specifiers[j],
+                            specifiers[j + 2],
+                            case_sensitive,
+                        ));
+                        j += 3;
+                    } else {
+                        class.push_str(&glob_char(specifiers[j], case_sensitive, true));
+                        j += 1;
+                    }
+                }
+
+                regex.push_str(&match (negated, class.is_empty()) {
+                    (true, true) => ".".to_owned(),
+                    (true, false) => format!("[^{}]", class),
+                    (false, true) => "[^\\x{0}-\\x{10ffff}]".to_owned(),
+                    (false, false) => format!("[{}]", class),
+                });
+                i = end + 1;
+            }
+            c => {
+                regex.push_str(&glob_char(c, case_sensitive, false));
+                i += 1;
+            }
+        }
+    }
+
+    regex.push_str("\\z");
+    regex
+}
+
+fn glob_char(c: char, case_sensitive: bool, in_class: bool) -> String {
+    let chars = if !case_sensitive && c.is_ascii_alphabetic() {
+        format!(
+            "{}{}",
+            class_char(c.to_ascii_lowercase()),
+            class_char(c.to_ascii_uppercase())
+        )
+    } else {
+        class_char(c)
+    };
+
+    if in_class {
+        chars
+    } else {
+        format!("[{}]", chars)
+    }
+}
+
+fn glob_range(start: char, end: char, case_sensitive: bool) -> String {
+    let mut class = String::new();
+    if start <= end {
+        class.push_str(&class_range(start, end));
+    }
+
+    // like glob, ranges between two ASCII letters are also matched
+    // case-insensitively
+    if !case_sensitive && start.is_ascii_alphabetic() && end.is_ascii_alphabetic() {
+        let (start, end) = (start.to_ascii_lowercase(), end.to_ascii_lowercase());
+        if start <= end {
+            class.push_str(&class_range(start, end));
+            class.push_str(&class_range(
+                start.to_ascii_uppercase(),
+                end.to_ascii_uppercase(),
+            ));
+        }
+    }
+    class
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aws_sdk_s3::types::ObjectStorageClass;
     use std::{str::FromStr, time::Duration};
 
+    #[test]
+    fn test_key_extension() {
+        assert_eq!(key_extension("logs/a.parquet"), Some("parquet"));
+        assert_eq!(key_extension("backup/data.tar.gz"), Some("gz"));
+        assert_eq!(key_extension("home/.profile"), None);
+        assert_eq!(key_extension("home/.config.json"), Some("json"));
+        assert_eq!(key_extension("bin/README"), None);
+        assert_eq!(key_extension("tmp/name."), None);
+        assert_eq!(key_extension("v1.2/data"), None);
+        assert_eq!(key_extension("dir/"), None);
+    }
+
+    #[test]
+    fn test_extensions() -> Result<(), anyhow::Error> {
+        let ext: Extensions = "Parquet, .csv".parse()?;
+        let object = |key: &str| Object::builder().key(key).build();
+
+        assert!(ext.filter(&object("a/b.PARQUET")));
+        assert!(ext.filter(&object("a/b.csv")));
+        assert!(!ext.filter(&object("a/b.json")));
+        assert!(!ext.filter(&object("a/.csv")));
+        assert_eq!(ext.flag(), "--ext csv,parquet");
+        assert!("csv,".parse::<Extensions>().is_err());
+        assert!("tar.gz".parse::<Extensions>().is_err());
+
+        assert!(NoExtension.filter(&object("a/.profile")));
+        assert!(NoExtension.filter(&object("a/README")));
+        assert!(!NoExtension.filter(&object("a/b.csv")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_set() -> Result<(), anyhow::Error> {
+        let file = IgnoreFile::parse("ignore", "tmp/\n*.bak\n!keep.bak\n*.log\n")?;
+        let set = IgnoreSet::new(&file, "data/")?;
+
+        assert!(set.ignores("data/tmp/a.csv"));
+        assert!(set.ignores("data/old/a.bak"));
+        assert!(!set.ignores("data/keep.bak"));
+        assert!(!set.ignores("data/a.csv"));
+        // matched below the search prefix only
+        assert!(!set.ignores("data/x/tmp/a.csv"));
+        assert!(set.ignores("data/x/tmp/a.log"));
+        assert_eq!(set.cost(), FilterCost::Ignore);
+        Ok(())
+    }
+
+    #[test]
+    fn filter_flags() -> Result<(), anyhow::Error> {
+        assert_eq!(FindSize::Bigger(5 * 1024).flag(), "--size +5k");
+        assert_eq!(FindSize::Lower(3 * 1024_i64.pow(3)).flag(), "--size -3G");
+        assert_eq!(FindSize::Equal(1500).flag(), "--size 1500");
+        assert_eq!(FindSize::Equal(0).flag(), "--size 0");
+        assert_eq!(FindTime::Upper(5 * 86_400).flag(), "--mtime -5d");
+        assert_eq!(FindTime::Lower(2 * 604_800).flag(), "--mtime +2w");
+        assert_eq!(FindTime::Lower(90).flag(), "--mtime +90s");
+        assert_eq!(Pattern::new("*.log")?.flag(), "--name *.log");
+        assert_eq!("*.LOG".parse::<InameGlob>()?.flag(), "--iname *.LOG");
+        assert_eq!(Regex::new(r"\d+")?.flag(), r"--regex \d+");
+        assert_eq!(
+            "name=*.log and size=+1k".parse::<WhereExpr>()?.flag(),
+            "--where (name matches *.log and size >= 1024 bytes)"
+        );
+        assert_eq!(
+            KeySample {
+                rate: 0.01,
+                seed: 7
+            }
+            .flag(),
+            "--sample-rate 0.01 --sample-seed 7"
+        );
+        assert_eq!(
+            PercentileCutoff::Oldest(10.0, 0).flag(),
+            "--oldest-percent 10"
+        );
+        let patterns = vec![Pattern::new("a*")?, Pattern::new("*b")?];
+        assert_eq!(GlobSet::new(&patterns, false)?.flag(), "--iname (2 globs)");
+        Ok(())
+    }
+
+    #[test]
+    fn exclude_destination_filter() {
+        let filter = ExcludeDestination("data/backup/".to_owned());
+        assert!(filter.filter(&Object::builder().key("data/a.csv").build()));
+        assert!(!filter.filter(&Object::builder().key("data/backup/data/a.csv").build()));
+    }
+
     #[test]
     fn findsize_filter() {
         let object = Object::builder().size(10).build();
@@ -93,6 +1096,123 @@ mod tests {
         assert!(!FindTime::Upper(10).filter(&object));
     }
 
+    #[test]
+    fn matches_mtime_boundaries() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let modified = |age: i64| Some(DateTime::from_secs(1_700_000_000 - age));
+        let day = 24 * 3600;
+
+        // exactly N seconds old is in both ranges
+        assert!(matches_mtime(
+            &FindTime::Upper(day),
+            modified(day).as_ref(),
+            now
+        ));
+        assert!(matches_mtime(
+            &FindTime::Lower(day),
+            modified(day).as_ref(),
+            now
+        ));
+        assert!(!matches_mtime(
+            &FindTime::Upper(day),
+            modified(day + 1).as_ref(),
+            now
+        ));
+        assert!(!matches_mtime(
+            &FindTime::Lower(day),
+            modified(day - 1).as_ref(),
+            now
+        ));
+
+        // -1d takes an object modified 23 hours ago
+        assert!(matches_mtime(
+            &FindTime::Upper(day),
+            modified(23 * 3600).as_ref(),
+            now
+        ));
+        assert!(!matches_mtime(
+            &FindTime::Lower(day),
+            modified(23 * 3600).as_ref(),
+            now
+        ));
+
+        // a subsecond modification time does not round up the age
+        let subsec = DateTime::from_secs_and_nanos(1_700_000_000 - 10, 999_000_000);
+        assert!(matches_mtime(&FindTime::Upper(10), Some(&subsec), now));
+        assert!(!matches_mtime(&FindTime::Upper(9), Some(&subsec), now));
+
+        // clock skew: modified in the future is a zero age
+        assert!(matches_mtime(
+            &FindTime::Upper(60),
+            modified(-30).as_ref(),
+            now
+        ));
+        assert!(matches_mtime(
+            &FindTime::Upper(0),
+            modified(-30).as_ref(),
+            now
+        ));
+        assert!(!matches_mtime(
+            &FindTime::Lower(60),
+            modified(-30).as_ref(),
+            now
+        ));
+
+        // no modification time, taken as modified at the epoch
+        assert!(!matches_mtime(&FindTime::Upper(day), None, now));
+        assert!(matches_mtime(&FindTime::Lower(day), None, now));
+        assert!(matches_mtime(&FindTime::Lower(0), None, now));
+        assert!(FindTime::Lower(day).filter(&Object::builder().key("a").build()));
+    }
+
+    #[test]
+    fn at_reference_filter() {
+        let reference = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let object = Object::builder()
+            .key("a")
+            .last_modified(DateTime::from_secs(1_700_000_000 - 3600))
+            .build();
+        let expr: WhereExpr = "name=a and mtime=-1h".parse().unwrap();
+
+        // the listing time is long past, the reference keeps the key a match
+        assert!(!FindTime::Upper(3600).filter(&object));
+        assert!(AtReference {
+            filter: FindTime::Upper(3600),
+            reference
+        }
+        .filter(&object));
+        assert!(AtReference {
+            filter: &expr,
+            reference
+        }
+        .filter(&object));
+        assert!(!expr.filter_at(&object, reference + Duration::from_secs(1)));
+        assert!(!expr.filter(&object));
+    }
+
+    #[test]
+    fn where_expr_filter() {
+        let expr: WhereExpr =
+            "(name=*.log and size=+1k and not depth>2) or (storage_class=GLACIER and etag=abc)"
+                .parse()
+                .unwrap();
+        let object = |key: &str, size: i64, class: Option<&str>, e_tag: &str| {
+            Object::builder()
+                .key(key)
+                .size(size)
+                .set_storage_class(class.map(ObjectStorageClass::from))
+                .e_tag(e_tag)
+                .build()
+        };
+
+        assert!(expr.filter(&object("logs/app.log", 2048, None, "\"x\"")));
+        assert!(!expr.filter(&object("logs/app.log", 512, None, "\"x\"")));
+        assert!(!expr.filter(&object("a/b/c/app.log", 2048, None, "\"x\"")));
+        assert!(expr.filter(&object("a/b/c/app.csv", 10, Some("GLACIER"), "\"abc\"")));
+        assert!(!expr.filter(&object("a/b/c/app.csv", 10, Some("GLACIER"), "\"abd\"")));
+        assert!(!expr.filter(&object("app.csv", 10, None, "\"abc\"")));
+    }
+
     #[test]
     fn nameglob_filter() {
         let object = Object::builder().key("some_key").build();
@@ -119,6 +1239,117 @@ mod tests {
         assert!(!InameGlob::from_str("other").unwrap().filter(&object));
     }
 
+    #[test]
+    fn literal_brackets_filter() {
+        let report = Object::builder().key("exports/report[2024].csv").build();
+        let other = Object::builder().key("exports/report2.csv").build();
+
+        let escaped = glob_pattern(r"*report\[2024\].csv").unwrap();
+        assert!(escaped.filter(&report));
+        assert!(!escaped.filter(&other));
+
+        let set = GlobSet::new(Some(&escaped), true).unwrap();
+        assert!(set.filter(&report));
+        assert!(!set.filter(&other));
+
+        let literal = NameLiteral("report[2024].csv".to_owned());
+        assert!(literal.filter(&report));
+        assert!(!literal.filter(&other));
+    }
+
+    #[test]
+    fn reservoir_quantiles() {
+        let mut exact = Reservoir::new(100);
+        for value in (1..=10).rev() {
+            exact.add(value);
+        }
+        assert_eq!(exact.quantile(0.1), Some(1));
+        assert_eq!(exact.quantile(0.25), Some(3));
+        assert_eq!(exact.quantile(1.0), Some(10));
+        assert_eq!(Reservoir::new(10).quantile(0.5), None);
+
+        // the rank of the estimate is within 1% of the values
+        let n = 200_000;
+        let rank = |values: &dyn Fn(i64) -> i64, estimate: i64| {
+            (0..n).filter(|x| values(*x) <= estimate).count() as f64 / n as f64
+        };
+        let distributions: [&dyn Fn(i64) -> i64; 3] = [&|x| x, &|x| (x * 7919) % n, &|x| {
+            ((x as f64 / n as f64).powi(4) * 1e9) as i64
+        }];
+        for values in distributions.iter() {
+            let mut sample = Reservoir::new(10_000);
+            for x in 0..n {
+                sample.add(values(x));
+            }
+            for fraction in [0.01, 0.1, 0.5, 0.9] {
+                let estimate = sample.quantile(fraction).unwrap();
+                assert!((rank(*values, estimate) - fraction).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn percentile_cutoff_filter() {
+        let object = |size: i64, secs: Option<i64>| {
+            Object::builder()
+                .key("key")
+                .size(size)
+                .set_last_modified(secs.map(DateTime::from_secs))
+                .build()
+        };
+
+        let oldest = PercentileCutoff::Oldest(10.0, 1000);
+        assert!(oldest.filter(&object(1, Some(1000))));
+        assert!(!oldest.filter(&object(1, Some(1001))));
+        assert!(!oldest.filter(&object(1, None)));
+
+        let largest = PercentileCutoff::Largest(10.0, 100);
+        assert!(largest.filter(&object(100, None)));
+        assert!(!largest.filter(&object(99, None)));
+    }
+
+    #[test]
+    fn inameglob_case_folding() {
+        let matches = |pattern: &str, key: &str| {
+            let object = Object::builder().key(key).build();
+            let iname = InameGlob::from_str(pattern).unwrap();
+            let set = GlobSet::new(Some(&iname.0), false).unwrap();
+            assert_eq!(
+                iname.filter(&object),
+                set.filter(&object),
+                "{} {}",
+                pattern,
+                key
+            );
+            iname.filter(&object)
+        };
+
+        assert!(matches("*.TXT", "dir/file.txt"));
+        assert!(matches("*.txt", "DIR/FILE.TXT"));
+        assert!(matches("Dir/[a-c]*.Log", "dIR/B.lOG"));
+        assert!(!matches("*.TXT", "file.txt.gz"));
+
+        // only ASCII letters are folded, other letters match their own case
+        assert!(matches("*ß*", "straße"));
+        assert!(!matches("STRASSE", "straße"));
+        assert!(!matches("STRAẞE", "straße"));
+        assert!(!matches("*É*", "café"));
+        assert!(matches("*É*", "CAFÉ"));
+    }
+
+    #[test]
+    fn prefixes_filter() {
+        let prefixes = PrefixSet::new(vec!["hold/".to_owned()]);
+        let held = Object::builder().key("hold/file").build();
+        let other = Object::builder().key("other/file").build();
+
+        assert!(!ExcludePrefixes(prefixes.clone()).filter(&held));
+        assert!(ExcludePrefixes(prefixes.clone()).filter(&other));
+
+        assert!(IncludePrefixes(prefixes.clone()).filter(&held));
+        assert!(!IncludePrefixes(prefixes).filter(&other));
+    }
+
     #[test]
     fn regex_filter() {
         let object = Object::builder().key("some_key").build();
@@ -135,4 +1366,190 @@ mod tests {
         assert!(!Regex::from_str("Ome").unwrap().filter(&object));
         assert!(!Regex::from_str("some_Key").unwrap().filter(&object));
     }
+
+    /// Deterministic pseudo-random strings over an alphabet
+    struct Corpus(u64);
+
+    impl Corpus {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn string(&mut self, alphabet: &[char], max_len: u64) -> String {
+            let len = self.next() % (max_len + 1);
+            (0..len)
+                .map(|_| alphabet[(self.next() % alphabet.len() as u64) as usize])
+                .collect()
+        }
+    }
+
+    #[test]
+    fn globset_equivalence() {
+        let pattern_chars = [
+            'a', 'b', 'Z', 'é', '/', '.', '-', '*', '*', '?', '[', ']', '!',
+        ];
+        let key_chars = ['a', 'b', 'B', 'z', 'É', 'é', '/', '.', '-', '[', '\n'];
+        let mut corpus = Corpus(0x2545_f491_4f6c_dd1d);
+
+        let mut patterns = Vec::new();
+        while patterns.len() < 2000 {
+            if let Ok(pattern) = Pattern::new(&corpus.string(&pattern_chars, 8)) {
+                patterns.push(pattern);
+            }
+        }
+        let keys: Vec<_> = (0..200)
+            .map(|_| Object::builder().key(corpus.string(&key_chars, 10)).build())
+            .collect();
+
+        for pattern in &patterns {
+            let set = GlobSet::new(Some(pattern), true).unwrap();
+            let iset = GlobSet::new(Some(pattern), false).unwrap();
+            let iname = InameGlob(pattern.clone());
+
+            for key in &keys {
+                assert_eq!(
+                    set.filter(key),
+                    pattern.filter(key),
+                    "{} {:?}",
+                    pattern,
+                    key.key
+                );
+                assert_eq!(
+                    iset.filter(key),
+                    iname.filter(key),
+                    "{} {:?}",
+                    pattern,
+                    key.key
+                );
+            }
+        }
+
+        for patterns in patterns.chunks(3) {
+            let set = GlobSet::new(patterns, true).unwrap();
+            for key in &keys {
+                assert_eq!(
+                    set.filter(key),
+                    patterns.iter().all(|x| x.filter(key)),
+                    "{:?} {:?}",
+                    patterns,
+                    key.key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn globset_recursive() {
+        let set =
+            |pattern: &str| GlobSet::new(Some(&Pattern::new(pattern).unwrap()), true).unwrap();
+        let key = |key: &str| Object::builder().key(key).build();
+
+        assert!(set("a/**/b").filter(&key("a/b")));
+        assert!(set("a/**/b").filter(&key("a/x/y/b")));
+        assert!(!set("a/**/b").filter(&key("a/xb")));
+        assert!(set("**/b").filter(&key("b")));
+        assert!(set("a/**").filter(&key("a/x/y")));
+        assert!(set("[!]]").filter(&key("a")));
+        assert!(!set("[!]]").filter(&key("]")));
+    }
+
+    #[test]
+    fn key_sample() {
+        let keys: Vec<_> = (0..10_000)
+            .map(|x| format!("data/part-{}.csv", x))
+            .collect();
+        let sample = |rate, seed| {
+            let sample = KeySample { rate, seed };
+            keys.iter()
+                .filter(|x| sample.selects(x))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let selected = sample(0.1, 42);
+        assert!((900..1100).contains(&selected.len()), "{}", selected.len());
+        assert_eq!(sample(0.1, 42), selected);
+        assert_ne!(sample(0.1, 7), selected);
+        // a lower rate selects a subset of the keys of a higher one
+        assert!(sample(0.05, 42).iter().all(|x| selected.contains(x)));
+        assert_eq!(sample(1.0, 42).len(), keys.len());
+
+        let filter = KeySample {
+            rate: 0.1,
+            seed: 42,
+        };
+        let object = Object::builder().key(&selected[0]).build();
+        assert!(filter.filter(&object));
+    }
+
+    #[test]
+    fn key_shard() {
+        let keys: Vec<_> = (0..10_000)
+            .map(|x| format!("data/part-{}.csv", x))
+            .collect();
+        let shards: Vec<_> = (0..4)
+            .map(|index| KeyShard(Shard { index, count: 4 }))
+            .collect();
+
+        // every key is in exactly one shard, the shards are about even
+        for key in &keys {
+            assert_eq!(shards.iter().filter(|x| x.selects(key)).count(), 1);
+        }
+        for shard in &shards {
+            let count = keys.iter().filter(|x| shard.selects(x)).count();
+            assert!((2300..2700).contains(&count), "{}", count);
+        }
+        assert!(keys
+            .iter()
+            .all(|x| KeyShard(Shard { index: 0, count: 1 }).selects(x)));
+        assert_eq!(shards[2].flag(), "--shard 2/4");
+    }
+
+    /// cargo test --release globset_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn globset_benchmark() {
+        use std::time::Instant;
+
+        // every key matches every pattern, the worst case for separate filters
+        let keys: Vec<_> = (0..10_000)
+            .map(|x| {
+                Object::builder()
+                    .key(format!("lake/data/0123456789/part-{}.parquet", x))
+                    .build()
+            })
+            .collect();
+        let repeated: Vec<_> = (0..500)
+            .map(|x| Pattern::new(&format!("lake/*/{}*/*.parquet", x % 10)).unwrap())
+            .collect();
+        let distinct: Vec<_> = (0..500)
+            .map(|x| {
+                let c = std::char::from_u32(0x100 + x).unwrap();
+                Pattern::new(&format!("lake/*[!{}]*.parquet", c)).unwrap()
+            })
+            .collect();
+
+        for (name, patterns) in [("repeated", repeated), ("distinct", distinct)] {
+            let start = Instant::now();
+            let separate = keys
+                .iter()
+                .filter(|key| patterns.iter().all(|x| x.filter(key)))
+                .count();
+            let separate_time = start.elapsed();
+
+            let set = GlobSet::new(&patterns, true).unwrap();
+            let start = Instant::now();
+            let compiled = keys.iter().filter(|key| set.filter(key)).count();
+            let compiled_time = start.elapsed();
+
+            assert_eq!(separate, compiled);
+            println!(
+                "500 {} patterns, 10000 keys: separate {:?}, GlobSet {:?}",
+                name, separate_time, compiled_time
+            );
+        }
+    }
 }
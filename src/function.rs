@@ -1,26 +1,60 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::process::ExitStatus;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use anyhow::Error;
 use async_trait::async_trait;
 use aws_smithy_types::date_time::Format;
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::StreamExt;
+use humansize::*;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::json;
 
-use aws_sdk_s3::types::{Delete, Object, ObjectCannedAcl, ObjectIdentifier, Tag, Tagging};
+use aws_sdk_s3::config::Region;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::copy_object::CopyObjectError;
+use aws_sdk_s3::operation::get_object_acl::{GetObjectAclError, GetObjectAclOutput};
+use aws_sdk_s3::operation::get_object_tagging::GetObjectTaggingError;
+use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectOutput};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{
+    BucketVersioningStatus, CompletedMultipartUpload, CompletedPart, Delete, Grant,
+    MetadataDirective, Object, ObjectCannedAcl, ObjectIdentifier, Owner, RestoreRequest,
+    StorageClass, Tag, Tagging,
+};
 use aws_sdk_s3::Client;
+use uuid::Uuid;
 
 use crate::arg::*;
+use crate::command::{
+    FindStream, PageSizeTuning, COMMAND_FAILED_EXIT_CODE, PLAN_COLLISION_EXIT_CODE,
+};
+use crate::datetime::{format_time, use_timezone};
 use crate::error::*;
-use crate::utils::combine_keys;
+use crate::export::ExportCommand;
+use crate::output::{OutputTarget, OutputWriter, UploadHeaders};
+use crate::plan::RequestRate;
+use crate::style::{storage_class_style, Style};
+use crate::utils::{
+    combine_keys, copy_source, display_key, error_context, listed_terminal_key, retry_with_backoff,
+    s3_error, terminal_key, throttled, KeyBytes,
+};
 
 impl Cmd {
     pub fn downcast(self) -> Box<dyn RunCommand> {
         match self {
-            Cmd::Print(l) => Box::new(l),
+            Cmd::Print(l) => Box::new(PrintCommand::new(l)),
             Cmd::Ls(l) => Box::new(l),
             Cmd::Exec(l) => Box::new(l),
             Cmd::Delete(l) => Box::new(l),
@@ -30,10 +64,326 @@ impl Cmd {
             Cmd::Public(l) => Box::new(l),
             Cmd::Copy(l) => Box::new(l),
             Cmd::Move(l) => Box::new(l),
+            Cmd::PlanCopy(l) => Box::new(l),
+            Cmd::EnforceStorage(l) => Box::new(l),
+            Cmd::SimulateLifecycle(l) => Box::new(l),
+            Cmd::SuggestLifecycle(l) => Box::new(l),
+            Cmd::Route(l) => Box::new(l),
+            Cmd::Trash(l) => Box::new(l),
+            Cmd::Untrash(l) => Box::new(l),
+            Cmd::Recompress(l) => Box::new(l),
+            Cmd::Touch(l) => Box::new(l),
+            Cmd::DiffTags(l) => Box::new(l),
+            Cmd::Verify(l) => Box::new(l),
+            Cmd::AclReport(l) => Box::new(l),
+            Cmd::Export(l) => Box::new(ExportCommand::new(l)),
+            Cmd::ListMultipart(l) => Box::new(l),
+            Cmd::Restore(l) => Box::new(l),
+            Cmd::Report(Report::KeyDistribution(l)) => Box::new(l),
             Cmd::Nothing(l) => Box::new(l),
             // _ => Box::new(FastPrint {}),
         }
     }
+
+    /// Points the commands operating on a single key at one of its versions
+    pub fn with_version_id(self, version_id: Option<String>) -> Cmd {
+        match self {
+            Cmd::Download(cmd) => Cmd::Download(Download { version_id, ..cmd }),
+            Cmd::LsTags(_) => Cmd::LsTags(ListTags { version_id }),
+            Cmd::Tags(cmd) => Cmd::Tags(SetTags { version_id, ..cmd }),
+            cmd => cmd,
+        }
+    }
+
+    /// The command with its text output colored by --color: ls on
+    /// stdout, print unless it writes to a file or S3
+    pub fn with_color(self, color: ColorMode, terminal: bool) -> Cmd {
+        match self {
+            Cmd::Ls(cmd) => Cmd::Ls(FastPrint {
+                color: color.enabled(terminal),
+                ..cmd
+            }),
+            Cmd::Print(cmd) => {
+                let stdout = cmd.output_file.is_none() && cmd.output_s3.is_none();
+                Cmd::Print(AdvancedPrint {
+                    color: stdout && color.enabled(terminal),
+                    ..cmd
+                })
+            }
+            cmd => cmd,
+        }
+    }
+
+    /// Whether the command acts on the single key of a key path, or a
+    /// version of it, instead of the keys listed under the path
+    pub fn single_object(&self, path: &S3Path) -> bool {
+        path.key().is_some() && matches!(self, Cmd::Download(_) | Cmd::LsTags(_) | Cmd::Tags(_))
+    }
+
+    /// Whether the command reads only the keys of the objects, so the keys
+    /// of --stdin need no HEAD request for it
+    pub fn key_only(&self) -> bool {
+        matches!(
+            self,
+            Cmd::Ls(_)
+                | Cmd::LsTags(_)
+                | Cmd::Tags(_)
+                | Cmd::Public(_)
+                | Cmd::Nothing(_)
+                | Cmd::Delete(MultipleDelete {
+                    if_unmodified: false,
+                    snapshot_guard: false,
+                    ..
+                })
+        )
+    }
+
+    /// Whether the command prints json lines
+    pub fn json_output(&self) -> bool {
+        matches!(
+            self,
+            Cmd::DiffTags(DiffTags {
+                format: OutputFormat::Json,
+                ..
+            }) | Cmd::Verify(Verify {
+                format: OutputFormat::Json,
+                ..
+            }) | Cmd::AclReport(AclReport {
+                format: OutputFormat::Json,
+                ..
+            })
+        )
+    }
+
+    /// Whether the command downloads the matched keys to the local filesystem
+    pub fn downloads(&self) -> bool {
+        matches!(
+            self,
+            Cmd::Download(_)
+                | Cmd::Exec(Exec {
+                    download_temp: true,
+                    ..
+                })
+                | Cmd::Copy(S3Copy {
+                    destination: CopyDestination::Local(_),
+                    ..
+                })
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cmd::Print(_) => "print",
+            Cmd::Ls(_) => "ls",
+            Cmd::Exec(_) => "exec",
+            Cmd::Delete(_) => "delete",
+            Cmd::Download(_) => "download",
+            Cmd::Tags(_) => "tags",
+            Cmd::LsTags(_) => "lstags",
+            Cmd::Public(_) => "public",
+            Cmd::Copy(_) => "copy",
+            Cmd::Move(_) => "move",
+            Cmd::PlanCopy(_) => "plan-copy",
+            Cmd::EnforceStorage(_) => "enforce-storage",
+            Cmd::SimulateLifecycle(_) => "simulate-lifecycle",
+            Cmd::SuggestLifecycle(_) => "suggest-lifecycle",
+            Cmd::Route(_) => "route",
+            Cmd::Trash(_) => "trash",
+            Cmd::Untrash(_) => "untrash",
+            Cmd::Recompress(_) => "recompress",
+            Cmd::Touch(_) => "touch",
+            Cmd::DiffTags(_) => "diff-tags",
+            Cmd::Verify(_) => "verify",
+            Cmd::AclReport(_) => "acl-report",
+            Cmd::Export(_) => "export",
+            Cmd::ListMultipart(_) => "list-multipart",
+            Cmd::Restore(_) => "restore",
+            Cmd::Report(Report::KeyDistribution(_)) => "key-distribution",
+            Cmd::Nothing(_) => "nothing",
+        }
+    }
+
+    /// The destination prefix excluded from the listing of a copy or move
+    /// into the source prefix, unless --no-auto-exclude is set
+    pub fn auto_exclude(&self, path: &S3Path) -> Option<String> {
+        let destination = match self {
+            Cmd::Copy(S3Copy {
+                destination: CopyDestination::S3(destination),
+                no_auto_exclude: false,
+                ..
+            })
+            | Cmd::Move(S3Move {
+                destination,
+                no_auto_exclude: false,
+                ..
+            })
+            | Cmd::PlanCopy(PlanCopy {
+                destination,
+                no_auto_exclude: false,
+                ..
+            }) => destination,
+            _ => return None,
+        };
+        nested_destination(path, destination)
+    }
+
+    /// The S3 path a copy or move writes to
+    pub fn s3_destination(&self) -> Option<&S3Path> {
+        match self {
+            Cmd::Copy(S3Copy {
+                destination: CopyDestination::S3(destination),
+                ..
+            })
+            | Cmd::Move(S3Move { destination, .. }) => Some(destination),
+            _ => None,
+        }
+    }
+
+    /// Whether the command changes objects in S3
+    pub fn mutates(&self) -> bool {
+        match self {
+            Cmd::Delete(_)
+            | Cmd::Tags(_)
+            | Cmd::Public(_)
+            | Cmd::Move(_)
+            | Cmd::Route(_)
+            | Cmd::Trash(_)
+            | Cmd::Untrash(_)
+            | Cmd::Recompress(_)
+            | Cmd::Touch(_) => true,
+            Cmd::Copy(cmd) => matches!(cmd.destination, CopyDestination::S3(_)),
+            Cmd::EnforceStorage(cmd) => cmd.apply,
+            Cmd::SuggestLifecycle(cmd) => cmd.apply,
+            Cmd::ListMultipart(cmd) => cmd.abort,
+            _ => false,
+        }
+    }
+
+    /// The S3 requests the command issues besides the listing
+    pub fn requests(&self) -> Vec<(&'static str, RequestRate)> {
+        use RequestRate::*;
+
+        match self {
+            Cmd::Exec(cmd) if cmd.download_temp => vec![("GetObject", PerObject)],
+            Cmd::Delete(cmd) => {
+                let mut requests = Vec::new();
+                if !cmd.quiet_versioning_warning {
+                    requests.push(("GetBucketVersioning", Once));
+                }
+                if cmd.if_unmodified {
+                    requests.push(("HeadObject", PerObject));
+                }
+                if cmd.snapshot_guard {
+                    // HeadObject only for changed keys or without If-Match
+                    requests.push(("DeleteObject", PerObject));
+                } else {
+                    requests.push(("DeleteObjects", PerPage));
+                }
+                requests
+            }
+            Cmd::Download(cmd) if cmd.verify_size_with_head => {
+                vec![("HeadObject", PerObject), ("GetObject", PerObject)]
+            }
+            Cmd::Download(_) => vec![("GetObject", PerObject)],
+            Cmd::Tags(_) => vec![("PutObjectTagging", PerObject)],
+            Cmd::LsTags(_) => vec![("GetObjectTagging", PerObject)],
+            Cmd::Public(_) => vec![("GetPublicAccessBlock", Once), ("PutObjectAcl", PerObject)],
+            Cmd::Copy(cmd) => match cmd.destination {
+                CopyDestination::Local(_) => vec![("GetObject", PerObject)],
+                CopyDestination::S3(_) => {
+                    let mut requests = Vec::new();
+                    if !cmd.no_preflight {
+                        requests.push(("HeadBucket", Once));
+                    }
+                    if cmd.if_unmodified {
+                        requests.push(("HeadObject", PerObject));
+                    }
+                    requests.push(("CopyObject", PerObject));
+                    requests
+                }
+            },
+            Cmd::Move(cmd) => {
+                let mut requests = Vec::new();
+                if !cmd.quiet_versioning_warning {
+                    requests.push(("GetBucketVersioning", Once));
+                }
+                if !cmd.no_preflight {
+                    requests.push(("HeadBucket", Once));
+                }
+                if cmd.if_unmodified {
+                    requests.push(("HeadObject", PerObject));
+                }
+                requests.push(("CopyObject", PerObject));
+                requests.push(("DeleteObjects", PerPage));
+                requests
+            }
+            Cmd::EnforceStorage(cmd) if cmd.apply => vec![("CopyObject", AtMostPerObject)],
+            Cmd::SuggestLifecycle(cmd) if cmd.apply => {
+                vec![("PutBucketLifecycleConfiguration", Once)]
+            }
+            Cmd::Route(cmd) => {
+                let mut requests = Vec::new();
+                if !cmd.no_preflight {
+                    requests.push(("HeadBucket", Once));
+                }
+                requests.push(("GetObjectTagging", PerObject));
+                requests.push(("CopyObject", AtMostPerObject));
+                if cmd.move_keys {
+                    requests.push(("DeleteObjects", PerPage));
+                }
+                requests
+            }
+            Cmd::Trash(cmd) => {
+                let mut requests = Vec::new();
+                if cmd.if_unmodified {
+                    requests.push(("HeadObject", PerObject));
+                }
+                requests.push(("CopyObject", AtMostPerObject));
+                requests.push(("DeleteObjects", PerPage));
+                requests
+            }
+            Cmd::Untrash(_) => vec![("CopyObject", AtMostPerObject), ("DeleteObjects", PerPage)],
+            Cmd::Recompress(cmd) => {
+                let mut requests = vec![
+                    ("HeadObject", PerObject),
+                    ("GetObject", AtMostPerObject),
+                    ("PutObject or multipart upload", AtMostPerObject),
+                    ("HeadObject on the target", AtMostPerObject),
+                ];
+                if cmd.delete_source {
+                    requests.push(("DeleteObject", AtMostPerObject));
+                }
+                requests
+            }
+            Cmd::Touch(_) => vec![
+                ("HeadObject", PerObject),
+                ("CopyObject, or a multipart copy above 5 GiB", PerObject),
+            ],
+            Cmd::DiffTags(_) => vec![
+                ("GetBucketLocation", Once),
+                ("GetObjectTagging", PerObject),
+                ("GetObjectTagging on the target", PerObject),
+                ("ListObjectsV2 on the target", PerPage),
+            ],
+            Cmd::Verify(_) => vec![("HeadObject", PerObject)],
+            Cmd::AclReport(_) => vec![("GetObjectAcl", PerObject)],
+            Cmd::Restore(_) => vec![("RestoreObject", PerObject)],
+            Cmd::Export(cmd) if cmd.include_tags => vec![("GetObjectTagging", PerObject)],
+            Cmd::ListMultipart(cmd) if cmd.abort => vec![
+                ("ListParts", PerObject),
+                ("AbortMultipartUpload", PerObject),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the command sends the matched keys in S3 requests, which
+    /// cannot address a key listed with bytes that are not valid UTF-8
+    pub fn requests_keys(&self) -> bool {
+        self.requests()
+            .iter()
+            .any(|(_, rate)| *rate != RequestRate::Once)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -42,39 +392,165 @@ pub struct ExecStatus {
     pub runcommand: String,
 }
 
+/// What a command did with a batch of objects, accumulated by the driver
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CommandStats {
+    pub processed: usize,
+    pub deleted: usize,
+    /// The listed sizes of the deleted keys and of the sources of the
+    /// moved keys
+    pub reclaimed_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub skipped: usize,
+    /// Keys listed with a size of 0 whose size was taken from HEAD
+    pub size_corrected: usize,
+    pub errors: Vec<String>,
+    pub mutations: Vec<Mutation>,
+}
+
+impl CommandStats {
+    pub fn processed(processed: usize) -> Self {
+        CommandStats {
+            processed,
+            ..Default::default()
+        }
+    }
+
+    /// Records a key changed by the batch
+    pub fn mutated(&mut self, action: &'static str, key: &str) {
+        match self.mutations.iter_mut().find(|x| x.action == action) {
+            Some(mutation) => mutation.keys.push(key.to_owned()),
+            None => self.mutations.push(Mutation {
+                action,
+                keys: vec![key.to_owned()],
+            }),
+        }
+    }
+}
+
+/// Keys of the source bucket changed by a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mutation {
+    pub action: &'static str,
+    pub keys: Vec<String>,
+}
+
 #[async_trait]
-pub trait RunCommand {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error>;
+pub trait RunCommand: Send + Sync {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error>;
+
+    /// Runs the command on a batch with the listed bytes of its keys which
+    /// are not valid UTF-8, for the commands printing the keys
+    async fn execute_listed(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+        _key_bytes: &KeyBytes,
+    ) -> Result<CommandStats, Error> {
+        self.execute(client, path, list).await
+    }
+
+    /// Checks run before listing starts, so a misconfigured command fails fast
+    async fn preflight(&self, _client: &Client, _path: &S3Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Whether the listing should include the object owners
+    fn fetch_owner(&self) -> bool {
+        false
+    }
+
+    /// Whether the common prefixes of --one-level are printed as PRE lines
+    fn lists_prefixes(&self) -> bool {
+        false
+    }
+
+    /// Work done once all the batches are executed, before the summary
+    async fn finish(&self, _client: &Client, _path: &S3Path) -> Result<CommandStats, Error> {
+        Ok(CommandStats::default())
+    }
+
+    /// Summary printed once all the batches are executed
+    fn summary(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the command found problems which should fail the run
+    fn failed(&self) -> bool {
+        false
+    }
+
+    /// The exit code of a run the command failed
+    fn exit_code(&self) -> i32 {
+        COMMAND_FAILED_EXIT_CODE
+    }
 }
 
 impl FastPrint {
     #[inline]
-    fn print_object<I: Write>(
+    pub(crate) fn print_object<I: Write>(
         &self,
         io: &mut I,
         bucket: &str,
         object: &Object,
+        key_bytes: Option<&[u8]>,
     ) -> std::io::Result<()> {
+        let key = object.key.as_deref().unwrap_or_default();
+        if self.print0 {
+            write!(io, "s3://{}/", bucket)?;
+            io.write_all(key_bytes.unwrap_or(key.as_bytes()))?;
+            return io.write_all(b"\0");
+        }
+
         writeln!(
             io,
-            "s3://{}/{}",
-            bucket,
-            object.key.clone().unwrap_or_default()
+            "{}{}",
+            Style::DIM.paint(&format!("s3://{}/", bucket), self.color),
+            listed_terminal_key(key, key_bytes)
         )
     }
 }
 
 #[async_trait]
 impl RunCommand for FastPrint {
-    async fn execute(&self, _c: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        self.execute_listed(client, path, list, &KeyBytes::default())
+            .await
+    }
+
+    async fn execute_listed(
+        &self,
+        _c: &Client,
+        path: &S3Path,
+        list: &[Object],
+        key_bytes: &KeyBytes,
+    ) -> Result<CommandStats, Error> {
         let mut stdout = std::io::stdout();
-        for x in list {
-            self.print_object(&mut stdout, &path.bucket, x)?
+        for (i, x) in list.iter().enumerate() {
+            self.print_object(&mut stdout, &path.bucket, x, key_bytes.get(i))?
         }
-        Ok(())
+        Ok(CommandStats::processed(list.len()))
+    }
+
+    fn lists_prefixes(&self) -> bool {
+        !self.print0
     }
 }
 
+/// The width of the --human-readable sizes of print, like 1023.99 KiB
+const HUMAN_SIZE_WIDTH: usize = 11;
+
 impl AdvancedPrint {
     #[inline]
     fn print_object<I: Write>(
@@ -82,40 +558,144 @@ impl AdvancedPrint {
         io: &mut I,
         bucket: &str,
         object: &Object,
+        key_bytes: Option<&[u8]>,
     ) -> std::io::Result<()> {
+        let size = object.size.unwrap_or_default();
+        let size = match self.human_readable {
+            true => format!(
+                "{:>width$}",
+                format_size(size.max(0) as u64, BINARY),
+                width = HUMAN_SIZE_WIDTH
+            ),
+            false => size.to_string(),
+        };
+        let class = format!("{:?}", object.storage_class);
+        let class = match storage_class_style(storage_class(object)) {
+            Some(style) => style.paint(&class, self.color).into_owned(),
+            None => class,
+        };
+
         writeln!(
             io,
-            "{0} {1:?} {2} {3:?} s3://{4}/{5} {6:?}",
+            "{0} {1:?} {2} {3:?} {4}{5} {6}",
             object.e_tag.as_ref().unwrap_or(&"NoEtag".to_string()),
-            object.owner.as_ref().map(|x| x.display_name.as_ref()),
-            object.size.unwrap_or_default(),
-            object.last_modified.unwrap().fmt(Format::DateTime),
-            bucket,
-            object.key.as_ref().unwrap_or(&"".to_string()),
-            object.storage_class,
+            object.owner.as_ref().and_then(owner_name),
+            size,
+            object
+                .last_modified
+                .as_ref()
+                .map_or("NoDate".to_owned(), |x| format_time(
+                    x,
+                    &self.date_format,
+                    &self.timezone
+                )),
+            Style::DIM.paint(&format!("s3://{}/", bucket), self.color),
+            listed_terminal_key(object.key.as_deref().unwrap_or_default(), key_bytes),
+            class,
         )
     }
 }
 
+// the display name is missing in many regions, the canonical id is not
+fn owner_name(owner: &Owner) -> Option<&String> {
+    owner.display_name.as_ref().or(owner.id.as_ref())
+}
+
+/// Print writing to the output destination of its options
+pub struct PrintCommand {
+    pub print: AdvancedPrint,
+    pub output: OutputWriter,
+}
+
+impl PrintCommand {
+    pub fn new(print: AdvancedPrint) -> Self {
+        let output = OutputWriter::new(OutputTarget::from_print(&print));
+        use_timezone(&print.timezone);
+        PrintCommand { print, output }
+    }
+}
+
 #[async_trait]
-impl RunCommand for AdvancedPrint {
-    async fn execute(&self, _c: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        let mut stdout = std::io::stdout();
-        for x in list {
-            self.print_object(&mut stdout, &path.bucket, x)?
+impl RunCommand for PrintCommand {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        self.execute_listed(client, path, list, &KeyBytes::default())
+            .await
+    }
+
+    async fn execute_listed(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+        key_bytes: &KeyBytes,
+    ) -> Result<CommandStats, Error> {
+        let mut buffer = Vec::new();
+        for (i, x) in list.iter().enumerate() {
+            self.print
+                .print_object(&mut buffer, &path.bucket, x, key_bytes.get(i))?
+        }
+
+        if let Err(e) = self.output.write(client, &buffer).await {
+            let error = format!("cannot write the output to {}: {}", self.output.target, e);
+            eprintln!("error: {}", error);
+            return Ok(CommandStats {
+                errors: vec![error],
+                ..Default::default()
+            });
+        }
+        Ok(CommandStats::processed(list.len()))
+    }
+
+    async fn preflight(&self, _client: &Client, _path: &S3Path) -> Result<(), Error> {
+        self.output.open().await
+    }
+
+    fn fetch_owner(&self) -> bool {
+        true
+    }
+
+    /// The PRE lines go to stdout, not into an --output file
+    fn lists_prefixes(&self) -> bool {
+        self.output.target == OutputTarget::Stdout
+    }
+
+    async fn finish(&self, client: &Client, _path: &S3Path) -> Result<CommandStats, Error> {
+        self.output.finish(client).await?;
+        Ok(CommandStats::default())
+    }
+
+    fn summary(&self) -> Option<String> {
+        match &self.output.target {
+            OutputTarget::Stdout => None,
+            target => Some(format!("Output: {}", target)),
         }
-        Ok(())
     }
 }
 
 impl Exec {
     #[inline]
-    fn exec<I: Write>(&self, io: &mut I, key: &str) -> Result<ExecStatus, Error> {
-        let command_str = self.utility.replace("{}", key);
-        let split: Vec<_> = command_str.split(' ').collect();
+    fn exec<I: Write>(
+        &self,
+        io: &mut I,
+        key: &str,
+        key_bytes: Option<&[u8]>,
+        file: Option<&Path>,
+    ) -> Result<ExecStatus, Error> {
+        let mut command_str = self.utility.clone();
+        if let Some(file) = file {
+            let file = file.to_str().ok_or(FunctionError::PathConverError)?;
+            command_str = command_str.replace("{file}", file);
+        }
+        let split = command_words(&command_str, key, key_bytes, !self.no_option_guard);
+        let command_str = command_str.replace("{}", key);
 
         let (command_name, command_args) = match &*split {
-            [command_name, ref command_args @ ..] => (*command_name, command_args),
+            [command_name, ref command_args @ ..] => (command_name, command_args),
             _ => return Err(FunctionError::CommandlineParse.into()),
         };
 
@@ -135,485 +715,6953 @@ impl Exec {
     }
 }
 
-#[async_trait]
-impl RunCommand for Exec {
-    async fn execute(&self, _: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        let mut stdout = std::io::stdout();
-        for x in list {
-            let key = x.key.as_deref().unwrap_or("");
-            let path = format!("s3://{}/{}", &path.bucket, key);
-            self.exec(&mut stdout, &path)?;
+/// The words of an exec command line with `{}` replaced by the key, a word
+/// with the key is a single argument whatever the key contains. With the
+/// guard a `--` argument precedes the first word which is the key alone
+/// when the key starts with `-`, so the utility does not take it for an
+/// option
+fn command_words(command: &str, key: &str, key_bytes: Option<&[u8]>, guard: bool) -> Vec<OsString> {
+    let mut guarded = !(guard && key.starts_with('-'));
+    let mut words = Vec::new();
+    for word in command.split(' ') {
+        if !guarded && word == "{}" {
+            words.push(OsString::from("--"));
+            guarded = true;
         }
-        Ok(())
+        words.push(substituted_word(word, key, key_bytes));
     }
+    words
 }
 
-#[async_trait]
-impl RunCommand for MultipleDelete {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        let key_list: Vec<_> = list
-            .iter()
-            .filter_map(|x| {
-                ObjectIdentifier::builder()
-                    .set_key(x.key.clone())
-                    .build()
-                    .ok()
-            })
-            .collect();
-
-        let objects = Delete::builder().set_objects(Some(key_list)).build()?;
+/// A command word with `{}` replaced by the key; a key which is not valid
+/// UTF-8 is passed with its listed bytes
+#[cfg(unix)]
+fn substituted_word(word: &str, key: &str, key_bytes: Option<&[u8]>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
 
-        client
-            .delete_objects()
-            .bucket(path.bucket.to_owned())
-            .delete(objects)
-            .send()
-            .await
-            .map_or_else(
-                |e| {
-                    eprintln!("{}", e);
-                    Ok(())
-                },
-                |r| {
-                    if let Some(deleted_list) = r.deleted {
-                        for object in deleted_list {
-                            println!(
-                                "deleted: s3://{}/{}",
-                                &path.bucket,
-                                object.key.as_ref().unwrap_or(&"".to_string())
-                            );
-                        }
-                    }
-                    Ok(())
-                },
-            )
+    let bytes = match key_bytes {
+        Some(bytes) => bytes,
+        None => return OsString::from(word.replace("{}", key)),
+    };
+    let mut line = Vec::new();
+    for (i, part) in word.split("{}").enumerate() {
+        if i > 0 {
+            line.extend_from_slice(bytes);
+        }
+        line.extend_from_slice(part.as_bytes());
     }
+    OsString::from_vec(line)
 }
 
-#[async_trait]
-impl RunCommand for SetTags {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        for object in list {
-            let tags = self
-                .tags
-                .iter()
-                .filter_map(|x| {
-                    Tag::builder()
-                        .key(x.key.clone())
-                        .value(x.value.clone())
-                        .build()
-                        .ok()
-                })
-                .collect();
-
-            let tagging = Tagging::builder().set_tag_set(Some(tags)).build().ok();
-
-            client
-                .put_object_tagging()
-                .bucket(path.bucket.to_owned())
-                .set_key(object.key.clone())
-                .set_tagging(tagging)
-                .send()
-                .await?;
+/// A command word with `{}` replaced by the key
+#[cfg(not(unix))]
+fn substituted_word(word: &str, key: &str, _key_bytes: Option<&[u8]>) -> OsString {
+    OsString::from(word.replace("{}", key))
+}
 
-            println!(
-                "tags are set for: s3://{}/{}",
-                &path.bucket,
-                &object.key.clone().unwrap()
-            );
-        }
-        Ok(())
+/// Key of a listed object, an object without a key is reported and skipped
+fn listed_key<'a>(object: &'a Object, stats: &mut CommandStats) -> Option<&'a str> {
+    if object.key.is_none() {
+        let error = FunctionError::ObjectFieldError;
+        eprintln!("error: {}", error);
+        stats.errors.push(error.to_string());
     }
+    object.key.as_deref()
 }
 
-#[async_trait]
-impl RunCommand for ListTags {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        for object in list {
-            let tag_output = client
-                .get_object_tagging()
-                .bucket(path.bucket.clone())
-                .set_key(object.key.clone())
-                .send()
-                .await?;
+impl Exec {
+    async fn exec_list<I: Write + Send>(
+        &self,
+        io: &mut I,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+        key_bytes: &KeyBytes,
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        for (i, x) in list.iter().enumerate() {
+            let key = match listed_key(x, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+            let s3_path = format!("s3://{}/{}", &path.bucket, key);
 
-            let tags: String = tag_output
-                .tag_set
-                .into_iter()
-                .map(|x| format!("{}:{}", x.key, x.value))
-                .collect::<Vec<String>>()
-                .join(",");
+            if !self.download_temp {
+                let path_bytes = key_bytes
+                    .get(i)
+                    .map(|x| [format!("s3://{}/", &path.bucket).as_bytes(), x].concat());
+                self.exec(io, &s3_path, path_bytes.as_deref(), None)?;
+                stats.processed += 1;
+                continue;
+            }
 
-            println!(
-                "s3://{}/{} {}",
-                &path.bucket,
-                object.key.as_ref().unwrap_or(&"".to_string()),
-                tags,
-            );
+            let size = x.size.unwrap_or_default() as u64;
+            if self.max_size.is_some_and(|max_size| size > max_size) {
+                println!(
+                    "skipped: s3://{}/{} (bigger than --max-size)",
+                    &path.bucket,
+                    display_key(key)
+                );
+                stats.skipped += 1;
+                continue;
+            }
+
+            let file_path = temp_file_path(key);
+            let mut count = 0;
+            let options = DownloadOptions {
+                flat: true,
+                force: true,
+                ..Default::default()
+            };
+            let size = x.size.unwrap_or_default() as u64;
+            let result = download_object(client, path, size, key, &file_path, options, &mut count)
+                .await
+                .and_then(|_| self.exec(io, &s3_path, None, Some(&file_path)));
+            stats.downloaded_bytes += count;
+            let _ = fs::remove_file(&file_path);
+
+            match result {
+                Ok(_) => stats.processed += 1,
+                Err(e) => {
+                    eprintln!("error: {}: {}", s3_path, e);
+                    stats.errors.push(format!("{}: {}", s3_path, e));
+                }
+            }
         }
-        Ok(())
+        Ok(stats)
     }
 }
 
-#[inline]
-fn generate_s3_url(region: &str, bucket: &str, key: &str) -> String {
-    match region {
-        "us-east-1" => format!("https://{}.s3.amazonaws.com/{}", bucket, key),
-        _ => format!("https://{}.s3-{}.amazonaws.com/{}", bucket, region, key),
-    }
+/// A unique temporary file keeping the key file name, so the utilities
+/// relying on the extension still work
+fn temp_file_path(key: &str) -> PathBuf {
+    let file_name = Path::new(key)
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!("s3find-{}-{}", Uuid::new_v4(), file_name))
 }
 
 #[async_trait]
-impl RunCommand for SetPublic {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        for object in list {
-            client
-                .put_object_acl()
-                .bucket(path.bucket.to_owned())
-                .set_key(object.key.clone())
-                .acl(ObjectCannedAcl::PublicRead)
-                .send()
-                .await?;
+impl RunCommand for Exec {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        self.execute_listed(client, path, list, &KeyBytes::default())
+            .await
+    }
 
-            let key = object.key.clone().unwrap();
-            let url = generate_s3_url(path.region.as_ref(), &path.bucket, &key);
-            println!("{} {}", key, url);
-        }
-        Ok(())
+    async fn execute_listed(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+        key_bytes: &KeyBytes,
+    ) -> Result<CommandStats, Error> {
+        self.exec_list(&mut std::io::stdout(), client, path, list, key_bytes)
+            .await
     }
 }
 
 #[async_trait]
-impl RunCommand for Download {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        for object in list {
-            let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+impl RunCommand for MultipleDelete {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        if self.snapshot_guard {
+            return Ok(self.guarded_deletes(client, &path.bucket, list).await);
+        }
 
-            let size = object.size.unwrap_or_default() as u64;
-            let file_path = Path::new(&self.destination).join(key);
-            let dir_path = file_path.parent().ok_or(FunctionError::ParentPathParse)?;
+        let mut stats = CommandStats::default();
+        let mut unmodified = Vec::with_capacity(list.len());
 
-            let mut count: u64 = 0;
-            let pb = ProgressBar::new(size);
-            pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .progress_chars("#>-"));
+        for object in list {
+            if !self.if_unmodified {
+                unmodified.push(object);
+                continue;
+            }
 
-            println!(
-                "downloading: s3://{}/{} => {}",
-                &path.bucket,
-                &key,
-                file_path
-                    .to_str()
-                    .ok_or(FunctionError::FileNameParseError)
-                    .unwrap()
-            );
+            match is_unmodified(client, &path.bucket, object).await {
+                Ok(true) => unmodified.push(object),
+                Ok(false) => {
+                    skipped_modified(&path.bucket, object.key.as_deref().unwrap_or_default())
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    stats.errors.push(e.to_string());
+                }
+            }
+        }
 
-            if file_path.exists() && !self.force {
-                return Ok(());
+        if unmodified.is_empty() {
+            return Ok(stats);
+        }
+
+        let mut key_list = Vec::with_capacity(unmodified.len());
+        for object in &unmodified {
+            if let Some(key) = listed_key(object, &mut stats) {
+                key_list.push(ObjectIdentifier::builder().key(key).build()?);
             }
+        }
+        if key_list.is_empty() {
+            return Ok(stats);
+        }
 
-            let mut stream = client
-                .get_object()
-                .bucket(&path.bucket)
-                .key(key)
-                .send()
-                .await?
-                .body;
+        let objects = Delete::builder().set_objects(Some(key_list)).build()?;
 
-            fs::create_dir_all(dir_path)?;
-            let mut output = File::create(&file_path)?;
+        let result = client
+            .delete_objects()
+            .bucket(path.bucket.to_owned())
+            .delete(objects)
+            .send()
+            .await;
 
-            while let Some(bytes) = stream.try_next().await? {
-                output.write_all(&bytes).unwrap();
-                count += bytes.len() as u64;
-                pb.set_position(count);
+        match result {
+            Err(e) => {
+                let error = error_context(&e);
+                eprintln!("{}", error);
+                stats.errors.push(error);
             }
+            Ok(r) => {
+                // the keys of the Errors array are left out of the totals
+                let sizes: HashMap<_, _> = unmodified
+                    .iter()
+                    .filter_map(|x| Some((x.key.as_deref()?, x.size.unwrap_or_default())))
+                    .collect();
+                for object in r.deleted.unwrap_or_default() {
+                    let key = object.key.as_deref().unwrap_or_default();
+                    let size = sizes.get(key).copied().unwrap_or_default() as u64;
+                    println!("deleted: s3://{}/{}", &path.bucket, display_key(key));
+                    stats.mutated("delete", key);
+                    stats.deleted += 1;
+                    stats.processed += 1;
+                    stats.reclaimed_bytes += size;
+                }
+                for e in r.errors.unwrap_or_default() {
+                    let error = format!(
+                        "s3://{}/{}: {}: {}",
+                        &path.bucket,
+                        display_key(e.key.as_deref().unwrap_or_default()),
+                        e.code.as_deref().unwrap_or("Error"),
+                        e.message.as_deref().unwrap_or_default()
+                    );
+                    eprintln!("error: {}", error);
+                    stats.errors.push(error);
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn preflight(&self, client: &Client, path: &S3Path) -> Result<(), Error> {
+        if !self.quiet_versioning_warning {
+            warn_versioned_bucket(client, &path.bucket).await;
         }
         Ok(())
     }
 }
 
+/// The result of a --snapshot-guard delete
+#[derive(Debug, PartialEq)]
+enum GuardedDelete {
+    Deleted,
+    /// The key changed since the listing, with its current ETag, or none
+    /// when it is gone
+    Changed(Option<String>),
+}
+
+impl MultipleDelete {
+    async fn guarded_deletes(
+        &self,
+        client: &Client,
+        bucket: &str,
+        list: &[Object],
+    ) -> CommandStats {
+        let mut stats = CommandStats::default();
+
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+            let listed = object.e_tag.as_deref().unwrap_or_default();
+
+            match self.guarded_delete(client, bucket, key, listed).await {
+                Ok(GuardedDelete::Deleted) => {
+                    println!("deleted: s3://{}/{}", bucket, display_key(key));
+                    stats.mutated("delete", key);
+                    stats.deleted += 1;
+                    stats.processed += 1;
+                    stats.reclaimed_bytes += object.size.unwrap_or_default() as u64;
+                }
+                Ok(GuardedDelete::Changed(current)) => {
+                    let current = current.unwrap_or_else(|| "deleted".to_owned());
+                    println!(
+                        "skipped: s3://{}/{} (ETag changed since listing: {} -> {})",
+                        bucket,
+                        display_key(key),
+                        listed,
+                        current
+                    );
+                    stats.skipped += 1;
+                }
+                Err(e) => {
+                    let error = format!("s3://{}/{}: {}", bucket, display_key(key), e);
+                    eprintln!("{}", error);
+                    stats.errors.push(error);
+                }
+            }
+        }
+        stats
+    }
+
+    /// Deletes the key while it has its listed ETag: a DeleteObject with
+    /// If-Match, or on backends without conditional deletes a HEAD before
+    /// the DeleteObject, which leaves a short window between the two
+    async fn guarded_delete(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        listed: &str,
+    ) -> Result<GuardedDelete, Error> {
+        let unsupported = &self.if_match_unsupported.0;
+
+        if !unsupported.load(Ordering::Relaxed) {
+            let if_match = listed.to_owned();
+            let result = client
+                .delete_object()
+                .bucket(bucket)
+                .key(key)
+                .customize()
+                .mutate_request(move |request| {
+                    request.headers_mut().insert("If-Match", if_match.clone());
+                })
+                .send()
+                .await;
+
+            match result {
+                Ok(_) => return Ok(GuardedDelete::Deleted),
+                Err(e) => match (e.raw_response().map(|r| r.status().as_u16()), e.code()) {
+                    (Some(412), _) | (Some(404), _) => {
+                        return Ok(GuardedDelete::Changed(
+                            current_e_tag(client, bucket, key).await?,
+                        ))
+                    }
+                    (Some(501), _) | (_, Some("NotImplemented")) => {
+                        if !unsupported.swap(true, Ordering::Relaxed) {
+                            eprintln!(
+                                "note: the backend has no conditional DeleteObject, the ETags are compared with HeadObject before every delete"
+                            );
+                        }
+                    }
+                    _ => return Err(s3_error(e)),
+                },
+            }
+        }
+
+        match current_e_tag(client, bucket, key).await? {
+            Some(current) if current == listed => {
+                client
+                    .delete_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map_err(s3_error)?;
+                Ok(GuardedDelete::Deleted)
+            }
+            current => Ok(GuardedDelete::Changed(current)),
+        }
+    }
+}
+
+/// The current ETag of the key, none when it does not exist
+async fn current_e_tag(client: &Client, bucket: &str, key: &str) -> Result<Option<String>, Error> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(head) => Ok(head.e_tag),
+        Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(404) => Ok(None),
+        Err(e) => Err(s3_error(e)),
+    }
+}
+
+impl SetTags {
+    /// The tag set, the same for every key
+    fn tagging(&self) -> Result<Tagging, Error> {
+        let tags = self
+            .tags
+            .iter()
+            .map(|x| Tag::builder().key(&x.key).value(&x.value).build())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Tagging::builder().set_tag_set(Some(tags)).build()?)
+    }
+}
+
 #[async_trait]
-impl RunCommand for S3Copy {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
+impl RunCommand for SetTags {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        let tagging = self.tagging()?;
+
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            let result = client
+                .put_object_tagging()
+                .bucket(path.bucket.to_owned())
+                .key(key)
+                .set_version_id(self.version_id.clone())
+                .tagging(tagging.clone())
+                .send()
+                .await;
+
+            match result {
+                Ok(_) => {
+                    println!("tags are set for: s3://{}/{}", &path.bucket, key);
+                    stats.mutated("tags", key);
+                    stats.processed += 1;
+                }
+                Err(e) => {
+                    let error = format!("s3://{}/{}: {}", &path.bucket, key, error_context(&e));
+                    eprintln!("error: {}", error);
+                    stats.errors.push(error);
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[async_trait]
+impl RunCommand for ListTags {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            let tag_output = match client
+                .get_object_tagging()
+                .bucket(path.bucket.clone())
+                .key(key)
+                .set_version_id(self.version_id.clone())
+                .send()
+                .await
+            {
+                Ok(output) => output,
+                Err(e) => {
+                    let error = format!("s3://{}/{}: {}", &path.bucket, key, s3_error(e));
+                    eprintln!("error: {}", error);
+                    stats.errors.push(error);
+                    continue;
+                }
+            };
+
+            let tags: String = tag_output
+                .tag_set
+                .into_iter()
+                .map(|x| format!("{}:{}", x.key, x.value))
+                .collect::<Vec<String>>()
+                .join(",");
+
+            println!("s3://{}/{} {}", &path.bucket, key, tags);
+            stats.processed += 1;
+        }
+        Ok(stats)
+    }
+}
+
+#[inline]
+fn generate_s3_url(region: &str, bucket: &str, key: &str) -> String {
+    match region {
+        "us-east-1" => format!("https://{}.s3.amazonaws.com/{}", bucket, key),
+        _ => format!("https://{}.s3-{}.amazonaws.com/{}", bucket, region, key),
+    }
+}
+
+#[async_trait]
+impl RunCommand for SetPublic {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        if self.blocked.0.load(Ordering::Relaxed) {
+            stats.skipped += list.len();
+            return Ok(stats);
+        }
+
         for object in list {
-            let key = object.key.clone().ok_or(FunctionError::ObjectFieldError)?;
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            let result = client
+                .put_object_acl()
+                .bucket(path.bucket.to_owned())
+                .key(key)
+                .acl(ObjectCannedAcl::PublicRead)
+                .send()
+                .await;
+
+            match result {
+                Ok(_) => {
+                    let url = generate_s3_url(path.region.as_ref(), &path.bucket, key);
+                    println!("{} {}", display_key(key), display_key(&url));
+                    stats.mutated("public", key);
+                    stats.processed += 1;
+                }
+                Err(e) => {
+                    let error = format!(
+                        "s3://{}/{}: {}",
+                        &path.bucket,
+                        display_key(key),
+                        error_context(&e)
+                    );
+                    eprintln!("error: {}", error);
+                    stats.errors.push(error);
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn preflight(&self, client: &Client, path: &S3Path) -> Result<(), Error> {
+        if let Some(note) = public_access_block_note(client, &path.bucket).await {
+            eprintln!("{}", note);
+            self.blocked.0.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Explains why public ACLs would have no effect on the bucket, when its
+/// public access block blocks or ignores them. A bucket without a public
+/// access block, or one the caller cannot read, gets the ACLs as before.
+async fn public_access_block_note(client: &Client, bucket: &str) -> Option<String> {
+    let config = match client.get_public_access_block().bucket(bucket).send().await {
+        Ok(output) => output.public_access_block_configuration?,
+        Err(e) => {
+            if e.code() != Some("NoSuchPublicAccessBlockConfiguration") {
+                eprintln!(
+                    "warning: could not read the public access block of {}: {}",
+                    bucket,
+                    error_context(&e)
+                );
+            }
+            return None;
+        }
+    };
+
+    let setting = if config.block_public_acls == Some(true) {
+        "BlockPublicAcls"
+    } else if config.ignore_public_acls == Some(true) {
+        "IgnorePublicAcls"
+    } else {
+        return None;
+    };
+
+    Some(format!(
+        "note: the public access block of {} sets {}, public-read ACLs would have no effect; skipping the objects",
+        bucket, setting
+    ))
+}
+
+#[async_trait]
+impl RunCommand for Download {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        download_objects(
+            client,
+            path,
+            list,
+            Path::new(&self.destination),
+            DownloadOptions {
+                force: self.force,
+                version_id: self.version_id.as_deref(),
+                range: self.byte_range(),
+                verify_size_with_head: self.verify_size_with_head,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+}
+
+/// How download_objects writes the listed objects
+#[derive(Debug, Default, Clone, Copy)]
+struct DownloadOptions<'a> {
+    /// Drop the key path and keep only the key name
+    flat: bool,
+    /// Overwrite the files which are already present
+    force: bool,
+    version_id: Option<&'a str>,
+    /// Download only a byte range into a .partial file
+    range: Option<ByteRange>,
+    /// HEAD the keys listed with a size of 0 and check the downloaded length
+    verify_size_with_head: bool,
+}
+
+/// Downloads the listed objects into a local directory
+async fn download_objects(
+    client: &Client,
+    path: &S3Path,
+    list: &[Object],
+    destination: &Path,
+    options: DownloadOptions<'_>,
+) -> Result<CommandStats, Error> {
+    let mut stats = CommandStats::default();
+    for object in list {
+        let key = match listed_key(object, &mut stats) {
+            Some(key) => key,
+            None => continue,
+        };
+
+        let mut file_path = destination.join(combine_keys(options.flat, key, &None));
+        if options.range.is_some() {
+            let mut partial = file_path.into_os_string();
+            partial.push(".partial");
+            file_path = partial.into();
+        }
+
+        println!(
+            "downloading: s3://{}/{} => {}",
+            &path.bucket,
+            key,
+            file_path.display()
+        );
+
+        if file_path.exists() && !options.force {
+            stats.skipped += 1;
+            continue;
+        }
+
+        let listed = object.size.unwrap_or_default();
+        let size = if options.verify_size_with_head && listed == 0 {
+            match head_size(client, &path.bucket, key, options.version_id).await {
+                Ok(size) => {
+                    if size != listed {
+                        println!(
+                            "size corrected: s3://{}/{} listed {}, head {}",
+                            &path.bucket, key, listed, size
+                        );
+                        stats.size_corrected += 1;
+                    }
+                    size
+                }
+                Err(e) => {
+                    let error = format!("s3://{}/{}: {}", &path.bucket, key, e);
+                    eprintln!("error: {}", error);
+                    stats.errors.push(error);
+                    continue;
+                }
+            }
+        } else {
+            listed
+        };
+
+        let mut count = 0;
+        let result = download_object(
+            client,
+            path,
+            size as u64,
+            key,
+            &file_path,
+            options,
+            &mut count,
+        )
+        .await;
+        stats.downloaded_bytes += count;
+
+        match result {
+            Ok(()) => stats.processed += 1,
+            Err(e) => {
+                eprintln!("error: s3://{}/{}: {}", &path.bucket, key, e);
+                stats
+                    .errors
+                    .push(format!("s3://{}/{}: {}", &path.bucket, key, e));
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Writes the object body into the file, `count` holds the bytes written
+/// even if the transfer fails midway
+async fn download_object(
+    client: &Client,
+    path: &S3Path,
+    size: u64,
+    key: &str,
+    file_path: &Path,
+    options: DownloadOptions<'_>,
+    count: &mut u64,
+) -> Result<(), Error> {
+    let dir_path = file_path.parent().ok_or(FunctionError::ParentPathParse)?;
+
+    // the body is cut at the range end in case the endpoint ignores the range
+    let limit = match options.range {
+        Some(range) => range.size().min(size.saturating_sub(range.start)),
+        None => size,
+    };
+    let pb = ProgressBar::new(limit);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
+        .progress_chars("#>-"));
+
+    let mut stream = client
+        .get_object()
+        .bucket(&path.bucket)
+        .key(key)
+        .set_version_id(options.version_id.map(str::to_owned))
+        .set_range(options.range.map(|x| x.header()))
+        .send()
+        .await
+        .map_err(s3_error)?
+        .body;
+
+    fs::create_dir_all(dir_path)?;
+    let mut output = File::create(file_path)?;
+
+    while let Some(bytes) = stream.try_next().await? {
+        let bytes = match options.range {
+            Some(range) => &bytes[..bytes.len().min((range.size() - *count) as usize)],
+            None => &bytes[..],
+        };
+        output.write_all(bytes)?;
+        *count += bytes.len() as u64;
+        pb.set_position(*count);
+    }
+
+    if options.verify_size_with_head && *count != limit {
+        return Err(FunctionError::DownloadSizeMismatch(*count, limit).into());
+    }
+    Ok(())
+}
+
+/// The stored size of the key, which an S3-compatible listing may report as 0
+async fn head_size(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<i64, Error> {
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id.map(str::to_owned))
+        .send()
+        .await
+        .map_err(s3_error)?;
+    Ok(head.content_length.unwrap_or_default())
+}
+
+/// Warning for destructive commands on a bucket which keeps the previous
+/// versions of the removed keys
+fn versioning_warning(bucket: &str, status: Option<&BucketVersioningStatus>) -> Option<String> {
+    let status = status?;
+    Some(format!(
+        "warning: versioning is {} for bucket {}: removed keys only get a delete marker \
+and their previous versions are kept, so no space is freed \
+(use --quiet-versioning-warning to hide this warning)",
+        status.as_str(),
+        bucket
+    ))
+}
+
+/// Looks up the bucket versioning status once, a failed lookup is not an error
+async fn warn_versioned_bucket(client: &Client, bucket: &str) {
+    let status = client
+        .get_bucket_versioning()
+        .bucket(bucket)
+        .send()
+        .await
+        .ok()
+        .and_then(|x| x.status);
+
+    if let Some(warning) = versioning_warning(bucket, status.as_ref()) {
+        eprintln!("{}", warning);
+    }
+}
+
+/// Verifies that the destination bucket exists and accepts writes under the
+/// destination prefix by writing and removing an empty probe object
+pub async fn check_destination(client: &Client, destination: &S3Path) -> Result<(), Error> {
+    let url = format!(
+        "s3://{}/{}",
+        destination.bucket,
+        destination.prefix.as_deref().unwrap_or_default()
+    );
+
+    client
+        .head_bucket()
+        .bucket(&destination.bucket)
+        .send()
+        .await
+        .map_err(|e| FunctionError::DestinationCheckError(url.clone(), error_context(&e)))?;
+
+    let probe_name = format!(".s3find-preflight-{}", Uuid::new_v4());
+    let probe_key = combine_keys(false, &probe_name, &destination.prefix);
+
+    let put_result = client
+        .put_object()
+        .bucket(&destination.bucket)
+        .key(&probe_key)
+        .body(ByteStream::from_static(b""))
+        .send()
+        .await;
 
-            let target = combine_keys(self.flat, &key, &self.destination.prefix);
-            let source_path = format!("{0}/{1}", &path.bucket, key);
+    // the probe is removed even when the put reported an error,
+    // as the object may have been written anyway
+    let delete_result = client
+        .delete_object()
+        .bucket(&destination.bucket)
+        .key(&probe_key)
+        .send()
+        .await;
 
+    put_result.map_err(|e| FunctionError::DestinationCheckError(url.clone(), error_context(&e)))?;
+    delete_result.map_err(|e| FunctionError::DestinationCheckError(url, error_context(&e)))?;
+    Ok(())
+}
+
+/// The key prefix of a destination inside the source path of the same
+/// bucket, the copied keys would be listed with the source keys. A
+/// destination at the source prefix itself cannot be told from the source.
+pub fn nested_destination(path: &S3Path, destination: &S3Path) -> Option<String> {
+    let source = path.prefix.as_deref().unwrap_or_default();
+    let prefix = combine_keys(false, "", &destination.prefix);
+    (path.bucket == destination.bucket && prefix.len() > source.len() && prefix.starts_with(source))
+        .then_some(prefix)
+}
+
+/// Refuses a destination inside the source path with --no-auto-exclude,
+/// otherwise notes that the keys under it are not copied
+fn check_nested_destination(
+    path: &S3Path,
+    destination: &S3Path,
+    no_auto_exclude: bool,
+) -> Result<(), Error> {
+    let prefix = match nested_destination(path, destination) {
+        Some(prefix) => prefix,
+        None => return Ok(()),
+    };
+    let url = format!("s3://{}/{}", destination.bucket, prefix);
+    if no_auto_exclude {
+        return Err(FunctionError::NestedDestination(
+            url,
+            format!(
+                "s3://{}/{}",
+                path.bucket,
+                path.prefix.as_deref().unwrap_or_default()
+            ),
+        )
+        .into());
+    }
+    eprintln!(
+        "note: the keys under {} are excluded, the destination is inside the source path",
+        url
+    );
+    Ok(())
+}
+
+/// Computes the target key for a copied key, reporting the keys which are
+/// skipped or failed because of a target key collision
+fn copy_target(
+    targets: &TargetTracker,
+    strategy: CollisionStrategy,
+    flat: bool,
+    destination: &S3Path,
+    bucket: &str,
+    key: &str,
+    stats: &mut CommandStats,
+) -> Option<String> {
+    let target = combine_keys(flat, key, &destination.prefix);
+    // only --flat maps two keys to one target, the other targets are not
+    // kept for the whole run
+    let resolved = match flat {
+        true => targets.resolve(strategy, key, target),
+        false => Ok(Some(target)),
+    };
+    match resolved {
+        Ok(Some(target)) if is_self_copy(bucket, key, &destination.bucket, &target) => {
+            skipped_self_copy(bucket, key);
+            stats.skipped += 1;
+            None
+        }
+        Ok(target) => {
+            if target.is_none() {
+                println!(
+                    "skipping: s3://{}/{} (target key is already used)",
+                    bucket,
+                    display_key(key)
+                );
+            }
+            target
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            stats.errors.push(e.to_string());
+            None
+        }
+    }
+}
+
+/// Whether the copy target is the source object itself, which S3 rejects
+/// unless the copy changes the storage class or the metadata
+#[inline]
+fn is_self_copy(bucket: &str, key: &str, destination_bucket: &str, target: &str) -> bool {
+    bucket == destination_bucket && key == target
+}
+
+fn skipped_self_copy(bucket: &str, key: &str) {
+    println!(
+        "skipping: s3://{}/{} (target is the source key)",
+        bucket,
+        display_key(key)
+    );
+}
+
+/// Copies a listed object. With `if_unmodified` the copy is conditional on
+/// the source still having the listed ETag and modification time, and
+/// `false` is returned when it was modified since listing.
+async fn copy_listed_object(
+    client: &Client,
+    bucket: &str,
+    object: &Object,
+    destination: &str,
+    target: String,
+    if_unmodified: bool,
+) -> Result<bool, Error> {
+    let key = object
+        .key
+        .as_deref()
+        .ok_or(FunctionError::ObjectFieldError)?;
+    let mut request = client
+        .copy_object()
+        .bucket(destination)
+        .key(target)
+        .copy_source(copy_source(bucket, key));
+
+    if if_unmodified {
+        request = request
+            .set_copy_source_if_match(object.e_tag.clone())
+            .set_copy_source_if_unmodified_since(object.last_modified);
+    }
+
+    match request.send().await {
+        Ok(_) => Ok(true),
+        Err(e) if if_unmodified && e.raw_response().map(|r| r.status().as_u16()) == Some(412) => {
+            skipped_modified(bucket, key);
+            Ok(false)
+        }
+        Err(e) => Err(s3_error(e)),
+    }
+}
+
+/// A listed object to copy with its key and target key
+type CopyItem<'a> = (&'a Object, String, String);
+
+/// Copies the items of a batch, up to `workers` at once, starting them in
+/// the order of the items. Returns the copied items in the order the
+/// copies ended and the errors of the failed copies, which are reported
+/// and do not stop the other copies of the batch.
+async fn copy_items<'a>(
+    client: &Client,
+    bucket: &str,
+    destination: &str,
+    items: Vec<CopyItem<'a>>,
+    if_unmodified: bool,
+    workers: &Workers,
+    verb: &str,
+) -> (Vec<CopyItem<'a>>, Vec<String>) {
+    // the futures are built before the stream, a closure over the borrowed
+    // objects is not general enough for the Send bound of async_trait
+    let copies: Vec<_> = items
+        .into_iter()
+        .map(|(object, key, target)| async move {
             println!(
-                "copying: s3://{0} => s3://{1}/{2}",
-                source_path, &self.destination.bucket, target,
+                "{}: s3://{}/{} => s3://{}/{}",
+                verb,
+                bucket,
+                display_key(&key),
+                destination,
+                display_key(&target),
             );
+            let copied = copy_listed_object(
+                client,
+                bucket,
+                object,
+                destination,
+                target.clone(),
+                if_unmodified,
+            )
+            .await;
+            ((object, key, target), copied)
+        })
+        .collect();
+    let mut copies = futures::stream::iter(copies).buffer_unordered(workers.current());
 
-            client
-                .copy_object()
-                .bucket(&path.bucket)
-                .key(target)
-                .copy_source(source_path)
-                .send()
-                .await?;
+    let mut copied = Vec::new();
+    let mut errors = Vec::new();
+    let mut throttled_copies = 0;
+    while let Some((item, result)) = copies.next().await {
+        match result {
+            Ok(true) => copied.push(item),
+            Ok(false) => {}
+            Err(e) => {
+                throttled_copies += throttled::<CopyObjectError>(&e) as usize;
+                let error = format!("s3://{}/{}: {}", bucket, item.1, e);
+                eprintln!("error: {}", error);
+                errors.push(error);
+            }
+        }
+    }
+    workers.observe(throttled_copies);
+    (copied, errors)
+}
+
+/// Whether the object still has the ETag it was listed with
+async fn is_unmodified(client: &Client, bucket: &str, object: &Object) -> Result<bool, Error> {
+    let key = object
+        .key
+        .as_deref()
+        .ok_or(FunctionError::ObjectFieldError)?;
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(head) => Ok(head.e_tag == object.e_tag),
+        Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(404) => Ok(false),
+        Err(e) => Err(s3_error(e)),
+    }
+}
+
+fn skipped_modified(bucket: &str, key: &str) {
+    println!(
+        "skipped: s3://{}/{} (modified since listing)",
+        bucket,
+        display_key(key)
+    );
+}
+
+/// The interval between two checks of the requested restores with --wait
+const RESTORE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How copy and move treat archived sources, which cannot be copied before
+/// they are restored
+#[derive(Debug, Clone, Copy)]
+struct RestoreOptions {
+    /// Request the restore of the archived sources which have none
+    request: bool,
+    days: i32,
+    /// Wait for the restores and copy the keys in the same run
+    wait: bool,
+}
+
+/// Whether a listed object is in an archive storage class, GLACIER_IR
+/// objects are readable right away
+fn is_archived(object: &Object) -> bool {
+    matches!(storage_class(object), "GLACIER" | "DEEP_ARCHIVE")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RestoreState {
+    Missing,
+    Ongoing,
+    Restored,
+}
+
+/// The restore state of an archived key, from the Restore header of HEAD
+async fn restore_state(client: &Client, bucket: &str, key: &str) -> Result<RestoreState, Error> {
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(s3_error)?;
+    Ok(match head.restore.as_deref() {
+        None => RestoreState::Missing,
+        Some(x) if x.contains(r#"ongoing-request="true""#) => RestoreState::Ongoing,
+        Some(_) => RestoreState::Restored,
+    })
+}
+
+async fn request_restore(client: &Client, bucket: &str, key: &str, days: i32) -> Result<(), Error> {
+    let request = client
+        .restore_object()
+        .bucket(bucket)
+        .key(key)
+        .restore_request(RestoreRequest::builder().days(days).build());
+    match request.send().await {
+        Ok(_) => Ok(()),
+        // RestoreAlreadyInProgress, requested since the HEAD
+        Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(409) => Ok(()),
+        Err(e) => Err(s3_error(e)),
+    }
+}
+
+/// The listed objects a copy can read, in the listing order: the objects
+/// which are not archived and the archived ones which are restored. The
+/// archived keys without a restore are reported in one error of the batch,
+/// with `request` their restore is requested and they are skipped like the
+/// keys with an ongoing restore, until a later run or the end of `wait`.
+async fn copyable_objects<'a>(
+    client: &Client,
+    bucket: &str,
+    list: &'a [Object],
+    options: RestoreOptions,
+    stats: &mut CommandStats,
+) -> Vec<&'a Object> {
+    let mut ready = Vec::new();
+    let mut pending = Vec::new();
+    let mut unrestored = Vec::new();
+
+    for (index, object) in list.iter().enumerate() {
+        let key = match object.key.as_deref() {
+            Some(key) if is_archived(object) => key,
+            _ => {
+                ready.push(index);
+                continue;
+            }
+        };
+        let state = match restore_state(client, bucket, key).await {
+            Ok(state) => state,
+            Err(e) => {
+                key_error(bucket, key, e, stats);
+                continue;
+            }
+        };
+        match state {
+            RestoreState::Restored => ready.push(index),
+            RestoreState::Ongoing => pending.push(index),
+            RestoreState::Missing if options.request => {
+                if let Err(e) = request_restore(client, bucket, key, options.days).await {
+                    key_error(bucket, key, e, stats);
+                    continue;
+                }
+                println!(
+                    "restore requested: s3://{}/{} for {} days",
+                    bucket,
+                    display_key(key),
+                    options.days
+                );
+                pending.push(index);
+            }
+            RestoreState::Missing => unrestored.push(display_key(key)),
+        }
+    }
+
+    while options.wait && !pending.is_empty() {
+        tokio::time::sleep(RESTORE_POLL_INTERVAL).await;
+        let mut ongoing = Vec::new();
+        for index in pending {
+            let key = list[index].key.as_deref().unwrap_or_default();
+            match restore_state(client, bucket, key).await {
+                Ok(RestoreState::Restored) => ready.push(index),
+                Ok(_) => ongoing.push(index),
+                Err(e) => key_error(bucket, key, e, stats),
+            }
+        }
+        pending = ongoing;
+    }
+
+    for index in pending {
+        println!(
+            "skipped: s3://{}/{} (restore in progress, run again once restored)",
+            bucket,
+            display_key(list[index].key.as_deref().unwrap_or_default())
+        );
+        stats.skipped += 1;
+    }
+    if !unrestored.is_empty() {
+        stats
+            .errors
+            .push(FunctionError::RestoreRequired(unrestored.join(", ")).to_string());
+    }
+
+    ready.sort_unstable();
+    ready.into_iter().map(|x| &list[x]).collect()
+}
+
+/// Reports the error of a key, which is left alone while the other keys
+/// of the batch go on
+fn key_error(bucket: &str, key: &str, e: Error, stats: &mut CommandStats) {
+    let error = format!("s3://{}/{}: {}", bucket, key, e);
+    eprintln!("error: {}", error);
+    stats.errors.push(error);
+}
+
+impl S3Copy {
+    fn restore_options(&self) -> RestoreOptions {
+        RestoreOptions {
+            request: self.restore_and_retry,
+            days: self.restore_days,
+            wait: self.wait,
+        }
+    }
+}
+
+impl S3Move {
+    fn restore_options(&self) -> RestoreOptions {
+        RestoreOptions {
+            request: self.restore_and_retry,
+            days: self.restore_days,
+            wait: self.wait,
+        }
+    }
+
+    /// The key of the --two-phase marker, next to the destination keys
+    fn marker_key(&self) -> String {
+        combine_keys(false, MOVE_MARKER, &self.destination.prefix)
+    }
+
+    /// Loads the keys copied by an interrupted --two-phase run of the same
+    /// source, which are deleted without being copied again
+    async fn resume_marker(&self, client: &Client, path: &S3Path) -> Result<(), Error> {
+        let marker_key = self.marker_key();
+        let output = match client
+            .get_object()
+            .bucket(&self.destination.bucket)
+            .key(&marker_key)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(404) => return Ok(()),
+            Err(e) => return Err(s3_error(e)),
+        };
+        let body = output.body.collect().await?.into_bytes();
+        let marker = format!("s3://{}/{}", self.destination.bucket, marker_key);
+        let keys = parse_move_marker(&body, &source_url(path))
+            .map_err(|e| FunctionError::MoveMarker(marker.clone(), e))?;
+
+        eprintln!(
+            "resuming: {} keys were copied by an interrupted move ({})",
+            keys.len(),
+            marker
+        );
+        let mut targets = self.targets.0.lock().unwrap();
+        let mut phases = self.phases.0.lock().unwrap();
+        for moved in keys {
+            targets.insert(moved.target.clone(), moved.key.clone());
+            phases.resumed.insert(moved.key.clone(), moved);
         }
         Ok(())
     }
-}
 
-#[async_trait]
-impl RunCommand for S3Move {
-    async fn execute(&self, client: &Client, path: &S3Path, list: &[Object]) -> Result<(), Error> {
-        for object in list {
-            let key = object.key.clone().ok_or(FunctionError::ObjectFieldError)?;
+    /// Verifies the copies of the first phase of --two-phase, the sources
+    /// are deleted by finish once every batch is copied
+    async fn copied_phase_one(
+        &self,
+        client: &Client,
+        copied: Vec<CopyItem<'_>>,
+        errors: Vec<String>,
+        stats: &mut CommandStats,
+    ) {
+        let mut verified = Vec::new();
+        let mut failed = false;
+        for (object, key, target) in copied {
+            let size = object.size.unwrap_or_default();
+            match head_size(client, &self.destination.bucket, &target, None).await {
+                Ok(copied) if copied == size => {}
+                Ok(copied) => {
+                    failed = true;
+                    stats.errors.push(format!(
+                        "s3://{}/{}: copied {} bytes, expected {}",
+                        self.destination.bucket, target, copied, size
+                    ));
+                }
+                Err(e) => {
+                    failed = true;
+                    stats.errors.push(format!(
+                        "s3://{}/{}: {}",
+                        self.destination.bucket, target, e
+                    ));
+                }
+            }
+            // a copy which fails the verification is rolled back as well
+            verified.push(MovedKey { key, target, size });
+        }
+        if !errors.is_empty() {
+            failed = true;
+            stats.errors.extend(errors);
+        }
+
+        let mut phases = self.phases.0.lock().unwrap();
+        phases.failed |= failed;
+        phases.copied.extend(verified);
+    }
+
+    /// Removes the copies of a failed --two-phase run, the sources are kept
+    async fn roll_back(&self, client: &Client, copied: &[MovedKey]) -> Result<(), Error> {
+        for chunk in copied.chunks(1000) {
+            let key_list = chunk
+                .iter()
+                .map(|x| ObjectIdentifier::builder().key(&x.target).build())
+                .collect::<Result<Vec<_>, _>>()?;
+            let delete = Delete::builder().set_objects(Some(key_list)).build().ok();
+            client
+                .delete_objects()
+                .bucket(&self.destination.bucket)
+                .set_delete(delete)
+                .send()
+                .await
+                .map_err(s3_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The marker of move --two-phase, listing the copied keys until their
+/// sources are deleted
+const MOVE_MARKER: &str = ".s3find-move.json";
+
+/// The source of a move as it is recorded in its marker
+fn source_url(path: &S3Path) -> String {
+    format!(
+        "s3://{}/{}",
+        path.bucket,
+        path.prefix.as_deref().unwrap_or_default()
+    )
+}
+
+/// The marker of the copied keys of a move of the source
+fn move_marker(source: &str, keys: &[MovedKey]) -> String {
+    let keys: Vec<_> = keys
+        .iter()
+        .map(|x| json!({"key": x.key, "target": x.target, "size": x.size}))
+        .collect();
+    json!({"source": source, "phase": "copied", "keys": keys}).to_string()
+}
+
+/// The copied keys of a marker, which must be the one of a move of the
+/// same source
+fn parse_move_marker(body: &[u8], source: &str) -> Result<Vec<MovedKey>, String> {
+    let marker: serde_json::Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    if marker["source"] != source {
+        return Err(format!(
+            "it was written by a move of {}",
+            marker["source"].as_str().unwrap_or("another source")
+        ));
+    }
+
+    let keys = marker["keys"].as_array().ok_or("it lists no keys")?;
+    keys.iter()
+        .map(
+            |x| match (x["key"].as_str(), x["target"].as_str(), x["size"].as_i64()) {
+                (Some(key), Some(target), Some(size)) => Ok(MovedKey {
+                    key: key.to_owned(),
+                    target: target.to_owned(),
+                    size,
+                }),
+                _ => Err(format!("invalid key entry {}", x)),
+            },
+        )
+        .collect()
+}
+
+#[async_trait]
+impl RunCommand for S3Copy {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let destination = match &self.destination {
+            CopyDestination::S3(destination) => destination,
+            CopyDestination::Local(directory) => {
+                return download_objects(
+                    client,
+                    path,
+                    list,
+                    directory,
+                    DownloadOptions {
+                        flat: self.flat,
+                        ..Default::default()
+                    },
+                )
+                .await
+            }
+        };
+
+        let mut stats = CommandStats::default();
+        let list = copyable_objects(
+            client,
+            &path.bucket,
+            list,
+            self.restore_options(),
+            &mut stats,
+        )
+        .await;
+
+        // the targets are resolved in the listing order whatever the
+        // schedule, so a collision keeps the same key
+        let mut items = Vec::new();
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+
+            if let Some(target) = copy_target(
+                &self.targets,
+                self.collision_strategy,
+                self.flat,
+                destination,
+                &path.bucket,
+                &key,
+                &mut stats,
+            ) {
+                items.push((object, key, target));
+            }
+        }
+        self.schedule
+            .sort_by_size(&mut items, |(x, ..)| x.size.unwrap_or_default());
+
+        let (copied, errors) = copy_items(
+            client,
+            &path.bucket,
+            &destination.bucket,
+            items,
+            self.if_unmodified,
+            &self.workers,
+            "copying",
+        )
+        .await;
+        stats.errors.extend(errors);
+        stats.processed += copied.len();
+        Ok(stats)
+    }
+
+    async fn preflight(&self, client: &Client, path: &S3Path) -> Result<(), Error> {
+        match &self.destination {
+            CopyDestination::S3(destination) => {
+                check_nested_destination(path, destination, self.no_auto_exclude)?;
+                if self.no_preflight {
+                    return Ok(());
+                }
+                check_destination(client, destination).await
+            }
+            CopyDestination::Local(_) => Ok(()),
+        }
+    }
+
+    fn summary(&self) -> Option<String> {
+        workers_summary(&self.workers).map(|x| x.trim_end().to_owned())
+    }
+}
+
+#[async_trait]
+impl RunCommand for S3Move {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        // once a copy of --two-phase failed, the later batches are not copied
+        if self.two_phase && self.phases.0.lock().unwrap().failed {
+            return Ok(stats);
+        }
+
+        // the deferred archived keys are neither copied nor deleted
+        let list = copyable_objects(
+            client,
+            &path.bucket,
+            list,
+            self.restore_options(),
+            &mut stats,
+        )
+        .await;
+        let mut items = Vec::new();
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+            if self.two_phase && self.phases.0.lock().unwrap().resumed.contains_key(&key) {
+                println!(
+                    "skipping: s3://{}/{} (copied by the interrupted move)",
+                    &path.bucket,
+                    display_key(&key)
+                );
+                continue;
+            }
+
+            if let Some(target) = copy_target(
+                &self.targets,
+                self.collision_strategy,
+                self.flat,
+                &self.destination,
+                &path.bucket,
+                &key,
+                &mut stats,
+            ) {
+                items.push((object, key, target));
+            }
+        }
+        self.schedule
+            .sort_by_size(&mut items, |(x, ..)| x.size.unwrap_or_default());
+
+        let (copied, errors) = copy_items(
+            client,
+            &path.bucket,
+            &self.destination.bucket,
+            items,
+            self.if_unmodified,
+            &self.workers,
+            "moving",
+        )
+        .await;
+        if self.two_phase {
+            self.copied_phase_one(client, copied, errors, &mut stats)
+                .await;
+            return Ok(stats);
+        }
+        // only the copied sources are deleted
+        stats.errors.extend(errors);
+        let moved = copied
+            .into_iter()
+            .map(|(object, key, _)| (key, object.size.unwrap_or_default()))
+            .collect();
+
+        stats.reclaimed_bytes += delete_moved(client, &path.bucket, moved, &mut stats).await?;
+        Ok(stats)
+    }
+
+    async fn preflight(&self, client: &Client, path: &S3Path) -> Result<(), Error> {
+        check_nested_destination(path, &self.destination, self.no_auto_exclude)?;
+        if !self.quiet_versioning_warning {
+            warn_versioned_bucket(client, &path.bucket).await;
+        }
+        if self.two_phase {
+            self.resume_marker(client, path).await?;
+        }
+        if self.no_preflight {
+            return Ok(());
+        }
+        check_destination(client, &self.destination).await
+    }
+
+    fn summary(&self) -> Option<String> {
+        workers_summary(&self.workers).map(|x| x.trim_end().to_owned())
+    }
+
+    /// The second phase of --two-phase: records the copied keys in the
+    /// marker, deletes the sources and then the marker. A failed first
+    /// phase keeps the sources, its copies are removed with
+    /// --rollback-on-failure.
+    async fn finish(&self, client: &Client, path: &S3Path) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        if !self.two_phase {
+            return Ok(stats);
+        }
+
+        // the failure is kept for the exit code of the run
+        let (copied, resumed, failed) = {
+            let mut phase = self.phases.0.lock().unwrap();
+            let copied = std::mem::take(&mut phase.copied);
+            (copied, std::mem::take(&mut phase.resumed), phase.failed)
+        };
+        if failed {
+            eprintln!("move failed during the copy, the source keys are kept");
+            if self.rollback_on_failure {
+                self.roll_back(client, &copied).await?;
+                eprintln!(
+                    "rolled back: removed {} copied keys from s3://{}",
+                    copied.len(),
+                    self.destination.bucket
+                );
+            }
+            return Ok(stats);
+        }
+
+        let mut moved: Vec<MovedKey> = resumed.into_values().collect();
+        moved.extend(copied);
+        if moved.is_empty() {
+            return Ok(stats);
+        }
+
+        let marker_key = self.marker_key();
+        client
+            .put_object()
+            .bucket(&self.destination.bucket)
+            .key(&marker_key)
+            .body(ByteStream::from(
+                move_marker(&source_url(path), &moved).into_bytes(),
+            ))
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        for chunk in moved.chunks(1000) {
+            let chunk = chunk.iter().map(|x| (x.key.clone(), x.size)).collect();
+            stats.reclaimed_bytes += delete_moved(client, &path.bucket, chunk, &mut stats).await?;
+        }
+
+        // the marker keeps the keys whose source delete was refused, a
+        // rerun deletes them without copying them again
+        let deleted: HashSet<&str> = stats
+            .mutations
+            .iter()
+            .flat_map(|x| &x.keys)
+            .map(String::as_str)
+            .collect();
+        let kept: Vec<MovedKey> = moved
+            .iter()
+            .filter(|x| !deleted.contains(x.key.as_str()))
+            .cloned()
+            .collect();
+        if !kept.is_empty() {
+            client
+                .put_object()
+                .bucket(&self.destination.bucket)
+                .key(&marker_key)
+                .body(ByteStream::from(
+                    move_marker(&source_url(path), &kept).into_bytes(),
+                ))
+                .send()
+                .await
+                .map_err(s3_error)?;
+            eprintln!(
+                "{} source keys were not deleted, they are kept in s3://{}/{} for the next run",
+                kept.len(),
+                self.destination.bucket,
+                marker_key
+            );
+            self.phases.0.lock().unwrap().failed = true;
+            return Ok(stats);
+        }
+
+        client
+            .delete_object()
+            .bucket(&self.destination.bucket)
+            .key(&marker_key)
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(stats)
+    }
+
+    fn failed(&self) -> bool {
+        self.phases.0.lock().unwrap().failed
+    }
+}
+
+#[async_trait]
+impl RunCommand for PlanCopy {
+    /// Resolves the target keys like copy and move, without any request
+    async fn execute(
+        &self,
+        _client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            let planned = combine_keys(self.flat, key, &self.destination.prefix);
+            if self.targets.0.lock().unwrap().contains_key(&planned) {
+                let mut tally = self.tally.0.lock().unwrap();
+                tally
+                    .collisions
+                    .entry(planned)
+                    .or_default()
+                    .push(key.to_owned());
+            }
+
+            let target = match copy_target(
+                &self.targets,
+                self.collision_strategy,
+                self.flat,
+                &self.destination,
+                &path.bucket,
+                key,
+                &mut stats,
+            ) {
+                Some(target) => target,
+                None => continue,
+            };
+            println!(
+                "s3://{0}/{1} => s3://{2}/{3}",
+                &path.bucket,
+                display_key(key),
+                &self.destination.bucket,
+                display_key(&target),
+            );
+            self.tally.0.lock().unwrap().planned += 1;
+            stats.processed += 1;
+        }
+        Ok(stats)
+    }
+
+    fn summary(&self) -> Option<String> {
+        let tally = self.tally.0.lock().unwrap();
+        let targets = self.targets.0.lock().unwrap();
+        let mut out = format!(
+            "Planned: {} keys, {} target key collisions",
+            tally.planned,
+            tally.collisions.len()
+        );
+        for (target, sources) in &tally.collisions {
+            let first = targets.get(target).map(String::as_str).unwrap_or_default();
+            out.push_str(&format!(
+                "\ncollision: s3://{}/{} <= {}, {}",
+                self.destination.bucket,
+                target,
+                first,
+                sources.join(", ")
+            ));
+        }
+        Some(out)
+    }
+
+    fn failed(&self) -> bool {
+        !self.tally.0.lock().unwrap().collisions.is_empty()
+    }
+
+    fn exit_code(&self) -> i32 {
+        PLAN_COLLISION_EXIT_CODE
+    }
+
+    async fn preflight(&self, _client: &Client, path: &S3Path) -> Result<(), Error> {
+        check_nested_destination(path, &self.destination, self.no_auto_exclude)
+    }
+}
+
+/// Removes the sources of the moved keys with their sizes, only the keys
+/// which were copied are listed. The keys in the Errors array of the
+/// response are reported and are not moved, the size of the deleted
+/// sources is returned.
+async fn delete_moved(
+    client: &Client,
+    bucket: &str,
+    moved: Vec<(String, i64)>,
+    stats: &mut CommandStats,
+) -> Result<u64, Error> {
+    if moved.is_empty() {
+        return Ok(0);
+    }
+
+    let key_list = moved
+        .iter()
+        .map(|(key, _)| ObjectIdentifier::builder().key(key).build())
+        .collect::<Result<Vec<_>, _>>()?;
+    let delete = Delete::builder().set_objects(Some(key_list)).build().ok();
+
+    let output = client
+        .delete_objects()
+        .bucket(bucket)
+        .set_delete(delete)
+        .send()
+        .await
+        .map_err(s3_error)?;
+
+    let sizes: HashMap<_, _> = moved.into_iter().collect();
+    let mut keys = Vec::new();
+    let mut bytes = 0;
+    for object in output.deleted.unwrap_or_default() {
+        let key = object.key.unwrap_or_default();
+        bytes += sizes.get(&key).copied().unwrap_or_default() as u64;
+        stats.processed += 1;
+        keys.push(key);
+    }
+    for e in output.errors.unwrap_or_default() {
+        let error = format!(
+            "s3://{}/{}: the source was copied but not deleted: {}",
+            bucket,
+            e.key.as_deref().unwrap_or_default(),
+            e.message.as_deref().unwrap_or_default()
+        );
+        eprintln!("error: {}", error);
+        stats.errors.push(error);
+    }
+    if !keys.is_empty() {
+        stats.mutations.push(Mutation {
+            action: "move",
+            keys,
+        });
+    }
+    Ok(bytes)
+}
+
+/// The number of tagging requests sent at once
+const TAG_FETCH_CONCURRENCY: usize = 16;
+
+/// Fetches the tags of the keys concurrently, the results are in the key order
+pub(crate) async fn fetch_tags(
+    client: &Client,
+    bucket: &str,
+    keys: Vec<String>,
+) -> Vec<Result<Vec<Tag>, Error>> {
+    futures::stream::iter(keys)
+        .map(|key| async move { object_tags(client, bucket, &key).await })
+        .buffered(TAG_FETCH_CONCURRENCY)
+        .collect()
+        .await
+}
+
+async fn object_tags(client: &Client, bucket: &str, key: &str) -> Result<Vec<Tag>, Error> {
+    let output = client
+        .get_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(s3_error)?;
+    Ok(output.tag_set)
+}
+
+impl TagRoute {
+    /// The destination of the first rule matching the tags, or the default one
+    fn destination(&self, tags: &[Tag]) -> Option<&S3Path> {
+        self.routes
+            .iter()
+            .find(|rule| {
+                tags.iter()
+                    .any(|tag| tag.key == rule.key && tag.value == rule.value)
+            })
+            .map(|rule| &rule.destination)
+            .or(self.default.as_ref())
+    }
+}
+
+#[async_trait]
+impl RunCommand for TagRoute {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        let objects: Vec<(&Object, &str)> = list
+            .iter()
+            .filter_map(|object| listed_key(object, &mut stats).map(|key| (object, key)))
+            .collect();
+        let keys = objects.iter().map(|(_, key)| key.to_string()).collect();
+        let tags = fetch_tags(client, &path.bucket, keys).await;
+
+        let mut moved = Vec::new();
+        for ((object, key), tags) in objects.into_iter().zip(tags) {
+            let destination = match tags.map(|tags| self.destination(&tags).cloned()) {
+                Ok(Some(destination)) => destination,
+                Ok(None) => {
+                    println!(
+                        "skipping: s3://{}/{} (no matching route)",
+                        &path.bucket,
+                        display_key(key)
+                    );
+                    stats.skipped += 1;
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("error: s3://{}/{}: {}", &path.bucket, key, e);
+                    stats
+                        .errors
+                        .push(format!("s3://{}/{}: {}", &path.bucket, key, e));
+                    continue;
+                }
+            };
+
+            let target = combine_keys(false, key, &destination.prefix);
+            if is_self_copy(&path.bucket, key, &destination.bucket, &target) {
+                skipped_self_copy(&path.bucket, key);
+                stats.skipped += 1;
+                continue;
+            }
+
+            println!(
+                "{0}: s3://{1}/{2} => s3://{3}/{4}",
+                if self.move_keys { "moving" } else { "copying" },
+                &path.bucket,
+                display_key(key),
+                &destination.bucket,
+                display_key(&target),
+            );
+
+            if let Err(e) = copy_listed_object(
+                client,
+                &path.bucket,
+                object,
+                &destination.bucket,
+                target,
+                false,
+            )
+            .await
+            {
+                eprintln!("error: s3://{}/{}: {}", &path.bucket, key, e);
+                stats
+                    .errors
+                    .push(format!("s3://{}/{}: {}", &path.bucket, key, e));
+                continue;
+            }
+            // a moved key is processed once its source is deleted
+            match self.move_keys {
+                true => moved.push((key.to_owned(), object.size.unwrap_or_default())),
+                false => stats.processed += 1,
+            }
+        }
+
+        stats.reclaimed_bytes += delete_moved(client, &path.bucket, moved, &mut stats).await?;
+        Ok(stats)
+    }
+
+    async fn preflight(&self, client: &Client, _path: &S3Path) -> Result<(), Error> {
+        if self.no_preflight {
+            return Ok(());
+        }
+
+        let mut checked: Vec<&S3Path> = Vec::new();
+        let destinations = self
+            .routes
+            .iter()
+            .map(|rule| &rule.destination)
+            .chain(self.default.as_ref());
+        for destination in destinations {
+            if !checked.contains(&destination) {
+                check_destination(client, destination).await?;
+                checked.push(destination);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EnforceStorage {
+    /// The rule an object has drifted from: the first rule matching the key,
+    /// if the object is old enough for it and stored in another class
+    fn drift(&self, object: &Object, now: i64) -> Option<&StorageRule> {
+        let key = object.key.as_deref().unwrap_or_default();
+        let rule = self.rules.iter().find(|rule| rule.glob.matches(key))?;
+
+        if let Some(age) = rule.age {
+            let last_modified = object.last_modified.map(|x| x.secs()).unwrap_or_default();
+            if now - last_modified < age {
+                return None;
+            }
+        }
+
+        if storage_class(object) == rule.class {
+            None
+        } else {
+            Some(rule)
+        }
+    }
+}
+
+// objects listed without a storage class are STANDARD
+pub(crate) fn storage_class(object: &Object) -> &str {
+    object
+        .storage_class
+        .as_ref()
+        .map(|x| x.as_str())
+        .unwrap_or("STANDARD")
+}
+
+#[async_trait]
+impl RunCommand for EnforceStorage {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let now = Utc::now().timestamp();
+        let mut stats = CommandStats::default();
+
+        for object in list {
+            let rule = match self.drift(object, now) {
+                Some(rule) => rule,
+                None => continue,
+            };
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+
+            if !self.apply {
+                println!(
+                    "drift: s3://{}/{} {} => {}",
+                    &path.bucket,
+                    display_key(&key),
+                    storage_class(object),
+                    rule.class
+                );
+                stats.processed += 1;
+                continue;
+            }
+
+            println!(
+                "changing storage class: s3://{}/{} {} => {}",
+                &path.bucket,
+                display_key(&key),
+                storage_class(object),
+                rule.class
+            );
+
+            let result = client
+                .copy_object()
+                .bucket(&path.bucket)
+                .key(&key)
+                .copy_source(copy_source(&path.bucket, &key))
+                .storage_class(StorageClass::from(rule.class.as_str()))
+                .metadata_directive(MetadataDirective::Copy)
+                .send()
+                .await;
+
+            match result {
+                Ok(_) => {
+                    stats.mutated("storage-class", &key);
+                    stats.processed += 1;
+                }
+                Err(e) => {
+                    let error = format!("s3://{}/{}: {}", &path.bucket, key, error_context(&e));
+                    eprintln!("{}", error);
+                    stats.errors.push(error);
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+const DAY: i64 = 24 * 3600;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LifecycleAction {
+    Expire,
+    Transition(String),
+    Keep,
+}
+
+impl fmt::Display for LifecycleAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LifecycleAction::Expire => write!(f, "expire"),
+            LifecycleAction::Transition(class) => write!(f, "transition to {}", class),
+            LifecycleAction::Keep => write!(f, "no action"),
+        }
+    }
+}
+
+/// S3 applies a rule at the midnight UTC following the last modification
+/// time plus the rule days
+fn lifecycle_due(last_modified: i64, days: i64, now: i64) -> bool {
+    let due = last_modified + days * DAY;
+    now >= (due + DAY - 1).div_euclid(DAY) * DAY
+}
+
+impl SimulateLifecycle {
+    fn classify(&self, object: &Object, now: i64) -> LifecycleAction {
+        let last_modified = object.last_modified.map(|x| x.secs()).unwrap_or_default();
+        let due = |days: i64| lifecycle_due(last_modified, days, now);
+
+        let expired = self
+            .rules
+            .iter()
+            .any(|rule| matches!(rule, LifecycleRule::Expire { days } if due(*days)));
+        if expired {
+            return LifecycleAction::Expire;
+        }
+
+        let transition = self.rules.iter().find_map(|rule| match rule {
+            LifecycleRule::Transition { class, days } if due(*days) => Some(class),
+            _ => None,
+        });
+        match transition {
+            Some(class) if class != storage_class(object) => {
+                LifecycleAction::Transition(class.to_owned())
+            }
+            _ => LifecycleAction::Keep,
+        }
+    }
+}
+
+impl LifecycleTally {
+    pub fn add(&self, action: &LifecycleAction, size: u64) {
+        let mut tally = self.0.lock().unwrap();
+        let entry = tally.entry(action.to_string()).or_default();
+        entry.0 += 1;
+        entry.1 += size;
+    }
+
+    pub fn summary(&self) -> String {
+        let file_size = make_format(BINARY);
+        let mut out = String::from("\nLifecycle simulation\n");
+        for (action, (count, bytes)) in self.0.lock().unwrap().iter() {
+            out.push_str(&format!(
+                "{:27} {} keys, {}\n",
+                format!("{}:", action),
+                count,
+                file_size(*bytes)
+            ));
+        }
+        out
+    }
+}
+
+#[async_trait]
+impl RunCommand for SimulateLifecycle {
+    async fn execute(
+        &self,
+        _c: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let now = Utc::now().timestamp();
+        for object in list {
+            let action = self.classify(object, now);
+            if self.verbose {
+                println!(
+                    "{}: s3://{}/{}",
+                    action,
+                    &path.bucket,
+                    display_key(object.key.as_deref().unwrap_or_default())
+                );
+            }
+            self.tally
+                .add(&action, object.size.unwrap_or_default() as u64);
+        }
+        Ok(CommandStats::processed(list.len()))
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some(self.tally.summary())
+    }
+}
+
+impl Trash {
+    /// The trashed key of the key on the date
+    fn target(&self, key: &str, date: &str) -> String {
+        format!("{}{}", self.trash_prefix.render(date), key)
+    }
+}
+
+#[async_trait]
+impl RunCommand for Trash {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let mut stats = CommandStats::default();
+        let mut moved = Vec::new();
+
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            // a listing above the trash prefix includes the trashed keys
+            if self.trash_prefix.strip(key).is_some() {
+                println!(
+                    "skipping: s3://{}/{} (already in the trash)",
+                    &path.bucket,
+                    display_key(key)
+                );
+                stats.skipped += 1;
+                continue;
+            }
+
+            let target = self.target(key, &date);
+            println!(
+                "trashing: s3://{0}/{1} => s3://{0}/{2} (restore: s3find 's3://{0}/{2}' untrash --trash-prefix '{3}')",
+                &path.bucket,
+                display_key(key),
+                display_key(&target),
+                self.trash_prefix.template,
+            );
+
+            // a failed copy keeps its source, the other keys are trashed
+            match copy_listed_object(
+                client,
+                &path.bucket,
+                object,
+                &path.bucket,
+                target,
+                self.if_unmodified,
+            )
+            .await
+            {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    key_error(&path.bucket, key, e, &mut stats);
+                    continue;
+                }
+            }
+
+            moved.push((key.to_owned(), object.size.unwrap_or_default()));
+        }
+
+        stats.reclaimed_bytes += delete_moved(client, &path.bucket, moved, &mut stats).await?;
+        Ok(stats)
+    }
+
+    async fn preflight(&self, _client: &Client, path: &S3Path) -> Result<(), Error> {
+        check_trash_recursion(&self.trash_prefix, path)
+    }
+}
+
+/// Refuses to trash a prefix inside the trash, the trashed keys would be
+/// trashed again
+fn check_trash_recursion(trash_prefix: &TrashPrefix, path: &S3Path) -> Result<(), Error> {
+    let prefix = path.prefix.as_deref().unwrap_or_default();
+    if !trash_prefix.root().is_empty() && prefix.starts_with(trash_prefix.root()) {
+        return Err(FunctionError::RecursiveTrash(
+            format!("s3://{}/{}", path.bucket, prefix),
+            trash_prefix.template.clone(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl RunCommand for Untrash {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        let mut moved = Vec::new();
+
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            let target = match self.trash_prefix.strip(key) {
+                Some(target) => target.to_owned(),
+                None => {
+                    println!(
+                        "skipping: s3://{}/{} (not in the trash)",
+                        &path.bucket,
+                        display_key(key)
+                    );
+                    stats.skipped += 1;
+                    continue;
+                }
+            };
+            println!(
+                "restoring: s3://{0}/{1} => s3://{0}/{2}",
+                &path.bucket,
+                display_key(key),
+                display_key(&target),
+            );
+
+            if let Err(e) =
+                copy_listed_object(client, &path.bucket, object, &path.bucket, target, false).await
+            {
+                key_error(&path.bucket, key, e, &mut stats);
+                continue;
+            }
+            moved.push((key.to_owned(), object.size.unwrap_or_default()));
+        }
+
+        delete_moved(client, &path.bucket, moved, &mut stats).await?;
+        Ok(stats)
+    }
+}
+
+/// Tags of a key pair which differ: the tag key and its source and target
+/// values, a missing tag has no value
+pub type TagChange = (String, Option<String>, Option<String>);
+
+/// A difference between a source key and its target key
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagDifference {
+    Tags(Vec<TagChange>),
+    MissingTarget,
+    MissingSource,
+}
+
+impl TagDifference {
+    fn text(&self, key: &str) -> String {
+        let key = display_key(key);
+        match self {
+            TagDifference::Tags(changes) => {
+                let value = |x: &Option<String>| x.clone().unwrap_or_else(|| "(none)".to_owned());
+                let changes: Vec<_> = changes
+                    .iter()
+                    .map(|(tag, source, target)| {
+                        format!("{}: {} => {}", tag, value(source), value(target))
+                    })
+                    .collect();
+                format!("tags differ: {} {}", key, changes.join(", "))
+            }
+            TagDifference::MissingTarget => format!("missing in target: {}", key),
+            TagDifference::MissingSource => format!("missing in source: {}", key),
+        }
+    }
+
+    fn json(&self, key: &str) -> String {
+        let event = match self {
+            TagDifference::Tags(changes) => {
+                let changes: Vec<_> = changes
+                    .iter()
+                    .map(|(tag, source, target)| {
+                        json!({ "tag": tag, "source": source, "target": target })
+                    })
+                    .collect();
+                json!({ "key": key, "kind": "tags", "changes": changes })
+            }
+            TagDifference::MissingTarget => {
+                json!({ "key": key, "kind": "missing-target" })
+            }
+            TagDifference::MissingSource => {
+                json!({ "key": key, "kind": "missing-source" })
+            }
+        };
+        event.to_string()
+    }
+}
+
+/// The tags whose values differ between the two tag sets, by tag key
+fn tag_changes(source: &[Tag], target: &[Tag]) -> Vec<TagChange> {
+    let values = |tags: &[Tag]| -> BTreeMap<String, String> {
+        tags.iter()
+            .map(|x| (x.key.clone(), x.value.clone()))
+            .collect()
+    };
+    let source = values(source);
+    let target = values(target);
+
+    let tags: BTreeSet<_> = source.keys().chain(target.keys()).collect();
+    tags.into_iter()
+        .filter(|tag| source.get(*tag) != target.get(*tag))
+        .map(|tag| {
+            (
+                tag.clone(),
+                source.get(tag).cloned(),
+                target.get(tag).cloned(),
+            )
+        })
+        .collect()
+}
+
+/// Sizes of a key compressed by recompress
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recompressed {
+    pub original: u64,
+    pub compressed: u64,
+}
+
+/// What recompress did with a key
+#[derive(Debug, Clone, PartialEq)]
+enum Recompression {
+    /// Skipped, the key is already encoded with the Content-Encoding
+    Encoded(String),
+    Compressed(Recompressed),
+}
+
+fn is_gzip(encoding: &str) -> bool {
+    encoding
+        .split(',')
+        .any(|x| x.trim().eq_ignore_ascii_case("gzip"))
+}
+
+/// Whether a Content-Encoding holds an encoding other than identity, the
+/// body of such a key is not the plain content
+fn is_encoded(encoding: &str) -> bool {
+    encoding.split(',').any(|x| {
+        let x = x.trim();
+        !x.is_empty() && !x.eq_ignore_ascii_case("identity")
+    })
+}
+
+impl Recompress {
+    fn target_key(&self, key: &str) -> String {
+        format!("{}{}", key, self.suffix.as_deref().unwrap_or_default())
+    }
+
+    /// Compresses the key to the target key, a key already encoded, with
+    /// gzip or any encoding but identity, is skipped
+    async fn recompress(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        key: &str,
+        target: &str,
+    ) -> Result<Recompression, Error> {
+        let head = client
+            .head_object()
+            .bucket(&path.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(s3_error)?;
+        if let Some(encoding) = head.content_encoding.filter(|x| is_encoded(x)) {
+            return Ok(Recompression::Encoded(encoding));
+        }
+
+        let mut output = OutputWriter::new(OutputTarget::S3(S3Path {
+            prefix: Some(target.to_owned()),
+            kind: PathKind::Key,
+            ..path.clone()
+        }));
+        output.headers = UploadHeaders {
+            content_type: head.content_type,
+            content_encoding: Some("gzip".to_owned()),
+            metadata: head.metadata,
+        };
+
+        let sizes = match self.compress(client, path, key, head.e_tag, &output).await {
+            Ok(sizes) => sizes,
+            Err(e) => {
+                output.cancel(client).await;
+                return Err(e);
+            }
+        };
+
+        let uploaded = client
+            .head_object()
+            .bucket(&path.bucket)
+            .key(target)
+            .send()
+            .await
+            .map_err(s3_error)?;
+        let length = uploaded.content_length.unwrap_or_default() as u64;
+        let encoded = uploaded.content_encoding.as_deref().is_some_and(is_gzip);
+        if length != sizes.compressed || !encoded {
+            return Err(FunctionError::RecompressVerifyError(
+                format!("s3://{}/{}", path.bucket, target),
+                length,
+                sizes.compressed,
+            )
+            .into());
+        }
+        Ok(Recompression::Compressed(sizes))
+    }
+
+    /// Streams the body through the encoder to the output, the memory holds
+    /// a part of the compressed output at most
+    async fn compress(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        key: &str,
+        e_tag: Option<String>,
+        output: &OutputWriter,
+    ) -> Result<Recompressed, Error> {
+        let mut stream = client
+            .get_object()
+            .bucket(&path.bucket)
+            .key(key)
+            .set_if_match(e_tag)
+            .send()
+            .await
+            .map_err(s3_error)?
+            .body;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(self.level));
+        let mut sizes = Recompressed {
+            original: 0,
+            compressed: 0,
+        };
+        while let Some(bytes) = stream.try_next().await? {
+            encoder.write_all(&bytes)?;
+            sizes.original += bytes.len() as u64;
+
+            let compressed = std::mem::take(encoder.get_mut());
+            sizes.compressed += compressed.len() as u64;
+            output.write(client, &compressed).await?;
+        }
+
+        let compressed = encoder.finish()?;
+        sizes.compressed += compressed.len() as u64;
+        output.write(client, &compressed).await?;
+        output.finish(client).await?;
+        Ok(sizes)
+    }
+
+    async fn delete_source(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        key: &str,
+        stats: &mut CommandStats,
+    ) {
+        let result = client
+            .delete_object()
+            .bucket(&path.bucket)
+            .key(key)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => {
+                stats.mutated("delete", key);
+                stats.deleted += 1;
+            }
+            Err(e) => {
+                let error = format!("s3://{}/{}: {}", &path.bucket, key, error_context(&e));
+                eprintln!("error: {}", error);
+                stats.errors.push(error);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RunCommand for Recompress {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+            let target = self.target_key(&key);
+
+            match self.recompress(client, path, &key, &target).await {
+                Ok(Recompression::Encoded(encoding)) => {
+                    println!(
+                        "skipped: s3://{}/{} is {} encoded",
+                        &path.bucket,
+                        display_key(&key),
+                        display_key(&encoding)
+                    );
+                    stats.skipped += 1;
+                }
+                Ok(Recompression::Compressed(sizes)) => {
+                    println!(
+                        "recompressed: s3://{0}/{1} => s3://{0}/{2} ({3} => {4})",
+                        &path.bucket,
+                        display_key(&key),
+                        display_key(&target),
+                        format_size(sizes.original, BINARY),
+                        format_size(sizes.compressed, BINARY),
+                    );
+                    stats.mutated("recompress", &target);
+                    stats.processed += 1;
+                    if self.delete_source {
+                        self.delete_source(client, path, &key, &mut stats).await;
+                    }
+                }
+                Err(e) => {
+                    let error = format!("s3://{}/{}: {}", &path.bucket, key, e);
+                    eprintln!("error: {}", error);
+                    stats.errors.push(error);
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// Objects up to this size are copied onto themselves by one CopyObject
+const MAX_COPY_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+/// Part size of the multipart copy of bigger objects, 5 TiB in 5120 parts
+const COPY_PART_SIZE: i64 = 1024 * 1024 * 1024;
+
+/// The byte ranges of the parts of a multipart copy
+fn copy_ranges(size: i64, part_size: i64) -> Vec<String> {
+    (0..size)
+        .step_by(part_size.max(1) as usize)
+        .map(|start| format!("bytes={}-{}", start, (start + part_size).min(size) - 1))
+        .collect()
+}
+
+/// What touch copies onto an object. A copy replacing the metadata resets
+/// everything it does not set, so every header, the storage class and the
+/// encryption of the HEAD are set again.
+struct TouchedObject {
+    head: HeadObjectOutput,
+    metadata: HashMap<String, String>,
+}
+
+impl TouchedObject {
+    fn expires(&self) -> Option<aws_sdk_s3::primitives::DateTime> {
+        self.head
+            .expires_string
+            .as_deref()
+            .and_then(|x| aws_sdk_s3::primitives::DateTime::from_str(x, Format::HttpDate).ok())
+    }
+}
+
+impl Touch {
+    /// The user metadata of the object with the marker set to `now`
+    fn metadata(
+        &self,
+        metadata: Option<HashMap<String, String>>,
+        now: chrono::DateTime<Utc>,
+    ) -> HashMap<String, String> {
+        let mut metadata = metadata.unwrap_or_default();
+        if let Some(marker) = &self.marker {
+            metadata.insert(
+                marker.clone(),
+                now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            );
+        }
+        metadata
+    }
+
+    /// Copies the object onto itself, false for an object modified since
+    /// its HEAD
+    async fn touch(&self, client: &Client, bucket: &str, key: &str) -> Result<bool, Error> {
+        let head = client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(s3_error)?;
+        if head.sse_customer_algorithm.is_some() {
+            return Err(FunctionError::CustomerKeyEncrypted.into());
+        }
+
+        let size = head.content_length.unwrap_or_default();
+        let object = TouchedObject {
+            metadata: self.metadata(head.metadata.clone(), Utc::now()),
+            head,
+        };
+        if size > MAX_COPY_SIZE {
+            self.copy_parts(client, bucket, key, &object, size).await
+        } else {
+            self.copy(client, bucket, key, &object).await
+        }
+    }
+
+    async fn copy(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        object: &TouchedObject,
+    ) -> Result<bool, Error> {
+        let head = &object.head;
+        let result = client
+            .copy_object()
+            .bucket(bucket)
+            .key(key)
+            .copy_source(copy_source(bucket, key))
+            .set_copy_source_if_match(head.e_tag.clone())
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(Some(object.metadata.clone()))
+            .set_content_type(head.content_type.clone())
+            .set_content_encoding(head.content_encoding.clone())
+            .set_content_disposition(head.content_disposition.clone())
+            .set_content_language(head.content_language.clone())
+            .set_cache_control(head.cache_control.clone())
+            .set_expires(object.expires())
+            .set_website_redirect_location(head.website_redirect_location.clone())
+            .set_storage_class(head.storage_class.clone())
+            .set_server_side_encryption(head.server_side_encryption.clone())
+            .set_ssekms_key_id(head.ssekms_key_id.clone())
+            .set_bucket_key_enabled(head.bucket_key_enabled)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(412) => Ok(false),
+            Err(e) => Err(s3_error(e)),
+        }
+    }
+
+    /// The multipart copy of an object bigger than a CopyObject allows, a
+    /// failed copy is aborted
+    async fn copy_parts(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        object: &TouchedObject,
+        size: i64,
+    ) -> Result<bool, Error> {
+        let head = &object.head;
+        let upload_id = client
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .set_metadata(Some(object.metadata.clone()))
+            .set_content_type(head.content_type.clone())
+            .set_content_encoding(head.content_encoding.clone())
+            .set_content_disposition(head.content_disposition.clone())
+            .set_content_language(head.content_language.clone())
+            .set_cache_control(head.cache_control.clone())
+            .set_expires(object.expires())
+            .set_website_redirect_location(head.website_redirect_location.clone())
+            .set_storage_class(head.storage_class.clone())
+            .set_server_side_encryption(head.server_side_encryption.clone())
+            .set_ssekms_key_id(head.ssekms_key_id.clone())
+            .set_bucket_key_enabled(head.bucket_key_enabled)
+            .send()
+            .await
+            .map_err(s3_error)?
+            .upload_id
+            .unwrap_or_default();
+
+        let result = self
+            .copy_upload_parts(client, bucket, key, head.e_tag.clone(), &upload_id, size)
+            .await;
+        if !matches!(result, Ok(true)) {
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+        }
+        result
+    }
+
+    async fn copy_upload_parts(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        e_tag: Option<String>,
+        upload_id: &str,
+        size: i64,
+    ) -> Result<bool, Error> {
+        let mut parts = Vec::new();
+        for (i, range) in copy_ranges(size, COPY_PART_SIZE).into_iter().enumerate() {
+            let part_number = i as i32 + 1;
+            let result = client
+                .upload_part_copy()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .copy_source(copy_source(bucket, key))
+                .copy_source_range(range)
+                .set_copy_source_if_match(e_tag.clone())
+                .send()
+                .await;
+            let copied = match result {
+                Ok(copied) => copied,
+                Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(412) => {
+                    return Ok(false)
+                }
+                Err(e) => return Err(s3_error(e)),
+            };
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(copied.copy_part_result.and_then(|x| x.e_tag))
+                    .build(),
+            );
+        }
+
+        client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl RunCommand for Touch {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+
+        for object in list {
+            let key = match listed_key(object, &mut stats) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+
+            match self.touch(client, &path.bucket, &key).await {
+                Ok(true) => {
+                    println!("touched: s3://{}/{}", &path.bucket, display_key(&key));
+                    stats.mutated("touch", &key);
+                    stats.processed += 1;
+                }
+                Ok(false) => {
+                    skipped_modified(&path.bucket, &key);
+                    stats.skipped += 1;
+                }
+                Err(e) => {
+                    let error = format!("s3://{}/{}: {}", &path.bucket, key, e);
+                    eprintln!("error: {}", error);
+                    stats.errors.push(error);
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+fn is_not_found(error: &Error) -> bool {
+    error
+        .downcast_ref::<SdkError<GetObjectTaggingError>>()
+        .and_then(|e| e.raw_response())
+        .map(|r| r.status().as_u16())
+        == Some(404)
+}
+
+/// The key below the path prefix
+fn relative_key<'a>(path: &S3Path, key: &'a str) -> &'a str {
+    key.strip_prefix(path.prefix.as_deref().unwrap_or_default())
+        .unwrap_or(key)
+}
+
+impl DiffTags {
+    fn target_key(&self, relative: &str) -> String {
+        format!(
+            "{}{}",
+            self.target.prefix.as_deref().unwrap_or_default(),
+            relative
+        )
+    }
+
+    /// Client of the target bucket, in its own region
+    fn target_client(&self, client: &Client) -> Client {
+        match self.tally.0.lock().unwrap().target_region.clone() {
+            Some(region) => Client::from_conf(
+                client
+                    .config()
+                    .to_builder()
+                    .region(Region::new(region))
+                    .build(),
+            ),
+            None => client.clone(),
+        }
+    }
+
+    fn report(&self, key: &str, difference: &TagDifference) {
+        match self.format {
+            OutputFormat::Text => println!("{}", difference.text(key)),
+            OutputFormat::Json => println!("{}", difference.json(key)),
+        }
+    }
+}
+
+#[async_trait]
+impl RunCommand for DiffTags {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        let keys: Vec<String> = list
+            .iter()
+            .filter_map(|object| listed_key(object, &mut stats).map(str::to_owned))
+            .collect();
+        let target_keys = keys
+            .iter()
+            .map(|key| self.target_key(relative_key(path, key)))
+            .collect();
+
+        let source_tags = fetch_tags(client, &path.bucket, keys.clone()).await;
+        let target_client = self.target_client(client);
+        let target_tags = fetch_tags(&target_client, &self.target.bucket, target_keys).await;
+
+        for ((key, source), target) in keys.iter().zip(source_tags).zip(target_tags) {
+            let relative = relative_key(path, key);
+            let difference = match (source, target) {
+                (Ok(source), Ok(target)) => match tag_changes(&source, &target) {
+                    changes if changes.is_empty() => None,
+                    changes => Some(TagDifference::Tags(changes)),
+                },
+                (Ok(_), Err(e)) if is_not_found(&e) => Some(TagDifference::MissingTarget),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("error: s3://{}/{}: {}", &path.bucket, key, e);
+                    stats
+                        .errors
+                        .push(format!("s3://{}/{}: {}", &path.bucket, key, e));
+                    continue;
+                }
+            };
+
+            let mut tally = self.tally.0.lock().unwrap();
+            tally.seen.insert(relative.to_owned());
+            match &difference {
+                None => tally.equal += 1,
+                Some(TagDifference::MissingTarget) => tally.missing_target += 1,
+                Some(_) => tally.differing += 1,
+            }
+            drop(tally);
+
+            if let Some(difference) = difference {
+                self.report(relative, &difference);
+            }
+            stats.processed += 1;
+        }
+
+        Ok(stats)
+    }
+
+    async fn preflight(&self, client: &Client, _path: &S3Path) -> Result<(), Error> {
+        let location = client
+            .get_bucket_location()
+            .bucket(&self.target.bucket)
+            .send()
+            .await
+            .map_err(s3_error)?
+            .location_constraint;
+        let region = match location.as_ref().map(|x| x.as_str()) {
+            None | Some("") => "us-east-1",
+            Some("EU") => "eu-west-1",
+            Some(region) => region,
+        };
+
+        if client.config().region().map(|x| x.as_ref()) != Some(region) {
+            self.tally.0.lock().unwrap().target_region = Some(region.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Walks the target path for the keys which have no source key
+    async fn finish(&self, client: &Client, _path: &S3Path) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        let stream = FindStream {
+            client: self.target_client(client),
+            path: self.target.clone(),
+            token: None,
+            page_size: 1000,
+            prefetch: 1,
+            initial: true,
+            accounted: None,
+            fetch_owner: false,
+            budget: Default::default(),
+            counters: Default::default(),
+            cache: None,
+            range: None,
+            list_api: ListApi::Auto,
+            tuning: PageSizeTuning::new(1000),
+            one_level: false,
+            list_prefixes: false,
+        }
+        .stream();
+        let mut stream = Box::pin(stream);
+
+        while let Some(list) = stream.next().await {
+            for object in list {
+                let key = object.key.as_deref().unwrap_or_default();
+                let relative = relative_key(&self.target, key);
+                if self.tally.0.lock().unwrap().seen.contains(relative) {
+                    continue;
+                }
+
+                self.tally.0.lock().unwrap().missing_source += 1;
+                self.report(relative, &TagDifference::MissingSource);
+                stats.processed += 1;
+            }
+        }
+        Ok(stats)
+    }
+
+    fn summary(&self) -> Option<String> {
+        let tally = self.tally.0.lock().unwrap();
+        Some(match self.format {
+            OutputFormat::Text => format!(
+                "\nTag comparison\n{:27} {} keys\n{:27} {} keys\n{:27} {} keys\n{:27} {} keys\n",
+                "equal:",
+                tally.equal,
+                "differing:",
+                tally.differing,
+                "missing in target:",
+                tally.missing_target,
+                "missing in source:",
+                tally.missing_source
+            ),
+            OutputFormat::Json => json!({
+                "equal": tally.equal,
+                "differing": tally.differing,
+                "missing_target": tally.missing_target,
+                "missing_source": tally.missing_source,
+            })
+            .to_string(),
+        })
+    }
+
+    fn failed(&self) -> bool {
+        let tally = self.tally.0.lock().unwrap();
+        tally.differing + tally.missing_target + tally.missing_source > 0
+    }
+}
+
+/// A difference between the listing and the HEAD of an object
+#[derive(Debug, Clone, PartialEq)]
+pub enum Discrepancy {
+    Size(i64, i64),
+    ETag(String, String),
+    /// Deleted since listing
+    Missing,
+}
+
+impl Discrepancy {
+    fn text(&self, bucket: &str, key: &str) -> String {
+        let key = display_key(key);
+        match self {
+            Discrepancy::Size(listed, head) => format!(
+                "size mismatch: s3://{}/{} listed {}, head {}",
+                bucket, key, listed, head
+            ),
+            Discrepancy::ETag(listed, head) => format!(
+                "etag mismatch: s3://{}/{} listed {}, head {}",
+                bucket, key, listed, head
+            ),
+            Discrepancy::Missing => {
+                format!("missing: s3://{}/{} (deleted since listing)", bucket, key)
+            }
+        }
+    }
+
+    fn json(&self, bucket: &str, key: &str) -> String {
+        let (kind, listed, head) = match self {
+            Discrepancy::Size(listed, head) => ("size", json!(listed), json!(head)),
+            Discrepancy::ETag(listed, head) => ("etag", json!(listed), json!(head)),
+            Discrepancy::Missing => ("missing", json!(null), json!(null)),
+        };
+        json!({
+            "bucket": bucket,
+            "key": key,
+            "kind": kind,
+            "listed": listed,
+            "head": head,
+        })
+        .to_string()
+    }
+}
+
+/// The differences between the listed object and its HEAD, None when the
+/// object is gone
+fn discrepancies(object: &Object, head: Option<&HeadObjectOutput>) -> Vec<Discrepancy> {
+    let head = match head {
+        Some(head) => head,
+        None => return vec![Discrepancy::Missing],
+    };
+    let mut found = Vec::new();
+
+    let listed_size = object.size.unwrap_or_default();
+    let head_size = head.content_length.unwrap_or_default();
+    if listed_size != head_size {
+        found.push(Discrepancy::Size(listed_size, head_size));
+    }
+
+    let listed_e_tag = object.e_tag.clone().unwrap_or_default();
+    let head_e_tag = head.e_tag.clone().unwrap_or_default();
+    if listed_e_tag != head_e_tag {
+        found.push(Discrepancy::ETag(listed_e_tag, head_e_tag));
+    }
+    found
+}
+
+/// The summary line of --workers auto
+pub(crate) fn workers_summary(workers: &Workers) -> Option<String> {
+    workers.settled().map(|(workers, throttled)| {
+        format!(
+            "{:27} {} ({} throttled requests)\n",
+            "workers settled at:", workers, throttled
+        )
+    })
+}
+
+/// Sends the HEAD requests of the keys, `workers` at once, the results are
+/// in the key order
+async fn head_objects(
+    client: &Client,
+    bucket: &str,
+    keys: Vec<String>,
+    workers: usize,
+) -> Vec<Result<Option<HeadObjectOutput>, Error>> {
+    futures::stream::iter(keys)
+        .map(|key| async move { head_object(client, bucket, &key).await })
+        .buffered(workers.max(1))
+        .collect()
+        .await
+}
+
+async fn head_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<HeadObjectOutput>, Error> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(head) => Ok(Some(head)),
+        Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(404) => Ok(None),
+        Err(e) => Err(s3_error(e)),
+    }
+}
+
+impl Verify {
+    fn report(&self, bucket: &str, key: &str, discrepancy: &Discrepancy) {
+        match self.format {
+            OutputFormat::Text => println!("{}", discrepancy.text(bucket, key)),
+            OutputFormat::Json => println!("{}", discrepancy.json(bucket, key)),
+        }
+    }
+}
+
+#[async_trait]
+impl RunCommand for Verify {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        let objects: Vec<_> = list
+            .iter()
+            .filter_map(|object| Some((object, listed_key(object, &mut stats)?.to_owned())))
+            .collect();
+        let keys = objects.iter().map(|(_, key)| key.clone()).collect();
+        let heads = head_objects(client, &path.bucket, keys, self.workers.current()).await;
+        self.workers.observe(
+            heads
+                .iter()
+                .filter(|x| matches!(x, Err(e) if throttled::<HeadObjectError>(e)))
+                .count(),
+        );
+
+        for ((object, key), head) in objects.into_iter().zip(heads) {
+            let head = match head {
+                Ok(head) => head,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    stats
+                        .errors
+                        .push(format!("s3://{}/{}: {}", &path.bucket, key, e));
+                    continue;
+                }
+            };
+
+            let mut found = discrepancies(object, head.as_ref());
+            let mut tally = self.tally.0.lock().unwrap();
+            if self.verify_size_with_head && object.size.unwrap_or_default() == 0 {
+                let before = found.len();
+                found.retain(|x| !matches!(x, Discrepancy::Size(..)));
+                if found.len() < before {
+                    tally.corrected += 1;
+                }
+            }
+            match found.first() {
+                None => tally.verified += 1,
+                Some(Discrepancy::Missing) => {
+                    tally.missing += 1;
+                    if !self.missing {
+                        stats.skipped += 1;
+                        continue;
+                    }
+                }
+                Some(_) => tally.mismatched += 1,
+            }
+            drop(tally);
+
+            for discrepancy in &found {
+                self.report(&path.bucket, &key, discrepancy);
+            }
+            stats.processed += 1;
+        }
+
+        Ok(stats)
+    }
+
+    fn summary(&self) -> Option<String> {
+        let tally = self.tally.0.lock().unwrap();
+        Some(match self.format {
+            OutputFormat::Text => {
+                let mut summary = format!(
+                    "\nVerification\n{:27} {} keys\n{:27} {} keys\n{:27} {} keys\n",
+                    "verified:",
+                    tally.verified,
+                    "mismatched:",
+                    tally.mismatched,
+                    "missing:",
+                    tally.missing
+                );
+                if self.verify_size_with_head {
+                    summary.push_str(&format!(
+                        "{:27} {} keys\n",
+                        "size corrected by head:", tally.corrected
+                    ));
+                }
+                if let Some(workers) = workers_summary(&self.workers) {
+                    summary.push_str(&workers);
+                }
+                summary
+            }
+            OutputFormat::Json => {
+                let mut summary = json!({
+                    "verified": tally.verified,
+                    "mismatched": tally.mismatched,
+                    "missing": tally.missing,
+                });
+                if self.verify_size_with_head {
+                    summary["corrected"] = json!(tally.corrected);
+                }
+                if let Some((workers, throttled)) = self.workers.settled() {
+                    summary["workers"] = json!(workers);
+                    summary["throttled"] = json!(throttled);
+                }
+                summary.to_string()
+            }
+        })
+    }
+
+    fn failed(&self) -> bool {
+        let tally = self.tally.0.lock().unwrap();
+        tally.mismatched > 0 || (self.missing && tally.missing > 0)
+    }
+}
+
+const ALL_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+const AUTHENTICATED_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AuthenticatedUsers";
+const LOG_DELIVERY_URI: &str = "http://acs.amazonaws.com/groups/s3/LogDelivery";
+
+/// Error code of the ACL requests on a bucket with BucketOwnerEnforced
+/// object ownership
+const ACLS_DISABLED_CODE: &str = "AccessControlListNotSupported";
+
+/// Attempts of a GetObjectAcl request failing with a server error or a
+/// throttling response
+const ACL_ATTEMPTS: u32 = 3;
+const ACL_BACKOFF: Duration = Duration::from_millis(200);
+
+/// A grant to someone besides the owner
+#[derive(Debug, Clone, PartialEq)]
+struct AclFinding {
+    category: AclCategory,
+    grantee: String,
+    permission: String,
+}
+
+/// The grants to the public, the other accounts and the log delivery group;
+/// the grants to the owner are private
+fn acl_findings(owner: Option<&str>, grants: &[Grant]) -> Vec<AclFinding> {
+    let mut findings = Vec::new();
+    for grant in grants {
+        let grantee = match &grant.grantee {
+            Some(grantee) => grantee,
+            None => continue,
+        };
+        let permission = grant
+            .permission
+            .as_ref()
+            .map(|x| x.as_str().to_owned())
+            .unwrap_or_default();
+
+        // AuthenticatedUsers is any AWS account, as public as AllUsers
+        let category = match grantee.uri.as_deref() {
+            Some(ALL_USERS_URI) | Some(AUTHENTICATED_USERS_URI) => match permission.as_str() {
+                "READ" | "READ_ACP" => AclCategory::PublicRead,
+                _ => AclCategory::PublicWrite,
+            },
+            Some(LOG_DELIVERY_URI) => AclCategory::LogDelivery,
+            None if owner.is_some() && grantee.id.as_deref() == owner => continue,
+            _ => AclCategory::CrossAccount,
+        };
+        let name = grantee
+            .uri
+            .as_deref()
+            .and_then(|uri| uri.rsplit('/').next())
+            .or(grantee.email_address.as_deref())
+            .or(grantee.id.as_deref())
+            .unwrap_or_default();
+
+        findings.push(AclFinding {
+            category,
+            grantee: name.to_owned(),
+            permission,
+        });
+    }
+    findings
+}
+
+/// The categories of the findings, private-only without findings
+fn acl_categories(findings: &[AclFinding]) -> Vec<AclCategory> {
+    let mut categories: Vec<_> = findings.iter().map(|x| x.category).collect();
+    categories.sort();
+    categories.dedup();
+    if categories.is_empty() {
+        categories.push(AclCategory::PrivateOnly);
+    }
+    categories
+}
+
+enum AclOutcome {
+    Acl(GetObjectAclOutput),
+    Disabled,
+    Missing,
+}
+
+async fn object_acl(client: &Client, bucket: &str, key: &str) -> Result<AclOutcome, Error> {
+    let response = retry_with_backoff(ACL_ATTEMPTS, ACL_BACKOFF, || async {
+        match client.get_object_acl().bucket(bucket).key(key).send().await {
+            Ok(acl) => Ok(Ok(acl)),
+            Err(e)
+                if e.raw_response()
+                    .map(|r| r.status().as_u16())
+                    .is_some_and(|status| status >= 500 || status == 429) =>
+            {
+                Err(e)
+            }
+            Err(e) => Ok(Err(e)),
+        }
+    })
+    .await;
+
+    match response {
+        Ok(Ok(acl)) => Ok(AclOutcome::Acl(acl)),
+        Ok(Err(e)) | Err(e) if e.code() == Some(ACLS_DISABLED_CODE) => Ok(AclOutcome::Disabled),
+        Ok(Err(e)) | Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(404) => {
+            Ok(AclOutcome::Missing)
+        }
+        Ok(Err(e)) | Err(e) => Err(s3_error(e)),
+    }
+}
+
+/// Sends the GetObjectAcl requests of the keys, `workers` at once, the
+/// results are in the key order
+async fn object_acls(
+    client: &Client,
+    bucket: &str,
+    keys: Vec<String>,
+    workers: usize,
+) -> Vec<Result<AclOutcome, Error>> {
+    futures::stream::iter(keys)
+        .map(|key| async move { object_acl(client, bucket, &key).await })
+        .buffered(workers.max(1))
+        .collect()
+        .await
+}
+
+impl AclReport {
+    fn report(&self, bucket: &str, key: &str, findings: &[AclFinding]) {
+        let categories = acl_categories(findings);
+        match self.format {
+            OutputFormat::Text => println!(
+                "{} s3://{}/{}{}",
+                categories
+                    .iter()
+                    .map(|x| x.name())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                bucket,
+                terminal_key(key),
+                findings
+                    .iter()
+                    .map(|x| format!(" {}:{}", x.grantee, x.permission))
+                    .collect::<String>()
+            ),
+            OutputFormat::Json => {
+                let record = json!({
+                    "bucket": bucket,
+                    "key": key,
+                    "categories": categories.iter().map(|x| x.name()).collect::<Vec<_>>(),
+                    "grants": findings
+                        .iter()
+                        .map(|x| json!({
+                            "category": x.category.name(),
+                            "grantee": x.grantee,
+                            "permission": x.permission,
+                        }))
+                        .collect::<Vec<_>>(),
+                });
+                println!("{}", record)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RunCommand for AclReport {
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        if self.tally.0.lock().unwrap().acls_disabled {
+            stats.skipped += list.len();
+            return Ok(stats);
+        }
+
+        let keys: Vec<_> = list
+            .iter()
+            .filter_map(|object| listed_key(object, &mut stats).map(str::to_owned))
+            .collect();
+        let acls = object_acls(client, &path.bucket, keys.clone(), self.workers.current()).await;
+        self.workers.observe(
+            acls.iter()
+                .filter(|x| matches!(x, Err(e) if throttled::<GetObjectAclError>(e)))
+                .count(),
+        );
+
+        for (key, acl) in keys.iter().zip(acls) {
+            match acl {
+                Ok(AclOutcome::Acl(acl)) => {
+                    let owner = acl.owner.as_ref().and_then(|owner| owner.id.as_deref());
+                    let findings = acl_findings(owner, acl.grants());
+                    let flagged = !findings.is_empty();
+
+                    let mut tally = self.tally.0.lock().unwrap();
+                    for category in acl_categories(&findings) {
+                        *tally.keys.entry(category).or_default() += 1;
+                    }
+                    drop(tally);
+
+                    if flagged || !self.only_flagged {
+                        self.report(&path.bucket, key, &findings);
+                    }
+                    stats.processed += 1;
+                }
+                Ok(AclOutcome::Disabled) => {
+                    let mut tally = self.tally.0.lock().unwrap();
+                    if !tally.acls_disabled {
+                        tally.acls_disabled = true;
+                        eprintln!("s3://{}: ACLs disabled for this bucket", &path.bucket);
+                    }
+                    stats.skipped += 1;
+                }
+                Ok(AclOutcome::Missing) => stats.skipped += 1,
+                Err(e) => {
+                    eprintln!("error: s3://{}/{}: {}", &path.bucket, key, e);
+                    stats
+                        .errors
+                        .push(format!("s3://{}/{}: {}", &path.bucket, key, e));
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn summary(&self) -> Option<String> {
+        let tally = self.tally.0.lock().unwrap();
+        let count = |category| tally.keys.get(&category).copied().unwrap_or_default();
+        Some(match self.format {
+            OutputFormat::Text => {
+                let mut out = String::from("\nACL report\n");
+                if tally.acls_disabled {
+                    out.push_str("ACLs disabled for this bucket\n");
+                }
+                for category in AclCategory::ALL.iter() {
+                    out.push_str(&format!(
+                        "{:27} {} keys\n",
+                        format!("{}:", category.name()),
+                        count(*category)
+                    ));
+                }
+                if let Some(workers) = workers_summary(&self.workers) {
+                    out.push_str(&workers);
+                }
+                out
+            }
+            OutputFormat::Json => {
+                let mut summary = serde_json::Map::new();
+                for category in AclCategory::ALL.iter() {
+                    summary.insert(category.name().to_owned(), json!(count(*category)));
+                }
+                summary.insert("acls_disabled".to_owned(), json!(tally.acls_disabled));
+                if let Some((workers, throttled)) = self.workers.settled() {
+                    summary.insert("workers".to_owned(), json!(workers));
+                    summary.insert("throttled".to_owned(), json!(throttled));
+                }
+                serde_json::Value::Object(summary).to_string()
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl RunCommand for DoNothing {
+    async fn execute(
+        &self,
+        _c: &Client,
+        _p: &S3Path,
+        _l: &[Object],
+    ) -> Result<CommandStats, Error> {
+        Ok(CommandStats::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_config::BehaviorVersion;
+    use aws_sdk_s3::{
+        primitives::DateTime,
+        types::{Grantee, ObjectStorageClass, Type},
+    };
+    use aws_smithy_types::date_time::Format;
+    use aws_types::region::Region;
+    use structopt::StructOpt;
+
+    use crate::replay::*;
+
+    // use std::fs::File;
+    // use std::io::prelude::*;
+    // use tempfile::Builder;
+
+    #[test]
+    fn test_advanced_print_object() -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let cmd = AdvancedPrint::default();
+        let bucket = "test";
+
+        let object = Object::builder()
+            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
+            .key("somepath/otherpath")
+            .size(4_997_288)
+            .storage_class(ObjectStorageClass::Standard)
+            .last_modified(DateTime::from_str(
+                "2017-07-19T19:04:17.000Z",
+                Format::DateTime,
+            )?)
+            .build();
+
+        cmd.print_object(&mut buf, bucket, &object, None)?;
+        let out = std::str::from_utf8(&buf)?;
+
+        println!("{}", out);
+        assert!(out.contains("9d48114aa7c18f9d68aa20086dbb7756"));
+        assert!(out.contains("None"));
+        assert!(out.contains("4997288"));
+        assert!(out.contains("2017-07-19T19:04:17Z"));
+        assert!(out.contains("s3://test/somepath/otherpath"));
+        assert!(out.contains("Standard"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_advanced_print_without_date() -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let object = Object::builder().key("somepath").size(1).build();
+
+        AdvancedPrint::default().print_object(&mut buf, "test", &object, None)?;
+
+        let out = std::str::from_utf8(&buf)?;
+        assert!(out.contains(" \"NoDate\" s3://test/somepath"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_advanced_print_owner() -> Result<(), Error> {
+        let cmd = AdvancedPrint::default();
+        let object = |owner: Owner| {
+            Object::builder()
+                .key("somepath/otherpath")
+                .owner(owner)
+                .last_modified(DateTime::from_secs(1_500_000_000))
+                .build()
+        };
+
+        let mut buf = Vec::new();
+        let owner = Owner::builder().display_name("ander").id("1a2b3c").build();
+        cmd.print_object(&mut buf, "test", &object(owner), None)?;
+        let out = std::str::from_utf8(&buf)?;
+        assert!(out.contains(r#"Some("ander")"#));
+
+        let mut buf = Vec::new();
+        let owner = Owner::builder().id("1a2b3c").build();
+        cmd.print_object(&mut buf, "test", &object(owner), None)?;
+        let out = std::str::from_utf8(&buf)?;
+        assert!(out.contains(r#"Some("1a2b3c")"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_print_object() -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let cmd = FastPrint::default();
+        let bucket = "test";
+
+        let object = Object::builder()
+            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
+            .key("somepath/otherpath")
+            .size(4_997_288)
+            .storage_class(ObjectStorageClass::Standard)
+            .last_modified(DateTime::from_str(
+                "2017-07-19T19:04:17.000Z",
+                Format::DateTime,
+            )?)
+            .build();
+
+        cmd.print_object(&mut buf, bucket, &object, None)?;
+        let out = std::str::from_utf8(&buf)?;
+
+        assert!(out.contains("s3://test/somepath/otherpath"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_color_modes() -> Result<(), Error> {
+        let object = Object::builder()
+            .e_tag("\"e\"")
+            .key("logs/app.log")
+            .size(1536)
+            .storage_class(ObjectStorageClass::Glacier)
+            .last_modified(DateTime::from_secs(1_500_000_000))
+            .build();
+        let print = |mode: ColorMode, terminal: bool| -> Result<String, Error> {
+            let mut buf = Vec::new();
+            let print = Cmd::Print(AdvancedPrint {
+                human_readable: true,
+                ..Default::default()
+            });
+            match (
+                print.with_color(mode, terminal),
+                Cmd::default().with_color(mode, terminal),
+            ) {
+                (Cmd::Print(print), Cmd::Ls(ls)) => {
+                    print.print_object(&mut buf, "test", &object, None)?;
+                    ls.print_object(&mut buf, "test", &object, None)?;
+                }
+                _ => unreachable!(),
+            }
+            Ok(String::from_utf8(buf)?)
+        };
+
+        let never = print(ColorMode::Never, true)?;
+        let always = print(ColorMode::Always, false)?;
+        assert!(!never.contains('\x1b'));
+        assert!(always.contains("\x1b[34mSome(Glacier)\x1b[0m"));
+        assert!(always.contains("\x1b[2ms3://test/\x1b[0mlogs/app.log"));
+        assert_eq!(crate::style::strip_ansi(&always), never);
+        assert_eq!(print(ColorMode::Auto, false)?, never);
+        assert!(never.starts_with("\"e\" None    1.50 KiB "));
+        assert!(never.ends_with("\ns3://test/logs/app.log\n"));
+
+        let to_file = Cmd::Print(AdvancedPrint {
+            output_file: Some("out.txt".into()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            to_file.with_color(ColorMode::Always, true),
+            Cmd::Print(AdvancedPrint { color: false, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec() -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let cmd = Exec {
+            utility: "echo test {}".to_owned(),
+            download_temp: false,
+            max_size: None,
+            no_option_guard: false,
+        };
+
+        let path = "s3://test/somepath/otherpath";
+        cmd.exec(&mut buf, path, None, None)?;
+        let out = std::str::from_utf8(&buf)?;
+
+        assert!(out.contains("test"));
+        assert!(out.contains("s3://test/somepath/otherpath"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_invalid_utf8_key() -> Result<(), Error> {
+        let (key, bytes) = crate::utils::decode_listed_key("print/bad%FF%E2%80%AEgpj.exe");
+        let bytes = bytes.as_deref();
+        let object = Object::builder()
+            .key(&key)
+            .size(1)
+            .last_modified(DateTime::from_secs(1_500_000_000))
+            .build();
+
+        let mut buf = Vec::new();
+        FastPrint::default().print_object(&mut buf, "test", &object, bytes)?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?,
+            "s3://test/print/bad\\xff\\u{202e}gpj.exe\n"
+        );
+
+        let print0 = FastPrint {
+            print0: true,
+            color: true,
+        };
+        let mut buf = Vec::new();
+        print0.print_object(&mut buf, "test", &object, bytes)?;
+        assert_eq!(buf, b"s3://test/print/bad\xff\xe2\x80\xaegpj.exe\0");
+        assert!(!print0.lists_prefixes());
+
+        let mut buf = Vec::new();
+        AdvancedPrint::default().print_object(&mut buf, "test", &object, bytes)?;
+        assert!(std::str::from_utf8(&buf)?.contains(r" s3://test/print/bad\xff\u{202e}gpj.exe "));
+
+        // a valid key with the private use characters of the last plane
+        // is printed as listed
+        let object = Object::builder().key("print/\u{10ffff}").build();
+        let mut buf = Vec::new();
+        print0.print_object(&mut buf, "test", &object, None)?;
+        assert_eq!(buf, "s3://test/print/\u{10ffff}\0".as_bytes());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_invalid_utf8_key() -> Result<(), Error> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let (key, bytes) = crate::utils::decode_listed_key("exec/bad%FF%E2%80%AE");
+        let words = command_words("echo s3://test/{}", &key, bytes.as_deref(), true);
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[1].as_bytes(), b"s3://test/exec/bad\xff\xe2\x80\xae");
+
+        assert_eq!(
+            command_words("echo {}", "a b", None, true),
+            vec!["echo", "a b"]
+        );
+
+        let cmd = Exec {
+            utility: "echo {}".to_owned(),
+            download_temp: false,
+            max_size: None,
+            no_option_guard: false,
+        };
+        let status = cmd.exec(&mut Vec::new(), &key, bytes.as_deref(), None)?;
+        assert!(status.status.success());
+        assert_eq!(status.runcommand, "echo exec/bad\u{fffd}\u{202e}");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_option_guard() -> Result<(), Error> {
+        let script = std::env::temp_dir().join(format!("s3find-argv-{}.sh", Uuid::new_v4()));
+        fs::write(&script, "for arg in \"$@\"; do echo \"[$arg]\"; done\n")?;
+        let argv = |utility: &str, key: &str, no_option_guard: bool| -> Result<String, Error> {
+            let cmd = Exec {
+                utility: format!("sh {} {}", script.display(), utility),
+                download_temp: false,
+                max_size: None,
+                no_option_guard,
+            };
+            let mut buf = Vec::new();
+            cmd.exec(&mut buf, key, None, None)?;
+            Ok(String::from_utf8(buf)?.trim_end().to_owned())
+        };
+
+        assert_eq!(argv("-l {}", "-rf", false)?, "[-l]\n[--]\n[-rf]");
+        assert_eq!(argv("{}", "--delete", false)?, "[--]\n[--delete]");
+        assert_eq!(argv("diff {} {}", "-x", false)?, "[diff]\n[--]\n[-x]\n[-x]");
+        // the words of a key with spaces stay one argument
+        assert_eq!(argv("-l {}", "a -rf", false)?, "[-l]\n[a -rf]");
+        assert_eq!(argv("--input={}", "a -rf", false)?, "[--input=a -rf]");
+        assert_eq!(argv("-l {}", "logs/a.txt", false)?, "[-l]\n[logs/a.txt]");
+        // an embedded placeholder and --no-option-guard are left alone
+        assert_eq!(argv("--input={}", "-rf", false)?, "[--input=-rf]");
+        assert_eq!(argv("{}", "-rf", true)?, "[-rf]");
+
+        fs::remove_file(&script)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exec_download_temp() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "hello world")]);
+        let cmd = Exec {
+            utility: "wc -c {file}".to_owned(),
+            download_temp: true,
+            max_size: Some(100),
+            no_option_guard: false,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("photos/a.jpg").size(11).build(),
+            Object::builder().key("photos/huge.jpg").size(101).build(),
+        ];
+
+        let mut buf = Vec::new();
+        let stats = cmd
+            .exec_list(&mut buf, &client, &path, &list, &KeyBytes::default())
+            .await?;
+        let out = std::str::from_utf8(&buf)?;
+
+        let (size, file) = out.trim().split_once(' ').unwrap();
+        assert_eq!(size, "11");
+        assert!(file.ends_with("-a.jpg"));
+        assert!(!Path::new(file).exists());
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.downloaded_bytes, 11);
+        assert_eq!(http_client.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_advanced_print() -> Result<(), Error> {
+        let object = Object::builder()
+            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
+            .key("somepath/otherpath")
+            .size(4_997_288)
+            .storage_class(ObjectStorageClass::Standard)
+            .last_modified(DateTime::from_str(
+                "2017-07-19T19:04:17.000Z",
+                Format::DateTime,
+            )?)
+            .build();
+
+        let cmd = Cmd::Print(AdvancedPrint::default()).downcast();
+        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
+        let client = Client::new(&config);
+
+        let path = S3Path {
+            bucket: "test".to_owned(),
+            prefix: None,
+            region: Region::from_static("us-east-1"),
+            kind: PathKind::Prefix,
+        };
+
+        cmd.execute(&client, &path, &[object]).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_print_output_s3() -> Result<(), Error> {
+        let etag = |e_tag: &str| ReplayResponse {
+            headers: vec![("ETag".to_owned(), e_tag.to_owned())],
+            ..ReplayResponse::new(200, "")
+        };
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>"#,
+            ),
+            etag("\"part1\""),
+            etag("\"part2\""),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<CompleteMultipartUploadResult><ETag>"whole-2"</ETag></CompleteMultipartUploadResult>"#,
+            ),
+        ]);
+        let print = AdvancedPrint {
+            output_s3: Some("s3://reports/runs/run-{date}.csv".parse()?),
+            ..Default::default()
+        };
+        let mut cmd = PrintCommand::new(print);
+        cmd.output.part_size = 100;
+        let path: S3Path = "s3://test/".parse()?;
+        let object = |key: &str| {
+            Object::builder()
+                .key(key)
+                .e_tag("\"etag\"")
+                .size(10)
+                .last_modified(DateTime::from_secs(1_500_000_000))
+                .build()
+        };
+
+        cmd.preflight(&client, &path).await?;
+        cmd.execute(&client, &path, &[object("a"), object("b")])
+            .await?;
+        cmd.execute(&client, &path, &[object("c")]).await?;
+        assert_eq!(http_client.requests().len(), 2);
+        cmd.finish(&client, &path).await?;
+
+        let date = Utc::now().format("%Y-%m-%d");
+        let key = format!("/runs/run-{}.csv", date);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests.iter().all(|x| x.path().ends_with(&key)));
+        assert!(requests[0].uri.contains("uploads"));
+        assert!(requests[1].uri.contains("partNumber=1"));
+        assert!(requests[1].uri.contains("uploadId=upload-1"));
+        assert_eq!(requests[1].body.len(), 100);
+        assert!(requests[2].uri.contains("partNumber=2"));
+        let output = [requests[1].body.clone(), requests[2].body.clone()].concat();
+        assert_eq!(String::from_utf8(output)?.lines().count(), 3);
+
+        let complete = String::from_utf8(requests[3].body.clone())?;
+        assert_eq!(requests[3].method, "POST");
+        assert!(requests[3].uri.contains("uploadId=upload-1"));
+        assert!(complete.contains("<ETag>&quot;part1&quot;</ETag><PartNumber>1</PartNumber>"));
+        assert!(complete.contains("<ETag>&quot;part2&quot;</ETag><PartNumber>2</PartNumber>"));
+        assert_eq!(
+            cmd.summary(),
+            Some(format!("Output: s3://reports/runs/run-{}.csv", date))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fastprint() -> Result<(), Error> {
+        let object = Object::builder()
+            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
+            .key("somepath/otherpath")
+            .size(4_997_288)
+            .storage_class(ObjectStorageClass::Standard)
+            .last_modified(DateTime::from_str(
+                "2017-07-19T19:04:17.000Z",
+                Format::DateTime,
+            )?)
+            .build();
+
+        let cmd = Cmd::Ls(FastPrint::default()).downcast();
+        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
+        let client = Client::new(&config);
+
+        let path = S3Path {
+            bucket: "test".to_owned(),
+            prefix: None,
+            region: Region::from_static("us-east-1"),
+            kind: PathKind::Prefix,
+        };
+
+        cmd.execute(&client, &path, &[object]).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn smoke_donothing() -> Result<(), Error> {
+        let object = Object::builder()
+            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
+            .key("somepath/otherpath")
+            .size(4_997_288)
+            .storage_class(ObjectStorageClass::Standard)
+            .last_modified(DateTime::from_str(
+                "2017-07-19T19:04:17.000Z",
+                Format::DateTime,
+            )?)
+            .build();
+
+        let cmd = Cmd::Nothing(DoNothing {}).downcast();
+        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
+        let client = Client::new(&config);
+
+        let path = S3Path {
+            bucket: "test".to_owned(),
+            prefix: None,
+            region: Region::from_static("us-east-1"),
+            kind: PathKind::Prefix,
+        };
+
+        cmd.execute(&client, &path, &[object]).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn smoke_exec() -> Result<(), Error> {
+        let object = Object::builder()
+            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
+            .key("somepath/otherpath")
+            .size(4_997_288)
+            .storage_class(ObjectStorageClass::Standard)
+            .last_modified(DateTime::from_str(
+                "2017-07-19T19:04:17.000Z",
+                Format::DateTime,
+            )?)
+            .build();
+
+        let cmd = Cmd::Exec(Exec {
+            utility: "echo {}".to_owned(),
+            download_temp: false,
+            max_size: None,
+            no_option_guard: false,
+        })
+        .downcast();
+
+        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
+        let client = Client::new(&config);
+
+        let path = S3Path {
+            bucket: "test".to_owned(),
+            prefix: None,
+            region: Region::from_static("us-east-1"),
+            kind: PathKind::Prefix,
+        };
+
+        cmd.execute(&client, &path, &[object]).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_s3_url() {
+        assert_eq!(
+            &generate_s3_url("us-east-1", "test-bucket", "somepath/somekey"),
+            "https://test-bucket.s3.amazonaws.com/somepath/somekey",
+        );
+        assert_eq!(
+            &generate_s3_url("eu-west-1", "test-bucket", "somepath/somekey"),
+            "https://test-bucket.s3-eu-west-1.amazonaws.com/somepath/somekey",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_destination() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(204, ""),
+        ]);
+        let destination: S3Path = "s3://dest/prefix".parse()?;
+
+        check_destination(&client, &destination).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, "HEAD");
+        assert_eq!(requests[1].method, "PUT");
+        assert_eq!(requests[2].method, "DELETE");
+        assert!(requests[1]
+            .path()
+            .starts_with("https://dest.s3.us-east-1.amazonaws.com/prefix/.s3find-preflight-"));
+        assert_eq!(requests[1].header("content-length"), Some("0"));
+        assert_eq!(requests[1].path(), requests[2].path());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_destination_forbidden() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(403, "")]);
+        let destination: S3Path = "s3://dest/prefix".parse()?;
+
+        let error = check_destination(&client, &destination).await.unwrap_err();
+
+        assert!(error.to_string().contains("s3://dest/prefix"));
+        assert_eq!(http_client.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_preflight() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![]);
+        let cmd = Cmd::Copy(S3Copy {
+            destination: "s3://dest/prefix".parse()?,
+            flat: false,
+            no_preflight: true,
+            if_unmodified: false,
+            no_auto_exclude: false,
+            collision_strategy: CollisionStrategy::Error,
+            restore_and_retry: false,
+            restore_days: 1,
+            wait: false,
+            workers: Workers::Fixed(1),
+            schedule: Schedule::Listing,
+            targets: TargetTracker::default(),
+        })
+        .downcast();
+
+        cmd.preflight(&client, &"s3://test/".parse()?).await?;
+
+        assert_eq!(http_client.requests().len(), 0);
+        Ok(())
+    }
+
+    fn flat_copy(strategy: CollisionStrategy) -> S3Copy {
+        S3Copy {
+            destination: "s3://dest/flat".parse().unwrap(),
+            flat: true,
+            no_preflight: true,
+            if_unmodified: false,
+            no_auto_exclude: false,
+            collision_strategy: strategy,
+            restore_and_retry: false,
+            restore_days: 1,
+            wait: false,
+            workers: Workers::Fixed(1),
+            schedule: Schedule::Listing,
+            targets: TargetTracker::default(),
+        }
+    }
+
+    fn colliding_batches() -> (S3Path, Vec<Object>, Vec<Object>) {
+        let path: S3Path = "s3://test/".parse().unwrap();
+        (
+            path,
+            vec![Object::builder().key("a/report").build()],
+            vec![Object::builder().key("b/report").build()],
+        )
+    }
+
+    #[test]
+    fn test_nested_destination() {
+        let nested = |path: &str, destination: &str| {
+            nested_destination(&path.parse().unwrap(), &destination.parse().unwrap())
+        };
+        assert_eq!(
+            nested("s3://b/data/", "s3://b/data/backup").as_deref(),
+            Some("data/backup/")
+        );
+        assert_eq!(
+            nested("s3://b/", "s3://b/backup/").as_deref(),
+            Some("backup/")
+        );
+        assert_eq!(nested("s3://b/data/", "s3://b/other"), None);
+        assert_eq!(nested("s3://b/data/", "s3://c/data/backup"), None);
+        assert_eq!(nested("s3://b/data/", "s3://b/data/"), None);
+        assert_eq!(nested("s3://b/data/backup/", "s3://b/data/"), None);
+        assert_eq!(nested("s3://b/", "s3://b/"), None);
+
+        let copy = |args: &[&str]| {
+            let mut argv = vec!["s3find", "s3://b/data/", "copy"];
+            argv.extend(args);
+            FindOpt::from_iter_safe(&argv).unwrap().cmd.unwrap()
+        };
+        let path = "s3://b/data/".parse().unwrap();
+        assert_eq!(
+            copy(&["s3://b/data/backup"]).auto_exclude(&path).as_deref(),
+            Some("data/backup/")
+        );
+        assert_eq!(
+            copy(&["--no-auto-exclude", "s3://b/data/backup"]).auto_exclude(&path),
+            None
+        );
+        assert_eq!(copy(&["/tmp"]).auto_exclude(&path), None);
+    }
+
+    #[tokio::test]
+    async fn test_nested_destination_refused() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![]);
+        let cmd = S3Move {
+            destination: "s3://test/data/backup".parse()?,
+            no_preflight: true,
+            quiet_versioning_warning: true,
+            no_auto_exclude: true,
+            flat: false,
+            if_unmodified: false,
+            collision_strategy: CollisionStrategy::Error,
+            restore_and_retry: false,
+            restore_days: 1,
+            wait: false,
+            workers: Workers::Fixed(1),
+            schedule: Schedule::Listing,
+            targets: TargetTracker::default(),
+            two_phase: false,
+            rollback_on_failure: false,
+            phases: Default::default(),
+        };
+
+        let error = cmd
+            .preflight(&client, &"s3://test/data/".parse()?)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Destination s3://test/data/backup/ is inside the source path s3://test/data/, the copied keys would be listed again"
+        );
+        assert!(cmd
+            .preflight(&client, &"s3://test/other/".parse()?)
+            .await
+            .is_ok());
+        assert!(http_client.requests().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_collision_error() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "")]);
+        let cmd = flat_copy(CollisionStrategy::Error);
+        let (path, first, second) = colliding_batches();
+
+        cmd.execute(&client, &path, &first).await?;
+        cmd.execute(&client, &path, &second).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].path(),
+            "https://dest.s3.us-east-1.amazonaws.com/flat/report"
+        );
+        assert_eq!(
+            requests[0].header("x-amz-copy-source"),
+            Some("test/a/report")
+        );
+        assert!(cmd
+            .targets
+            .resolve(
+                CollisionStrategy::Error,
+                "b/report",
+                "flat/report".to_owned()
+            )
+            .unwrap_err()
+            .to_string()
+            .contains("a/report"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_collision_skip() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "")]);
+        let cmd = flat_copy(CollisionStrategy::Skip);
+        let (path, first, second) = colliding_batches();
+
+        cmd.execute(&client, &path, &first).await?;
+        cmd.execute(&client, &path, &second).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].header("x-amz-copy-source"),
+            Some("test/a/report")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_collision_suffix() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(200, ""),
+        ]);
+        let cmd = flat_copy(CollisionStrategy::Suffix);
+        let (path, first, second) = colliding_batches();
+
+        cmd.execute(&client, &path, &first).await?;
+        cmd.execute(&client, &path, &second).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1].path(),
+            "https://dest.s3.us-east-1.amazonaws.com/flat/report.1"
+        );
+        assert_eq!(
+            requests[1].header("x-amz-copy-source"),
+            Some("test/b/report")
+        );
+        Ok(())
+    }
+
+    fn plan_copy(strategy: CollisionStrategy) -> PlanCopy {
+        PlanCopy {
+            destination: "s3://dest/flat".parse().unwrap(),
+            flat: true,
+            no_auto_exclude: false,
+            collision_strategy: strategy,
+            targets: TargetTracker::default(),
+            tally: PlanTally::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plan_copy_collision() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![]);
+        let cmd = plan_copy(CollisionStrategy::Error);
+        let (path, first, second) = colliding_batches();
+
+        cmd.execute(&client, &path, &first).await?;
+        let stats = cmd.execute(&client, &path, &second).await?;
+
+        assert!(http_client.requests().is_empty());
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(
+            cmd.summary().unwrap(),
+            "Planned: 1 keys, 1 target key collisions\n\
+collision: s3://dest/flat/report <= a/report, b/report"
+        );
+        assert!(cmd.failed());
+        assert_eq!(cmd.exit_code(), PLAN_COLLISION_EXIT_CODE);
+        assert_eq!(PLAN_COLLISION_EXIT_CODE, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_copy_matches_copy() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(200, ""),
+        ]);
+        let (path, first, second) = colliding_batches();
+
+        let plan = plan_copy(CollisionStrategy::Suffix);
+        plan.execute(&client, &path, &first).await?;
+        plan.execute(&client, &path, &second).await?;
+        assert!(http_client.requests().is_empty());
+
+        let copy = flat_copy(CollisionStrategy::Suffix);
+        copy.execute(&client, &path, &first).await?;
+        copy.execute(&client, &path, &second).await?;
+
+        assert_eq!(plan.targets, copy.targets);
+        assert_eq!(plan.tally.0.lock().unwrap().planned, 2);
+        assert!(plan.failed());
+
+        let plan = plan_copy(CollisionStrategy::Error);
+        plan.execute(&client, &path, &first).await?;
+        assert_eq!(
+            plan.summary().unwrap(),
+            "Planned: 1 keys, 0 target key collisions"
+        );
+        assert!(!plan.failed());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_move_collision_keeps_source() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(200, "<DeleteResult></DeleteResult>"),
+        ]);
+        let cmd = S3Move {
+            destination: "s3://dest/flat".parse()?,
+            flat: true,
+            no_preflight: true,
+            if_unmodified: false,
+            no_auto_exclude: false,
+            quiet_versioning_warning: false,
+            collision_strategy: CollisionStrategy::Skip,
+            restore_and_retry: false,
+            restore_days: 1,
+            wait: false,
+            workers: Workers::Fixed(1),
+            schedule: Schedule::Listing,
+            targets: TargetTracker::default(),
+            two_phase: false,
+            rollback_on_failure: false,
+            phases: Default::default(),
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a/report").build(),
+            Object::builder().key("b/report").build(),
+        ];
+
+        cmd.execute(&client, &path, &list).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        let body = String::from_utf8(requests[1].body.clone())?;
+        assert!(body.contains("<Key>a/report</Key>"));
+        assert!(!body.contains("b/report"));
+        Ok(())
+    }
+
+    fn storage_rules(rules: &[&str]) -> EnforceStorage {
+        EnforceStorage {
+            rules: rules.iter().map(|x| x.parse().unwrap()).collect(),
+            apply: true,
+        }
+    }
+
+    fn stored_object(key: &str, class: ObjectStorageClass, age: i64) -> Object {
+        Object::builder()
+            .key(key)
+            .storage_class(class)
+            .last_modified(DateTime::from_secs(1_700_000_000 - age))
+            .build()
+    }
+
+    #[test]
+    fn test_enforce_storage_drift() {
+        let now = 1_700_000_000;
+        let day = 24 * 3600;
+        let cmd = storage_rules(&[
+            "raw/old/*=GLACIER",
+            "raw/*=STANDARD_IA@30d",
+            "archive/*=DEEP_ARCHIVE",
+        ]);
+
+        let drifted = stored_object("raw/old/a", ObjectStorageClass::StandardIa, 0);
+        assert_eq!(
+            cmd.drift(&drifted, now).map(|x| x.class.as_str()),
+            Some("GLACIER")
+        );
+
+        let aged = stored_object("raw/b", ObjectStorageClass::Standard, 31 * day);
+        assert_eq!(
+            cmd.drift(&aged, now).map(|x| x.class.as_str()),
+            Some("STANDARD_IA")
+        );
+
+        let fresh = stored_object("raw/c", ObjectStorageClass::Standard, 29 * day);
+        assert_eq!(cmd.drift(&fresh, now), None);
+
+        let compliant = stored_object("archive/d", ObjectStorageClass::DeepArchive, 0);
+        assert_eq!(cmd.drift(&compliant, now), None);
+
+        let unmatched = stored_object("other/e", ObjectStorageClass::Glacier, 0);
+        assert_eq!(cmd.drift(&unmatched, now), None);
+
+        let unlisted_class = Object::builder()
+            .key("archive/f")
+            .last_modified(DateTime::from_secs(now))
+            .build();
+        assert_eq!(
+            cmd.drift(&unlisted_class, now).map(|x| x.class.as_str()),
+            Some("DEEP_ARCHIVE")
+        );
+    }
+
+    #[test]
+    fn test_enforce_storage_first_rule_wins() {
+        let now = 1_700_000_000;
+        let cmd = storage_rules(&["raw/*=STANDARD_IA@30d", "*=GLACIER"]);
+
+        // too young for the first matching rule, the later rules are not tried
+        let fresh = stored_object("raw/a", ObjectStorageClass::Standard, 0);
+        assert_eq!(cmd.drift(&fresh, now), None);
+
+        let other = stored_object("logs/b", ObjectStorageClass::Standard, 0);
+        assert_eq!(
+            cmd.drift(&other, now).map(|x| x.class.as_str()),
+            Some("GLACIER")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enforce_storage_apply() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<CopyObjectResult><ETag>"9d48114aa7c18f9d68aa20086dbb7756"</ETag></CopyObjectResult>"#,
+        )]);
+        let cmd = storage_rules(&["raw/*=STANDARD_IA", "archive/*=DEEP_ARCHIVE"]);
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            stored_object("archive/a", ObjectStorageClass::DeepArchive, 0),
+            stored_object("raw/b", ObjectStorageClass::Standard, 0),
+            stored_object("raw/c", ObjectStorageClass::StandardIa, 0),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].path(),
+            "https://test.s3.us-east-1.amazonaws.com/raw/b"
+        );
+        assert_eq!(requests[0].header("x-amz-copy-source"), Some("test/raw/b"));
+        assert_eq!(
+            requests[0].header("x-amz-storage-class"),
+            Some("STANDARD_IA")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_enforce_storage_print() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![]);
+        let cmd = EnforceStorage {
+            apply: false,
+            ..storage_rules(&["raw/*=STANDARD_IA"])
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![stored_object("raw/b", ObjectStorageClass::Standard, 0)];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert!(http_client.requests().is_empty());
+        Ok(())
+    }
+
+    fn listed_object(key: &str, e_tag: &str) -> Object {
+        Object::builder()
+            .key(key)
+            .e_tag(e_tag)
+            .last_modified(DateTime::from_secs(1_700_000_000))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_copy_if_unmodified() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(412, ""),
+        ]);
+        let cmd = S3Copy {
+            if_unmodified: true,
+            flat: false,
+            ..flat_copy(CollisionStrategy::Error)
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            listed_object("a/report", "\"etag-a\""),
+            listed_object("b/report", "\"etag-b\""),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert!(stats.errors.is_empty());
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].header("x-amz-copy-source-if-match"),
+            Some("\"etag-a\"")
+        );
+        assert_eq!(
+            requests[0].header("x-amz-copy-source-if-unmodified-since"),
+            Some("Tue, 14 Nov 2023 22:13:20 GMT")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_source_encoded() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![copy_result()]);
+        let cmd = S3Copy {
+            flat: false,
+            ..flat_copy(CollisionStrategy::Error)
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![listed_object("logs\u{1}/a b?.txt", "\"etag\"")];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        let requests = http_client.requests();
+        assert_eq!(
+            requests[0].header("x-amz-copy-source"),
+            Some("test/logs%01/a%20b%3F.txt")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_bytes_exclude_errors() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>a</Key></Deleted><Deleted><Key>c</Key></Deleted><Error><Key>b</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error></DeleteResult>"#,
+        )]);
+        let cmd = MultipleDelete::default();
+        let path: S3Path = "s3://test/".parse()?;
+        let list: Vec<_> = [("a", 100), ("b", 2000), ("c", 30)]
+            .iter()
+            .map(|(key, size)| Object::builder().key(*key).size(*size).build())
+            .collect();
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.deleted, 2);
+        assert_eq!(stats.reclaimed_bytes, 130);
+        assert_eq!(
+            stats.errors,
+            vec!["s3://test/b: AccessDenied: Access Denied".to_owned()]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_if_unmodified() -> Result<(), Error> {
+        let head = |e_tag: &str| ReplayResponse {
+            headers: vec![("ETag".to_owned(), e_tag.to_owned())],
+            ..ReplayResponse::new(200, "")
+        };
+        let (client, http_client) = replay_client(vec![
+            head("\"etag-a\""),
+            head("\"changed\""),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>a</Key></Deleted></DeleteResult>"#,
+            ),
+        ]);
+        let cmd = MultipleDelete {
+            if_unmodified: true,
+            quiet_versioning_warning: false,
+            ..Default::default()
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            listed_object("a", "\"etag-a\""),
+            listed_object("b", "\"etag-b\""),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.deleted, 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, "HEAD");
+        assert_eq!(requests[1].method, "HEAD");
+        let body = String::from_utf8(requests[2].body.clone())?;
+        assert!(body.contains("<Key>a</Key>"));
+        assert!(!body.contains("<Key>b</Key>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_snapshot_guard() -> Result<(), Error> {
+        let head = |e_tag: &str| ReplayResponse {
+            headers: vec![("ETag".to_owned(), e_tag.to_owned())],
+            ..ReplayResponse::new(200, "")
+        };
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(204, ""),
+            ReplayResponse::new(
+                412,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>PreconditionFailed</Code><Message>At least one of the pre-conditions you specified did not hold</Message></Error>"#,
+            ),
+            head("\"fresh\""),
+        ]);
+        let cmd = MultipleDelete {
+            snapshot_guard: true,
+            quiet_versioning_warning: true,
+            ..Default::default()
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            listed_object("a", "\"etag-a\""),
+            listed_object("b", "\"etag-b\""),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.deleted, 1);
+        assert_eq!(stats.skipped, 1);
+        assert!(stats.errors.is_empty());
+        assert_eq!(stats.mutations[0].keys, vec!["a"]);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, "DELETE");
+        assert!(requests[0].path().ends_with("/a"));
+        assert_eq!(requests[0].header("if-match"), Some("\"etag-a\""));
+        assert_eq!(requests[1].method, "DELETE");
+        assert_eq!(requests[1].header("if-match"), Some("\"etag-b\""));
+        assert_eq!(requests[2].method, "HEAD");
+        assert!(requests[2].path().ends_with("/b"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_snapshot_guard_without_if_match() -> Result<(), Error> {
+        let head = |e_tag: &str| ReplayResponse {
+            headers: vec![("ETag".to_owned(), e_tag.to_owned())],
+            ..ReplayResponse::new(200, "")
+        };
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                501,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>NotImplemented</Code><Message>A header you provided implies functionality that is not implemented</Message></Error>"#,
+            ),
+            head("\"etag-a\""),
+            ReplayResponse::new(204, ""),
+            head("\"changed\""),
+        ]);
+        let cmd = MultipleDelete {
+            snapshot_guard: true,
+            quiet_versioning_warning: true,
+            ..Default::default()
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            listed_object("a", "\"etag-a\""),
+            listed_object("b", "\"etag-b\""),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.deleted, 1);
+        assert_eq!(stats.skipped, 1);
+        assert!(cmd.if_match_unsupported.0.load(Ordering::Relaxed));
+        let requests = http_client.requests();
+        let methods: Vec<_> = requests.iter().map(|x| x.method.as_str()).collect();
+        // the second key skips the conditional delete
+        assert_eq!(methods, vec!["DELETE", "HEAD", "DELETE", "HEAD"]);
+        assert_eq!(requests[2].header("if-match"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_object_without_key() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let commands = vec![
+            Cmd::Download(Download {
+                force: false,
+                destination: dir.path().display().to_string(),
+                range_bytes: None,
+                range: None,
+                verify_size_with_head: false,
+                version_id: None,
+            }),
+            Cmd::Tags(SetTags {
+                tags: vec!["key:value".parse()?],
+                version_id: None,
+            }),
+            Cmd::Public(SetPublic {
+                acknowledged: true,
+                blocked: Default::default(),
+            }),
+            Cmd::Exec(Exec {
+                utility: "echo {}".to_owned(),
+                download_temp: false,
+                max_size: None,
+                no_option_guard: false,
+            }),
+            Cmd::LsTags(ListTags { version_id: None }),
+            Cmd::Delete(MultipleDelete {
+                quiet_versioning_warning: true,
+                ..Default::default()
+            }),
+        ];
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![Object::builder().size(10).build()];
+
+        for cmd in commands {
+            let (client, http_client) = replay_client(vec![]);
+
+            let stats = cmd.downcast().execute(&client, &path, &list).await?;
+
+            assert_eq!(stats.processed, 0);
+            assert_eq!(stats.errors, vec!["S3 Object is not complete".to_owned()]);
+            assert!(http_client.requests().is_empty());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_errors_skip_the_key() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a").build(),
+            Object::builder().key("b").build(),
+        ];
+        let denied = || {
+            ReplayResponse::new(
+                403,
+                "<Error><Code>AccessDenied</Code><Message>denied</Message></Error>",
+            )
+        };
+
+        let (client, _) = replay_client(vec![
+            denied(),
+            ReplayResponse::new(
+                200,
+                "<Tagging><TagSet><Tag><Key>k</Key><Value>v</Value></Tag></TagSet></Tagging>",
+            ),
+        ]);
+        let cmd = Cmd::LsTags(ListTags { version_id: None }).downcast();
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.errors.len(), 1);
+        assert!(stats.errors[0].starts_with("s3://test/a: AccessDenied"));
+
+        let (client, http_client) = replay_client(vec![denied(), copy_result()]);
+        let cmd = S3Copy {
+            flat: false,
+            workers: "1".parse()?,
+            ..flat_copy(CollisionStrategy::Error)
+        };
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.errors.len(), 1);
+        assert!(stats.errors[0].starts_with("s3://test/a: AccessDenied"));
+        assert_eq!(http_client.requests().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_version() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "old content")]);
+        let cmd = Cmd::Download(Download {
+            force: false,
+            destination: dir.path().display().to_string(),
+            range_bytes: None,
+            range: None,
+            verify_size_with_head: false,
+            version_id: None,
+        })
+        .with_version_id(Some("v1".to_owned()))
+        .downcast();
+        let path: S3Path = "s3://test/dir/key".parse()?;
+        let list = vec![Object::builder().key("dir/key").size(11).build()];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.downloaded_bytes, 11);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("dir/key"))?,
+            "old content"
+        );
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].uri.contains("versionId=v1"));
+        Ok(())
+    }
+
+    fn object_head(headers: &[(&str, &str)]) -> ReplayResponse {
+        ReplayResponse {
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..ReplayResponse::new(200, "")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recompress_in_place() -> Result<(), Error> {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let content = r#"{"id": 1, "name": "example"}"#.repeat(50);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(9));
+        encoder.write_all(content.as_bytes())?;
+        let compressed = encoder.finish()?.len().to_string();
+
+        let (client, http_client) = replay_client(vec![
+            object_head(&[
+                ("Content-Type", "application/json"),
+                ("ETag", "\"source\""),
+                ("x-amz-meta-origin", "export"),
+            ]),
+            ReplayResponse::new(200, &content),
+            object_head(&[("ETag", "\"gzipped\"")]),
+            object_head(&[
+                ("Content-Length", &compressed),
+                ("Content-Encoding", "gzip"),
+            ]),
+        ]);
+        let cmd = Recompress {
+            level: 9,
+            suffix: None,
+            delete_source: false,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = [Object::builder().key("data/a.json").size(1400).build()];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert!(stats.errors.is_empty());
+        assert_eq!(
+            stats.mutations,
+            vec![Mutation {
+                action: "recompress",
+                keys: vec!["data/a.json".to_owned()],
+            }]
+        );
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[1].method, "GET");
+        assert_eq!(requests[1].header("if-match"), Some("\"source\""));
+        let put = &requests[2];
+        assert_eq!(put.method, "PUT");
+        assert!(put.path().ends_with("/data/a.json"));
+        assert_eq!(put.header("content-encoding"), Some("gzip"));
+        assert_eq!(put.header("content-type"), Some("application/json"));
+        assert_eq!(put.header("x-amz-meta-origin"), Some("export"));
+        let mut decompressed = String::new();
+        GzDecoder::new(&put.body[..]).read_to_string(&mut decompressed)?;
+        assert_eq!(decompressed, content);
+        assert_eq!(requests[3].method, "HEAD");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_encoded() {
+        assert!(is_encoded("gzip"));
+        assert!(is_encoded("identity, br"));
+        assert!(!is_encoded("identity"));
+        assert!(!is_encoded("Identity"));
+        assert!(!is_encoded(""));
+    }
+
+    #[tokio::test]
+    async fn test_recompress_skips_encoded() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            object_head(&[("Content-Encoding", "gzip")]),
+            object_head(&[("Content-Encoding", "br, GZIP")]),
+            object_head(&[("Content-Encoding", "br")]),
+            object_head(&[("Content-Encoding", "zstd")]),
+        ]);
+        let cmd = Recompress {
+            level: 6,
+            suffix: Some(".gz".to_owned()),
+            delete_source: true,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = [
+            Object::builder().key("a.json").build(),
+            Object::builder().key("b.json").build(),
+            Object::builder().key("c.json").build(),
+            Object::builder().key("d.json").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!((stats.skipped, stats.processed, stats.deleted), (4, 0, 0));
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests.iter().all(|x| x.method == "HEAD"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recompress_suffix_delete_source() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            object_head(&[("Content-Type", "text/plain")]),
+            ReplayResponse::new(200, "hello"),
+            object_head(&[]),
+            // a target holding other bytes fails the verification
+            object_head(&[("Content-Length", "1"), ("Content-Encoding", "gzip")]),
+        ]);
+        let cmd = Recompress {
+            level: 6,
+            suffix: Some(".gz".to_owned()),
+            delete_source: true,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = [Object::builder().key("a.txt").build()];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!((stats.processed, stats.deleted), (0, 0));
+        assert_eq!(stats.errors.len(), 1);
+        assert!(stats.errors[0].starts_with("s3://test/a.txt: Recompressed s3://test/a.txt.gz"));
+        // the source of a failed verification is kept
+        assert!(http_client.requests().iter().all(|x| x.method != "DELETE"));
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(6));
+        encoder.write_all(b"hello")?;
+        let compressed = encoder.finish()?.len().to_string();
+        let (client, http_client) = replay_client(vec![
+            object_head(&[("Content-Type", "text/plain")]),
+            ReplayResponse::new(200, "hello"),
+            object_head(&[]),
+            object_head(&[
+                ("Content-Length", &compressed),
+                ("Content-Encoding", "gzip"),
+            ]),
+            ReplayResponse::new(204, ""),
+        ]);
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!((stats.processed, stats.deleted), (1, 1));
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[2].path().ends_with("/a.txt.gz"));
+        assert!(requests[3].path().ends_with("/a.txt.gz"));
+        assert_eq!(requests[4].method, "DELETE");
+        assert!(requests[4].path().ends_with("/a.txt"));
+        assert_eq!(
+            stats.mutations,
+            vec![
+                Mutation {
+                    action: "recompress",
+                    keys: vec!["a.txt.gz".to_owned()],
+                },
+                Mutation {
+                    action: "delete",
+                    keys: vec!["a.txt".to_owned()],
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_touch() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            object_head(&[
+                ("Content-Type", "application/json"),
+                ("Content-Length", "12"),
+                ("Cache-Control", "max-age=60"),
+                ("ETag", "\"a\""),
+                ("x-amz-meta-origin", "export"),
+                ("x-amz-storage-class", "STANDARD_IA"),
+                ("x-amz-server-side-encryption", "aws:kms"),
+                ("x-amz-server-side-encryption-aws-kms-key-id", "key-1"),
+            ]),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<CopyObjectResult><ETag>"b"</ETag></CopyObjectResult>"#,
+            ),
+            object_head(&[("ETag", "\"c\"")]),
+            // modified since its HEAD
+            ReplayResponse::new(412, ""),
+        ]);
+        let cmd = Touch {
+            marker: Some("touched-at".to_owned()),
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = [
+            Object::builder().key("data/a.json").build(),
+            Object::builder().key("data/c.json").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!((stats.processed, stats.skipped), (1, 1));
+        assert!(stats.errors.is_empty());
+        assert_eq!(
+            stats.mutations,
+            vec![Mutation {
+                action: "touch",
+                keys: vec!["data/a.json".to_owned()],
+            }]
+        );
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        let copy = &requests[1];
+        assert_eq!(copy.method, "PUT");
+        assert!(copy.path().ends_with("/data/a.json"));
+        assert_eq!(copy.header("x-amz-copy-source"), Some("test/data/a.json"));
+        assert_eq!(copy.header("x-amz-copy-source-if-match"), Some("\"a\""));
+        assert_eq!(copy.header("x-amz-metadata-directive"), Some("REPLACE"));
+        assert_eq!(copy.header("content-type"), Some("application/json"));
+        assert_eq!(copy.header("cache-control"), Some("max-age=60"));
+        assert_eq!(copy.header("x-amz-meta-origin"), Some("export"));
+        assert!(copy.header("x-amz-meta-touched-at").is_some());
+        assert_eq!(copy.header("x-amz-storage-class"), Some("STANDARD_IA"));
+        assert_eq!(copy.header("x-amz-server-side-encryption"), Some("aws:kms"));
+        assert_eq!(
+            copy.header("x-amz-server-side-encryption-aws-kms-key-id"),
+            Some("key-1")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_touch_multipart() -> Result<(), Error> {
+        let size = (MAX_COPY_SIZE + COPY_PART_SIZE / 2).to_string();
+        let mut responses = vec![
+            object_head(&[
+                ("Content-Type", "video/mp4"),
+                ("Content-Length", &size),
+                ("ETag", "\"big\""),
+            ]),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult><Bucket>test</Bucket><Key>big.mp4</Key><UploadId>up1</UploadId></InitiateMultipartUploadResult>"#,
+            ),
+        ];
+        for part in 1..=6 {
+            responses.push(ReplayResponse::new(
+                200,
+                &format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<CopyPartResult><ETag>"p{}"</ETag></CopyPartResult>"#,
+                    part
+                ),
+            ));
+        }
+        responses.push(ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<CompleteMultipartUploadResult><ETag>"done"</ETag></CompleteMultipartUploadResult>"#,
+        ));
+        let (client, http_client) = replay_client(responses);
+        let cmd = Touch { marker: None };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = [Object::builder().key("big.mp4").build()];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 9);
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(requests[1].header("content-type"), Some("video/mp4"));
+        assert_eq!(
+            requests[2].header("x-amz-copy-source-range"),
+            Some("bytes=0-1073741823")
+        );
+        assert_eq!(
+            requests[7].header("x-amz-copy-source-range"),
+            Some(
+                format!(
+                    "bytes=5368709120-{}",
+                    MAX_COPY_SIZE + COPY_PART_SIZE / 2 - 1
+                )
+                .as_str()
+            )
+        );
+        assert!(requests[2..8]
+            .iter()
+            .all(|x| x.header("x-amz-copy-source-if-match") == Some("\"big\"")));
+        let complete = String::from_utf8_lossy(&requests[8].body);
+        assert!(
+            complete.contains("<PartNumber>6</PartNumber>"),
+            "{}",
+            complete
+        );
+        assert!(complete.contains("p6"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_ranges() {
+        assert_eq!(
+            copy_ranges(25, 10),
+            vec!["bytes=0-9", "bytes=10-19", "bytes=20-24"]
+        );
+        assert_eq!(copy_ranges(20, 10), vec!["bytes=0-9", "bytes=10-19"]);
+        assert!(copy_ranges(0, 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_download_size_from_head() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let content = "x".repeat(1234);
+        let (client, http_client) = replay_client(vec![
+            head(1234, "\"etag\""),
+            ReplayResponse::new(200, &content),
+            head(1234, "\"etag\""),
+            ReplayResponse::new(200, "truncated"),
+        ]);
+        let cmd = Download {
+            force: true,
+            destination: dir.path().display().to_string(),
+            range_bytes: None,
+            range: None,
+            verify_size_with_head: true,
+            version_id: None,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = [
+            Object::builder().key("full").size(0).build(),
+            Object::builder().key("short").size(0).build(),
+            Object::builder().key("listed").size(6).build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list[..2]).await?;
+
+        assert_eq!(stats.size_corrected, 2);
+        assert_eq!(stats.processed, 1);
+        assert_eq!(fs::metadata(dir.path().join("full"))?.len(), 1234);
+        assert_eq!(
+            stats.errors,
+            vec!["s3://test/short: Downloaded 9 bytes, expected 1234".to_owned()]
+        );
+        let methods: Vec<_> = http_client
+            .requests()
+            .iter()
+            .map(|x| x.method.clone())
+            .collect();
+        assert_eq!(methods, vec!["HEAD", "GET", "HEAD", "GET"]);
+
+        // a listed size is used as is
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "listed")]);
+        let stats = cmd.execute(&client, &path, &list[2..]).await?;
+        assert_eq!((stats.processed, stats.size_corrected), (1, 0));
+        assert_eq!(http_client.requests()[0].method, "GET");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_size_from_head() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![head(1234, "\"a\""), head(1234, "\"b\"")]);
+        let cmd = Verify {
+            verify_size_with_head: true,
+            ..verify(false)
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![listed("a", 0, "\"a\""), listed("b", 0, "\"other\"")];
+
+        cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(
+            *cmd.tally.0.lock().unwrap(),
+            VerifyCounts {
+                verified: 1,
+                mismatched: 1,
+                missing: 0,
+                corrected: 2,
+            }
+        );
+        assert!(cmd
+            .summary()
+            .unwrap()
+            .contains("size corrected by head:     2 keys"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tags_version() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "")]);
+        let cmd = Cmd::Tags(SetTags {
+            tags: vec!["env:prod".parse()?],
+            version_id: None,
+        })
+        .with_version_id(Some("v1".to_owned()))
+        .downcast();
+        let path: S3Path = "s3://test/dir/key".parse()?;
+        let list = vec![Object::builder().key("dir/key").build()];
+
+        cmd.execute(&client, &path, &list).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].uri.contains("tagging"));
+        assert!(requests[0].uri.contains("versionId=v1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_tags_results() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(403, "<Error><Code>AccessDenied</Code></Error>"),
+            ReplayResponse::new(200, ""),
+        ]);
+        let cmd = Cmd::Tags(SetTags {
+            tags: vec!["env:prod".parse()?, "team:data".parse()?],
+            version_id: None,
+        })
+        .downcast();
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("denied").build(),
+            Object::builder().key("allowed").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.errors.len(), 1);
+        assert!(stats.errors[0].starts_with("s3://test/denied: "));
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        for request in requests {
+            let body = String::from_utf8(request.body)?;
+            assert!(body.contains("<Key>env</Key><Value>prod</Value>"));
+            assert!(body.contains("<Key>team</Key><Value>data</Value>"));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_command_error_request_ids() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![
+            ReplayResponse {
+                status: 403,
+                timeout: false,
+                headers: vec![
+                    ("x-amz-request-id".to_owned(), "4442587FB7D0A2F9".to_owned()),
+                    ("x-amz-id-2".to_owned(), "vlR7PnpV2Ce81l0P".to_owned()),
+                ],
+                body: "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>"
+                    .to_owned(),
+            },
+            ReplayResponse::new(200, ""),
+        ]);
+        let cmd = SetPublic {
+            acknowledged: true,
+            blocked: Default::default(),
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a").build(),
+            Object::builder().key("b").build(),
+        ];
+
+        // a failed key is reported and the next keys are still changed
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(
+            stats.errors,
+            vec!["s3://test/a: AccessDenied: Access Denied (request id: 4442587FB7D0A2F9, extended request id: vlR7PnpV2Ce81l0P)"]
+        );
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.mutations[0].keys, vec!["b"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_public_access_block_skips_acls() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a").size(1).build(),
+            Object::builder().key("b").size(1).build(),
+        ];
+
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<PublicAccessBlockConfiguration><BlockPublicAcls>true</BlockPublicAcls><IgnorePublicAcls>false</IgnorePublicAcls><BlockPublicPolicy>false</BlockPublicPolicy><RestrictPublicBuckets>false</RestrictPublicBuckets></PublicAccessBlockConfiguration>"#,
+        )]);
+        let cmd = SetPublic {
+            acknowledged: true,
+            blocked: Default::default(),
+        };
+        cmd.preflight(&client, &path).await?;
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 0);
+        assert_eq!(stats.skipped, 2);
+        assert!(stats.mutations.is_empty());
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].uri.contains("publicAccessBlock"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_public_without_access_block() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![Object::builder().key("a").size(1).build()];
+
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                404,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>NoSuchPublicAccessBlockConfiguration</Code><Message>The public access block configuration was not found</Message></Error>"#,
+            ),
+            ReplayResponse::new(200, ""),
+        ]);
+        let cmd = SetPublic {
+            acknowledged: true,
+            blocked: Default::default(),
+        };
+        cmd.preflight(&client, &path).await?;
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert_eq!(
+            stats.mutations,
+            vec![Mutation {
+                action: "public",
+                keys: vec!["a".to_owned()],
+            }]
+        );
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].uri.contains("acl"));
+        assert_eq!(requests[1].header("x-amz-acl"), Some("public-read"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_versioning_warning() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<VersioningConfiguration><Status>Enabled</Status></VersioningConfiguration>"#,
+            ),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<VersioningConfiguration/>"#,
+            ),
+        ]);
+
+        let status = |client: Client| async move {
+            client
+                .get_bucket_versioning()
+                .bucket("test")
+                .send()
+                .await
+                .map(|x| x.status)
+        };
+
+        let enabled = status(client.clone()).await?;
+        let warning = versioning_warning("test", enabled.as_ref()).unwrap();
+        assert!(warning.contains("versioning is Enabled for bucket test"));
+
+        let disabled = status(client).await?;
+        assert_eq!(versioning_warning("test", disabled.as_ref()), None);
+
+        assert!(http_client.requests()[0].uri.contains("versioning"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_preflight_versioning() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "")]);
+        let cmd = MultipleDelete {
+            if_unmodified: false,
+            quiet_versioning_warning: false,
+            ..Default::default()
+        };
+        cmd.preflight(&client, &path).await?;
+        assert_eq!(http_client.requests().len(), 1);
+
+        let (client, http_client) = replay_client(vec![]);
+        let cmd = MultipleDelete {
+            if_unmodified: false,
+            quiet_versioning_warning: true,
+            ..Default::default()
+        };
+        cmd.preflight(&client, &path).await?;
+        assert!(http_client.requests().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_to_local_directory() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, "first"),
+            ReplayResponse::new(200, "second"),
+        ]);
+        let cmd = S3Copy {
+            destination: format!("file://{}", dir.path().display()).parse()?,
+            ..flat_copy(CollisionStrategy::Error)
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("logs/a.log").build(),
+            Object::builder().key("logs/2024/b.log").build(),
+        ];
+
+        cmd.preflight(&client, &path).await?;
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 2);
+        assert_eq!(fs::read_to_string(dir.path().join("a.log"))?, "first");
+        assert_eq!(fs::read_to_string(dir.path().join("b.log"))?, "second");
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(
+            requests[0].path(),
+            "https://test.s3.us-east-1.amazonaws.com/logs/a.log"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_skipped_bytes() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        fs::create_dir_all(dir.path().join("logs"))?;
+        fs::write(dir.path().join("logs/present.log"), "present")?;
+
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "body")]);
+        let cmd = Download {
+            force: false,
+            destination: dir.path().display().to_string(),
+            range_bytes: None,
+            range: None,
+            verify_size_with_head: false,
+            version_id: None,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("logs/present.log").size(1000).build(),
+            Object::builder().key("logs/new.log").size(1000).build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.downloaded_bytes, 4);
+        assert_eq!(http_client.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_failed() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let (client, _) = replay_client(vec![ReplayResponse::new(403, "")]);
+        let cmd = Download {
+            force: false,
+            destination: dir.path().display().to_string(),
+            range_bytes: None,
+            range: None,
+            verify_size_with_head: false,
+            version_id: None,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![Object::builder().key("denied.log").build()];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 0);
+        assert_eq!(stats.downloaded_bytes, 0);
+        assert_eq!(stats.errors.len(), 1);
+        assert!(stats.errors[0].starts_with("s3://test/denied.log"));
+        Ok(())
+    }
+
+    fn lifecycle(rules: &[&str]) -> SimulateLifecycle {
+        SimulateLifecycle {
+            rules: rules.iter().map(|x| x.parse().unwrap()).collect(),
+            verbose: false,
+            tally: LifecycleTally::default(),
+        }
+    }
+
+    fn modified_at(key: &str, last_modified: &str, size: i64) -> Object {
+        Object::builder()
+            .key(key)
+            .size(size)
+            .last_modified(DateTime::from_str(last_modified, Format::DateTime).unwrap())
+            .build()
+    }
+
+    fn timestamp(s: &str) -> i64 {
+        DateTime::from_str(s, Format::DateTime).unwrap().secs()
+    }
+
+    #[test]
+    fn test_lifecycle_day_boundary() {
+        let cmd = lifecycle(&["transition:STANDARD_IA:30d"]);
+        let before_midnight = modified_at("a", "2024-01-01T23:59:59Z", 1);
+        let after_midnight = modified_at("b", "2024-01-02T00:00:01Z", 1);
+
+        // due at the midnight following last_modified + 30 days
+        let now = timestamp("2024-01-31T23:59:59Z");
+        assert_eq!(cmd.classify(&before_midnight, now), LifecycleAction::Keep);
+
+        let now = timestamp("2024-02-01T00:00:00Z");
+        assert_eq!(
+            cmd.classify(&before_midnight, now),
+            LifecycleAction::Transition("STANDARD_IA".to_owned())
+        );
+        assert_eq!(cmd.classify(&after_midnight, now), LifecycleAction::Keep);
+
+        let now = timestamp("2024-02-01T23:59:59Z");
+        assert_eq!(cmd.classify(&after_midnight, now), LifecycleAction::Keep);
+
+        let now = timestamp("2024-02-02T00:00:00Z");
+        assert_eq!(
+            cmd.classify(&after_midnight, now),
+            LifecycleAction::Transition("STANDARD_IA".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_rule_order() {
+        let cmd = lifecycle(&[
+            "transition:GLACIER:90d",
+            "transition:STANDARD_IA:30d",
+            "expire:365d",
+        ]);
+        let now = timestamp("2024-06-01T00:00:00Z");
+
+        let object = modified_at("a", "2024-04-01T00:00:00Z", 1);
+        assert_eq!(
+            cmd.classify(&object, now),
+            LifecycleAction::Transition("STANDARD_IA".to_owned())
+        );
+
+        // the first due transition in rule order wins
+        let object = modified_at("b", "2024-01-01T00:00:00Z", 1);
+        assert_eq!(
+            cmd.classify(&object, now),
+            LifecycleAction::Transition("GLACIER".to_owned())
+        );
+
+        // an expiration wins over any transition
+        let object = modified_at("c", "2023-01-01T00:00:00Z", 1);
+        assert_eq!(cmd.classify(&object, now), LifecycleAction::Expire);
+
+        let object = modified_at("d", "2024-05-30T00:00:00Z", 1);
+        assert_eq!(cmd.classify(&object, now), LifecycleAction::Keep);
+
+        // already stored in the target class
+        let object = Object::builder()
+            .key("e")
+            .storage_class(ObjectStorageClass::StandardIa)
+            .last_modified(DateTime::from_secs(timestamp("2024-04-01T00:00:00Z")))
+            .build();
+        assert_eq!(cmd.classify(&object, now), LifecycleAction::Keep);
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_tally() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![]);
+        let cmd = lifecycle(&["transition:STANDARD_IA:30d", "expire:365d"]);
+        let path: S3Path = "s3://test/".parse()?;
+
+        cmd.execute(
+            &client,
+            &path,
+            &[
+                modified_at("old", "2000-01-01T00:00:00Z", 1024),
+                modified_at("older", "1999-01-01T00:00:00Z", 1024),
+            ],
+        )
+        .await?;
+        cmd.execute(
+            &client,
+            &path,
+            &[Object::builder()
+                .key("new")
+                .size(10)
+                .last_modified(DateTime::from_secs(Utc::now().timestamp()))
+                .build()],
+        )
+        .await?;
+
+        let tally = cmd.tally.0.lock().unwrap().clone();
+        assert_eq!(tally.get("expire"), Some(&(2, 2048)));
+        assert_eq!(tally.get("no action"), Some(&(1, 10)));
+        assert_eq!(tally.len(), 2);
+
+        let summary = cmd.summary().unwrap();
+        assert!(summary.contains("expire:                     2 keys, 2 KiB"));
+        assert!(http_client.requests().is_empty());
+        Ok(())
+    }
+
+    fn head(size: i64, e_tag: &str) -> ReplayResponse {
+        ReplayResponse {
+            status: 200,
+            timeout: false,
+            headers: vec![
+                ("Content-Length".to_owned(), size.to_string()),
+                ("ETag".to_owned(), e_tag.to_owned()),
+            ],
+            body: String::new(),
+        }
+    }
+
+    fn delete_result(keys: &[&str]) -> ReplayResponse {
+        let deleted: String = keys
+            .iter()
+            .map(|x| format!("<Deleted><Key>{}</Key></Deleted>", x))
+            .collect();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult>{}</DeleteResult>"#,
+                deleted
+            ),
+        )
+    }
+
+    #[test]
+    fn test_trash_target() -> Result<(), Error> {
+        let cmd = Trash {
+            trash_prefix: ".trash/{date}/".parse()?,
+            if_unmodified: false,
+        };
+
+        assert_eq!(
+            cmd.target("logs/a.log", "2024-05-01"),
+            ".trash/2024-05-01/logs/a.log"
+        );
+        assert_eq!(
+            cmd.trash_prefix.strip(".trash/2024-05-01/logs/a.log"),
+            Some("logs/a.log")
+        );
+        assert_eq!(cmd.trash_prefix.strip(".trash/latest/logs/a.log"), None);
+        assert_eq!(cmd.trash_prefix.strip(".trash/2024-05-01/"), None);
+        assert_eq!(cmd.trash_prefix.strip("logs/a.log"), None);
+
+        let custom: TrashPrefix = "recycle/{date}/bin/".parse()?;
+        assert_eq!(custom.render("2024-05-01"), "recycle/2024-05-01/bin/");
+        assert_eq!(custom.strip("recycle/2024-05-01/bin/a"), Some("a"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_trash_recursion() -> Result<(), Error> {
+        let trash_prefix: TrashPrefix = ".trash/{date}/".parse()?;
+
+        for path in ["s3://test/.trash/", "s3://test/.trash/2024-05-01/logs"] {
+            let path: S3Path = path.parse()?;
+            assert!(check_trash_recursion(&trash_prefix, &path).is_err());
+        }
+        for path in ["s3://test/", "s3://test/logs/", "s3://test/.tr"] {
+            let path: S3Path = path.parse()?;
+            assert!(check_trash_recursion(&trash_prefix, &path).is_ok());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_trash() -> Result<(), Error> {
+        let (client, http_client) =
+            replay_client(vec![copy_result(), delete_result(&["logs/a.log"])]);
+        let cmd = Trash {
+            trash_prefix: ".trash/{date}/".parse()?,
+            if_unmodified: false,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("logs/a.log").size(10).build(),
+            Object::builder().key(".trash/2024-05-01/b.log").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.reclaimed_bytes, 10);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].path(),
+            format!(
+                "https://test.s3.us-east-1.amazonaws.com/.trash/{}/logs/a.log",
+                Utc::now().format("%Y-%m-%d")
+            )
+        );
+        assert_eq!(
+            requests[0].header("x-amz-copy-source"),
+            Some("test/logs/a.log")
+        );
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(stats.mutations[0].keys, vec!["logs/a.log"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_trash_keeps_failed_copies() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            copy_result(),
+            ReplayResponse::new(403, ""),
+            copy_result(),
+            delete_result(&["a", "c"]),
+        ]);
+        let cmd = Trash {
+            trash_prefix: ".trash/{date}/".parse()?,
+            if_unmodified: false,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a").size(1).build(),
+            Object::builder().key("b").size(2).build(),
+            Object::builder().key("c").size(4).build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.reclaimed_bytes, 5);
+        assert_eq!(stats.errors.len(), 1);
+        assert!(stats.errors[0].starts_with("s3://test/b: "));
+        // the copied sources are deleted, the failed one is kept
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        let deleted = String::from_utf8(requests[3].body.clone())?;
+        assert!(deleted.contains("<Key>a</Key>") && deleted.contains("<Key>c</Key>"));
+        assert!(!deleted.contains("<Key>b</Key>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_untrash() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            copy_result(),
+            delete_result(&[".trash/2024-05-01/logs/a.log"]),
+        ]);
+        let cmd = Untrash {
+            trash_prefix: ".trash/{date}/".parse()?,
+        };
+        let path: S3Path = "s3://test/.trash/".parse()?;
+        let list = vec![
+            Object::builder()
+                .key(".trash/2024-05-01/logs/a.log")
+                .build(),
+            Object::builder().key(".trash/notes.txt").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.skipped, 1);
+        let requests = http_client.requests();
+        assert_eq!(
+            requests[0].path(),
+            "https://test.s3.us-east-1.amazonaws.com/logs/a.log"
+        );
+        assert_eq!(
+            requests[0].header("x-amz-copy-source"),
+            Some("test/.trash/2024-05-01/logs/a.log")
+        );
+        assert_eq!(
+            stats.mutations[0].keys,
+            vec![".trash/2024-05-01/logs/a.log"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_changes() {
+        let tags = |tags: &[(&str, &str)]| -> Vec<Tag> {
+            tags.iter()
+                .map(|(key, value)| Tag::builder().key(*key).value(*value).build().unwrap())
+                .collect()
+        };
+
+        assert!(tag_changes(
+            &tags(&[("a", "1"), ("b", "2")]),
+            &tags(&[("b", "2"), ("a", "1")])
+        )
+        .is_empty());
+        let changes = tag_changes(
+            &tags(&[("env", "prod"), ("team", "x")]),
+            &tags(&[("env", "dev")]),
+        );
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "env".to_owned(),
+                    Some("prod".to_owned()),
+                    Some("dev".to_owned())
+                ),
+                ("team".to_owned(), Some("x".to_owned()), None),
+            ]
+        );
+        assert_eq!(
+            TagDifference::Tags(changes).text("b.parquet"),
+            "tags differ: b.parquet env: prod => dev, team: x => (none)"
+        );
+        assert_eq!(
+            TagDifference::MissingTarget.json("c.parquet"),
+            r#"{"key":"c.parquet","kind":"missing-target"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diff_tags() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            tagging(&[("env", "prod")]),
+            tagging(&[("env", "prod"), ("team", "x")]),
+            tagging(&[]),
+            tagging(&[("env", "prod")]),
+            tagging(&[("env", "dev")]),
+            ReplayResponse::new(
+                404,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message></Error>"#,
+            ),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>replica</Name><KeyCount>3</KeyCount>
+<Contents><Key>data/a.parquet</Key></Contents>
+<Contents><Key>data/b.parquet</Key></Contents>
+<Contents><Key>data/d.parquet</Key></Contents>
+</ListBucketResult>"#,
+            ),
+        ]);
+        let cmd = DiffTags {
+            target: "s3://replica/data/".parse()?,
+            format: OutputFormat::Text,
+            tally: Default::default(),
+        };
+        let path: S3Path = "s3://primary/data/".parse()?;
+        let list = vec![
+            Object::builder().key("data/a.parquet").build(),
+            Object::builder().key("data/b.parquet").build(),
+            Object::builder().key("data/c.parquet").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+        let finished = cmd.finish(&client, &path).await?;
+
+        assert_eq!(stats.processed, 3);
+        assert!(stats.errors.is_empty());
+        assert_eq!(finished.processed, 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 7);
+        assert_eq!(
+            requests[0].path(),
+            "https://primary.s3.us-east-1.amazonaws.com/data/a.parquet"
+        );
+        assert_eq!(
+            requests[4].path(),
+            "https://replica.s3.us-east-1.amazonaws.com/data/b.parquet"
+        );
+
+        let tally = cmd.tally.0.lock().unwrap().clone();
+        assert_eq!(tally.equal, 1);
+        assert_eq!(tally.differing, 1);
+        assert_eq!(tally.missing_target, 1);
+        assert_eq!(tally.missing_source, 1);
+        assert!(cmd.failed());
+        Ok(())
+    }
+
+    fn acl_response(grants: &[(&str, &str)]) -> ReplayResponse {
+        let grants: String = grants
+            .iter()
+            .map(|(grantee, permission)| {
+                let grantee = if grantee.starts_with("http") {
+                    format!(
+                        r#"<Grantee xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="Group"><URI>{}</URI></Grantee>"#,
+                        grantee
+                    )
+                } else {
+                    format!(
+                        r#"<Grantee xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="CanonicalUser"><ID>{}</ID></Grantee>"#,
+                        grantee
+                    )
+                };
+                format!(
+                    "<Grant>{}<Permission>{}</Permission></Grant>",
+                    grantee, permission
+                )
+            })
+            .collect();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<AccessControlPolicy><Owner><ID>owner</ID></Owner><AccessControlList>{}</AccessControlList></AccessControlPolicy>"#,
+                grants
+            ),
+        )
+    }
+
+    fn acl_report(only_flagged: bool) -> AclReport {
+        AclReport {
+            workers: Workers::Fixed(2),
+            only_flagged,
+            format: OutputFormat::Text,
+            tally: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_acl_findings() {
+        let grant = |grantee: Grantee, permission: &str| {
+            Grant::builder()
+                .grantee(grantee)
+                .permission(permission.into())
+                .build()
+        };
+        let group = |uri: &str| {
+            Grantee::builder()
+                .r#type(Type::Group)
+                .uri(uri)
+                .build()
+                .unwrap()
+        };
+        let user = |id: &str| {
+            Grantee::builder()
+                .r#type(Type::CanonicalUser)
+                .id(id)
+                .build()
+                .unwrap()
+        };
+
+        let owner_only = [grant(user("owner"), "FULL_CONTROL")];
+        assert!(acl_findings(Some("owner"), &owner_only).is_empty());
+        assert_eq!(
+            acl_categories(&acl_findings(Some("owner"), &owner_only)),
+            vec![AclCategory::PrivateOnly]
+        );
+
+        let grants = [
+            grant(user("owner"), "FULL_CONTROL"),
+            grant(group(LOG_DELIVERY_URI), "WRITE"),
+            grant(group(AUTHENTICATED_USERS_URI), "READ"),
+            grant(user("partner"), "READ"),
+            grant(group(ALL_USERS_URI), "WRITE_ACP"),
+            grant(group(ALL_USERS_URI), "READ_ACP"),
+        ];
+        let findings = acl_findings(Some("owner"), &grants);
+        assert_eq!(
+            findings
+                .iter()
+                .map(|x| (x.category, x.grantee.as_str(), x.permission.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (AclCategory::LogDelivery, "LogDelivery", "WRITE"),
+                (AclCategory::PublicRead, "AuthenticatedUsers", "READ"),
+                (AclCategory::CrossAccount, "partner", "READ"),
+                (AclCategory::PublicWrite, "AllUsers", "WRITE_ACP"),
+                (AclCategory::PublicRead, "AllUsers", "READ_ACP"),
+            ]
+        );
+        assert_eq!(
+            acl_categories(&findings),
+            vec![
+                AclCategory::PublicRead,
+                AclCategory::PublicWrite,
+                AclCategory::CrossAccount,
+                AclCategory::LogDelivery,
+            ]
+        );
+
+        // without an owner every canonical user is another account
+        assert_eq!(
+            acl_findings(None, &owner_only)[0].category,
+            AclCategory::CrossAccount
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acl_report() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            acl_response(&[("owner", "FULL_CONTROL")]),
+            acl_response(&[("owner", "FULL_CONTROL"), (ALL_USERS_URI, "READ")]),
+            acl_response(&[("partner", "FULL_CONTROL")]),
+            ReplayResponse::new(404, ""),
+        ]);
+        let cmd = acl_report(true);
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("private").build(),
+            Object::builder().key("public").build(),
+            Object::builder().key("shared").build(),
+            Object::builder().key("deleted").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests.iter().all(|x| x.uri.contains("acl")));
+        assert_eq!(
+            requests[1].path(),
+            "https://test.s3.us-east-1.amazonaws.com/public"
+        );
+        assert_eq!(stats.processed, 3);
+        assert_eq!(stats.skipped, 1);
+
+        let tally = cmd.tally.0.lock().unwrap().clone();
+        assert_eq!(
+            tally.keys,
+            vec![
+                (AclCategory::PrivateOnly, 1),
+                (AclCategory::PublicRead, 1),
+                (AclCategory::CrossAccount, 1),
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert!(!tally.acls_disabled);
+        assert!(cmd
+            .summary()
+            .unwrap()
+            .contains("public-write:               0 keys"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_acl_report_disabled() -> Result<(), Error> {
+        let disabled = ReplayResponse::new(
+            400,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessControlListNotSupported</Code><Message>The bucket does not allow ACLs</Message></Error>"#,
+        );
+        let (client, http_client) = replay_client(vec![disabled.clone(), disabled]);
+        let mut cmd = acl_report(false);
+        cmd.format = OutputFormat::Json;
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a").build(),
+            Object::builder().key("b").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!(stats.skipped, 2);
+        assert!(stats.errors.is_empty());
+        assert!(cmd.tally.0.lock().unwrap().acls_disabled);
+
+        // the next batches send no request
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!(stats.skipped, 2);
+        assert_eq!(http_client.requests().len(), 2);
+
+        let summary: serde_json::Value = serde_json::from_str(&cmd.summary().unwrap())?;
+        assert_eq!(summary["acls_disabled"], json!(true));
+        assert_eq!(summary["private-only"], json!(0));
+        Ok(())
+    }
+
+    fn verify(missing: bool) -> Verify {
+        Verify {
+            workers: Workers::Fixed(2),
+            missing,
+            verify_size_with_head: false,
+            format: OutputFormat::Text,
+            tally: Default::default(),
+        }
+    }
+
+    fn listed(key: &str, size: i64, e_tag: &str) -> Object {
+        Object::builder().key(key).size(size).e_tag(e_tag).build()
+    }
+
+    #[tokio::test]
+    async fn test_verify() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            head(10, "\"a\""),
+            head(12, "\"b\""),
+            ReplayResponse::new(404, ""),
+        ]);
+        let cmd = verify(false);
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            listed("same", 10, "\"a\""),
+            listed("truncated", 10, "\"b\""),
+            listed("deleted", 10, "\"c\""),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|x| x.method == "HEAD"));
+        assert_eq!(
+            requests[1].path(),
+            "https://test.s3.us-east-1.amazonaws.com/truncated"
+        );
+        assert_eq!(
+            *cmd.tally.0.lock().unwrap(),
+            VerifyCounts {
+                verified: 1,
+                mismatched: 1,
+                missing: 1,
+                corrected: 0,
+            }
+        );
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.skipped, 1);
+        assert!(cmd.failed());
+        assert!(cmd
+            .summary()
+            .unwrap()
+            .contains("mismatched:                 1 keys"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_missing() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![listed("deleted", 10, "\"c\"")];
+
+        for missing in [false, true] {
+            let (client, _) = replay_client(vec![ReplayResponse::new(404, "")]);
+            let cmd = verify(missing);
+
+            let stats = cmd.execute(&client, &path, &list).await?;
+
+            assert_eq!(cmd.tally.0.lock().unwrap().missing, 1);
+            assert_eq!(stats.skipped, if missing { 0 } else { 1 });
+            assert_eq!(cmd.failed(), missing);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_auto_workers() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![
+            head(10, "\"a\""),
+            ReplayResponse::new(503, ""),
+            head(10, "\"a\""),
+        ]);
+        let cmd = Verify {
+            workers: "auto".parse()?,
+            ..verify(false)
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![listed("a", 10, "\"a\"")];
+
+        // grows after the first batch, backs off after the throttled one
+        let mut workers = Vec::new();
+        for _ in 0..3 {
+            cmd.execute(&client, &path, &list).await?;
+            workers.push(cmd.workers.current());
+        }
+        assert_eq!(workers, vec![12, 6, 10]);
+        assert_eq!(cmd.workers.settled(), Some((10, 1)));
+        assert!(cmd
+            .summary()
+            .unwrap()
+            .contains("workers settled at:         10 (1 throttled requests)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_discrepancies() {
+        let object = Object::builder().key("a").size(10).e_tag("\"a\"").build();
+        let head = |size: i64, e_tag: &str| {
+            HeadObjectOutput::builder()
+                .content_length(size)
+                .e_tag(e_tag)
+                .build()
+        };
+
+        assert!(discrepancies(&object, Some(&head(10, "\"a\""))).is_empty());
+        assert_eq!(
+            discrepancies(&object, Some(&head(12, "\"b\""))),
+            vec![
+                Discrepancy::Size(10, 12),
+                Discrepancy::ETag("\"a\"".to_owned(), "\"b\"".to_owned())
+            ]
+        );
+        assert_eq!(discrepancies(&object, None), vec![Discrepancy::Missing]);
+
+        assert_eq!(
+            Discrepancy::Size(10, 12).text("test", "a"),
+            "size mismatch: s3://test/a listed 10, head 12"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&Discrepancy::Size(10, 12).json("test", "a")).unwrap();
+        assert_eq!(
+            json,
+            json!({"bucket": "test", "key": "a", "kind": "size", "listed": 10, "head": 12})
+        );
+    }
+
+    fn tag_route(routes: &[&str], default: Option<&str>, move_keys: bool) -> TagRoute {
+        TagRoute {
+            routes: routes.iter().map(|x| x.parse().unwrap()).collect(),
+            default: default.map(|x| x.parse().unwrap()),
+            move_keys,
+            no_preflight: true,
+        }
+    }
+
+    fn tagging(tags: &[(&str, &str)]) -> ReplayResponse {
+        let tags: String = tags
+            .iter()
+            .map(|(key, value)| format!("<Tag><Key>{}</Key><Value>{}</Value></Tag>", key, value))
+            .collect();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Tagging><TagSet>{}</TagSet></Tagging>"#,
+                tags
+            ),
+        )
+    }
+
+    fn copy_result() -> ReplayResponse {
+        ReplayResponse::new(
+            200,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<CopyObjectResult><ETag>"etag"</ETag></CopyObjectResult>"#,
+        )
+    }
+
+    #[test]
+    fn test_route_destination() -> Result<(), Error> {
+        let tag = |key: &str, value: &str| Tag::builder().key(key).value(value).build();
+        let cmd = tag_route(
+            &[
+                "dataset=alpha=>s3://lake/alpha/",
+                "team=data=>s3://lake/data/",
+                "dataset=beta=>s3://lake/beta/",
+            ],
+            None,
+            false,
+        );
+
+        assert_eq!(
+            cmd.destination(&[tag("dataset", "beta")?]),
+            Some(&"s3://lake/beta/".parse()?)
+        );
+        assert_eq!(
+            cmd.destination(&[tag("dataset", "beta")?, tag("team", "data")?]),
+            Some(&"s3://lake/data/".parse()?)
+        );
+        assert_eq!(cmd.destination(&[tag("dataset", "gamma")?]), None);
+        assert_eq!(cmd.destination(&[]), None);
+
+        let cmd = tag_route(
+            &["dataset=alpha=>s3://lake/alpha/"],
+            Some("s3://lake/other/"),
+            false,
+        );
+        assert_eq!(
+            cmd.destination(&[tag("dataset", "gamma")?]),
+            Some(&"s3://lake/other/".parse()?)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_route_copy() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            tagging(&[("dataset", "alpha")]),
+            tagging(&[("owner", "x"), ("dataset", "beta")]),
+            tagging(&[]),
+            copy_result(),
+            copy_result(),
+        ]);
+        let cmd = tag_route(
+            &[
+                "dataset=alpha=>s3://lake/alpha/",
+                "dataset=beta=>s3://lake/beta/",
+            ],
+            None,
+            false,
+        );
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a.parquet").build(),
+            Object::builder().key("b.parquet").build(),
+            Object::builder().key("c.parquet").build(),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.skipped, 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[..3].iter().all(|x| x.uri.contains("tagging")));
+        assert_eq!(requests[3].method, "PUT");
+        assert_eq!(
+            requests[3].path(),
+            "https://lake.s3.us-east-1.amazonaws.com/alpha/a.parquet"
+        );
+        assert_eq!(
+            requests[3].header("x-amz-copy-source"),
+            Some("test/a.parquet")
+        );
+        assert_eq!(
+            requests[4].path(),
+            "https://lake.s3.us-east-1.amazonaws.com/beta/b.parquet"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_route_move() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            tagging(&[("dataset", "gamma")]),
+            copy_result(),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>a</Key></Deleted></DeleteResult>"#,
+            ),
+        ]);
+        let cmd = tag_route(
+            &["dataset=alpha=>s3://lake/alpha/"],
+            Some("s3://lake/other/"),
+            true,
+        );
+        let path: S3Path = "s3://test/".parse()?;
 
-            let target = combine_keys(self.flat, &key, &self.destination.prefix);
-            let source_path = format!("{0}/{1}", &path.bucket, key);
+        let stats = cmd
+            .execute(
+                &client,
+                &path,
+                &[Object::builder().key("a").size(10).build()],
+            )
+            .await?;
 
-            println!(
-                "moving: s3://{0} => s3://{1}/{2}",
-                source_path, &self.destination.bucket, target,
-            );
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.reclaimed_bytes, 10);
+        let requests = http_client.requests();
+        assert_eq!(
+            requests[1].path(),
+            "https://lake.s3.us-east-1.amazonaws.com/other/a"
+        );
+        assert_eq!(requests[2].method, "POST");
+        assert!(String::from_utf8(requests[2].body.clone())?.contains("<Key>a</Key>"));
+        Ok(())
+    }
 
-            client
-                .copy_object()
-                .bucket(&path.bucket)
-                .key(target)
-                .copy_source(source_path)
-                .send()
-                .await?;
-        }
+    #[test]
+    fn test_is_self_copy() {
+        assert!(is_self_copy("test", "a/b", "test", "a/b"));
+        assert!(!is_self_copy("test", "a/b", "other", "a/b"));
+        assert!(!is_self_copy("test", "a/b", "test", "c/a/b"));
+        assert!(!is_self_copy("test", "a/b", "test", "b"));
+    }
 
-        let key_list: Vec<_> = list
-            .iter()
-            .filter_map(|x| {
-                ObjectIdentifier::builder()
-                    .set_key(x.key.clone())
-                    .build()
-                    .ok()
-            })
-            .collect();
+    #[tokio::test]
+    async fn test_move_skips_self_copy() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            copy_result(),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>dir/b</Key></Deleted></DeleteResult>"#,
+            ),
+        ]);
+        let cmd = S3Move {
+            destination: "s3://test/".parse()?,
+            flat: true,
+            no_preflight: true,
+            if_unmodified: false,
+            no_auto_exclude: false,
+            quiet_versioning_warning: true,
+            collision_strategy: CollisionStrategy::Error,
+            restore_and_retry: false,
+            restore_days: 1,
+            wait: false,
+            workers: Workers::Fixed(1),
+            schedule: Schedule::Listing,
+            targets: Default::default(),
+            two_phase: false,
+            rollback_on_failure: false,
+            phases: Default::default(),
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a").build(),
+            Object::builder().key("dir/b").build(),
+        ];
 
-        let delete = Delete::builder().set_objects(Some(key_list)).build().ok();
+        let stats = cmd.execute(&client, &path, &list).await?;
 
-        client
-            .delete_objects()
-            .bucket(path.bucket.clone())
-            .set_delete(delete)
-            .send()
-            .await?;
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.processed, 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].path(),
+            "https://test.s3.us-east-1.amazonaws.com/b"
+        );
+        let body = String::from_utf8(requests[1].body.clone())?;
+        assert!(body.contains("<Key>dir/b</Key>"));
+        assert!(!body.contains("<Key>a</Key>"));
         Ok(())
     }
-}
 
-#[async_trait]
-impl RunCommand for DoNothing {
-    async fn execute(&self, _c: &Client, _p: &S3Path, _l: &[Object]) -> Result<(), Error> {
-        Ok(())
+    fn archived_object(key: &str, class: ObjectStorageClass) -> Object {
+        Object::builder().key(key).storage_class(class).build()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use aws_config::BehaviorVersion;
-    use aws_sdk_s3::{primitives::DateTime, types::ObjectStorageClass};
-    use aws_smithy_types::date_time::Format;
-    use aws_types::region::Region;
+    fn restore_head(restore: Option<&str>) -> ReplayResponse {
+        ReplayResponse {
+            headers: restore
+                .map(|x| ("x-amz-restore".to_owned(), x.to_owned()))
+                .into_iter()
+                .collect(),
+            ..ReplayResponse::new(200, "")
+        }
+    }
 
-    // use std::fs::File;
-    // use std::io::prelude::*;
-    // use tempfile::Builder;
+    fn restoring_move(request: bool, wait: bool) -> S3Move {
+        S3Move {
+            destination: "s3://dest/".parse().unwrap(),
+            flat: false,
+            no_preflight: true,
+            if_unmodified: false,
+            no_auto_exclude: false,
+            quiet_versioning_warning: true,
+            collision_strategy: CollisionStrategy::Error,
+            restore_and_retry: request,
+            restore_days: 3,
+            wait,
+            workers: Workers::Fixed(1),
+            schedule: Schedule::Listing,
+            targets: Default::default(),
+            two_phase: false,
+            rollback_on_failure: false,
+            phases: Default::default(),
+        }
+    }
 
-    #[test]
-    fn test_advanced_print_object() -> Result<(), Error> {
-        let mut buf = Vec::new();
-        let cmd = AdvancedPrint {};
-        let bucket = "test";
+    fn two_phase_move(rollback_on_failure: bool) -> S3Move {
+        S3Move {
+            two_phase: true,
+            rollback_on_failure,
+            ..restoring_move(false, false)
+        }
+    }
 
-        let object = Object::builder()
-            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
-            .key("somepath/otherpath")
-            .size(4_997_288)
-            .storage_class(ObjectStorageClass::Standard)
-            .last_modified(DateTime::from_str(
-                "2017-07-19T19:04:17.000Z",
-                Format::DateTime,
-            )?)
-            .build();
+    fn sized_head(size: i64) -> ReplayResponse {
+        ReplayResponse {
+            headers: vec![("content-length".to_owned(), size.to_string())],
+            ..ReplayResponse::new(200, "")
+        }
+    }
 
-        cmd.print_object(&mut buf, bucket, &object)?;
-        let out = std::str::from_utf8(&buf)?;
+    #[test]
+    fn test_move_marker() {
+        let keys = vec![
+            MovedKey {
+                key: "data/a".to_owned(),
+                target: "backup/data/a".to_owned(),
+                size: 10,
+            },
+            MovedKey {
+                key: "data/b".to_owned(),
+                target: "backup/data/b".to_owned(),
+                size: 0,
+            },
+        ];
+        let marker = move_marker("s3://test/data/", &keys);
 
-        println!("{}", out);
-        assert!(out.contains("9d48114aa7c18f9d68aa20086dbb7756"));
-        assert!(out.contains("None"));
-        assert!(out.contains("4997288"));
-        assert!(out.contains("2017-07-19T19:04:17Z"));
-        assert!(out.contains("s3://test/somepath/otherpath"));
-        assert!(out.contains("Standard"));
-        Ok(())
+        assert_eq!(
+            parse_move_marker(marker.as_bytes(), "s3://test/data/"),
+            Ok(keys)
+        );
+        assert_eq!(
+            parse_move_marker(marker.as_bytes(), "s3://test/other/"),
+            Err("it was written by a move of s3://test/data/".to_owned())
+        );
+        assert!(parse_move_marker(b"{}", "s3://test/").is_err());
     }
 
-    #[test]
-    fn test_fast_print_object() -> Result<(), Error> {
-        let mut buf = Vec::new();
-        let cmd = FastPrint {};
-        let bucket = "test";
+    #[tokio::test]
+    async fn test_two_phase_move_resumes_deletes() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+        let list = sized_objects(&[10, 20]);
 
-        let object = Object::builder()
-            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
-            .key("somepath/otherpath")
-            .size(4_997_288)
-            .storage_class(ObjectStorageClass::Standard)
-            .last_modified(DateTime::from_str(
-                "2017-07-19T19:04:17.000Z",
-                Format::DateTime,
-            )?)
-            .build();
+        // the first run copies both keys and stops at the delete
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(404, ""),
+            copy_result(),
+            copy_result(),
+            sized_head(10),
+            sized_head(20),
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(500, ""),
+        ]);
+        let cmd = two_phase_move(false);
+        cmd.preflight(&client, &path).await?;
+        // the keys are moved once their sources are deleted
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!(stats.processed, 0);
+        assert!(stats.errors.is_empty());
+        assert!(cmd.finish(&client, &path).await.is_err());
 
-        cmd.print_object(&mut buf, bucket, &object)?;
-        let out = std::str::from_utf8(&buf)?;
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 7);
+        assert_eq!(requests[5].method, "PUT");
+        assert!(requests[5].uri.contains(MOVE_MARKER));
+        let marker = requests[5].body.clone();
 
-        assert!(out.contains("s3://test/somepath/otherpath"));
+        // the rerun deletes the sources from the marker without copying
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, std::str::from_utf8(&marker)?),
+            ReplayResponse::new(200, ""),
+            delete_result(&["k0", "k1"]),
+            ReplayResponse::new(204, ""),
+        ]);
+        let cmd = two_phase_move(false);
+        cmd.preflight(&client, &path).await?;
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!(stats.processed, 0);
+        assert_eq!(http_client.requests().len(), 1);
+
+        let stats = cmd.finish(&client, &path).await?;
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.reclaimed_bytes, 30);
+        let requests = http_client.requests();
+        let methods: Vec<_> = requests.iter().map(|x| x.method.as_str()).collect();
+        assert_eq!(methods, vec!["GET", "PUT", "POST", "DELETE"]);
+        assert!(requests.iter().all(|x| !x.uri.contains("x-id=CopyObject")));
+        let deleted = String::from_utf8(requests[2].body.clone())?;
+        assert!(deleted.contains("<Key>k0</Key>") && deleted.contains("<Key>k1</Key>"));
         Ok(())
     }
 
-    #[test]
-    fn test_exec() -> Result<(), Error> {
-        let mut buf = Vec::new();
-        let cmd = Exec {
-            utility: "echo test {}".to_owned(),
-        };
+    #[tokio::test]
+    async fn test_two_phase_move_keeps_undeleted_keys() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(404, ""),
+            copy_result(),
+            copy_result(),
+            sized_head(10),
+            sized_head(20),
+            ReplayResponse::new(200, ""),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>k0</Key></Deleted><Error><Key>k1</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error></DeleteResult>"#,
+            ),
+            ReplayResponse::new(200, ""),
+        ]);
+        let cmd = two_phase_move(false);
+        cmd.preflight(&client, &path).await?;
+        cmd.execute(&client, &path, &sized_objects(&[10, 20]))
+            .await?;
 
-        let path = "s3://test/somepath/otherpath";
-        cmd.exec(&mut buf, path)?;
-        let out = std::str::from_utf8(&buf)?;
+        let stats = cmd.finish(&client, &path).await?;
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.reclaimed_bytes, 10);
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(stats.mutations[0].keys, vec!["k0"]);
+        assert!(cmd.failed());
 
-        assert!(out.contains("test"));
-        assert!(out.contains("s3://test/somepath/otherpath"));
+        // the marker is rewritten with the undeleted key instead of removed
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 8);
+        assert_eq!(requests[7].method, "PUT");
+        let kept =
+            parse_move_marker(&requests[7].body, "s3://test/").map_err(|e| anyhow::anyhow!(e))?;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].key, "k1");
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_advanced_print() -> Result<(), Error> {
-        let object = Object::builder()
-            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
-            .key("somepath/otherpath")
-            .size(4_997_288)
-            .storage_class(ObjectStorageClass::Standard)
-            .last_modified(DateTime::from_str(
-                "2017-07-19T19:04:17.000Z",
-                Format::DateTime,
-            )?)
-            .build();
+    async fn test_two_phase_move_rollback() -> Result<(), Error> {
+        let path: S3Path = "s3://test/".parse()?;
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(404, ""),
+            copy_result(),
+            sized_head(10),
+            ReplayResponse::new(500, ""),
+            ReplayResponse::new(200, "<DeleteResult></DeleteResult>"),
+        ]);
+        let cmd = two_phase_move(true);
+        cmd.preflight(&client, &path).await?;
+        let stats = cmd.execute(&client, &path, &sized_objects(&[10])).await?;
+        assert!(stats.errors.is_empty());
+        let stats = cmd
+            .execute(&client, &path, &sized_objects(&[10, 20])[1..])
+            .await?;
+        assert_eq!(stats.errors.len(), 1);
+        assert!(cmd.failed());
 
-        let cmd = Cmd::Print(AdvancedPrint {}).downcast();
-        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
-        let client = Client::new(&config);
+        let stats = cmd.finish(&client, &path).await?;
+        assert_eq!(stats.reclaimed_bytes, 0);
+        assert!(cmd.failed());
 
-        let path = S3Path {
-            bucket: "test".to_owned(),
-            prefix: None,
-            region: Region::from_static("us-east-1"),
-        };
+        // the copied key is removed from the destination, no source is deleted
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[4].uri.starts_with("https://dest."));
+        let deleted = String::from_utf8(requests[4].body.clone())?;
+        assert!(deleted.contains("<Key>k0</Key>"));
+        Ok(())
+    }
 
-        cmd.execute(&client, &path, &[object]).await?;
+    #[tokio::test]
+    async fn test_move_defers_archived_keys() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            restore_head(None),
+            restore_head(Some(
+                r#"ongoing-request="false", expiry-date="Fri, 23 Dec 2026 00:00:00 GMT""#,
+            )),
+            restore_head(None),
+            copy_result(),
+            copy_result(),
+            copy_result(),
+            delete_result(&["a", "c", "d"]),
+        ]);
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            archived_object("a", ObjectStorageClass::Standard),
+            archived_object("b", ObjectStorageClass::Glacier),
+            archived_object("c", ObjectStorageClass::DeepArchive),
+            archived_object("d", ObjectStorageClass::GlacierIr),
+            archived_object("e", ObjectStorageClass::DeepArchive),
+        ];
+
+        let stats = restoring_move(false, false)
+            .execute(&client, &path, &list)
+            .await?;
+
+        assert_eq!(stats.processed, 3);
+        assert_eq!(
+            stats.errors,
+            vec!["Archived keys require restore first, run with --restore-and-retry: b, e"]
+        );
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 7);
+        assert!(requests[..3].iter().all(|x| x.method == "HEAD"));
+        let copied: Vec<_> = requests[3..6]
+            .iter()
+            .map(|x| x.header("x-amz-copy-source").unwrap_or_default())
+            .collect();
+        assert_eq!(copied, vec!["test/a", "test/c", "test/d"]);
+        let body = String::from_utf8(requests[6].body.clone())?;
+        assert!(body.contains("<Key>a</Key>"));
+        assert!(body.contains("<Key>c</Key>"));
+        assert!(!body.contains("<Key>b</Key>"));
+        assert!(!body.contains("<Key>e</Key>"));
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_fastprint() -> Result<(), Error> {
-        let object = Object::builder()
-            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
-            .key("somepath/otherpath")
-            .size(4_997_288)
-            .storage_class(ObjectStorageClass::Standard)
-            .last_modified(DateTime::from_str(
-                "2017-07-19T19:04:17.000Z",
-                Format::DateTime,
-            )?)
-            .build();
+    async fn test_move_requests_restore() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            restore_head(None),
+            ReplayResponse::new(202, ""),
+            restore_head(Some(r#"ongoing-request="true""#)),
+        ]);
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            archived_object("b", ObjectStorageClass::Glacier),
+            archived_object("c", ObjectStorageClass::Glacier),
+        ];
 
-        let cmd = Cmd::Ls(FastPrint {}).downcast();
-        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
-        let client = Client::new(&config);
+        let stats = restoring_move(true, false)
+            .execute(&client, &path, &list)
+            .await?;
 
-        let path = S3Path {
-            bucket: "test".to_owned(),
-            prefix: None,
-            region: Region::from_static("us-east-1"),
+        assert_eq!(stats.processed, 0);
+        assert_eq!(stats.skipped, 2);
+        assert!(stats.errors.is_empty());
+        assert!(stats.mutations.is_empty());
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].method, "POST");
+        assert!(requests[1].uri.ends_with("/b?restore"));
+        assert!(String::from_utf8(requests[1].body.clone())?.contains("<Days>3</Days>"));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_copy_waits_for_restore() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            restore_head(None),
+            ReplayResponse::new(202, ""),
+            restore_head(Some(r#"ongoing-request="true""#)),
+            restore_head(Some(r#"ongoing-request="true""#)),
+            restore_head(Some(r#"ongoing-request="false""#)),
+            restore_head(Some(r#"ongoing-request="false""#)),
+            copy_result(),
+            copy_result(),
+        ]);
+        let cmd = S3Copy {
+            restore_and_retry: true,
+            restore_days: 3,
+            wait: true,
+            ..flat_copy(CollisionStrategy::Error)
         };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            archived_object("g", ObjectStorageClass::Glacier),
+            archived_object("h", ObjectStorageClass::DeepArchive),
+        ];
 
-        cmd.execute(&client, &path, &[object]).await?;
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.skipped, 0);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 8);
+        assert_eq!(requests[6].header("x-amz-copy-source"), Some("test/g"));
+        assert_eq!(requests[7].header("x-amz-copy-source"), Some("test/h"));
+
+        let parse = |args: &[&str]| {
+            let mut argv = vec!["s3find", "s3://test/", "move", "s3://dest/"];
+            argv.extend(args);
+            FindOpt::from_iter_safe(&argv)
+        };
+        assert!(parse(&["--wait"]).is_err());
+        assert!(parse(&["--restore-and-retry", "--restore-days", "7", "--wait"]).is_ok());
         Ok(())
     }
 
     #[tokio::test]
-    async fn smoke_donothing() -> Result<(), Error> {
-        let object = Object::builder()
-            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
-            .key("somepath/otherpath")
-            .size(4_997_288)
-            .storage_class(ObjectStorageClass::Standard)
-            .last_modified(DateTime::from_str(
-                "2017-07-19T19:04:17.000Z",
-                Format::DateTime,
-            )?)
-            .build();
+    async fn test_download_range() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let body = "0123456789".repeat(10);
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(206, &body[..16]),
+            ReplayResponse::new(200, &body),
+            ReplayResponse::new(200, "small"),
+        ]);
+        let cmd = Download {
+            force: false,
+            destination: dir.path().display().to_string(),
+            range_bytes: Some(16),
+            range: None,
+            verify_size_with_head: false,
+            version_id: None,
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = vec![
+            Object::builder().key("a.parquet").size(100).build(),
+            Object::builder().key("b.parquet").size(100).build(),
+            Object::builder().key("c.parquet").size(5).build(),
+        ];
 
-        let cmd = Cmd::Nothing(DoNothing {}).downcast();
-        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
-        let client = Client::new(&config);
+        let stats = cmd.execute(&client, &path, &list).await?;
 
-        let path = S3Path {
-            bucket: "test".to_owned(),
-            prefix: None,
-            region: Region::from_static("us-east-1"),
-        };
+        assert_eq!(stats.processed, 3);
+        assert_eq!(stats.downloaded_bytes, 37);
+        let requests = http_client.requests();
+        assert_eq!(requests[0].header("range"), Some("bytes=0-15"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.parquet.partial"))?,
+            "0123456789012345"
+        );
+        // the body is cut even if the range is ignored
+        assert_eq!(fs::read(dir.path().join("b.parquet.partial"))?.len(), 16);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("c.parquet.partial"))?,
+            "small"
+        );
+        assert!(!dir.path().join("a.parquet").exists());
+        Ok(())
+    }
 
-        cmd.execute(&client, &path, &[object]).await
+    fn sized_objects(sizes: &[i64]) -> Vec<Object> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(index, size)| {
+                Object::builder()
+                    .key(format!("k{}", index))
+                    .size(*size)
+                    .build()
+            })
+            .collect()
     }
 
     #[tokio::test]
-    async fn smoke_exec() -> Result<(), Error> {
-        let object = Object::builder()
-            .e_tag("9d48114aa7c18f9d68aa20086dbb7756")
-            .key("somepath/otherpath")
-            .size(4_997_288)
-            .storage_class(ObjectStorageClass::Standard)
-            .last_modified(DateTime::from_str(
-                "2017-07-19T19:04:17.000Z",
-                Format::DateTime,
-            )?)
-            .build();
+    async fn test_move_reports_undeleted_sources() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![
+            copy_result(),
+            copy_result(),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult><Deleted><Key>k0</Key></Deleted><Error><Key>k1</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error></DeleteResult>"#,
+            ),
+        ]);
+        let path: S3Path = "s3://test/".parse()?;
 
-        let cmd = Cmd::Exec(Exec {
-            utility: "echo {}".to_owned(),
-        })
-        .downcast();
+        let stats = restoring_move(false, false)
+            .execute(&client, &path, &sized_objects(&[10, 20]))
+            .await?;
 
-        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
-        let client = Client::new(&config);
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.reclaimed_bytes, 10);
+        assert_eq!(
+            stats.errors,
+            vec!["s3://test/k1: the source was copied but not deleted: Access Denied"]
+        );
+        assert_eq!(stats.mutations.len(), 1);
+        assert_eq!(stats.mutations[0].keys, vec!["k0"]);
+        Ok(())
+    }
 
-        let path = S3Path {
-            bucket: "test".to_owned(),
-            prefix: None,
-            region: Region::from_static("us-east-1"),
+    #[tokio::test]
+    async fn test_copy_schedule_largest_first() -> Result<(), Error> {
+        let (client, http_client) =
+            replay_client(vec![copy_result(), copy_result(), copy_result()]);
+        let cmd = S3Copy {
+            flat: false,
+            schedule: Schedule::LargestFirst,
+            ..flat_copy(CollisionStrategy::Error)
         };
+        let path: S3Path = "s3://test/".parse()?;
+
+        let stats = cmd
+            .execute(&client, &path, &sized_objects(&[10, 300, 20]))
+            .await?;
 
-        cmd.execute(&client, &path, &[object]).await
+        assert_eq!(stats.processed, 3);
+        let sources: Vec<_> = http_client
+            .requests()
+            .iter()
+            .map(|x| x.header("x-amz-copy-source").unwrap().to_owned())
+            .collect();
+        assert_eq!(sources, vec!["test/k1", "test/k2", "test/k0"]);
+        // the targets of a copy without --flat cannot collide
+        assert!(cmd.targets.0.lock().unwrap().is_empty());
+        Ok(())
     }
 
-    #[test]
-    fn test_generate_s3_url() {
-        assert_eq!(
-            &generate_s3_url("us-east-1", "test-bucket", "somepath/somekey"),
-            "https://test-bucket.s3.amazonaws.com/somepath/somekey",
-        );
+    #[tokio::test]
+    async fn test_copy_auto_workers() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![
+            copy_result(),
+            ReplayResponse::new(503, ""),
+            copy_result(),
+        ]);
+        let cmd = S3Copy {
+            flat: false,
+            workers: "auto".parse()?,
+            ..flat_copy(CollisionStrategy::Error)
+        };
+        let path: S3Path = "s3://test/".parse()?;
+        let list = sized_objects(&[10]);
+
+        let mut workers = Vec::new();
+        for _ in 0..3 {
+            cmd.execute(&client, &path, &list).await?;
+            workers.push(cmd.workers.current());
+        }
+        assert_eq!(workers, vec![12, 6, 10]);
         assert_eq!(
-            &generate_s3_url("eu-west-1", "test-bucket", "somepath/somekey"),
-            "https://test-bucket.s3-eu-west-1.amazonaws.com/somepath/somekey",
+            cmd.summary().as_deref(),
+            Some("workers settled at:         10 (1 throttled requests)")
         );
+        Ok(())
+    }
+
+    /// The time a copy with 4 workers takes for the keys, the replay client
+    /// answers the copy of every key after a second per unit of its size
+    async fn copy_makespan(sizes: &[i64], schedule: Schedule) -> Result<Duration, Error> {
+        let list = sized_objects(sizes);
+        let delays = list
+            .iter()
+            .map(|x| {
+                (
+                    format!("/{}", x.key().unwrap()),
+                    Duration::from_secs(x.size().unwrap() as u64),
+                )
+            })
+            .collect();
+        let (client, _) = path_delayed_replay_client(vec![copy_result(); sizes.len()], delays);
+        let cmd = S3Copy {
+            flat: false,
+            workers: Workers::Fixed(4),
+            schedule,
+            ..flat_copy(CollisionStrategy::Error)
+        };
+        let path: S3Path = "s3://test/".parse()?;
+
+        let start = tokio::time::Instant::now();
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, sizes.len());
+        Ok(start.elapsed())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_schedule_makespan() -> Result<(), Error> {
+        let sizes = [2, 2, 2, 2, 2, 2, 2, 2, 8];
+
+        let listing = copy_makespan(&sizes, Schedule::Listing).await?;
+        let largest = copy_makespan(&sizes, Schedule::LargestFirst).await?;
+
+        assert_eq!(listing.as_secs(), 12);
+        assert_eq!(largest.as_secs(), 8);
+        Ok(())
     }
 }
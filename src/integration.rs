@@ -0,0 +1,318 @@
+//! End-to-end runs against a S3-compatible endpoint like MinIO or
+//! localstack, ignored unless asked for:
+//!
+//! ```sh
+//! S3FIND_INTEGRATION_ENDPOINT=http://localhost:9000 cargo test -- --ignored integration
+//! ```
+//!
+//! The access keys are S3FIND_INTEGRATION_ACCESS_KEY and
+//! S3FIND_INTEGRATION_SECRET_KEY, minioadmin by default. Every test creates
+//! a bucket of its own with the fixture keys and removes it at the end. The
+//! client sends path-style requests to the endpoint, like MinIO expects.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::Error;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use structopt::StructOpt;
+
+use crate::arg::{Cmd, FindOpt};
+use crate::command::{FilterList, Find, RunCounters};
+use crate::run::list_filter_execute;
+
+const REGION: &str = "us-east-1";
+
+/// The keys of a fixture bucket: key, size and tagging
+const FIXTURES: &[(&str, usize, Option<&str>)] = &[
+    ("logs/a.log", 10, Some("env=prod")),
+    ("logs/b.log", 2000, None),
+    ("data/c.csv", 5000, Some("env=dev")),
+    ("data/d.csv", 0, None),
+];
+
+/// A bucket of the endpoint created for a test
+struct TestBucket {
+    client: Client,
+    name: String,
+}
+
+impl TestBucket {
+    /// A client of the endpoint, none when S3FIND_INTEGRATION_ENDPOINT is
+    /// not set
+    fn client() -> Option<Client> {
+        let endpoint = std::env::var("S3FIND_INTEGRATION_ENDPOINT").ok()?;
+        let key = |name: &str| std::env::var(name).unwrap_or_else(|_| "minioadmin".to_owned());
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .region(Region::new(REGION))
+            .credentials_provider(Credentials::new(
+                key("S3FIND_INTEGRATION_ACCESS_KEY"),
+                key("S3FIND_INTEGRATION_SECRET_KEY"),
+                None,
+                None,
+                "integration",
+            ))
+            .build();
+        Some(Client::from_conf(config))
+    }
+
+    /// Creates a bucket with a unique name and uploads the fixtures
+    async fn create(client: Client) -> Result<Self, Error> {
+        let name = format!("s3find-it-{}", uuid::Uuid::new_v4().simple());
+        client.create_bucket().bucket(&name).send().await?;
+        let bucket = TestBucket { client, name };
+
+        for (key, size, tagging) in FIXTURES {
+            bucket
+                .client
+                .put_object()
+                .bucket(&bucket.name)
+                .key(*key)
+                .body(ByteStream::from(vec![b'x'; *size]))
+                .set_tagging(tagging.map(str::to_owned))
+                .send()
+                .await?;
+        }
+        Ok(bucket)
+    }
+
+    /// Removes the keys and the bucket
+    async fn teardown(&self) -> Result<(), Error> {
+        for key in self.keys("").await? {
+            self.client
+                .delete_object()
+                .bucket(&self.name)
+                .key(key)
+                .send()
+                .await?;
+        }
+        self.client
+            .delete_bucket()
+            .bucket(&self.name)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// The keys under the prefix, in the listing order
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.name)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            keys.extend(page?.contents().iter().filter_map(|x| x.key.clone()));
+        }
+        Ok(keys)
+    }
+
+    /// Runs s3find over the bucket like the binary does, with the arguments
+    /// after the path
+    async fn run(&self, args: &[&str]) -> Result<Arc<RunCounters>, Error> {
+        let path = format!("s3://{}/", self.name);
+        let mut argv = vec!["s3find", path.as_str()];
+        argv.extend(args);
+        let opts = FindOpt::from_iter_safe(argv)?;
+        opts.validate()?;
+        let filters = FilterList::from_opts(&opts)?;
+
+        let path = opts.path.clone().unwrap();
+        let find = Find {
+            client: self.client.clone(),
+            path: crate::arg::S3Path {
+                region: Region::new(REGION),
+                ..path
+            },
+            limit: opts.limit,
+            page_size: 1000,
+            prefetch: 1,
+            stats: false,
+            summarize: false,
+            command: opts.cmd.clone().unwrap_or_default().downcast(),
+            key_requests: opts.cmd.as_ref().is_some_and(Cmd::requests_keys),
+            counters: Default::default(),
+            credentials_provider: None,
+            budget: Default::default(),
+            notifiers: Vec::new(),
+            manifest: None,
+            batch_headers: None,
+            cache: None,
+            shard: None,
+            list_api: Default::default(),
+            dedup: None,
+            size_buckets: Default::default(),
+            one_level: false,
+            mtime_recheck: None,
+        };
+
+        find.command.preflight(&find.client, &find.path).await?;
+        let run = &find;
+        list_filter_execute(
+            find.to_stream().stream(),
+            find.limit,
+            None,
+            |x| {
+                run.counters.scan();
+                let matched = filters.test_match(x.object.clone());
+                let identifier = run.dedup_identifier(x);
+                let counters = &run.counters;
+                async move { counters.filtered(run.admit(matched.await, identifier)) }
+            },
+            &mut |acc, x| run.exec(acc, x),
+        )
+        .await;
+        let finished = find.command.finish(&find.client, &find.path).await?;
+        find.counters.add(&finished);
+        Ok(find.counters)
+    }
+}
+
+/// Runs the test in a fixture bucket and removes the bucket, whatever the
+/// result of the test. Without an endpoint the test is skipped.
+async fn with_bucket<F>(test: F)
+where
+    F: for<'a> FnOnce(&'a TestBucket) -> LocalBoxFuture<'a, Result<(), Error>>,
+{
+    let client = match TestBucket::client() {
+        Some(client) => client,
+        None => {
+            eprintln!("skipped: S3FIND_INTEGRATION_ENDPOINT is not set");
+            return;
+        }
+    };
+    let bucket = TestBucket::create(client).await.unwrap();
+    let result = test(&bucket).await;
+    let teardown = bucket.teardown().await;
+    result.unwrap();
+    teardown.unwrap();
+}
+
+#[tokio::test]
+#[ignore]
+async fn integration_list_with_filters() {
+    with_bucket(|bucket| {
+        async move {
+            let counters = bucket
+                .run(&["--name", "*.csv", "--size", "+1k", "ls"])
+                .await?;
+            assert_eq!(counters.scanned.load(Ordering::Relaxed), 4);
+            assert_eq!(counters.matched.load(Ordering::Relaxed), 1);
+
+            let counters = bucket.run(&["--regex", r"^logs/", "nothing"]).await?;
+            assert_eq!(counters.matched.load(Ordering::Relaxed), 2);
+            Ok(())
+        }
+        .boxed_local()
+    })
+    .await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn integration_download() {
+    with_bucket(|bucket| {
+        async move {
+            let dir = tempfile::TempDir::new()?;
+            let destination = dir.path().display().to_string();
+
+            let counters = bucket
+                .run(&["--name", "*.log", "download", &destination])
+                .await?;
+
+            assert_eq!(counters.error_count.load(Ordering::Relaxed), 0);
+            assert_eq!(std::fs::metadata(dir.path().join("logs/a.log"))?.len(), 10);
+            assert_eq!(
+                std::fs::metadata(dir.path().join("logs/b.log"))?.len(),
+                2000
+            );
+            assert!(!dir.path().join("data").exists());
+            Ok(())
+        }
+        .boxed_local()
+    })
+    .await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn integration_copy() {
+    with_bucket(|bucket| {
+        async move {
+            let destination = format!("s3://{}/backup/", bucket.name);
+
+            let counters = bucket
+                .run(&["--name", "*.csv", "copy", &destination])
+                .await?;
+
+            assert_eq!(counters.processed.load(Ordering::Relaxed), 2);
+            assert_eq!(
+                bucket.keys("backup/").await?,
+                vec!["backup/data/c.csv", "backup/data/d.csv"]
+            );
+            assert_eq!(bucket.keys("data/").await?.len(), 2);
+            Ok(())
+        }
+        .boxed_local()
+    })
+    .await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn integration_tags() {
+    with_bucket(|bucket| {
+        async move {
+            bucket
+                .run(&["--regex", r"a\.log$", "tags", "team:core"])
+                .await?;
+
+            let tagging = bucket
+                .client
+                .get_object_tagging()
+                .bucket(&bucket.name)
+                .key("logs/a.log")
+                .send()
+                .await?;
+            let tags: Vec<_> = tagging
+                .tag_set()
+                .iter()
+                .map(|x| format!("{}={}", x.key(), x.value()))
+                .collect();
+            assert_eq!(tags, vec!["team=core"]);
+            Ok(())
+        }
+        .boxed_local()
+    })
+    .await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn integration_delete() {
+    with_bucket(|bucket| {
+        async move {
+            let counters = bucket
+                .run(&["--name", "*.log", "delete", "--quiet-versioning-warning"])
+                .await?;
+
+            assert_eq!(counters.deleted.load(Ordering::Relaxed), 2);
+            assert_eq!(counters.reclaimed_bytes.load(Ordering::Relaxed), 2010);
+            assert!(bucket.keys("logs/").await?.is_empty());
+            assert_eq!(bucket.keys("data/").await?.len(), 2);
+            Ok(())
+        }
+        .boxed_local()
+    })
+    .await;
+}
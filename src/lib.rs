@@ -1,8 +1,36 @@
 #![recursion_limit = "256"]
 pub mod arg;
+pub mod cache;
 pub mod command;
+pub mod complete;
+pub mod credentials;
+pub mod datetime;
+pub mod dedup;
+pub mod distribution;
 pub mod error;
+pub mod estimate;
+pub mod export;
 pub mod filter;
 pub mod function;
+#[cfg(test)]
+mod integration;
+pub mod lifecycle;
+pub mod manifest;
+pub mod metrics;
+pub mod multipart;
+pub mod notify;
+pub mod output;
+pub mod parse;
+pub mod pipe;
+pub mod plan;
+pub mod progress;
+#[cfg(test)]
+mod replay;
+pub mod report;
+pub mod restore;
 pub mod run;
+pub mod schema;
+pub mod shard;
+pub mod style;
 pub mod utils;
+pub mod wizard;
@@ -0,0 +1,613 @@
+//! Lifecycle configuration proposed by suggest-lifecycle from the ages of
+//! the matched keys, the modification time approximating the last access.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use anyhow::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::types::{
+    BucketLifecycleConfiguration, ExpirationStatus, LifecycleExpiration, LifecycleRule,
+    LifecycleRuleFilter, Object, Transition, TransitionStorageClass,
+};
+use aws_sdk_s3::Client;
+use chrono::Utc;
+use humansize::*;
+use serde_json::json;
+
+use crate::arg::*;
+use crate::function::{storage_class, CommandStats, RunCommand};
+use crate::utils::s3_error;
+
+pub const REVIEW_BANNER: &str = "Review before applying: the rules are proposed from the modification \
+times of the listed keys, and applying them replaces the whole lifecycle configuration of the bucket";
+
+const RULE_ID_PREFIX: &str = "s3find-";
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// The path prefix and `depth` more key segments, None for the keys above
+/// that depth
+pub fn prefix_group(base: &str, key: &str, depth: usize) -> Option<String> {
+    let rest = key.strip_prefix(base)?;
+    let end = rest.match_indices('/').nth(depth.checked_sub(1)?)?.0;
+    Some(format!("{}{}", base, &rest[..=end]))
+}
+
+/// Whole days since the modification, a modification in the future is 0
+pub fn age_days(last_modified: i64, now: i64) -> i64 {
+    (now - last_modified).max(0) / SECONDS_PER_DAY
+}
+
+impl PrefixAges {
+    pub fn add(&mut self, age: i64, size: u64, class: &str) {
+        *self.ages.entry(age).or_default() += 1;
+        self.bytes = self.bytes.saturating_add(size);
+        *self.classes.entry(class.to_owned()).or_default() += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.ages.values().sum()
+    }
+
+    /// The smallest age with at least `fraction` of the keys at or below it
+    pub fn quantile(&self, fraction: f64) -> Option<i64> {
+        let rank = ((fraction * self.count() as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (age, count) in &self.ages {
+            seen += count;
+            if seen >= rank {
+                return Some(*age);
+            }
+        }
+        None
+    }
+
+    /// The age of the most recently modified key
+    pub fn youngest(&self) -> Option<i64> {
+        self.ages.keys().next().copied()
+    }
+}
+
+/// The rule proposed for a prefix
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleProposal {
+    pub prefix: String,
+    /// Storage class and age in days
+    pub transition: Option<(String, i64)>,
+    pub expiration: Option<i64>,
+}
+
+impl RuleProposal {
+    fn id(&self) -> String {
+        format!("{}{}", RULE_ID_PREFIX, self.prefix.trim_end_matches('/'))
+    }
+}
+
+impl SuggestLifecycle {
+    /// The rule for the keys of a prefix: the expiration when every key is
+    /// past the retention, otherwise a transition of the STANDARD keys at the
+    /// age the access percent of the keys is younger than
+    pub fn propose(&self, prefix: &str, ages: &PrefixAges) -> Option<RuleProposal> {
+        let expiration = self
+            .retention_days
+            .filter(|days| ages.youngest().is_some_and(|youngest| youngest >= *days));
+
+        let standard = ages.classes.get("STANDARD").copied().unwrap_or_default();
+        let transition = match expiration {
+            None if standard > 0 => ages
+                .quantile(self.access_percent / 100.0)
+                .map(|age| {
+                    (
+                        self.transition_class.clone(),
+                        age.max(self.min_transition_days),
+                    )
+                })
+                .filter(|(_, days)| self.retention_days.is_none_or(|x| *days < x)),
+            _ => None,
+        };
+
+        if transition.is_none() && expiration.is_none() {
+            return None;
+        }
+        Some(RuleProposal {
+            prefix: prefix.to_owned(),
+            transition,
+            expiration,
+        })
+    }
+
+    /// The rules of every prefix group, the keys above the group depth have
+    /// no rule
+    pub fn suggest(&self, groups: &BTreeMap<String, PrefixAges>) -> Vec<RuleProposal> {
+        groups
+            .iter()
+            .filter(|(prefix, _)| !prefix.is_empty())
+            .filter_map(|(prefix, ages)| self.propose(prefix, ages))
+            .collect()
+    }
+
+    /// One line per prefix group, printed with the proposal
+    fn analysis(&self, groups: &BTreeMap<String, PrefixAges>) -> String {
+        let file_size = make_format(BINARY);
+        let mut out = String::new();
+        for (prefix, ages) in groups {
+            let classes = ages
+                .classes
+                .iter()
+                .map(|(class, count)| format!("{} {}", class, count))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let prefix = match prefix.as_str() {
+                "" => "(above the group depth, no rule)",
+                prefix => prefix,
+            };
+            out.push_str(&format!(
+                "{}: {} keys, {}, {}% modified within {} days, {}\n",
+                prefix,
+                ages.count(),
+                file_size(ages.bytes),
+                self.access_percent,
+                ages.quantile(self.access_percent / 100.0)
+                    .unwrap_or_default(),
+                classes
+            ));
+        }
+        out
+    }
+}
+
+/// The configuration as taken by `aws s3api put-bucket-lifecycle-configuration`
+pub fn lifecycle_json(rules: &[RuleProposal]) -> String {
+    let rules: Vec<_> = rules
+        .iter()
+        .map(|rule| {
+            let mut value = json!({
+                "ID": rule.id(),
+                "Filter": {"Prefix": rule.prefix},
+                "Status": "Enabled",
+            });
+            if let Some((class, days)) = &rule.transition {
+                value["Transitions"] = json!([{"Days": days, "StorageClass": class}]);
+            }
+            if let Some(days) = rule.expiration {
+                value["Expiration"] = json!({ "Days": days });
+            }
+            value
+        })
+        .collect();
+    serde_json::to_string_pretty(&json!({ "Rules": rules })).unwrap_or_default()
+}
+
+/// The configuration as the PutBucketLifecycleConfiguration request body
+pub fn lifecycle_xml(rules: &[RuleProposal]) -> String {
+    let mut out = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- {} -->\n<LifecycleConfiguration xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\n",
+        REVIEW_BANNER
+    );
+    for rule in rules {
+        out.push_str("  <Rule>\n");
+        out.push_str(&format!("    <ID>{}</ID>\n", xml_escape(&rule.id())));
+        out.push_str(&format!(
+            "    <Filter><Prefix>{}</Prefix></Filter>\n",
+            xml_escape(&rule.prefix)
+        ));
+        out.push_str("    <Status>Enabled</Status>\n");
+        if let Some((class, days)) = &rule.transition {
+            out.push_str(&format!(
+                "    <Transition><Days>{}</Days><StorageClass>{}</StorageClass></Transition>\n",
+                days, class
+            ));
+        }
+        if let Some(days) = rule.expiration {
+            out.push_str(&format!(
+                "    <Expiration><Days>{}</Days></Expiration>\n",
+                days
+            ));
+        }
+        out.push_str("  </Rule>\n");
+    }
+    out.push_str("</LifecycleConfiguration>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Replaces the lifecycle configuration of the bucket with the rules
+pub async fn put_lifecycle(
+    client: &Client,
+    bucket: &str,
+    rules: &[RuleProposal],
+) -> Result<(), Error> {
+    let mut configuration = BucketLifecycleConfiguration::builder();
+    for rule in rules {
+        let mut builder = LifecycleRule::builder()
+            .id(rule.id())
+            .filter(LifecycleRuleFilter::builder().prefix(&rule.prefix).build())
+            .status(ExpirationStatus::Enabled);
+        if let Some((class, days)) = &rule.transition {
+            builder = builder.transitions(
+                Transition::builder()
+                    .days(*days as i32)
+                    .storage_class(TransitionStorageClass::from(class.as_str()))
+                    .build(),
+            );
+        }
+        if let Some(days) = rule.expiration {
+            builder = builder.expiration(LifecycleExpiration::builder().days(days as i32).build());
+        }
+        configuration = configuration.rules(builder.build()?);
+    }
+
+    client
+        .put_bucket_lifecycle_configuration()
+        .bucket(bucket)
+        .lifecycle_configuration(configuration.build()?)
+        .send()
+        .await
+        .map_err(s3_error)?;
+    Ok(())
+}
+
+fn confirmed(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn confirm(prompt: &str) -> bool {
+    eprint!("{} [y/N] ", prompt);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).is_ok() && confirmed(&answer)
+}
+
+#[async_trait]
+impl RunCommand for SuggestLifecycle {
+    async fn execute(
+        &self,
+        _c: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let now = Utc::now().timestamp();
+        let base = path.prefix.as_deref().unwrap_or_default();
+        let mut groups = self.tally.0.lock().unwrap();
+
+        for object in list {
+            let key = object.key.as_deref().unwrap_or_default();
+            let group = prefix_group(base, key, self.depth).unwrap_or_default();
+            let age = object
+                .last_modified
+                .map(|x| age_days(x.secs(), now))
+                .unwrap_or_default();
+            let size = object.size.unwrap_or_default().max(0) as u64;
+            groups
+                .entry(group)
+                .or_default()
+                .add(age, size, storage_class(object));
+        }
+
+        Ok(CommandStats::processed(list.len()))
+    }
+
+    /// Prints the analysis and the banner on stderr and the configuration on
+    /// stdout or in the output file
+    async fn finish(&self, client: &Client, path: &S3Path) -> Result<CommandStats, Error> {
+        let groups = self.tally.0.lock().unwrap().clone();
+        let rules = self.suggest(&groups);
+
+        eprint!("{}", self.analysis(&groups));
+        eprintln!("{}", REVIEW_BANNER);
+
+        let configuration = match self.format {
+            LifecycleFormat::Json => lifecycle_json(&rules) + "\n",
+            LifecycleFormat::Xml => lifecycle_xml(&rules),
+        };
+        match &self.output_file {
+            Some(file) => fs::write(file, configuration)?,
+            None => print!("{}", configuration),
+        }
+
+        let mut stats = CommandStats::default();
+        if !self.apply {
+            return Ok(stats);
+        }
+        if rules.is_empty() {
+            eprintln!("No rule proposed, the lifecycle configuration is not changed");
+            return Ok(stats);
+        }
+        let prompt = format!(
+            "Replace the lifecycle configuration of s3://{} with {} rules?",
+            path.bucket,
+            rules.len()
+        );
+        if !confirm(&prompt) {
+            eprintln!("Not applied");
+            return Ok(stats);
+        }
+
+        match put_lifecycle(client, &path.bucket, &rules).await {
+            Ok(()) => stats.processed = rules.len(),
+            Err(e) => {
+                eprintln!("error: s3://{}: {}", path.bucket, e);
+                stats.errors.push(format!("s3://{}: {}", path.bucket, e));
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::*;
+    use aws_sdk_s3::primitives::DateTime;
+    use aws_sdk_s3::types::ObjectStorageClass;
+
+    fn suggest(retention_days: Option<i64>) -> SuggestLifecycle {
+        SuggestLifecycle {
+            depth: 1,
+            transition_class: "STANDARD_IA".to_owned(),
+            access_percent: 80.0,
+            min_transition_days: 30,
+            retention_days,
+            format: LifecycleFormat::Json,
+            output_file: None,
+            apply: false,
+            tally: Default::default(),
+        }
+    }
+
+    /// `count` STANDARD keys of every age in the range
+    fn ages(range: std::ops::Range<i64>, count: u64) -> PrefixAges {
+        let mut ages = PrefixAges::default();
+        for age in range {
+            for _ in 0..count {
+                ages.add(age, 1024, "STANDARD");
+            }
+        }
+        ages
+    }
+
+    #[test]
+    fn test_prefix_group() {
+        assert_eq!(
+            prefix_group("", "logs/2024/a.log", 1).as_deref(),
+            Some("logs/")
+        );
+        assert_eq!(
+            prefix_group("", "logs/2024/a.log", 2).as_deref(),
+            Some("logs/2024/")
+        );
+        assert_eq!(
+            prefix_group("data/", "data/raw/a.csv", 1).as_deref(),
+            Some("data/raw/")
+        );
+        assert_eq!(prefix_group("", "logs/a.log", 2), None);
+        assert_eq!(prefix_group("", "top.txt", 1), None);
+        assert_eq!(prefix_group("data/", "other/a.csv", 1), None);
+        assert_eq!(prefix_group("", "logs/a.log", 0), None);
+    }
+
+    #[test]
+    fn test_age_days() {
+        assert_eq!(age_days(0, 86_399), 0);
+        assert_eq!(age_days(0, 86_400), 1);
+        assert_eq!(age_days(0, 10 * 86_400 + 5), 10);
+        assert_eq!(age_days(100, 0), 0);
+    }
+
+    #[test]
+    fn test_age_quantile() {
+        // uniform ages 0..100
+        let uniform = ages(0..100, 1);
+        assert_eq!(uniform.count(), 100);
+        assert_eq!(uniform.quantile(0.8), Some(79));
+        assert_eq!(uniform.quantile(0.0), Some(0));
+        assert_eq!(uniform.quantile(1.0), Some(99));
+        assert_eq!(uniform.youngest(), Some(0));
+
+        // most keys are young, a long tail of old keys
+        let mut skewed = ages(0..10, 90);
+        skewed.ages.extend(ages(300..310, 10).ages);
+        assert_eq!(skewed.quantile(0.8), Some(8));
+        assert_eq!(skewed.quantile(0.95), Some(304));
+
+        assert_eq!(PrefixAges::default().quantile(0.8), None);
+    }
+
+    #[test]
+    fn test_transition_proposal() {
+        let cmd = suggest(None);
+
+        // uniform ages 0..200, 80% are at most 159 days old
+        assert_eq!(
+            cmd.propose("logs/", &ages(0..200, 1)),
+            Some(RuleProposal {
+                prefix: "logs/".to_owned(),
+                transition: Some(("STANDARD_IA".to_owned(), 159)),
+                expiration: None,
+            })
+        );
+
+        // young keys: the S3 minimum applies
+        assert_eq!(
+            cmd.propose("tmp/", &ages(0..10, 5)).unwrap().transition,
+            Some(("STANDARD_IA".to_owned(), 30))
+        );
+
+        // nothing left in STANDARD
+        let mut archived = PrefixAges::default();
+        archived.add(400, 1, "GLACIER");
+        assert_eq!(cmd.propose("archive/", &archived), None);
+    }
+
+    #[test]
+    fn test_expiration_proposal() {
+        let cmd = suggest(Some(365));
+
+        // every key is past the retention: expire, no transition
+        assert_eq!(
+            cmd.propose("old/", &ages(400..500, 1)),
+            Some(RuleProposal {
+                prefix: "old/".to_owned(),
+                transition: None,
+                expiration: Some(365),
+            })
+        );
+
+        // keys within the retention keep the prefix, the young ones transition
+        let mut mixed = ages(0..50, 1);
+        mixed.ages.extend(ages(400..410, 1).ages);
+        assert_eq!(
+            cmd.propose("mixed/", &mixed),
+            Some(RuleProposal {
+                prefix: "mixed/".to_owned(),
+                transition: Some(("STANDARD_IA".to_owned(), 47)),
+                expiration: None,
+            })
+        );
+
+        // a transition at or past the retention is not proposed
+        let mut late = ages(0..1000, 1);
+        late.add(0, 1, "STANDARD");
+        let cmd = suggest(Some(500));
+        assert_eq!(cmd.propose("late/", &late), None);
+    }
+
+    #[test]
+    fn test_suggest_skips_ungrouped_keys() {
+        let cmd = suggest(None);
+        let mut groups = BTreeMap::new();
+        groups.insert("".to_owned(), ages(0..100, 1));
+        groups.insert("logs/".to_owned(), ages(0..100, 1));
+        groups.insert("empty/".to_owned(), PrefixAges::default());
+
+        let rules = cmd.suggest(&groups);
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].prefix, "logs/");
+
+        let analysis = cmd.analysis(&groups);
+        assert!(analysis.contains("(above the group depth, no rule): 100 keys"));
+        assert!(analysis.contains("logs/: 100 keys, 100 KiB, 80% modified within 79 days"));
+    }
+
+    fn rules() -> Vec<RuleProposal> {
+        vec![
+            RuleProposal {
+                prefix: "logs/".to_owned(),
+                transition: Some(("STANDARD_IA".to_owned(), 45)),
+                expiration: None,
+            },
+            RuleProposal {
+                prefix: "tmp/<x>&/".to_owned(),
+                transition: None,
+                expiration: Some(30),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_lifecycle_json() -> Result<(), Error> {
+        let value: serde_json::Value = serde_json::from_str(&lifecycle_json(&rules()))?;
+        assert_eq!(
+            value,
+            json!({"Rules": [
+                {
+                    "ID": "s3find-logs",
+                    "Filter": {"Prefix": "logs/"},
+                    "Status": "Enabled",
+                    "Transitions": [{"Days": 45, "StorageClass": "STANDARD_IA"}],
+                },
+                {
+                    "ID": "s3find-tmp/<x>&",
+                    "Filter": {"Prefix": "tmp/<x>&/"},
+                    "Status": "Enabled",
+                    "Expiration": {"Days": 30},
+                },
+            ]})
+        );
+        assert_eq!(lifecycle_json(&[]), "{\n  \"Rules\": []\n}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lifecycle_xml() {
+        let xml = lifecycle_xml(&rules());
+        assert!(xml.contains(&format!("<!-- {} -->", REVIEW_BANNER)));
+        assert!(xml.contains("<ID>s3find-logs</ID>"));
+        assert!(xml.contains(
+            "<Transition><Days>45</Days><StorageClass>STANDARD_IA</StorageClass></Transition>"
+        ));
+        assert!(xml.contains("<Filter><Prefix>tmp/&lt;x&gt;&amp;/</Prefix></Filter>"));
+        assert!(xml.contains("<Expiration><Days>30</Days></Expiration>"));
+        assert_eq!(xml.matches("<Rule>").count(), 2);
+    }
+
+    #[test]
+    fn test_confirmed() {
+        assert!(confirmed("y\n"));
+        assert!(confirmed(" YES "));
+        assert!(!confirmed("\n"));
+        assert!(!confirmed("no"));
+    }
+
+    #[tokio::test]
+    async fn test_collect_ages() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![]);
+        let cmd = suggest(None);
+        let path: S3Path = "s3://test/data/".parse()?;
+        let now = Utc::now().timestamp();
+        let object = |key: &str, days: i64, class| {
+            Object::builder()
+                .key(key)
+                .size(10)
+                .last_modified(DateTime::from_secs(now - days * 86_400 - 60))
+                .storage_class(class)
+                .build()
+        };
+        let list = vec![
+            object("data/raw/a.csv", 40, ObjectStorageClass::Standard),
+            object("data/raw/b.csv", 2, ObjectStorageClass::Glacier),
+            object("data/top.csv", 5, ObjectStorageClass::Standard),
+        ];
+
+        let stats = cmd.execute(&client, &path, &list).await?;
+        assert_eq!(stats.processed, 3);
+
+        let groups = cmd.tally.0.lock().unwrap().clone();
+        assert_eq!(groups.len(), 2);
+        let raw = &groups["data/raw/"];
+        assert_eq!(
+            raw.ages.iter().collect::<Vec<_>>(),
+            vec![(&2, &1), (&40, &1)]
+        );
+        assert_eq!(raw.bytes, 20);
+        assert_eq!(raw.classes["GLACIER"], 1);
+        assert_eq!(groups[""].count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_lifecycle() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![ReplayResponse::new(200, "")]);
+
+        put_lifecycle(&client, "test", &rules()).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert!(requests[0].uri.contains("lifecycle"));
+        let body = String::from_utf8(requests[0].body.clone())?;
+        assert!(body.contains("<ID>s3find-logs</ID>"));
+        assert!(body.contains("<Prefix>logs/</Prefix>"));
+        assert!(body.contains("<StorageClass>STANDARD_IA</StorageClass>"));
+        assert!(body.contains("<Expiration><Days>30</Days></Expiration>"));
+        Ok(())
+    }
+}
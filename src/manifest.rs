@@ -0,0 +1,424 @@
+//! Manifest of the matched objects for S3 Batch Operations jobs
+//!
+//! CSV lines are `bucket,key[,versionId]`, a field with a comma, a quote or
+//! a line break is quoted. JSON lines are `{"Bucket": ..., "Key": ..., "VersionId": ...}`.
+//! A key which is not valid UTF-8 has U+FFFD in place of its invalid bytes,
+//! JSON lines add its listed bytes as `key_bytes_base64`.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Error;
+use aws_sdk_s3::types::Object;
+use aws_sdk_s3::Client;
+use serde_json::json;
+
+use crate::arg::{FindOpt, ManifestFormat, S3Path};
+use crate::output::{OutputTarget, OutputWriter};
+use crate::utils::{add_key_bytes, KeyBytes};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestTarget {
+    S3(S3Path),
+    Local(PathBuf),
+}
+
+impl ManifestTarget {
+    pub fn from_opts(opts: &FindOpt) -> Option<ManifestTarget> {
+        match (&opts.emit_manifest, &opts.manifest_local) {
+            (Some(path), _) => Some(ManifestTarget::S3(path.clone())),
+            (None, Some(path)) => Some(ManifestTarget::Local(path.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+pub fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+pub fn manifest_line(
+    format: ManifestFormat,
+    bucket: &str,
+    key: &str,
+    key_bytes: Option<&[u8]>,
+    version_id: Option<&str>,
+) -> String {
+    match format {
+        ManifestFormat::Csv => {
+            let mut line = format!("{},{}", csv_field(bucket), csv_field(key));
+            if let Some(version_id) = version_id {
+                line.push(',');
+                line.push_str(&csv_field(version_id));
+            }
+            line.push('\n');
+            line
+        }
+        ManifestFormat::Json => {
+            let mut object = json!({ "Bucket": bucket, "Key": key });
+            if let Some(version_id) = version_id {
+                object["VersionId"] = json!(version_id);
+            }
+            add_key_bytes(&mut object, key_bytes);
+            format!("{}\n", object)
+        }
+    }
+}
+
+/// Where the manifest was written, printed after the run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestLocation {
+    pub target: ManifestTarget,
+    pub e_tag: Option<String>,
+    pub format: ManifestFormat,
+    pub versioned: bool,
+    pub objects: u64,
+}
+
+impl ManifestLocation {
+    /// The manifest section of a create-job request, for CSV manifests in S3
+    pub fn job_manifest(&self) -> Option<String> {
+        let path = match (&self.target, self.format) {
+            (ManifestTarget::S3(path), ManifestFormat::Csv) => path,
+            _ => return None,
+        };
+
+        let mut fields = vec!["Bucket", "Key"];
+        if self.versioned {
+            fields.push("VersionId");
+        }
+        let manifest = json!({
+            "Spec": {
+                "Format": "S3BatchOperations_CSV_20180820",
+                "Fields": fields,
+            },
+            "Location": {
+                "ObjectArn": format!(
+                    "arn:aws:s3:::{}/{}",
+                    path.bucket,
+                    path.prefix.as_deref().unwrap_or_default()
+                ),
+                "ETag": self.e_tag.as_deref().unwrap_or_default().trim_matches('"'),
+            },
+        });
+        Some(manifest.to_string())
+    }
+}
+
+impl fmt::Display for ManifestLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            ManifestTarget::S3(path) => write!(
+                f,
+                "Manifest: s3://{}/{} ({} objects, ETag {})",
+                path.bucket,
+                path.prefix.as_deref().unwrap_or_default(),
+                self.objects,
+                self.e_tag.as_deref().unwrap_or("unknown")
+            )?,
+            ManifestTarget::Local(path) => {
+                write!(f, "Manifest: {} ({} objects)", path.display(), self.objects)?
+            }
+        }
+
+        if let Some(manifest) = self.job_manifest() {
+            write!(f, "\nBatch Operations manifest: {}", manifest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Streams the manifest lines of the matched batches: to the local file as
+/// they come, to S3 in parts once the manifest reaches the threshold
+pub struct ManifestWriter {
+    target: ManifestTarget,
+    format: ManifestFormat,
+    version_id: Option<String>,
+    objects: AtomicU64,
+    pub output: OutputWriter,
+}
+
+impl ManifestWriter {
+    pub fn new(
+        target: ManifestTarget,
+        format: ManifestFormat,
+        version_id: Option<String>,
+    ) -> Result<Self, Error> {
+        let output = OutputWriter::create(match &target {
+            ManifestTarget::S3(path) => OutputTarget::S3(path.clone()),
+            ManifestTarget::Local(path) => OutputTarget::File(path.clone()),
+        })?;
+
+        Ok(ManifestWriter {
+            target,
+            format,
+            version_id,
+            objects: AtomicU64::new(0),
+            output,
+        })
+    }
+
+    pub async fn append(
+        &self,
+        client: &Client,
+        bucket: &str,
+        objects: &[Object],
+        key_bytes: &KeyBytes,
+    ) -> Result<(), Error> {
+        let mut lines = String::new();
+        for (i, object) in objects.iter().enumerate() {
+            let key = match object.key.as_deref() {
+                Some(key) => key,
+                None => continue,
+            };
+            lines.push_str(&manifest_line(
+                self.format,
+                bucket,
+                key,
+                key_bytes.get(i),
+                self.version_id.as_deref(),
+            ));
+            self.objects.fetch_add(1, Ordering::Relaxed);
+        }
+        self.output.write(client, lines.as_bytes()).await
+    }
+
+    /// Writes the rest of the manifest; a failed multipart upload is aborted
+    pub async fn finish(&self, client: &Client) -> Result<ManifestLocation, Error> {
+        let e_tag = self.output.finish(client).await?;
+
+        Ok(ManifestLocation {
+            target: self.target.clone(),
+            e_tag,
+            format: self.format,
+            versioned: self.version_id.is_some(),
+            objects: self.objects.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::*;
+
+    fn objects(keys: &[&str]) -> Vec<Object> {
+        keys.iter()
+            .map(|key| Object::builder().key(*key).build())
+            .collect()
+    }
+
+    fn etag(status: u16, e_tag: &str, body: &str) -> ReplayResponse {
+        ReplayResponse {
+            status,
+            timeout: false,
+            headers: vec![("ETag".to_owned(), e_tag.to_owned())],
+            body: body.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_manifest_line() {
+        assert_eq!(
+            manifest_line(ManifestFormat::Csv, "bucket", "logs/a.log", None, None),
+            "bucket,logs/a.log\n"
+        );
+        assert_eq!(
+            manifest_line(
+                ManifestFormat::Csv,
+                "bucket",
+                r#"reports/q1, "final".csv"#,
+                None,
+                Some("v1")
+            ),
+            "bucket,\"reports/q1, \"\"final\"\".csv\",v1\n"
+        );
+        assert_eq!(
+            manifest_line(ManifestFormat::Csv, "bucket", "line\nbreak", None, None),
+            "bucket,\"line\nbreak\"\n"
+        );
+        assert_eq!(
+            manifest_line(ManifestFormat::Json, "bucket", "a\"b", None, Some("v1")),
+            "{\"Bucket\":\"bucket\",\"Key\":\"a\\\"b\",\"VersionId\":\"v1\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_manifest_line_invalid_utf8() {
+        let (key, bytes) = crate::utils::decode_listed_key("manifest/bad%FF%E2%80%AE");
+        assert_eq!(
+            manifest_line(ManifestFormat::Csv, "bucket", &key, bytes.as_deref(), None),
+            "bucket,manifest/bad\u{fffd}\u{202e}\n"
+        );
+
+        let line = manifest_line(ManifestFormat::Json, "bucket", &key, bytes.as_deref(), None);
+        let object: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(object["Key"], "manifest/bad\u{fffd}\u{202e}");
+        assert_eq!(object["key_bytes_base64"], "bWFuaWZlc3QvYmFk/+KArg==");
+    }
+
+    #[tokio::test]
+    async fn test_local_manifest() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("manifest.csv");
+        let (client, http_client) = replay_client(vec![]);
+        let writer = ManifestWriter::new(
+            ManifestTarget::Local(path.clone()),
+            ManifestFormat::Csv,
+            None,
+        )?;
+
+        writer
+            .append(
+                &client,
+                "bucket",
+                &objects(&["a,b", "c"]),
+                &KeyBytes::default(),
+            )
+            .await?;
+        writer
+            .append(&client, "bucket", &objects(&["d"]), &KeyBytes::default())
+            .await?;
+        let location = writer.finish(&client).await?;
+
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "bucket,\"a,b\"\nbucket,c\nbucket,d\n"
+        );
+        assert_eq!(location.objects, 3);
+        assert_eq!(location.job_manifest(), None);
+        assert!(http_client.requests().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_small_manifest_upload() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![etag(200, "\"abc\"", "")]);
+        let writer = ManifestWriter::new(
+            ManifestTarget::S3("s3://manifests/run.csv".parse()?),
+            ManifestFormat::Csv,
+            Some("v1".to_owned()),
+        )?;
+
+        writer
+            .append(&client, "bucket", &objects(&["key"]), &KeyBytes::default())
+            .await?;
+        let location = writer.finish(&client).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].path(),
+            "https://manifests.s3.us-east-1.amazonaws.com/run.csv"
+        );
+        assert_eq!(requests[0].body, b"bucket,key,v1\n");
+
+        assert_eq!(location.e_tag.as_deref(), Some("\"abc\""));
+        assert_eq!(
+            location.job_manifest().unwrap(),
+            r#"{"Location":{"ETag":"abc","ObjectArn":"arn:aws:s3:::manifests/run.csv"},"Spec":{"Fields":["Bucket","Key","VersionId"],"Format":"S3BatchOperations_CSV_20180820"}}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multipart_manifest_upload() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult><Bucket>manifests</Bucket><Key>run.csv</Key><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>"#,
+            ),
+            etag(200, "\"part1\"", ""),
+            etag(200, "\"part2\"", ""),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<CompleteMultipartUploadResult><Bucket>manifests</Bucket><Key>run.csv</Key><ETag>"whole-2"</ETag></CompleteMultipartUploadResult>"#,
+            ),
+        ]);
+        let mut writer = ManifestWriter::new(
+            ManifestTarget::S3("s3://manifests/run.csv".parse()?),
+            ManifestFormat::Csv,
+            None,
+        )?;
+        writer.output.part_size = 16;
+
+        // 3 lines of 9 bytes: one part of 16 bytes, 11 bytes left
+        writer
+            .append(
+                &client,
+                "bucket",
+                &objects(&["aa", "bb"]),
+                &KeyBytes::default(),
+            )
+            .await?;
+        assert_eq!(http_client.requests().len(), 2);
+        writer
+            .append(&client, "bucket", &objects(&["cc"]), &KeyBytes::default())
+            .await?;
+        assert_eq!(http_client.requests().len(), 2);
+
+        let location = writer.finish(&client).await?;
+        assert_eq!(location.e_tag.as_deref(), Some("\"whole-2\""));
+        assert_eq!(location.objects, 3);
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].uri.contains("uploads"));
+        assert!(requests[1].uri.contains("partNumber=1"));
+        assert!(requests[1].uri.contains("uploadId=upload-1"));
+        assert_eq!(requests[1].body, b"bucket,aa\nbucket");
+        assert!(requests[2].uri.contains("partNumber=2"));
+        assert_eq!(requests[2].body, b",bb\nbucket,cc\n");
+
+        let complete = String::from_utf8(requests[3].body.clone())?;
+        assert!(requests[3].uri.contains("uploadId=upload-1"));
+        assert!(complete.contains("<ETag>&quot;part1&quot;</ETag><PartNumber>1</PartNumber>"));
+        assert!(complete.contains("<ETag>&quot;part2&quot;</ETag><PartNumber>2</PartNumber>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_multipart_is_aborted() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>"#,
+            ),
+            etag(200, "\"part1\"", ""),
+            ReplayResponse::new(500, "<Error><Code>InternalError</Code></Error>"),
+            ReplayResponse::new(204, ""),
+        ]);
+        let mut writer = ManifestWriter::new(
+            ManifestTarget::S3("s3://manifests/run.csv".parse()?),
+            ManifestFormat::Csv,
+            None,
+        )?;
+        writer.output.part_size = 16;
+
+        writer
+            .append(
+                &client,
+                "bucket",
+                &objects(&["aa", "bb"]),
+                &KeyBytes::default(),
+            )
+            .await?;
+        assert!(writer.finish(&client).await.is_err());
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[3].method, "DELETE");
+        assert!(requests[3].uri.contains("uploadId=upload-1"));
+        Ok(())
+    }
+}
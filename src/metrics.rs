@@ -0,0 +1,399 @@
+use std::fs;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use serde_json::json;
+
+use crate::arg::MetricsTarget;
+
+const EMF_NAMESPACE: &str = "s3find";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunMetrics {
+    pub bucket: String,
+    pub prefix: String,
+    pub command: String,
+    pub scanned: u64,
+    pub matched: u64,
+    pub matched_bytes: u64,
+    pub deleted: u64,
+    pub downloaded_bytes: u64,
+    pub requests: u64,
+    /// The requests by api: list, get, put and delete
+    pub api_requests: Vec<(&'static str, u64)>,
+    pub errors: u64,
+    pub duration: Duration,
+}
+
+impl RunMetrics {
+    // (statsd name, EMF name, EMF unit, value)
+    fn values(&self) -> [(&'static str, &'static str, &'static str, u64); 7] {
+        [
+            (
+                "s3find.objects.scanned",
+                "ObjectsScanned",
+                "Count",
+                self.scanned,
+            ),
+            (
+                "s3find.objects.matched",
+                "ObjectsMatched",
+                "Count",
+                self.matched,
+            ),
+            (
+                "s3find.bytes.matched",
+                "BytesMatched",
+                "Bytes",
+                self.matched_bytes,
+            ),
+            (
+                "s3find.objects.deleted",
+                "ObjectsDeleted",
+                "Count",
+                self.deleted,
+            ),
+            (
+                "s3find.bytes.downloaded",
+                "BytesDownloaded",
+                "Bytes",
+                self.downloaded_bytes,
+            ),
+            ("s3find.requests", "Requests", "Count", self.requests),
+            (
+                "s3find.duration",
+                "Duration",
+                "Milliseconds",
+                self.duration.as_millis() as u64,
+            ),
+        ]
+    }
+
+    pub fn statsd_lines(&self) -> Vec<String> {
+        let tags = format!(
+            "bucket:{},prefix:{},command:{}",
+            self.bucket, self.prefix, self.command
+        );
+
+        self.values()
+            .iter()
+            .map(|(name, _, unit, value)| {
+                let kind = if *unit == "Milliseconds" { "ms" } else { "c" };
+                format!("{}:{}|{}|#{}", name, value, kind, tags)
+            })
+            .collect()
+    }
+
+    pub fn emf_line(&self, timestamp: u64) -> String {
+        let definitions: Vec<_> = self
+            .values()
+            .iter()
+            .map(|(_, name, unit, _)| json!({ "Name": name, "Unit": unit }))
+            .collect();
+
+        let mut document = json!({
+            "_aws": {
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": EMF_NAMESPACE,
+                    "Dimensions": [["bucket", "prefix", "command"]],
+                    "Metrics": definitions,
+                }],
+            },
+            "bucket": self.bucket,
+            "prefix": self.prefix,
+            "command": self.command,
+        });
+
+        for (_, name, _, value) in self.values().iter() {
+            document[*name] = json!(value);
+        }
+
+        document.to_string()
+    }
+
+    /// The metrics in the Prometheus text exposition format, for the
+    /// node_exporter textfile collector
+    pub fn prometheus_text(&self) -> String {
+        let labels = format!(
+            r#"bucket="{}",prefix="{}",command="{}""#,
+            prometheus_label(&self.bucket),
+            prometheus_label(&self.prefix),
+            prometheus_label(&self.command)
+        );
+
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            text.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (extra, value) in samples {
+                text.push_str(&format!("{}{{{}{}}} {}\n", name, labels, extra, value));
+            }
+        };
+        let sample = |value: u64| [(String::new(), value.to_string())];
+
+        metric(
+            "s3find_objects_scanned_total",
+            "counter",
+            "Objects listed by the run.",
+            &sample(self.scanned),
+        );
+        metric(
+            "s3find_objects_matched_total",
+            "counter",
+            "Objects which passed the filters.",
+            &sample(self.matched),
+        );
+        metric(
+            "s3find_bytes_total",
+            "counter",
+            "Bytes of the matched objects.",
+            &sample(self.matched_bytes),
+        );
+        let requests: Vec<_> = self
+            .api_requests
+            .iter()
+            .map(|(api, value)| (format!(r#",api="{}""#, api), value.to_string()))
+            .collect();
+        metric(
+            "s3find_requests_total",
+            "counter",
+            "S3 requests issued by the run.",
+            &requests,
+        );
+        metric(
+            "s3find_errors_total",
+            "counter",
+            "Errors of the run.",
+            &sample(self.errors),
+        );
+        metric(
+            "s3find_duration_seconds",
+            "gauge",
+            "Duration of the run in seconds.",
+            &[(String::new(), self.duration.as_secs_f64().to_string())],
+        );
+        text
+    }
+}
+
+/// Escapes a label value of the Prometheus text format
+fn prometheus_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+pub trait MetricsSink {
+    fn emit(&self, metrics: &RunMetrics) -> Result<(), Error>;
+}
+
+pub struct StatsdSink {
+    pub address: String,
+}
+
+impl MetricsSink for StatsdSink {
+    fn emit(&self, metrics: &RunMetrics) -> Result<(), Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        for line in metrics.statsd_lines() {
+            socket.send_to(line.as_bytes(), &self.address)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct EmfSink;
+
+impl MetricsSink for EmfSink {
+    fn emit(&self, metrics: &RunMetrics) -> Result<(), Error> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        println!("{}", metrics.emf_line(timestamp));
+        Ok(())
+    }
+}
+
+/// A textfile of the node_exporter textfile collector, written to a
+/// temporary file next to it and renamed, so a scrape never reads half of it
+pub struct PrometheusTextfile {
+    pub path: PathBuf,
+}
+
+impl MetricsSink for PrometheusTextfile {
+    fn emit(&self, metrics: &RunMetrics) -> Result<(), Error> {
+        let name = self
+            .path
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("{} is not a file", self.path.display()))?;
+        // the collector only reads the files ending with .prom
+        let temp = self.path.with_file_name(format!(
+            ".{}.{}.tmp",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+
+        let written =
+            fs::write(&temp, metrics.prometheus_text()).and_then(|_| fs::rename(&temp, &self.path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        Ok(written?)
+    }
+}
+
+pub fn metrics_sink(target: &MetricsTarget) -> Box<dyn MetricsSink> {
+    match target {
+        MetricsTarget::Statsd(address) => Box::new(StatsdSink {
+            address: address.to_owned(),
+        }),
+        MetricsTarget::Emf => Box::new(EmfSink),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn run_metrics() -> RunMetrics {
+        RunMetrics {
+            bucket: "test".to_owned(),
+            prefix: "some/path".to_owned(),
+            command: "delete".to_owned(),
+            scanned: 10,
+            matched: 4,
+            matched_bytes: 4096,
+            deleted: 4,
+            downloaded_bytes: 0,
+            requests: 3,
+            api_requests: vec![("list", 1), ("get", 0), ("put", 0), ("delete", 2)],
+            errors: 1,
+            duration: Duration::from_millis(1500),
+        }
+    }
+
+    #[test]
+    fn test_statsd_sink() -> Result<(), Error> {
+        let server = UdpSocket::bind("127.0.0.1:0")?;
+        server.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let sink = StatsdSink {
+            address: server.local_addr()?.to_string(),
+        };
+        sink.emit(&run_metrics())?;
+
+        let mut buf = [0; 512];
+        let mut datagrams = Vec::new();
+        for _ in 0..7 {
+            let (len, _) = server.recv_from(&mut buf)?;
+            datagrams.push(String::from_utf8(buf[..len].to_vec())?);
+        }
+
+        let tags = "#bucket:test,prefix:some/path,command:delete";
+        assert_eq!(
+            datagrams[0],
+            format!("s3find.objects.scanned:10|c|{}", tags)
+        );
+        assert_eq!(datagrams[1], format!("s3find.objects.matched:4|c|{}", tags));
+        assert_eq!(
+            datagrams[2],
+            format!("s3find.bytes.matched:4096|c|{}", tags)
+        );
+        assert_eq!(datagrams[3], format!("s3find.objects.deleted:4|c|{}", tags));
+        assert_eq!(datagrams[5], format!("s3find.requests:3|c|{}", tags));
+        assert_eq!(datagrams[6], format!("s3find.duration:1500|ms|{}", tags));
+        Ok(())
+    }
+
+    #[test]
+    fn test_emf_line() -> Result<(), Error> {
+        let line = run_metrics().emf_line(1_500_000_000_000);
+        let document: Value = serde_json::from_str(&line)?;
+
+        let aws = &document["_aws"];
+        assert_eq!(aws["Timestamp"], 1_500_000_000_000_u64);
+
+        let directive = &aws["CloudWatchMetrics"][0];
+        assert_eq!(directive["Namespace"], "s3find");
+        assert_eq!(
+            directive["Dimensions"],
+            json!([["bucket", "prefix", "command"]])
+        );
+
+        let metrics = directive["Metrics"].as_array().unwrap();
+        assert_eq!(metrics.len(), 7);
+        for definition in metrics {
+            let name = definition["Name"].as_str().unwrap();
+            assert!(document[name].is_u64());
+            assert!(definition["Unit"].is_string());
+        }
+
+        assert_eq!(document["bucket"], "test");
+        assert_eq!(document["prefix"], "some/path");
+        assert_eq!(document["command"], "delete");
+        assert_eq!(document["ObjectsScanned"], 10);
+        assert_eq!(document["Duration"], 1500);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let mut metrics = run_metrics();
+        metrics.prefix = "some/\"path\"".to_owned();
+        let labels = r#"bucket="test",prefix="some/\"path\"",command="delete""#;
+
+        let expected = [
+            "# HELP s3find_objects_scanned_total Objects listed by the run.".to_owned(),
+            "# TYPE s3find_objects_scanned_total counter".to_owned(),
+            format!("s3find_objects_scanned_total{{{}}} 10", labels),
+            "# HELP s3find_objects_matched_total Objects which passed the filters.".to_owned(),
+            "# TYPE s3find_objects_matched_total counter".to_owned(),
+            format!("s3find_objects_matched_total{{{}}} 4", labels),
+            "# HELP s3find_bytes_total Bytes of the matched objects.".to_owned(),
+            "# TYPE s3find_bytes_total counter".to_owned(),
+            format!("s3find_bytes_total{{{}}} 4096", labels),
+            "# HELP s3find_requests_total S3 requests issued by the run.".to_owned(),
+            "# TYPE s3find_requests_total counter".to_owned(),
+            format!(r#"s3find_requests_total{{{},api="list"}} 1"#, labels),
+            format!(r#"s3find_requests_total{{{},api="get"}} 0"#, labels),
+            format!(r#"s3find_requests_total{{{},api="put"}} 0"#, labels),
+            format!(r#"s3find_requests_total{{{},api="delete"}} 2"#, labels),
+            "# HELP s3find_errors_total Errors of the run.".to_owned(),
+            "# TYPE s3find_errors_total counter".to_owned(),
+            format!("s3find_errors_total{{{}}} 1", labels),
+            "# HELP s3find_duration_seconds Duration of the run in seconds.".to_owned(),
+            "# TYPE s3find_duration_seconds gauge".to_owned(),
+            format!("s3find_duration_seconds{{{}}} 1.5", labels),
+        ];
+        assert_eq!(
+            metrics.prometheus_text(),
+            expected
+                .iter()
+                .map(|x| format!("{}\n", x))
+                .collect::<String>()
+        );
+    }
+
+    #[test]
+    fn test_prometheus_textfile() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("s3find_cleanup.prom");
+        fs::write(&path, "stale")?;
+
+        PrometheusTextfile { path: path.clone() }.emit(&run_metrics())?;
+
+        assert_eq!(fs::read_to_string(&path)?, run_metrics().prometheus_text());
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        let missing = dir.path().join("missing").join("s3find.prom");
+        assert!(PrometheusTextfile { path: missing }
+            .emit(&run_metrics())
+            .is_err());
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+//! Incomplete multipart uploads keep their parts stored until they are
+//! aborted, but never show up in the object listing.
+
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::{MultipartUpload, Object, ObjectStorageClass};
+use aws_sdk_s3::Client;
+use aws_smithy_types::date_time::Format;
+use humansize::*;
+
+use crate::arg::{ListMultipart, S3Path};
+use crate::command::{FilterList, Find};
+use crate::function::{CommandStats, RunCommand};
+use crate::utils::s3_error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upload {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: Option<DateTime>,
+    pub initiator: Option<String>,
+    pub storage_class: Option<ObjectStorageClass>,
+}
+
+impl From<MultipartUpload> for Upload {
+    fn from(upload: MultipartUpload) -> Self {
+        Upload {
+            key: upload.key.unwrap_or_default(),
+            upload_id: upload.upload_id.unwrap_or_default(),
+            initiated: upload.initiated,
+            // the display name is missing in many regions, the id is not
+            initiator: upload
+                .initiator
+                .and_then(|initiator| initiator.display_name.or(initiator.id)),
+            storage_class: upload
+                .storage_class
+                .map(|class| ObjectStorageClass::from(class.as_str())),
+        }
+    }
+}
+
+impl Upload {
+    /// The upload as a listed object for the filters: the initiation date is
+    /// its modification time and it has no size
+    pub fn as_object(&self) -> Object {
+        Object::builder()
+            .key(&self.key)
+            .set_last_modified(self.initiated)
+            .set_storage_class(self.storage_class.clone())
+            .build()
+    }
+
+    fn line(&self, bucket: &str) -> String {
+        format!(
+            "s3://{}/{} {} {} {:?}",
+            bucket,
+            self.key,
+            self.upload_id,
+            self.initiated
+                .and_then(|x| x.fmt(Format::DateTime).ok())
+                .unwrap_or_default(),
+            self.initiator,
+        )
+    }
+}
+
+/// What list-multipart found and aborted
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MultipartSummary {
+    pub listed: usize,
+    pub matched: usize,
+    pub aborted: usize,
+    /// Parts size of the aborted uploads, as listed before the abort
+    pub reclaimed_bytes: u64,
+    pub errors: usize,
+    pub abort: bool,
+}
+
+impl fmt::Display for MultipartSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file_size = make_format(BINARY);
+        write!(
+            f,
+            "Multipart uploads: {} matched of {} listed",
+            self.matched, self.listed
+        )?;
+        if self.abort {
+            write!(
+                f,
+                "\nAborted: {} uploads, ~{} reclaimed",
+                self.aborted,
+                file_size(self.reclaimed_bytes)
+            )?;
+        }
+        if self.errors > 0 {
+            write!(f, "\nErrors: {}", self.errors)?;
+        }
+        Ok(())
+    }
+}
+
+impl ListMultipart {
+    /// Lists the uploads under the path page by page, prints the matched ones
+    /// and aborts them with --abort
+    pub async fn run(
+        &self,
+        find: &Find,
+        filters: &FilterList<'_>,
+    ) -> Result<MultipartSummary, Error> {
+        let mut summary = MultipartSummary {
+            abort: self.abort,
+            ..Default::default()
+        };
+        let mut key_marker = None;
+        let mut upload_id_marker = None;
+
+        loop {
+            if find.budget.exhausted(&find.counters) {
+                find.counters
+                    .budget_exhausted
+                    .store(true, Ordering::Relaxed);
+                break;
+            }
+            if find.budget.expired(&find.counters) {
+                break;
+            }
+
+            let page = find
+                .client
+                .list_multipart_uploads()
+                .bucket(&find.path.bucket)
+                .prefix(find.path.prefix.clone().unwrap_or_default())
+                .set_key_marker(key_marker)
+                .set_upload_id_marker(upload_id_marker)
+                .send()
+                .await
+                .map_err(s3_error)?;
+
+            for upload in page.uploads.unwrap_or_default() {
+                if find.limit.is_some_and(|limit| summary.matched >= limit) {
+                    return Ok(summary);
+                }
+
+                let upload = Upload::from(upload);
+                summary.listed += 1;
+                find.counters.scan();
+                if !find
+                    .counters
+                    .filtered(filters.test_match(upload.as_object()).await)
+                {
+                    continue;
+                }
+                summary.matched += 1;
+                println!("{}", upload.line(&find.path.bucket));
+
+                if self.abort {
+                    match abort_upload(&find.client, &find.path.bucket, &upload).await {
+                        Ok(bytes) => {
+                            summary.aborted += 1;
+                            summary.reclaimed_bytes += bytes;
+                        }
+                        Err(e) => {
+                            eprintln!(
+                                "error: s3://{}/{} {}: {}",
+                                &find.path.bucket, upload.key, upload.upload_id, e
+                            );
+                            summary.errors += 1;
+                        }
+                    }
+                }
+            }
+
+            if !page.is_truncated.unwrap_or_default()
+                || (page.next_key_marker.is_none() && page.next_upload_id_marker.is_none())
+            {
+                break;
+            }
+            key_marker = page.next_key_marker;
+            upload_id_marker = page.next_upload_id_marker;
+        }
+
+        Ok(summary)
+    }
+}
+
+/// Aborts the upload, returning the size of its parts listed before
+async fn abort_upload(client: &Client, bucket: &str, upload: &Upload) -> Result<u64, Error> {
+    let bytes = parts_size(client, bucket, upload).await?;
+    client
+        .abort_multipart_upload()
+        .bucket(bucket)
+        .key(&upload.key)
+        .upload_id(&upload.upload_id)
+        .send()
+        .await
+        .map_err(s3_error)?;
+    Ok(bytes)
+}
+
+async fn parts_size(client: &Client, bucket: &str, upload: &Upload) -> Result<u64, Error> {
+    let mut bytes = 0;
+    let mut marker = None;
+
+    loop {
+        let page = client
+            .list_parts()
+            .bucket(bucket)
+            .key(&upload.key)
+            .upload_id(&upload.upload_id)
+            .set_part_number_marker(marker)
+            .send()
+            .await
+            .map_err(s3_error)?;
+        bytes += page
+            .parts()
+            .iter()
+            .filter_map(|part| part.size)
+            .sum::<i64>() as u64;
+
+        if !page.is_truncated.unwrap_or_default() || page.next_part_number_marker.is_none() {
+            return Ok(bytes);
+        }
+        marker = page.next_part_number_marker;
+    }
+}
+
+/// The uploads are listed by ListMultipart::run instead of the object
+/// listing, there is nothing to do with the listed keys
+#[async_trait]
+impl RunCommand for ListMultipart {
+    async fn execute(
+        &self,
+        _c: &Client,
+        _p: &S3Path,
+        _l: &[Object],
+    ) -> Result<CommandStats, Error> {
+        Ok(CommandStats::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg::{Cmd, FindOpt};
+    use crate::replay::*;
+    use structopt::StructOpt;
+
+    fn upload(key: &str, upload_id: &str) -> String {
+        format!(
+            "<Upload><Key>{}</Key><UploadId>{}</UploadId><Initiator><ID>id-1</ID><DisplayName>ops</DisplayName></Initiator><StorageClass>STANDARD</StorageClass><Initiated>2024-01-02T03:04:05.000Z</Initiated></Upload>",
+            key, upload_id
+        )
+    }
+
+    fn uploads_page(uploads: &[(&str, &str)], next: Option<(&str, &str)>) -> ReplayResponse {
+        let markers = next
+            .map(|(key, upload_id)| {
+                format!(
+                    "<NextKeyMarker>{}</NextKeyMarker><NextUploadIdMarker>{}</NextUploadIdMarker>",
+                    key, upload_id
+                )
+            })
+            .unwrap_or_default();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListMultipartUploadsResult><Bucket>test</Bucket><IsTruncated>{}</IsTruncated>{}{}</ListMultipartUploadsResult>"#,
+                next.is_some(),
+                markers,
+                uploads
+                    .iter()
+                    .map(|(key, upload_id)| upload(key, upload_id))
+                    .collect::<String>()
+            ),
+        )
+    }
+
+    fn parts_page(sizes: &[i64], next: Option<&str>) -> ReplayResponse {
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListPartsResult><Bucket>test</Bucket><IsTruncated>{}</IsTruncated>{}{}</ListPartsResult>"#,
+                next.is_some(),
+                next.map(|x| format!("<NextPartNumberMarker>{}</NextPartNumberMarker>", x))
+                    .unwrap_or_default(),
+                sizes
+                    .iter()
+                    .enumerate()
+                    .map(|(n, size)| format!(
+                        "<Part><PartNumber>{}</PartNumber><Size>{}</Size></Part>",
+                        n + 1,
+                        size
+                    ))
+                    .collect::<String>()
+            ),
+        )
+    }
+
+    fn test_find(client: Client, args: &[&str]) -> (Find, FindOpt) {
+        let opts =
+            FindOpt::from_iter_safe(["s3find", "s3://test/tmp/"].iter().chain(args)).unwrap();
+        let find = Find {
+            client,
+            path: opts.path.clone().unwrap(),
+            limit: opts.limit,
+            page_size: 1000,
+            prefetch: 1,
+            stats: false,
+            summarize: false,
+            command: opts.cmd.clone().unwrap_or_default().downcast(),
+            key_requests: opts.cmd.as_ref().is_some_and(Cmd::requests_keys),
+            counters: Default::default(),
+            credentials_provider: None,
+            budget: Default::default(),
+            notifiers: Vec::new(),
+            manifest: None,
+            batch_headers: None,
+            cache: None,
+            shard: None,
+            list_api: Default::default(),
+            dedup: None,
+            size_buckets: Default::default(),
+            one_level: false,
+            mtime_recheck: None,
+        };
+        (find, opts)
+    }
+
+    #[test]
+    fn test_upload_object() {
+        let upload = Upload::from(
+            MultipartUpload::builder()
+                .key("tmp/a.bin")
+                .upload_id("u1")
+                .initiated(DateTime::from_secs(1_700_000_000))
+                .initiator(aws_sdk_s3::types::Initiator::builder().id("id-1").build())
+                .build(),
+        );
+        assert_eq!(upload.initiator.as_deref(), Some("id-1"));
+
+        let object = upload.as_object();
+        assert_eq!(object.key.as_deref(), Some("tmp/a.bin"));
+        assert_eq!(
+            object.last_modified,
+            Some(DateTime::from_secs(1_700_000_000))
+        );
+        assert_eq!(object.size, None);
+        assert_eq!(
+            upload.line("test"),
+            "s3://test/tmp/a.bin u1 2023-11-14T22:13:20Z Some(\"id-1\")"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_pagination() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            uploads_page(
+                &[("tmp/a.bin", "u1"), ("tmp/b.txt", "u2")],
+                Some(("tmp/b.txt", "u2")),
+            ),
+            uploads_page(&[("tmp/c.bin", "u3")], None),
+        ]);
+        let (find, opts) = test_find(client, &["--name", "*.bin", "list-multipart"]);
+        let filters = FilterList::from_opts(&opts)?;
+
+        let summary = ListMultipart { abort: false }.run(&find, &filters).await?;
+        assert_eq!(
+            summary,
+            MultipartSummary {
+                listed: 3,
+                matched: 2,
+                ..Default::default()
+            }
+        );
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].uri.contains("uploads"));
+        assert!(requests[0].uri.contains("prefix=tmp%2F"));
+        assert!(!requests[0].uri.contains("key-marker"));
+        assert!(requests[1].uri.contains("key-marker=tmp%2Fb.txt"));
+        assert!(requests[1].uri.contains("upload-id-marker=u2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_abort_uploads() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            uploads_page(&[("tmp/a.bin", "u1"), ("tmp/b.bin", "u2")], None),
+            parts_page(&[100, 200], Some("2")),
+            parts_page(&[50], None),
+            ReplayResponse::new(204, ""),
+            parts_page(&[], None),
+            ReplayResponse::new(404, "<Error><Code>NoSuchUpload</Code></Error>"),
+        ]);
+        let (find, opts) = test_find(client, &["list-multipart", "--abort"]);
+        let filters = FilterList::from_opts(&opts)?;
+
+        let summary = ListMultipart { abort: true }.run(&find, &filters).await?;
+        assert_eq!(
+            summary,
+            MultipartSummary {
+                listed: 2,
+                matched: 2,
+                aborted: 1,
+                reclaimed_bytes: 350,
+                errors: 1,
+                abort: true,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "Multipart uploads: 2 matched of 2 listed\nAborted: 1 uploads, ~350 B reclaimed\nErrors: 1"
+        );
+
+        let requests = http_client.requests();
+        assert_eq!(
+            requests
+                .iter()
+                .map(|x| format!("{} {}", x.method, x.path()))
+                .collect::<Vec<_>>(),
+            vec![
+                "GET https://test.s3.us-east-1.amazonaws.com/",
+                "GET https://test.s3.us-east-1.amazonaws.com/tmp/a.bin",
+                "GET https://test.s3.us-east-1.amazonaws.com/tmp/a.bin",
+                "DELETE https://test.s3.us-east-1.amazonaws.com/tmp/a.bin",
+                "GET https://test.s3.us-east-1.amazonaws.com/tmp/b.bin",
+                "DELETE https://test.s3.us-east-1.amazonaws.com/tmp/b.bin",
+            ]
+        );
+        assert!(requests[0].uri.contains("uploads"));
+        assert!(requests[1].uri.contains("uploadId=u1"));
+        assert!(requests[2].uri.contains("part-number-marker=2"));
+        assert!(requests[3].uri.contains("uploadId=u1"));
+        assert!(requests[5].uri.contains("uploadId=u2"));
+        Ok(())
+    }
+}
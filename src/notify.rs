@@ -0,0 +1,323 @@
+//! Events posted to --notify-url and published to --notify-sns for every
+//! batch of changed keys
+//!
+//! The event is a JSON object, see "Mutation events" in the README:
+//! `{"version": 1, "action": "delete", "bucket": "...", "keys": [...], "timestamp": "..."}`
+//!
+//! The SNS topic is published with aws-sdk-sns, which needs a build with the
+//! `sns` feature.
+
+use std::time::Duration;
+
+use anyhow::Error;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::json;
+
+use crate::arg::{NotifyUrl, SnsTopic};
+use crate::error::FunctionError;
+use crate::function::Mutation;
+use crate::utils::retry_with_backoff;
+
+/// Version of the event schema, changed on incompatible changes
+pub const EVENT_VERSION: u32 = 1;
+
+const NOTIFY_ATTEMPTS: u32 = 3;
+const NOTIFY_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationEvent {
+    pub action: String,
+    pub bucket: String,
+    pub keys: Vec<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl MutationEvent {
+    pub fn new(mutation: &Mutation, bucket: &str) -> Self {
+        MutationEvent {
+            action: mutation.action.to_owned(),
+            bucket: bucket.to_owned(),
+            keys: mutation.keys.clone(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn json(&self) -> String {
+        json!({
+            "version": EVENT_VERSION,
+            "action": self.action,
+            "bucket": self.bucket,
+            "keys": self.keys,
+            "timestamp": self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        })
+        .to_string()
+    }
+}
+
+enum Transport {
+    Http(Client<HttpConnector>),
+    Https(Client<HttpsConnector<HttpConnector>>),
+}
+
+/// Posts the events to a webhook
+pub struct WebhookNotifier {
+    url: Uri,
+    transport: Transport,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &NotifyUrl) -> Result<Self, Error> {
+        let url: Uri = url.0.parse()?;
+        let transport = match url.scheme_str() {
+            Some("https") => Transport::Https(
+                Client::builder().build(
+                    HttpsConnectorBuilder::new()
+                        .with_native_roots()
+                        .https_only()
+                        .enable_http1()
+                        .build(),
+                ),
+            ),
+            _ => Transport::Http(Client::new()),
+        };
+
+        Ok(WebhookNotifier { url, transport })
+    }
+
+    async fn post(&self, body: String) -> Result<(), Error> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header("content-type", "application/json")
+            .body(Body::from(body))?;
+
+        let response = match &self.transport {
+            Transport::Http(client) => client.request(request).await?,
+            Transport::Https(client) => client.request(request).await?,
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(FunctionError::NotifyError(self.url.to_string(), status.as_u16()).into());
+        }
+        Ok(())
+    }
+
+    /// Posts the event, retrying a failed post with a backoff
+    pub async fn notify(&self, event: &MutationEvent) -> Result<(), Error> {
+        retry_with_backoff(NOTIFY_ATTEMPTS, NOTIFY_BACKOFF, || self.post(event.json())).await
+    }
+}
+
+#[cfg(feature = "sns")]
+pub use self::sns::SnsNotifier;
+
+/// Publishes the events to an SNS topic
+#[cfg(not(feature = "sns"))]
+pub struct SnsNotifier;
+
+#[cfg(not(feature = "sns"))]
+impl SnsNotifier {
+    pub fn new(
+        _topic: &SnsTopic,
+        _credentials_provider: Option<SharedCredentialsProvider>,
+    ) -> Result<Self, Error> {
+        Err(FunctionError::NotifySnsUnsupported.into())
+    }
+
+    pub async fn notify(&self, _event: &MutationEvent) -> Result<(), Error> {
+        Err(FunctionError::NotifySnsUnsupported.into())
+    }
+}
+
+#[cfg(feature = "sns")]
+mod sns {
+    use aws_sdk_sns::config::{BehaviorVersion, Region};
+    use aws_sdk_sns::{Client, Config};
+
+    use super::*;
+    use crate::utils::s3_error;
+
+    /// Publishes the events to an SNS topic
+    pub struct SnsNotifier {
+        client: Client,
+        topic_arn: String,
+    }
+
+    impl SnsNotifier {
+        /// The client publishes in the region of the topic
+        pub fn new(
+            topic: &SnsTopic,
+            credentials_provider: Option<SharedCredentialsProvider>,
+        ) -> Result<Self, Error> {
+            let mut config = Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new(topic.region.clone()));
+            config.set_credentials_provider(credentials_provider);
+            let config = config.build();
+            Ok(Self::from_client(Client::from_conf(config), &topic.arn))
+        }
+
+        pub fn from_client(client: Client, topic_arn: &str) -> Self {
+            SnsNotifier {
+                client,
+                topic_arn: topic_arn.to_owned(),
+            }
+        }
+
+        async fn publish(&self, message: String) -> Result<(), Error> {
+            self.client
+                .publish()
+                .topic_arn(&self.topic_arn)
+                .message(message)
+                .send()
+                .await
+                .map_err(s3_error)?;
+            Ok(())
+        }
+
+        /// Publishes the event, retrying a failed publish with a backoff
+        pub async fn notify(&self, event: &MutationEvent) -> Result<(), Error> {
+            retry_with_backoff(NOTIFY_ATTEMPTS, NOTIFY_BACKOFF, || {
+                self.publish(event.json())
+            })
+            .await
+        }
+    }
+}
+
+/// A target of the events, --notify-url or --notify-sns
+pub enum Notifier {
+    Webhook(Box<WebhookNotifier>),
+    Sns(SnsNotifier),
+}
+
+impl Notifier {
+    pub async fn notify(&self, event: &MutationEvent) -> Result<(), Error> {
+        match self {
+            Notifier::Webhook(notifier) => notifier.notify(event).await,
+            Notifier::Sns(notifier) => notifier.notify(event).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    use crate::replay::webhook_server;
+
+    fn event() -> MutationEvent {
+        MutationEvent {
+            action: "delete".to_owned(),
+            bucket: "test".to_owned(),
+            keys: vec!["a".to_owned(), "b".to_owned()],
+            timestamp: "2024-03-01T10:00:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_event_json() -> Result<(), Error> {
+        let document: Value = serde_json::from_str(&event().json())?;
+
+        assert_eq!(
+            document,
+            json!({
+                "version": 1,
+                "action": "delete",
+                "bucket": "test",
+                "keys": ["a", "b"],
+                "timestamp": "2024-03-01T10:00:00.000Z",
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notify_retry() -> Result<(), Error> {
+        let (url, bodies) = webhook_server(vec![500]);
+        let notifier = WebhookNotifier::new(&url)?;
+
+        notifier.notify(&event()).await?;
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0], event().json());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notify_failed() -> Result<(), Error> {
+        let (url, bodies) = webhook_server(vec![503, 503, 503]);
+        let notifier = WebhookNotifier::new(&url)?;
+
+        let error = notifier.notify(&event()).await.unwrap_err();
+
+        assert!(error.to_string().ends_with("failed with status 503"));
+        assert_eq!(bodies.lock().unwrap().len(), 3);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "sns"))]
+    #[test]
+    fn test_sns_unsupported() {
+        let topic: SnsTopic = "arn:aws:sns:us-east-1:123456789012:catalog"
+            .parse()
+            .unwrap();
+
+        assert!(SnsNotifier::new(&topic, None).is_err());
+    }
+
+    #[cfg(feature = "sns")]
+    mod sns {
+        use percent_encoding::percent_decode_str;
+
+        use super::*;
+        use crate::replay::{sns_replay_client, ReplayResponse};
+
+        const PUBLISHED: &str = r#"<PublishResponse xmlns="http://sns.amazonaws.com/doc/2010-03-31/">
+<PublishResult><MessageId>1</MessageId></PublishResult>
+<ResponseMetadata><RequestId>r</RequestId></ResponseMetadata>
+</PublishResponse>"#;
+
+        /// A field of the form encoded Publish request
+        fn form_field(body: &[u8], name: &str) -> Option<String> {
+            String::from_utf8_lossy(body).split('&').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                (key == name).then(|| {
+                    percent_decode_str(&value.replace('+', " "))
+                        .decode_utf8_lossy()
+                        .into_owned()
+                })
+            })
+        }
+
+        #[tokio::test]
+        async fn test_sns_notify_retry() -> Result<(), Error> {
+            let (client, http_client) = sns_replay_client(vec![
+                ReplayResponse::new(500, ""),
+                ReplayResponse::new(200, PUBLISHED),
+            ]);
+            let notifier =
+                SnsNotifier::from_client(client, "arn:aws:sns:us-east-1:123456789012:catalog");
+
+            notifier.notify(&event()).await?;
+
+            let requests = http_client.requests();
+            assert_eq!(requests.len(), 2);
+            let body = &requests[1].body;
+            assert_eq!(form_field(body, "Action").as_deref(), Some("Publish"));
+            assert_eq!(
+                form_field(body, "TopicArn").as_deref(),
+                Some("arn:aws:sns:us-east-1:123456789012:catalog")
+            );
+            assert_eq!(form_field(body, "Message"), Some(event().json()));
+            Ok(())
+        }
+    }
+}
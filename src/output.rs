@@ -0,0 +1,406 @@
+//! Destinations of the print output: stdout, a local file gzip compressed
+//! when its name ends with `.gz`, or a S3 key uploaded in parts while the
+//! output streams.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::Error;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tokio::sync::Mutex;
+
+use crate::arg::{AdvancedPrint, S3Path};
+use crate::error::FunctionError;
+use crate::utils::s3_error;
+
+/// Output size from which the upload is split into parts of this size,
+/// above the 5 MiB minimum part size of S3
+pub const MULTIPART_THRESHOLD: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputTarget {
+    Stdout,
+    File(PathBuf),
+    S3(S3Path),
+}
+
+impl OutputTarget {
+    /// The destination of the print options, the date placeholders of the
+    /// key are expanded once when the run starts
+    pub fn from_print(cmd: &AdvancedPrint) -> OutputTarget {
+        match (&cmd.output_file, &cmd.output_s3) {
+            (Some(path), _) => OutputTarget::File(path.clone()),
+            (None, Some(key)) => {
+                let date = Utc::now().format("%Y-%m-%d").to_string();
+                OutputTarget::S3(key.render(&date))
+            }
+            (None, None) => OutputTarget::Stdout,
+        }
+    }
+}
+
+impl fmt::Display for OutputTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputTarget::Stdout => write!(f, "stdout"),
+            OutputTarget::File(path) => write!(f, "{}", path.display()),
+            OutputTarget::S3(path) => write!(
+                f,
+                "s3://{}/{}",
+                path.bucket,
+                path.prefix.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+enum LocalSink {
+    Stdout(io::Stdout),
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl LocalSink {
+    fn open(target: &OutputTarget) -> io::Result<Option<LocalSink>> {
+        let path = match target {
+            OutputTarget::Stdout => return Ok(Some(LocalSink::Stdout(io::stdout()))),
+            OutputTarget::File(path) => path,
+            OutputTarget::S3(_) => return Ok(None),
+        };
+
+        let file = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|x| x == "gz") {
+            Ok(Some(LocalSink::Gzip(GzEncoder::new(
+                file,
+                Compression::default(),
+            ))))
+        } else {
+            Ok(Some(LocalSink::Plain(file)))
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            LocalSink::Stdout(stdout) => stdout.lock().write_all(data),
+            LocalSink::Plain(file) => file.write_all(data),
+            LocalSink::Gzip(file) => file.write_all(data),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            LocalSink::Stdout(stdout) => stdout.flush(),
+            LocalSink::Plain(file) => file.flush(),
+            LocalSink::Gzip(file) => {
+                file.try_finish()?;
+                file.get_mut().flush()
+            }
+        }
+    }
+}
+
+/// Headers of an output uploaded to S3
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UploadHeaders {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Default)]
+struct OutputState {
+    opened: bool,
+    local: Option<LocalSink>,
+    buffer: Vec<u8>,
+    upload_id: Option<String>,
+    parts: Vec<CompletedPart>,
+    failed: bool,
+}
+
+/// Streams the written output: to stdout or the local file as it comes,
+/// to S3 in parts once the output reaches the part size
+pub struct OutputWriter {
+    pub target: OutputTarget,
+    pub part_size: usize,
+    pub headers: UploadHeaders,
+    state: Mutex<OutputState>,
+}
+
+impl OutputWriter {
+    /// A writer of the target, the local file is created by `open` or the
+    /// first write
+    pub fn new(target: OutputTarget) -> Self {
+        OutputWriter {
+            target,
+            part_size: MULTIPART_THRESHOLD,
+            headers: UploadHeaders::default(),
+            state: Mutex::new(OutputState::default()),
+        }
+    }
+
+    /// A writer of the target with the local file already created
+    pub fn create(target: OutputTarget) -> Result<Self, Error> {
+        let mut writer = OutputWriter::new(target);
+        let state = writer.state.get_mut();
+        state.local = LocalSink::open(&writer.target)?;
+        state.opened = true;
+        Ok(writer)
+    }
+
+    /// Creates the local file, so an unwritable path fails before listing
+    pub async fn open(&self) -> Result<(), Error> {
+        self.opened(&mut *self.state.lock().await)
+    }
+
+    fn opened(&self, state: &mut OutputState) -> Result<(), Error> {
+        if !state.opened {
+            state.local = LocalSink::open(&self.target)?;
+            state.opened = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the data; a failed multipart upload is aborted and the rest
+    /// of the output is dropped
+    pub async fn write(&self, client: &Client, data: &[u8]) -> Result<(), Error> {
+        let mut state = self.state.lock().await;
+        self.opened(&mut state)?;
+
+        let path = match &self.target {
+            OutputTarget::S3(path) => path,
+            _ => match &mut state.local {
+                Some(local) => return Ok(local.write_all(data)?),
+                None => return Ok(()),
+            },
+        };
+
+        if state.failed {
+            return Err(FunctionError::OutputAborted(self.target.to_string()).into());
+        }
+        state.buffer.extend_from_slice(data);
+        while state.buffer.len() >= self.part_size {
+            let part: Vec<u8> = state.buffer.drain(..self.part_size).collect();
+            if let Err(e) = self.upload_part(client, path, &mut state, part).await {
+                self.abort(client, path, &mut state).await;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn upload_part(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        state: &mut OutputState,
+        part: Vec<u8>,
+    ) -> Result<(), Error> {
+        let key = path.prefix.as_deref().unwrap_or_default();
+
+        let upload_id = match &state.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let upload = client
+                    .create_multipart_upload()
+                    .bucket(&path.bucket)
+                    .key(key)
+                    .set_content_type(self.headers.content_type.clone())
+                    .set_content_encoding(self.headers.content_encoding.clone())
+                    .set_metadata(self.headers.metadata.clone())
+                    .send()
+                    .await
+                    .map_err(s3_error)?;
+                let upload_id = upload.upload_id.unwrap_or_default();
+                state.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+
+        let part_number = state.parts.len() as i32 + 1;
+        let uploaded = client
+            .upload_part()
+            .bucket(&path.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(part))
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        state.parts.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(uploaded.e_tag)
+                .build(),
+        );
+        Ok(())
+    }
+
+    /// Drops the output after a failure of the producer, an upload in
+    /// progress is aborted
+    pub async fn cancel(&self, client: &Client) {
+        if let OutputTarget::S3(path) = &self.target {
+            self.abort(client, path, &mut *self.state.lock().await)
+                .await;
+        }
+    }
+
+    async fn abort(&self, client: &Client, path: &S3Path, state: &mut OutputState) {
+        state.failed = true;
+        state.buffer.clear();
+        if let Some(upload_id) = state.upload_id.take() {
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(&path.bucket)
+                .key(path.prefix.as_deref().unwrap_or_default())
+                .upload_id(upload_id)
+                .send()
+                .await;
+        }
+    }
+
+    /// Writes the rest of the output, returns the ETag of an uploaded
+    /// output; a failed multipart upload is aborted
+    pub async fn finish(&self, client: &Client) -> Result<Option<String>, Error> {
+        let mut state = self.state.lock().await;
+        self.opened(&mut state)?;
+
+        let path = match &self.target {
+            OutputTarget::S3(path) => path,
+            _ => {
+                if let Some(local) = &mut state.local {
+                    local.finish()?;
+                }
+                return Ok(None);
+            }
+        };
+
+        if state.failed {
+            return Err(FunctionError::OutputAborted(self.target.to_string()).into());
+        }
+        let result = self.upload(client, path, &mut state).await;
+        if result.is_err() {
+            self.abort(client, path, &mut state).await;
+        }
+        result
+    }
+
+    async fn upload(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        state: &mut OutputState,
+    ) -> Result<Option<String>, Error> {
+        let key = path.prefix.as_deref().unwrap_or_default();
+        let rest = std::mem::take(&mut state.buffer);
+
+        if state.upload_id.is_none() {
+            let put = client
+                .put_object()
+                .bucket(&path.bucket)
+                .key(key)
+                .set_content_type(self.headers.content_type.clone())
+                .set_content_encoding(self.headers.content_encoding.clone())
+                .set_metadata(self.headers.metadata.clone())
+                .body(ByteStream::from(rest))
+                .send()
+                .await
+                .map_err(s3_error)?;
+            return Ok(put.e_tag);
+        }
+
+        if !rest.is_empty() {
+            self.upload_part(client, path, state, rest).await?;
+        }
+
+        let completed = client
+            .complete_multipart_upload()
+            .bucket(&path.bucket)
+            .key(key)
+            .set_upload_id(state.upload_id.clone())
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(state.parts.clone()))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(completed.e_tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn test_gzip_file_roundtrip() -> Result<(), Error> {
+        let dir = tempfile::TempDir::new()?;
+        let (client, http_client) = replay_client(vec![]);
+
+        let path = dir.path().join("results.csv.gz");
+        let writer = OutputWriter::new(OutputTarget::File(path.clone()));
+        writer.open().await?;
+        writer.write(&client, b"s3://test/a\n").await?;
+        writer.write(&client, b"s3://test/b\n").await?;
+        assert_eq!(writer.finish(&client).await?, None);
+
+        let mut output = String::new();
+        GzDecoder::new(File::open(&path)?).read_to_string(&mut output)?;
+        assert_eq!(output, "s3://test/a\ns3://test/b\n");
+
+        let path = dir.path().join("results.csv");
+        let writer = OutputWriter::new(OutputTarget::File(path.clone()));
+        writer.write(&client, b"s3://test/a\n").await?;
+        writer.finish(&client).await?;
+        assert_eq!(std::fs::read_to_string(&path)?, "s3://test/a\n");
+        assert!(http_client.requests().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_file() {
+        let writer = OutputWriter::new(OutputTarget::File("/nonexistent/results.csv".into()));
+        assert!(writer.open().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_part_is_aborted() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>"#,
+            ),
+            ReplayResponse::new(500, "<Error><Code>InternalError</Code></Error>"),
+            ReplayResponse::new(204, ""),
+        ]);
+        let mut writer = OutputWriter::new(OutputTarget::S3("s3://reports/run.csv".parse()?));
+        writer.part_size = 4;
+
+        assert!(writer.write(&client, b"s3://test/a\n").await.is_err());
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].method, "DELETE");
+        assert!(requests[2].uri.contains("uploadId=upload-1"));
+
+        // the rest of the output is dropped, nothing is completed
+        assert!(writer.write(&client, b"s3://test/b\n").await.is_err());
+        assert!(writer.finish(&client).await.is_err());
+        assert_eq!(http_client.requests().len(), 3);
+        Ok(())
+    }
+}
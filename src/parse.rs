@@ -0,0 +1,122 @@
+//! The parsers of the s3find arguments for code which embeds s3find as a
+//! library. The types of this module, their `FromStr` grammars and their
+//! `Display` forms are kept stable across the minor releases: a parsed
+//! value renders back to its canonical command line string, which parses
+//! to the same value.
+//!
+//! | Type        | Grammar                   | Examples                      |
+//! |-------------|---------------------------|-------------------------------|
+//! | [`S3Path`]  | `s3://bucket[/prefix]`    | `s3://bucket/logs/`           |
+//! | [`FindSize`]| `[+-]<number>[kMGTP]`     | `+10M`, `-1k`, `512`          |
+//! | [`FindTime`]| `[+-]<number>[smhdw]`     | `-2h`, `+30d`                 |
+//! | [`FindTag`] | `<key>:<value>`           | `env:prod`                    |
+//!
+//! A path:
+//!
+//! ```
+//! use s3find::parse::S3Path;
+//!
+//! let path: S3Path = "s3://bucket/logs/2024".parse().unwrap();
+//! assert_eq!(path.bucket, "bucket");
+//! assert_eq!(path.prefix.as_deref(), Some("logs/2024"));
+//! assert_eq!(path.key(), Some("logs/2024"));
+//! assert_eq!(path.to_string(), "s3://bucket/logs/2024");
+//! ```
+//!
+//! A size, the units are powers of 1024 and the canonical form uses the
+//! largest unit which divides the size:
+//!
+//! ```
+//! use s3find::parse::FindSize;
+//!
+//! let size: FindSize = "+2048k".parse().unwrap();
+//! assert_eq!(size, FindSize::Bigger(2 * 1024 * 1024));
+//! assert_eq!(size.to_string(), "+2M");
+//! assert_eq!("-100".parse::<FindSize>().unwrap(), FindSize::Lower(100));
+//! ```
+//!
+//! A time, `-` keeps the keys modified within the period and `+` or no
+//! sign the keys modified before it:
+//!
+//! ```
+//! use s3find::parse::FindTime;
+//!
+//! let time: FindTime = "-120m".parse().unwrap();
+//! assert_eq!(time, FindTime::Upper(7200));
+//! assert_eq!(time.to_string(), "-2h");
+//! assert_eq!("14d".parse::<FindTime>().unwrap().to_string(), "+2w");
+//! ```
+//!
+//! A tag, and the errors of the parsers, which are [`FindError`] values
+//! behind `anyhow::Error`:
+//!
+//! ```
+//! use s3find::parse::{FindError, FindTag};
+//!
+//! let tag: FindTag = "env:prod".parse().unwrap();
+//! assert_eq!((tag.key.as_str(), tag.value.as_str()), ("env", "prod"));
+//! assert_eq!(tag.to_string(), "env:prod");
+//!
+//! let error = "env".parse::<FindTag>().unwrap_err();
+//! assert!(matches!(
+//!     error.downcast_ref::<FindError>(),
+//!     Some(FindError::TagParseError)
+//! ));
+//! ```
+//!
+//! [`FindError`] is `#[non_exhaustive]`, a match on it needs a wildcard arm.
+
+pub use crate::arg::{FindError, FindSize, FindTag, FindTime, PathKind, S3Path};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    fn round_trip<T>(input: &str, canonical: &str)
+    where
+        T: FromStr<Err = anyhow::Error> + Display + PartialEq + std::fmt::Debug,
+    {
+        let value: T = input.parse().unwrap();
+        assert_eq!(value.to_string(), canonical);
+        assert_eq!(canonical.parse::<T>().unwrap(), value);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip::<S3Path>("s3://bucket", "s3://bucket");
+        round_trip::<S3Path>("s3://bucket/", "s3://bucket/");
+        round_trip::<S3Path>("s3://bucket/dir/file.txt", "s3://bucket/dir/file.txt");
+
+        round_trip::<FindSize>("0", "0");
+        round_trip::<FindSize>("1000", "1000");
+        round_trip::<FindSize>("1024", "1k");
+        round_trip::<FindSize>("+1024M", "+1G");
+        round_trip::<FindSize>("-3P", "-3P");
+
+        round_trip::<FindTime>("0", "+0s");
+        round_trip::<FindTime>("90", "+90s");
+        round_trip::<FindTime>("+60m", "+1h");
+        round_trip::<FindTime>("-48h", "-2d");
+        round_trip::<FindTime>("-7d", "-1w");
+
+        round_trip::<FindTag>("key:value", "key:value");
+    }
+
+    #[test]
+    fn test_display_of_built_values() {
+        let values = [
+            FindSize::Equal(5 * 1024_i64.pow(4)),
+            FindSize::Bigger(1),
+            FindSize::Lower(1536),
+        ];
+        for value in values {
+            assert_eq!(value.to_string().parse::<FindSize>().unwrap(), value);
+        }
+
+        for value in [FindTime::Upper(86_401), FindTime::Lower(604_800)] {
+            assert_eq!(value.to_string().parse::<FindTime>().unwrap(), value);
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! `--stdin`: the command runs over the keys of s3:// URLs read one per
+//! line, like the output of `ls` of another run, instead of a listing.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::BufRead;
+use std::sync::atomic::Ordering;
+
+use anyhow::Error;
+use aws_sdk_s3::types::Object;
+use futures::StreamExt;
+
+use crate::arg::{PathKind, S3Path};
+use crate::command::{head_listing, FilterList, Find, FindStat};
+use crate::error::FunctionError;
+use crate::function::CommandStats;
+use crate::run::list_filter_execute;
+use crate::utils::{display_key, error_context, ListedObject};
+
+/// The number of HEAD requests sent at once
+const HEAD_CONCURRENCY: usize = 16;
+
+/// A line of the input which is not the URL of a key
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidLine {
+    /// The line number, from 1
+    pub number: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.number, self.reason)
+    }
+}
+
+/// The keys of the input grouped by bucket, the buckets in the order of
+/// their first line and the keys in the order of the lines, without
+/// duplicates
+#[derive(Debug, Default, PartialEq)]
+pub struct PipedKeys {
+    pub buckets: Vec<(String, Vec<String>)>,
+    pub invalid: Vec<InvalidLine>,
+}
+
+/// The bucket and the key of a `s3://bucket/key` URL. The bucket is parsed
+/// like a search path, the key is the rest of the line as it is.
+pub fn parse_url(line: &str) -> Result<(String, String), String> {
+    if !line.starts_with("s3://") {
+        return Err("expected a s3://bucket/key URL".to_owned());
+    }
+    let path: S3Path = line.parse().map_err(|e: Error| e.to_string())?;
+    let key = line["s3://".len() + path.bucket.len()..]
+        .strip_prefix('/')
+        .ok_or_else(|| format!("invalid bucket in {}", line))?;
+    if key.is_empty() {
+        return Err(format!("no key in {}", line));
+    }
+    Ok((path.bucket, key.to_owned()))
+}
+
+/// Reads the URLs of the input, one per line or, with `null`, ended by a
+/// NUL byte and taken as they are; empty lines are ignored. An invalid line
+/// is kept in `invalid`, with `strict` it is an error.
+pub fn read_urls(input: impl BufRead, strict: bool, null: bool) -> Result<PipedKeys, Error> {
+    let mut keys = PipedKeys::default();
+    let mut seen = HashSet::new();
+
+    let delimiter = if null { b'\0' } else { b'\n' };
+    for (index, line) in input.split(delimiter).enumerate() {
+        let line = line?;
+        let parsed = String::from_utf8(line)
+            .map_err(|_| "not valid UTF-8".to_owned())
+            .and_then(|line| {
+                let line = match null {
+                    true => &line,
+                    false => line.trim_end_matches('\r'),
+                };
+                if line.trim().is_empty() {
+                    Ok(None)
+                } else if line.starts_with("PRE ") {
+                    Err("a common prefix of --one-level, not a key".to_owned())
+                } else {
+                    parse_url(line).map(Some)
+                }
+            });
+
+        let (bucket, key) = match parsed {
+            Ok(Some(url)) => url,
+            Ok(None) => continue,
+            Err(reason) if strict => return Err(FunctionError::StdinLine(index + 1, reason).into()),
+            Err(reason) => {
+                keys.invalid.push(InvalidLine {
+                    number: index + 1,
+                    reason,
+                });
+                continue;
+            }
+        };
+
+        if !seen.insert((bucket.clone(), key.clone())) {
+            continue;
+        }
+        match keys.buckets.iter_mut().find(|(x, _)| *x == bucket) {
+            Some((_, bucket_keys)) => bucket_keys.push(key),
+            None => keys.buckets.push((bucket, vec![key])),
+        }
+    }
+    Ok(keys)
+}
+
+/// The objects of the keys of a bucket. With `head` every key is sent a
+/// HEAD request for its size, modification time, ETag and storage class,
+/// a missing key is skipped; otherwise the objects have only their key.
+async fn piped_objects(find: &Find, keys: Vec<String>, head: bool) -> Vec<Object> {
+    if !head {
+        return keys
+            .into_iter()
+            .map(|key| Object::builder().key(key).build())
+            .collect();
+    }
+
+    let bucket = &find.path.bucket;
+    let heads: Vec<_> = futures::stream::iter(keys)
+        .map(|key| async move {
+            let head = find
+                .client
+                .head_object()
+                .bucket(bucket)
+                .key(&key)
+                .send()
+                .await;
+            (key, head)
+        })
+        .buffered(HEAD_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut objects = Vec::new();
+    for (key, head) in heads {
+        match head {
+            Ok(head) => objects.push(head_listing(key, head)),
+            Err(e) => {
+                let mut stats = CommandStats::default();
+                match e.raw_response().map(|x| x.status().as_u16()) {
+                    Some(404) => {
+                        println!(
+                            "skipped: s3://{}/{} (no such key)",
+                            bucket,
+                            display_key(&key)
+                        );
+                        stats.skipped += 1;
+                    }
+                    _ => {
+                        let error = format!(
+                            "cannot read s3://{}/{}: {}",
+                            bucket,
+                            display_key(&key),
+                            error_context(&e)
+                        );
+                        eprintln!("error: {}", error);
+                        stats.errors.push(error);
+                    }
+                }
+                find.counters.add(&stats);
+            }
+        }
+    }
+    objects
+}
+
+/// Runs the command over the piped keys bucket by bucket, every bucket is
+/// the path of the command for its keys. `--limit` counts the matched keys
+/// of all the buckets.
+pub async fn run_piped(
+    find: &mut Find,
+    filters: &FilterList<'_>,
+    keys: PipedKeys,
+    head: bool,
+    mut stats: Option<FindStat>,
+) -> Result<Option<FindStat>, Error> {
+    for line in &keys.invalid {
+        eprintln!("warning: skipped {}", line);
+    }
+
+    for (bucket, bucket_keys) in keys.buckets {
+        let limit = match find.limit {
+            Some(limit) => {
+                match limit.saturating_sub(find.counters.matched.load(Ordering::Relaxed) as usize) {
+                    0 => break,
+                    remaining => Some(remaining),
+                }
+            }
+            None => None,
+        };
+
+        find.path = S3Path {
+            bucket,
+            prefix: None,
+            region: find.path.region.clone(),
+            kind: PathKind::Prefix,
+        };
+        find.command.preflight(&find.client, &find.path).await?;
+
+        let find = &*find;
+        let objects: Vec<ListedObject> = piped_objects(find, bucket_keys, head)
+            .await
+            .into_iter()
+            .map(ListedObject::from)
+            .collect();
+        stats = list_filter_execute(
+            futures::stream::once(async { objects }),
+            limit,
+            stats,
+            |x| {
+                find.counters.scan();
+                let matched = filters.test_match(x.object.clone());
+                let identifier = find.dedup_identifier(x);
+                let counters = &find.counters;
+                async move { counters.filtered(find.admit(matched.await, identifier)) }
+            },
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg::{Cmd, FindOpt};
+    use crate::replay::*;
+    use std::io::Cursor;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("s3://bucket/logs/a b+c.log"),
+            Ok(("bucket".to_owned(), "logs/a b+c.log".to_owned()))
+        );
+        assert_eq!(
+            parse_url("s3://bucket/dir/"),
+            Ok(("bucket".to_owned(), "dir/".to_owned()))
+        );
+        assert!(parse_url("s3://bucket").is_err());
+        assert!(parse_url("s3://bucket/").is_err());
+        assert!(parse_url("bucket/key").is_err());
+        assert!(parse_url(" s3://bucket/key").is_err());
+        assert!(parse_url("s3://my.bucket/key").is_err());
+    }
+
+    #[test]
+    fn test_read_urls() -> Result<(), Error> {
+        let input = "s3://one/a\n\ns3://two/b\r\nnot a url\ns3://one/c\ns3://one/a\ns3://two/\n";
+
+        let keys = read_urls(Cursor::new(input), false, false)?;
+
+        assert_eq!(
+            keys.buckets,
+            vec![
+                ("one".to_owned(), vec!["a".to_owned(), "c".to_owned()]),
+                ("two".to_owned(), vec!["b".to_owned()]),
+            ]
+        );
+        assert_eq!(
+            keys.invalid.iter().map(|x| x.number).collect::<Vec<_>>(),
+            vec![4, 7]
+        );
+        assert_eq!(
+            keys.invalid[0].to_string(),
+            "line 4: expected a s3://bucket/key URL"
+        );
+
+        let error = read_urls(Cursor::new(input), true, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid line 4 of stdin: expected a s3://bucket/key URL"
+        );
+
+        let invalid = read_urls(Cursor::new(b"s3://one/\xff\n".to_vec()), false, false)?;
+        assert_eq!(invalid.invalid[0].reason, "not valid UTF-8");
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_urls_of_one_level_and_print0() -> Result<(), Error> {
+        let input = "PRE s3://one/dir/\ns3://one/a\n";
+        let keys = read_urls(Cursor::new(input), false, false)?;
+        assert_eq!(keys.buckets, vec![("one".to_owned(), vec!["a".to_owned()])]);
+        assert_eq!(
+            keys.invalid[0].to_string(),
+            "line 1: a common prefix of --one-level, not a key"
+        );
+
+        // NUL ended URLs keep the newlines and the spaces of their keys
+        let input = "s3://one/a\nb \0s3://one/c\r\0\0";
+        let keys = read_urls(Cursor::new(input), true, true)?;
+        assert_eq!(
+            keys.buckets,
+            vec![("one".to_owned(), vec!["a\nb ".to_owned(), "c\r".to_owned()])]
+        );
+        Ok(())
+    }
+
+    fn piped_find(client: aws_sdk_s3::Client, args: &[&str]) -> Find {
+        let mut argv = vec!["s3find", "--stdin"];
+        argv.extend(args);
+        let opts = FindOpt::from_iter_safe(argv).unwrap();
+        Find {
+            client,
+            path: opts.path.clone().unwrap_or_default(),
+            limit: opts.limit,
+            page_size: 1000,
+            prefetch: 1,
+            stats: false,
+            summarize: false,
+            command: opts.cmd.clone().unwrap_or_default().downcast(),
+            key_requests: opts.cmd.as_ref().is_some_and(Cmd::requests_keys),
+            counters: Default::default(),
+            credentials_provider: None,
+            budget: Default::default(),
+            notifiers: Vec::new(),
+            manifest: None,
+            batch_headers: None,
+            cache: None,
+            shard: None,
+            list_api: Default::default(),
+            dedup: None,
+            size_buckets: Default::default(),
+            one_level: false,
+            mtime_recheck: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_piped_delete() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(200, "<DeleteResult></DeleteResult>"),
+            ReplayResponse::new(200, "<DeleteResult></DeleteResult>"),
+        ]);
+        let mut find = piped_find(client, &["delete", "--quiet-versioning-warning"]);
+        let filters = FilterList(Vec::new());
+        let input =
+            "s3://logs/app/1.log\ns3://logs/app/2.log\nlogs/3.log\ns3://archive/old/1.log\n";
+        let keys = read_urls(Cursor::new(input), false, false)?;
+
+        run_piped(&mut find, &filters, keys, false, None).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|x| x.method == "POST"));
+        assert!(requests[0].uri.starts_with("https://logs.s3."));
+        let body = String::from_utf8(requests[0].body.clone())?;
+        assert!(body.contains("<Key>app/1.log</Key>"));
+        assert!(body.contains("<Key>app/2.log</Key>"));
+        assert!(!body.contains("old/1.log"));
+        assert!(requests[1].uri.starts_with("https://archive.s3."));
+        let body = String::from_utf8(requests[1].body.clone())?;
+        assert!(body.contains("<Key>old/1.log</Key>"));
+        assert!(!body.contains("app/"));
+        assert_eq!(find.counters.matched.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_piped_head_for_filters() -> Result<(), Error> {
+        let head = |size: &str| ReplayResponse {
+            headers: vec![
+                ("Content-Length".to_owned(), size.to_owned()),
+                ("ETag".to_owned(), "\"e\"".to_owned()),
+            ],
+            ..ReplayResponse::new(200, "")
+        };
+        let (client, http_client) = replay_client(vec![
+            head("10"),
+            ReplayResponse::new(404, ""),
+            head("5000"),
+            ReplayResponse::new(200, "<DeleteResult></DeleteResult>"),
+        ]);
+        let opts = FindOpt::from_iter_safe(["s3find", "--stdin", "--size", "+1k", "delete"])?;
+        let filters = FilterList::from_opts(&opts)?;
+        let mut find = piped_find(client, &["delete", "--quiet-versioning-warning"]);
+        let keys = read_urls(
+            Cursor::new("s3://b/small\ns3://b/gone\ns3://b/large\n"),
+            false,
+            false,
+        )?;
+
+        assert!(filters.reads_metadata());
+        run_piped(&mut find, &filters, keys, true, None).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[..3].iter().all(|x| x.method == "HEAD"));
+        let body = String::from_utf8(requests[3].body.clone())?;
+        assert!(body.contains("<Key>large</Key>"));
+        assert!(!body.contains("small"));
+        assert_eq!(find.counters.matched.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+}
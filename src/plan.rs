@@ -0,0 +1,569 @@
+use std::fmt;
+
+use crate::arg::*;
+use crate::command::FilterList;
+use crate::error::FunctionError;
+
+const OBJECTS_PER_ESTIMATE: u64 = 1000;
+
+/// How often a command issues a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestRate {
+    /// Once per run, before the listing
+    Once,
+    /// Once per page of matched objects
+    PerPage,
+    /// Once per matched object
+    PerObject,
+    /// For the matched objects the command has to change only
+    AtMostPerObject,
+}
+
+impl RequestRate {
+    /// Requests per 1000 matched objects listed in pages of `page_size`
+    pub fn per_thousand(self, page_size: i64) -> u64 {
+        match self {
+            RequestRate::Once => 1,
+            RequestRate::PerPage => pages(page_size),
+            RequestRate::PerObject | RequestRate::AtMostPerObject => OBJECTS_PER_ESTIMATE,
+        }
+    }
+}
+
+fn pages(page_size: i64) -> u64 {
+    let page_size = page_size.max(1) as u64;
+    OBJECTS_PER_ESTIMATE.div_ceil(page_size)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listing {
+    Objects {
+        bucket: String,
+        prefix: String,
+        page_size: i64,
+        fetch_owner: bool,
+        list_api: ListApi,
+        /// --one-level, a delimited listing of the path only
+        one_level: bool,
+    },
+    Version {
+        bucket: String,
+        key: String,
+        version_id: String,
+    },
+    Uploads {
+        bucket: String,
+        prefix: String,
+    },
+    /// The keys of the s3:// URLs of --stdin, with a HEAD request each
+    /// when a filter or the command reads more than the key
+    Stdin {
+        head_object: bool,
+    },
+}
+
+/// What an invocation would do, built from the options without any request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub listing: Listing,
+    pub filters: Vec<String>,
+    /// A first listing pass computes the percentile cutoffs
+    pub percentile_pass: bool,
+    pub command: &'static str,
+    pub mutates: bool,
+    pub requests: Vec<(&'static str, RequestRate)>,
+    pub safety: Vec<String>,
+}
+
+impl Plan {
+    pub fn new(opts: &FindOpt) -> Result<Plan, FunctionError> {
+        let cmd = opts
+            .cmd
+            .clone()
+            .unwrap_or_default()
+            .with_version_id(opts.version_id.clone());
+
+        let path = opts.path.clone().unwrap_or_default();
+        let listing = match (&opts.version_id, &cmd) {
+            _ if opts.stdin => Listing::Stdin {
+                head_object: !cmd.key_only() || FilterList::from_opts(opts)?.reads_metadata(),
+            },
+            (Some(version_id), _) => Listing::Version {
+                bucket: path.bucket,
+                key: path.prefix.unwrap_or_default(),
+                version_id: version_id.clone(),
+            },
+            (None, Cmd::ListMultipart(_)) => Listing::Uploads {
+                bucket: path.bucket,
+                prefix: path.prefix.unwrap_or_default(),
+            },
+            (None, _) => Listing::Objects {
+                bucket: path.bucket,
+                prefix: path.prefix.unwrap_or_default(),
+                page_size: opts.page_size,
+                fetch_owner: cmd.clone().downcast().fetch_owner(),
+                list_api: opts.list_api,
+                one_level: opts.one_level,
+            },
+        };
+
+        let mut filters: Vec<_> = FilterList::from_opts(opts)?
+            .0
+            .iter()
+            .map(|filter| filter.filter.describe())
+            .collect();
+        if let (Some(shard), true) = (opts.shard, opts.shard_by_range) {
+            filters.push(format!(
+                "keys of the range of shard {}, split by a delimited listing of the top level before the run",
+                shard
+            ));
+        }
+        if let Some(percent) = opts.oldest_percent {
+            filters.push(format!(
+                "oldest {}% by modification time, cutoff from the first pass",
+                percent
+            ));
+        }
+        if let Some(percent) = opts.largest_percent {
+            filters.push(format!(
+                "largest {}% by size, cutoff from the first pass",
+                percent
+            ));
+        }
+
+        Ok(Plan {
+            listing,
+            filters,
+            percentile_pass: opts.oldest_percent.is_some() || opts.largest_percent.is_some(),
+            command: cmd.name(),
+            mutates: cmd.mutates(),
+            requests: cmd.requests(),
+            safety: safety(opts, &cmd),
+        })
+    }
+
+    fn page_size(&self) -> i64 {
+        match self.listing {
+            Listing::Objects { page_size, .. } => page_size,
+            Listing::Version { .. } => 1,
+            Listing::Uploads { .. } | Listing::Stdin { .. } => OBJECTS_PER_ESTIMATE as i64,
+        }
+    }
+}
+
+fn safety(opts: &FindOpt, cmd: &Cmd) -> Vec<String> {
+    let mut safety = Vec::new();
+
+    if let Some(limit) = opts.limit {
+        safety.push(format!("--limit: stops after {} matched objects", limit));
+    }
+    if let Some(max_requests) = opts.max_requests {
+        safety.push(format!(
+            "--max-requests: stops after {} requests",
+            max_requests
+        ));
+    }
+    if let Some(max_cost) = opts.max_cost_usd {
+        safety.push(format!("--max-cost-usd: stops at ${:.2}", max_cost));
+    }
+    if let Some(max_duration) = opts.max_duration {
+        safety.push(format!(
+            "--max-duration: stops listing {}s after the launch",
+            max_duration.0.as_secs()
+        ));
+    }
+    if let Some(rate) = opts.sample_rate {
+        safety.push(format!("--sample-rate: acts on {} of the keys", rate));
+    }
+
+    match cmd {
+        Cmd::Delete(MultipleDelete {
+            if_unmodified: true,
+            ..
+        })
+        | Cmd::Copy(S3Copy {
+            if_unmodified: true,
+            ..
+        })
+        | Cmd::Move(S3Move {
+            if_unmodified: true,
+            ..
+        })
+        | Cmd::Trash(Trash {
+            if_unmodified: true,
+            ..
+        }) => safety.push("--if-unmodified: skips objects changed since the listing".to_owned()),
+        Cmd::Delete(MultipleDelete {
+            snapshot_guard: true,
+            ..
+        }) => safety
+            .push("--snapshot-guard: deletes a key only while it has its listed ETag".to_owned()),
+        Cmd::EnforceStorage(EnforceStorage { apply: false, .. }) => {
+            safety.push("report only: --apply is not set".to_owned())
+        }
+        Cmd::SuggestLifecycle(SuggestLifecycle { apply: false, .. }) => {
+            safety.push("report only: --apply is not set".to_owned())
+        }
+        Cmd::SuggestLifecycle(SuggestLifecycle { apply: true, .. }) => safety.push(
+            "--apply: asks for a confirmation before replacing the lifecycle configuration"
+                .to_owned(),
+        ),
+        Cmd::ListMultipart(ListMultipart { abort: false }) => {
+            safety.push("report only: --abort is not set".to_owned())
+        }
+        Cmd::PlanCopy(_) => {
+            safety.push("plan only: prints the target keys, nothing is copied".to_owned())
+        }
+        Cmd::Public(_) => safety.push(
+            "public access block: no ACL is set when the bucket blocks public ACLs".to_owned(),
+        ),
+        Cmd::Recompress(_) => safety.push("keys already encoded with gzip are skipped".to_owned()),
+        Cmd::Touch(_) => safety.push(
+            "keys changed since their HEAD are skipped; object ACLs are not copied".to_owned(),
+        ),
+        Cmd::Download(Download { force: false, .. }) => {
+            safety.push("existing files are kept: --force is not set".to_owned())
+        }
+        _ => (),
+    }
+
+    safety
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Listing")?;
+        match &self.listing {
+            Listing::Objects {
+                bucket,
+                prefix,
+                page_size,
+                fetch_owner,
+                list_api,
+                one_level,
+            } => {
+                let delimiter = match one_level {
+                    true => "\"/\"",
+                    false => "none",
+                };
+                match list_api {
+                    ListApi::V1 => writeln!(
+                        f,
+                        "  ListObjects bucket={} prefix={:?} delimiter={} marker=none max-keys={}",
+                        bucket, prefix, delimiter, page_size
+                    )?,
+                    _ => writeln!(
+                        f,
+                        "  ListObjectsV2 bucket={} prefix={:?} delimiter={} start-after=none max-keys={} fetch-owner={}",
+                        bucket, prefix, delimiter, page_size, fetch_owner
+                    )?,
+                }
+                if *one_level {
+                    writeln!(
+                        f,
+                        "  one level: the keys directly under the prefix, the prefixes below are not listed"
+                    )?;
+                }
+                if *list_api == ListApi::Auto {
+                    writeln!(
+                        f,
+                        "  ListObjects instead when the endpoint does not implement ListObjectsV2"
+                    )?;
+                }
+                writeln!(
+                    f,
+                    "  ~{} requests per {} listed objects",
+                    pages(*page_size),
+                    OBJECTS_PER_ESTIMATE
+                )?;
+                if self.percentile_pass {
+                    writeln!(
+                        f,
+                        "  listed twice: a first pass computes the percentile cutoffs, ~{} requests per {} listed objects in total",
+                        2 * pages(*page_size),
+                        OBJECTS_PER_ESTIMATE
+                    )?;
+                }
+            }
+            Listing::Version {
+                bucket,
+                key,
+                version_id,
+            } => {
+                writeln!(
+                    f,
+                    "  HeadObject bucket={} key={:?} version-id={}, no listing",
+                    bucket, key, version_id
+                )?;
+            }
+            Listing::Uploads { bucket, prefix } => {
+                writeln!(
+                    f,
+                    "  ListMultipartUploads bucket={} prefix={:?} max-uploads=1000, no object listing",
+                    bucket, prefix
+                )?;
+            }
+            Listing::Stdin { head_object } => {
+                writeln!(f, "  s3:// URLs read from stdin, one per line, no listing")?;
+                if *head_object {
+                    writeln!(
+                        f,
+                        "  HeadObject per key, a filter or the command reads more than the key"
+                    )?;
+                }
+            }
+        }
+
+        writeln!(f, "Filters (client-side, no extra requests)")?;
+        if self.filters.is_empty() {
+            writeln!(f, "  none, every listed object matches")?;
+        }
+        for (n, filter) in self.filters.iter().enumerate() {
+            writeln!(f, "  {}. {}", n + 1, filter)?;
+        }
+
+        writeln!(f, "Command")?;
+        writeln!(
+            f,
+            "  {}, {}",
+            self.command,
+            if self.mutates {
+                "mutates objects"
+            } else {
+                "read-only"
+            }
+        )?;
+        if self.requests.is_empty() {
+            writeln!(f, "  no requests besides the listing")?;
+        }
+        for (operation, rate) in &self.requests {
+            let estimate = rate.per_thousand(self.page_size());
+            match rate {
+                RequestRate::Once => writeln!(f, "  {}: once", operation)?,
+                RequestRate::AtMostPerObject => writeln!(
+                    f,
+                    "  {}: up to {} per {} matched objects",
+                    operation, estimate, OBJECTS_PER_ESTIMATE
+                )?,
+                _ => writeln!(
+                    f,
+                    "  {}: ~{} per {} matched objects",
+                    operation, estimate, OBJECTS_PER_ESTIMATE
+                )?,
+            }
+        }
+
+        writeln!(f, "Safety")?;
+        if self.safety.is_empty() {
+            writeln!(f, "  none")?;
+        }
+        for line in &self.safety {
+            writeln!(f, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn plan(args: &[&str]) -> Plan {
+        let mut argv = vec!["s3find"];
+        argv.extend_from_slice(args);
+        Plan::new(&FindOpt::from_iter_safe(argv).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_listing_plan() {
+        let plan = plan(&["s3://bucket/logs/", "--page-size", "250"]);
+
+        assert_eq!(
+            plan.listing,
+            Listing::Objects {
+                bucket: "bucket".to_owned(),
+                prefix: "logs/".to_owned(),
+                page_size: 250,
+                fetch_owner: false,
+                list_api: ListApi::Auto,
+                one_level: false,
+            }
+        );
+        assert!(plan.filters.is_empty());
+        assert_eq!(plan.command, "ls");
+        assert!(!plan.mutates);
+        assert!(plan.requests.is_empty());
+        assert!(plan.safety.is_empty());
+
+        let text = plan.to_string();
+        assert!(text.contains("max-keys=250"));
+        assert!(text.contains("~4 requests per 1000 listed objects"));
+        assert!(text.contains("none, every listed object matches"));
+        assert!(text.contains("ls, read-only"));
+        assert!(text.contains("ListObjects instead when the endpoint does not implement"));
+    }
+
+    #[test]
+    fn test_list_api_plan() {
+        let text = plan(&["s3://bucket/logs/", "--list-api", "v1"]).to_string();
+        assert!(
+            text.contains("ListObjects bucket=bucket prefix=\"logs/\" delimiter=none marker=none")
+        );
+        assert!(!text.contains("ListObjectsV2"));
+
+        let text = plan(&["s3://bucket/logs/", "--list-api", "v2"]).to_string();
+        assert!(!text.contains("ListObjects instead"));
+    }
+
+    #[test]
+    fn test_one_level_plan() {
+        let text = plan(&["s3://bucket/logs/", "--one-level"]).to_string();
+        assert!(text.contains("ListObjectsV2 bucket=bucket prefix=\"logs/\" delimiter=\"/\""));
+        assert!(text.contains("one level: the keys directly under the prefix"));
+
+        let text = plan(&["s3://bucket/logs/"]).to_string();
+        assert!(!text.contains("one level"));
+    }
+
+    #[test]
+    fn test_delete_plan() {
+        let plan = plan(&[
+            "s3://bucket/logs/",
+            "--name",
+            "*.log",
+            "--size",
+            "+1k",
+            "--sample-rate",
+            "0.5",
+            "--limit",
+            "10",
+            "delete",
+            "--if-unmodified",
+        ]);
+
+        assert_eq!(
+            plan.filters,
+            vec![
+                "size >= 1024 bytes",
+                "sample of 0.5 of the keys, seed 0",
+                "name matches *.log",
+            ]
+        );
+        assert!(plan.mutates);
+        assert_eq!(
+            plan.requests,
+            vec![
+                ("GetBucketVersioning", RequestRate::Once),
+                ("HeadObject", RequestRate::PerObject),
+                ("DeleteObjects", RequestRate::PerPage),
+            ]
+        );
+        assert_eq!(
+            plan.safety,
+            vec![
+                "--limit: stops after 10 matched objects",
+                "--sample-rate: acts on 0.5 of the keys",
+                "--if-unmodified: skips objects changed since the listing",
+            ]
+        );
+
+        let text = plan.to_string();
+        assert!(text.contains("delete, mutates objects"));
+        assert!(text.contains("HeadObject: ~1000 per 1000 matched objects"));
+        assert!(text.contains("DeleteObjects: ~1 per 1000 matched objects"));
+    }
+
+    #[test]
+    fn test_version_plan() {
+        let plan = plan(&[
+            "s3://bucket/report.csv",
+            "--version-id",
+            "v1",
+            "--where",
+            "not (size=+10 or storage_class=GLACIER)",
+            "lstags",
+        ]);
+
+        assert_eq!(
+            plan.listing,
+            Listing::Version {
+                bucket: "bucket".to_owned(),
+                key: "report.csv".to_owned(),
+                version_id: "v1".to_owned(),
+            }
+        );
+        assert_eq!(
+            plan.filters,
+            vec!["not (size >= 10 bytes or storage class = GLACIER)"]
+        );
+        assert_eq!(
+            plan.requests,
+            vec![("GetObjectTagging", RequestRate::PerObject)]
+        );
+        assert!(plan.to_string().contains("no listing"));
+    }
+
+    #[test]
+    fn test_percentile_plan() {
+        let plan = plan(&["s3://bucket/logs/", "--oldest-percent", "10"]);
+
+        assert!(plan.percentile_pass);
+        assert_eq!(
+            plan.filters,
+            vec!["oldest 10% by modification time, cutoff from the first pass"]
+        );
+        assert!(plan
+            .to_string()
+            .contains("listed twice: a first pass computes the percentile cutoffs, ~2 requests"));
+    }
+
+    #[test]
+    fn test_report_only_plan() {
+        let plan = plan(&[
+            "s3://bucket",
+            "enforce-storage",
+            "--rule",
+            "*.log=GLACIER@30d",
+        ]);
+
+        assert!(!plan.mutates);
+        assert!(plan.requests.is_empty());
+        assert_eq!(plan.safety, vec!["report only: --apply is not set"]);
+    }
+
+    #[test]
+    fn test_stdin_plan() {
+        let keys = plan(&["--stdin", "delete"]);
+        assert_eq!(keys.listing, Listing::Stdin { head_object: false });
+        assert!(keys.to_string().contains("read from stdin"));
+
+        let sized = plan(&["--stdin", "--size", "+1k", "delete"]);
+        assert_eq!(sized.listing, Listing::Stdin { head_object: true });
+        assert!(sized.to_string().contains("HeadObject per key"));
+    }
+
+    #[test]
+    fn test_multipart_plan() {
+        let plan = plan(&["s3://bucket/tmp/", "list-multipart", "--abort"]);
+
+        assert_eq!(
+            plan.listing,
+            Listing::Uploads {
+                bucket: "bucket".to_owned(),
+                prefix: "tmp/".to_owned(),
+            }
+        );
+        assert!(plan.mutates);
+        assert_eq!(
+            plan.requests,
+            vec![
+                ("ListParts", RequestRate::PerObject),
+                ("AbortMultipartUpload", RequestRate::PerObject),
+            ]
+        );
+        assert!(plan
+            .to_string()
+            .contains("ListMultipartUploads bucket=bucket"));
+    }
+}
@@ -0,0 +1,330 @@
+//! Progress events for a supervising process, --progress-fd and
+//! --progress-socket. Every event is a JSON object on its own line:
+//!
+//! ```json
+//! {"schema":"s3find.progress/1","event":"progress","elapsed_secs":5.0,"pages":3,"scanned":3000,...}
+//! ```
+//!
+//! `progress` events follow the listing at most once per interval, a last
+//! `summary` event ends the run. The counters are the ones of --summarize and
+//! never decrease between two events. A failed write stops the events and
+//! never the run.
+
+use std::io::{self, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use crate::arg::FindOpt;
+use crate::command::RunCounters;
+
+/// The schema of the events, bumped when a field changes meaning or goes away
+pub const PROGRESS_SCHEMA: &str = "s3find.progress/1";
+
+/// How long a write to a socket may block the run
+#[cfg(unix)]
+const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Destination of the progress events
+pub trait ProgressEmitter: Send {
+    fn emit(&mut self, line: &str) -> io::Result<()>;
+}
+
+/// A file descriptor inherited from the supervisor, left open at the end
+#[cfg(unix)]
+pub struct FdEmitter(std::mem::ManuallyDrop<std::fs::File>);
+
+#[cfg(unix)]
+impl FdEmitter {
+    pub fn new(fd: i32) -> Self {
+        use std::os::unix::io::FromRawFd;
+
+        // the supervisor owns the descriptor, a closed one fails the first
+        // write and stops the events
+        FdEmitter(std::mem::ManuallyDrop::new(unsafe {
+            std::fs::File::from_raw_fd(fd)
+        }))
+    }
+}
+
+#[cfg(unix)]
+impl ProgressEmitter for FdEmitter {
+    fn emit(&mut self, line: &str) -> io::Result<()> {
+        writeln!(*self.0, "{}", line)?;
+        self.0.flush()
+    }
+}
+
+/// A Unix socket the supervisor listens on
+#[cfg(unix)]
+pub struct SocketEmitter(std::os::unix::net::UnixStream);
+
+#[cfg(unix)]
+impl SocketEmitter {
+    pub fn connect(path: &std::path::Path) -> io::Result<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        Ok(SocketEmitter::new(stream))
+    }
+
+    pub fn new(stream: std::os::unix::net::UnixStream) -> Self {
+        // a stalled supervisor fails the write instead of stopping the run
+        let _ = stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT));
+        SocketEmitter(stream)
+    }
+}
+
+#[cfg(unix)]
+impl ProgressEmitter for SocketEmitter {
+    fn emit(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.0, "{}", line)
+    }
+}
+
+/// The emitter of --progress-fd or --progress-socket
+#[cfg(unix)]
+pub fn progress_emitter(opts: &FindOpt) -> Result<Option<Box<dyn ProgressEmitter>>, Error> {
+    if let Some(fd) = opts.progress_fd {
+        return Ok(Some(Box::new(FdEmitter::new(fd))));
+    }
+    match &opts.progress_socket {
+        Some(path) => Ok(Some(Box::new(SocketEmitter::connect(path)?))),
+        None => Ok(None),
+    }
+}
+
+/// The emitter of --progress-fd or --progress-socket
+#[cfg(not(unix))]
+pub fn progress_emitter(opts: &FindOpt) -> Result<Option<Box<dyn ProgressEmitter>>, Error> {
+    if opts.progress_fd.is_some() || opts.progress_socket.is_some() {
+        anyhow::bail!("--progress-fd and --progress-socket are only supported on Unix");
+    }
+    Ok(None)
+}
+
+#[derive(Default)]
+struct ReporterState {
+    emitter: Option<Box<dyn ProgressEmitter>>,
+    pages: u64,
+    last: Option<Instant>,
+}
+
+/// Sends the run counters to the emitter, at most once per interval
+pub struct ProgressReporter {
+    interval: Duration,
+    start: Instant,
+    state: Mutex<ReporterState>,
+}
+
+impl ProgressReporter {
+    pub fn new(emitter: Option<Box<dyn ProgressEmitter>>, interval: Duration) -> Self {
+        ProgressReporter {
+            interval,
+            start: Instant::now(),
+            state: Mutex::new(ReporterState {
+                emitter,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Counts a listed page, then reports like `tick`
+    pub fn page(&self, counters: &RunCounters) {
+        self.state.lock().unwrap().pages += 1;
+        self.tick(counters);
+    }
+
+    /// Emits a progress event when the interval passed since the last one
+    pub fn tick(&self, counters: &RunCounters) {
+        let mut state = self.state.lock().unwrap();
+        if state.emitter.is_none() || state.last.is_some_and(|x| x.elapsed() < self.interval) {
+            return;
+        }
+        state.last = Some(Instant::now());
+        self.send(&mut state, "progress", counters);
+    }
+
+    /// Emits the final summary event of the run
+    pub fn summary(&self, counters: &RunCounters) {
+        let mut state = self.state.lock().unwrap();
+        self.send(&mut state, "summary", counters);
+    }
+
+    fn send(&self, state: &mut ReporterState, kind: &str, counters: &RunCounters) {
+        let line = self.event(kind, state.pages, counters).to_string();
+        if let Some(emitter) = &mut state.emitter {
+            if let Err(e) = emitter.emit(&line) {
+                eprintln!("warning: progress events stopped: {}", e);
+                state.emitter = None;
+            }
+        }
+    }
+
+    fn event(&self, kind: &str, pages: u64, counters: &RunCounters) -> Value {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let scanned = counters.scanned.load(Ordering::Relaxed);
+        let rate = if elapsed > 0.0 {
+            scanned as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        json!({
+            "schema": PROGRESS_SCHEMA,
+            "event": kind,
+            "elapsed_secs": elapsed,
+            "pages": pages,
+            "scanned": scanned,
+            "matched": counters.matched.load(Ordering::Relaxed),
+            "processed": counters.processed.load(Ordering::Relaxed),
+            "deleted": counters.deleted.load(Ordering::Relaxed),
+            "deleted_bytes": counters.reclaimed_bytes.load(Ordering::Relaxed),
+            "skipped": counters.skipped.load(Ordering::Relaxed),
+            "errors": counters.error_count.load(Ordering::Relaxed),
+            "requests": counters.requests.load(Ordering::Relaxed),
+            "scanned_per_sec": rate,
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::arg::Cmd;
+    use crate::command::Find;
+    use crate::replay::*;
+    use crate::run::list_filter_execute;
+    use aws_sdk_s3::Client;
+    use futures::StreamExt;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use structopt::StructOpt;
+
+    fn test_find(client: Client) -> Find {
+        let opts = FindOpt::from_iter_safe(["s3find", "s3://test/", "nothing"]).unwrap();
+        Find {
+            client,
+            path: opts.path.clone().unwrap(),
+            limit: None,
+            page_size: 1000,
+            prefetch: 1,
+            stats: false,
+            summarize: false,
+            command: opts.cmd.clone().unwrap_or_default().downcast(),
+            key_requests: opts.cmd.as_ref().is_some_and(Cmd::requests_keys),
+            counters: Default::default(),
+            credentials_provider: None,
+            budget: Default::default(),
+            notifiers: Vec::new(),
+            manifest: None,
+            batch_headers: None,
+            cache: None,
+            shard: None,
+            list_api: Default::default(),
+            dedup: None,
+            size_buckets: Default::default(),
+            one_level: false,
+            mtime_recheck: None,
+        }
+    }
+
+    fn keys_page(keys: &[&str], token: Option<&str>) -> ReplayResponse {
+        let contents: String = keys
+            .iter()
+            .map(|key| format!("<Contents><Key>{}</Key><Size>1</Size></Contents>", key))
+            .collect();
+        ReplayResponse::new(
+            200,
+            &format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>"#,
+                token.is_some(),
+                token
+                    .map(|x| format!("<NextContinuationToken>{}</NextContinuationToken>", x))
+                    .unwrap_or_default(),
+                contents
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_progress_events() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![
+            keys_page(&["a/1", "a/2", "b/1"], Some("t1")),
+            keys_page(&["b/2", "c/1"], Some("t2")),
+            keys_page(&["c/2"], None),
+        ]);
+        let find = test_find(client);
+        let (writer, reader) = UnixStream::pair()?;
+        let reporter =
+            ProgressReporter::new(Some(Box::new(SocketEmitter::new(writer))), Duration::ZERO);
+
+        list_filter_execute(
+            find.to_stream()
+                .stream()
+                .inspect(|_| reporter.page(&find.counters)),
+            None,
+            None,
+            |x| {
+                find.counters.scan();
+                let matched = x.key.as_deref().is_some_and(|x| x.starts_with("b/"));
+                let matched = find.counters.filtered(matched);
+                async move { matched }
+            },
+            &mut |acc, x| find.exec(acc, x),
+        )
+        .await;
+        reporter.summary(&find.counters);
+        drop(reporter);
+
+        let events: Vec<Value> = BufReader::new(reader)
+            .lines()
+            .map(|x| serde_json::from_str(&x.unwrap()).unwrap())
+            .collect();
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|x| x["schema"] == PROGRESS_SCHEMA));
+        assert!(events[..3].iter().all(|x| x["event"] == "progress"));
+        for (previous, next) in events.iter().zip(&events[1..]) {
+            for field in ["pages", "scanned", "matched", "processed"] {
+                assert!(
+                    previous[field].as_u64() <= next[field].as_u64(),
+                    "{}",
+                    field
+                );
+            }
+        }
+
+        let summary = &events[3];
+        assert_eq!(summary["event"], "summary");
+        assert_eq!(summary["pages"], 3);
+        assert_eq!(summary["scanned"], 6);
+        assert_eq!(summary["matched"], 2);
+        assert_eq!(summary["errors"], 0);
+        Ok(())
+    }
+
+    struct Failing(u32);
+
+    impl ProgressEmitter for Failing {
+        fn emit(&mut self, _: &str) -> io::Result<()> {
+            self.0 += 1;
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+    }
+
+    #[test]
+    fn test_failed_write_stops_events() {
+        let reporter = ProgressReporter::new(Some(Box::new(Failing(0))), Duration::ZERO);
+        let counters = RunCounters::default();
+
+        reporter.page(&counters);
+        reporter.page(&counters);
+        reporter.summary(&counters);
+
+        let state = reporter.state.lock().unwrap();
+        assert!(state.emitter.is_none());
+        assert_eq!(state.pages, 2);
+    }
+}
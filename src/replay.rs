@@ -0,0 +1,273 @@
+//! Canned S3 responses for tests: the client answers requests in order and
+//! records what was sent, so tests can assert the issued requests.
+
+use std::collections::VecDeque;
+use std::convert::{Infallible, TryInto};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::config::http::{HttpRequest, HttpResponse};
+use aws_sdk_s3::config::{Credentials, Region, RuntimeComponents};
+use aws_sdk_s3::Client;
+use aws_smithy_runtime_api::client::http::{
+    HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpConnector,
+};
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::retry::RetryConfig;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+
+use crate::arg::NotifyUrl;
+
+#[derive(Debug, Clone)]
+pub struct ReplayResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// The request times out instead of getting the response
+    pub timeout: bool,
+}
+
+impl ReplayResponse {
+    pub fn new(status: u16, body: &str) -> Self {
+        ReplayResponse {
+            status,
+            headers: Vec::new(),
+            body: body.to_owned(),
+            timeout: false,
+        }
+    }
+
+    /// A request which times out before any response
+    pub fn timeout() -> Self {
+        ReplayResponse {
+            timeout: true,
+            ..ReplayResponse::new(0, "")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    /// Request uri without the query string
+    pub fn path(&self) -> &str {
+        self.uri.split('?').next().unwrap_or_default()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayClient {
+    responses: Arc<Mutex<VecDeque<ReplayResponse>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    delay: Option<Duration>,
+    /// The delays of the requests by the end of their path
+    path_delays: Vec<(String, Duration)>,
+}
+
+impl ReplayClient {
+    pub fn new(responses: Vec<ReplayResponse>) -> Self {
+        ReplayClient {
+            responses: Arc::new(Mutex::new(responses.into())),
+            requests: Default::default(),
+            delay: None,
+            path_delays: Vec::new(),
+        }
+    }
+
+    /// Every response is sent after the delay, like a slow network
+    pub fn with_delay(self, delay: Duration) -> Self {
+        ReplayClient {
+            delay: Some(delay),
+            ..self
+        }
+    }
+
+    /// The response to a request whose path ends with one of the paths is
+    /// sent after its delay, like the transfer of a larger object
+    pub fn with_path_delays(self, path_delays: Vec<(String, Duration)>) -> Self {
+        ReplayClient {
+            path_delays,
+            ..self
+        }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl HttpConnector for ReplayClient {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let request_path = request.uri().split('?').next().unwrap_or_default();
+        let delay = self
+            .path_delays
+            .iter()
+            .find(|(path, _)| request_path.ends_with(path.as_str()))
+            .map(|(_, delay)| *delay)
+            .or(self.delay);
+        self.requests.lock().unwrap().push(RecordedRequest {
+            method: request.method().to_owned(),
+            uri: request.uri().to_owned(),
+            headers: request
+                .headers()
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            body: request.body().bytes().unwrap_or_default().to_vec(),
+        });
+
+        let result = match self.responses.lock().unwrap().pop_front() {
+            Some(replay) if replay.timeout => {
+                Err(ConnectorError::timeout("ReplayClient: timed out".into()))
+            }
+            Some(replay) => {
+                let mut response = HttpResponse::new(
+                    replay.status.try_into().unwrap(),
+                    SdkBody::from(replay.body),
+                );
+                for (name, value) in replay.headers {
+                    response.headers_mut().insert(name, value);
+                }
+                Ok(response)
+            }
+            None => Err(ConnectorError::other(
+                "ReplayClient: no more responses".into(),
+                None,
+            )),
+        };
+
+        match delay {
+            Some(delay) => HttpConnectorFuture::new(async move {
+                tokio::time::sleep(delay).await;
+                result
+            }),
+            None => HttpConnectorFuture::ready(result),
+        }
+    }
+}
+
+impl HttpClient for ReplayClient {
+    fn http_connector(
+        &self,
+        _settings: &HttpConnectorSettings,
+        _components: &RuntimeComponents,
+    ) -> SharedHttpConnector {
+        SharedHttpConnector::new(self.clone())
+    }
+}
+
+pub fn replay_client(responses: Vec<ReplayResponse>) -> (Client, ReplayClient) {
+    client_with(ReplayClient::new(responses))
+}
+
+/// Replay client answering every request after the delay
+pub fn delayed_replay_client(
+    responses: Vec<ReplayResponse>,
+    delay: Duration,
+) -> (Client, ReplayClient) {
+    client_with(ReplayClient::new(responses).with_delay(delay))
+}
+
+/// Replay client answering a request whose path ends with one of the paths
+/// after its delay
+pub fn path_delayed_replay_client(
+    responses: Vec<ReplayResponse>,
+    path_delays: Vec<(String, Duration)>,
+) -> (Client, ReplayClient) {
+    client_with(ReplayClient::new(responses).with_path_delays(path_delays))
+}
+
+fn client_with(http_client: ReplayClient) -> (Client, ReplayClient) {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .credentials_provider(Credentials::for_tests())
+        .region(Region::from_static("us-east-1"))
+        .retry_config(RetryConfig::disabled())
+        .http_client(http_client.clone())
+        .build();
+    (Client::from_conf(config), http_client)
+}
+
+/// CloudWatch client answering the requests with the responses in order
+#[cfg(feature = "cloudwatch")]
+pub fn cloudwatch_replay_client(
+    responses: Vec<ReplayResponse>,
+) -> (aws_sdk_cloudwatch::Client, ReplayClient) {
+    let http_client = ReplayClient::new(responses);
+    let config = aws_sdk_cloudwatch::Config::builder()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .credentials_provider(Credentials::for_tests())
+        .region(Region::from_static("us-east-1"))
+        .retry_config(RetryConfig::disabled())
+        .http_client(http_client.clone())
+        .build();
+    (aws_sdk_cloudwatch::Client::from_conf(config), http_client)
+}
+
+/// SNS client answering the requests with the responses in order
+#[cfg(feature = "sns")]
+pub fn sns_replay_client(responses: Vec<ReplayResponse>) -> (aws_sdk_sns::Client, ReplayClient) {
+    let http_client = ReplayClient::new(responses);
+    let config = aws_sdk_sns::Config::builder()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .credentials_provider(Credentials::for_tests())
+        .region(Region::from_static("us-east-1"))
+        .retry_config(RetryConfig::disabled())
+        .http_client(http_client.clone())
+        .build();
+    (aws_sdk_sns::Client::from_conf(config), http_client)
+}
+
+/// Local webhook answering with the statuses in order and 200 afterwards,
+/// the posted bodies are recorded
+pub fn webhook_server(statuses: Vec<u16>) -> (NotifyUrl, Arc<Mutex<Vec<String>>>) {
+    let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+    let bodies: Arc<Mutex<Vec<String>>> = Default::default();
+
+    let recorded = bodies.clone();
+    let make_service = make_service_fn(move |_| {
+        let statuses = statuses.clone();
+        let recorded = recorded.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let statuses = statuses.clone();
+                let recorded = recorded.clone();
+                async move {
+                    let body = hyper::body::to_bytes(request.into_body()).await?;
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8_lossy(&body).into_owned());
+                    let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+                    Ok::<_, hyper::Error>(
+                        Response::builder()
+                            .status(status)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = NotifyUrl(format!("http://{}/hook", server.local_addr()));
+    tokio::spawn(server);
+    (url, bodies)
+}
@@ -0,0 +1,371 @@
+use std::fs;
+use std::sync::atomic::Ordering;
+
+use anyhow::Error;
+use humansize::*;
+
+use crate::arg::{ReportFile, ReportFormat};
+use crate::command::{FilterSelectivity, FindStat, RunCounters};
+use crate::metrics::RunMetrics;
+
+const REDACTED: &str = "<redacted>";
+const SECRET_ARGUMENTS: &[&str] = &["--aws-secret-key"];
+
+/// Summary of a run rendered into the --report-file
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunReport {
+    pub metrics: RunMetrics,
+    pub stats: Option<FindStat>,
+    pub processed: u64,
+    pub reclaimed_bytes: u64,
+    pub skipped: u64,
+    pub error_count: u64,
+    pub errors: Vec<String>,
+    pub arguments: Vec<String>,
+    pub filters: Vec<FilterSelectivity>,
+}
+
+type Section = (&'static str, Vec<(&'static str, String)>);
+
+impl RunReport {
+    pub fn new<I: IntoIterator<Item = String>>(
+        metrics: RunMetrics,
+        stats: Option<FindStat>,
+        counters: &RunCounters,
+        arguments: I,
+    ) -> Self {
+        RunReport {
+            metrics,
+            stats,
+            processed: counters.processed.load(Ordering::Relaxed),
+            reclaimed_bytes: counters.reclaimed_bytes.load(Ordering::Relaxed),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+            error_count: counters.error_count.load(Ordering::Relaxed),
+            errors: counters.errors.lock().unwrap().clone(),
+            arguments: redact_arguments(arguments),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Adds the selectivity of the filters of the run
+    pub fn with_filters(self, filters: Vec<FilterSelectivity>) -> Self {
+        RunReport { filters, ..self }
+    }
+
+    fn sections(&self) -> Vec<Section> {
+        let file_size = make_format(BINARY);
+        let metrics = &self.metrics;
+
+        let mut sections = vec![(
+            "Run",
+            vec![
+                (
+                    "Path",
+                    format!("s3://{}/{}", metrics.bucket, metrics.prefix),
+                ),
+                ("Command", metrics.command.clone()),
+                ("Arguments", self.arguments.join(" ")),
+                (
+                    "Duration",
+                    format!("{:.1}s", metrics.duration.as_secs_f64()),
+                ),
+            ],
+        )];
+
+        if let Some(stats) = &self.stats {
+            let mut matched = vec![
+                ("Total files", stats.total_files.to_string()),
+                ("Total space", file_size(stats.total_space)),
+                ("Largest file", stats.max_key.clone()),
+                ("Smallest file", stats.min_key.clone()),
+                ("Average file size", file_size(stats.average_size())),
+            ];
+            if stats.negative_sizes > 0 {
+                matched.push(("Negative sizes", stats.negative_sizes.to_string()));
+            }
+            sections.push(("Matched", matched));
+        }
+
+        sections.push((
+            "Actions",
+            vec![
+                ("Objects scanned", metrics.scanned.to_string()),
+                ("Objects processed", self.processed.to_string()),
+                ("Objects skipped", self.skipped.to_string()),
+                ("Objects deleted", metrics.deleted.to_string()),
+                ("Space reclaimed", file_size(self.reclaimed_bytes)),
+                ("Bytes downloaded", file_size(metrics.downloaded_bytes)),
+                ("S3 requests", metrics.requests.to_string()),
+                ("Errors", self.error_count.to_string()),
+            ],
+        ));
+
+        sections
+    }
+
+    fn omitted_errors(&self) -> u64 {
+        self.error_count.saturating_sub(self.errors.len() as u64)
+    }
+
+    pub fn markdown(&self) -> String {
+        let mut out = String::from("# s3find report\n");
+
+        for (title, rows) in self.sections() {
+            out.push_str(&format!("\n## {}\n\n| | |\n|---|---|\n", title));
+            for (name, value) in rows {
+                out.push_str(&format!("| {} | {} |\n", name, value.replace('|', "\\|")));
+            }
+        }
+
+        if !self.filters.is_empty() {
+            out.push_str(
+                "\n## Filters\n\n| Filter | Evaluated | Rejected | Pass-through |\n|---|---|---|---|\n",
+            );
+            for filter in &self.filters {
+                out.push_str(&format!(
+                    "| {} | {} | {} | {} |\n",
+                    filter.flag.replace('|', "\\|"),
+                    filter.evaluated,
+                    filter.rejected,
+                    filter.pass_rate_text()
+                ));
+            }
+        }
+
+        out.push_str("\n## Errors\n\n");
+        if self.errors.is_empty() {
+            out.push_str("No errors\n");
+        }
+        for error in &self.errors {
+            out.push_str(&format!("- {}\n", error));
+        }
+        if self.omitted_errors() > 0 {
+            out.push_str(&format!("- ... and {} more\n", self.omitted_errors()));
+        }
+        out
+    }
+
+    pub fn html(&self) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>s3find report</title>\n\
+<style>body{font-family:sans-serif}td,th{padding:2px 12px;text-align:left}</style>\n\
+</head>\n<body>\n<h1>s3find report</h1>\n",
+        );
+
+        for (title, rows) in self.sections() {
+            out.push_str(&format!("<h2>{}</h2>\n<table>\n", title));
+            for (name, value) in rows {
+                out.push_str(&format!(
+                    "<tr><th>{}</th><td>{}</td></tr>\n",
+                    name,
+                    escape_html(&value)
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+
+        if !self.filters.is_empty() {
+            out.push_str(
+                "<h2>Filters</h2>\n<table>\n<tr><th>Filter</th><th>Evaluated</th><th>Rejected</th><th>Pass-through</th></tr>\n",
+            );
+            for filter in &self.filters {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    escape_html(&filter.flag),
+                    filter.evaluated,
+                    filter.rejected,
+                    filter.pass_rate_text()
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+
+        out.push_str("<h2>Errors</h2>\n");
+        if self.errors.is_empty() {
+            out.push_str("<p>No errors</p>\n");
+        } else {
+            out.push_str("<ul>\n");
+            for error in &self.errors {
+                out.push_str(&format!("<li>{}</li>\n", escape_html(error)));
+            }
+            if self.omitted_errors() > 0 {
+                out.push_str(&format!(
+                    "<li>... and {} more</li>\n",
+                    self.omitted_errors()
+                ));
+            }
+            out.push_str("</ul>\n");
+        }
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    pub fn write(&self, file: &ReportFile) -> Result<(), Error> {
+        let content = match file.format {
+            ReportFormat::Markdown => self.markdown(),
+            ReportFormat::Html => self.html(),
+        };
+        fs::write(&file.path, content)?;
+        Ok(())
+    }
+}
+
+pub fn redact_arguments<I: IntoIterator<Item = String>>(arguments: I) -> Vec<String> {
+    let mut redact_next = false;
+
+    arguments
+        .into_iter()
+        .map(|argument| {
+            if redact_next {
+                redact_next = false;
+                return REDACTED.to_owned();
+            }
+
+            for secret in SECRET_ARGUMENTS {
+                if argument == *secret {
+                    redact_next = true;
+                } else if argument.starts_with(&format!("{}=", secret)) {
+                    return format!("{}={}", secret, REDACTED);
+                }
+            }
+            argument
+        })
+        .collect()
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn run_report() -> RunReport {
+        let counters = RunCounters::default();
+        counters.processed.store(2, Ordering::Relaxed);
+        counters.reclaimed_bytes.store(2048, Ordering::Relaxed);
+        counters.error_count.store(1, Ordering::Relaxed);
+        counters
+            .errors
+            .lock()
+            .unwrap()
+            .push("AccessDenied: s3://test/logs/b.log".to_owned());
+
+        let stats = FindStat {
+            total_files: 3,
+            total_space: 3072,
+            max_size: Some(2048),
+            min_size: Some(0),
+            max_key: "logs/a.log".to_owned(),
+            min_key: "logs/c.log".to_owned(),
+            negative_sizes: 0,
+            sizes: Default::default(),
+        };
+
+        let metrics = RunMetrics {
+            bucket: "test".to_owned(),
+            prefix: "logs/".to_owned(),
+            command: "delete".to_owned(),
+            scanned: 10,
+            matched: 3,
+            matched_bytes: 3072,
+            deleted: 2,
+            downloaded_bytes: 0,
+            requests: 2,
+            api_requests: vec![("list", 1), ("get", 0), ("put", 0), ("delete", 1)],
+            errors: 0,
+            duration: Duration::from_millis(2500),
+        };
+
+        RunReport::new(
+            metrics,
+            Some(stats),
+            &counters,
+            vec![
+                "s3find",
+                "s3://test/logs/",
+                "--aws-access-key",
+                "AKIAEXAMPLE",
+                "--aws-secret-key",
+                "verysecret",
+                "delete",
+            ]
+            .into_iter()
+            .map(str::to_owned),
+        )
+    }
+
+    #[test]
+    fn test_redact_arguments() {
+        let arguments = vec![
+            "s3find",
+            "--aws-secret-key",
+            "secret1",
+            "--aws-secret-key=secret2",
+        ]
+        .into_iter()
+        .map(str::to_owned);
+
+        assert_eq!(
+            redact_arguments(arguments),
+            vec![
+                "s3find",
+                "--aws-secret-key",
+                "<redacted>",
+                "--aws-secret-key=<redacted>"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let report = run_report().markdown();
+
+        assert!(report.starts_with("# s3find report\n"));
+        assert!(report.contains("\n## Run\n"));
+        assert!(report.contains("\n## Matched\n"));
+        assert!(report.contains("\n## Actions\n"));
+        assert!(report.contains("\n## Errors\n"));
+        assert!(report.contains("| Path | s3://test/logs/ |"));
+        assert!(report.contains("| Duration | 2.5s |"));
+        assert!(report.contains("| Objects deleted | 2 |"));
+        assert!(report.contains("| Space reclaimed | 2 KiB |"));
+        assert!(report.contains("- AccessDenied: s3://test/logs/b.log"));
+        assert!(report.contains("--aws-secret-key <redacted>"));
+        assert!(!report.contains("verysecret"));
+    }
+
+    #[test]
+    fn test_filters_report() {
+        let report = run_report().with_filters(vec![FilterSelectivity {
+            flag: "--name *.log".to_owned(),
+            evaluated: 10,
+            rejected: 7,
+        }]);
+
+        assert!(!run_report().markdown().contains("## Filters"));
+        assert!(report.markdown().contains(
+            "\n## Filters\n\n| Filter | Evaluated | Rejected | Pass-through |\n|---|---|---|---|\n| --name *.log | 10 | 7 | 30.0% |\n"
+        ));
+        assert!(report
+            .html()
+            .contains("<tr><td>--name *.log</td><td>10</td><td>7</td><td>30.0%</td></tr>"));
+    }
+
+    #[test]
+    fn test_html_report() {
+        let report = run_report().html();
+
+        assert!(report.starts_with("<!DOCTYPE html>"));
+        assert!(report.contains("<h2>Matched</h2>"));
+        assert!(report.contains("<li>AccessDenied: s3://test/logs/b.log</li>"));
+        assert!(report.contains("--aws-secret-key &lt;redacted&gt;"));
+        assert!(!report.contains("verysecret"));
+    }
+}
@@ -0,0 +1,351 @@
+//! The restore command: RestoreObject requests for the matched keys, sent
+//! --workers at once. With --ledger every request is appended to a JSON
+//! lines file, and the keys it records as initiated are skipped by the next
+//! run, so a restore of millions of keys can be interrupted and resumed.
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::operation::restore_object::RestoreObjectError;
+use aws_sdk_s3::types::{Object, RestoreRequest};
+use aws_sdk_s3::Client;
+use chrono::Utc;
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use crate::arg::*;
+use crate::error::FunctionError;
+use crate::function::{workers_summary, CommandStats, RunCommand};
+use crate::utils::{display_key, s3_error, throttled};
+
+/// The outcome of the restore request of a key, the error of an outcome
+/// of Error
+async fn restore_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    days: i32,
+) -> (RestoreOutcome, Option<Error>) {
+    let request = client
+        .restore_object()
+        .bucket(bucket)
+        .key(key)
+        .restore_request(RestoreRequest::builder().days(days).build());
+    match request.send().await {
+        Ok(_) => (RestoreOutcome::Initiated, None),
+        Err(e) if e.code() == Some("RestoreAlreadyInProgress") => {
+            (RestoreOutcome::AlreadyInProgress, None)
+        }
+        Err(e)
+            if matches!(
+                e.code(),
+                Some("InvalidObjectState" | "ObjectAlreadyInActiveTierError")
+            ) =>
+        {
+            (RestoreOutcome::InvalidState, None)
+        }
+        Err(e) => (RestoreOutcome::Error, Some(s3_error(e))),
+    }
+}
+
+/// The keys a ledger records as initiated. A line cut short by an
+/// interrupted run is ignored, its key is requested again
+fn ledger_initiated(ledger: &str) -> Vec<(String, String)> {
+    ledger
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|entry| entry["outcome"] == RestoreOutcome::Initiated.name())
+        .filter_map(|entry| {
+            Some((
+                entry["bucket"].as_str()?.to_owned(),
+                entry["key"].as_str()?.to_owned(),
+            ))
+        })
+        .collect()
+}
+
+fn ledger_error(ledger: &Path, e: std::io::Error) -> Error {
+    FunctionError::RestoreLedger(ledger.display().to_string(), e.to_string()).into()
+}
+
+impl Restore {
+    /// Appends the lines of a batch to the ledger and flushes them, a line
+    /// cut short by an interrupted run is ended first
+    fn append_ledger(&self, lines: &[Value]) -> Result<(), Error> {
+        let ledger = match &self.ledger {
+            Some(ledger) if !lines.is_empty() => ledger,
+            _ => return Ok(()),
+        };
+        let append = || -> std::io::Result<()> {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .create(true)
+                .append(true)
+                .open(ledger)?;
+            let mut last = [b'\n'];
+            if file.metadata()?.len() > 0 {
+                file.seek(SeekFrom::End(-1))?;
+                file.read_exact(&mut last)?;
+            }
+            let mut writer = BufWriter::new(file);
+            if last[0] != b'\n' {
+                writeln!(writer)?;
+            }
+            for line in lines {
+                writeln!(writer, "{}", line)?;
+            }
+            writer.flush()
+        };
+        append().map_err(|e| ledger_error(ledger, e))
+    }
+}
+
+#[async_trait]
+impl RunCommand for Restore {
+    async fn preflight(&self, _client: &Client, _path: &S3Path) -> Result<(), Error> {
+        let ledger = match &self.ledger {
+            Some(ledger) => ledger,
+            None => return Ok(()),
+        };
+        let content = match fs::read_to_string(ledger) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(ledger_error(ledger, e)),
+        };
+        let mut tally = self.tally.0.lock().unwrap();
+        tally.initiated.extend(ledger_initiated(&content));
+        Ok(())
+    }
+
+    async fn execute(
+        &self,
+        client: &Client,
+        path: &S3Path,
+        list: &[Object],
+    ) -> Result<CommandStats, Error> {
+        let mut stats = CommandStats::default();
+        let bucket = path.bucket.as_str();
+        let mut keys = Vec::new();
+        {
+            let mut tally = self.tally.0.lock().unwrap();
+            for key in list.iter().filter_map(|x| x.key.as_deref()) {
+                if tally
+                    .initiated
+                    .contains(&(bucket.to_owned(), key.to_owned()))
+                {
+                    println!(
+                        "skipped: s3://{}/{} (initiated in the ledger)",
+                        bucket,
+                        display_key(key)
+                    );
+                    tally.skipped += 1;
+                    stats.skipped += 1;
+                } else {
+                    keys.push(key.to_owned());
+                }
+            }
+        }
+
+        let days = self.restore_days;
+        let outcomes: Vec<_> = futures::stream::iter(keys.clone())
+            .map(|key| async move { restore_object(client, bucket, &key, days).await })
+            .buffered(self.workers.current().max(1))
+            .collect()
+            .await;
+        self.workers.observe(
+            outcomes
+                .iter()
+                .filter(|(_, e)| matches!(e, Some(e) if throttled::<RestoreObjectError>(e)))
+                .count(),
+        );
+
+        let time = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let mut lines = Vec::new();
+        let mut tally = self.tally.0.lock().unwrap();
+        for (key, (outcome, error)) in keys.iter().zip(outcomes) {
+            let mut line = json!({
+                "time": time,
+                "bucket": bucket,
+                "key": key,
+                "outcome": outcome.name(),
+            });
+            match (outcome, error) {
+                (RestoreOutcome::Error, Some(e)) => {
+                    eprintln!("error: s3://{}/{}: {}", bucket, display_key(key), e);
+                    stats.errors.push(format!("s3://{}/{}: {}", bucket, key, e));
+                    line["error"] = json!(e.to_string());
+                }
+                (outcome, _) => {
+                    println!(
+                        "restore {}: s3://{}/{}",
+                        outcome.name(),
+                        bucket,
+                        display_key(key)
+                    );
+                    stats.processed += 1;
+                }
+            }
+            if outcome == RestoreOutcome::Initiated {
+                tally.initiated.insert((bucket.to_owned(), key.to_string()));
+            }
+            *tally.outcomes.entry(outcome).or_default() += 1;
+            lines.push(line);
+        }
+        drop(tally);
+
+        self.append_ledger(&lines)?;
+        Ok(stats)
+    }
+
+    fn summary(&self) -> Option<String> {
+        let tally = self.tally.0.lock().unwrap();
+        let mut summary = String::from("Restore:");
+        for outcome in [
+            RestoreOutcome::Initiated,
+            RestoreOutcome::AlreadyInProgress,
+            RestoreOutcome::InvalidState,
+            RestoreOutcome::Error,
+        ] {
+            summary.push_str(&format!(
+                " {} {},",
+                tally.outcomes.get(&outcome).copied().unwrap_or_default(),
+                outcome.name()
+            ));
+        }
+        summary.push_str(&format!(" {} skipped by the ledger", tally.skipped));
+        if let Some(workers) = workers_summary(&self.workers) {
+            summary.push_str(&workers);
+        }
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::{replay_client, ReplayResponse};
+    use structopt::StructOpt;
+
+    fn restore(args: &[&str]) -> Restore {
+        let mut argv = vec!["s3find", "s3://test/", "restore"];
+        argv.extend(args);
+        match FindOpt::from_iter_safe(&argv).unwrap().cmd {
+            Some(Cmd::Restore(cmd)) => cmd,
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+    }
+
+    fn error(status: u16, code: &str) -> ReplayResponse {
+        ReplayResponse::new(
+            status,
+            &format!("<Error><Code>{}</Code><Message>m</Message></Error>", code),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_restore_ledger() -> Result<(), Error> {
+        let ledger = std::env::temp_dir().join(format!("s3find-{}.jsonl", uuid::Uuid::new_v4()));
+        let ledger_arg = ledger.display().to_string();
+        let path: S3Path = "s3://test/".parse()?;
+        let list: Vec<_> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|key| Object::builder().key(*key).build())
+            .collect();
+
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(202, ""),
+            error(409, "RestoreAlreadyInProgress"),
+            error(403, "InvalidObjectState"),
+            error(400, "MalformedXML"),
+            ReplayResponse::new(202, ""),
+        ]);
+        let cmd = restore(&["--workers", "1", "--ledger", &ledger_arg]);
+        cmd.preflight(&client, &path).await?;
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.processed, 4);
+        assert_eq!(stats.errors.len(), 1);
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[0].uri.contains("/a?restore"));
+        assert!(std::str::from_utf8(&requests[0].body)?.contains("<Days>1</Days>"));
+
+        let lines: Vec<Value> = fs::read_to_string(&ledger)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        let outcomes: Vec<_> = lines
+            .iter()
+            .map(|x| (x["key"].as_str().unwrap(), x["outcome"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("a", "initiated"),
+                ("b", "already-in-progress"),
+                ("c", "invalid-state"),
+                ("d", "error"),
+                ("e", "initiated"),
+            ]
+        );
+        assert!(lines
+            .iter()
+            .all(|x| x["bucket"] == "test" && x["time"].is_string()));
+        assert!(lines[3]["error"].is_string());
+        assert_eq!(
+            cmd.summary().unwrap(),
+            "Restore: 2 initiated, 1 already-in-progress, 1 invalid-state, 1 error, 0 skipped by the ledger"
+        );
+
+        // a second run with the ledger requests only the keys not initiated
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(202, ""),
+            ReplayResponse::new(202, ""),
+            ReplayResponse::new(202, ""),
+        ]);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&ledger)?
+            .write_all(br#"{"bucket":"test","key":"b","outc"#)?;
+        let cmd = restore(&["--workers", "1", "--ledger", &ledger_arg]);
+        cmd.preflight(&client, &path).await?;
+        let stats = cmd.execute(&client, &path, &list).await?;
+
+        assert_eq!(stats.skipped, 2);
+        let requests = http_client.requests();
+        let keys: Vec<_> = requests
+            .iter()
+            .map(|x| x.uri.split('?').next().unwrap().rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(keys, vec!["b", "c", "d"]);
+        let initiated = ledger_initiated(&fs::read_to_string(&ledger)?);
+        assert_eq!(initiated.len(), 5);
+
+        fs::remove_file(&ledger)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ledger_initiated() {
+        let ledger = concat!(
+            r#"{"bucket":"test","key":"a","outcome":"initiated"}"#,
+            "\n",
+            r#"{"bucket":"test","key":"b","outcome":"error"}"#,
+            "\n",
+            r#"{"bucket":"other","key":"c","outcome":"initiated"}"#,
+            "\n",
+            r#"{"bucket":"test","key":"d","#,
+        );
+        assert_eq!(
+            ledger_initiated(ledger),
+            vec![
+                ("test".to_owned(), "a".to_owned()),
+                ("other".to_owned(), "c".to_owned())
+            ]
+        );
+    }
+}
@@ -1,23 +1,37 @@
-use aws_sdk_s3::types::Object;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
 use futures::stream::Stream;
 use futures::stream::StreamExt;
 use futures::Future;
 
-use crate::command::FindStat;
+use crate::command::{DeleteStats, FindStat, RunCounters};
+use crate::utils::ListedObject;
 
 const CHUNK: usize = 1000;
 
+/// Minimal time between two listing heartbeats
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of trailing key characters shown by the heartbeat
+const HEARTBEAT_KEY_WIDTH: usize = 50;
+
+/// The number of batches between two running totals of a delete
+pub const DELETE_PROGRESS_BATCHES: u64 = 10;
+
 pub async fn list_filter_execute<P, F, Fut, Fut2>(
-    iterator: impl Stream<Item = Vec<Object>>,
+    iterator: impl Stream<Item = Vec<ListedObject>>,
     limit: Option<usize>,
     stats: Option<FindStat>,
     p: P,
     f: &mut F,
 ) -> Option<FindStat>
 where
-    P: FnMut(&Object) -> Fut,
+    P: FnMut(&ListedObject) -> Fut,
     Fut: Future<Output = bool>,
-    F: FnMut(Option<FindStat>, Vec<Object>) -> Fut2,
+    F: FnMut(Option<FindStat>, Vec<ListedObject>) -> Fut2,
     Fut2: Future<Output = Option<FindStat>>,
 {
     match limit {
@@ -28,20 +42,20 @@ where
 
 #[inline]
 async fn list_filter_limit_execute<P, F, Fut, Fut2>(
-    iterator: impl Stream<Item = Vec<Object>>,
+    iterator: impl Stream<Item = Vec<ListedObject>>,
     limit: usize,
     stats: Option<FindStat>,
     p: P,
     f: &mut F,
 ) -> Option<FindStat>
 where
-    P: FnMut(&Object) -> Fut,
+    P: FnMut(&ListedObject) -> Fut,
     Fut: Future<Output = bool>,
-    F: FnMut(Option<FindStat>, Vec<Object>) -> Fut2,
+    F: FnMut(Option<FindStat>, Vec<ListedObject>) -> Fut2,
     Fut2: Future<Output = Option<FindStat>>,
 {
     iterator
-        .map(|x| futures::stream::iter(x.into_iter()))
+        .map(futures::stream::iter)
         .flatten()
         .filter(p)
         .take(limit)
@@ -52,22 +66,174 @@ where
 
 #[inline]
 async fn list_filter_unlimited_execute<P, F, Fut, Fut2>(
-    iterator: impl Stream<Item = Vec<Object>>,
+    iterator: impl Stream<Item = Vec<ListedObject>>,
     stats: Option<FindStat>,
     p: P,
     f: &mut F,
 ) -> Option<FindStat>
 where
-    P: FnMut(&Object) -> Fut,
+    P: FnMut(&ListedObject) -> Fut,
     Fut: Future<Output = bool>,
-    F: FnMut(Option<FindStat>, Vec<Object>) -> Fut2,
+    F: FnMut(Option<FindStat>, Vec<ListedObject>) -> Fut2,
     Fut2: Future<Output = Option<FindStat>>,
 {
     iterator
-        .map(|x| futures::stream::iter(x.into_iter()))
+        .map(futures::stream::iter)
         .flatten()
         .filter(p)
         .chunks(CHUNK)
         .fold(stats, f)
         .await
 }
+
+/// Listing counters reported by the heartbeat
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ListingProgress {
+    pub pages: u64,
+    pub scanned: u64,
+    pub matched: u64,
+    pub key: String,
+}
+
+impl fmt::Display for ListingProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.key.chars().count();
+        let key = if width > HEARTBEAT_KEY_WIDTH {
+            let tail: String = self.key.chars().skip(width - HEARTBEAT_KEY_WIDTH).collect();
+            format!("...{}", tail)
+        } else {
+            self.key.clone()
+        };
+
+        write!(
+            f,
+            "listing: {} pages, {} keys scanned, {} matched, at {}",
+            self.pages, self.scanned, self.matched, key
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct HeartbeatState {
+    progress: ListingProgress,
+    last: Option<Instant>,
+    reported: bool,
+}
+
+/// Reports the listing progress on a listed page, at most once per interval
+pub struct Heartbeat<F> {
+    interval: Duration,
+    state: Mutex<HeartbeatState>,
+    report: F,
+}
+
+impl<F: Fn(&ListingProgress)> Heartbeat<F> {
+    pub fn new(interval: Duration, report: F) -> Self {
+        Heartbeat {
+            interval,
+            state: Default::default(),
+            report,
+        }
+    }
+
+    pub fn page(&self, page: &[ListedObject]) {
+        let mut state = self.state.lock().unwrap();
+        state.progress.pages += 1;
+        state.progress.scanned += page.len() as u64;
+        if let Some(key) = page.last().and_then(|x| x.key.as_deref()) {
+            state.progress.key = key.to_owned();
+        }
+
+        if state.last.is_none_or(|x| x.elapsed() >= self.interval) {
+            (self.report)(&state.progress);
+            state.last = Some(Instant::now());
+            state.reported = true;
+        }
+    }
+
+    pub fn matched(&self, matched: bool) -> bool {
+        if matched {
+            self.state.lock().unwrap().progress.matched += 1;
+        }
+        matched
+    }
+
+    /// Whether a heartbeat was reported since the last call, so the caller
+    /// can clear the heartbeat line before printing anything else
+    pub fn take_reported(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().reported)
+    }
+}
+
+/// Counts the executed batches of a delete and gives its running totals
+/// every `every` batches
+pub struct DeleteProgress {
+    every: u64,
+    batches: AtomicU64,
+}
+
+impl DeleteProgress {
+    pub fn new(every: u64) -> Self {
+        DeleteProgress {
+            every,
+            batches: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts an executed batch, the totals once every `every` batches
+    pub fn batch(&self, counters: &RunCounters) -> Option<String> {
+        let batches = self.batches.fetch_add(1, Ordering::Relaxed) + 1;
+        match batches % self.every {
+            0 => Some(DeleteStats::new(counters).progress_line()),
+            _ => None,
+        }
+    }
+}
+
+/// Prints the heartbeat as a single updating line on stderr
+pub fn print_heartbeat(progress: &ListingProgress) {
+    eprint!("\r\x1b[2K{}", progress);
+}
+
+/// Clears the heartbeat line
+pub fn clear_heartbeat() {
+    eprint!("\r\x1b[2K");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_progress() {
+        let counters = RunCounters::default();
+        counters.deleted.store(3000, Ordering::Relaxed);
+        let progress = DeleteProgress::new(3);
+
+        let lines: Vec<_> = (0..6).map(|_| progress.batch(&counters)).collect();
+
+        assert_eq!(lines.iter().filter(|x| x.is_some()).count(), 2);
+        assert_eq!(
+            lines[2].as_deref(),
+            Some("deleted 3000 objects, ~0 B so far")
+        );
+    }
+
+    #[test]
+    fn test_listing_progress_display() {
+        let progress = ListingProgress {
+            pages: 2,
+            scanned: 2000,
+            matched: 3,
+            key: format!("logs/{}", "x".repeat(60)),
+        };
+
+        assert_eq!(
+            progress.to_string(),
+            format!(
+                "listing: 2 pages, 2000 keys scanned, 3 matched, at ...{}",
+                "x".repeat(50)
+            )
+        );
+    }
+}
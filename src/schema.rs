@@ -0,0 +1,218 @@
+//! The hidden --dump-cli-schema output: the options and subcommands of the
+//! clap command tree as JSON, for tools which generate wrappers or docs
+//! instead of parsing --help. The keys of the objects are sorted, so the
+//! outputs of two releases can be diffed.
+
+use serde_json::{json, Value};
+use structopt::clap::{App, ArgSettings};
+use structopt::StructOpt;
+
+use crate::arg::FindOpt;
+
+/// The version of the layout of the schema, raised when a key is removed
+/// or changes its meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// An argument of a command, flattened from the flag, option and
+/// positional builders of clap
+struct ArgSchema<'a> {
+    name: &'a str,
+    long: Option<&'a str>,
+    short: Option<char>,
+    index: Option<u64>,
+    help: Option<&'a str>,
+    long_help: Option<&'a str>,
+    required: bool,
+    multiple: bool,
+    default: Option<String>,
+    possible_values: Option<Vec<&'a str>>,
+    env: Option<String>,
+}
+
+impl ArgSchema<'_> {
+    fn to_json(&self, takes_value: bool) -> Value {
+        // clap only knows strings, the flags are the booleans
+        let kind = match (takes_value, &self.possible_values) {
+            (false, _) => "flag",
+            (true, Some(_)) => "enum",
+            (true, None) => "string",
+        };
+        json!({
+            "name": self.name,
+            "long": self.long,
+            "short": self.short,
+            "index": self.index,
+            "type": kind,
+            "help": self.help,
+            "long_help": self.long_help,
+            "required": self.required,
+            "multiple": self.multiple,
+            "default": self.default,
+            "possible_values": self.possible_values,
+            "env": self.env,
+        })
+    }
+}
+
+/// The schema of the s3find command tree
+pub fn cli_schema() -> Value {
+    let mut schema = command_schema(&FindOpt::clap());
+    schema["schema_version"] = json!(SCHEMA_VERSION);
+    schema["version"] = json!(env!("CARGO_PKG_VERSION"));
+    schema
+}
+
+fn command_schema(app: &App) -> Value {
+    let parser = &app.p;
+    let mut args = Vec::new();
+
+    for flag in parser.flags.iter() {
+        if flag.b.settings.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        let schema = ArgSchema {
+            name: flag.b.name,
+            long: flag.s.long,
+            short: flag.s.short,
+            index: None,
+            help: flag.b.help,
+            long_help: flag.b.long_help,
+            required: false,
+            multiple: flag.b.settings.is_set(ArgSettings::Multiple),
+            default: None,
+            possible_values: None,
+            env: None,
+        };
+        args.push(schema.to_json(false));
+    }
+
+    for opt in parser.opts.iter() {
+        if opt.b.settings.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        let schema = ArgSchema {
+            name: opt.b.name,
+            long: opt.s.long,
+            short: opt.s.short,
+            index: None,
+            help: opt.b.help,
+            long_help: opt.b.long_help,
+            required: opt.b.settings.is_set(ArgSettings::Required),
+            multiple: opt.b.settings.is_set(ArgSettings::Multiple),
+            default: opt.v.default_val.map(|x| x.to_string_lossy().into_owned()),
+            possible_values: opt.v.possible_vals.clone(),
+            env: opt
+                .v
+                .env
+                .as_ref()
+                .map(|(name, _)| name.to_string_lossy().into_owned()),
+        };
+        args.push(schema.to_json(true));
+    }
+
+    for positional in parser.positionals.values() {
+        if positional.b.settings.is_set(ArgSettings::Hidden) {
+            continue;
+        }
+        let schema = ArgSchema {
+            name: positional.b.name,
+            long: None,
+            short: None,
+            index: Some(positional.index),
+            help: positional.b.help,
+            long_help: positional.b.long_help,
+            required: positional.b.settings.is_set(ArgSettings::Required),
+            multiple: positional.b.settings.is_set(ArgSettings::Multiple),
+            default: positional
+                .v
+                .default_val
+                .map(|x| x.to_string_lossy().into_owned()),
+            possible_values: positional.v.possible_vals.clone(),
+            env: positional
+                .v
+                .env
+                .as_ref()
+                .map(|(name, _)| name.to_string_lossy().into_owned()),
+        };
+        args.push(schema.to_json(true));
+    }
+    args.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+    let mut subcommands: Vec<Value> = parser.subcommands.iter().map(command_schema).collect();
+    subcommands.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+    json!({
+        "name": parser.meta.name,
+        "about": parser.meta.about,
+        "long_about": parser.meta.long_about,
+        "args": args,
+        "subcommands": subcommands,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The argument of the command, the subcommands given by their names
+    fn find_arg<'a>(schema: &'a Value, path: &[&str], name: &str) -> &'a Value {
+        let command = path.iter().fold(schema, |command, subcommand| {
+            command["subcommands"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|x| x["name"] == *subcommand)
+                .unwrap()
+        });
+        command["args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["name"] == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cli_schema() -> Result<(), serde_json::Error> {
+        let output = format!("{:#}", cli_schema());
+        let schema: Value = serde_json::from_str(&output)?;
+
+        assert_eq!(schema["schema_version"], SCHEMA_VERSION);
+        assert_eq!(schema["name"], "s3find");
+
+        let path = find_arg(&schema, &[], "path");
+        assert_eq!(path["index"], 1);
+        assert_eq!(path["type"], "string");
+
+        let page_size = find_arg(&schema, &[], "number");
+        assert_eq!(page_size["long"], "page-size");
+        assert_eq!(page_size["default"], "1000");
+
+        let color = find_arg(&schema, &[], "color");
+        assert_eq!(color["type"], "enum");
+        assert_eq!(color["default"], "auto");
+        assert_eq!(color["possible_values"], json!(["auto", "always", "never"]));
+
+        let summarize = find_arg(&schema, &[], "summarize");
+        assert_eq!(summarize["type"], "flag");
+        assert_eq!(summarize["default"], Value::Null);
+
+        for command in ["copy", "move"] {
+            let days = find_arg(&schema, &[command], "restore-days");
+            assert_eq!(days["long"], "restore-days");
+            assert_eq!(days["default"], "1");
+        }
+
+        // hidden options stay out of the schema
+        let options = schema["args"].as_array().unwrap();
+        assert!(options.iter().all(|x| x["name"] != "dump-cli-schema"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_schema_deterministic() {
+        assert_eq!(cli_schema().to_string(), cli_schema().to_string());
+        let output = cli_schema().to_string();
+        assert!(output.find("\"about\"").unwrap() < output.find("\"args\"").unwrap());
+    }
+}
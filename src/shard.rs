@@ -0,0 +1,239 @@
+//! Key ranges of --shard-by-range. The top level below the search prefix is
+//! listed once with a delimiter, its keys and common prefixes are split into
+//! runs of about the same number of entries and every shard lists only the
+//! keys of its range.
+//!
+//! The ranges split the entries, not the keys below them: a common prefix
+//! holding most of the keys makes its shard the longest.
+
+use std::fmt;
+
+use anyhow::Error;
+use aws_sdk_s3::types::EncodingType;
+use aws_sdk_s3::Client;
+
+use crate::arg::{S3Path, Shard};
+use crate::utils::{decode_url_key, s3_error};
+
+/// Levels descended while the top level is a single common prefix
+const MAX_SAMPLE_DEPTH: usize = 8;
+
+/// The keys of a shard, from `from` included to `to` excluded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyRange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl KeyRange {
+    /// The range of the shard between the boundaries of `boundaries`
+    pub fn of_shard(boundaries: &[String], shard: Shard) -> KeyRange {
+        let index = shard.index as usize;
+        KeyRange {
+            from: index
+                .checked_sub(1)
+                .and_then(|x| boundaries.get(x))
+                .cloned(),
+            to: boundaries.get(index).cloned(),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.from.as_deref().is_none_or(|from| key >= from) && !self.ends_before(key)
+    }
+
+    /// Whether the key sorts after the range, a listing ends there
+    pub fn ends_before(&self, key: &str) -> bool {
+        self.to.as_deref().is_some_and(|to| key >= to)
+    }
+
+    /// The StartAfter of the listing. StartAfter excludes its key, the start
+    /// without its last character sorts before it, the few keys listed
+    /// between the two are dropped by `contains`
+    pub fn start_after(&self) -> Option<String> {
+        let mut start = self.from.clone()?;
+        start.pop();
+        Some(start).filter(|x| !x.is_empty())
+    }
+}
+
+impl fmt::Display for KeyRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.from, &self.to) {
+            (None, None) => write!(f, "all keys"),
+            (Some(from), None) => write!(f, "keys from {}", from),
+            (None, Some(to)) => write!(f, "keys before {}", to),
+            (Some(from), Some(to)) => write!(f, "keys from {} before {}", from, to),
+        }
+    }
+}
+
+/// The n-1 boundaries splitting the sorted entries into n runs of about the
+/// same length. The ranges cover every key whatever the entries, with fewer
+/// entries than shards some ranges are empty.
+pub fn boundaries(entries: &[String], count: u64) -> Vec<String> {
+    let mut entries = entries.to_vec();
+    entries.sort();
+    entries.dedup();
+
+    (1..count as usize)
+        .map(|i| {
+            entries
+                .get(i * entries.len() / count as usize)
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// The keys and common prefixes of the top level below the prefix, the
+/// level below a top level made of a single common prefix is listed instead.
+/// Only the first page of every level is listed.
+pub async fn sample_entries(client: &Client, path: &S3Path) -> Result<Vec<String>, Error> {
+    let mut prefix = path.prefix.clone().unwrap_or_default();
+    let mut entries = Vec::new();
+
+    for _ in 0..MAX_SAMPLE_DEPTH {
+        let page = client
+            .list_objects_v2()
+            .bucket(path.bucket.clone())
+            .prefix(prefix.clone())
+            .delimiter("/")
+            .encoding_type(EncodingType::Url)
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        let encoded = page.encoding_type == Some(EncodingType::Url);
+        entries = page
+            .contents()
+            .iter()
+            .filter_map(|x| x.key.as_deref())
+            .chain(
+                page.common_prefixes()
+                    .iter()
+                    .filter_map(|x| x.prefix.as_deref()),
+            )
+            .map(|x| {
+                if encoded {
+                    decode_url_key(x)
+                } else {
+                    x.to_owned()
+                }
+            })
+            .collect();
+
+        match (page.contents().is_empty(), page.common_prefixes()) {
+            (true, [_]) => prefix = entries[0].clone(),
+            _ => break,
+        }
+    }
+    Ok(entries)
+}
+
+/// The key range of a shard, from a sample of the bucket
+pub async fn shard_range(client: &Client, path: &S3Path, shard: Shard) -> Result<KeyRange, Error> {
+    let entries = sample_entries(client, path).await?;
+    Ok(KeyRange::of_shard(
+        &boundaries(&entries, shard.count),
+        shard,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::*;
+
+    fn shard(index: u64, count: u64) -> Shard {
+        Shard { index, count }
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let entries: Vec<_> = ["d/", "a/", "c/", "b/", "e.csv", "f/", "b/"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(boundaries(&entries, 3), vec!["c/", "e.csv"]);
+        assert_eq!(boundaries(&entries, 1), Vec::<String>::new());
+        // more shards than entries
+        assert_eq!(boundaries(&entries[..1], 3), vec!["d/", "d/"]);
+        assert_eq!(boundaries(&[], 2), vec![""]);
+    }
+
+    #[test]
+    fn test_ranges_partition_keys() {
+        let keys = [
+            "a/1", "a/2", "b.csv", "b/1", "c/x/y", "d", "d/1", "e/", "e/1", "z",
+        ];
+        for entries in [
+            vec!["a/", "b.csv", "b/", "c/", "d", "d/", "e/", "z"],
+            vec!["a/"],
+            vec![],
+        ] {
+            let entries: Vec<_> = entries.iter().map(|x| x.to_string()).collect();
+            let boundaries = boundaries(&entries, 4);
+            let ranges: Vec<_> = (0..4)
+                .map(|i| KeyRange::of_shard(&boundaries, shard(i, 4)))
+                .collect();
+            for key in &keys {
+                assert_eq!(
+                    ranges.iter().filter(|x| x.contains(key)).count(),
+                    1,
+                    "{} in {:?}",
+                    key,
+                    ranges
+                );
+            }
+        }
+
+        let boundaries = boundaries(
+            &["a/", "b/", "c/", "d/"]
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>(),
+            2,
+        );
+        let range = KeyRange::of_shard(&boundaries, shard(1, 2));
+        assert_eq!(range.to_string(), "keys from c/");
+        assert_eq!(range.start_after().as_deref(), Some("c"));
+        assert!(!range.contains("c.csv"));
+        assert!(range.contains("c/1"));
+
+        let range = KeyRange::of_shard(&boundaries, shard(0, 2));
+        assert_eq!(range.to_string(), "keys before c/");
+        assert_eq!(range.start_after(), None);
+        assert!(range.ends_before("c/"));
+    }
+
+    #[tokio::test]
+    async fn test_sample_entries() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><CommonPrefixes><Prefix>data/</Prefix></CommonPrefixes></ListBucketResult>"#,
+            ),
+            ReplayResponse::new(
+                200,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>test</Name><EncodingType>url</EncodingType>
+<Contents><Key>data/readme%20first.txt</Key></Contents>
+<CommonPrefixes><Prefix>data/2023/</Prefix></CommonPrefixes>
+<CommonPrefixes><Prefix>data/2024/</Prefix></CommonPrefixes></ListBucketResult>"#,
+            ),
+        ]);
+        let path: S3Path = "s3://test/".parse()?;
+
+        let range = shard_range(&client, &path, shard(1, 2)).await?;
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].uri.contains("delimiter=%2F"));
+        assert!(requests[1].uri.contains("prefix=data%2F"));
+        assert_eq!(range.from.as_deref(), Some("data/2024/"));
+        assert_eq!(range.to, None);
+        Ok(())
+    }
+}
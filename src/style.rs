@@ -0,0 +1,67 @@
+//! ANSI styling of the text output of ls and print. A styled line holds
+//! the characters of the plain one, only the escape sequences are added.
+
+use std::borrow::Cow;
+
+/// The SGR parameters of an ANSI style
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style(&'static str);
+
+impl Style {
+    pub const DIM: Style = Style("2");
+    pub const BLUE: Style = Style("34");
+    pub const YELLOW: Style = Style("33");
+
+    /// The text between the escape sequences of the style, the text as it
+    /// is without `color`
+    pub fn paint(self, text: &str, color: bool) -> Cow<'_, str> {
+        match color {
+            true => Cow::Owned(format!("\x1b[{}m{}\x1b[0m", self.0, text)),
+            false => Cow::Borrowed(text),
+        }
+    }
+}
+
+/// The style of a storage class: the archive classes in blue,
+/// REDUCED_REDUNDANCY in yellow, none for STANDARD and the others
+pub fn storage_class_style(class: &str) -> Option<Style> {
+    match class {
+        "GLACIER" | "DEEP_ARCHIVE" | "GLACIER_IR" => Some(Style::BLUE),
+        "REDUCED_REDUNDANCY" => Some(Style::YELLOW),
+        _ => None,
+    }
+}
+
+/// The text without its ANSI escape sequences
+#[cfg(test)]
+pub(crate) fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a letter
+            chars.by_ref().find(|x| x.is_ascii_alphabetic());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(Style::DIM.paint("s3://b/", false), "s3://b/");
+        assert_eq!(Style::DIM.paint("s3://b/", true), "\x1b[2ms3://b/\x1b[0m");
+        assert_eq!(strip_ansi(&Style::BLUE.paint("GLACIER", true)), "GLACIER");
+        assert_eq!(storage_class_style("DEEP_ARCHIVE"), Some(Style::BLUE));
+        assert_eq!(
+            storage_class_style("REDUCED_REDUNDANCY"),
+            Some(Style::YELLOW)
+        );
+        assert_eq!(storage_class_style("STANDARD"), None);
+    }
+}
@@ -1,5 +1,30 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::future::Future;
+use std::ops::Deref;
+use std::time::Duration;
+
+use anyhow::Error;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::{RequestId, RequestIdExt};
+use aws_sdk_s3::types::Object;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+
+use crate::arg::{CollisionStrategy, TargetTracker};
+use crate::error::FunctionError;
+
 const DELIMETER: char = '/';
 
+/// The characters of a key percent-encoded in `x-amz-copy-source`, all but
+/// the unreserved ones and the `/` between the key segments
+const COPY_SOURCE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
 trait S3Key {
     fn key_name(self) -> Self;
     fn join_key(self, dest: &Self) -> Self;
@@ -39,9 +64,270 @@ pub fn combine_keys(flat: bool, source: &str, destination: &Option<String>) -> S
     }
 }
 
+/// Renders a key for human output, marking directory placeholder keys
+#[inline]
+pub fn display_key(key: &str) -> String {
+    let key = terminal_key(key);
+    if key.ends_with(DELIMETER) {
+        format!("{} (dir marker)", key)
+    } else {
+        key.into_owned()
+    }
+}
+
+/// The `x-amz-copy-source` of a key: the bucket and the URL-encoded key, a
+/// raw key with a control character or a `?` cannot be sent in the header
+pub fn copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, utf8_percent_encode(key, COPY_SOURCE))
+}
+
+/// Decodes a key listed with encoding-type=url, S3 encodes a space as '+'
+pub fn decode_url_key(key: &str) -> String {
+    decode_listed_key(key).0
+}
+
+/// Decodes a listed key like `decode_url_key`, with the listed bytes of a
+/// key which is not valid UTF-8. The key has U+FFFD in place of the invalid
+/// bytes, the decoding the filters and the records see
+pub fn decode_listed_key(key: &str) -> (String, Option<Vec<u8>>) {
+    let bytes: Vec<u8> = percent_decode_str(&key.replace('+', " ")).collect();
+    match String::from_utf8(bytes) {
+        Ok(key) => (key, None),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            (String::from_utf8_lossy(&bytes).into_owned(), Some(bytes))
+        }
+    }
+}
+
+/// A listed object, with the listed bytes of its key when they are not
+/// valid UTF-8. The SDK keys are strings, the bytes travel next to them
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListedObject {
+    pub object: Object,
+    pub key_bytes: Option<Vec<u8>>,
+}
+
+impl From<Object> for ListedObject {
+    fn from(object: Object) -> Self {
+        ListedObject {
+            object,
+            key_bytes: None,
+        }
+    }
+}
+
+impl Deref for ListedObject {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        &self.object
+    }
+}
+
+/// The listed bytes of the keys of a batch which are not valid UTF-8, by
+/// the position of their object in the batch
+#[derive(Debug, Default)]
+pub struct KeyBytes(Vec<Option<Vec<u8>>>);
+
+impl KeyBytes {
+    /// Splits listed objects into the objects of a batch and their bytes
+    pub fn split(list: Vec<ListedObject>) -> (Vec<Object>, KeyBytes) {
+        if list.iter().all(|x| x.key_bytes.is_none()) {
+            return (
+                list.into_iter().map(|x| x.object).collect(),
+                KeyBytes::default(),
+            );
+        }
+        let (objects, bytes) = list.into_iter().map(|x| (x.object, x.key_bytes)).unzip();
+        (objects, KeyBytes(bytes))
+    }
+
+    /// The listed bytes of the key of the object at `index` in the batch
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.0.get(index)?.as_deref()
+    }
+}
+
+/// Adds the base64 listed bytes of a key which is not valid UTF-8 to a
+/// JSON record, as `key_bytes_base64`; the key of the record keeps U+FFFD
+pub fn add_key_bytes(record: &mut Value, bytes: Option<&[u8]>) {
+    if let Some(bytes) = bytes {
+        record["key_bytes_base64"] = json!(aws_smithy_types::base64::encode(bytes));
+    }
+}
+
+/// Characters which hide or reorder the text around them on a terminal:
+/// controls, bidi overrides and isolates, zero-width characters
+fn hidden_char(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{61c}'
+                | '\u{200b}'..='\u{200f}'
+                | '\u{202a}'..='\u{202e}'
+                | '\u{2060}'..='\u{2069}'
+                | '\u{feff}'
+        )
+}
+
+fn push_visible(out: &mut String, text: &str) {
+    for c in text.chars() {
+        if hidden_char(c) {
+            let _ = write!(out, "\\u{{{:x}}}", c as u32);
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+/// A key for text output on a terminal, hidden characters show as
+/// `\u{202e}`
+pub fn terminal_key(key: &str) -> Cow<'_, str> {
+    listed_terminal_key(key, None)
+}
+
+/// A listed key for text output on a terminal: the invalid bytes of a key
+/// which is not valid UTF-8 show as `\xff`, hidden characters as `\u{202e}`
+pub fn listed_terminal_key<'a>(key: &'a str, bytes: Option<&[u8]>) -> Cow<'a, str> {
+    if bytes.is_none() && !key.chars().any(hidden_char) {
+        return Cow::Borrowed(key);
+    }
+
+    let mut out = String::with_capacity(key.len());
+    match bytes {
+        Some(bytes) => {
+            for chunk in bytes.utf8_chunks() {
+                push_visible(&mut out, chunk.valid());
+                for byte in chunk.invalid() {
+                    let _ = write!(out, "\\x{:02x}", byte);
+                }
+            }
+        }
+        None => push_visible(&mut out, key),
+    }
+    Cow::Owned(out)
+}
+
+/// Describes a failed S3 request with its request id and extended request
+/// id, the two values AWS support asks for
+pub fn error_context<E: ProvideErrorMetadata>(e: &SdkError<E>) -> String {
+    let mut context = match (e.code(), e.message()) {
+        (Some(code), Some(message)) => format!("{}: {}", code, message),
+        (Some(code), None) => code.to_owned(),
+        _ => e.to_string(),
+    };
+
+    let ids: Vec<_> = [
+        ("request id", e.request_id()),
+        ("extended request id", e.extended_request_id()),
+    ]
+    .iter()
+    .filter_map(|(name, id)| Some(format!("{}: {}", name, (*id)?)))
+    .collect();
+    if !ids.is_empty() {
+        context.push_str(&format!(" ({})", ids.join(", ")));
+    }
+    context
+}
+
+/// Converts a failed S3 request to an error displayed with its request ids,
+/// the request error itself stays available to `downcast_ref`
+pub fn s3_error<E>(e: SdkError<E>) -> Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    let context = error_context(&e);
+    Error::new(e).context(context)
+}
+
+/// Whether an error of `s3_error` is a throttled request, 503 Slow Down or
+/// 429 Too Many Requests
+pub fn throttled<E>(e: &Error) -> bool
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    e.downcast_ref::<SdkError<E>>()
+        .and_then(|e| e.raw_response())
+        .is_some_and(|r| matches!(r.status().as_u16(), 429 | 503))
+}
+
+/// Runs the operation until it succeeds or `attempts` runs have failed,
+/// doubling the delay after every failure
+pub async fn retry_with_backoff<T, E, F, Fut>(
+    attempts: u32,
+    delay: Duration,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = delay;
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(_) if attempt < attempts => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[inline]
+fn suffix_key(key: &str, n: usize) -> String {
+    match key.strip_suffix(DELIMETER) {
+        Some(stripped) => format!("{}.{}{}", stripped, n, DELIMETER),
+        None => format!("{}.{}", key, n),
+    }
+}
+
+impl TargetTracker {
+    /// Registers the target key of a source key, resolving a collision with
+    /// an already registered target according to the strategy.
+    /// Returns None when the key should be skipped.
+    pub fn resolve(
+        &self,
+        strategy: CollisionStrategy,
+        source: &str,
+        target: String,
+    ) -> Result<Option<String>, FunctionError> {
+        let mut targets = self.0.lock().unwrap();
+
+        let existing = match targets.get(&target) {
+            None => {
+                targets.insert(target.clone(), source.to_owned());
+                return Ok(Some(target));
+            }
+            Some(existing) => existing.to_owned(),
+        };
+
+        match strategy {
+            CollisionStrategy::Error => Err(FunctionError::TargetCollisionError(
+                source.to_owned(),
+                existing,
+                target,
+            )),
+            CollisionStrategy::Skip => Ok(None),
+            CollisionStrategy::Suffix => {
+                let target = (1..)
+                    .map(|n| suffix_key(&target, n))
+                    .find(|x| !targets.contains_key(x))
+                    .unwrap_or_default();
+                targets.insert(target.clone(), source.to_owned());
+                Ok(Some(target))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::replay::*;
 
     #[test]
     fn test_key_name() {
@@ -80,4 +366,183 @@ mod tests {
         assert_eq!(&combine_keys(false, "some/path", &None), "some/path",);
         assert_eq!(&combine_keys(true, "some/path", &None), "path",);
     }
+
+    #[test]
+    fn test_display_key() {
+        assert_eq!(display_key("some/report"), "some/report");
+        assert_eq!(display_key("some/report/"), "some/report/ (dir marker)");
+    }
+
+    #[test]
+    fn test_suffix_key() {
+        assert_eq!(suffix_key("report", 1), "report.1");
+        assert_eq!(suffix_key("report/", 2), "report.2/");
+    }
+
+    #[test]
+    fn test_target_tracker() {
+        let tracker = TargetTracker::default();
+
+        assert_eq!(
+            tracker
+                .resolve(CollisionStrategy::Error, "a/report", "report".to_owned())
+                .unwrap(),
+            Some("report".to_owned())
+        );
+        assert!(tracker
+            .resolve(CollisionStrategy::Error, "b/report", "report".to_owned())
+            .is_err());
+        assert_eq!(
+            tracker
+                .resolve(CollisionStrategy::Skip, "b/report", "report".to_owned())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            tracker
+                .resolve(CollisionStrategy::Suffix, "b/report", "report".to_owned())
+                .unwrap(),
+            Some("report.1".to_owned())
+        );
+        assert_eq!(
+            tracker
+                .resolve(CollisionStrategy::Suffix, "c/report", "report".to_owned())
+                .unwrap(),
+            Some("report.2".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_copy_source() {
+        assert_eq!(
+            copy_source("bucket", "logs/a-b_c.~1"),
+            "bucket/logs/a-b_c.~1"
+        );
+        assert_eq!(
+            copy_source("bucket", "logs\u{1}/a b+c?d%é"),
+            "bucket/logs%01/a%20b%2Bc%3Fd%25%C3%A9"
+        );
+    }
+
+    #[test]
+    fn test_decode_url_key() {
+        assert_eq!(decode_url_key("logs/a.txt"), "logs/a.txt");
+        assert_eq!(decode_url_key("logs%01/a+b%2Bc.txt"), "logs\u{1}/a b+c.txt");
+        assert_eq!(decode_url_key("caf%C3%A9%7F"), "café\u{7f}");
+    }
+
+    #[test]
+    fn test_invalid_utf8_key() {
+        let (key, bytes) = decode_listed_key("utf8/bad%FF%E2%80%AEgpj.exe");
+        let bytes = bytes.unwrap();
+        assert_eq!(key, "utf8/bad\u{fffd}\u{202e}gpj.exe");
+        assert_eq!(bytes, b"utf8/bad\xff\xe2\x80\xaegpj.exe");
+        assert_eq!(
+            listed_terminal_key(&key, Some(&bytes)),
+            r"utf8/bad\xff\u{202e}gpj.exe"
+        );
+        assert_eq!(terminal_key(&key), "utf8/bad\u{fffd}\\u{202e}gpj.exe");
+
+        let mut record = json!({ "key": key });
+        add_key_bytes(&mut record, Some(&bytes));
+        assert_eq!(record["key"], "utf8/bad\u{fffd}\u{202e}gpj.exe");
+        assert_eq!(record["key_bytes_base64"], "dXRmOC9iYWT/4oCuZ3BqLmV4ZQ==");
+
+        assert_eq!(
+            decode_listed_key("utf8/caf%C3%A9"),
+            ("utf8/café".to_owned(), None)
+        );
+        // a valid key is never taken for listed bytes, whatever it contains
+        assert_eq!(
+            decode_listed_key("utf8/%EF%BF%BD%F4%8F%BF%BF"),
+            ("utf8/\u{fffd}\u{10ffff}".to_owned(), None)
+        );
+    }
+
+    #[test]
+    fn test_key_bytes_of_a_batch() {
+        let listed = |key: &str| {
+            let (key, key_bytes) = decode_listed_key(key);
+            ListedObject {
+                object: Object::builder().key(key).build(),
+                key_bytes,
+            }
+        };
+
+        let (objects, bytes) = KeyBytes::split(vec![listed("a"), listed("b")]);
+        assert_eq!(objects.len(), 2);
+        assert_eq!((bytes.get(0), bytes.get(1)), (None, None));
+
+        let (objects, bytes) = KeyBytes::split(vec![listed("a"), listed("b%FF"), listed("c")]);
+        assert_eq!(objects[1].key.as_deref(), Some("b\u{fffd}"));
+        assert_eq!(bytes.get(0), None);
+        assert_eq!(bytes.get(1), Some(&b"b\xff"[..]));
+        assert_eq!(bytes.get(2), None);
+    }
+
+    #[test]
+    fn test_terminal_key() {
+        assert_eq!(terminal_key("logs/a.txt"), "logs/a.txt");
+        assert_eq!(
+            terminal_key("report\u{202e}fdp.exe"),
+            r"report\u{202e}fdp.exe"
+        );
+        assert_eq!(terminal_key("a\tb\u{200b}c\n"), r"a\u{9}b\u{200b}c\u{a}");
+        assert_eq!(display_key("dir\u{1}/"), r"dir\u{1}/ (dir marker)");
+    }
+
+    #[tokio::test]
+    async fn test_error_context() -> Result<(), Error> {
+        let (client, _) = replay_client(vec![ReplayResponse {
+            status: 403,
+            timeout: false,
+            headers: vec![
+                ("x-amz-request-id".to_owned(), "4442587FB7D0A2F9".to_owned()),
+                ("x-amz-id-2".to_owned(), "vlR7PnpV2Ce81l0P".to_owned()),
+            ],
+            body: r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>"#
+                .to_owned(),
+        }]);
+
+        let e = client
+            .head_bucket()
+            .bucket("test")
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_context(&e),
+            "AccessDenied: Access Denied (request id: 4442587FB7D0A2F9, extended request id: vlR7PnpV2Ce81l0P)"
+        );
+
+        let error = s3_error(e);
+        assert!(error.to_string().contains("request id: 4442587FB7D0A2F9"));
+        assert!(error
+            .downcast_ref::<SdkError<aws_sdk_s3::operation::head_bucket::HeadBucketError>>()
+            .is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let operation = || async {
+            match calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) {
+                0 => Err("failed"),
+                n => Ok(n),
+            }
+        };
+
+        assert_eq!(
+            retry_with_backoff(3, Duration::from_millis(1), operation).await,
+            Ok(1)
+        );
+
+        calls.store(0, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(
+            retry_with_backoff(1, Duration::from_millis(1), operation).await,
+            Err("failed")
+        );
+    }
 }
@@ -0,0 +1,399 @@
+//! `s3find wizard`: builds an invocation from questions on the terminal.
+//! Every answer is checked with the parser of its option, the invocation
+//! as a whole with the parser of the command line.
+
+use std::io::{self, BufRead, Write};
+
+use anyhow::Error;
+use aws_sdk_s3::Client;
+use structopt::StructOpt;
+
+use crate::arg::{glob_pattern, FindOpt, FindSize, FindTime, InameGlob, S3Path};
+use crate::error::FunctionError;
+
+pub const WIZARD_COMMAND: &str = "wizard";
+
+const DEFAULT_ACTION: &str = "ls";
+
+/// The questions and answers of the wizard
+pub trait Prompt {
+    fn say(&mut self, line: &str) -> io::Result<()>;
+
+    /// The trimmed answer, none at the end of the input
+    fn ask(&mut self, question: &str) -> io::Result<Option<String>>;
+}
+
+/// Questions on a writer, answers read line by line, stdin and stdout for
+/// the command
+pub struct Terminal<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Terminal<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Terminal { input, output }
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt for Terminal<R, W> {
+    fn say(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.output, "{}", line)
+    }
+
+    fn ask(&mut self, question: &str) -> io::Result<Option<String>> {
+        write!(self.output, "{} ", question)?;
+        self.output.flush()?;
+
+        let mut answer = String::new();
+        match self.input.read_line(&mut answer)? {
+            0 => Ok(None),
+            _ => Ok(Some(answer.trim().to_owned())),
+        }
+    }
+}
+
+/// The invocation built by the wizard
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    /// The arguments after the program name
+    pub args: Vec<String>,
+    /// Whether the user asked to run it now
+    pub run: bool,
+}
+
+impl Invocation {
+    /// The invocation as a shell command line, for copy and paste
+    pub fn command_line(&self) -> String {
+        std::iter::once("s3find".to_owned())
+            .chain(self.args.iter().map(|x| shell_quote(x)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn opts(&self) -> Result<FindOpt, structopt::clap::Error> {
+        FindOpt::from_iter_safe(
+            std::iter::once("s3find").chain(self.args.iter().map(String::as_str)),
+        )
+    }
+}
+
+/// Quotes an argument for a POSIX shell when it holds other characters than
+/// the ones of plain paths and flags
+pub fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Splits a line into words like a shell: single and double quotes group
+/// words, a backslash outside of single quotes escapes the next character
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                let escaped = chars.next().ok_or("trailing backslash")?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    match quote {
+        Some(q) => Err(format!("unterminated {} quote", q)),
+        None => {
+            words.extend(word);
+            Ok(words)
+        }
+    }
+}
+
+/// The subcommands part of the help, the actions to choose from
+fn actions_help() -> String {
+    let mut help = Vec::new();
+    let _ = FindOpt::clap().write_long_help(&mut help);
+    let help = String::from_utf8_lossy(&help);
+    help.split_once("SUBCOMMANDS:\n")
+        .map(|(_, actions)| actions.split("\n\n").next().unwrap_or_default())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+fn answer<P: Prompt>(prompt: &mut P, question: &str) -> Result<String, Error> {
+    prompt
+        .ask(question)?
+        .ok_or_else(|| FunctionError::WizardCancelled.into())
+}
+
+fn confirmed<P: Prompt>(prompt: &mut P, question: &str, default: bool) -> Result<bool, Error> {
+    loop {
+        match answer(prompt, question)?.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => prompt.say("please answer y or n")?,
+        }
+    }
+}
+
+/// Asks until the answer parses, the answer as typed
+fn parsed<P: Prompt, T, E: std::fmt::Display>(
+    prompt: &mut P,
+    question: &str,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Result<String, Error> {
+    loop {
+        let value = answer(prompt, question)?;
+        match parse(&value) {
+            Ok(_) => return Ok(value),
+            Err(e) => prompt.say(&format!("invalid value: {}", e))?,
+        }
+    }
+}
+
+/// Whether the bucket may exist: only a missing bucket is refused, any
+/// other failure of the check is a note
+async fn bucket_exists<P: Prompt>(
+    prompt: &mut P,
+    client: &Client,
+    bucket: &str,
+) -> Result<bool, Error> {
+    match client.head_bucket().bucket(bucket).send().await {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_response().map(|r| r.status().as_u16()) == Some(404) => {
+            prompt.say(&format!("bucket {} does not exist", bucket))?;
+            Ok(false)
+        }
+        Err(e) => {
+            prompt.say(&format!(
+                "note: cannot check the bucket: {}",
+                crate::utils::error_context(&e)
+            ))?;
+            Ok(true)
+        }
+    }
+}
+
+/// Asks for the path, the filters and the action of an invocation
+pub async fn wizard<P: Prompt>(prompt: &mut P, client: &Client) -> Result<Invocation, Error> {
+    let mut args = Vec::new();
+
+    let path = loop {
+        let value = answer(prompt, "S3 path, like s3://bucket/prefix:")?;
+        let path = match value.parse::<S3Path>() {
+            Ok(path) => path,
+            Err(e) => {
+                prompt.say(&format!("invalid path: {}", e))?;
+                continue;
+            }
+        };
+        if !confirmed(prompt, "Check that the bucket exists? [Y/n]", true)?
+            || bucket_exists(prompt, client, &path.bucket).await?
+        {
+            break value;
+        }
+    };
+    args.push(path);
+
+    prompt.say("Filters: name, iname, size or mtime, an empty answer goes on")?;
+    loop {
+        let (flag, value) = match answer(prompt, "Filter:")?.as_str() {
+            "" => break,
+            "name" => (
+                "--name",
+                parsed(prompt, "Glob pattern, like *.log:", glob_pattern)?,
+            ),
+            "iname" => (
+                "--iname",
+                parsed(
+                    prompt,
+                    "Case-insensitive glob pattern:",
+                    str::parse::<InameGlob>,
+                )?,
+            ),
+            "size" => (
+                "--size",
+                parsed(
+                    prompt,
+                    "Size, like 5k, +10M or -1G:",
+                    str::parse::<FindSize>,
+                )?,
+            ),
+            "mtime" => (
+                "--mtime",
+                parsed(
+                    prompt,
+                    "Modification time, like -5d or +1h:",
+                    str::parse::<FindTime>,
+                )?,
+            ),
+            other => {
+                prompt.say(&format!("unknown filter {}", other))?;
+                continue;
+            }
+        };
+        args.push(flag.to_owned());
+        args.push(value);
+    }
+
+    prompt.say(&format!("Actions:\n{}", actions_help()))?;
+    loop {
+        let action = answer(prompt, &format!("Action [{}]:", DEFAULT_ACTION))?;
+        let action = if action.is_empty() {
+            DEFAULT_ACTION.to_owned()
+        } else {
+            action
+        };
+        let options = answer(
+            prompt,
+            &format!("Options and arguments of {}, empty for none:", action),
+        )?;
+        let options = match split_words(&options) {
+            Ok(options) => options,
+            Err(e) => {
+                prompt.say(&format!("invalid options: {}", e))?;
+                continue;
+            }
+        };
+
+        let invocation = Invocation {
+            args: args
+                .iter()
+                .cloned()
+                .chain(std::iter::once(action))
+                .chain(options)
+                .collect(),
+            run: false,
+        };
+        match invocation.opts() {
+            Ok(_) => {
+                args = invocation.args;
+                break;
+            }
+            Err(e) => prompt.say(&e.message)?,
+        }
+    }
+
+    let mut invocation = Invocation { args, run: false };
+    prompt.say(&format!("Command:\n  {}", invocation.command_line()))?;
+    invocation.run = confirmed(prompt, "Run it now? [y/N]", false)?;
+    Ok(invocation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::*;
+    use std::io::Cursor;
+
+    fn session(answers: &[&str]) -> Terminal<Cursor<String>, Vec<u8>> {
+        let input: String = answers.iter().map(|x| format!("{}\n", x)).collect();
+        Terminal::new(Cursor::new(input), Vec::new())
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("s3://bucket/logs/"), "s3://bucket/logs/");
+        assert_eq!(shell_quote("-5d"), "-5d");
+        assert_eq!(shell_quote("*.log"), "'*.log'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words(r#"--destination s3://b/x 'echo {}' "a b" c\ d"#).unwrap(),
+            vec!["--destination", "s3://b/x", "echo {}", "a b", "c d"]
+        );
+        assert_eq!(split_words("  ").unwrap(), Vec::<String>::new());
+        assert_eq!(split_words("''").unwrap(), vec![""]);
+        assert!(split_words("'open").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wizard_session() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![
+            ReplayResponse::new(404, ""),
+            ReplayResponse::new(200, ""),
+        ]);
+        let mut prompt = session(&[
+            "bucket/logs",
+            "s3://missing/logs/",
+            "",
+            "s3://bucket/logs/",
+            "y",
+            "name",
+            "[",
+            "*.log",
+            "color",
+            "size",
+            "+10M",
+            "mtime",
+            "-5d",
+            "",
+            "exec",
+            "",
+            "exec",
+            "'echo {}'",
+            "n",
+        ]);
+
+        let invocation = wizard(&mut prompt, &client).await?;
+
+        assert!(!invocation.run);
+        assert_eq!(
+            invocation.command_line(),
+            "s3find s3://bucket/logs/ --name '*.log' --size +10M --mtime -5d exec 'echo {}'"
+        );
+        let opts = invocation.opts()?;
+        assert_eq!(opts.name.len(), 1);
+        assert_eq!(opts.size.len(), 1);
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "HEAD");
+
+        let output = String::from_utf8(prompt.output)?;
+        assert!(output.contains("invalid path"));
+        assert!(output.contains("bucket missing does not exist"));
+        assert!(output.contains("invalid value"));
+        assert!(output.contains("unknown filter color"));
+        assert!(output.contains("    exec "));
+        assert!(!output.contains("authorization flow"));
+        assert!(output.contains("Run it now?"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wizard_defaults() -> Result<(), Error> {
+        let (client, http_client) = replay_client(vec![]);
+        let mut prompt = session(&["s3://bucket/", "n", "", "", "", "y"]);
+
+        let invocation = wizard(&mut prompt, &client).await?;
+
+        assert!(invocation.run);
+        assert_eq!(invocation.command_line(), "s3find s3://bucket/ ls");
+        assert!(http_client.requests().is_empty());
+
+        let mut prompt = session(&["s3://bucket/", "n"]);
+        assert!(wizard(&mut prompt, &client).await.is_err());
+        Ok(())
+    }
+}